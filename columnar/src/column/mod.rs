@@ -13,9 +13,9 @@ pub use serialize::{
     serialize_column_mappable_to_u128, serialize_column_mappable_to_u64,
 };
 
-use crate::column_index::ColumnIndex;
+use crate::column_index::{ColumnIndex, OptionalIndex};
 use crate::column_values::monotonic_mapping::StrictlyMonotonicMappingToInternal;
-use crate::column_values::{monotonic_map_column, ColumnValues};
+use crate::column_values::{monotonic_map_column, ColumnValues, VecColumn};
 use crate::{Cardinality, MonotonicallyMappableToU64, RowId};
 
 #[derive(Clone)]
@@ -37,6 +37,17 @@ impl<T: MonotonicallyMappableToU64> Column<T> {
     }
 }
 
+impl<T: PartialOrd + Copy + Debug + Default + Send + Sync + 'static> Column<T> {
+    /// Builds a column over `num_rows` rows without any value.
+    pub fn build_empty_column(num_rows: RowId) -> Column<T> {
+        let values: &'static [T] = &[];
+        Column {
+            idx: ColumnIndex::Optional(OptionalIndex::empty(num_rows)),
+            values: Arc::new(VecColumn::from(values)),
+        }
+    }
+}
+
 impl<T: PartialOrd + Copy + Debug + Send + Sync + 'static> Column<T> {
     pub fn get_cardinality(&self) -> Cardinality {
         self.idx.get_cardinality()
@@ -235,6 +235,11 @@ impl Set<RowId> for OptionalIndex {
 }
 
 impl OptionalIndex {
+    /// Returns an optional index over `num_rows` rows, none of which has a value.
+    pub fn empty(num_rows: RowId) -> OptionalIndex {
+        Self::for_test(num_rows, &[])
+    }
+
     pub fn for_test(num_rows: RowId, row_ids: &[RowId]) -> OptionalIndex {
         assert!(row_ids
             .last()
@@ -10,6 +10,7 @@ use std::net::Ipv6Addr;
 use std::sync::Arc;
 
 pub use merge_mapping::{MergeRowOrder, ShuffleMergeOrder, StackMergeOrder};
+pub use term_merger::TermMerger;
 
 use super::writer::ColumnarSerializer;
 use crate::column::{serialize_column_mappable_to_u128, serialize_column_mappable_to_u64};
@@ -58,9 +58,9 @@ impl<'a> TermMerger<'a> {
         }
     }
 
-    pub(crate) fn matching_segments<'b: 'a>(
-        &'b self,
-    ) -> impl 'b + Iterator<Item = (usize, TermOrdinal)> {
+    /// Returns the ordinal of the streams containing the current term, with the ordinal of the
+    /// term in each of them.
+    pub fn matching_segments<'b: 'a>(&'b self) -> impl 'b + Iterator<Item = (usize, TermOrdinal)> {
         self.current_streamers
             .iter()
             .map(|heap_item| (heap_item.segment_ord, heap_item.streamer.term_ord()))
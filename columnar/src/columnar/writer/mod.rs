@@ -76,6 +76,37 @@ impl Default for ColumnarWriter {
     }
 }
 
+fn numerical_value_to_u64(numerical_value: NumericalValue) -> u64 {
+    match numerical_value {
+        NumericalValue::I64(val) => val.to_u64(),
+        NumericalValue::U64(val) => val,
+        NumericalValue::F64(val) => val.to_u64(),
+    }
+}
+
+fn first_value_keys<V>(
+    ops: impl Iterator<Item = ColumnOperation<V>>,
+    num_rows: RowId,
+    to_key: impl Fn(V) -> u64,
+) -> Vec<Option<u64>> {
+    let mut keys = vec![None; num_rows as usize];
+    let mut current_row_opt: Option<RowId> = None;
+    for op in ops {
+        match op {
+            ColumnOperation::NewDoc(row) => {
+                current_row_opt = Some(row);
+            }
+            ColumnOperation::Value(value) => {
+                // Only the first value of the row is kept.
+                if let Some(row) = current_row_opt.take() {
+                    keys[row as usize] = Some(to_key(value));
+                }
+            }
+        }
+    }
+    keys
+}
+
 #[inline]
 fn mutate_or_create_column<V, TMutator>(
     arena_hash_map: &mut ArenaHashMap,
@@ -104,46 +135,46 @@ impl ColumnarWriter {
             + self.datetime_field_hash_map.mem_usage()
     }
 
-    /// Returns the list of doc ids from 0..num_docs sorted by the `sort_field`
-    /// column.
-    ///
-    /// If the column is multivalued, use the first value for scoring.
-    /// If no value is associated to a specific row, the document is assigned
-    /// the lowest possible score.
+    /// Returns, for each row of `0..num_rows`, a key of the first value of the column
+    /// `column_name`, or `None` if the row has no value.
     ///
-    /// The sort applied is stable.
-    pub fn sort_order(&self, sort_field: &str, num_docs: RowId, reversed: bool) -> Vec<u32> {
-        let Some(numerical_col_writer) =
-            self.numerical_field_hash_map.get::<NumericalColumnWriter>(sort_field.as_bytes()) else {
-                return Vec::new();
-        };
+    /// The keys are ordered like the values they stand for: numerical, bool and date values
+    /// are mapped to their monotonic `u64` representation, str and bytes values to their term
+    /// ordinal. Ip address columns are not supported.
+    pub fn first_value_sort_keys(&self, column_name: &str, num_rows: RowId) -> Vec<Option<u64>> {
+        let column_key = column_name.as_bytes();
         let mut symbols_buffer = Vec::new();
-        let mut values = Vec::new();
-        let mut last_doc_opt: Option<RowId> = None;
-        for op in numerical_col_writer.operation_iterator(&self.arena, None, &mut symbols_buffer) {
-            match op {
-                ColumnOperation::NewDoc(doc) => {
-                    last_doc_opt = Some(doc);
-                }
-                ColumnOperation::Value(numerical_value) => {
-                    if let Some(last_doc) = last_doc_opt {
-                        let score: f32 = f64::coerce(numerical_value) as f32;
-                        values.push((score, last_doc));
-                    }
-                }
-            }
+        if let Some(column_writer) = self
+            .numerical_field_hash_map
+            .get::<NumericalColumnWriter>(column_key)
+        {
+            let ops = column_writer.operation_iterator(&self.arena, None, &mut symbols_buffer);
+            return first_value_keys(ops, num_rows, numerical_value_to_u64);
         }
-        for doc in values.len() as u32..num_docs {
-            values.push((0.0f32, doc));
+        if let Some(column_writer) = self.datetime_field_hash_map.get::<ColumnWriter>(column_key) {
+            let ops = column_writer.operation_iterator(&self.arena, None, &mut symbols_buffer);
+            return first_value_keys(ops, num_rows, numerical_value_to_u64);
         }
-        values.sort_by(|(left_score, _), (right_score, _)| {
-            if reversed {
-                right_score.partial_cmp(left_score).unwrap()
-            } else {
-                left_score.partial_cmp(right_score).unwrap()
-            }
-        });
-        values.into_iter().map(|(_score, doc)| doc).collect()
+        if let Some(column_writer) = self.bool_field_hash_map.get::<ColumnWriter>(column_key) {
+            let ops = column_writer.operation_iterator(&self.arena, None, &mut symbols_buffer);
+            return first_value_keys(ops, num_rows, bool::to_u64);
+        }
+        let str_or_bytes_column_writer = self
+            .str_field_hash_map
+            .get::<StrOrBytesColumnWriter>(column_key)
+            .or_else(|| {
+                self.bytes_field_hash_map
+                    .get::<StrOrBytesColumnWriter>(column_key)
+            });
+        if let Some(column_writer) = str_or_bytes_column_writer {
+            let term_id_mapping =
+                self.dictionaries[column_writer.dictionary_id as usize].term_id_mapping();
+            let ops = column_writer.operation_iterator(&self.arena, None, &mut symbols_buffer);
+            return first_value_keys(ops, num_rows, |unordered_id| {
+                term_id_mapping.to_ord(unordered_id).0 as u64
+            });
+        }
+        vec![None; num_rows as usize]
     }
 
     /// Records a column type. This is useful to bypass the coercion process,
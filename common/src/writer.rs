@@ -52,6 +52,11 @@ impl<W: TerminatingWrite> TerminatingWrite for CountingWriter<W> {
     fn terminate_ref(&mut self, token: AntiCallToken) -> io::Result<()> {
         self.underlying.terminate_ref(token)
     }
+
+    #[inline]
+    fn sync(&mut self) -> io::Result<()> {
+        self.underlying.sync()
+    }
 }
 
 /// Struct used to prevent from calling
@@ -72,12 +77,24 @@ pub trait TerminatingWrite: Write + Send + Sync {
     /// You should implement this function to define custom behavior.
     /// This function should flush any buffer it may hold.
     fn terminate_ref(&mut self, _: AntiCallToken) -> io::Result<()>;
+
+    /// Flushes the writer and persists what was written so far, so that it survives a crash.
+    /// The writer can still be used afterwards.
+    ///
+    /// Defaults to `flush`, for writers without a durable storage.
+    fn sync(&mut self) -> io::Result<()> {
+        self.flush()
+    }
 }
 
 impl<W: TerminatingWrite + ?Sized> TerminatingWrite for Box<W> {
     fn terminate_ref(&mut self, token: AntiCallToken) -> io::Result<()> {
         self.as_mut().terminate_ref(token)
     }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.as_mut().sync()
+    }
 }
 
 impl<W: TerminatingWrite> TerminatingWrite for BufWriter<W> {
@@ -85,6 +102,11 @@ impl<W: TerminatingWrite> TerminatingWrite for BufWriter<W> {
         self.flush()?;
         self.get_mut().terminate_ref(a)
     }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.get_mut().sync()
+    }
 }
 
 impl<'a> TerminatingWrite for &'a mut Vec<u8> {
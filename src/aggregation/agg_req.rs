@@ -21,6 +21,7 @@
 //!                 field: "score".to_string(),
 //!                 ranges: vec![(3f64..7f64).into(), (7f64..20f64).into()],
 //!                 keyed: false,
+//!                 missing: None,
 //!             }),
 //!             sub_aggregation: Default::default(),
 //!         }),
@@ -50,10 +51,21 @@ use std::collections::{HashMap, HashSet};
 use serde::{Deserialize, Serialize};
 
 pub use super::bucket::RangeAggregation;
-use super::bucket::{HistogramAggregation, TermsAggregation};
+use super::bucket::{
+    AutoDateHistogramAggregation, CompositeAggregation, CompositeSourceType,
+    DateHistogramAggregationReq, FilterAggregation, FiltersAggregation, GeoGridAggregation,
+    HistogramAggregation, MultiTermsAggregation, NestedAggregation, RandomSamplerAggregation,
+    SamplerAggregation, SignificantTermsAggregation, TermsAggregation,
+};
+use super::expression::Expression;
 use super::metric::{
-    AverageAggregation, CountAggregation, MaxAggregation, MinAggregation, StatsAggregation,
-    SumAggregation,
+    AverageAggregation, CountAggregation, ExtendedStatsAggregation, MaxAggregation, MinAggregation,
+    StatsAggregation, SumAggregation, WeightedAverageAggregation,
+};
+use super::pipeline::{
+    AvgBucketAggregation, BucketScriptAggregation, BucketSelectorAggregation,
+    BucketSortAggregation, CumulativeSumAggregation, DerivativeAggregation, MaxBucketAggregation,
+    MovingAvgAggregation,
 };
 use super::VecWithNames;
 
@@ -68,12 +80,14 @@ pub type Aggregations = HashMap<String, Aggregation>;
 pub(crate) struct AggregationsInternal {
     pub(crate) metrics: VecWithNames<MetricAggregation>,
     pub(crate) buckets: VecWithNames<BucketAggregationInternal>,
+    pub(crate) pipelines: VecWithNames<PipelineAggregation>,
 }
 
 impl From<Aggregations> for AggregationsInternal {
     fn from(aggs: Aggregations) -> Self {
         let mut metrics = vec![];
         let mut buckets = vec![];
+        let mut pipelines = vec![];
         for (key, agg) in aggs {
             match agg {
                 Aggregation::Bucket(bucket) => buckets.push((
@@ -84,11 +98,13 @@ impl From<Aggregations> for AggregationsInternal {
                     },
                 )),
                 Aggregation::Metric(metric) => metrics.push((key, metric)),
+                Aggregation::Pipeline(pipeline) => pipelines.push((key, pipeline)),
             }
         }
         Self {
             metrics: VecWithNames::from_entries(metrics),
             buckets: VecWithNames::from_entries(buckets),
+            pipelines: VecWithNames::from_entries(pipelines),
         }
     }
 }
@@ -116,12 +132,49 @@ impl BucketAggregationInternal {
             _ => None,
         }
     }
+    pub(crate) fn as_date_histogram(&self) -> Option<&DateHistogramAggregationReq> {
+        match &self.bucket_agg {
+            BucketAggregationType::DateHistogram(histogram) => Some(histogram),
+            _ => None,
+        }
+    }
+    pub(crate) fn as_auto_date_histogram(&self) -> Option<&AutoDateHistogramAggregation> {
+        match &self.bucket_agg {
+            BucketAggregationType::AutoDateHistogram(histogram) => Some(histogram),
+            _ => None,
+        }
+    }
     pub(crate) fn as_term(&self) -> Option<&TermsAggregation> {
         match &self.bucket_agg {
             BucketAggregationType::Terms(terms) => Some(terms),
             _ => None,
         }
     }
+    pub(crate) fn as_multi_terms(&self) -> Option<&MultiTermsAggregation> {
+        match &self.bucket_agg {
+            BucketAggregationType::MultiTerms(multi_terms) => Some(multi_terms),
+            _ => None,
+        }
+    }
+    pub(crate) fn as_significant_terms(&self) -> Option<&SignificantTermsAggregation> {
+        match &self.bucket_agg {
+            BucketAggregationType::SignificantTerms(significant_terms) => Some(significant_terms),
+            _ => None,
+        }
+    }
+    pub(crate) fn as_composite(&self) -> Option<&CompositeAggregation> {
+        match &self.bucket_agg {
+            BucketAggregationType::Composite(composite) => Some(composite),
+            _ => None,
+        }
+    }
+    pub(crate) fn as_geo_grid(&self) -> Option<&GeoGridAggregation> {
+        match &self.bucket_agg {
+            BucketAggregationType::GeoHashGrid(geo_grid)
+            | BucketAggregationType::GeoTileGrid(geo_grid) => Some(geo_grid),
+            _ => None,
+        }
+    }
 }
 
 /// Extract all fields, where the term directory is used in the tree.
@@ -142,9 +195,35 @@ pub fn get_fast_field_names(aggs: &Aggregations) -> HashSet<String> {
     fast_field_names
 }
 
-/// Aggregation request of [`BucketAggregation`] or [`MetricAggregation`].
+/// Returns true if an aggregation in the tree reads the scores of the documents, i.e. the tree
+/// contains a `sampler` aggregation.
+pub(crate) fn requires_scoring(aggs: &Aggregations) -> bool {
+    aggs.values().any(|agg| match agg {
+        Aggregation::Bucket(bucket) => {
+            matches!(bucket.bucket_agg, BucketAggregationType::Sampler(_))
+                || requires_scoring(&bucket.sub_aggregation)
+        }
+        _ => false,
+    })
+}
+
+/// Resolves `now` in the date math of the range aggregations in the tree to the given timestamp in
+/// microseconds.
+pub(crate) fn resolve_date_math_now(aggs: &mut Aggregations, now: i64) {
+    for agg in aggs.values_mut() {
+        if let Aggregation::Bucket(bucket) = agg {
+            if let BucketAggregationType::Range(range) = &mut bucket.bucket_agg {
+                range.resolve_now(now);
+            }
+            resolve_date_math_now(&mut bucket.sub_aggregation, now);
+        }
+    }
+}
+
+/// Aggregation request of [`BucketAggregation`], [`MetricAggregation`] or
+/// [`PipelineAggregation`].
 ///
-/// An aggregation is either a bucket or a metric.
+/// An aggregation is either a bucket, a metric or a pipeline.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Aggregation {
@@ -152,6 +231,8 @@ pub enum Aggregation {
     Bucket(BucketAggregation),
     /// Metric aggregation, see [`MetricAggregation`] for details.
     Metric(MetricAggregation),
+    /// Pipeline aggregation, see [`PipelineAggregation`] for details.
+    Pipeline(PipelineAggregation),
 }
 
 impl Aggregation {
@@ -165,6 +246,7 @@ impl Aggregation {
         match self {
             Aggregation::Bucket(bucket) => bucket.get_fast_field_names(fast_field_names),
             Aggregation::Metric(metric) => metric.get_fast_field_names(fast_field_names),
+            Aggregation::Pipeline(_) => {}
         }
     }
 }
@@ -194,8 +276,26 @@ pub struct BucketAggregation {
 
 impl BucketAggregation {
     fn get_term_dict_field_names(&self, term_dict_field_names: &mut HashSet<String>) {
-        if let BucketAggregationType::Terms(terms) = &self.bucket_agg {
-            term_dict_field_names.insert(terms.field.to_string());
+        match &self.bucket_agg {
+            BucketAggregationType::Terms(terms) => {
+                term_dict_field_names.insert(terms.field.to_string());
+            }
+            BucketAggregationType::MultiTerms(multi_terms) => {
+                for source in &multi_terms.terms {
+                    term_dict_field_names.insert(source.field.to_string());
+                }
+            }
+            BucketAggregationType::SignificantTerms(significant_terms) => {
+                term_dict_field_names.insert(significant_terms.field.to_string());
+            }
+            BucketAggregationType::Composite(composite) => {
+                for source in &composite.sources {
+                    if let CompositeSourceType::Terms(terms) = &source.source {
+                        term_dict_field_names.insert(terms.field.to_string());
+                    }
+                }
+            }
+            _ => {}
         }
         term_dict_field_names.extend(get_term_dict_field_names(&self.sub_aggregation));
     }
@@ -214,9 +314,45 @@ pub enum BucketAggregationType {
     /// Put data into buckets of user-defined ranges.
     #[serde(rename = "histogram")]
     Histogram(HistogramAggregation),
+    /// Put data into buckets of fixed or calendar-aware date intervals.
+    #[serde(rename = "date_histogram")]
+    DateHistogram(DateHistogramAggregationReq),
+    /// Put data into buckets of date intervals, which are picked for a target number of buckets.
+    #[serde(rename = "auto_date_histogram")]
+    AutoDateHistogram(AutoDateHistogramAggregation),
     /// Put data into buckets of terms.
     #[serde(rename = "terms")]
     Terms(TermsAggregation),
+    /// Put data into buckets of the combined terms of multiple fields.
+    #[serde(rename = "multi_terms")]
+    MultiTerms(MultiTermsAggregation),
+    /// Put data into buckets of terms, which are unusually frequent compared to the whole index.
+    #[serde(rename = "significant_terms")]
+    SignificantTerms(SignificantTermsAggregation),
+    /// Put data matching a query into a single bucket.
+    #[serde(rename = "filter")]
+    Filter(FilterAggregation),
+    /// Put data into buckets of user-defined queries.
+    #[serde(rename = "filters")]
+    Filters(FiltersAggregation),
+    /// Put data into buckets of the combined keys of multiple sources, which can be paginated.
+    #[serde(rename = "composite")]
+    Composite(CompositeAggregation),
+    /// Put geo points into buckets of geohash cells.
+    #[serde(rename = "geohash_grid")]
+    GeoHashGrid(GeoGridAggregation),
+    /// Put geo points into buckets of map tiles.
+    #[serde(rename = "geotile_grid")]
+    GeoTileGrid(GeoGridAggregation),
+    /// Put the elements of a nested json path into a single bucket, as if they were documents.
+    #[serde(rename = "nested")]
+    Nested(NestedAggregation),
+    /// Put the top scoring documents of every segment into a single bucket.
+    #[serde(rename = "sampler")]
+    Sampler(SamplerAggregation),
+    /// Put a random sample of the documents into a single bucket.
+    #[serde(rename = "random_sampler")]
+    RandomSampler(RandomSamplerAggregation),
 }
 
 impl BucketAggregationType {
@@ -227,6 +363,37 @@ impl BucketAggregationType {
             BucketAggregationType::Histogram(histogram) => {
                 fast_field_names.insert(histogram.field.to_string())
             }
+            BucketAggregationType::DateHistogram(histogram) => {
+                fast_field_names.insert(histogram.field.to_string())
+            }
+            BucketAggregationType::AutoDateHistogram(histogram) => {
+                fast_field_names.insert(histogram.field.to_string())
+            }
+            BucketAggregationType::MultiTerms(multi_terms) => {
+                for source in &multi_terms.terms {
+                    fast_field_names.insert(source.field.to_string());
+                }
+                true
+            }
+            BucketAggregationType::SignificantTerms(significant_terms) => {
+                fast_field_names.insert(significant_terms.field.to_string())
+            }
+            BucketAggregationType::Filter(_) | BucketAggregationType::Filters(_) => false,
+            BucketAggregationType::Composite(composite) => {
+                for source in &composite.sources {
+                    fast_field_names.insert(source.source.field().to_string());
+                }
+                true
+            }
+            BucketAggregationType::GeoHashGrid(geo_grid)
+            | BucketAggregationType::GeoTileGrid(geo_grid) => {
+                let (lat_field, lon_field) = geo_grid.lat_lon_fields();
+                fast_field_names.insert(lat_field);
+                fast_field_names.insert(lon_field)
+            }
+            // The sub-aggregations read the fast fields of the nested documents.
+            BucketAggregationType::Nested(_) => false,
+            BucketAggregationType::Sampler(_) | BucketAggregationType::RandomSampler(_) => false,
         };
     }
 }
@@ -256,25 +423,92 @@ pub enum MetricAggregation {
     /// extracted values.
     #[serde(rename = "stats")]
     Stats(StatsAggregation),
+    /// Computes the `stats` extended with the sum of squares, variance, standard deviation and
+    /// standard deviation bounds over the extracted values.
+    #[serde(rename = "extended_stats")]
+    ExtendedStats(ExtendedStatsAggregation),
     /// Computes the sum of the extracted values.
     #[serde(rename = "sum")]
     Sum(SumAggregation),
+    /// Computes the average of the extracted values, weighted by another extracted value.
+    #[serde(rename = "weighted_avg")]
+    WeightedAverage(WeightedAverageAggregation),
 }
 
 impl MetricAggregation {
     fn get_fast_field_names(&self, fast_field_names: &mut HashSet<String>) {
-        let fast_field_name = match self {
-            MetricAggregation::Average(avg) => avg.field_name(),
-            MetricAggregation::Count(count) => count.field_name(),
-            MetricAggregation::Max(max) => max.field_name(),
-            MetricAggregation::Min(min) => min.field_name(),
-            MetricAggregation::Stats(stats) => stats.field_name(),
-            MetricAggregation::Sum(sum) => sum.field_name(),
+        let (field_name, script) = match self {
+            MetricAggregation::Average(avg) => (avg.field_name(), &avg.script),
+            MetricAggregation::Count(count) => (count.field_name(), &count.script),
+            MetricAggregation::Max(max) => (max.field_name(), &max.script),
+            MetricAggregation::Min(min) => (min.field_name(), &min.script),
+            MetricAggregation::Stats(stats) => (stats.field_name(), &stats.script),
+            MetricAggregation::ExtendedStats(extended_stats) => {
+                (extended_stats.field_name(), &extended_stats.script)
+            }
+            MetricAggregation::Sum(sum) => (sum.field_name(), &sum.script),
+            MetricAggregation::WeightedAverage(weighted_avg) => {
+                for source in [&weighted_avg.value, &weighted_avg.weight] {
+                    insert_value_fast_field_names(&source.field, &source.script, fast_field_names);
+                }
+                return;
+            }
         };
-        fast_field_names.insert(fast_field_name.to_string());
+        insert_value_fast_field_names(field_name, script, fast_field_names);
     }
 }
 
+/// Inserts the fast fields read by the field or script of a metric aggregation.
+fn insert_value_fast_field_names(
+    field_name: &str,
+    script: &Option<String>,
+    fast_field_names: &mut HashSet<String>,
+) {
+    match script {
+        Some(script) => {
+            // Invalid scripts are reported when the aggregation is collected.
+            if let Ok(expression) = Expression::parse(script) {
+                fast_field_names.extend(expression.variables().into_iter().map(str::to_string));
+            }
+        }
+        None => {
+            fast_field_names.insert(field_name.to_string());
+        }
+    }
+}
+
+/// The aggregations in this family don't collect documents, but compute values from the results
+/// of other aggregations, e.g. the derivative of a metric over the buckets of a histogram.
+///
+/// See the [pipeline module](super::pipeline) for how the input values are referenced.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PipelineAggregation {
+    /// Computes the difference of a metric to the previous histogram bucket.
+    #[serde(rename = "derivative")]
+    Derivative(DerivativeAggregation),
+    /// Computes the cumulative sum of a metric over the histogram buckets.
+    #[serde(rename = "cumulative_sum")]
+    CumulativeSum(CumulativeSumAggregation),
+    /// Computes the average of a metric over a sliding window of histogram buckets.
+    #[serde(rename = "moving_avg")]
+    MovingAvg(MovingAvgAggregation),
+    /// Computes a value per bucket from other values of the bucket with a script.
+    #[serde(rename = "bucket_script")]
+    BucketScript(BucketScriptAggregation),
+    /// Filters the buckets with a script.
+    #[serde(rename = "bucket_selector")]
+    BucketSelector(BucketSelectorAggregation),
+    /// Sorts and truncates the buckets.
+    #[serde(rename = "bucket_sort")]
+    BucketSort(BucketSortAggregation),
+    /// Computes the average of a metric over the buckets of a sibling aggregation.
+    #[serde(rename = "avg_bucket")]
+    AvgBucket(AvgBucketAggregation),
+    /// Finds the maximum of a metric over the buckets of a sibling aggregation.
+    #[serde(rename = "max_bucket")]
+    MaxBucket(MaxBucketAggregation),
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -325,6 +559,7 @@ mod tests {
                         (20f64..f64::MAX).into(),
                     ],
                     keyed: true,
+                    ..Default::default()
                 }),
                 sub_aggregation: Default::default(),
             }),
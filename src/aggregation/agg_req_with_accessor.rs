@@ -1,20 +1,31 @@
 //! This will enhance the request tree with access to the fastfield and metadata.
 
+use std::net::Ipv6Addr;
 use std::rc::Rc;
 use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
 
-use columnar::{Column, StrColumn};
+use columnar::{Column, ColumnType, StrColumn};
 
-use super::agg_req::{Aggregation, Aggregations, BucketAggregationType, MetricAggregation};
-use super::bucket::{HistogramAggregation, RangeAggregation, TermsAggregation};
+use super::agg_req::{
+    get_fast_field_names, Aggregation, Aggregations, BucketAggregationType, MetricAggregation,
+};
+use super::bucket::{
+    AutoDateHistogramAggregation, CompositeSourceAccessor, DateHistogramAggregationReq, DocScores,
+    FilterWeights, GeoGridType, HistogramAggregation, RangeAggregation, SignificantTermsBackground,
+    TermsAggregation,
+};
+use super::expression::Expression;
+use super::memory_budget::MemoryAccount;
 use super::metric::{
-    AverageAggregation, CountAggregation, MaxAggregation, MinAggregation, StatsAggregation,
-    SumAggregation,
+    AverageAggregation, CountAggregation, ExtendedStatsAggregation, MaxAggregation, MinAggregation,
+    StatsAggregation, SumAggregation, WeightedAverageAggregation,
 };
 use super::segment_agg_result::BucketCount;
-use super::VecWithNames;
+use super::{f64_from_fastfield_u64, VecWithNames};
+use crate::fastfield::NestedDocuments;
 use crate::schema::Type;
-use crate::{SegmentReader, TantivyError};
+use crate::{DocId, SegmentReader, TantivyError};
 
 #[derive(Clone, Default)]
 pub(crate) struct AggregationsWithAccessor {
@@ -38,13 +49,30 @@ impl AggregationsWithAccessor {
 #[derive(Clone)]
 pub struct BucketAggregationWithAccessor {
     /// In general there can be buckets without fast field access, e.g. buckets that are created
-    /// based on search terms. So eventually this needs to be Option or moved.
-    pub(crate) accessor: Column<u64>,
+    /// based on search terms, like the filter aggregation.
+    pub(crate) accessor: Option<Column<u64>>,
+    /// The fast field of a range aggregation on an ip field, which is read instead of `accessor`.
+    pub(crate) ip_column: Option<Column<Ipv6Addr>>,
     pub(crate) str_dict_column: Option<StrColumn>,
+    pub(crate) filter_weights: Option<FilterWeights>,
+    /// The fast fields of the sources of a composite aggregation, the fields of a multi_terms
+    /// aggregation or the latitude and longitude fields of a geo grid aggregation.
+    pub(crate) composite_sources: Vec<CompositeSourceAccessor>,
+    /// The background frequencies of a significant_terms aggregation.
+    pub(crate) significant_terms_background: Option<SignificantTermsBackground>,
+    /// The elements of the nested path of a nested aggregation, which are collected by the
+    /// sub-aggregations.
+    pub(crate) nested_documents: Option<Arc<NestedDocuments>>,
+    /// The scores of the collected documents, which are read by a sampler aggregation.
+    pub(crate) doc_scores: Option<DocScores>,
+    /// The seed of a random_sampler aggregation in the segment.
+    pub(crate) random_sampler_seed: Option<u64>,
     pub(crate) field_type: Type,
     pub(crate) bucket_agg: BucketAggregationType,
     pub(crate) sub_aggregation: AggregationsWithAccessor,
     pub(crate) bucket_count: BucketCount,
+    /// The memory account, which is shared between the aggregations of one segment collector.
+    pub(crate) memory_account: MemoryAccount,
 }
 
 impl BucketAggregationWithAccessor {
@@ -54,48 +82,176 @@ impl BucketAggregationWithAccessor {
         reader: &SegmentReader,
         bucket_count: Rc<AtomicU32>,
         max_bucket_count: u32,
+        memory_account: &MemoryAccount,
+        doc_scores: &DocScores,
     ) -> crate::Result<BucketAggregationWithAccessor> {
         let mut str_dict_column = None;
+        let mut ip_column = None;
+        let mut filter_weights = None;
+        let mut composite_sources = Vec::new();
+        let mut significant_terms_background = None;
+        let mut nested_documents = None;
+        let mut sampler_doc_scores = None;
+        let mut random_sampler_seed = None;
         let (accessor, field_type) = match &bucket {
             BucketAggregationType::Range(RangeAggregation {
-                field: field_name, ..
-            }) => get_ff_reader_and_validate(reader, field_name)?,
+                field: field_name,
+                ..
+            }) => {
+                if let Some(column) = reader.fast_fields().column_opt(field_name)? {
+                    ip_column = Some(column);
+                    (None, Type::IpAddr)
+                } else {
+                    get_optional_ff_reader_and_validate(reader, field_name, NUMERIC_COLUMN_TYPES)?
+                }
+            }
             BucketAggregationType::Histogram(HistogramAggregation {
-                field: field_name, ..
-            }) => get_ff_reader_and_validate(reader, field_name)?,
+                field: field_name,
+                ..
+            }) => get_optional_ff_reader_and_validate(reader, field_name, NUMERIC_COLUMN_TYPES)?,
+            BucketAggregationType::DateHistogram(DateHistogramAggregationReq {
+                field: field_name,
+                ..
+            }) => get_optional_ff_reader_and_validate(reader, field_name, NUMERIC_COLUMN_TYPES)?,
+            BucketAggregationType::AutoDateHistogram(AutoDateHistogramAggregation {
+                field: field_name,
+                ..
+            }) => get_optional_ff_reader_and_validate(reader, field_name, NUMERIC_COLUMN_TYPES)?,
             BucketAggregationType::Terms(TermsAggregation {
-                field: field_name, ..
+                field: field_name,
+                ..
             }) => {
-                str_dict_column = reader.fast_fields().str(&field_name)?;
-                get_ff_reader_and_validate(reader, field_name)?
+                str_dict_column = reader.fast_fields().str(field_name)?;
+                get_optional_ff_reader_and_validate(reader, field_name, TEXT_COLUMN_TYPES)?
+            }
+            BucketAggregationType::SignificantTerms(significant_terms) => {
+                str_dict_column = reader.fast_fields().str(&significant_terms.field)?;
+                significant_terms_background = Some(SignificantTermsBackground::from_req(
+                    significant_terms,
+                    reader,
+                )?);
+                get_optional_ff_reader_and_validate(
+                    reader,
+                    &significant_terms.field,
+                    SOURCE_COLUMN_TYPES,
+                )?
+            }
+            BucketAggregationType::Filter(_) | BucketAggregationType::Filters(_) => {
+                // Filter buckets are defined by queries and don't read a fast field.
+                filter_weights = Some(FilterWeights::from_req(bucket, reader)?);
+                (None, Type::U64)
+            }
+            BucketAggregationType::MultiTerms(multi_terms) => {
+                composite_sources = get_source_accessors(
+                    reader,
+                    multi_terms.terms.iter().map(|source| source.field.as_str()),
+                    SOURCE_COLUMN_TYPES,
+                )?;
+                (None, Type::U64)
+            }
+            BucketAggregationType::Composite(composite) => {
+                // Composite buckets read one fast field per source.
+                composite_sources = get_source_accessors(
+                    reader,
+                    composite.sources.iter().map(|source| source.source.field()),
+                    SOURCE_COLUMN_TYPES,
+                )?;
+                (None, Type::U64)
+            }
+            BucketAggregationType::GeoHashGrid(geo_grid)
+            | BucketAggregationType::GeoTileGrid(geo_grid) => {
+                let grid_type = if let BucketAggregationType::GeoHashGrid(_) = bucket {
+                    GeoGridType::GeoHash
+                } else {
+                    GeoGridType::GeoTile
+                };
+                geo_grid.validate(grid_type)?;
+                // The latitudes and the longitudes are the two sources.
+                let (lat_field, lon_field) = geo_grid.lat_lon_fields();
+                composite_sources = get_source_accessors(
+                    reader,
+                    [lat_field.as_str(), lon_field.as_str()].into_iter(),
+                    NUMERIC_COLUMN_TYPES,
+                )?;
+                (None, Type::F64)
+            }
+            BucketAggregationType::Nested(nested) => {
+                nested.validate(sub_aggregation)?;
+                let sub_aggregation_fields = get_fast_field_names(sub_aggregation);
+                nested_documents = Some(Arc::new(NestedDocuments::build(
+                    reader.fast_fields(),
+                    reader.max_doc(),
+                    &nested.path,
+                    sub_aggregation_fields.iter().map(String::as_str),
+                )?));
+                (None, Type::U64)
+            }
+            BucketAggregationType::Sampler(sampler) => {
+                sampler.validate()?;
+                sampler_doc_scores = Some(doc_scores.clone());
+                (None, Type::U64)
+            }
+            BucketAggregationType::RandomSampler(random_sampler) => {
+                random_sampler.validate()?;
+                random_sampler_seed = Some(random_sampler.segment_seed(reader));
+                (None, Type::U64)
             }
         };
+        // The sub-aggregations of a nested aggregation collect the nested documents.
+        let sub_aggregation_reader = nested_documents.as_ref().map(|nested_documents| {
+            reader.with_fast_fields(
+                nested_documents.fast_fields().clone(),
+                nested_documents.num_nested_docs(),
+            )
+        });
         let sub_aggregation = sub_aggregation.clone();
         Ok(BucketAggregationWithAccessor {
             accessor,
             field_type,
             sub_aggregation: get_aggs_with_accessor_and_validate(
                 &sub_aggregation,
-                reader,
+                sub_aggregation_reader.as_ref().unwrap_or(reader),
                 bucket_count.clone(),
                 max_bucket_count,
+                memory_account,
+                doc_scores,
             )?,
             bucket_agg: bucket.clone(),
             str_dict_column,
+            ip_column,
+            filter_weights,
+            composite_sources,
+            significant_terms_background,
+            nested_documents,
+            doc_scores: sampler_doc_scores,
+            random_sampler_seed,
             bucket_count: BucketCount {
                 bucket_count,
                 max_bucket_count,
             },
+            memory_account: memory_account.clone(),
         })
     }
+
+    /// Returns the fast field reader of the bucket aggregation.
+    ///
+    /// # Panics
+    /// Panics if the bucket aggregation doesn't read from a fast field.
+    pub(crate) fn column(&self) -> &Column<u64> {
+        self.accessor
+            .as_ref()
+            .expect("internal error: fast field not loaded for bucket aggregation")
+    }
 }
 
-/// Contains the metric request and the fast field accessor.
+/// Contains the metric request and the accessors of its values.
 #[derive(Clone)]
 pub struct MetricAggregationWithAccessor {
     pub metric: MetricAggregation,
-    pub field_type: Type,
-    pub accessor: Column<u64>,
+    /// The values the metric is computed on.
+    pub values: MetricValueAccessor,
+    /// The weights of the values, only set for the weighted average.
+    weights: Option<MetricValueAccessor>,
 }
 
 impl MetricAggregationWithAccessor {
@@ -103,23 +259,235 @@ impl MetricAggregationWithAccessor {
         metric: &MetricAggregation,
         reader: &SegmentReader,
     ) -> crate::Result<MetricAggregationWithAccessor> {
+        if let MetricAggregation::ExtendedStats(extended_stats) = metric {
+            extended_stats.validate()?;
+        }
         match &metric {
-            MetricAggregation::Average(AverageAggregation { field: field_name })
-            | MetricAggregation::Count(CountAggregation { field: field_name })
-            | MetricAggregation::Max(MaxAggregation { field: field_name })
-            | MetricAggregation::Min(MinAggregation { field: field_name })
-            | MetricAggregation::Stats(StatsAggregation { field: field_name })
-            | MetricAggregation::Sum(SumAggregation { field: field_name }) => {
-                let (accessor, field_type) = get_ff_reader_and_validate(reader, field_name)?;
-
+            MetricAggregation::Average(AverageAggregation {
+                field: field_name,
+                script,
+                missing,
+            })
+            | MetricAggregation::Count(CountAggregation {
+                field: field_name,
+                script,
+                missing,
+            })
+            | MetricAggregation::Max(MaxAggregation {
+                field: field_name,
+                script,
+                missing,
+            })
+            | MetricAggregation::Min(MinAggregation {
+                field: field_name,
+                script,
+                missing,
+            })
+            | MetricAggregation::Stats(StatsAggregation {
+                field: field_name,
+                script,
+                missing,
+            })
+            | MetricAggregation::ExtendedStats(ExtendedStatsAggregation {
+                field: field_name,
+                script,
+                missing,
+                ..
+            })
+            | MetricAggregation::Sum(SumAggregation {
+                field: field_name,
+                script,
+                missing,
+            }) => Ok(MetricAggregationWithAccessor {
+                values: MetricValueAccessor::try_from_field_or_script(
+                    reader,
+                    field_name,
+                    script.as_deref(),
+                    *missing,
+                )?,
+                weights: None,
+                metric: metric.clone(),
+            }),
+            MetricAggregation::WeightedAverage(WeightedAverageAggregation { value, weight }) => {
                 Ok(MetricAggregationWithAccessor {
-                    accessor,
-                    field_type,
+                    values: MetricValueAccessor::try_from_field_or_script(
+                        reader,
+                        &value.field,
+                        value.script.as_deref(),
+                        value.missing,
+                    )?,
+                    weights: Some(MetricValueAccessor::try_from_field_or_script(
+                        reader,
+                        &weight.field,
+                        weight.script.as_deref(),
+                        weight.missing,
+                    )?),
                     metric: metric.clone(),
                 })
             }
         }
     }
+
+    /// Returns the weights of the weighted average.
+    ///
+    /// # Panics
+    /// Panics if the metric is not a weighted average.
+    pub(crate) fn weights(&self) -> &MetricValueAccessor {
+        self.weights
+            .as_ref()
+            .expect("internal error: weights not loaded for weighted average")
+    }
+}
+
+/// The values of a metric aggregation, read from a fast field or computed by a script over fast
+/// fields.
+#[derive(Clone)]
+pub struct MetricValueAccessor {
+    source: MetricValueSource,
+    /// The value collected for documents without a value.
+    missing: Option<f64>,
+}
+
+#[derive(Clone)]
+enum MetricValueSource {
+    Field {
+        accessor: Column<u64>,
+        field_type: Type,
+    },
+    Script {
+        expression: Expression,
+        variables: Vec<ScriptVariable>,
+    },
+}
+
+/// A fast field referenced by a script.
+#[derive(Clone)]
+struct ScriptVariable {
+    field_name: String,
+    accessor: Column<u64>,
+    field_type: Type,
+}
+
+impl MetricValueAccessor {
+    fn try_from_field_or_script(
+        reader: &SegmentReader,
+        field_name: &str,
+        script: Option<&str>,
+        missing: Option<f64>,
+    ) -> crate::Result<Self> {
+        let source = match script {
+            None if !field_name.is_empty() => {
+                let (accessor, field_type) =
+                    get_ff_reader_and_validate(reader, field_name, NUMERIC_COLUMN_TYPES)?;
+                MetricValueSource::Field {
+                    accessor,
+                    field_type,
+                }
+            }
+            Some(script) if field_name.is_empty() => {
+                let expression = Expression::parse(script)?;
+                let mut variables: Vec<ScriptVariable> = Vec::new();
+                for field_name in expression.variables() {
+                    if variables
+                        .iter()
+                        .any(|variable| variable.field_name == field_name)
+                    {
+                        continue;
+                    }
+                    let (accessor, field_type) =
+                        get_ff_reader_and_validate(reader, field_name, NUMERIC_COLUMN_TYPES)?;
+                    variables.push(ScriptVariable {
+                        field_name: field_name.to_string(),
+                        accessor,
+                        field_type,
+                    });
+                }
+                MetricValueSource::Script {
+                    expression,
+                    variables,
+                }
+            }
+            _ => {
+                return Err(TantivyError::InvalidArgument(
+                    "exactly one of field and script is required for a metric aggregation"
+                        .to_string(),
+                ))
+            }
+        };
+        Ok(Self { source, missing })
+    }
+
+    /// Calls `collect` with every value of the document, or with the missing value if the
+    /// document has no value.
+    #[inline]
+    pub(crate) fn collect_values(&self, doc: DocId, mut collect: impl FnMut(f64)) {
+        match &self.source {
+            MetricValueSource::Field {
+                accessor,
+                field_type,
+            } => {
+                if let Some(missing) = self.missing {
+                    if !accessor.has_value(doc) {
+                        collect(missing);
+                        return;
+                    }
+                }
+                for val in accessor.values(doc) {
+                    collect(f64_from_fastfield_u64(val, field_type));
+                }
+            }
+            MetricValueSource::Script { .. } => {
+                if let Some(value) = self.first_value(doc) {
+                    collect(value);
+                }
+            }
+        }
+    }
+
+    /// Returns the first value of the document, or the missing value if the document has no
+    /// value.
+    #[inline]
+    pub(crate) fn first_value(&self, doc: DocId) -> Option<f64> {
+        let value = match &self.source {
+            MetricValueSource::Field {
+                accessor,
+                field_type,
+            } => accessor
+                .first(doc)
+                .map(|val| f64_from_fastfield_u64(val, field_type)),
+            MetricValueSource::Script {
+                expression,
+                variables,
+            } => expression
+                .eval(&|field_name| {
+                    let variable = variables
+                        .iter()
+                        .find(|variable| variable.field_name == field_name)?;
+                    let val = variable.accessor.first(doc)?;
+                    Some(f64_from_fastfield_u64(val, &variable.field_type))
+                })
+                .filter(|value| value.is_finite()),
+        };
+        value.or(self.missing)
+    }
+}
+
+fn get_source_accessors<'a>(
+    reader: &SegmentReader,
+    field_names: impl Iterator<Item = &'a str>,
+    allowed_column_types: &[ColumnType],
+) -> crate::Result<Vec<CompositeSourceAccessor>> {
+    field_names
+        .map(|field_name| {
+            let (accessor, field_type) =
+                get_ff_reader_and_validate(reader, field_name, allowed_column_types)?;
+            Ok(CompositeSourceAccessor {
+                accessor,
+                str_dict_column: reader.fast_fields().str(field_name)?,
+                field_type,
+            })
+        })
+        .collect()
 }
 
 pub(crate) fn get_aggs_with_accessor_and_validate(
@@ -127,6 +495,8 @@ pub(crate) fn get_aggs_with_accessor_and_validate(
     reader: &SegmentReader,
     bucket_count: Rc<AtomicU32>,
     max_bucket_count: u32,
+    memory_account: &MemoryAccount,
+    doc_scores: &DocScores,
 ) -> crate::Result<AggregationsWithAccessor> {
     let mut metrics = vec![];
     let mut buckets = vec![];
@@ -140,12 +510,16 @@ pub(crate) fn get_aggs_with_accessor_and_validate(
                     reader,
                     Rc::clone(&bucket_count),
                     max_bucket_count,
+                    memory_account,
+                    doc_scores,
                 )?,
             )),
             Aggregation::Metric(metric) => metrics.push((
                 key.to_string(),
                 MetricAggregationWithAccessor::try_from_metric(metric, reader)?,
             )),
+            // Pipeline aggregations are computed on the final result.
+            Aggregation::Pipeline(_) => {}
         }
     }
     Ok(AggregationsWithAccessor::from_data(
@@ -154,26 +528,86 @@ pub(crate) fn get_aggs_with_accessor_and_validate(
     ))
 }
 
-/// Get fast field reader with given cardinatility.
+/// The column types aggregations on numeric values can read from.
+const NUMERIC_COLUMN_TYPES: &[ColumnType] = &[
+    ColumnType::I64,
+    ColumnType::U64,
+    ColumnType::F64,
+    ColumnType::DateTime,
+];
+
+/// The column types the terms aggregations can read from.
+const TEXT_COLUMN_TYPES: &[ColumnType] = &[ColumnType::Str];
+
+/// The column types the sources of composite and multi_terms aggregations can read from. Text is
+/// preferred, if a json path has both text and numeric values.
+const SOURCE_COLUMN_TYPES: &[ColumnType] = &[
+    ColumnType::Str,
+    ColumnType::I64,
+    ColumnType::U64,
+    ColumnType::F64,
+    ColumnType::DateTime,
+];
+
+/// Same as `get_ff_reader_and_validate`, but wraps the fast field reader for buckets, which
+/// don't necessarily have one.
+fn get_optional_ff_reader_and_validate(
+    reader: &SegmentReader,
+    field_name: &str,
+    allowed_column_types: &[ColumnType],
+) -> crate::Result<(Option<columnar::Column<u64>>, Type)> {
+    let (accessor, field_type) =
+        get_ff_reader_and_validate(reader, field_name, allowed_column_types)?;
+    Ok((Some(accessor), field_type))
+}
+
+/// Get the fast field reader of a field or json path, with the type of its values.
+///
+/// The values of a json path can be spread over columns of different types, the first column
+/// in the order of `allowed_column_types` is used. A json path without values in the segment
+/// gets an empty column.
 fn get_ff_reader_and_validate(
     reader: &SegmentReader,
     field_name: &str,
+    allowed_column_types: &[ColumnType],
 ) -> crate::Result<(columnar::Column<u64>, Type)> {
-    let field = reader.schema().get_field(field_name)?;
-    // TODO we should get type metadata from columnar
-    let field_type = reader
+    let (field, json_path) = reader
         .schema()
-        .get_field_entry(field)
-        .field_type()
-        .value_type();
-    // TODO Do validation
-
+        .find_field(field_name)
+        .ok_or_else(|| TantivyError::FieldNotFound(field_name.to_string()))?;
     let ff_fields = reader.fast_fields();
-    let ff_field = ff_fields.u64_lenient(field_name)?.ok_or_else(|| {
-        TantivyError::InvalidArgument(format!(
-            "No numerical fast field found for field: {}",
-            field_name
-        ))
-    })?;
-    Ok((ff_field, field_type))
+    if let Some((ff_field, column_type)) =
+        ff_fields.u64_lenient_for_type(Some(allowed_column_types), field_name)?
+    {
+        return Ok((ff_field, column_type_to_type(column_type)));
+    }
+    let field_type = reader.schema().get_field_entry(field).field_type();
+    if !json_path.is_empty() && field_type.is_fast() {
+        return Ok((
+            Column::build_empty_column(reader.max_doc()),
+            column_type_to_type(allowed_column_types[0]),
+        ));
+    }
+    let expected_values = if allowed_column_types.contains(&ColumnType::Str) {
+        "text"
+    } else {
+        "numerical"
+    };
+    Err(TantivyError::InvalidArgument(format!(
+        "No {} fast field found for field: {}",
+        expected_values, field_name
+    )))
+}
+
+fn column_type_to_type(column_type: ColumnType) -> Type {
+    match column_type {
+        ColumnType::I64 => Type::I64,
+        ColumnType::U64 => Type::U64,
+        ColumnType::F64 => Type::F64,
+        ColumnType::DateTime => Type::Date,
+        ColumnType::Str => Type::Str,
+        ColumnType::Bytes => Type::Bytes,
+        ColumnType::Bool => Type::Bool,
+        ColumnType::IpAddr => Type::IpAddr,
+    }
 }
@@ -8,9 +8,10 @@ use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
 
 use super::agg_req::BucketAggregationInternal;
-use super::bucket::GetDocCount;
+use super::bucket::{GeoPoint, GetDocCount};
 use super::intermediate_agg_result::{IntermediateBucketResult, IntermediateMetricResult};
-use super::metric::{SingleMetricResult, Stats};
+use super::metric::{ExtendedStats, SingleMetricResult, Stats};
+use super::pipeline::BucketMetricValue;
 use super::Key;
 use crate::schema::Schema;
 use crate::TantivyError;
@@ -78,19 +79,43 @@ pub enum MetricResult {
     Min(SingleMetricResult),
     /// Stats metric result.
     Stats(Stats),
+    /// Extended stats metric result.
+    ExtendedStats(Box<ExtendedStats>),
     /// Sum metric result.
     Sum(SingleMetricResult),
+    /// Weighted average metric result.
+    WeightedAverage(SingleMetricResult),
+    /// Derivative pipeline result.
+    Derivative(SingleMetricResult),
+    /// Cumulative sum pipeline result.
+    CumulativeSum(SingleMetricResult),
+    /// Moving average pipeline result.
+    MovingAvg(SingleMetricResult),
+    /// Bucket script pipeline result.
+    BucketScript(SingleMetricResult),
+    /// Average bucket pipeline result.
+    AvgBucket(SingleMetricResult),
+    /// Max bucket pipeline result.
+    MaxBucket(BucketMetricValue),
 }
 
 impl MetricResult {
-    fn get_value(&self, agg_property: &str) -> crate::Result<Option<f64>> {
+    pub(crate) fn get_value(&self, agg_property: &str) -> crate::Result<Option<f64>> {
         match self {
             MetricResult::Average(avg) => Ok(avg.value),
             MetricResult::Count(count) => Ok(count.value),
             MetricResult::Max(max) => Ok(max.value),
             MetricResult::Min(min) => Ok(min.value),
             MetricResult::Stats(stats) => stats.get_value(agg_property),
+            MetricResult::ExtendedStats(extended_stats) => extended_stats.get_value(agg_property),
             MetricResult::Sum(sum) => Ok(sum.value),
+            MetricResult::WeightedAverage(weighted_avg) => Ok(weighted_avg.value),
+            MetricResult::Derivative(derivative) => Ok(derivative.value),
+            MetricResult::CumulativeSum(cumulative_sum) => Ok(cumulative_sum.value),
+            MetricResult::MovingAvg(moving_avg) => Ok(moving_avg.value),
+            MetricResult::BucketScript(script) => Ok(script.value),
+            MetricResult::AvgBucket(avg_bucket) => Ok(avg_bucket.value),
+            MetricResult::MaxBucket(max_bucket) => Ok(max_bucket.value),
         }
     }
 }
@@ -112,9 +137,15 @@ impl From<IntermediateMetricResult> for MetricResult {
             IntermediateMetricResult::Stats(intermediate_stats) => {
                 MetricResult::Stats(intermediate_stats.finalize())
             }
+            IntermediateMetricResult::ExtendedStats(intermediate_extended_stats) => {
+                MetricResult::ExtendedStats(Box::new(intermediate_extended_stats.finalize()))
+            }
             IntermediateMetricResult::Sum(intermediate_sum) => {
                 MetricResult::Sum(intermediate_sum.finalize().into())
             }
+            IntermediateMetricResult::WeightedAverage(intermediate_weighted_avg) => {
+                MetricResult::WeightedAverage(intermediate_weighted_avg.finalize().into())
+            }
         }
     }
 }
@@ -123,6 +154,25 @@ impl From<IntermediateMetricResult> for MetricResult {
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum BucketResult {
+    /// This is the auto date histogram result, which contains the buckets and the picked
+    /// interval. It is the first variant, since the untagged deserialization would otherwise
+    /// match another variant with `buckets`.
+    AutoDateHistogram {
+        /// The buckets without holes between the first and the last bucket.
+        ///
+        /// See [`AutoDateHistogramAggregation`](super::bucket::AutoDateHistogramAggregation)
+        buckets: BucketEntries<BucketEntry>,
+        /// The interval of the buckets, like `1d`.
+        interval: String,
+    },
+    /// This is the geo grid result, which contains a bucket for each cell. Its buckets have a
+    /// `centroid`, so that the untagged deserialization doesn't match them as range buckets.
+    GeoGrid {
+        /// The buckets sorted by doc_count.
+        ///
+        /// See [`GeoGridAggregation`](super::bucket::GeoGridAggregation)
+        buckets: Vec<GeoGridBucketEntry>,
+    },
     /// This is the range entry for a bucket, which contains a key, count, from, to, and optionally
     /// sub-aggregations.
     Range {
@@ -151,6 +201,51 @@ pub enum BucketResult {
         /// The upper bound error for the doc count of each term.
         doc_count_error_upper_bound: Option<u64>,
     },
+    /// This is the multi terms result
+    MultiTerms {
+        /// The buckets.
+        ///
+        /// See [`MultiTermsAggregation`](super::bucket::MultiTermsAggregation)
+        buckets: Vec<MultiTermsBucketEntry>,
+        /// The number of documents that didn’t make it into to TOP N due to segment_size or size
+        sum_other_doc_count: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        /// The upper bound error for the doc count of each bucket.
+        doc_count_error_upper_bound: Option<u64>,
+    },
+    /// This is the significant terms result
+    SignificantTerms {
+        /// The number of documents in the foreground set.
+        doc_count: u64,
+        /// The number of documents in the background set.
+        bg_count: u64,
+        /// The buckets sorted by score.
+        ///
+        /// See [`SignificantTermsAggregation`](super::bucket::SignificantTermsAggregation)
+        buckets: Vec<SignificantTermBucketEntry>,
+    },
+    /// This is the composite result, which contains one page of buckets.
+    Composite {
+        /// The key of the last bucket. Pass it as `after` to the next request to fetch the next
+        /// page.
+        ///
+        /// See [`CompositeAggregation`](super::bucket::CompositeAggregation)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        after_key: Option<FxHashMap<String, Key>>,
+        /// The buckets sorted by key.
+        buckets: Vec<CompositeBucketEntry>,
+    },
+    /// This is the filters result, which contains a bucket for each filter name.
+    Filters {
+        /// The buckets by filter name.
+        ///
+        /// See [`FiltersAggregation`](super::bucket::FiltersAggregation)
+        buckets: FxHashMap<String, FilterBucketEntry>,
+    },
+    /// This is the filter result, which is a single bucket.
+    ///
+    /// See [`FilterAggregation`](super::bucket::FilterAggregation)
+    Filter(FilterBucketEntry),
 }
 
 impl BucketResult {
@@ -224,6 +319,169 @@ impl GetDocCount for BucketEntry {
     }
 }
 
+/// This is the entry for a filter bucket, which contains a count, and optionally
+/// sub-aggregations.
+///
+/// # JSON Format
+/// ```json
+/// {
+///   ...
+///     "errors": {
+///       "doc_count": 34,
+///       "avg_latency": { "value": 512.0 }
+///     }
+///   ...
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FilterBucketEntry {
+    /// Number of documents in the bucket.
+    pub doc_count: u64,
+    #[serde(flatten)]
+    /// Sub-aggregations in this bucket.
+    pub sub_aggregation: AggregationResults,
+}
+
+/// This is the entry for a multi terms bucket, which contains a key with a term for every field,
+/// count, and optionally sub-aggregations.
+///
+/// # JSON Format
+/// ```json
+/// {
+///   ...
+///     "country_browser": {
+///       "buckets": [
+///         {
+///           "key": ["us", "firefox"],
+///           "key_as_string": "us|firefox",
+///           "doc_count": 6
+///         }
+///       ]
+///    }
+///    ...
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MultiTermsBucketEntry {
+    /// The key of the bucket, in the order of the fields.
+    pub key: Vec<Key>,
+    /// The terms of the key joined by `|`.
+    pub key_as_string: String,
+    /// Number of documents in the bucket.
+    pub doc_count: u64,
+    #[serde(flatten)]
+    /// Sub-aggregations in this bucket.
+    pub sub_aggregation: AggregationResults,
+}
+impl GetDocCount for MultiTermsBucketEntry {
+    fn doc_count(&self) -> u64 {
+        self.doc_count
+    }
+}
+
+/// This is the entry for a geo grid bucket, which contains the key of the cell, a count, the
+/// centroid of the points in the cell, and optionally sub-aggregations.
+///
+/// # JSON Format
+/// ```json
+/// {
+///   ...
+///     "clusters": {
+///       "buckets": [
+///         {
+///           "key": "u17",
+///           "doc_count": 3,
+///           "centroid": { "lat": 52.37, "lon": 4.9 }
+///         }
+///       ]
+///    }
+///    ...
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GeoGridBucketEntry {
+    /// The geohash or the `{zoom}/{x}/{y}` tile of the cell.
+    pub key: String,
+    /// Number of documents in the bucket.
+    pub doc_count: u64,
+    /// The mean of the points in the cell.
+    pub centroid: GeoPoint,
+    #[serde(flatten)]
+    /// Sub-aggregations in this bucket.
+    pub sub_aggregation: AggregationResults,
+}
+
+/// This is the entry for a significant terms bucket, which contains a key, the foreground and
+/// background counts, the score, and optionally sub-aggregations.
+///
+/// # JSON Format
+/// ```json
+/// {
+///   ...
+///     "failure_causes": {
+///       "doc_count": 47,
+///       "bg_count": 5064,
+///       "buckets": [
+///         {
+///           "key": "/checkout",
+///           "doc_count": 36,
+///           "bg_count": 667,
+///           "score": 0.37
+///         }
+///       ]
+///    }
+///    ...
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SignificantTermBucketEntry {
+    /// The term of the bucket.
+    pub key: Key,
+    /// Number of documents of the foreground set in the bucket.
+    pub doc_count: u64,
+    /// Number of documents of the background set in the bucket.
+    pub bg_count: u64,
+    /// The significance of the term.
+    pub score: f64,
+    #[serde(flatten)]
+    /// Sub-aggregations in this bucket.
+    pub sub_aggregation: AggregationResults,
+}
+
+/// This is the entry for a composite bucket, which contains a key with a value for every source,
+/// count, and optionally sub-aggregations.
+///
+/// # JSON Format
+/// ```json
+/// {
+///   ...
+///     "tenant_days": {
+///       "after_key": { "tenant": "acme", "day": 1546387200000000.0 },
+///       "buckets": [
+///         {
+///           "key": { "tenant": "acme", "day": 1546300800000000.0 },
+///           "doc_count": 5
+///         },
+///         {
+///           "key": { "tenant": "acme", "day": 1546387200000000.0 },
+///           "doc_count": 2
+///         }
+///       ]
+///    }
+///    ...
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CompositeBucketEntry {
+    /// The key of the bucket by source name.
+    pub key: FxHashMap<String, Key>,
+    /// Number of documents in the bucket.
+    pub doc_count: u64,
+    #[serde(flatten)]
+    /// Sub-aggregations in this bucket.
+    pub sub_aggregation: AggregationResults,
+}
+
 /// This is the range entry for a bucket, which contains a key, count, and optionally
 /// sub-aggregations.
 ///
@@ -0,0 +1,1083 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Debug;
+use std::mem;
+
+use columnar::{Column, MonotonicallyMappableToU64, StrColumn};
+use rustc_hash::FxHashMap;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{get_bucket_val, parse_into_milliseconds, parse_offset_into_milliseconds, Order};
+use crate::aggregation::agg_req_with_accessor::{
+    AggregationsWithAccessor, BucketAggregationWithAccessor,
+};
+use crate::aggregation::intermediate_agg_result::{
+    IntermediateBucketResult, IntermediateCompositeBucketEntry, IntermediateCompositeBucketResult,
+};
+use crate::aggregation::memory_budget::blueprint_memory;
+use crate::aggregation::segment_agg_result::{
+    build_segment_agg_collector, SegmentAggregationCollector,
+};
+use crate::aggregation::{f64_from_fastfield_u64, Key};
+use crate::schema::Type;
+use crate::{DocId, TantivyError};
+
+/// Creates multi-key buckets from the combinations of the values of several sources. The buckets
+/// are sorted by key and can be paginated, which allows to stream through all buckets of an
+/// aggregation, e.g. to export all combinations of (tenant, day, status).
+///
+/// Every source is a [`TermsCompositeSource`], a [`HistogramCompositeSource`] or a
+/// [`DateHistogramCompositeSource`]. A document falls into a bucket for every combination of
+/// the values of its sources.
+///
+/// Only the first [`size`](CompositeAggregation::size) buckets are returned. To fetch the next
+/// page, pass the `after_key` of the response as [`after`](CompositeAggregation::after) of the
+/// next request. When a page has no buckets, all buckets have been returned.
+///
+/// Result type is
+/// [`BucketResult::Composite`](crate::aggregation::agg_result::BucketResult::Composite) with
+/// [`CompositeBucketEntry`](crate::aggregation::agg_result::CompositeBucketEntry) on the
+/// `AggregationCollector`.
+///
+/// Result type is
+/// [`IntermediateBucketResult::Composite`](crate::aggregation::intermediate_agg_result::IntermediateBucketResult::Composite) with
+/// [`IntermediateCompositeBucketEntry`](crate::aggregation::intermediate_agg_result::IntermediateCompositeBucketEntry) on the
+/// `DistributedAggregationCollector`.
+///
+/// # Limitations/Compatibility
+/// Documents without a value for one of the sources are ignored, `missing_bucket` is not
+/// supported. The keys of date histogram sources are timestamps in microseconds, like the keys
+/// of the histogram aggregation on date fields.
+///
+/// # Request JSON Format
+/// ```json
+/// {
+///     "tenant_days": {
+///         "composite": {
+///             "size": 2,
+///             "sources": [
+///                 { "tenant": { "terms": { "field": "tenant" } } },
+///                 { "day": { "date_histogram": { "field": "timestamp", "fixed_interval": "1d" } } }
+///             ],
+///             "after": { "tenant": "acme", "day": 1546300800000000.0 }
+///         }
+///     }
+/// }
+/// ```
+///
+/// # Response JSON Format
+/// ```json
+/// {
+///     "tenant_days": {
+///         "after_key": { "tenant": "acme", "day": 1546473600000000.0 },
+///         "buckets": [
+///             { "key": { "tenant": "acme", "day": 1546387200000000.0 }, "doc_count": 4 },
+///             { "key": { "tenant": "acme", "day": 1546473600000000.0 }, "doc_count": 2 }
+///         ]
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CompositeAggregation {
+    /// The sources of the bucket keys. The buckets are sorted by the first source, then by the
+    /// second source and so on.
+    pub sources: Vec<CompositeSource>,
+    /// The number of buckets to return. Defaults to 10.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub size: Option<u32>,
+    /// Only buckets with a key after this key are returned. This is the `after_key` of the
+    /// previous page.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub after: Option<HashMap<String, Key>>,
+}
+
+impl CompositeAggregation {
+    /// Returns the number of buckets to return.
+    pub fn size(&self) -> usize {
+        self.size.unwrap_or(10) as usize
+    }
+
+    /// Compares two bucket keys in the order of the sources.
+    pub(crate) fn cmp_keys(&self, left: &[Key], right: &[Key]) -> Ordering {
+        for ((source, left), right) in self.sources.iter().zip(left).zip(right) {
+            let ordering = match (left, right) {
+                (Key::Str(left), Key::Str(right)) => left.cmp(right),
+                (Key::F64(left), Key::F64(right)) => left.total_cmp(right),
+                (Key::F64(_), Key::Str(_)) => Ordering::Less,
+                (Key::Str(_), Key::F64(_)) => Ordering::Greater,
+            };
+            let ordering = match source.source.order() {
+                Order::Asc => ordering,
+                Order::Desc => ordering.reverse(),
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+
+    fn validate(&self) -> crate::Result<()> {
+        if self.sources.is_empty() {
+            return Err(TantivyError::InvalidArgument(
+                "composite aggregation requires at least one source".to_string(),
+            ));
+        }
+        if self.size == Some(0) {
+            return Err(TantivyError::InvalidArgument(
+                "size of composite aggregation must be a positive value".to_string(),
+            ));
+        }
+        let mut names = HashSet::new();
+        for source in &self.sources {
+            if !names.insert(source.name.as_str()) {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "duplicate source name {:?} in composite aggregation",
+                    source.name
+                )));
+            }
+            source.source.validate()?;
+        }
+        if let Some(after) = &self.after {
+            if after.len() != self.sources.len()
+                || !after.keys().all(|name| names.contains(name.as_str()))
+            {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "after key {:?} of composite aggregation must contain exactly the sources {:?}",
+                    after.keys().collect::<Vec<_>>(),
+                    names
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A named source of a [`CompositeAggregation`].
+///
+/// De/Serializes to elasticsearch compatible JSON, e.g.
+/// `{ "tenant": { "terms": { "field": "tenant" } } }`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompositeSource {
+    /// The name of the source, which is used in the bucket keys.
+    pub name: String,
+    /// The source of the values.
+    pub source: CompositeSourceType,
+}
+
+impl Serialize for CompositeSource {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer {
+        let map: HashMap<&str, &CompositeSourceType> =
+            std::iter::once((self.name.as_str(), &self.source)).collect();
+        map.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CompositeSource {
+    fn deserialize<D>(deserializer: D) -> Result<CompositeSource, D::Error>
+    where D: Deserializer<'de> {
+        let map = HashMap::<String, CompositeSourceType>::deserialize(deserializer)?;
+        if map.len() != 1 {
+            return Err(de::Error::custom(format!(
+                "expected exactly one named source in composite source, got {}",
+                map.len()
+            )));
+        }
+        let (name, source) = map.into_iter().next().unwrap();
+        Ok(CompositeSource { name, source })
+    }
+}
+
+/// The source types of a [`CompositeAggregation`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CompositeSourceType {
+    /// Uses the terms or numeric values of a field.
+    #[serde(rename = "terms")]
+    Terms(TermsCompositeSource),
+    /// Uses the histogram bucket of the values of a numeric field.
+    #[serde(rename = "histogram")]
+    Histogram(HistogramCompositeSource),
+    /// Uses the date histogram bucket of the values of a date field.
+    #[serde(rename = "date_histogram")]
+    DateHistogram(DateHistogramCompositeSource),
+}
+
+impl CompositeSourceType {
+    /// Returns the field of the source.
+    pub fn field(&self) -> &str {
+        match self {
+            CompositeSourceType::Terms(terms) => &terms.field,
+            CompositeSourceType::Histogram(histogram) => &histogram.field,
+            CompositeSourceType::DateHistogram(date_histogram) => &date_histogram.field,
+        }
+    }
+
+    /// Returns the order of the source. Defaults to ascending.
+    pub fn order(&self) -> Order {
+        match self {
+            CompositeSourceType::Terms(terms) => terms.order,
+            CompositeSourceType::Histogram(histogram) => histogram.order,
+            CompositeSourceType::DateHistogram(date_histogram) => date_histogram.order,
+        }
+        .unwrap_or(Order::Asc)
+    }
+
+    fn validate(&self) -> crate::Result<()> {
+        match self {
+            CompositeSourceType::Terms(_) => {}
+            CompositeSourceType::Histogram(histogram) => {
+                if histogram.interval <= 0.0f64 {
+                    return Err(TantivyError::InvalidArgument(
+                        "interval must be a positive value".to_string(),
+                    ));
+                }
+            }
+            CompositeSourceType::DateHistogram(date_histogram) => {
+                date_histogram.interval_and_offset()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A composite source with the values of a string or numeric field.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TermsCompositeSource {
+    /// The field to aggregate on.
+    pub field: String,
+    /// The order of the keys. Defaults to ascending.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub order: Option<Order>,
+}
+
+/// A composite source with histogram buckets of a numeric field, see
+/// [`HistogramAggregation`](super::HistogramAggregation).
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct HistogramCompositeSource {
+    /// The field to aggregate on.
+    pub field: String,
+    /// The interval to chunk your data range. Each bucket spans a value range of [0..interval).
+    /// Must be a positive value.
+    pub interval: f64,
+    /// Shifts the grid of buckets by `offset`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub offset: Option<f64>,
+    /// The order of the keys. Defaults to ascending.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub order: Option<Order>,
+}
+
+/// A composite source with date histogram buckets of a date field, see
+/// [`DateHistogramAggregationReq`](super::DateHistogramAggregationReq).
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DateHistogramCompositeSource {
+    /// The field to aggregate on.
+    pub field: String,
+    /// The fixed interval of the buckets, e.g. `30d`. Accepted units are `ms`, `s`, `m`, `h`
+    /// and `d`.
+    pub fixed_interval: String,
+    /// Shifts the grid of buckets by `offset`, e.g. `-6h`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub offset: Option<String>,
+    /// The order of the keys. Defaults to ascending.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub order: Option<Order>,
+}
+
+impl DateHistogramCompositeSource {
+    /// Returns interval and offset in microseconds, the resolution of date fast fields.
+    fn interval_and_offset(&self) -> crate::Result<(f64, f64)> {
+        let interval = parse_into_milliseconds(&self.fixed_interval)?;
+        if interval == 0 {
+            return Err(TantivyError::InvalidArgument(
+                "interval must be a positive value".to_string(),
+            ));
+        }
+        let offset = match self.offset.as_deref() {
+            Some(offset) => parse_offset_into_milliseconds(offset)?,
+            None => 0,
+        };
+        Ok((interval as f64 * 1000.0, offset as f64 * 1000.0))
+    }
+}
+
+/// The fast field of a composite source in a segment.
+#[derive(Clone)]
+pub(crate) struct CompositeSourceAccessor {
+    pub(crate) accessor: Column<u64>,
+    pub(crate) str_dict_column: Option<StrColumn>,
+    pub(crate) field_type: Type,
+}
+
+#[derive(Clone, Debug)]
+enum SegmentCompositeSourceKind {
+    /// Terms of a string field.
+    ///
+    /// The term ordinal `ord` is encoded as `2 * ord + 1`, so that an `after` term, which
+    /// doesn't exist in the segment, can be encoded as an even value between the ordinals.
+    StrTerms,
+    /// Values of a numeric field.
+    NumericTerms,
+    /// Histogram buckets of a numeric field.
+    Histogram { interval: f64, offset: f64 },
+}
+
+/// A composite source in a segment.
+///
+/// Values are encoded as `u64`, so that the order of the encoded values matches the requested
+/// order of the keys.
+#[derive(Clone, Debug)]
+struct SegmentCompositeSource {
+    kind: SegmentCompositeSourceKind,
+    field_type: Type,
+    order: Order,
+}
+
+impl SegmentCompositeSource {
+    fn from_req_and_validate(
+        source: &CompositeSourceType,
+        accessor: &CompositeSourceAccessor,
+    ) -> crate::Result<Self> {
+        let is_numeric = matches!(
+            accessor.field_type,
+            Type::U64 | Type::I64 | Type::F64 | Type::Date
+        );
+        let kind = match source {
+            CompositeSourceType::Terms(_) if accessor.str_dict_column.is_some() => {
+                SegmentCompositeSourceKind::StrTerms
+            }
+            CompositeSourceType::Terms(_) if is_numeric => SegmentCompositeSourceKind::NumericTerms,
+            CompositeSourceType::Histogram(histogram) if is_numeric => {
+                SegmentCompositeSourceKind::Histogram {
+                    interval: histogram.interval,
+                    offset: histogram.offset.unwrap_or(0.0),
+                }
+            }
+            CompositeSourceType::DateHistogram(date_histogram)
+                if accessor.field_type == Type::Date =>
+            {
+                let (interval, offset) = date_histogram.interval_and_offset()?;
+                SegmentCompositeSourceKind::Histogram { interval, offset }
+            }
+            _ => {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "composite source on field {:?} is not supported for field type {:?}",
+                    source.field(),
+                    accessor.field_type
+                )))
+            }
+        };
+        Ok(SegmentCompositeSource {
+            kind,
+            field_type: accessor.field_type,
+            order: source.order(),
+        })
+    }
+
+    #[inline]
+    fn encode(&self, val: u64) -> u64 {
+        let encoded = match self.kind {
+            SegmentCompositeSourceKind::StrTerms => 2 * val + 1,
+            SegmentCompositeSourceKind::NumericTerms => {
+                f64_from_fastfield_u64(val, &self.field_type).to_u64()
+            }
+            SegmentCompositeSourceKind::Histogram { interval, offset } => {
+                let val = f64_from_fastfield_u64(val, &self.field_type);
+                get_bucket_val(val, interval, offset).to_u64()
+            }
+        };
+        self.apply_order(encoded)
+    }
+
+    fn encode_after(&self, key: &Key, accessor: &CompositeSourceAccessor) -> crate::Result<u64> {
+        let encoded = match (&self.kind, key) {
+            (SegmentCompositeSourceKind::StrTerms, Key::Str(term)) => {
+                let dictionary = accessor
+                    .str_dict_column
+                    .as_ref()
+                    .expect("internal error: term dictionary not loaded for composite source")
+                    .dictionary();
+                if let Some(ord) = dictionary.term_ord(term)? {
+                    2 * ord + 1
+                } else {
+                    let mut stream = dictionary.range().gt(term).into_stream()?;
+                    let next_ord = if stream.advance() {
+                        stream.term_ord()
+                    } else {
+                        dictionary.num_terms() as u64
+                    };
+                    2 * next_ord
+                }
+            }
+            (SegmentCompositeSourceKind::StrTerms, Key::F64(_)) => {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "expected string in after key of composite aggregation, got {:?}",
+                    key
+                )))
+            }
+            (_, Key::F64(val)) => val.to_u64(),
+            (_, Key::Str(_)) => {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "expected number in after key of composite aggregation, got {:?}",
+                    key
+                )))
+            }
+        };
+        Ok(self.apply_order(encoded))
+    }
+
+    fn decode(
+        &self,
+        encoded: u64,
+        accessor: &CompositeSourceAccessor,
+        buffer: &mut String,
+    ) -> crate::Result<Key> {
+        let val = self.apply_order(encoded);
+        match self.kind {
+            SegmentCompositeSourceKind::StrTerms => {
+                let term_ord = val / 2;
+                let term_dict = accessor
+                    .str_dict_column
+                    .as_ref()
+                    .expect("internal error: term dictionary not loaded for composite source");
+                if !term_dict.ord_to_str(term_ord, buffer)? {
+                    return Err(TantivyError::InternalError(format!(
+                        "Couldn't find term_ord {} in dict",
+                        term_ord
+                    )));
+                }
+                Ok(Key::Str(buffer.to_string()))
+            }
+            SegmentCompositeSourceKind::NumericTerms
+            | SegmentCompositeSourceKind::Histogram { .. } => Ok(Key::F64(f64::from_u64(val))),
+        }
+    }
+
+    /// Inverts the encoded value for descending order. Applying it twice is a no-op.
+    #[inline]
+    fn apply_order(&self, encoded: u64) -> u64 {
+        match self.order {
+            Order::Asc => encoded,
+            Order::Desc => !encoded,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct SegmentCompositeBucketEntry {
+    doc_count: u64,
+    sub_aggregations: Option<Box<dyn SegmentAggregationCollector>>,
+}
+
+impl Debug for SegmentCompositeBucketEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SegmentCompositeBucketEntry")
+            .field("doc_count", &self.doc_count)
+            .finish()
+    }
+}
+
+impl SegmentCompositeBucketEntry {
+    fn from_blueprint(blueprint: &Option<Box<dyn SegmentAggregationCollector>>) -> Self {
+        Self {
+            doc_count: 0,
+            sub_aggregations: blueprint.clone(),
+        }
+    }
+
+    fn collect(
+        &mut self,
+        doc: DocId,
+        sub_aggregation_accessor: &AggregationsWithAccessor,
+    ) -> crate::Result<()> {
+        self.doc_count += 1;
+        if let Some(sub_aggregations) = self.sub_aggregations.as_mut() {
+            sub_aggregations.collect(doc, sub_aggregation_accessor)?;
+        }
+        Ok(())
+    }
+}
+
+/// The first `size` buckets after the `after` key, by encoded key.
+#[derive(Clone, Debug)]
+struct CompositeBuckets {
+    entries: BTreeMap<Vec<u64>, SegmentCompositeBucketEntry>,
+    size: usize,
+    after_key: Option<Vec<u64>>,
+    blueprint: Option<Box<dyn SegmentAggregationCollector>>,
+}
+
+impl CompositeBuckets {
+    #[inline]
+    fn collect(
+        &mut self,
+        key: &[u64],
+        doc: DocId,
+        sub_aggregation_accessor: &AggregationsWithAccessor,
+    ) -> crate::Result<()> {
+        if let Some(after_key) = &self.after_key {
+            if key <= after_key.as_slice() {
+                return Ok(());
+            }
+        }
+        if let Some(entry) = self.entries.get_mut(key) {
+            return entry.collect(doc, sub_aggregation_accessor);
+        }
+        if self.entries.len() >= self.size {
+            // The evicted bucket has `size` smaller buckets in this segment, so it can't make it
+            // into the page anymore. The same holds for all keys greater than the largest key.
+            let max_key = self
+                .entries
+                .keys()
+                .next_back()
+                .expect("internal error: size of composite aggregation is zero");
+            if key > max_key.as_slice() {
+                return Ok(());
+            }
+            let max_key = max_key.clone();
+            self.entries.remove(&max_key);
+        }
+        let mut entry = SegmentCompositeBucketEntry::from_blueprint(&self.blueprint);
+        entry.collect(doc, sub_aggregation_accessor)?;
+        self.entries.insert(key.to_vec(), entry);
+        Ok(())
+    }
+
+    fn force_flush(&mut self, agg_with_accessor: &AggregationsWithAccessor) -> crate::Result<()> {
+        for entry in self.entries.values_mut() {
+            if let Some(sub_aggregations) = entry.sub_aggregations.as_mut() {
+                sub_aggregations.flush_staged_docs(agg_with_accessor, false)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Estimates the memory of the buckets, which is bounded by `size` buckets.
+    fn memory_consumption(&self, num_sources: usize) -> u64 {
+        let entry_memory = mem::size_of::<(Vec<u64>, SegmentCompositeBucketEntry)>()
+            + num_sources * mem::size_of::<u64>();
+        self.entries.len() as u64 * (entry_memory as u64 + blueprint_memory(&self.blueprint))
+    }
+}
+
+/// The collector for the composite aggregation in a segment.
+///
+/// Since every segment keeps the first `size` buckets after the `after` key, merging the
+/// buckets of all segments and cutting off after `size` buckets gives exact results.
+#[derive(Clone, Debug)]
+pub(crate) struct SegmentCompositeCollector {
+    sources: Vec<SegmentCompositeSource>,
+    buckets: CompositeBuckets,
+    /// Buffers for the encoded values of one document, one per source.
+    values_buffer: Vec<Vec<u64>>,
+    /// Buffers to build the keys of one document.
+    positions_buffer: Vec<usize>,
+    key_buffer: Vec<u64>,
+}
+
+impl SegmentCompositeCollector {
+    pub(crate) fn from_req_and_validate(
+        req: &CompositeAggregation,
+        bucket_with_accessor: &BucketAggregationWithAccessor,
+    ) -> crate::Result<Self> {
+        req.validate()?;
+        let source_accessors = &bucket_with_accessor.composite_sources;
+        let sources = req
+            .sources
+            .iter()
+            .zip(source_accessors)
+            .map(|(source, accessor)| {
+                SegmentCompositeSource::from_req_and_validate(&source.source, accessor)
+            })
+            .collect::<crate::Result<Vec<_>>>()?;
+        let after_key = if let Some(after) = &req.after {
+            let after_key = req
+                .sources
+                .iter()
+                .zip(&sources)
+                .zip(source_accessors)
+                .map(|((source, segment_source), accessor)| {
+                    // Validation ensures that all sources are in the after key.
+                    segment_source.encode_after(&after[&source.name], accessor)
+                })
+                .collect::<crate::Result<Vec<_>>>()?;
+            Some(after_key)
+        } else {
+            None
+        };
+
+        let sub_aggregations = &bucket_with_accessor.sub_aggregation;
+        let blueprint = if sub_aggregations.is_empty() {
+            None
+        } else {
+            Some(build_segment_agg_collector(sub_aggregations)?)
+        };
+
+        let num_sources = sources.len();
+        Ok(SegmentCompositeCollector {
+            sources,
+            buckets: CompositeBuckets {
+                entries: BTreeMap::new(),
+                size: req.size(),
+                after_key,
+                blueprint,
+            },
+            values_buffer: vec![Vec::new(); num_sources],
+            positions_buffer: vec![0; num_sources],
+            key_buffer: Vec::with_capacity(num_sources),
+        })
+    }
+
+    pub(crate) fn into_intermediate_bucket_result(
+        self,
+        agg_with_accessor: &BucketAggregationWithAccessor,
+    ) -> crate::Result<IntermediateBucketResult> {
+        let bucket_count = &agg_with_accessor.bucket_count;
+        bucket_count.add_count(self.buckets.entries.len() as u32);
+        bucket_count.validate_bucket_count()?;
+
+        let mut buckets = FxHashMap::default();
+        let mut buffer = String::new();
+        for (encoded_key, entry) in self.buckets.entries {
+            let key = self
+                .sources
+                .iter()
+                .zip(encoded_key)
+                .zip(&agg_with_accessor.composite_sources)
+                .map(|((source, encoded), accessor)| source.decode(encoded, accessor, &mut buffer))
+                .collect::<crate::Result<Vec<Key>>>()?;
+            let sub_aggregation = if let Some(sub_aggregation) = entry.sub_aggregations {
+                sub_aggregation
+                    .into_intermediate_aggregations_result(&agg_with_accessor.sub_aggregation)?
+            } else {
+                Default::default()
+            };
+            buckets.insert(
+                serde_json::to_string(&key)?,
+                IntermediateCompositeBucketEntry {
+                    key,
+                    doc_count: entry.doc_count,
+                    sub_aggregation,
+                },
+            );
+        }
+        Ok(IntermediateBucketResult::Composite(
+            IntermediateCompositeBucketResult { buckets },
+        ))
+    }
+
+    #[inline]
+    pub(crate) fn collect_block(
+        &mut self,
+        docs: &[DocId],
+        bucket_with_accessor: &BucketAggregationWithAccessor,
+        force_flush: bool,
+    ) -> crate::Result<()> {
+        let source_accessors = &bucket_with_accessor.composite_sources;
+        let sub_aggregation_accessor = &bucket_with_accessor.sub_aggregation;
+        let memory_before = self.buckets.memory_consumption(self.sources.len());
+
+        'docs: for &doc in docs {
+            for ((source, accessor), values) in self
+                .sources
+                .iter()
+                .zip(source_accessors)
+                .zip(self.values_buffer.iter_mut())
+            {
+                values.clear();
+                values.extend(accessor.accessor.values(doc).map(|val| source.encode(val)));
+                if values.is_empty() {
+                    continue 'docs;
+                }
+                values.sort_unstable();
+                values.dedup();
+            }
+
+            // Collect the doc into the bucket of every combination of the values.
+            let buckets = &mut self.buckets;
+            for_each_combination(
+                &self.values_buffer,
+                &mut self.positions_buffer,
+                &mut self.key_buffer,
+                |key| buckets.collect(key, doc, sub_aggregation_accessor),
+            )?;
+        }
+        let memory_after = self.buckets.memory_consumption(self.sources.len());
+        bucket_with_accessor
+            .memory_account
+            .add_memory_consumed(memory_after.saturating_sub(memory_before))?;
+
+        if force_flush {
+            self.buckets.force_flush(sub_aggregation_accessor)?;
+        }
+        Ok(())
+    }
+}
+
+/// Calls `collect` with every combination of one value of each of the `values`.
+///
+/// `positions_buffer` must have the same length as `values`, and none of the `values` may be
+/// empty.
+#[inline]
+pub(crate) fn for_each_combination(
+    values: &[Vec<u64>],
+    positions_buffer: &mut [usize],
+    key_buffer: &mut Vec<u64>,
+    mut collect: impl FnMut(&[u64]) -> crate::Result<()>,
+) -> crate::Result<()> {
+    positions_buffer.iter_mut().for_each(|pos| *pos = 0);
+    loop {
+        key_buffer.clear();
+        key_buffer.extend(
+            positions_buffer
+                .iter()
+                .zip(values)
+                .map(|(&pos, values)| values[pos]),
+        );
+        collect(key_buffer)?;
+
+        let mut source_pos = positions_buffer.len();
+        loop {
+            if source_pos == 0 {
+                return Ok(());
+            }
+            source_pos -= 1;
+            positions_buffer[source_pos] += 1;
+            if positions_buffer[source_pos] < values[source_pos].len() {
+                break;
+            }
+            positions_buffer[source_pos] = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::aggregation::agg_req::Aggregations;
+    use crate::aggregation::tests::{
+        exec_request, get_test_index_2_segments, get_test_index_from_values_and_terms,
+    };
+
+    fn get_test_index(merge_segments: bool) -> crate::Result<crate::Index> {
+        let segment_and_values = vec![
+            vec![(1.0, "acme".to_string()), (12.0, "globex".to_string())],
+            vec![(3.0, "acme".to_string()), (15.0, "acme".to_string())],
+            vec![
+                (5.0, "initech".to_string()),
+                (11.0, "globex".to_string()),
+                (25.0, "acme".to_string()),
+            ],
+        ];
+        get_test_index_from_values_and_terms(merge_segments, &segment_and_values)
+    }
+
+    fn composite_request(size: u32, after: Option<&Value>) -> Aggregations {
+        let mut composite = json!({
+            "size": size,
+            "sources": [
+                { "tenant": { "terms": { "field": "string_id" } } },
+                { "score": { "histogram": { "field": "score", "interval": 10.0 } } }
+            ]
+        });
+        if let Some(after) = after {
+            composite["after"] = after.clone();
+        }
+        serde_json::from_value(json!({ "tenants": { "composite": composite } })).unwrap()
+    }
+
+    #[test]
+    fn composite_aggregation_test() -> crate::Result<()> {
+        composite_aggregation_test_merge_segment(false)?;
+        composite_aggregation_test_merge_segment(true)
+    }
+
+    fn composite_aggregation_test_merge_segment(merge_segments: bool) -> crate::Result<()> {
+        let index = get_test_index(merge_segments)?;
+
+        let res = exec_request(composite_request(2, None), &index)?;
+        assert_eq!(
+            res["tenants"],
+            json!({
+                "after_key": { "tenant": "acme", "score": 10.0 },
+                "buckets": [
+                    { "key": { "tenant": "acme", "score": 0.0 }, "doc_count": 2 },
+                    { "key": { "tenant": "acme", "score": 10.0 }, "doc_count": 1 }
+                ]
+            })
+        );
+
+        let res = exec_request(
+            composite_request(2, Some(&res["tenants"]["after_key"])),
+            &index,
+        )?;
+        assert_eq!(
+            res["tenants"],
+            json!({
+                "after_key": { "tenant": "globex", "score": 10.0 },
+                "buckets": [
+                    { "key": { "tenant": "acme", "score": 20.0 }, "doc_count": 1 },
+                    { "key": { "tenant": "globex", "score": 10.0 }, "doc_count": 2 }
+                ]
+            })
+        );
+
+        let res = exec_request(
+            composite_request(2, Some(&res["tenants"]["after_key"])),
+            &index,
+        )?;
+        assert_eq!(
+            res["tenants"],
+            json!({
+                "after_key": { "tenant": "initech", "score": 0.0 },
+                "buckets": [
+                    { "key": { "tenant": "initech", "score": 0.0 }, "doc_count": 1 }
+                ]
+            })
+        );
+
+        let res = exec_request(
+            composite_request(2, Some(&res["tenants"]["after_key"])),
+            &index,
+        )?;
+        assert_eq!(res["tenants"], json!({ "buckets": [] }));
+        Ok(())
+    }
+
+    #[test]
+    fn composite_aggregation_pagination_matches_single_page() -> crate::Result<()> {
+        let segment_and_values: Vec<Vec<(f64, String)>> = (0..10)
+            .map(|segment| {
+                (0..20)
+                    .map(|i| {
+                        let val = ((segment * 7 + i * 13) % 50) as f64;
+                        (val, format!("term{}", (segment + i) % 7))
+                    })
+                    .collect()
+            })
+            .collect();
+        let index = get_test_index_from_values_and_terms(false, &segment_and_values)?;
+
+        let all_buckets =
+            exec_request(composite_request(1000, None), &index)?["tenants"]["buckets"].clone();
+
+        let mut paginated_buckets = Vec::new();
+        let mut after_key = None;
+        loop {
+            let res = exec_request(composite_request(3, after_key.as_ref()), &index)?;
+            let buckets = res["tenants"]["buckets"].as_array().unwrap().clone();
+            if buckets.is_empty() {
+                break;
+            }
+            assert!(buckets.len() <= 3);
+            paginated_buckets.extend(buckets);
+            after_key = Some(res["tenants"]["after_key"].clone());
+        }
+        assert_eq!(Value::Array(paginated_buckets), all_buckets);
+        assert_eq!(
+            all_buckets
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|bucket| bucket["doc_count"].as_u64().unwrap())
+                .sum::<u64>(),
+            200
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn composite_aggregation_desc_order_and_missing_after_term() -> crate::Result<()> {
+        let index = get_test_index(false)?;
+
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "tenants": {
+                "composite": {
+                    "sources": [
+                        { "tenant": { "terms": { "field": "string_id", "order": "desc" } } },
+                        { "score": { "terms": { "field": "score_i64" } } }
+                    ],
+                    "after": { "tenant": "hooli", "score": 0.0 }
+                }
+            }
+        }))
+        .unwrap();
+
+        let res = exec_request(agg_req, &index)?;
+        assert_eq!(
+            res["tenants"]["buckets"],
+            json!([
+                { "key": { "tenant": "globex", "score": 11.0 }, "doc_count": 1 },
+                { "key": { "tenant": "globex", "score": 12.0 }, "doc_count": 1 },
+                { "key": { "tenant": "acme", "score": 1.0 }, "doc_count": 1 },
+                { "key": { "tenant": "acme", "score": 3.0 }, "doc_count": 1 },
+                { "key": { "tenant": "acme", "score": 15.0 }, "doc_count": 1 },
+                { "key": { "tenant": "acme", "score": 25.0 }, "doc_count": 1 }
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn composite_aggregation_date_histogram_and_multi_values() -> crate::Result<()> {
+        let index = get_test_index_2_segments(false)?;
+
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "days": {
+                "composite": {
+                    "sources": [
+                        { "day": { "date_histogram": { "field": "date", "fixed_interval": "1d" } } },
+                        { "text": { "terms": { "field": "text" } } }
+                    ]
+                },
+                "aggs": {
+                    "avg_score": { "avg": { "field": "score" } }
+                }
+            },
+            "scores": {
+                "composite": {
+                    "sources": [ { "score": { "terms": { "field": "scores_i64" } } } ]
+                }
+            }
+        }))
+        .unwrap();
+
+        let res = exec_request(agg_req, &index)?;
+        assert_eq!(
+            res["days"]["buckets"],
+            json!([
+                {
+                    "key": { "day": 1546300800000000.0, "text": "cool" },
+                    "doc_count": 1,
+                    "avg_score": { "value": 1.0 }
+                },
+                {
+                    "key": { "day": 1546387200000000.0, "text": "cool" },
+                    "doc_count": 4,
+                    "avg_score": { "value": 6.5 }
+                },
+                {
+                    "key": { "day": 1546387200000000.0, "text": "nohit" },
+                    "doc_count": 1,
+                    "avg_score": { "value": 6.0 }
+                },
+                {
+                    "key": { "day": 1546473600000000.0, "text": "cool" },
+                    "doc_count": 2,
+                    "avg_score": { "value": 29.0 }
+                },
+                {
+                    "key": { "day": 1546473600000000.0, "text": "nohit" },
+                    "doc_count": 1,
+                    "avg_score": { "value": 44.0 }
+                }
+            ])
+        );
+        // A document is counted once per distinct value.
+        assert_eq!(
+            res["scores"]["buckets"],
+            json!([
+                { "key": { "score": 1.0 }, "doc_count": 1 },
+                { "key": { "score": 2.0 }, "doc_count": 1 },
+                { "key": { "score": 5.0 }, "doc_count": 1 }
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn composite_aggregation_invalid_requests() -> crate::Result<()> {
+        let index = get_test_index(true)?;
+
+        let exec = |composite: Value| {
+            let agg_req: Aggregations =
+                serde_json::from_value(json!({ "tenants": { "composite": composite } })).unwrap();
+            exec_request(agg_req, &index)
+        };
+
+        assert!(exec(json!({ "sources": [] })).is_err());
+        assert!(exec(json!({
+            "sources": [
+                { "tenant": { "terms": { "field": "string_id" } } },
+                { "tenant": { "terms": { "field": "score" } } }
+            ]
+        }))
+        .is_err());
+        assert!(exec(json!({
+            "size": 0,
+            "sources": [ { "tenant": { "terms": { "field": "string_id" } } } ]
+        }))
+        .is_err());
+        // after must contain all sources with matching types
+        assert!(exec(json!({
+            "sources": [
+                { "tenant": { "terms": { "field": "string_id" } } },
+                { "score": { "terms": { "field": "score" } } }
+            ],
+            "after": { "tenant": "acme" }
+        }))
+        .is_err());
+        assert!(exec(json!({
+            "sources": [ { "tenant": { "terms": { "field": "string_id" } } } ],
+            "after": { "tenant": 1.0 }
+        }))
+        .is_err());
+        assert!(exec(json!({
+            "sources": [ { "score": { "histogram": { "field": "string_id", "interval": 1.0 } } } ]
+        }))
+        .is_err());
+        assert!(exec(json!({
+            "sources": [ { "score": { "histogram": { "field": "score", "interval": 0.0 } } } ]
+        }))
+        .is_err());
+        assert!(exec(json!({
+            "sources": [
+                { "day": { "date_histogram": { "field": "score", "fixed_interval": "1d" } } }
+            ]
+        }))
+        .is_err());
+
+        let invalid_source: serde_json::Result<CompositeSource> = serde_json::from_value(json!({
+            "a": { "terms": { "field": "string_id" } },
+            "b": { "terms": { "field": "string_id" } }
+        }));
+        assert!(invalid_source.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn composite_aggregation_serde_roundtrip() {
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "tenants": {
+                "composite": {
+                    "size": 5,
+                    "sources": [
+                        { "tenant": { "terms": { "field": "string_id", "order": "desc" } } },
+                        { "day": { "date_histogram": {
+                            "field": "date", "fixed_interval": "1d", "offset": "-6h"
+                        } } }
+                    ],
+                    "after": { "tenant": "acme", "day": 0.0 }
+                }
+            }
+        }))
+        .unwrap();
+        let roundtrip: Aggregations =
+            serde_json::from_str(&serde_json::to_string(&agg_req).unwrap()).unwrap();
+        assert_eq!(agg_req, roundtrip);
+
+        let source = DateHistogramCompositeSource {
+            field: "date".to_string(),
+            fixed_interval: "1d".to_string(),
+            offset: Some("-6h".to_string()),
+            order: None,
+        };
+        assert_eq!(
+            source.interval_and_offset().unwrap(),
+            (86_400_000_000.0, -21_600_000_000.0)
+        );
+    }
+}
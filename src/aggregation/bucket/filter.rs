@@ -0,0 +1,767 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use rustc_hash::FxHashMap;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::aggregation::agg_req::BucketAggregationType;
+use crate::aggregation::agg_req_with_accessor::{
+    AggregationsWithAccessor, BucketAggregationWithAccessor,
+};
+use crate::aggregation::intermediate_agg_result::{
+    IntermediateBucketResult, IntermediateFilterBucketEntry, IntermediateFiltersBucketResult,
+};
+use crate::aggregation::segment_agg_result::{
+    build_segment_agg_collector, BucketCount, SegmentAggregationCollector,
+};
+use crate::query::{EnableScoring, Query, QueryParser, Scorer, Weight};
+use crate::{DocId, DocSet, SegmentReader, TERMINATED};
+
+/// The default key of the bucket, which contains the documents that match none of the filters.
+pub const DEFAULT_OTHER_BUCKET_KEY: &str = "_other_";
+
+/// The query, which defines the documents of a filter bucket.
+///
+/// De/Serializes from/to a query string in the tantivy query language, e.g. `"level:error"`.
+///
+/// Query strings are parsed with the [`QueryParser`] against the schema and the tokenizers of
+/// the index, without default fields, so every term has to be prefixed with its field name.
+/// To use queries which can't be expressed in the query language, build the query directly
+/// and pass it as [`FilterQuery::Query`].
+#[derive(Debug)]
+pub enum FilterQuery {
+    /// A query string, which is parsed with the [`QueryParser`].
+    QueryString(String),
+    /// A query object. This variant can't be serialized.
+    Query(Box<dyn Query>),
+}
+
+impl FilterQuery {
+    fn weight(&self, reader: &SegmentReader) -> crate::Result<Box<dyn Weight>> {
+        let schema = reader.schema();
+        let enable_scoring = EnableScoring::disabled_from_schema(schema);
+        match self {
+            FilterQuery::QueryString(query_str) => {
+                let query_parser =
+                    QueryParser::new(schema.clone(), Vec::new(), reader.tokenizers().clone());
+                query_parser.parse_query(query_str)?.weight(enable_scoring)
+            }
+            FilterQuery::Query(query) => query.weight(enable_scoring),
+        }
+    }
+}
+
+impl Clone for FilterQuery {
+    fn clone(&self) -> Self {
+        match self {
+            FilterQuery::QueryString(query_str) => FilterQuery::QueryString(query_str.clone()),
+            FilterQuery::Query(query) => FilterQuery::Query(query.box_clone()),
+        }
+    }
+}
+
+/// Filter queries are compared by their query string.
+///
+/// Query objects don't implement `PartialEq`, so a [`FilterQuery::Query`] is never equal to
+/// another filter query.
+impl PartialEq for FilterQuery {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (FilterQuery::QueryString(left), FilterQuery::QueryString(right)) => left == right,
+            _ => false,
+        }
+    }
+}
+
+impl From<&str> for FilterQuery {
+    fn from(query_str: &str) -> Self {
+        FilterQuery::QueryString(query_str.to_string())
+    }
+}
+
+impl From<Box<dyn Query>> for FilterQuery {
+    fn from(query: Box<dyn Query>) -> Self {
+        FilterQuery::Query(query)
+    }
+}
+
+impl Serialize for FilterQuery {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer {
+        match self {
+            FilterQuery::QueryString(query_str) => serializer.serialize_str(query_str),
+            FilterQuery::Query(query) => Err(serde::ser::Error::custom(format!(
+                "can't serialize query object {:?}, use a query string instead",
+                query
+            ))),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FilterQuery {
+    fn deserialize<D>(deserializer: D) -> Result<FilterQuery, D::Error>
+    where D: Deserializer<'de> {
+        let query_str = String::deserialize(deserializer)?;
+        if query_str.trim().is_empty() {
+            return Err(de::Error::custom("unexpected empty filter query"));
+        }
+        Ok(FilterQuery::QueryString(query_str))
+    }
+}
+
+/// A single bucket aggregation, which contains all documents matching the filter query.
+///
+/// Sub-aggregations are computed on the documents in the bucket, which are the documents
+/// collected by the search query that also match the filter.
+///
+/// Result type is [`BucketResult::Filter`](crate::aggregation::agg_result::BucketResult::Filter)
+/// on the `AggregationCollector`.
+///
+/// Result type is
+/// [`IntermediateBucketResult::Filter`](crate::aggregation::intermediate_agg_result::IntermediateBucketResult::Filter)
+/// on the `DistributedAggregationCollector`.
+///
+/// # Limitations/Compatibility
+/// The filter is a query string in the tantivy query language (see [`FilterQuery`]) and not an
+/// elasticsearch query DSL object.
+///
+/// # Request JSON Format
+/// ```json
+/// {
+///     "errors": {
+///         "filter": "level:error",
+///         "aggs": {
+///             "avg_latency": { "avg": { "field": "latency" } }
+///         }
+///     }
+/// }
+/// ```
+///
+/// # Response JSON Format
+/// ```json
+/// {
+///     "errors": {
+///         "doc_count": 34,
+///         "avg_latency": { "value": 512.0 }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FilterAggregation {
+    /// The query which defines the documents in the bucket.
+    pub filter: FilterQuery,
+}
+
+impl FilterAggregation {
+    /// Creates a new [`FilterAggregation`] instance from a query.
+    pub fn new(filter: impl Into<FilterQuery>) -> Self {
+        Self {
+            filter: filter.into(),
+        }
+    }
+}
+
+/// A multi bucket aggregation, where each bucket contains the documents matching its filter
+/// query. A document can fall into multiple buckets.
+///
+/// Sub-aggregations are computed for every bucket, which allows e.g. to compare metrics of
+/// "errors vs warnings vs info" side-by-side in a single search.
+///
+/// Result type is [`BucketResult::Filters`](crate::aggregation::agg_result::BucketResult::Filters)
+/// with [`FilterBucketEntry`](crate::aggregation::agg_result::FilterBucketEntry) on the
+/// `AggregationCollector`.
+///
+/// Result type is
+/// [`IntermediateBucketResult::Filters`](crate::aggregation::intermediate_agg_result::IntermediateBucketResult::Filters) with
+/// [`IntermediateFilterBucketEntry`](crate::aggregation::intermediate_agg_result::IntermediateFilterBucketEntry) on the
+/// `DistributedAggregationCollector`.
+///
+/// # Limitations/Compatibility
+/// Only named filters are supported. The filters are query strings in the tantivy query language
+/// (see [`FilterQuery`]) and not elasticsearch query DSL objects.
+///
+/// # Request JSON Format
+/// ```json
+/// {
+///     "levels": {
+///         "filters": {
+///             "filters": {
+///                 "errors": "level:error",
+///                 "warnings": "level:warn"
+///             },
+///             "other_bucket_key": "other_levels"
+///         }
+///     }
+/// }
+/// ```
+///
+/// # Response JSON Format
+/// ```json
+/// {
+///     "levels": {
+///         "buckets": {
+///             "errors": { "doc_count": 34 },
+///             "warnings": { "doc_count": 439 },
+///             "other_levels": { "doc_count": 2450 }
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FiltersAggregation {
+    /// The filter queries by bucket name.
+    pub filters: HashMap<String, FilterQuery>,
+    /// Adds a bucket with the documents that match none of the filters.
+    ///
+    /// Defaults to false, unless `other_bucket_key` is set.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub other_bucket: Option<bool>,
+    /// The key of the other bucket. Defaults to `_other_`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub other_bucket_key: Option<String>,
+}
+
+impl FiltersAggregation {
+    /// Returns the key of the other bucket, if the other bucket is requested.
+    pub(crate) fn other_bucket_key(&self) -> Option<&str> {
+        let other_bucket = self.other_bucket.unwrap_or(self.other_bucket_key.is_some());
+        if other_bucket {
+            Some(
+                self.other_bucket_key
+                    .as_deref()
+                    .unwrap_or(DEFAULT_OTHER_BUCKET_KEY),
+            )
+        } else {
+            None
+        }
+    }
+
+    fn validate(&self) -> crate::Result<()> {
+        if let Some(other_bucket_key) = self.other_bucket_key() {
+            if self.filters.contains_key(other_bucket_key) {
+                return Err(crate::TantivyError::InvalidArgument(format!(
+                    "other_bucket_key {:?} is also used as a filter name",
+                    other_bucket_key
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The filter weights of a filter or filters aggregation for one segment.
+///
+/// The weights are created once per segment. The scorers are created lazily by each
+/// collector, since a collector may be cloned for every parent bucket.
+#[derive(Clone)]
+pub(crate) struct FilterWeights {
+    reader: SegmentReader,
+    /// The weights sorted by key. The key is `None` for the filter aggregation.
+    weights: Vec<(Option<String>, Arc<dyn Weight>)>,
+}
+
+impl FilterWeights {
+    pub(crate) fn from_req(
+        req: &BucketAggregationType,
+        reader: &SegmentReader,
+    ) -> crate::Result<Self> {
+        let mut weights = match req {
+            BucketAggregationType::Filter(filter) => {
+                vec![(None, Arc::from(filter.filter.weight(reader)?))]
+            }
+            BucketAggregationType::Filters(filters) => {
+                filters.validate()?;
+                filters
+                    .filters
+                    .iter()
+                    .map(|(key, filter)| {
+                        Ok((Some(key.to_string()), Arc::from(filter.weight(reader)?)))
+                    })
+                    .collect::<crate::Result<Vec<_>>>()?
+            }
+            _ => {
+                return Err(crate::TantivyError::InternalError(
+                    "expected filter or filters aggregation".to_string(),
+                ))
+            }
+        };
+        weights.sort_by(|left, right| left.0.cmp(&right.0));
+        Ok(FilterWeights {
+            reader: reader.clone(),
+            weights,
+        })
+    }
+
+    fn scorer(&self, pos: usize) -> crate::Result<Box<dyn Scorer>> {
+        self.weights[pos].1.scorer(&self.reader, 1.0)
+    }
+}
+
+struct SegmentFilterBucketEntry {
+    key: Option<String>,
+    doc_count: u64,
+    /// Lazily created, see `FilterWeights`.
+    scorer: Option<Box<dyn Scorer>>,
+    sub_aggregation: Option<Box<dyn SegmentAggregationCollector>>,
+}
+
+impl Clone for SegmentFilterBucketEntry {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            doc_count: self.doc_count,
+            // A scorer can't be cloned, the clone will create its own on the first collect.
+            scorer: None,
+            sub_aggregation: self.sub_aggregation.clone(),
+        }
+    }
+}
+
+impl Debug for SegmentFilterBucketEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SegmentFilterBucketEntry")
+            .field("key", &self.key)
+            .field("doc_count", &self.doc_count)
+            .finish()
+    }
+}
+
+impl SegmentFilterBucketEntry {
+    fn new(key: Option<String>, blueprint: &Option<Box<dyn SegmentAggregationCollector>>) -> Self {
+        Self {
+            key,
+            doc_count: 0,
+            scorer: None,
+            sub_aggregation: blueprint.clone(),
+        }
+    }
+
+    fn into_intermediate_bucket_entry(
+        self,
+        agg_with_accessor: &AggregationsWithAccessor,
+    ) -> crate::Result<IntermediateFilterBucketEntry> {
+        let sub_aggregation = if let Some(sub_aggregation) = self.sub_aggregation {
+            sub_aggregation.into_intermediate_aggregations_result(agg_with_accessor)?
+        } else {
+            Default::default()
+        };
+        Ok(IntermediateFilterBucketEntry {
+            doc_count: self.doc_count,
+            sub_aggregation,
+        })
+    }
+
+    #[inline]
+    fn collect(
+        &mut self,
+        doc: DocId,
+        sub_aggregation_accessor: &AggregationsWithAccessor,
+    ) -> crate::Result<()> {
+        self.doc_count += 1;
+        if let Some(sub_aggregation) = self.sub_aggregation.as_mut() {
+            sub_aggregation.collect(doc, sub_aggregation_accessor)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, sub_aggregation_accessor: &AggregationsWithAccessor) -> crate::Result<()> {
+        if let Some(sub_aggregation) = self.sub_aggregation.as_mut() {
+            sub_aggregation.flush_staged_docs(sub_aggregation_accessor, true)?;
+        }
+        Ok(())
+    }
+}
+
+/// The collector intersects the collected documents with the documents of each filter.
+///
+/// Collected documents arrive in increasing doc id order, so every filter scorer is only advanced
+/// with `seek`.
+#[derive(Clone, Debug)]
+pub(crate) struct SegmentFilterCollector {
+    /// One bucket per filter, in the same order as the `FilterWeights`.
+    buckets: Vec<SegmentFilterBucketEntry>,
+    other_bucket: Option<SegmentFilterBucketEntry>,
+}
+
+impl SegmentFilterCollector {
+    pub(crate) fn from_req_and_validate(
+        req: &BucketAggregationWithAccessor,
+        bucket_count: &BucketCount,
+    ) -> crate::Result<Self> {
+        let filter_weights = req
+            .filter_weights
+            .as_ref()
+            .expect("internal error: filter weights not loaded for filter aggregation");
+        let blueprint = if req.sub_aggregation.is_empty() {
+            None
+        } else {
+            Some(build_segment_agg_collector(&req.sub_aggregation)?)
+        };
+        let buckets: Vec<SegmentFilterBucketEntry> = filter_weights
+            .weights
+            .iter()
+            .map(|(key, _weight)| SegmentFilterBucketEntry::new(key.clone(), &blueprint))
+            .collect();
+        let other_bucket = match &req.bucket_agg {
+            BucketAggregationType::Filters(filters) => filters
+                .other_bucket_key()
+                .map(|key| SegmentFilterBucketEntry::new(Some(key.to_string()), &blueprint)),
+            _ => None,
+        };
+
+        let num_buckets = buckets.len() + usize::from(other_bucket.is_some());
+        bucket_count.add_count(num_buckets as u32);
+        bucket_count.validate_bucket_count()?;
+
+        Ok(SegmentFilterCollector {
+            buckets,
+            other_bucket,
+        })
+    }
+
+    pub(crate) fn into_intermediate_bucket_result(
+        self,
+        agg_with_accessor: &BucketAggregationWithAccessor,
+    ) -> crate::Result<IntermediateBucketResult> {
+        let sub_aggregation_accessor = &agg_with_accessor.sub_aggregation;
+        if let BucketAggregationType::Filter(_) = agg_with_accessor.bucket_agg {
+            let bucket = self
+                .buckets
+                .into_iter()
+                .next()
+                .expect("internal error: filter aggregation without bucket");
+            return Ok(IntermediateBucketResult::Filter(
+                bucket.into_intermediate_bucket_entry(sub_aggregation_accessor)?,
+            ));
+        }
+
+        let mut buckets = FxHashMap::default();
+        for bucket in self.buckets.into_iter().chain(self.other_bucket) {
+            let key = bucket.key.clone().unwrap_or_default();
+            buckets.insert(
+                key,
+                bucket.into_intermediate_bucket_entry(sub_aggregation_accessor)?,
+            );
+        }
+        Ok(IntermediateBucketResult::Filters(
+            IntermediateFiltersBucketResult { buckets },
+        ))
+    }
+
+    #[inline]
+    pub(crate) fn collect_block(
+        &mut self,
+        docs: &[DocId],
+        bucket_with_accessor: &BucketAggregationWithAccessor,
+        force_flush: bool,
+    ) -> crate::Result<()> {
+        let filter_weights = bucket_with_accessor
+            .filter_weights
+            .as_ref()
+            .expect("internal error: filter weights not loaded for filter aggregation");
+        let sub_aggregation_accessor = &bucket_with_accessor.sub_aggregation;
+
+        for &doc in docs {
+            let mut matched_any = false;
+            for (pos, bucket) in self.buckets.iter_mut().enumerate() {
+                if bucket.scorer.is_none() {
+                    bucket.scorer = Some(filter_weights.scorer(pos)?);
+                }
+                let scorer = bucket.scorer.as_mut().unwrap();
+                // The same doc may be collected twice in a row, e.g. by a parent bucket
+                // aggregation on a multi-valued field, so we must not seek backwards.
+                if scorer.doc() < doc {
+                    scorer.seek(doc);
+                }
+                if scorer.doc() == doc && doc != TERMINATED {
+                    matched_any = true;
+                    bucket.collect(doc, sub_aggregation_accessor)?;
+                }
+            }
+            if !matched_any {
+                if let Some(other_bucket) = self.other_bucket.as_mut() {
+                    other_bucket.collect(doc, sub_aggregation_accessor)?;
+                }
+            }
+        }
+
+        if force_flush {
+            for bucket in self.buckets.iter_mut().chain(self.other_bucket.as_mut()) {
+                bucket.flush(sub_aggregation_accessor)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::aggregation::agg_req::{Aggregation, Aggregations, BucketAggregation};
+    use crate::aggregation::tests::{
+        exec_request, exec_request_with_query, get_test_index_from_values_and_terms,
+    };
+    use crate::collector::Count;
+    use crate::query::TermQuery;
+    use crate::schema::{IndexRecordOption, Schema, Term, TextFieldIndexing, TextOptions};
+    use crate::tokenizer::{LowerCaser, RawTokenizer, TextAnalyzer};
+    use crate::Index;
+
+    fn get_test_index(merge_segments: bool) -> crate::Result<crate::Index> {
+        let segment_and_values = vec![
+            vec![(1.0, "error".to_string()), (2.0, "warn".to_string())],
+            vec![(3.0, "error".to_string()), (4.0, "info".to_string())],
+            vec![
+                (5.0, "info".to_string()),
+                (6.0, "error".to_string()),
+                (7.0, "debug".to_string()),
+            ],
+        ];
+        get_test_index_from_values_and_terms(merge_segments, &segment_and_values)
+    }
+
+    #[test]
+    fn filter_aggregation_test() -> crate::Result<()> {
+        filter_aggregation_test_merge_segment(false)?;
+        filter_aggregation_test_merge_segment(true)
+    }
+
+    fn filter_aggregation_test_merge_segment(merge_segments: bool) -> crate::Result<()> {
+        let index = get_test_index(merge_segments)?;
+
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "errors": {
+                "filter": "string_id:error",
+                "aggs": {
+                    "avg_score": { "avg": { "field": "score" } }
+                }
+            }
+        }))
+        .unwrap();
+
+        let res = exec_request(agg_req, &index)?;
+        assert_eq!(res["errors"]["doc_count"], 3);
+        assert_eq!(res["errors"]["avg_score"]["value"], 10.0 / 3.0);
+        Ok(())
+    }
+
+    #[test]
+    fn filter_aggregation_intersects_with_query() -> crate::Result<()> {
+        let index = get_test_index(false)?;
+
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "errors": { "filter": "string_id:error" }
+        }))
+        .unwrap();
+
+        let res = exec_request_with_query(agg_req, &index, Some(("text_id", "error")))?;
+        assert_eq!(res["errors"]["doc_count"], 3);
+
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "errors": { "filter": "string_id:error" }
+        }))
+        .unwrap();
+        let res = exec_request_with_query(agg_req, &index, Some(("text_id", "info")))?;
+        assert_eq!(res["errors"]["doc_count"], 0);
+        Ok(())
+    }
+
+    #[test]
+    fn filters_aggregation_test() -> crate::Result<()> {
+        filters_aggregation_test_merge_segment(false)?;
+        filters_aggregation_test_merge_segment(true)
+    }
+
+    fn filters_aggregation_test_merge_segment(merge_segments: bool) -> crate::Result<()> {
+        let index = get_test_index(merge_segments)?;
+
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "levels": {
+                "filters": {
+                    "filters": {
+                        "errors": "string_id:error",
+                        "warnings": "string_id:warn",
+                        "infos": "string_id:info",
+                        "errors_and_warnings": "string_id:error string_id:warn"
+                    },
+                    "other_bucket": true
+                },
+                "aggs": {
+                    "max_score": { "max": { "field": "score" } }
+                }
+            }
+        }))
+        .unwrap();
+
+        let res = exec_request(agg_req, &index)?;
+        assert_eq!(
+            res["levels"],
+            json!({
+                "buckets": {
+                    "errors": { "doc_count": 3, "max_score": { "value": 6.0 } },
+                    "warnings": { "doc_count": 1, "max_score": { "value": 2.0 } },
+                    "infos": { "doc_count": 2, "max_score": { "value": 5.0 } },
+                    "errors_and_warnings": { "doc_count": 4, "max_score": { "value": 6.0 } },
+                    "_other_": { "doc_count": 1, "max_score": { "value": 7.0 } }
+                }
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn filters_aggregation_empty_buckets() -> crate::Result<()> {
+        let index = get_test_index(false)?;
+
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "levels": {
+                "filters": {
+                    "filters": {
+                        "errors": "string_id:error",
+                        "fatal": "string_id:fatal"
+                    },
+                    "other_bucket_key": "rest"
+                }
+            }
+        }))
+        .unwrap();
+
+        let res = exec_request_with_query(agg_req, &index, Some(("text_id", "warn")))?;
+        assert_eq!(
+            res["levels"],
+            json!({
+                "buckets": {
+                    "errors": { "doc_count": 0 },
+                    "fatal": { "doc_count": 0 },
+                    "rest": { "doc_count": 1 }
+                }
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn filter_aggregation_from_query_object() -> crate::Result<()> {
+        let index = get_test_index(false)?;
+        let string_field = index.schema().get_field("string_id").unwrap();
+        let query: Box<dyn Query> = Box::new(TermQuery::new(
+            Term::from_field_text(string_field, "info"),
+            IndexRecordOption::Basic,
+        ));
+
+        let agg_req: Aggregations = vec![(
+            "infos".to_string(),
+            Aggregation::Bucket(BucketAggregation {
+                bucket_agg: BucketAggregationType::Filter(FilterAggregation::new(query)),
+                sub_aggregation: Default::default(),
+            }),
+        )]
+        .into_iter()
+        .collect();
+
+        let res = exec_request(agg_req.clone(), &index)?;
+        assert_eq!(res["infos"]["doc_count"], 2);
+
+        // Query objects can't be serialized
+        assert!(serde_json::to_string(&agg_req).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn filter_aggregation_with_custom_tokenizer() -> crate::Result<()> {
+        let mut schema_builder = Schema::builder();
+        let text_options = TextOptions::default().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_tokenizer("lowercase_keyword")
+                .set_index_option(IndexRecordOption::Basic),
+        );
+        let message_field = schema_builder.add_text_field("message", text_options);
+        let index = Index::create_in_ram(schema_builder.build());
+        index.tokenizers().register(
+            "lowercase_keyword",
+            TextAnalyzer::from(RawTokenizer).filter(LowerCaser),
+        );
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.add_document(doc!(message_field => "Disk Full"))?;
+        index_writer.add_document(doc!(message_field => "disk full"))?;
+        index_writer.add_document(doc!(message_field => "Disk"))?;
+        index_writer.commit()?;
+
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "disk_full": { "filter": "message:\"DISK FULL\"" }
+        }))
+        .unwrap();
+        let res = exec_request(agg_req, &index)?;
+        assert_eq!(res["disk_full"]["doc_count"], 2);
+
+        // The counts agree with the query parser of the index.
+        let query =
+            QueryParser::for_index(&index, Vec::new()).parse_query("message:\"DISK FULL\"")?;
+        assert_eq!(index.reader()?.searcher().search(&query, &Count)?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn filter_aggregation_invalid_requests() -> crate::Result<()> {
+        let index = get_test_index(false)?;
+
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "errors": { "filter": "unknown_field:error" }
+        }))
+        .unwrap();
+        assert!(exec_request(agg_req, &index).is_err());
+
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "levels": {
+                "filters": {
+                    "filters": { "_other_": "string_id:error" },
+                    "other_bucket": true
+                }
+            }
+        }))
+        .unwrap();
+        let err = exec_request(agg_req, &index).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "An invalid argument was passed: 'other_bucket_key \"_other_\" is also used as a \
+             filter name'"
+        );
+
+        let agg_req: serde_json::Result<Aggregations> = serde_json::from_value(json!({
+            "errors": { "filter": "" }
+        }));
+        assert!(agg_req.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn filter_aggregation_serde_roundtrip() {
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "levels": {
+                "filters": {
+                    "filters": { "errors": "string_id:error" },
+                    "other_bucket_key": "rest"
+                }
+            },
+            "errors": { "filter": "string_id:error" }
+        }))
+        .unwrap();
+        let agg_req_json: Value = serde_json::to_value(&agg_req).unwrap();
+        assert_eq!(
+            agg_req_json,
+            json!({
+                "levels": {
+                    "filters": {
+                        "filters": { "errors": "string_id:error" },
+                        "other_bucket_key": "rest"
+                    }
+                },
+                "errors": { "filter": "string_id:error" }
+            })
+        );
+    }
+}
@@ -0,0 +1,683 @@
+use std::fmt::Debug;
+
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use super::{cut_off_buckets, GetDocCount};
+use crate::aggregation::agg_req_with_accessor::{
+    AggregationsWithAccessor, BucketAggregationWithAccessor,
+};
+use crate::aggregation::f64_from_fastfield_u64;
+use crate::aggregation::intermediate_agg_result::{
+    IntermediateBucketResult, IntermediateGeoGridBucketEntry, IntermediateGeoGridBucketResult,
+};
+use crate::aggregation::memory_budget::{blueprint_memory, hash_map_memory};
+use crate::aggregation::segment_agg_result::{
+    build_segment_agg_collector, SegmentAggregationCollector,
+};
+use crate::{DocId, TantivyError};
+
+const GEOHASH_BASE32: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+const MAX_GEOHASH_PRECISION: u8 = 12;
+const MAX_GEOTILE_PRECISION: u8 = 29;
+/// The latitude limit of the web mercator projection, which is used by map tiles.
+const MAX_MERCATOR_LATITUDE: f64 = 85.051_128_779_806_59;
+
+/// Groups geo points into the cells of a grid and counts the number of documents per cell.
+///
+/// The same request is used by two grids:
+/// - `geohash_grid` uses [geohash](https://en.wikipedia.org/wiki/Geohash) cells. The precision
+///   is the length of the geohash, from 1 to 12, and defaults to 5. Keys are geohashes like
+///   `u173z`.
+/// - `geotile_grid` uses the tiles of web maps. The precision is the zoom level, from 0 to 29,
+///   and defaults to 7. Keys are `{zoom}/{x}/{y}` like `8/131/84`.
+///
+/// The points are read either from the `lat` and `lon` paths of a fast json field `field`, e.g.
+/// a document `{"location": {"lat": 52.37, "lon": 4.91}}`, or from the two numeric fast fields
+/// `lat_field` and `lon_field`.
+///
+/// Every bucket contains the centroid of the points in the cell, so that a map can place the
+/// cluster where its points are instead of in the middle of the cell.
+///
+/// Result type is [`BucketResult::GeoGrid`](crate::aggregation::agg_result::BucketResult::GeoGrid)
+/// with [`GeoGridBucketEntry`](crate::aggregation::agg_result::GeoGridBucketEntry) on the
+/// `AggregationCollector`.
+///
+/// Result type is
+/// [`IntermediateBucketResult::GeoGrid`](crate::aggregation::intermediate_agg_result::IntermediateBucketResult::GeoGrid) with
+/// [`IntermediateGeoGridBucketEntry`](crate::aggregation::intermediate_agg_result::IntermediateGeoGridBucketEntry) on the
+/// `DistributedAggregationCollector`.
+///
+/// # Limitations/Compatibility
+/// Documents without a latitude or a longitude are ignored. The precision can't be passed as a
+/// distance like `1km`.
+///
+/// # Request JSON Format
+/// ```json
+/// {
+///     "clusters": {
+///         "geohash_grid": {
+///             "field": "location",
+///             "precision": 3,
+///             "bounds": {
+///                 "top_left": { "lat": 53.0, "lon": 4.0 },
+///                 "bottom_right": { "lat": 52.0, "lon": 5.0 }
+///             }
+///         }
+///     }
+/// }
+/// ```
+///
+/// # Response JSON Format
+/// ```json
+/// {
+///     "clusters": {
+///         "buckets": [
+///             { "key": "u17", "doc_count": 3, "centroid": { "lat": 52.37, "lon": 4.9 } },
+///             { "key": "u16", "doc_count": 1, "centroid": { "lat": 52.08, "lon": 4.3 } }
+///         ]
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct GeoGridAggregation {
+    /// A fast json field with `lat` and `lon` values.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub field: Option<String>,
+    /// The fast field with the latitudes, used together with `lon_field` instead of `field`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub lat_field: Option<String>,
+    /// The fast field with the longitudes, used together with `lat_field` instead of `field`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub lon_field: Option<String>,
+    /// The geohash length or the zoom level of the tiles. Higher values create smaller cells.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub precision: Option<u8>,
+    /// Only points inside the bounds are aggregated.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub bounds: Option<GeoBoundingBox>,
+    /// The maximum number of buckets returned, the cells with the most documents. Defaults to
+    /// 10000.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub size: Option<u32>,
+    /// The number of cells fetched from each segment. Defaults to 10 * size.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    #[serde(alias = "shard_size")]
+    pub segment_size: Option<u32>,
+}
+
+/// A geo point in degrees.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct GeoPoint {
+    /// The latitude, from -90 to 90.
+    pub lat: f64,
+    /// The longitude, from -180 to 180.
+    pub lon: f64,
+}
+
+/// A bounding box, which crosses the antimeridian if the left longitude is greater than the right
+/// longitude.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GeoBoundingBox {
+    /// The north west corner.
+    pub top_left: GeoPoint,
+    /// The south east corner.
+    pub bottom_right: GeoPoint,
+}
+
+impl GeoBoundingBox {
+    fn contains(&self, lat: f64, lon: f64) -> bool {
+        if lat > self.top_left.lat || lat < self.bottom_right.lat {
+            return false;
+        }
+        if self.top_left.lon <= self.bottom_right.lon {
+            self.top_left.lon <= lon && lon <= self.bottom_right.lon
+        } else {
+            self.top_left.lon <= lon || lon <= self.bottom_right.lon
+        }
+    }
+}
+
+/// The grid of a [`GeoGridAggregation`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum GeoGridType {
+    GeoHash,
+    GeoTile,
+}
+
+impl GeoGridType {
+    fn name(self) -> &'static str {
+        match self {
+            GeoGridType::GeoHash => "geohash_grid",
+            GeoGridType::GeoTile => "geotile_grid",
+        }
+    }
+
+    fn max_precision(self) -> u8 {
+        match self {
+            GeoGridType::GeoHash => MAX_GEOHASH_PRECISION,
+            GeoGridType::GeoTile => MAX_GEOTILE_PRECISION,
+        }
+    }
+
+    fn min_precision(self) -> u8 {
+        match self {
+            GeoGridType::GeoHash => 1,
+            GeoGridType::GeoTile => 0,
+        }
+    }
+
+    fn default_precision(self) -> u8 {
+        match self {
+            GeoGridType::GeoHash => 5,
+            GeoGridType::GeoTile => 7,
+        }
+    }
+
+    /// Returns the id of the cell, which contains the point.
+    fn cell(self, lat: f64, lon: f64, precision: u8) -> u64 {
+        match self {
+            GeoGridType::GeoHash => geohash_cell(lat, lon, precision),
+            GeoGridType::GeoTile => geotile_cell(lat, lon, precision),
+        }
+    }
+
+    /// Returns the key of a cell id returned by [`GeoGridType::cell`].
+    fn cell_key(self, cell: u64, precision: u8) -> String {
+        match self {
+            GeoGridType::GeoHash => (0..precision)
+                .rev()
+                .map(|pos| GEOHASH_BASE32[((cell >> (5 * pos as u32)) & 31) as usize] as char)
+                .collect(),
+            GeoGridType::GeoTile => {
+                format!("{}/{}/{}", precision, cell >> 32, cell & u32::MAX as u64)
+            }
+        }
+    }
+}
+
+/// Encodes the bits of the geohash, which alternately halve the longitude and the latitude range.
+fn geohash_cell(lat: f64, lon: f64, precision: u8) -> u64 {
+    let (mut lat_min, mut lat_max) = (-90.0, 90.0);
+    let (mut lon_min, mut lon_max) = (-180.0, 180.0);
+    let mut cell = 0u64;
+    for bit in 0..5 * precision as u32 {
+        let (val, min, max) = if bit % 2 == 0 {
+            (lon, &mut lon_min, &mut lon_max)
+        } else {
+            (lat, &mut lat_min, &mut lat_max)
+        };
+        let mid = (*min + *max) / 2.0;
+        cell <<= 1;
+        if val >= mid {
+            cell |= 1;
+            *min = mid;
+        } else {
+            *max = mid;
+        }
+    }
+    cell
+}
+
+/// Encodes the x coordinate of the tile in the upper and the y coordinate in the lower 32 bits.
+fn geotile_cell(lat: f64, lon: f64, zoom: u8) -> u64 {
+    let num_tiles = (1u64 << zoom) as f64;
+    let max_tile = (1u64 << zoom) - 1;
+    let x = ((lon + 180.0) / 360.0 * num_tiles).floor();
+    let lat_rad = lat
+        .clamp(-MAX_MERCATOR_LATITUDE, MAX_MERCATOR_LATITUDE)
+        .to_radians();
+    let y = ((1.0 - lat_rad.tan().asinh() / std::f64::consts::PI) / 2.0 * num_tiles).floor();
+    let x = (x.max(0.0) as u64).min(max_tile);
+    let y = (y.max(0.0) as u64).min(max_tile);
+    (x << 32) | y
+}
+
+impl GeoGridAggregation {
+    /// Returns the fast fields of the latitudes and the longitudes.
+    pub(crate) fn lat_lon_fields(&self) -> (String, String) {
+        match (&self.field, &self.lat_field, &self.lon_field) {
+            (Some(field), _, _) => (format!("{}.lat", field), format!("{}.lon", field)),
+            (None, lat_field, lon_field) => (
+                lat_field.clone().unwrap_or_default(),
+                lon_field.clone().unwrap_or_default(),
+            ),
+        }
+    }
+
+    pub(crate) fn size(&self) -> u32 {
+        self.size.unwrap_or(10_000)
+    }
+
+    fn segment_size(&self) -> u32 {
+        self.segment_size
+            .unwrap_or_else(|| self.size().saturating_mul(10))
+            .max(self.size())
+    }
+
+    fn precision(&self, grid_type: GeoGridType) -> u8 {
+        self.precision
+            .unwrap_or_else(|| grid_type.default_precision())
+    }
+
+    pub(crate) fn validate(&self, grid_type: GeoGridType) -> crate::Result<()> {
+        let has_valid_fields = matches!(
+            (&self.field, &self.lat_field, &self.lon_field),
+            (Some(_), None, None) | (None, Some(_), Some(_))
+        );
+        if !has_valid_fields {
+            return Err(TantivyError::InvalidArgument(format!(
+                "{} aggregation requires either field or lat_field and lon_field",
+                grid_type.name()
+            )));
+        }
+        let precision = self.precision(grid_type);
+        if precision < grid_type.min_precision() || precision > grid_type.max_precision() {
+            return Err(TantivyError::InvalidArgument(format!(
+                "{} aggregation precision must be between {} and {}, but is {}",
+                grid_type.name(),
+                grid_type.min_precision(),
+                grid_type.max_precision(),
+                precision
+            )));
+        }
+        if self.size() == 0 {
+            return Err(TantivyError::InvalidArgument(format!(
+                "{} aggregation size must be greater than 0",
+                grid_type.name()
+            )));
+        }
+        if let Some(bounds) = self.bounds.as_ref() {
+            if bounds.top_left.lat < bounds.bottom_right.lat {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "{} aggregation bounds top_left latitude {} is below the bottom_right \
+                     latitude {}",
+                    grid_type.name(),
+                    bounds.top_left.lat,
+                    bounds.bottom_right.lat
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The bucket of a cell in a segment.
+#[derive(Clone)]
+struct SegmentGeoGridBucketEntry {
+    doc_count: u64,
+    /// The number of points in the cell, which can be larger than the number of documents, if a
+    /// document has several points.
+    point_count: u64,
+    lat_sum: f64,
+    lon_sum: f64,
+    sub_aggregations: Option<Box<dyn SegmentAggregationCollector>>,
+}
+
+impl Debug for SegmentGeoGridBucketEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SegmentGeoGridBucketEntry")
+            .field("doc_count", &self.doc_count)
+            .field("point_count", &self.point_count)
+            .finish()
+    }
+}
+
+impl GetDocCount for (u64, SegmentGeoGridBucketEntry) {
+    fn doc_count(&self) -> u64 {
+        self.1.doc_count
+    }
+}
+
+/// The collector for the geohash_grid and geotile_grid aggregations in a segment.
+#[derive(Clone, Debug)]
+pub(crate) struct SegmentGeoGridCollector {
+    grid_type: GeoGridType,
+    precision: u8,
+    bounds: Option<GeoBoundingBox>,
+    segment_size: u32,
+    buckets: FxHashMap<u64, SegmentGeoGridBucketEntry>,
+    blueprint: Option<Box<dyn SegmentAggregationCollector>>,
+    /// The cells of the points of one document.
+    cells_buffer: Vec<u64>,
+}
+
+impl SegmentGeoGridCollector {
+    pub(crate) fn from_req_and_validate(
+        grid_type: GeoGridType,
+        req: &GeoGridAggregation,
+        bucket_with_accessor: &BucketAggregationWithAccessor,
+    ) -> crate::Result<Self> {
+        let sub_aggregations = &bucket_with_accessor.sub_aggregation;
+        let blueprint = if sub_aggregations.is_empty() {
+            None
+        } else {
+            Some(build_segment_agg_collector(sub_aggregations)?)
+        };
+        Ok(SegmentGeoGridCollector {
+            grid_type,
+            precision: req.precision(grid_type),
+            bounds: req.bounds,
+            segment_size: req.segment_size(),
+            buckets: FxHashMap::default(),
+            blueprint,
+            cells_buffer: Vec::new(),
+        })
+    }
+
+    pub(crate) fn into_intermediate_bucket_result(
+        self,
+        agg_with_accessor: &BucketAggregationWithAccessor,
+    ) -> crate::Result<IntermediateBucketResult> {
+        let mut entries: Vec<(u64, SegmentGeoGridBucketEntry)> = self.buckets.into_iter().collect();
+        entries.sort_unstable_by(|left, right| {
+            right
+                .1
+                .doc_count
+                .cmp(&left.1.doc_count)
+                .then(left.0.cmp(&right.0))
+        });
+        cut_off_buckets(&mut entries, self.segment_size as usize);
+
+        let bucket_count = &agg_with_accessor.bucket_count;
+        bucket_count.add_count(entries.len() as u32);
+        bucket_count.validate_bucket_count()?;
+
+        let mut buckets = FxHashMap::default();
+        for (cell, entry) in entries {
+            let sub_aggregation = if let Some(sub_aggregation) = entry.sub_aggregations {
+                sub_aggregation
+                    .into_intermediate_aggregations_result(&agg_with_accessor.sub_aggregation)?
+            } else {
+                Default::default()
+            };
+            buckets.insert(
+                self.grid_type.cell_key(cell, self.precision),
+                IntermediateGeoGridBucketEntry {
+                    doc_count: entry.doc_count,
+                    point_count: entry.point_count,
+                    lat_sum: entry.lat_sum,
+                    lon_sum: entry.lon_sum,
+                    sub_aggregation,
+                },
+            );
+        }
+
+        Ok(IntermediateBucketResult::GeoGrid(
+            IntermediateGeoGridBucketResult { buckets },
+        ))
+    }
+
+    #[inline]
+    pub(crate) fn collect_block(
+        &mut self,
+        docs: &[DocId],
+        bucket_with_accessor: &BucketAggregationWithAccessor,
+        force_flush: bool,
+    ) -> crate::Result<()> {
+        let (lat_accessor, lon_accessor) = match &bucket_with_accessor.composite_sources[..] {
+            [lat_accessor, lon_accessor] => (lat_accessor, lon_accessor),
+            _ => {
+                return Err(TantivyError::InternalError(
+                    "geo grid aggregation requires a latitude and a longitude field".to_string(),
+                ))
+            }
+        };
+        let sub_aggregation_accessor = &bucket_with_accessor.sub_aggregation;
+        let memory_before = self.memory_consumption();
+
+        for &doc in docs {
+            self.cells_buffer.clear();
+            // The latitudes and longitudes of a document with several points are paired in the
+            // order of their values.
+            let points = lat_accessor
+                .accessor
+                .values(doc)
+                .zip(lon_accessor.accessor.values(doc));
+            for (lat, lon) in points {
+                let lat = f64_from_fastfield_u64(lat, &lat_accessor.field_type);
+                let lon = f64_from_fastfield_u64(lon, &lon_accessor.field_type);
+                if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+                    continue;
+                }
+                if let Some(bounds) = self.bounds.as_ref() {
+                    if !bounds.contains(lat, lon) {
+                        continue;
+                    }
+                }
+                let cell = self.grid_type.cell(lat, lon, self.precision);
+                let blueprint = &self.blueprint;
+                let entry = self
+                    .buckets
+                    .entry(cell)
+                    .or_insert_with(|| SegmentGeoGridBucketEntry {
+                        doc_count: 0,
+                        point_count: 0,
+                        lat_sum: 0.0,
+                        lon_sum: 0.0,
+                        sub_aggregations: blueprint.clone(),
+                    });
+                entry.point_count += 1;
+                entry.lat_sum += lat;
+                entry.lon_sum += lon;
+                self.cells_buffer.push(cell);
+            }
+
+            // A document is counted once per cell, even if several of its points are in it.
+            self.cells_buffer.sort_unstable();
+            self.cells_buffer.dedup();
+            for cell in &self.cells_buffer {
+                let entry = self
+                    .buckets
+                    .get_mut(cell)
+                    .expect("bucket was just inserted");
+                entry.doc_count += 1;
+                if let Some(sub_aggregations) = entry.sub_aggregations.as_mut() {
+                    sub_aggregations.collect(doc, sub_aggregation_accessor)?;
+                }
+            }
+        }
+        bucket_with_accessor
+            .memory_account
+            .add_memory_consumed(self.memory_consumption() - memory_before)?;
+
+        if force_flush {
+            self.force_flush(sub_aggregation_accessor)?;
+        }
+        Ok(())
+    }
+
+    /// Estimates the memory of the buckets, which grows with the number of cells.
+    fn memory_consumption(&self) -> u64 {
+        hash_map_memory(&self.buckets)
+            + self.buckets.len() as u64 * blueprint_memory(&self.blueprint)
+    }
+
+    fn force_flush(&mut self, agg_with_accessor: &AggregationsWithAccessor) -> crate::Result<()> {
+        for entry in self.buckets.values_mut() {
+            if let Some(sub_aggregations) = entry.sub_aggregations.as_mut() {
+                sub_aggregations.flush_staged_docs(agg_with_accessor, false)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::aggregation::agg_req::Aggregations;
+    use crate::aggregation::tests::exec_request;
+    use crate::schema::{Schema, FAST, STORED};
+    use crate::Index;
+
+    const AMSTERDAM: (f64, f64) = (52.374081, 4.912350);
+    const AMSTERDAM_CENTRAL: (f64, f64) = (52.378, 4.900);
+    const ROTTERDAM: (f64, f64) = (51.922, 4.479);
+    const SYDNEY: (f64, f64) = (-33.868, 151.209);
+
+    /// Indexes the points both into the json field `location` and into the fields `lat` and
+    /// `lon`, one segment per slice.
+    fn get_test_index(segments: &[&[(f64, f64)]]) -> crate::Result<Index> {
+        let mut schema_builder = Schema::builder();
+        let location = schema_builder.add_json_field("location", STORED | FAST);
+        let lat = schema_builder.add_f64_field("lat", FAST);
+        let lon = schema_builder.add_f64_field("lon", FAST);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer = index.writer_for_tests()?;
+        for points in segments {
+            for &(lat_val, lon_val) in points.iter() {
+                let json = json!({ "lat": lat_val, "lon": lon_val });
+                index_writer.add_document(doc!(
+                    location => json.as_object().unwrap().clone(),
+                    lat => lat_val,
+                    lon => lon_val,
+                ))?;
+            }
+            index_writer.commit()?;
+        }
+        Ok(index)
+    }
+
+    #[test]
+    fn geohash_cells_test() {
+        let (lat, lon) = AMSTERDAM;
+        let cell = GeoGridType::GeoHash.cell(lat, lon, 12);
+        assert_eq!(GeoGridType::GeoHash.cell_key(cell, 12), "u173zy3j4h3d");
+        let cell = GeoGridType::GeoHash.cell(lat, lon, 1);
+        assert_eq!(GeoGridType::GeoHash.cell_key(cell, 1), "u");
+
+        let cell = GeoGridType::GeoTile.cell(lat, lon, 8);
+        assert_eq!(GeoGridType::GeoTile.cell_key(cell, 8), "8/131/84");
+        let cell = GeoGridType::GeoTile.cell(lat, lon, 0);
+        assert_eq!(GeoGridType::GeoTile.cell_key(cell, 0), "0/0/0");
+        // The poles are clamped to the last tile.
+        let cell = GeoGridType::GeoTile.cell(-90.0, 180.0, 2);
+        assert_eq!(GeoGridType::GeoTile.cell_key(cell, 2), "2/3/3");
+    }
+
+    #[test]
+    fn geohash_grid_test() -> crate::Result<()> {
+        let index = get_test_index(&[
+            &[AMSTERDAM, ROTTERDAM, SYDNEY],
+            &[AMSTERDAM_CENTRAL, AMSTERDAM],
+        ])?;
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "clusters": {
+                "geohash_grid": { "field": "location", "precision": 3 },
+                "aggs": { "avg_lat": { "avg": { "field": "lat" } } }
+            }
+        }))
+        .unwrap();
+        let res = exec_request(agg_req, &index)?;
+
+        let buckets = &res["clusters"]["buckets"];
+        assert_eq!(buckets.as_array().unwrap().len(), 3);
+        assert_eq!(buckets[0]["key"], "u17");
+        assert_eq!(buckets[0]["doc_count"], 3);
+        let centroid_lat = (AMSTERDAM.0 * 2.0 + AMSTERDAM_CENTRAL.0) / 3.0;
+        let centroid_lon = (AMSTERDAM.1 * 2.0 + AMSTERDAM_CENTRAL.1) / 3.0;
+        assert!((buckets[0]["centroid"]["lat"].as_f64().unwrap() - centroid_lat).abs() < 1e-9);
+        assert!((buckets[0]["centroid"]["lon"].as_f64().unwrap() - centroid_lon).abs() < 1e-9);
+        assert!((buckets[0]["avg_lat"]["value"].as_f64().unwrap() - centroid_lat).abs() < 1e-9);
+        // Equal counts are sorted by key.
+        assert_eq!(buckets[1]["key"], "r3g");
+        assert_eq!(buckets[1]["doc_count"], 1);
+        assert_eq!(buckets[2]["key"], "u15");
+        assert_eq!(buckets[2]["doc_count"], 1);
+
+        // The lat and lon fields give the same cells.
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "clusters": {
+                "geohash_grid": {
+                    "lat_field": "lat",
+                    "lon_field": "lon",
+                    "precision": 3,
+                    "size": 1
+                }
+            }
+        }))
+        .unwrap();
+        let res = exec_request(agg_req, &index)?;
+        let buckets = &res["clusters"]["buckets"];
+        assert_eq!(buckets.as_array().unwrap().len(), 1);
+        assert_eq!(buckets[0]["key"], "u17");
+        assert_eq!(buckets[0]["doc_count"], 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn geotile_grid_bounds_test() -> crate::Result<()> {
+        let index = get_test_index(&[
+            &[AMSTERDAM, ROTTERDAM, SYDNEY],
+            &[AMSTERDAM_CENTRAL, AMSTERDAM],
+        ])?;
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "clusters": {
+                "geotile_grid": {
+                    "field": "location",
+                    "precision": 8,
+                    "bounds": {
+                        "top_left": { "lat": 53.0, "lon": 4.7 },
+                        "bottom_right": { "lat": 52.0, "lon": 5.0 }
+                    }
+                }
+            }
+        }))
+        .unwrap();
+        let res = exec_request(agg_req, &index)?;
+        assert_eq!(
+            res["clusters"]["buckets"],
+            json!([{
+                "key": "8/131/84",
+                "doc_count": 3,
+                "centroid": {
+                    "lat": (AMSTERDAM.0 * 2.0 + AMSTERDAM_CENTRAL.0) / 3.0,
+                    "lon": (AMSTERDAM.1 * 2.0 + AMSTERDAM_CENTRAL.1) / 3.0,
+                }
+            }])
+        );
+
+        // Bounds across the antimeridian only contain Sydney.
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "clusters": {
+                "geotile_grid": {
+                    "lat_field": "lat",
+                    "lon_field": "lon",
+                    "precision": 0,
+                    "bounds": {
+                        "top_left": { "lat": 0.0, "lon": 100.0 },
+                        "bottom_right": { "lat": -50.0, "lon": -100.0 }
+                    }
+                }
+            }
+        }))
+        .unwrap();
+        let res = exec_request(agg_req, &index)?;
+        assert_eq!(res["clusters"]["buckets"][0]["key"], "0/0/0");
+        assert_eq!(res["clusters"]["buckets"][0]["doc_count"], 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn geo_grid_invalid_request_test() -> crate::Result<()> {
+        let index = get_test_index(&[&[AMSTERDAM]])?;
+        let invalid_reqs = [
+            json!({ "geohash_grid": { "field": "location", "precision": 13 } }),
+            json!({ "geotile_grid": { "field": "location", "precision": 30 } }),
+            json!({ "geohash_grid": { "field": "location", "lat_field": "lat" } }),
+            json!({ "geohash_grid": { "lat_field": "lat" } }),
+        ];
+        for invalid_req in invalid_reqs {
+            let agg_req: Aggregations =
+                serde_json::from_value(json!({ "clusters": invalid_req })).unwrap();
+            let err = exec_request(agg_req, &index).unwrap_err();
+            assert!(matches!(err, TantivyError::InvalidArgument(_)), "{:?}", err);
+        }
+        Ok(())
+    }
+}
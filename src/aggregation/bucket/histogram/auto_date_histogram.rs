@@ -0,0 +1,635 @@
+use std::fmt::Debug;
+use std::mem;
+use std::sync::Arc;
+
+use columnar::MonotonicallyMappableToU64;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use super::{CalendarInterval, DateHistogramAggregationReq, DateInterval, DateRounding};
+use crate::aggregation::agg_req_with_accessor::{
+    AggregationsWithAccessor, BucketAggregationWithAccessor,
+};
+use crate::aggregation::date::MICROS_PER_SECOND;
+use crate::aggregation::intermediate_agg_result::{
+    IntermediateAggregationResults, IntermediateAutoDateHistogramBucketResult,
+    IntermediateBucketResult, IntermediateHistogramBucketEntry, MergeFruits,
+};
+use crate::aggregation::memory_budget::{blueprint_memory, hash_map_memory};
+use crate::aggregation::segment_agg_result::{
+    build_segment_agg_collector, SegmentAggregationCollector,
+};
+use crate::schema::Type;
+use crate::{DocId, TantivyError};
+
+/// AutoDateHistogramAggregation is similar to `DateHistogramAggregationReq`, but instead of an
+/// interval it takes the target number of buckets and picks an interval which returns at most
+/// that many buckets.
+///
+/// The interval is picked from the following list, and returned as `interval` in the result:
+/// `1s`, `5s`, `10s`, `30s`, `1m`, `5m`, `10m`, `30m`, `1h`, `3h`, `12h`, `1d`, `1w`, `1M`,
+/// `3M` and `1y`. Minutes and coarser intervals are calendar-aware like in the date histogram,
+/// and rounding happens in the requested `time_zone`.
+///
+/// During collection, each segment starts with the finest interval and switches to a coarser
+/// interval when it has more buckets than the target, so the memory is bounded by the target
+/// number of buckets.
+///
+/// # Limitations/Compatibility
+/// Intervals coarser than one year are not supported, so a range of many years can return more
+/// than `buckets` buckets. The `format` and `missing` parameters are not supported.
+///
+/// # JSON Format
+/// ```json
+/// {
+///     "sales_over_time": {
+///         "auto_date_histogram": {
+///             "field": "sold_at",
+///             "buckets": 20
+///         }
+///     }
+/// }
+/// ```
+///
+/// Response
+/// See [`BucketEntry`](crate::aggregation::agg_result::BucketEntry)
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AutoDateHistogramAggregation {
+    /// The field to aggregate on.
+    pub field: String,
+    /// The target number of buckets. Defaults to 10.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub buckets: Option<u32>,
+    /// The finest interval to use, e.g. `day` to never return buckets of hours.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub minimum_interval: Option<MinimumInterval>,
+    /// The time zone in which the buckets are rounded, either a UTC offset like `-05:00` or a
+    /// name from the time zone database like `Europe/Berlin`. Defaults to UTC.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub time_zone: Option<String>,
+    /// Whether to return the buckets as a hash map
+    #[serde(default)]
+    pub keyed: bool,
+}
+
+impl AutoDateHistogramAggregation {
+    /// Returns the target number of buckets.
+    pub fn target_buckets(&self) -> u32 {
+        self.buckets.unwrap_or(10)
+    }
+
+    fn validate(&self) -> crate::Result<()> {
+        if self.target_buckets() == 0 {
+            return Err(TantivyError::InvalidArgument(
+                "auto_date_histogram requires buckets to be a positive value".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the position of the finest interval in [`AUTO_INTERVALS`].
+    pub(crate) fn min_interval_pos(&self) -> usize {
+        let name = match self.minimum_interval {
+            None | Some(MinimumInterval::Second) => "1s",
+            Some(MinimumInterval::Minute) => "1m",
+            Some(MinimumInterval::Hour) => "1h",
+            Some(MinimumInterval::Day) => "1d",
+            Some(MinimumInterval::Month) => "1M",
+            Some(MinimumInterval::Year) => "1y",
+        };
+        auto_interval_pos(name).expect("the minimum interval is in the list of intervals")
+    }
+}
+
+/// The finest interval of the [`AutoDateHistogramAggregation`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MinimumInterval {
+    /// One second
+    #[serde(rename = "second")]
+    Second,
+    /// One minute
+    #[serde(rename = "minute")]
+    Minute,
+    /// One hour
+    #[serde(rename = "hour")]
+    Hour,
+    /// One day
+    #[serde(rename = "day")]
+    Day,
+    /// One month
+    #[serde(rename = "month")]
+    Month,
+    /// One year
+    #[serde(rename = "year")]
+    Year,
+}
+
+/// An interval of the auto date histogram with its name in the result.
+struct AutoInterval {
+    name: &'static str,
+    interval: DateInterval,
+}
+
+const fn fixed(name: &'static str, seconds: i64) -> AutoInterval {
+    AutoInterval {
+        name,
+        interval: DateInterval::Fixed(seconds * MICROS_PER_SECOND),
+    }
+}
+
+const fn calendar(name: &'static str, calendar_interval: CalendarInterval) -> AutoInterval {
+    AutoInterval {
+        name,
+        interval: DateInterval::Calendar(calendar_interval),
+    }
+}
+
+/// The intervals from fine to coarse. The buckets of an interval nest into the buckets of the
+/// following intervals, except for weeks, which don't nest into months.
+const AUTO_INTERVALS: [AutoInterval; 16] = [
+    fixed("1s", 1),
+    fixed("5s", 5),
+    fixed("10s", 10),
+    fixed("30s", 30),
+    calendar("1m", CalendarInterval::Minute),
+    fixed("5m", 5 * 60),
+    fixed("10m", 10 * 60),
+    fixed("30m", 30 * 60),
+    calendar("1h", CalendarInterval::Hour),
+    fixed("3h", 3 * 3_600),
+    fixed("12h", 12 * 3_600),
+    calendar("1d", CalendarInterval::Day),
+    calendar("1w", CalendarInterval::Week),
+    calendar("1M", CalendarInterval::Month),
+    calendar("3M", CalendarInterval::Quarter),
+    calendar("1y", CalendarInterval::Year),
+];
+
+const DAY_POS: usize = 11;
+const WEEK_POS: usize = 12;
+
+/// Returns the position of the interval with the name in [`AUTO_INTERVALS`].
+pub(crate) fn auto_interval_pos(name: &str) -> Option<usize> {
+    AUTO_INTERVALS
+        .iter()
+        .position(|auto_interval| auto_interval.name == name)
+}
+
+pub(crate) fn auto_interval_name(pos: usize) -> &'static str {
+    AUTO_INTERVALS[pos].name
+}
+
+/// Returns the roundings of all intervals in the time zone.
+pub(crate) fn auto_interval_roundings(
+    time_zone: Option<&str>,
+) -> crate::Result<Arc<Vec<DateRounding>>> {
+    let req = DateHistogramAggregationReq {
+        calendar_interval: Some(CalendarInterval::Day),
+        time_zone: time_zone.map(str::to_string),
+        ..Default::default()
+    };
+    let rounding = req.rounding()?;
+    Ok(Arc::new(
+        AUTO_INTERVALS
+            .iter()
+            .map(|auto_interval| rounding.with_interval(auto_interval.interval))
+            .collect(),
+    ))
+}
+
+/// Returns the position of the next interval to switch to during collection. Weeks are skipped,
+/// since the buckets of days collected into weeks couldn't be split into months later.
+fn next_collect_interval_pos(pos: usize) -> usize {
+    if pos + 1 == WEEK_POS {
+        pos + 2
+    } else {
+        pos + 1
+    }
+}
+
+/// Counts the buckets between the first and the last timestamp, but stops counting after `limit`.
+fn count_buckets(rounding: &DateRounding, first: i64, last: i64, limit: usize) -> usize {
+    let mut bucket_start = rounding.round_down(first);
+    let last_bucket_start = rounding.round_down(last);
+    let mut count = 1;
+    while bucket_start < last_bucket_start && count <= limit {
+        let next = rounding.next_bucket(bucket_start);
+        if next <= bucket_start {
+            break;
+        }
+        bucket_start = next;
+        count += 1;
+    }
+    count
+}
+
+/// Returns the position of the finest interval, which is not finer than the interval of the
+/// sorted buckets and returns at most `target_buckets` buckets, including the empty buckets
+/// between them.
+pub(crate) fn final_interval_pos(
+    buckets: &[IntermediateHistogramBucketEntry],
+    interval_pos: usize,
+    target_buckets: usize,
+    roundings: &[DateRounding],
+) -> usize {
+    let (first, last) = match (buckets.first(), buckets.last()) {
+        (Some(first), Some(last)) => (first.key as i64, last.key as i64),
+        _ => return interval_pos,
+    };
+    let last_pos = AUTO_INTERVALS.len() - 1;
+    (interval_pos..last_pos)
+        .filter(|&pos| pos != WEEK_POS || interval_pos <= DAY_POS)
+        .find(|&pos| count_buckets(&roundings[pos], first, last, target_buckets) <= target_buckets)
+        .unwrap_or(last_pos)
+}
+
+/// Rounds the keys of the sorted buckets with a coarser rounding and merges the buckets with the
+/// same key.
+pub(crate) fn rebucket(
+    buckets: Vec<IntermediateHistogramBucketEntry>,
+    rounding: &DateRounding,
+) -> crate::Result<Vec<IntermediateHistogramBucketEntry>> {
+    let mut rebucketed: Vec<IntermediateHistogramBucketEntry> = Vec::with_capacity(buckets.len());
+    for mut bucket in buckets {
+        bucket.key = rounding.round_down(bucket.key as i64) as f64;
+        match rebucketed.last_mut() {
+            Some(last) if last.key == bucket.key => last.merge_fruits(bucket)?,
+            _ => rebucketed.push(bucket),
+        }
+    }
+    Ok(rebucketed)
+}
+
+#[derive(Clone)]
+struct SegmentAutoDateHistogramBucketEntry {
+    doc_count: u64,
+    sub_aggregations: Option<Box<dyn SegmentAggregationCollector>>,
+    /// The sub aggregation results of the buckets, which were merged into this bucket when the
+    /// interval was increased.
+    merged_sub_aggregation: IntermediateAggregationResults,
+}
+
+impl Debug for SegmentAutoDateHistogramBucketEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SegmentAutoDateHistogramBucketEntry")
+            .field("doc_count", &self.doc_count)
+            .finish()
+    }
+}
+
+impl SegmentAutoDateHistogramBucketEntry {
+    fn merge(
+        &mut self,
+        other: SegmentAutoDateHistogramBucketEntry,
+        sub_aggregation_accessor: &AggregationsWithAccessor,
+    ) -> crate::Result<()> {
+        self.doc_count += other.doc_count;
+        if let Some(mut sub_aggregations) = other.sub_aggregations {
+            sub_aggregations.flush_staged_docs(sub_aggregation_accessor, true)?;
+            self.merged_sub_aggregation.merge_fruits(
+                sub_aggregations.into_intermediate_aggregations_result(sub_aggregation_accessor)?,
+            )?;
+        }
+        self.merged_sub_aggregation
+            .merge_fruits(other.merged_sub_aggregation)
+    }
+}
+
+/// The collector puts the values of the date fast field into the buckets of the current
+/// interval, and switches to the next coarser interval when there are more buckets than the
+/// target.
+#[derive(Clone, Debug)]
+pub(crate) struct SegmentAutoDateHistogramCollector {
+    roundings: Arc<Vec<DateRounding>>,
+    /// The position of the current interval in [`AUTO_INTERVALS`].
+    interval_pos: usize,
+    target_buckets: usize,
+    time_zone: Option<String>,
+    /// The buckets by UTC start timestamp in microseconds.
+    buckets: FxHashMap<i64, SegmentAutoDateHistogramBucketEntry>,
+    blueprint: Option<Box<dyn SegmentAggregationCollector>>,
+}
+
+impl SegmentAutoDateHistogramCollector {
+    pub(crate) fn from_req_and_validate(
+        req: &AutoDateHistogramAggregation,
+        sub_aggregation: &AggregationsWithAccessor,
+        field_type: Type,
+    ) -> crate::Result<Self> {
+        if field_type != Type::Date {
+            return Err(TantivyError::InvalidArgument(format!(
+                "auto_date_histogram requires a date field, but field {:?} has type {:?}",
+                req.field, field_type
+            )));
+        }
+        req.validate()?;
+        let blueprint = if sub_aggregation.is_empty() {
+            None
+        } else {
+            Some(build_segment_agg_collector(sub_aggregation)?)
+        };
+        Ok(SegmentAutoDateHistogramCollector {
+            roundings: auto_interval_roundings(req.time_zone.as_deref())?,
+            interval_pos: req.min_interval_pos(),
+            target_buckets: req.target_buckets() as usize,
+            time_zone: req.time_zone.clone(),
+            buckets: Default::default(),
+            blueprint,
+        })
+    }
+
+    pub(crate) fn into_intermediate_bucket_result(
+        self,
+        agg_with_accessor: &BucketAggregationWithAccessor,
+    ) -> crate::Result<IntermediateBucketResult> {
+        agg_with_accessor
+            .bucket_count
+            .add_count(self.buckets.len() as u32);
+        agg_with_accessor.bucket_count.validate_bucket_count()?;
+
+        let mut entries: Vec<(i64, SegmentAutoDateHistogramBucketEntry)> =
+            self.buckets.into_iter().collect();
+        entries.sort_unstable_by_key(|(key, _)| *key);
+        let buckets = entries
+            .into_iter()
+            .map(|(key, entry)| {
+                let mut sub_aggregation = entry.merged_sub_aggregation;
+                if let Some(sub_aggregations) = entry.sub_aggregations {
+                    sub_aggregation.merge_fruits(
+                        sub_aggregations.into_intermediate_aggregations_result(
+                            &agg_with_accessor.sub_aggregation,
+                        )?,
+                    )?;
+                }
+                Ok(IntermediateHistogramBucketEntry {
+                    key: key as f64,
+                    doc_count: entry.doc_count,
+                    sub_aggregation,
+                })
+            })
+            .collect::<crate::Result<_>>()?;
+        Ok(IntermediateBucketResult::AutoDateHistogram(
+            IntermediateAutoDateHistogramBucketResult {
+                buckets,
+                interval: auto_interval_name(self.interval_pos).to_string(),
+                time_zone: self.time_zone,
+            },
+        ))
+    }
+
+    #[inline]
+    pub(crate) fn collect_block(
+        &mut self,
+        docs: &[DocId],
+        bucket_with_accessor: &BucketAggregationWithAccessor,
+        force_flush: bool,
+    ) -> crate::Result<()> {
+        let accessor = bucket_with_accessor.column();
+        let sub_aggregation_accessor = &bucket_with_accessor.sub_aggregation;
+        let memory_before = self.memory_consumption();
+        let rounding = &self.roundings[self.interval_pos];
+        for &doc in docs {
+            for val in accessor.values(doc) {
+                let bucket_start = rounding.round_down(i64::from_u64(val));
+                let blueprint = &self.blueprint;
+                let entry = self.buckets.entry(bucket_start).or_insert_with(|| {
+                    SegmentAutoDateHistogramBucketEntry {
+                        doc_count: 0,
+                        sub_aggregations: blueprint.clone(),
+                        merged_sub_aggregation: Default::default(),
+                    }
+                });
+                entry.doc_count += 1;
+                if let Some(sub_aggregations) = entry.sub_aggregations.as_mut() {
+                    sub_aggregations.collect(doc, sub_aggregation_accessor)?;
+                }
+            }
+        }
+        while self.buckets.len() > self.target_buckets
+            && self.interval_pos + 1 < AUTO_INTERVALS.len()
+        {
+            self.increase_interval(sub_aggregation_accessor)?;
+        }
+        bucket_with_accessor
+            .memory_account
+            .add_memory_consumed(self.memory_consumption().saturating_sub(memory_before))?;
+        if force_flush {
+            for entry in self.buckets.values_mut() {
+                if let Some(sub_aggregations) = entry.sub_aggregations.as_mut() {
+                    sub_aggregations.flush_staged_docs(sub_aggregation_accessor, false)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Switches to the next coarser interval and merges the buckets, which fall into the same
+    /// bucket of the new interval.
+    fn increase_interval(
+        &mut self,
+        sub_aggregation_accessor: &AggregationsWithAccessor,
+    ) -> crate::Result<()> {
+        self.interval_pos = next_collect_interval_pos(self.interval_pos);
+        let rounding = &self.roundings[self.interval_pos];
+        let buckets = mem::take(&mut self.buckets);
+        for (key, entry) in buckets {
+            match self.buckets.entry(rounding.round_down(key)) {
+                std::collections::hash_map::Entry::Occupied(mut occupied) => {
+                    occupied.get_mut().merge(entry, sub_aggregation_accessor)?;
+                }
+                std::collections::hash_map::Entry::Vacant(vacant) => {
+                    vacant.insert(entry);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Estimates the memory of the buckets, which grows with the number of buckets.
+    fn memory_consumption(&self) -> u64 {
+        hash_map_memory(&self.buckets)
+            + self.buckets.len() as u64 * blueprint_memory(&self.blueprint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use time::format_description::well_known::Rfc3339;
+    use time::OffsetDateTime;
+
+    use crate::aggregation::agg_req::Aggregations;
+    use crate::aggregation::intermediate_agg_result::IntermediateAggregationResults;
+    use crate::aggregation::tests::exec_request;
+    use crate::aggregation::DistributedAggregationCollector;
+    use crate::indexer::NoMergePolicy;
+    use crate::query::AllQuery;
+    use crate::schema::{FAST, INDEXED};
+    use crate::{DateTime, Index};
+
+    fn micros(date: &str) -> i64 {
+        let date_time = OffsetDateTime::parse(date, &Rfc3339).unwrap();
+        DateTime::from_utc(date_time).into_timestamp_micros()
+    }
+
+    fn get_test_index(segments: &[Vec<&str>]) -> crate::Result<Index> {
+        let mut schema_builder = crate::schema::Schema::builder();
+        let date_field = schema_builder.add_date_field("date", FAST);
+        schema_builder.add_u64_field("score", FAST | INDEXED);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer = index.writer_with_num_threads(1, 30_000_000)?;
+        index_writer.set_merge_policy(Box::new(NoMergePolicy));
+        for dates in segments {
+            for date in dates {
+                index_writer.add_document(
+                    doc!(date_field => DateTime::from_timestamp_micros(micros(date))),
+                )?;
+            }
+            index_writer.commit()?;
+        }
+        Ok(index)
+    }
+
+    fn auto_date_histogram(req: Value, index: &Index) -> crate::Result<Value> {
+        let agg_req: Aggregations =
+            serde_json::from_value(json!({ "histogram": { "auto_date_histogram": req } })).unwrap();
+        Ok(exec_request(agg_req, index)?["histogram"].clone())
+    }
+
+    fn doc_counts(res: &Value) -> Vec<u64> {
+        res["buckets"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|bucket| bucket["doc_count"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn auto_date_histogram_picks_interval() -> crate::Result<()> {
+        let index = get_test_index(&[
+            vec!["2019-01-01T10:00:00Z", "2019-01-01T10:00:03Z"],
+            vec!["2019-01-01T10:00:09Z"],
+        ])?;
+        let res = auto_date_histogram(json!({ "field": "date" }), &index)?;
+        assert_eq!(res["interval"], "1s");
+        assert_eq!(doc_counts(&res), vec![1, 0, 0, 1, 0, 0, 0, 0, 0, 1]);
+
+        let res = auto_date_histogram(json!({ "field": "date", "buckets": 3 }), &index)?;
+        assert_eq!(res["interval"], "5s");
+        assert_eq!(doc_counts(&res), vec![2, 1]);
+        assert_eq!(
+            res["buckets"][0]["key"],
+            micros("2019-01-01T10:00:00Z") as f64
+        );
+        assert_eq!(res["buckets"][1]["key_as_string"], "2019-01-01T10:00:05Z");
+
+        let res = auto_date_histogram(
+            json!({ "field": "date", "minimum_interval": "hour", "keyed": true }),
+            &index,
+        )?;
+        assert_eq!(res["interval"], "1h");
+        assert_eq!(res["buckets"]["2019-01-01T10:00:00Z"]["doc_count"], 3);
+        Ok(())
+    }
+
+    #[test]
+    fn auto_date_histogram_merges_segments_with_different_intervals() -> crate::Result<()> {
+        let index = get_test_index(&[
+            // Collected with an interval of 1s.
+            vec!["2019-01-01T10:00:00Z"],
+            // Collected with an interval of 3M.
+            vec![
+                "2019-01-03T10:00:00Z",
+                "2019-02-01T10:00:00Z",
+                "2019-03-01T10:00:00Z",
+                "2019-05-01T10:00:00Z",
+            ],
+        ])?;
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "histogram": {
+                "auto_date_histogram": { "field": "date", "buckets": 3 },
+                "aggs": { "per_day": { "date_histogram": { "field": "date", "calendar_interval": "day", "min_doc_count": 1 } } }
+            }
+        }))
+        .unwrap();
+        let res = exec_request(agg_req, &index)?;
+        assert_eq!(res["histogram"]["interval"], "3M");
+        assert_eq!(doc_counts(&res["histogram"]), vec![4, 1]);
+        assert_eq!(
+            res["histogram"]["buckets"][0]["per_day"]["buckets"]
+                .as_array()
+                .unwrap()
+                .len(),
+            4
+        );
+
+        let res = auto_date_histogram(json!({ "field": "date", "buckets": 5 }), &index)?;
+        assert_eq!(res["interval"], "1M");
+        assert_eq!(doc_counts(&res), vec![2, 1, 1, 0, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn auto_date_histogram_weeks_and_time_zone() -> crate::Result<()> {
+        let index = get_test_index(&[vec![
+            "2019-01-01T10:00:00Z",
+            "2019-01-06T23:30:00Z",
+            "2019-01-20T10:00:00Z",
+        ]])?;
+        let res = auto_date_histogram(json!({ "field": "date", "buckets": 3 }), &index)?;
+        assert_eq!(res["interval"], "1w");
+        assert_eq!(doc_counts(&res), vec![2, 0, 1]);
+        assert_eq!(res["buckets"][0]["key_as_string"], "2018-12-31T00:00:00Z");
+
+        // The second document falls into the second week in the time zone.
+        let res = auto_date_histogram(
+            json!({ "field": "date", "buckets": 3, "time_zone": "+01:00" }),
+            &index,
+        )?;
+        assert_eq!(res["interval"], "1w");
+        assert_eq!(doc_counts(&res), vec![1, 1, 1]);
+        assert_eq!(
+            res["buckets"][0]["key_as_string"],
+            "2018-12-31T00:00:00+01:00"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn auto_date_histogram_distributed_merge() -> crate::Result<()> {
+        let first = get_test_index(&[vec!["2019-01-01T10:00:00Z", "2019-01-01T10:00:01Z"]])?;
+        let second = get_test_index(&[vec!["2019-01-01T12:00:00Z", "2019-01-01T14:30:00Z"]])?;
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "histogram": { "auto_date_histogram": { "field": "date", "buckets": 5 } }
+        }))
+        .unwrap();
+        let collect = |index: &Index| -> crate::Result<IntermediateAggregationResults> {
+            let collector = DistributedAggregationCollector::from_aggs(agg_req.clone(), None);
+            index.reader()?.searcher().search(&AllQuery, &collector)
+        };
+        let mut merged = collect(&first)?;
+        merged.merge_fruits(collect(&second)?)?;
+        let res = serde_json::to_value(merged.into_final_bucket_result(agg_req, &first.schema())?)?;
+        assert_eq!(res["histogram"]["interval"], "1h");
+        assert_eq!(doc_counts(&res["histogram"]), vec![2, 0, 1, 0, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn auto_date_histogram_invalid_requests() -> crate::Result<()> {
+        let index = get_test_index(&[vec!["2019-01-01T10:00:00Z"]])?;
+        for req in [
+            json!({ "field": "date", "buckets": 0 }),
+            json!({ "field": "date", "time_zone": "Mars/Olympus" }),
+            json!({ "field": "score" }),
+        ] {
+            assert!(auto_date_histogram(req.clone(), &index).is_err(), "{}", req);
+        }
+        let agg_req: serde_json::Result<Aggregations> = serde_json::from_value(json!({
+            "histogram": { "auto_date_histogram": { "field": "date", "minimum_interval": "week" } }
+        }));
+        assert!(agg_req.is_err());
+        Ok(())
+    }
+}
@@ -1,25 +1,54 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use columnar::MonotonicallyMappableToU64;
+use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
 
+use crate::aggregation::agg_req::AggregationsInternal;
+use crate::aggregation::agg_req_with_accessor::{
+    AggregationsWithAccessor, BucketAggregationWithAccessor,
+};
+use crate::aggregation::agg_result::BucketEntry;
+use crate::aggregation::date::{
+    civil_from_days, days_from_civil, MICROS_PER_DAY, MICROS_PER_SECOND,
+};
+use crate::aggregation::intermediate_agg_result::{
+    IntermediateBucketResult, IntermediateHistogramBucketEntry,
+};
+use crate::aggregation::memory_budget::{blueprint_memory, hash_map_memory};
+use crate::aggregation::segment_agg_result::{
+    build_segment_agg_collector, SegmentAggregationCollector,
+};
+use crate::aggregation::{format_date_with_offset, Key, MAX_BUCKET_COUNT};
+use crate::schema::{Schema, Type};
+use crate::{DocId, TantivyError};
+
 /// DateHistogramAggregation is similar to `HistogramAggregation`, but it can only be used with date
 /// type.
 ///
-/// Currently only **fixed time** intervals are supported. Calendar-aware time intervals are not
-/// supported.
+/// The buckets are either **fixed time** intervals, like `30d`, or **calendar-aware** intervals,
+/// like `month`, which respect the different lengths of months and years.
 ///
-/// Like the histogram, values are rounded down into the closest bucket.
+/// Like the histogram, values are rounded down into the closest bucket. Rounding happens in the
+/// requested `time_zone`, so that e.g. daily buckets start at midnight local time, also across
+/// daylight saving time changes.
 ///
-/// For this calculation all fastfield values are converted to f64.
+/// The bucket keys are UTC timestamps in microseconds, like the keys of the histogram
+/// aggregation on date fields. `key_as_string` is the start of the bucket in RFC3339 format,
+/// with the UTC offset of the time zone.
 ///
 /// # Limitations/Compatibility
-/// Only fixed time intervals are supported.
+/// The `format`, `extended_bounds` and `hard_bounds` parameters are not supported.
 ///
 /// # JSON Format
 /// ```json
 /// {
-///     "prices": {
+///     "sales_per_month": {
 ///         "date_histogram": {
-///             "field": "price",
-///             "fixed_interval": "30d"
+///             "field": "sold_at",
+///             "calendar_interval": "month",
+///             "time_zone": "Europe/Berlin"
 ///         }
 ///     }
 /// }
@@ -51,19 +80,481 @@ pub struct DateHistogramAggregationReq {
     ///
     /// Fractional time values are not supported, but you can address this by shifting to another
     /// time unit (e.g., `1.5h` could instead be specified as `90m`).
-    pub fixed_interval: String,
+    ///
+    /// Exactly one of `fixed_interval` and `calendar_interval` has to be set.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub fixed_interval: Option<String>,
+    /// The calendar-aware interval of the buckets, see [`CalendarInterval`].
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub calendar_interval: Option<CalendarInterval>,
     /// Intervals implicitly defines an absolute grid of buckets `[interval * k, interval * (k +
-    /// 1))`.
+    /// 1))`. The offset shifts this grid, e.g. `+6h` for days starting at 6am. Negative offsets
+    /// like `-6h` are supported.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
     pub offset: Option<String>,
+    /// The time zone in which buckets are rounded. Either a UTC offset like `+01:00` or `-0800`,
+    /// or an IANA time zone like `Europe/Berlin`. Defaults to `UTC`.
+    ///
+    /// IANA time zones are read from the time zone database of the system, which is looked up in
+    /// `/usr/share/zoneinfo`, `/share/zoneinfo` and `/etc/zoneinfo` on Unix. The database is not
+    /// bundled with tantivy, so the request fails if it is not installed, e.g. in a minimal
+    /// container image without the `tzdata` package, or on other platforms. UTC offsets don't
+    /// need the database.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub time_zone: Option<String>,
+    /// The minimum number of documents in a bucket to be returned. Defaults to 0.
+    ///
+    /// With 0, empty buckets are returned between the first and the last non-empty bucket.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub min_doc_count: Option<u64>,
     /// Whether to return the buckets as a hash map
     #[serde(default)]
     pub keyed: bool,
 }
 
 impl DateHistogramAggregationReq {
-    fn validate(&self) -> crate::Result<()> {
+    /// Returns the minimum number of documents required for a bucket to be returned.
+    pub fn min_doc_count(&self) -> u64 {
+        self.min_doc_count.unwrap_or(0)
+    }
+
+    /// Validates the request and returns the rounding of the timestamps into buckets.
+    pub(crate) fn rounding(&self) -> crate::Result<DateRounding> {
+        let interval = match (&self.fixed_interval, self.calendar_interval) {
+            (Some(fixed_interval), None) => {
+                let interval = parse_into_milliseconds(fixed_interval)?;
+                if interval == 0 {
+                    return Err(TantivyError::InvalidArgument(
+                        "fixed_interval must be a positive value".to_string(),
+                    ));
+                }
+                DateInterval::Fixed(interval as i64 * 1000)
+            }
+            (None, Some(calendar_interval)) => DateInterval::Calendar(calendar_interval),
+            _ => {
+                return Err(TantivyError::InvalidArgument(
+                    "exactly one of fixed_interval and calendar_interval is required".to_string(),
+                ))
+            }
+        };
+        let offset = match self.offset.as_deref() {
+            Some(offset) => parse_offset_into_milliseconds(offset)? * 1000,
+            None => 0,
+        };
+        let time_zone = match self.time_zone.as_deref() {
+            Some(time_zone) => DateHistogramTimeZone::parse(time_zone)?,
+            None => DateHistogramTimeZone::Fixed(0),
+        };
+        Ok(DateRounding {
+            interval,
+            offset,
+            time_zone,
+        })
+    }
+}
+
+/// Calendar-aware intervals for the [`DateHistogramAggregationReq`].
+///
+/// Weeks start on Monday. De/Serializes from/to the unit name like `"month"`, the single unit
+/// form like `"1M"` is accepted as well.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CalendarInterval {
+    /// One minute
+    #[serde(rename = "minute", alias = "1m")]
+    Minute,
+    /// One hour
+    #[serde(rename = "hour", alias = "1h")]
+    Hour,
+    /// One day
+    #[serde(rename = "day", alias = "1d")]
+    Day,
+    /// One week, starting on Monday
+    #[serde(rename = "week", alias = "1w")]
+    Week,
+    /// One month
+    #[serde(rename = "month", alias = "1M")]
+    Month,
+    /// Three months, starting in January, April, July and October
+    #[serde(rename = "quarter", alias = "1q")]
+    Quarter,
+    /// One year
+    #[serde(rename = "year", alias = "1y")]
+    Year,
+}
+
+impl CalendarInterval {
+    /// Rounds the local timestamp in microseconds down to the start of the interval.
+    pub(crate) fn round_down(self, local: i64) -> i64 {
+        let fixed = |interval: i64| local - local.rem_euclid(interval);
+        match self {
+            CalendarInterval::Minute => fixed(60 * MICROS_PER_SECOND),
+            CalendarInterval::Hour => fixed(3_600 * MICROS_PER_SECOND),
+            CalendarInterval::Day => fixed(MICROS_PER_DAY),
+            CalendarInterval::Week => {
+                let days = local.div_euclid(MICROS_PER_DAY);
+                // 1970-01-01 was a Thursday.
+                let days_since_monday = (days + 3).rem_euclid(7);
+                (days - days_since_monday) * MICROS_PER_DAY
+            }
+            CalendarInterval::Month | CalendarInterval::Quarter | CalendarInterval::Year => {
+                let (year, month, _day) = civil_from_days(local.div_euclid(MICROS_PER_DAY));
+                let month = match self {
+                    CalendarInterval::Month => month,
+                    CalendarInterval::Quarter => (month - 1) / 3 * 3 + 1,
+                    _ => 1,
+                };
+                days_from_civil(year, month, 1) * MICROS_PER_DAY
+            }
+        }
+    }
+
+    /// Returns the start of the next interval for a local timestamp at the start of an interval.
+    fn next(self, local: i64) -> i64 {
+        let add_months = |months: i64| {
+            let (year, month, _day) = civil_from_days(local.div_euclid(MICROS_PER_DAY));
+            let month_index = year * 12 + (month - 1) + months;
+            days_from_civil(
+                month_index.div_euclid(12),
+                month_index.rem_euclid(12) + 1,
+                1,
+            ) * MICROS_PER_DAY
+        };
+        match self {
+            CalendarInterval::Minute => local + 60 * MICROS_PER_SECOND,
+            CalendarInterval::Hour => local + 3_600 * MICROS_PER_SECOND,
+            CalendarInterval::Day => local + MICROS_PER_DAY,
+            CalendarInterval::Week => local + 7 * MICROS_PER_DAY,
+            CalendarInterval::Month => add_months(1),
+            CalendarInterval::Quarter => add_months(3),
+            CalendarInterval::Year => add_months(12),
+        }
+    }
+}
+
+/// The directories in which the time zone database of the system is looked up.
+const TIME_ZONE_DATABASE_DIRECTORIES: [&str; 3] =
+    ["/usr/share/zoneinfo", "/share/zoneinfo", "/etc/zoneinfo"];
+
+/// The time zone in which date histogram buckets are rounded.
+#[derive(Clone, Debug)]
+pub(crate) enum DateHistogramTimeZone {
+    /// A fixed UTC offset in seconds.
+    Fixed(i32),
+    /// A time zone from the time zone database, which may change its UTC offset over time.
+    Zone(Arc<tz::TimeZone>),
+}
+
+impl DateHistogramTimeZone {
+    fn parse(time_zone: &str) -> crate::Result<Self> {
+        if time_zone == "Z" || time_zone == "UTC" {
+            return Ok(DateHistogramTimeZone::Fixed(0));
+        }
+        if let Some(offset) = time_zone.strip_prefix('+') {
+            return Ok(DateHistogramTimeZone::Fixed(parse_utc_offset(
+                time_zone, offset,
+            )?));
+        }
+        if let Some(offset) = time_zone.strip_prefix('-') {
+            return Ok(DateHistogramTimeZone::Fixed(-parse_utc_offset(
+                time_zone, offset,
+            )?));
+        }
+        let zone = tz::TimeZone::from_posix_tz(time_zone).map_err(|err| {
+            if time_zone.contains('/') {
+                // IANA time zone names are like `Area/Location`, and are only read from the
+                // time zone database.
+                TantivyError::InvalidArgument(format!(
+                    "could not load time zone {:?}: it was not found in the time zone database \
+                     of the system ({}). Install the time zone database, e.g. the `tzdata` \
+                     package, or use a UTC offset like +01:00",
+                    time_zone,
+                    TIME_ZONE_DATABASE_DIRECTORIES.join(", ")
+                ))
+            } else {
+                TantivyError::InvalidArgument(format!(
+                    "could not load time zone {:?}: {}",
+                    time_zone, err
+                ))
+            }
+        })?;
+        Ok(DateHistogramTimeZone::Zone(Arc::new(zone)))
+    }
+
+    /// Returns the UTC offset in seconds at the given UTC timestamp in microseconds.
+    fn utc_offset(&self, utc: i64) -> i32 {
+        match self {
+            DateHistogramTimeZone::Fixed(offset) => *offset,
+            DateHistogramTimeZone::Zone(zone) => zone
+                .find_local_time_type(utc.div_euclid(MICROS_PER_SECOND))
+                .map(|local_time_type| local_time_type.ut_offset())
+                .unwrap_or(0),
+        }
+    }
+
+    fn to_local(&self, utc: i64) -> i64 {
+        utc + self.utc_offset(utc) as i64 * MICROS_PER_SECOND
+    }
+
+    fn to_utc(&self, local: i64) -> i64 {
+        // The offset depends on the UTC time we are looking for. Guess it with the offset at the
+        // local time, which is only wrong close to offset changes, and correct it once.
+        let guess = local - self.utc_offset(local) as i64 * MICROS_PER_SECOND;
+        local - self.utc_offset(guess) as i64 * MICROS_PER_SECOND
+    }
+}
+
+/// Parses a UTC offset without sign in the format `hh:mm`, `hhmm` or `hh` into seconds.
+fn parse_utc_offset(time_zone: &str, offset: &str) -> crate::Result<i32> {
+    let invalid_offset = || {
+        TantivyError::InvalidArgument(format!(
+            "could not parse time zone offset {:?}, expected e.g. +01:00",
+            time_zone
+        ))
+    };
+    let digits = offset.replacen(':', "", 1);
+    if !(digits.len() == 2 || digits.len() == 4) || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid_offset());
+    }
+    let hours: i32 = digits[..2].parse().map_err(|_| invalid_offset())?;
+    let minutes: i32 = digits[2..].parse().unwrap_or(0);
+    if hours > 18 || minutes > 59 {
+        return Err(invalid_offset());
+    }
+    Ok(hours * 3_600 + minutes * 60)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum DateInterval {
+    /// A fixed interval in microseconds.
+    Fixed(i64),
+    Calendar(CalendarInterval),
+}
+
+/// Rounds timestamps in microseconds down to the start of their date histogram bucket.
+#[derive(Clone, Debug)]
+pub(crate) struct DateRounding {
+    interval: DateInterval,
+    /// The offset of the buckets in microseconds.
+    offset: i64,
+    time_zone: DateHistogramTimeZone,
+}
+
+impl DateRounding {
+    /// Returns the UTC start of the bucket of the UTC timestamp.
+    #[inline]
+    pub(crate) fn round_down(&self, utc: i64) -> i64 {
+        let local = self.time_zone.to_local(utc) - self.offset;
+        let bucket_start = match self.interval {
+            DateInterval::Fixed(interval) => local - local.rem_euclid(interval),
+            DateInterval::Calendar(calendar_interval) => calendar_interval.round_down(local),
+        };
+        self.time_zone.to_utc(bucket_start + self.offset)
+    }
+
+    /// Returns the UTC start of the bucket following the bucket starting at `bucket_start`.
+    pub(crate) fn next_bucket(&self, bucket_start: i64) -> i64 {
+        let local = self.time_zone.to_local(bucket_start) - self.offset;
+        let next_local = match self.interval {
+            DateInterval::Fixed(interval) => local + interval,
+            DateInterval::Calendar(calendar_interval) => calendar_interval.next(local),
+        };
+        self.round_down(self.time_zone.to_utc(next_local + self.offset))
+    }
+
+    /// Returns the UTC offset in seconds of the time zone at the UTC timestamp.
+    pub(crate) fn utc_offset(&self, utc: i64) -> i32 {
+        self.time_zone.utc_offset(utc)
+    }
+
+    /// Returns a rounding with the same offset and time zone, but another interval.
+    pub(crate) fn with_interval(&self, interval: DateInterval) -> DateRounding {
+        DateRounding {
+            interval,
+            offset: self.offset,
+            time_zone: self.time_zone.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct SegmentDateHistogramBucketEntry {
+    doc_count: u64,
+    sub_aggregations: Option<Box<dyn SegmentAggregationCollector>>,
+}
+
+impl Debug for SegmentDateHistogramBucketEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SegmentDateHistogramBucketEntry")
+            .field("doc_count", &self.doc_count)
+            .finish()
+    }
+}
+
+/// The collector puts the values of the date fast field into their buckets.
+///
+/// Unlike the `SegmentHistogramCollector`, buckets are created lazily, since the buckets of
+/// calendar intervals don't have a fixed width.
+#[derive(Clone, Debug)]
+pub(crate) struct SegmentDateHistogramCollector {
+    rounding: DateRounding,
+    /// The buckets by UTC start timestamp in microseconds.
+    buckets: FxHashMap<i64, SegmentDateHistogramBucketEntry>,
+    blueprint: Option<Box<dyn SegmentAggregationCollector>>,
+}
+
+impl SegmentDateHistogramCollector {
+    pub(crate) fn from_req_and_validate(
+        req: &DateHistogramAggregationReq,
+        sub_aggregation: &AggregationsWithAccessor,
+        field_type: Type,
+    ) -> crate::Result<Self> {
+        if field_type != Type::Date {
+            return Err(TantivyError::InvalidArgument(format!(
+                "date_histogram requires a date field, but field {:?} has type {:?}",
+                req.field, field_type
+            )));
+        }
+        let rounding = req.rounding()?;
+        let blueprint = if sub_aggregation.is_empty() {
+            None
+        } else {
+            Some(build_segment_agg_collector(sub_aggregation)?)
+        };
+        Ok(SegmentDateHistogramCollector {
+            rounding,
+            buckets: Default::default(),
+            blueprint,
+        })
+    }
+
+    pub(crate) fn into_intermediate_bucket_result(
+        self,
+        agg_with_accessor: &BucketAggregationWithAccessor,
+    ) -> crate::Result<IntermediateBucketResult> {
+        agg_with_accessor
+            .bucket_count
+            .add_count(self.buckets.len() as u32);
+        agg_with_accessor.bucket_count.validate_bucket_count()?;
+
+        let mut entries: Vec<(i64, SegmentDateHistogramBucketEntry)> =
+            self.buckets.into_iter().collect();
+        entries.sort_unstable_by_key(|(key, _)| *key);
+        let buckets = entries
+            .into_iter()
+            .map(|(key, entry)| {
+                let sub_aggregation = if let Some(sub_aggregation) = entry.sub_aggregations {
+                    sub_aggregation
+                        .into_intermediate_aggregations_result(&agg_with_accessor.sub_aggregation)?
+                } else {
+                    Default::default()
+                };
+                Ok(IntermediateHistogramBucketEntry {
+                    key: key as f64,
+                    doc_count: entry.doc_count,
+                    sub_aggregation,
+                })
+            })
+            .collect::<crate::Result<_>>()?;
+        Ok(IntermediateBucketResult::Histogram { buckets })
+    }
+
+    #[inline]
+    pub(crate) fn collect_block(
+        &mut self,
+        docs: &[DocId],
+        bucket_with_accessor: &BucketAggregationWithAccessor,
+        force_flush: bool,
+    ) -> crate::Result<()> {
+        let accessor = bucket_with_accessor.column();
+        let sub_aggregation_accessor = &bucket_with_accessor.sub_aggregation;
+        let memory_before = self.memory_consumption();
+        for &doc in docs {
+            for val in accessor.values(doc) {
+                let bucket_start = self.rounding.round_down(i64::from_u64(val));
+                let blueprint = &self.blueprint;
+                let entry = self.buckets.entry(bucket_start).or_insert_with(|| {
+                    SegmentDateHistogramBucketEntry {
+                        doc_count: 0,
+                        sub_aggregations: blueprint.clone(),
+                    }
+                });
+                entry.doc_count += 1;
+                if let Some(sub_aggregations) = entry.sub_aggregations.as_mut() {
+                    sub_aggregations.collect(doc, sub_aggregation_accessor)?;
+                }
+            }
+        }
+        bucket_with_accessor
+            .memory_account
+            .add_memory_consumed(self.memory_consumption() - memory_before)?;
+        if force_flush {
+            for entry in self.buckets.values_mut() {
+                if let Some(sub_aggregations) = entry.sub_aggregations.as_mut() {
+                    sub_aggregations.flush_staged_docs(sub_aggregation_accessor, false)?;
+                }
+            }
+        }
         Ok(())
     }
+
+    /// Estimates the memory of the buckets, which grows with the number of buckets.
+    fn memory_consumption(&self) -> u64 {
+        hash_map_memory(&self.buckets)
+            + self.buckets.len() as u64 * blueprint_memory(&self.blueprint)
+    }
+}
+
+/// Converts the merged and sorted intermediate buckets, which were rounded with `rounding`, to the
+/// final buckets. With a `min_doc_count` of 0, the gaps between the buckets are filled with empty
+/// buckets.
+pub(crate) fn intermediate_date_histogram_buckets_to_final_buckets(
+    buckets: Vec<IntermediateHistogramBucketEntry>,
+    rounding: &DateRounding,
+    min_doc_count: u64,
+    sub_aggregation: &AggregationsInternal,
+    schema: &Schema,
+) -> crate::Result<Vec<BucketEntry>> {
+    let mut final_buckets: Vec<BucketEntry> = Vec::with_capacity(buckets.len());
+    let mut next_bucket_start: Option<i64> = None;
+    for bucket in buckets {
+        let bucket_start = bucket.key as i64;
+        if min_doc_count == 0 {
+            if let Some(mut empty_bucket_start) = next_bucket_start {
+                while empty_bucket_start < bucket_start {
+                    if final_buckets.len() >= MAX_BUCKET_COUNT as usize {
+                        return Err(TantivyError::InvalidArgument(
+                            "Aborting aggregation because too many buckets were created"
+                                .to_string(),
+                        ));
+                    }
+                    let empty_bucket = IntermediateHistogramBucketEntry {
+                        key: empty_bucket_start as f64,
+                        doc_count: 0,
+                        sub_aggregation: Default::default(),
+                    };
+                    final_buckets
+                        .push(empty_bucket.into_final_bucket_entry(sub_aggregation, schema)?);
+                    let next = rounding.next_bucket(empty_bucket_start);
+                    if next <= empty_bucket_start {
+                        break;
+                    }
+                    empty_bucket_start = next;
+                }
+            }
+            next_bucket_start = Some(rounding.next_bucket(bucket_start));
+        }
+        if bucket.doc_count >= min_doc_count {
+            final_buckets.push(bucket.into_final_bucket_entry(sub_aggregation, schema)?);
+        }
+    }
+
+    for bucket in final_buckets.iter_mut() {
+        if let Key::F64(val) = bucket.key {
+            let utc = val as i64;
+            bucket.key_as_string = Some(format_date_with_offset(utc, rounding.utc_offset(utc))?);
+        }
+    }
+    Ok(final_buckets)
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -77,7 +568,13 @@ pub enum DateHistogramParseError {
     UnitMissing(String),
 }
 
-fn parse_into_milliseconds(input: &str) -> Result<u64, DateHistogramParseError> {
+impl From<DateHistogramParseError> for TantivyError {
+    fn from(err: DateHistogramParseError) -> Self {
+        TantivyError::InvalidArgument(format!("could not parse date interval: {:?}", err))
+    }
+}
+
+pub(crate) fn parse_into_milliseconds(input: &str) -> Result<u64, DateHistogramParseError> {
     let split_boundary = input
         .char_indices()
         .take_while(|(pos, el)| el.is_numeric())
@@ -102,9 +599,27 @@ fn parse_into_milliseconds(input: &str) -> Result<u64, DateHistogramParseError>
     Ok(number * multiplier_from_unit)
 }
 
+/// Like `parse_into_milliseconds`, but accepts a leading `+` or `-` sign, e.g. `-6h`.
+pub(crate) fn parse_offset_into_milliseconds(input: &str) -> Result<i64, DateHistogramParseError> {
+    if let Some(negative_offset) = input.strip_prefix('-') {
+        Ok(-(parse_into_milliseconds(negative_offset)? as i64))
+    } else {
+        Ok(parse_into_milliseconds(input.strip_prefix('+').unwrap_or(input))? as i64)
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use serde_json::Value;
+    use time::format_description::well_known::Rfc3339;
+    use time::OffsetDateTime;
+
     use super::*;
+    use crate::aggregation::agg_req::Aggregations;
+    use crate::aggregation::tests::exec_request;
+    use crate::indexer::NoMergePolicy;
+    use crate::schema::FAST;
+    use crate::{DateTime, Index};
 
     #[test]
     fn parser_test() {
@@ -122,5 +637,265 @@ mod tests {
             parse_into_milliseconds("ms").unwrap_err(),
             DateHistogramParseError::NumberMissing("ms".to_string())
         );
+        assert_eq!(parse_offset_into_milliseconds("-1h").unwrap(), -3_600_000);
+        assert_eq!(parse_offset_into_milliseconds("+1s").unwrap(), 1_000);
+    }
+
+    #[test]
+    fn civil_days_test() {
+        for days in -1_000_000..1_000_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(days_from_civil(2020, 2, 29), 18_321);
+    }
+
+    fn micros(date: &str) -> i64 {
+        let date_time = OffsetDateTime::parse(date, &Rfc3339).unwrap();
+        DateTime::from_utc(date_time).into_timestamp_micros()
+    }
+
+    fn get_test_index(segments: &[Vec<&str>]) -> crate::Result<Index> {
+        let mut schema_builder = crate::schema::Schema::builder();
+        let date_field = schema_builder.add_date_field("date", FAST);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer = index.writer_with_num_threads(1, 30_000_000)?;
+        index_writer.set_merge_policy(Box::new(NoMergePolicy));
+        for dates in segments {
+            for date in dates {
+                index_writer.add_document(
+                    doc!(date_field => DateTime::from_timestamp_micros(micros(date))),
+                )?;
+            }
+            index_writer.commit()?;
+        }
+        Ok(index)
+    }
+
+    fn date_histogram(req: Value, index: &Index) -> crate::Result<Value> {
+        let agg_req: Aggregations =
+            serde_json::from_value(json!({ "histogram": { "date_histogram": req } })).unwrap();
+        Ok(exec_request(agg_req, index)?["histogram"].clone())
+    }
+
+    #[test]
+    fn date_histogram_calendar_month_with_offset_time_zone() -> crate::Result<()> {
+        let index = get_test_index(&[
+            vec!["2019-01-15T10:00:00Z", "2019-01-31T23:30:00Z"],
+            vec!["2019-02-01T00:00:00Z", "2019-04-10T00:00:00Z"],
+        ])?;
+
+        let res = date_histogram(
+            json!({ "field": "date", "calendar_interval": "month", "time_zone": "+01:00" }),
+            &index,
+        )?;
+        assert_eq!(
+            res["buckets"],
+            json!([
+                {
+                    "key": micros("2018-12-31T23:00:00Z") as f64,
+                    "key_as_string": "2019-01-01T00:00:00+01:00",
+                    "doc_count": 1
+                },
+                {
+                    "key": micros("2019-01-31T23:00:00Z") as f64,
+                    "key_as_string": "2019-02-01T00:00:00+01:00",
+                    "doc_count": 2
+                },
+                {
+                    "key": micros("2019-02-28T23:00:00Z") as f64,
+                    "key_as_string": "2019-03-01T00:00:00+01:00",
+                    "doc_count": 0
+                },
+                {
+                    "key": micros("2019-03-31T23:00:00Z") as f64,
+                    "key_as_string": "2019-04-01T00:00:00+01:00",
+                    "doc_count": 1
+                }
+            ])
+        );
+
+        // Without time zone, the second document falls into January.
+        let res = date_histogram(
+            json!({ "field": "date", "calendar_interval": "1M", "min_doc_count": 1 }),
+            &index,
+        )?;
+        assert_eq!(res["buckets"][0]["key_as_string"], "2019-01-01T00:00:00Z");
+        assert_eq!(res["buckets"][0]["doc_count"], 2);
+        assert_eq!(res["buckets"][1]["key_as_string"], "2019-02-01T00:00:00Z");
+        assert_eq!(res["buckets"][1]["doc_count"], 1);
+        assert_eq!(res["buckets"][2]["key_as_string"], "2019-04-01T00:00:00Z");
+        assert_eq!(res["buckets"][3], Value::Null);
+        Ok(())
+    }
+
+    #[test]
+    fn date_histogram_calendar_intervals() -> crate::Result<()> {
+        let index = get_test_index(&[vec![
+            "2019-12-31T23:59:59Z",
+            "2020-01-01T00:00:00Z",
+            "2020-02-29T12:00:00Z",
+            "2020-05-06T12:00:00Z",
+        ]])?;
+        let bucket_strings = |res: &Value| {
+            res["buckets"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|bucket| {
+                    format!(
+                        "{} {}",
+                        bucket["key_as_string"].as_str().unwrap(),
+                        bucket["doc_count"]
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let res = date_histogram(
+            json!({ "field": "date", "calendar_interval": "year" }),
+            &index,
+        )?;
+        assert_eq!(
+            bucket_strings(&res),
+            vec!["2019-01-01T00:00:00Z 1", "2020-01-01T00:00:00Z 3"]
+        );
+
+        let res = date_histogram(
+            json!({ "field": "date", "calendar_interval": "quarter" }),
+            &index,
+        )?;
+        assert_eq!(
+            bucket_strings(&res),
+            vec![
+                "2019-10-01T00:00:00Z 1",
+                "2020-01-01T00:00:00Z 2",
+                "2020-04-01T00:00:00Z 1"
+            ]
+        );
+
+        let res = date_histogram(
+            json!({ "field": "date", "calendar_interval": "week", "min_doc_count": 1 }),
+            &index,
+        )?;
+        assert_eq!(
+            bucket_strings(&res),
+            vec![
+                "2019-12-30T00:00:00Z 2",
+                "2020-02-24T00:00:00Z 1",
+                "2020-05-04T00:00:00Z 1"
+            ]
+        );
+
+        let res = date_histogram(
+            json!({ "field": "date", "fixed_interval": "1d", "offset": "+12h", "min_doc_count": 1 }),
+            &index,
+        )?;
+        assert_eq!(
+            bucket_strings(&res),
+            vec![
+                "2019-12-31T12:00:00Z 2",
+                "2020-02-29T12:00:00Z 1",
+                "2020-05-06T12:00:00Z 1"
+            ]
+        );
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn date_histogram_iana_time_zone_dst() -> crate::Result<()> {
+        // Europe/Berlin switches from +01:00 to +02:00 on 2019-03-31.
+        let index = get_test_index(&[vec![
+            "2019-03-30T23:30:00Z",
+            "2019-03-31T21:30:00Z",
+            "2019-03-31T22:30:00Z",
+        ]])?;
+        let res = date_histogram(
+            json!({ "field": "date", "calendar_interval": "day", "time_zone": "Europe/Berlin" }),
+            &index,
+        )?;
+        assert_eq!(
+            res["buckets"],
+            json!([
+                {
+                    "key": micros("2019-03-30T23:00:00Z") as f64,
+                    "key_as_string": "2019-03-31T00:00:00+01:00",
+                    "doc_count": 2
+                },
+                {
+                    "key": micros("2019-03-31T22:00:00Z") as f64,
+                    "key_as_string": "2019-04-01T00:00:00+02:00",
+                    "doc_count": 1
+                }
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn date_histogram_keyed_and_sub_aggregation() -> crate::Result<()> {
+        let index = get_test_index(&[
+            vec!["2019-01-01T10:00:00Z", "2019-01-01T12:00:00Z"],
+            vec!["2019-01-02T10:00:00Z"],
+        ])?;
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "days": {
+                "date_histogram": { "field": "date", "fixed_interval": "1d", "keyed": true },
+                "aggs": {
+                    "hours": { "date_histogram": { "field": "date", "calendar_interval": "hour" } }
+                }
+            }
+        }))
+        .unwrap();
+        let res = exec_request(agg_req, &index)?;
+        assert_eq!(
+            res["days"]["buckets"]["2019-01-01T00:00:00Z"]["doc_count"],
+            2
+        );
+        assert_eq!(
+            res["days"]["buckets"]["2019-01-01T00:00:00Z"]["hours"]["buckets"]
+                .as_array()
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(
+            res["days"]["buckets"]["2019-01-02T00:00:00Z"]["doc_count"],
+            1
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn date_histogram_invalid_requests() -> crate::Result<()> {
+        let index = get_test_index(&[vec!["2019-01-01T10:00:00Z"]])?;
+        for req in [
+            json!({ "field": "date" }),
+            json!({ "field": "date", "fixed_interval": "1d", "calendar_interval": "day" }),
+            json!({ "field": "date", "fixed_interval": "1M" }),
+            json!({ "field": "date", "fixed_interval": "0d" }),
+            json!({ "field": "date", "calendar_interval": "day", "time_zone": "+1:00" }),
+            json!({ "field": "date", "calendar_interval": "day", "time_zone": "Mars/Olympus" }),
+            json!({ "field": "date", "calendar_interval": "day", "offset": "1x" }),
+        ] {
+            assert!(date_histogram(req.clone(), &index).is_err(), "{}", req);
+        }
+        let invalid_interval: serde_json::Result<CalendarInterval> =
+            serde_json::from_value(json!("2M"));
+        assert!(invalid_interval.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn date_histogram_missing_time_zone_error() {
+        let err = DateHistogramTimeZone::parse("Mars/Olympus").unwrap_err();
+        let TantivyError::InvalidArgument(msg) = err else {
+            panic!("expected an invalid argument error, got {:?}", err);
+        };
+        assert!(msg.contains("time zone database"), "{}", msg);
+        assert!(msg.contains("/usr/share/zoneinfo"), "{}", msg);
     }
 }
@@ -1,5 +1,6 @@
 use std::cmp::Ordering;
 use std::fmt::Display;
+use std::mem;
 
 use columnar::Column;
 use itertools::Itertools;
@@ -13,6 +14,7 @@ use crate::aggregation::agg_result::BucketEntry;
 use crate::aggregation::intermediate_agg_result::{
     IntermediateAggregationResults, IntermediateBucketResult, IntermediateHistogramBucketEntry,
 };
+use crate::aggregation::memory_budget::MemoryAccount;
 use crate::aggregation::segment_agg_result::{
     GenericSegmentAggregationResultsCollector, SegmentAggregationCollector,
 };
@@ -119,6 +121,10 @@ pub struct HistogramAggregation {
     /// Whether to return the buckets as a hash map
     #[serde(default)]
     pub keyed: bool,
+    /// The value to use for documents without a value in the field. By default such documents
+    /// are ignored.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub missing: Option<f64>,
 }
 
 impl HistogramAggregation {
@@ -210,6 +216,7 @@ pub struct SegmentHistogramCollector {
     min_doc_count: u64,
     first_bucket_num: i64,
     bounds: HistogramBounds,
+    missing: Option<f64>,
 }
 
 impl SegmentHistogramCollector {
@@ -285,13 +292,31 @@ impl SegmentHistogramCollector {
         sub_aggregation: &AggregationsWithAccessor,
         field_type: Type,
         accessor: &Column<u64>,
+        memory_account: &MemoryAccount,
     ) -> crate::Result<Self> {
         req.validate()?;
         let min = f64_from_fastfield_u64(accessor.min_value(), &field_type);
         let max = f64_from_fastfield_u64(accessor.max_value(), &field_type);
+        // The missing value needs a bucket, even if it is outside of the values in the fast field.
+        let (min, max) = match req.missing {
+            Some(missing) => (min.min(missing), max.max(missing)),
+            None => (min, max),
+        };
 
         let (min, max) = get_req_min_max(req, Some((min, max)));
 
+        // The buckets are accounted before they are allocated, since a small interval on a wide
+        // value range may generate a huge number of buckets.
+        let bucket_memory = if sub_aggregation.is_empty() {
+            mem::size_of::<SegmentHistogramBucketEntry>()
+        } else {
+            mem::size_of::<SegmentHistogramBucketEntry>()
+                + mem::size_of::<GenericSegmentAggregationResultsCollector>()
+        };
+        memory_account.add_memory_consumed(
+            get_num_buckets(req, min, max).saturating_mul(bucket_memory as u64),
+        )?;
+
         // We compute and generate the buckets range (min, max) based on the request and the min
         // max in the fast field, but this is likely not ideal when this is a subbucket, where many
         // unnecessary buckets may be generated.
@@ -330,6 +355,7 @@ impl SegmentHistogramCollector {
             bounds,
             sub_aggregations,
             min_doc_count: req.min_doc_count(),
+            missing: req.missing,
         })
     }
 
@@ -347,8 +373,20 @@ impl SegmentHistogramCollector {
         let get_bucket_num =
             |val| (get_bucket_num_f64(val, interval, offset) as i64 - first_bucket_num) as usize;
 
-        let accessor = &bucket_with_accessor.accessor;
+        let accessor = bucket_with_accessor.column();
         for doc in docs {
+            if let Some(missing) = self.missing {
+                if !accessor.has_value(*doc) {
+                    self.increment_bucket_if_in_bounds(
+                        missing,
+                        &bounds,
+                        get_bucket_num(missing),
+                        *doc,
+                        &bucket_with_accessor.sub_aggregation,
+                    )?;
+                    continue;
+                }
+            }
             for val in accessor.values(*doc) {
                 let val = self.f64_from_fastfield_u64(val);
 
@@ -419,7 +457,7 @@ fn get_bucket_num_f64(val: f64, interval: f64, offset: f64) -> f64 {
 }
 
 #[inline]
-fn get_bucket_val(val: f64, interval: f64, offset: f64) -> f64 {
+pub(crate) fn get_bucket_val(val: f64, interval: f64, offset: f64) -> f64 {
     let bucket_pos = get_bucket_num_f64(val, interval, offset);
     bucket_pos * interval + offset
 }
@@ -505,8 +543,12 @@ pub(crate) fn intermediate_histogram_buckets_to_final_buckets(
     };
 
     // If we have a date type on the histogram buckets, we add the `key_as_string` field as rfc339
-    let field = schema.get_field(&histogram_req.field)?;
-    if schema.get_field_entry(field).field_type().is_date() {
+    let is_date_field = schema
+        .get_field(&histogram_req.field)
+        .map_or(false, |field| {
+            schema.get_field_entry(field).field_type().is_date()
+        });
+    if is_date_field {
         for bucket in buckets.iter_mut() {
             if let crate::aggregation::Key::F64(val) = bucket.key {
                 let key_as_string = format_date(val as i64)?;
@@ -543,6 +585,18 @@ pub(crate) fn generate_buckets(req: &HistogramAggregation, min: f64, max: f64) -
     generate_buckets_with_opt_minmax(req, Some((min, max)))
 }
 
+/// Returns the number of buckets `generate_buckets` generates for min and max, which are already
+/// adjusted by the request.
+fn get_num_buckets(req: &HistogramAggregation, min: f64, max: f64) -> u64 {
+    let offset = req.offset.unwrap_or(0.0);
+    let first_bucket_num = get_bucket_num_f64(min, req.interval, offset) as i64;
+    let last_bucket_num = get_bucket_num_f64(max, req.interval, offset) as i64;
+    if last_bucket_num < first_bucket_num {
+        return 0;
+    }
+    last_bucket_num.abs_diff(first_bucket_num).saturating_add(1)
+}
+
 /// Generates buckets with req.interval
 /// Range is computed for provided min_max and request extended_bounds/hard_bounds
 /// returns empty vec when there is no range to span
@@ -689,7 +743,8 @@ mod tests {
     use crate::aggregation::metric::{AverageAggregation, StatsAggregation};
     use crate::aggregation::tests::{
         exec_request, exec_request_with_query, get_test_index_2_segments,
-        get_test_index_from_values, get_test_index_with_num_docs,
+        get_test_index_from_values, get_test_index_with_missing_values,
+        get_test_index_with_num_docs,
     };
 
     #[test]
@@ -1275,12 +1330,16 @@ mod tests {
                 "stats".to_string(),
                 Aggregation::Metric(MetricAggregation::Stats(StatsAggregation {
                     field: "score_f64".to_string(),
+                    script: None,
+                    missing: None,
                 })),
             ),
             (
                 "avg".to_string(),
                 Aggregation::Metric(MetricAggregation::Average(AverageAggregation {
                     field: "score_f64".to_string(),
+                    script: None,
+                    missing: None,
                 })),
             ),
         ]
@@ -1528,4 +1587,79 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn histogram_memory_limit_test() -> crate::Result<()> {
+        let index = get_test_index_from_values(true, &[0.0, 1_000.0])?;
+
+        let agg_req: Aggregations = vec![(
+            "my_interval".to_string(),
+            Aggregation::Bucket(BucketAggregation {
+                bucket_agg: BucketAggregationType::Histogram(HistogramAggregation {
+                    field: "score_f64".to_string(),
+                    interval: 0.000_001,
+                    ..Default::default()
+                }),
+                sub_aggregation: Default::default(),
+            }),
+        )]
+        .into_iter()
+        .collect();
+
+        // The billion buckets are rejected before they are allocated.
+        let res = exec_request(agg_req, &index);
+        assert!(res.unwrap_err().to_string().starts_with(
+            "Aggregation error: 'Aborting aggregation because the memory limit was exceeded"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn histogram_missing_test() -> crate::Result<()> {
+        for merge_segments in [false, true] {
+            let index = get_test_index_with_missing_values(merge_segments)?;
+            let histogram = |field: &str, missing: f64| {
+                Aggregation::Bucket(BucketAggregation {
+                    bucket_agg: BucketAggregationType::Histogram(HistogramAggregation {
+                        field: field.to_string(),
+                        interval: 5.0,
+                        missing: Some(missing),
+                        ..Default::default()
+                    }),
+                    sub_aggregation: Default::default(),
+                })
+            };
+            // The missing values are outside of the value range of the fast fields.
+            let agg_req: Aggregations = vec![
+                ("histogram".to_string(), histogram("score", 20.0)),
+                ("histogram_f64".to_string(), histogram("score_f64", -5.0)),
+            ]
+            .into_iter()
+            .collect();
+
+            let res = exec_request(agg_req, &index)?;
+            assert_eq!(
+                res["histogram"]["buckets"],
+                json!([
+                    { "key": 0.0, "doc_count": 2 },
+                    { "key": 5.0, "doc_count": 2 },
+                    { "key": 10.0, "doc_count": 1 },
+                    { "key": 15.0, "doc_count": 0 },
+                    { "key": 20.0, "doc_count": 2 }
+                ])
+            );
+            assert_eq!(
+                res["histogram_f64"]["buckets"],
+                json!([
+                    { "key": -5.0, "doc_count": 2 },
+                    { "key": 0.0, "doc_count": 2 },
+                    { "key": 5.0, "doc_count": 2 },
+                    { "key": 10.0, "doc_count": 1 }
+                ])
+            );
+        }
+
+        Ok(())
+    }
 }
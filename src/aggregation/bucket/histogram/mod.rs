@@ -1,4 +1,6 @@
+mod auto_date_histogram;
 mod date_histogram;
 mod histogram;
+pub use auto_date_histogram::*;
 pub use date_histogram::*;
 pub use histogram::*;
@@ -7,17 +7,41 @@
 //! Results of intermediate buckets are
 //! [`IntermediateBucketResult`](super::intermediate_agg_result::IntermediateBucketResult)
 
+mod composite;
+mod filter;
+mod geo_grid;
 mod histogram;
+mod multi_terms;
+mod nested;
 mod range;
+mod sampler;
+mod significant_terms;
 mod term_agg;
 
 use std::collections::HashMap;
 
+pub use composite::{
+    CompositeAggregation, CompositeSource, CompositeSourceType, DateHistogramCompositeSource,
+    HistogramCompositeSource, TermsCompositeSource,
+};
+pub(crate) use composite::{CompositeSourceAccessor, SegmentCompositeCollector};
+pub use filter::{FilterAggregation, FilterQuery, FiltersAggregation, DEFAULT_OTHER_BUCKET_KEY};
+pub(crate) use filter::{FilterWeights, SegmentFilterCollector};
+pub use geo_grid::{GeoBoundingBox, GeoGridAggregation, GeoPoint};
+pub(crate) use geo_grid::{GeoGridType, SegmentGeoGridCollector};
 pub(crate) use histogram::SegmentHistogramCollector;
 pub use histogram::*;
+pub(crate) use multi_terms::SegmentMultiTermsCollector;
+pub use multi_terms::{MultiTermsAggregation, MultiTermsSource};
+pub use nested::NestedAggregation;
+pub(crate) use nested::SegmentNestedCollector;
 pub(crate) use range::SegmentRangeCollector;
 pub use range::*;
+pub(crate) use sampler::{DocScores, SegmentSamplerCollector};
+pub use sampler::{RandomSamplerAggregation, SamplerAggregation};
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+pub use significant_terms::{JlhHeuristic, NxyHeuristic, SignificantTermsAggregation};
+pub(crate) use significant_terms::{SegmentSignificantTermsCollector, SignificantTermsBackground};
 pub use term_agg::*;
 
 /// Order for buckets in a bucket aggregation.
@@ -0,0 +1,534 @@
+use std::fmt::Debug;
+
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use super::composite::for_each_combination;
+use super::{
+    cut_off_buckets, get_agg_name_and_property, CompositeSourceAccessor, CustomOrder, GetDocCount,
+    Order, OrderTarget, TermBucketEntry, TermsAggregation, TermsAggregationInternal,
+};
+use crate::aggregation::agg_req_with_accessor::{
+    AggregationsWithAccessor, BucketAggregationWithAccessor,
+};
+use crate::aggregation::intermediate_agg_result::{
+    IntermediateBucketResult, IntermediateMultiTermsBucketEntry, IntermediateMultiTermsBucketResult,
+};
+use crate::aggregation::memory_budget::{blueprint_memory, hash_map_memory};
+use crate::aggregation::segment_agg_result::{
+    build_segment_agg_collector, SegmentAggregationCollector,
+};
+use crate::aggregation::{f64_from_fastfield_u64, Key};
+use crate::schema::Type;
+use crate::{DocId, TantivyError};
+
+/// Creates a bucket for every unique combination of the terms of several fields and counts the
+/// number of documents.
+///
+/// Unlike nesting `terms` aggregations, the top `size` buckets are selected over all
+/// combinations, e.g. the top 10 (country, browser) pairs. The fields can be a mix of string and
+/// numeric fast fields. A document falls into a bucket for every combination of the values of its
+/// fields.
+///
+/// `size`, `segment_size`, `order`, `min_doc_count` and the document count error work like in the
+/// [`TermsAggregation`].
+///
+/// Result type is
+/// [`BucketResult::MultiTerms`](crate::aggregation::agg_result::BucketResult::MultiTerms) with
+/// [`MultiTermsBucketEntry`](crate::aggregation::agg_result::MultiTermsBucketEntry) on the
+/// `AggregationCollector`.
+///
+/// Result type is
+/// [`IntermediateBucketResult::MultiTerms`](crate::aggregation::intermediate_agg_result::IntermediateBucketResult::MultiTerms) with
+/// [`IntermediateMultiTermsBucketEntry`](crate::aggregation::intermediate_agg_result::IntermediateMultiTermsBucketEntry) on the
+/// `DistributedAggregationCollector`.
+///
+/// # Limitations/Compatibility
+/// Documents without a value for one of the fields are ignored. `min_doc_count` of 0 is not
+/// supported, since it would return every combination of all terms.
+///
+/// # Request JSON Format
+/// ```json
+/// {
+///     "country_browser": {
+///         "multi_terms": {
+///             "terms": [ { "field": "country" }, { "field": "browser" } ],
+///             "size": 10
+///         }
+///     }
+/// }
+/// ```
+///
+/// # Response JSON Format
+/// ```json
+/// {
+///     "country_browser": {
+///         "doc_count_error_upper_bound": 0,
+///         "sum_other_doc_count": 0,
+///         "buckets": [
+///             { "key": ["us", "firefox"], "key_as_string": "us|firefox", "doc_count": 6 },
+///             { "key": ["de", "chrome"], "key_as_string": "de|chrome", "doc_count": 4 }
+///         ]
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MultiTermsAggregation {
+    /// The fields to aggregate on. At least two fields are required.
+    pub terms: Vec<MultiTermsSource>,
+    /// By default, the top 10 combinations with the most documents are returned.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub size: Option<u32>,
+    /// Unused by tantivy, see [`TermsAggregation::split_size`].
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    #[serde(alias = "shard_size")]
+    pub split_size: Option<u32>,
+    /// The number of combinations fetched from each segment, see
+    /// [`TermsAggregation::segment_size`]. Defaults to 10 * size.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub segment_size: Option<u32>,
+    /// Whether to return `doc_count_error_upper_bound`, see
+    /// [`TermsAggregation::show_term_doc_count_error`].
+    ///
+    /// Defaults to true when ordering by count desc.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub show_term_doc_count_error: Option<bool>,
+    /// Filter all combinations that are lower than `min_doc_count`. Defaults to 1.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub min_doc_count: Option<u64>,
+    /// Set the order, see [`TermsAggregation::order`]. `_key` orders by the first field, then by
+    /// the second field and so on.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub order: Option<CustomOrder>,
+}
+
+/// A field of a [`MultiTermsAggregation`].
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MultiTermsSource {
+    /// The field to aggregate on.
+    pub field: String,
+}
+
+impl MultiTermsAggregation {
+    /// Returns the request with populated defaults, which are the same as for the
+    /// [`TermsAggregation`].
+    pub(crate) fn terms_internal(&self) -> TermsAggregationInternal {
+        TermsAggregationInternal::from_req(&TermsAggregation {
+            field: String::new(),
+            size: self.size,
+            split_size: self.split_size,
+            segment_size: self.segment_size,
+            show_term_doc_count_error: self.show_term_doc_count_error,
+            min_doc_count: self.min_doc_count,
+            order: self.order.clone(),
+            missing: None,
+        })
+    }
+
+    fn validate(&self) -> crate::Result<()> {
+        if self.terms.len() < 2 {
+            return Err(TantivyError::InvalidArgument(
+                "multi_terms aggregation requires at least two fields".to_string(),
+            ));
+        }
+        if self.min_doc_count == Some(0) {
+            return Err(TantivyError::InvalidArgument(
+                "min_doc_count of 0 is not supported by the multi_terms aggregation".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A field of a multi_terms aggregation in a segment.
+///
+/// Values are the term ordinals for string fields and the fast field values for numeric fields,
+/// so that the order of the values matches the order of the keys.
+#[derive(Clone, Debug)]
+enum SegmentMultiTermsSource {
+    Str,
+    Numeric(Type),
+}
+
+impl SegmentMultiTermsSource {
+    fn from_accessor(field: &str, accessor: &CompositeSourceAccessor) -> crate::Result<Self> {
+        if accessor.str_dict_column.is_some() {
+            return Ok(SegmentMultiTermsSource::Str);
+        }
+        match accessor.field_type {
+            Type::U64 | Type::I64 | Type::F64 | Type::Date => {
+                Ok(SegmentMultiTermsSource::Numeric(accessor.field_type))
+            }
+            field_type => Err(TantivyError::InvalidArgument(format!(
+                "multi_terms aggregation on field {:?} is not supported for field type {:?}",
+                field, field_type
+            ))),
+        }
+    }
+
+    fn decode(
+        &self,
+        val: u64,
+        accessor: &CompositeSourceAccessor,
+        buffer: &mut String,
+    ) -> crate::Result<Key> {
+        match self {
+            SegmentMultiTermsSource::Str => {
+                let term_dict = accessor
+                    .str_dict_column
+                    .as_ref()
+                    .expect("internal error: term dictionary not loaded for multi_terms field");
+                if !term_dict.ord_to_str(val, buffer)? {
+                    return Err(TantivyError::InternalError(format!(
+                        "Couldn't find term_ord {} in dict",
+                        val
+                    )));
+                }
+                Ok(Key::Str(buffer.to_string()))
+            }
+            SegmentMultiTermsSource::Numeric(field_type) => {
+                Ok(Key::F64(f64_from_fastfield_u64(val, field_type)))
+            }
+        }
+    }
+}
+
+impl GetDocCount for (Vec<u64>, TermBucketEntry) {
+    fn doc_count(&self) -> u64 {
+        self.1.doc_count
+    }
+}
+
+/// The collector for the multi_terms aggregation in a segment.
+#[derive(Clone, Debug)]
+pub(crate) struct SegmentMultiTermsCollector {
+    sources: Vec<SegmentMultiTermsSource>,
+    buckets: FxHashMap<Vec<u64>, TermBucketEntry>,
+    req: TermsAggregationInternal,
+    blueprint: Option<Box<dyn SegmentAggregationCollector>>,
+    /// Buffers for the values of one document, one per field.
+    values_buffer: Vec<Vec<u64>>,
+    /// Buffers to build the keys of one document.
+    positions_buffer: Vec<usize>,
+    key_buffer: Vec<u64>,
+}
+
+impl SegmentMultiTermsCollector {
+    pub(crate) fn from_req_and_validate(
+        req: &MultiTermsAggregation,
+        bucket_with_accessor: &BucketAggregationWithAccessor,
+    ) -> crate::Result<Self> {
+        req.validate()?;
+        let sub_aggregations = &bucket_with_accessor.sub_aggregation;
+        if let Some(custom_order) = req.order.as_ref() {
+            // Validate sub aggregtion exists
+            if let OrderTarget::SubAggregation(sub_agg_name) = &custom_order.target {
+                let (agg_name, _agg_property) = get_agg_name_and_property(sub_agg_name);
+
+                sub_aggregations.metrics.get(agg_name).ok_or_else(|| {
+                    TantivyError::InvalidArgument(format!(
+                        "could not find aggregation with name {} in metric sub_aggregations",
+                        agg_name
+                    ))
+                })?;
+            }
+        }
+
+        let sources = req
+            .terms
+            .iter()
+            .zip(&bucket_with_accessor.composite_sources)
+            .map(|(source, accessor)| {
+                SegmentMultiTermsSource::from_accessor(&source.field, accessor)
+            })
+            .collect::<crate::Result<Vec<_>>>()?;
+
+        let blueprint = if sub_aggregations.is_empty() {
+            None
+        } else {
+            Some(build_segment_agg_collector(sub_aggregations)?)
+        };
+
+        let num_sources = sources.len();
+        Ok(SegmentMultiTermsCollector {
+            sources,
+            buckets: FxHashMap::default(),
+            req: req.terms_internal(),
+            blueprint,
+            values_buffer: vec![Vec::new(); num_sources],
+            positions_buffer: vec![0; num_sources],
+            key_buffer: Vec::with_capacity(num_sources),
+        })
+    }
+
+    pub(crate) fn into_intermediate_bucket_result(
+        self,
+        agg_with_accessor: &BucketAggregationWithAccessor,
+    ) -> crate::Result<IntermediateBucketResult> {
+        let mut entries: Vec<(Vec<u64>, TermBucketEntry)> = self.buckets.into_iter().collect();
+
+        let order_by_sub_aggregation =
+            matches!(self.req.order.target, OrderTarget::SubAggregation(_));
+
+        match self.req.order.target {
+            OrderTarget::Key => {
+                // Term ordinals and fast field values have the same order as their keys.
+                if self.req.order.order == Order::Desc {
+                    entries.sort_unstable_by(|left, right| right.0.cmp(&left.0));
+                } else {
+                    entries.sort_unstable_by(|left, right| left.0.cmp(&right.0));
+                }
+            }
+            OrderTarget::SubAggregation(_name) => {
+                // don't sort and cut off, like the terms aggregation.
+            }
+            OrderTarget::Count => {
+                if self.req.order.order == Order::Desc {
+                    entries.sort_unstable_by_key(|bucket| std::cmp::Reverse(bucket.doc_count()));
+                } else {
+                    entries.sort_unstable_by_key(|bucket| bucket.doc_count());
+                }
+            }
+        }
+
+        let (term_doc_count_before_cutoff, sum_other_doc_count) = if order_by_sub_aggregation {
+            (0, 0)
+        } else {
+            cut_off_buckets(&mut entries, self.req.segment_size as usize)
+        };
+
+        let bucket_count = &agg_with_accessor.bucket_count;
+        bucket_count.add_count(entries.len() as u32);
+        bucket_count.validate_bucket_count()?;
+
+        let mut buckets = FxHashMap::default();
+        let mut buffer = String::new();
+        for (values, entry) in entries {
+            let key = self
+                .sources
+                .iter()
+                .zip(values)
+                .zip(&agg_with_accessor.composite_sources)
+                .map(|((source, val), accessor)| source.decode(val, accessor, &mut buffer))
+                .collect::<crate::Result<Vec<Key>>>()?;
+            let entry = entry.into_intermediate_bucket_entry(&agg_with_accessor.sub_aggregation)?;
+            buckets.insert(
+                serde_json::to_string(&key)?,
+                IntermediateMultiTermsBucketEntry {
+                    key,
+                    doc_count: entry.doc_count,
+                    sub_aggregation: entry.sub_aggregation,
+                },
+            );
+        }
+
+        Ok(IntermediateBucketResult::MultiTerms(
+            IntermediateMultiTermsBucketResult {
+                buckets,
+                sum_other_doc_count,
+                doc_count_error_upper_bound: term_doc_count_before_cutoff,
+            },
+        ))
+    }
+
+    #[inline]
+    pub(crate) fn collect_block(
+        &mut self,
+        docs: &[DocId],
+        bucket_with_accessor: &BucketAggregationWithAccessor,
+        force_flush: bool,
+    ) -> crate::Result<()> {
+        let source_accessors = &bucket_with_accessor.composite_sources;
+        let sub_aggregation_accessor = &bucket_with_accessor.sub_aggregation;
+        let memory_before = self.memory_consumption();
+
+        'docs: for &doc in docs {
+            for (accessor, values) in source_accessors.iter().zip(self.values_buffer.iter_mut()) {
+                values.clear();
+                values.extend(accessor.accessor.values(doc));
+                if values.is_empty() {
+                    continue 'docs;
+                }
+                values.sort_unstable();
+                values.dedup();
+            }
+
+            let buckets = &mut self.buckets;
+            let blueprint = &self.blueprint;
+            for_each_combination(
+                &self.values_buffer,
+                &mut self.positions_buffer,
+                &mut self.key_buffer,
+                |key| {
+                    // Avoids allocating the key for existing buckets.
+                    if !buckets.contains_key(key) {
+                        buckets.insert(key.to_vec(), TermBucketEntry::from_blueprint(blueprint));
+                    }
+                    let entry = buckets.get_mut(key).expect("bucket was just inserted");
+                    entry.doc_count += 1;
+                    if let Some(sub_aggregations) = entry.sub_aggregations.as_mut() {
+                        sub_aggregations.collect(doc, sub_aggregation_accessor)?;
+                    }
+                    Ok(())
+                },
+            )?;
+        }
+        bucket_with_accessor
+            .memory_account
+            .add_memory_consumed(self.memory_consumption() - memory_before)?;
+
+        if force_flush {
+            self.force_flush(sub_aggregation_accessor)?;
+        }
+        Ok(())
+    }
+
+    /// Estimates the memory of the buckets, which grows with the number of term combinations.
+    fn memory_consumption(&self) -> u64 {
+        let key_memory = (self.sources.len() * std::mem::size_of::<u64>()) as u64;
+        hash_map_memory(&self.buckets)
+            + self.buckets.len() as u64 * (key_memory + blueprint_memory(&self.blueprint))
+    }
+
+    fn force_flush(&mut self, agg_with_accessor: &AggregationsWithAccessor) -> crate::Result<()> {
+        for entry in self.buckets.values_mut() {
+            if let Some(sub_aggregations) = entry.sub_aggregations.as_mut() {
+                sub_aggregations.flush_staged_docs(agg_with_accessor, false)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use crate::aggregation::agg_req::Aggregations;
+    use crate::aggregation::tests::{exec_request, get_test_index_from_values_and_terms};
+
+    fn get_test_index(merge_segments: bool) -> crate::Result<crate::Index> {
+        let segment_and_values = vec![
+            vec![(1.0, "a".to_string()), (1.0, "a".to_string())],
+            vec![
+                (2.0, "b".to_string()),
+                (1.0, "a".to_string()),
+                (2.0, "a".to_string()),
+            ],
+            vec![
+                (2.0, "b".to_string()),
+                (2.0, "b".to_string()),
+                (3.0, "c".to_string()),
+            ],
+            vec![(2.0, "b".to_string())],
+        ];
+        get_test_index_from_values_and_terms(merge_segments, &segment_and_values)
+    }
+
+    fn multi_terms_request(multi_terms: Value) -> Aggregations {
+        serde_json::from_value(json!({ "pairs": multi_terms })).unwrap()
+    }
+
+    fn keys(res: &Value) -> Vec<String> {
+        res["pairs"]["buckets"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|bucket| bucket["key_as_string"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn multi_terms_aggregation_test() -> crate::Result<()> {
+        multi_terms_aggregation_test_merge_segment(false)?;
+        multi_terms_aggregation_test_merge_segment(true)
+    }
+
+    fn multi_terms_aggregation_test_merge_segment(merge_segments: bool) -> crate::Result<()> {
+        let index = get_test_index(merge_segments)?;
+
+        let res = exec_request(
+            multi_terms_request(json!({
+                "multi_terms": {
+                    "terms": [ { "field": "string_id" }, { "field": "score_i64" } ],
+                    "size": 2
+                }
+            })),
+            &index,
+        )?;
+        assert_eq!(
+            res["pairs"],
+            json!({
+                "buckets": [
+                    { "key": ["b", 2.0], "key_as_string": "b|2", "doc_count": 4 },
+                    { "key": ["a", 1.0], "key_as_string": "a|1", "doc_count": 3 }
+                ],
+                "sum_other_doc_count": 2,
+                "doc_count_error_upper_bound": 0
+            })
+        );
+
+        let res = exec_request(
+            multi_terms_request(json!({
+                "multi_terms": {
+                    "terms": [ { "field": "string_id" }, { "field": "score" } ],
+                    "order": { "_key": "asc" }
+                }
+            })),
+            &index,
+        )?;
+        assert_eq!(keys(&res), vec!["a|1", "a|2", "b|2", "c|3"]);
+
+        let res = exec_request(
+            multi_terms_request(json!({
+                "multi_terms": {
+                    "terms": [ { "field": "score_f64" }, { "field": "string_id" } ],
+                    "order": { "_key": "desc" }
+                }
+            })),
+            &index,
+        )?;
+        assert_eq!(keys(&res), vec!["3|c", "2|b", "2|a", "1|a"]);
+
+        let res = exec_request(
+            multi_terms_request(json!({
+                "multi_terms": {
+                    "terms": [ { "field": "string_id" }, { "field": "score" } ],
+                    "min_doc_count": 2,
+                    "order": { "avg_fraction": "asc" }
+                },
+                "aggs": {
+                    "avg_fraction": { "avg": { "field": "fraction_f64" } }
+                }
+            })),
+            &index,
+        )?;
+        assert_eq!(keys(&res), vec!["a|1", "b|2"]);
+        assert_eq!(res["pairs"]["buckets"][1]["avg_fraction"]["value"], 0.02);
+
+        Ok(())
+    }
+
+    #[test]
+    fn multi_terms_aggregation_invalid_requests() -> crate::Result<()> {
+        let index = get_test_index(false)?;
+
+        for multi_terms in [
+            json!({ "terms": [ { "field": "string_id" } ] }),
+            json!({
+                "terms": [ { "field": "string_id" }, { "field": "score" } ],
+                "min_doc_count": 0
+            }),
+            json!({
+                "terms": [ { "field": "string_id" }, { "field": "score" } ],
+                "order": { "unknown": "desc" }
+            }),
+            json!({ "terms": [ { "field": "string_id" }, { "field": "unknown" } ] }),
+        ] {
+            let agg_req = multi_terms_request(json!({ "multi_terms": multi_terms }));
+            assert!(exec_request(agg_req, &index).is_err(), "{}", multi_terms);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,328 @@
+use std::fmt::Debug;
+
+use serde::{Deserialize, Serialize};
+
+use crate::aggregation::agg_req::{Aggregation, Aggregations, BucketAggregationType};
+use crate::aggregation::agg_req_with_accessor::BucketAggregationWithAccessor;
+use crate::aggregation::intermediate_agg_result::{
+    IntermediateBucketResult, IntermediateFilterBucketEntry,
+};
+use crate::aggregation::segment_agg_result::{
+    build_segment_agg_collector, SegmentAggregationCollector,
+};
+use crate::{DocId, TantivyError};
+
+/// Aggregates the elements of a nested json path as if every element was a document.
+///
+/// By default the values of all elements of an array of objects are flattened, e.g. for orders
+/// like `{"items": [{"sku": "a", "price": 3}, {"sku": "b", "price": 5}]}` a `terms` aggregation on
+/// `order.items.sku` with a `sum` on `order.items.price` would add the prices of all items of the
+/// order to every sku of the order. If `items` is declared as nested path with
+/// [`JsonObjectOptions::add_nested_path`](crate::schema::JsonObjectOptions::add_nested_path), the
+/// sub-aggregations of a `nested` aggregation on `order.items` only see the values of one item at
+/// a time.
+///
+/// The result is a single bucket with the number of elements of the collected documents as
+/// `doc_count`.
+///
+/// Result type is [`BucketResult::Filter`](crate::aggregation::agg_result::BucketResult::Filter)
+/// on the `AggregationCollector`.
+///
+/// Result type is
+/// [`IntermediateBucketResult::Filter`](crate::aggregation::intermediate_agg_result::IntermediateBucketResult::Filter)
+/// on the `DistributedAggregationCollector`.
+///
+/// # Limitations/Compatibility
+/// The sub-aggregations can only use fast fields below the nested path. `filter`, `filters`,
+/// `significant_terms`, `nested` and `sampler` aggregations are not supported as
+/// sub-aggregations, and there is no `reverse_nested` aggregation.
+///
+/// The fast fields of the elements are copied into memory once per segment, so the cost grows
+/// with the number of values of the fields used by the sub-aggregations.
+///
+/// # Request JSON Format
+/// ```json
+/// {
+///     "items": {
+///         "nested": { "path": "order.items" },
+///         "aggs": {
+///             "skus": {
+///                 "terms": { "field": "order.items.sku" },
+///                 "aggs": { "revenue": { "sum": { "field": "order.items.price" } } }
+///             }
+///         }
+///     }
+/// }
+/// ```
+///
+/// # Response JSON Format
+/// ```json
+/// {
+///     "items": {
+///         "doc_count": 5,
+///         "skus": { ... }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct NestedAggregation {
+    /// The nested path, including the name of the json field.
+    pub path: String,
+}
+
+impl NestedAggregation {
+    pub(crate) fn validate(&self, sub_aggregation: &Aggregations) -> crate::Result<()> {
+        if self.path.is_empty() {
+            return Err(TantivyError::InvalidArgument(
+                "nested aggregation requires a path".to_string(),
+            ));
+        }
+        validate_nested_sub_aggregations(sub_aggregation)
+    }
+}
+
+/// Sub-aggregations of a nested aggregation read the fast fields of the elements. Aggregations,
+/// which use another data structure of the segment, would mix up elements and documents.
+fn validate_nested_sub_aggregations(aggs: &Aggregations) -> crate::Result<()> {
+    for agg in aggs.values() {
+        let Aggregation::Bucket(bucket) = agg else {
+            continue;
+        };
+        let unsupported_agg = match &bucket.bucket_agg {
+            BucketAggregationType::Filter(_) => Some("filter"),
+            BucketAggregationType::Filters(_) => Some("filters"),
+            BucketAggregationType::SignificantTerms(_) => Some("significant_terms"),
+            BucketAggregationType::Nested(_) => Some("nested"),
+            BucketAggregationType::Sampler(_) => Some("sampler"),
+            _ => None,
+        };
+        if let Some(unsupported_agg) = unsupported_agg {
+            return Err(TantivyError::InvalidArgument(format!(
+                "{} aggregation is not supported inside a nested aggregation",
+                unsupported_agg
+            )));
+        }
+        validate_nested_sub_aggregations(&bucket.sub_aggregation)?;
+    }
+    Ok(())
+}
+
+/// The collector of the nested aggregation in a segment, which collects the nested documents of
+/// every collected document.
+#[derive(Clone, Debug)]
+pub(crate) struct SegmentNestedCollector {
+    doc_count: u64,
+    sub_aggregation: Option<Box<dyn SegmentAggregationCollector>>,
+}
+
+impl SegmentNestedCollector {
+    pub(crate) fn from_req_and_validate(
+        req: &BucketAggregationWithAccessor,
+    ) -> crate::Result<Self> {
+        let sub_aggregation = if req.sub_aggregation.is_empty() {
+            None
+        } else {
+            Some(build_segment_agg_collector(&req.sub_aggregation)?)
+        };
+        req.bucket_count.add_count(1);
+        req.bucket_count.validate_bucket_count()?;
+        Ok(SegmentNestedCollector {
+            doc_count: 0,
+            sub_aggregation,
+        })
+    }
+
+    pub(crate) fn into_intermediate_bucket_result(
+        self,
+        agg_with_accessor: &BucketAggregationWithAccessor,
+    ) -> crate::Result<IntermediateBucketResult> {
+        let sub_aggregation = if let Some(sub_aggregation) = self.sub_aggregation {
+            sub_aggregation
+                .into_intermediate_aggregations_result(&agg_with_accessor.sub_aggregation)?
+        } else {
+            Default::default()
+        };
+        Ok(IntermediateBucketResult::Filter(
+            IntermediateFilterBucketEntry {
+                doc_count: self.doc_count,
+                sub_aggregation,
+            },
+        ))
+    }
+
+    #[inline]
+    pub(crate) fn collect_block(
+        &mut self,
+        docs: &[DocId],
+        bucket_with_accessor: &BucketAggregationWithAccessor,
+        force_flush: bool,
+    ) -> crate::Result<()> {
+        let nested_documents = bucket_with_accessor
+            .nested_documents
+            .as_ref()
+            .expect("internal error: nested documents not loaded for nested aggregation");
+        let sub_aggregation_accessor = &bucket_with_accessor.sub_aggregation;
+
+        for &doc in docs {
+            let nested_docs = nested_documents.nested_docs(doc);
+            self.doc_count += nested_docs.len() as u64;
+            if let Some(sub_aggregation) = self.sub_aggregation.as_mut() {
+                for nested_doc in nested_docs {
+                    sub_aggregation.collect(nested_doc, sub_aggregation_accessor)?;
+                }
+            }
+        }
+
+        if force_flush {
+            if let Some(sub_aggregation) = self.sub_aggregation.as_mut() {
+                sub_aggregation.flush_staged_docs(sub_aggregation_accessor, true)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::aggregation::agg_req::Aggregations;
+    use crate::aggregation::tests::exec_request;
+    use crate::schema::{JsonObjectOptions, Schema, FAST, STORED};
+    use crate::{Index, TantivyError};
+
+    fn get_test_index(nested: bool) -> crate::Result<Index> {
+        let mut schema_builder = Schema::builder();
+        let mut json_options = JsonObjectOptions::from(STORED | FAST);
+        if nested {
+            json_options = json_options.add_nested_path("items");
+        }
+        let order = schema_builder.add_json_field("order", json_options);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer = index.writer_for_tests()?;
+        let orders = [
+            json!({
+                "customer": "alice",
+                "items": [{ "sku": "a", "price": 3 }, { "sku": "b", "price": 5 }]
+            }),
+            json!({ "customer": "bob", "items": { "sku": "b", "price": 7.5 } }),
+        ];
+        for order_json in orders {
+            index_writer.add_document(doc!(order => order_json.as_object().unwrap().clone()))?;
+        }
+        index_writer.commit()?;
+        index_writer.add_document(doc!(order => json!({
+            "customer": "carol",
+            "items": [{ "sku": "a", "price": 1 }, { "price": 100 }, { "sku": "c" }]
+        }).as_object().unwrap().clone()))?;
+        index_writer.add_document(doc!(order => json!({ "customer": "dave" })
+            .as_object()
+            .unwrap()
+            .clone()))?;
+        index_writer.commit()?;
+        Ok(index)
+    }
+
+    fn revenue_by_sku_req() -> Aggregations {
+        serde_json::from_value(json!({
+            "items": {
+                "nested": { "path": "order.items" },
+                "aggs": {
+                    "skus": {
+                        "terms": { "field": "order.items.sku", "order": { "_key": "asc" } },
+                        "aggs": { "revenue": { "sum": { "field": "order.items.price" } } }
+                    }
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn nested_aggregation_test() -> crate::Result<()> {
+        let index = get_test_index(true)?;
+        let res = exec_request(revenue_by_sku_req(), &index)?;
+        assert_eq!(res["items"]["doc_count"], 6);
+        let buckets = &res["items"]["skus"]["buckets"];
+        assert_eq!(buckets[0]["key"], "a");
+        assert_eq!(buckets[0]["doc_count"], 2);
+        assert_eq!(buckets[0]["revenue"]["value"], 4.0);
+        assert_eq!(buckets[1]["key"], "b");
+        assert_eq!(buckets[1]["doc_count"], 2);
+        assert_eq!(buckets[1]["revenue"]["value"], 12.5);
+        assert_eq!(buckets[2]["key"], "c");
+        assert_eq!(buckets[2]["doc_count"], 1);
+        assert_eq!(buckets[2]["revenue"]["value"], 0.0);
+
+        // The nested aggregation works inside of another bucket aggregation.
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "customers": {
+                "terms": { "field": "order.customer", "order": { "_key": "asc" } },
+                "aggs": {
+                    "items": {
+                        "nested": { "path": "order.items" },
+                        "aggs": { "max_price": { "max": { "field": "order.items.price" } } }
+                    }
+                }
+            }
+        }))
+        .unwrap();
+        let res = exec_request(agg_req, &index)?;
+        let buckets = &res["customers"]["buckets"];
+        assert_eq!(buckets[0]["key"], "alice");
+        assert_eq!(buckets[0]["items"]["doc_count"], 2);
+        assert_eq!(buckets[0]["items"]["max_price"]["value"], 5.0);
+        assert_eq!(buckets[2]["key"], "carol");
+        assert_eq!(buckets[2]["items"]["doc_count"], 3);
+        assert_eq!(buckets[2]["items"]["max_price"]["value"], 100.0);
+        assert_eq!(buckets[3]["key"], "dave");
+        assert_eq!(buckets[3]["items"]["doc_count"], 0);
+        Ok(())
+    }
+
+    #[test]
+    fn nested_aggregation_without_nested_path_test() -> crate::Result<()> {
+        // Without the nested path, the json field has no elements.
+        let index = get_test_index(false)?;
+        let res = exec_request(revenue_by_sku_req(), &index)?;
+        assert_eq!(res["items"]["doc_count"], 0);
+        assert_eq!(res["items"]["skus"]["buckets"], json!([]));
+        Ok(())
+    }
+
+    #[test]
+    fn nested_aggregation_invalid_request_test() -> crate::Result<()> {
+        let index = get_test_index(true)?;
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "items": {
+                "nested": { "path": "order.items" },
+                "aggs": { "customers": { "terms": { "field": "order.customer" } } }
+            }
+        }))
+        .unwrap();
+        let err = exec_request(agg_req, &index).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "An invalid argument was passed: 'Field order.customer is not below the nested path \
+             order.items'"
+        );
+
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "items": {
+                "nested": { "path": "order.items" },
+                "aggs": {
+                    "skus": {
+                        "terms": { "field": "order.items.sku" },
+                        "aggs": {
+                            "cheap": { "filter": "order.items.price:[0 TO 5]" }
+                        }
+                    }
+                }
+            }
+        }))
+        .unwrap();
+        let err = exec_request(agg_req, &index).unwrap_err();
+        assert!(matches!(err, TantivyError::InvalidArgument(_)));
+        Ok(())
+    }
+}
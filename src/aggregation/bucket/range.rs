@@ -1,4 +1,5 @@
 use std::fmt::Debug;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::ops::Range;
 
 use columnar::MonotonicallyMappableToU64;
@@ -8,6 +9,7 @@ use serde::{Deserialize, Serialize};
 use crate::aggregation::agg_req_with_accessor::{
     AggregationsWithAccessor, BucketAggregationWithAccessor,
 };
+use crate::aggregation::date::parse_date_math;
 use crate::aggregation::intermediate_agg_result::{
     IntermediateBucketResult, IntermediateRangeBucketEntry, IntermediateRangeBucketResult,
 };
@@ -17,7 +19,7 @@ use crate::aggregation::segment_agg_result::{
 use crate::aggregation::{
     f64_from_fastfield_u64, f64_to_fastfield_u64, format_date, Key, SerializedKey,
 };
-use crate::schema::Type;
+use crate::schema::{IntoIpv6Addr, Type};
 use crate::{DocId, TantivyError};
 
 /// Provide user-defined buckets to aggregate on.
@@ -36,6 +38,9 @@ use crate::{DocId, TantivyError};
 /// [`IntermediateRangeBucketEntry`](crate::aggregation::intermediate_agg_result::IntermediateRangeBucketEntry) on the
 /// `DistributedAggregationCollector`.
 ///
+/// On date and ip fields, the bounds can be given as strings, see [`RangeBound`]. The buckets on
+/// these fields contain the bounds as strings in `from_as_string` and `to_as_string`.
+///
 /// # Limitations/Compatibility
 /// Overlapping ranges are not yet supported.
 ///
@@ -53,6 +58,32 @@ use crate::{DocId, TantivyError};
 ///     }
 /// }
 /// ```
+///
+/// On a date field:
+/// ```json
+/// {
+///     "last_week": {
+///         "field": "timestamp",
+///         "ranges": [
+///             { "from": "now-7d/d", "to": "now/d" },
+///             { "from": "now/d" }
+///         ]
+///     }
+/// }
+/// ```
+///
+/// On an ip field:
+/// ```json
+/// {
+///     "networks": {
+///         "field": "client_ip",
+///         "ranges": [
+///             { "to": "10.0.0.5" },
+///             { "mask": "10.0.0.0/25" }
+///         ]
+///     }
+/// }
+/// ```
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct RangeAggregation {
     /// The field to aggregate on.
@@ -63,6 +94,10 @@ pub struct RangeAggregation {
     /// Whether to return the buckets as a hash map
     #[serde(default)]
     pub keyed: bool,
+    /// The value to use for documents without a value in the field. By default such documents
+    /// are ignored.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub missing: Option<f64>,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -74,11 +109,68 @@ pub struct RangeAggregationRange {
     /// The from range value, which is inclusive in the range.
     /// `None` equals to an open ended interval.
     #[serde(skip_serializing_if = "Option::is_none", default)]
-    pub from: Option<f64>,
+    pub from: Option<RangeBound>,
     /// The to range value, which is not inclusive in the range.
     /// `None` equals to an open ended interval.
     #[serde(skip_serializing_if = "Option::is_none", default)]
-    pub to: Option<f64>,
+    pub to: Option<RangeBound>,
+    /// A CIDR block like `10.0.0.0/25` on ip fields, which sets `from` and `to` to the first and
+    /// after the last address of the block. The key defaults to the mask.
+    ///
+    /// Can't be combined with `from` and `to`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub mask: Option<String>,
+}
+
+/// A bound of a [`RangeAggregationRange`].
+///
+/// Strings are parsed depending on the type of the field:
+/// - Date fields accept RFC3339 dates like `2019-01-01T00:00:00Z`, plain dates like `2019-01-01`
+///   and date math like `now-7d/d` or `2019-01-01||+1M/d`. `now` is resolved once, when the
+///   collector is created.
+/// - Ip fields accept IPv4 and IPv6 addresses.
+/// - Numeric fields accept numbers.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RangeBound {
+    /// A value of the field, in microseconds on date fields.
+    Number(f64),
+    /// A date, date math expression, ip address or number.
+    Str(String),
+}
+
+impl From<f64> for RangeBound {
+    fn from(number: f64) -> Self {
+        RangeBound::Number(number)
+    }
+}
+
+impl From<&str> for RangeBound {
+    fn from(text: &str) -> Self {
+        RangeBound::Str(text.to_string())
+    }
+}
+
+impl RangeAggregation {
+    /// Replaces `now` in the date math of the bounds with the given timestamp in microseconds, so
+    /// that all segments use the same ranges.
+    pub(crate) fn resolve_now(&mut self, now: i64) {
+        let Ok(now) = format_date(now) else {
+            return;
+        };
+        let bounds = self
+            .ranges
+            .iter_mut()
+            .flat_map(|range| [range.from.as_mut(), range.to.as_mut()])
+            .flatten();
+        for bound in bounds {
+            if let RangeBound::Str(date_math) = bound {
+                if let Some(math) = date_math.strip_prefix("now") {
+                    *date_math = format!("{}||{}", now, math);
+                }
+            }
+        }
+    }
 }
 
 impl From<Range<f64>> for RangeAggregationRange {
@@ -86,39 +178,40 @@ impl From<Range<f64>> for RangeAggregationRange {
         let from = if range.start == f64::MIN {
             None
         } else {
-            Some(range.start)
+            Some(range.start.into())
         };
         let to = if range.end == f64::MAX {
             None
         } else {
-            Some(range.end)
+            Some(range.end.into())
         };
         RangeAggregationRange {
             key: None,
             from,
             to,
+            mask: None,
         }
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
-/// Internally used u64 range for one range bucket.
+/// Internally used u128 range for one range bucket.
 pub(crate) struct InternalRangeAggregationRange {
     /// Custom key for the range bucket
     key: Option<String>,
-    /// `u64` range value
-    range: Range<u64>,
+    /// `u128` range value, which is a `u64` fast field value or an ip address.
+    range: Range<u128>,
 }
 
-impl From<Range<u64>> for InternalRangeAggregationRange {
-    fn from(range: Range<u64>) -> Self {
+impl From<Range<u128>> for InternalRangeAggregationRange {
+    fn from(range: Range<u128>) -> Self {
         InternalRangeAggregationRange { key: None, range }
     }
 }
 
 #[derive(Clone, Debug)]
 pub(crate) struct SegmentRangeAndBucketEntry {
-    range: Range<u64>,
+    range: Range<u128>,
     bucket: SegmentRangeBucketEntry,
 }
 
@@ -129,6 +222,8 @@ pub struct SegmentRangeCollector {
     /// The buckets containing the aggregation data.
     buckets: Vec<SegmentRangeAndBucketEntry>,
     field_type: Type,
+    /// The missing value of the request in fast field value space.
+    missing: Option<u64>,
 }
 
 #[derive(Clone)]
@@ -141,6 +236,10 @@ pub(crate) struct SegmentRangeBucketEntry {
     /// The to range of the bucket. Equals `f64::MAX` when `None`. Open interval, `to` is not
     /// inclusive.
     pub to: Option<f64>,
+    /// The from range of the bucket as string on date and ip fields.
+    pub from_as_string: Option<String>,
+    /// The to range of the bucket as string on date and ip fields.
+    pub to_as_string: Option<String>,
 }
 
 impl Debug for SegmentRangeBucketEntry {
@@ -150,6 +249,8 @@ impl Debug for SegmentRangeBucketEntry {
             .field("doc_count", &self.doc_count)
             .field("from", &self.from)
             .field("to", &self.to)
+            .field("from_as_string", &self.from_as_string)
+            .field("to_as_string", &self.to_as_string)
             .finish()
     }
 }
@@ -170,6 +271,8 @@ impl SegmentRangeBucketEntry {
             sub_aggregation,
             from: self.from,
             to: self.to,
+            from_as_string: self.from_as_string,
+            to_as_string: self.to_as_string,
         })
     }
 }
@@ -216,15 +319,15 @@ impl SegmentRangeCollector {
                     .clone()
                     .map(|key| Ok(Key::Str(key)))
                     .unwrap_or_else(|| range_to_key(&range.range, &field_type))?;
-                let to = if range.range.end == u64::MAX {
-                    None
+                let (to, to_as_string) = if range.range.end == u128::MAX {
+                    (None, None)
                 } else {
-                    Some(f64_from_fastfield_u64(range.range.end, &field_type))
+                    bound_to_value_and_string(range.range.end, &field_type)?
                 };
-                let from = if range.range.start == u64::MIN {
-                    None
+                let (from, from_as_string) = if range.range.start == u128::MIN {
+                    (None, None)
                 } else {
-                    Some(f64_from_fastfield_u64(range.range.start, &field_type))
+                    bound_to_value_and_string(range.range.start, &field_type)?
                 };
                 let sub_aggregation = if sub_aggregation.is_empty() {
                     None
@@ -244,6 +347,8 @@ impl SegmentRangeCollector {
                         key,
                         from,
                         to,
+                        from_as_string,
+                        to_as_string,
                     },
                 })
             })
@@ -252,9 +357,18 @@ impl SegmentRangeCollector {
         bucket_count.add_count(buckets.len() as u32);
         bucket_count.validate_bucket_count()?;
 
+        let missing = req
+            .missing
+            .map(|missing| {
+                f64_to_fastfield_u64(missing, &field_type)
+                    .ok_or_else(|| TantivyError::InvalidArgument("invalid field type".to_string()))
+            })
+            .transpose()?;
+
         Ok(SegmentRangeCollector {
             buckets,
             field_type,
+            missing,
         })
     }
 
@@ -265,11 +379,31 @@ impl SegmentRangeCollector {
         bucket_with_accessor: &BucketAggregationWithAccessor,
         force_flush: bool,
     ) -> crate::Result<()> {
-        let accessor = &bucket_with_accessor.accessor;
-        for doc in docs {
-            for val in accessor.values(*doc) {
-                let bucket_pos = self.get_bucket_pos(val);
-                self.increment_bucket(bucket_pos, *doc, &bucket_with_accessor.sub_aggregation)?;
+        if let Some(ip_column) = &bucket_with_accessor.ip_column {
+            for doc in docs {
+                for ip in ip_column.values(*doc) {
+                    let bucket_pos = self.get_bucket_pos(u128::from(ip));
+                    self.increment_bucket(bucket_pos, *doc, &bucket_with_accessor.sub_aggregation)?;
+                }
+            }
+        } else {
+            let accessor = bucket_with_accessor.column();
+            for doc in docs {
+                if let Some(missing) = self.missing {
+                    if !accessor.has_value(*doc) {
+                        let bucket_pos = self.get_bucket_pos(u128::from(missing));
+                        self.increment_bucket(
+                            bucket_pos,
+                            *doc,
+                            &bucket_with_accessor.sub_aggregation,
+                        )?;
+                        continue;
+                    }
+                }
+                for val in accessor.values(*doc) {
+                    let bucket_pos = self.get_bucket_pos(u128::from(val));
+                    self.increment_bucket(bucket_pos, *doc, &bucket_with_accessor.sub_aggregation)?;
+                }
             }
         }
 
@@ -301,7 +435,7 @@ impl SegmentRangeCollector {
     }
 
     #[inline]
-    fn get_bucket_pos(&self, val: u64) -> usize {
+    fn get_bucket_pos(&self, val: u128) -> usize {
         let pos = self
             .buckets
             .binary_search_by_key(&val, |probe| probe.range.start)
@@ -311,7 +445,7 @@ impl SegmentRangeCollector {
     }
 }
 
-/// Converts the user provided f64 range value to fast field value space.
+/// Converts the user provided range to fast field value space.
 ///
 /// Internally fast field values are always stored as u64.
 /// If the fast field has u64 `[1, 2, 5]`, these values are stored as is in the fast field.
@@ -323,22 +457,41 @@ impl SegmentRangeCollector {
 /// fast field.
 /// The alternative would be that every value read would be converted to the f64 range, but that is
 /// more computational expensive when many documents are hit.
-fn to_u64_range(
+///
+/// The values are widened to u128 to also cover ip addresses.
+fn to_u128_range(
     range: &RangeAggregationRange,
     field_type: &Type,
 ) -> crate::Result<InternalRangeAggregationRange> {
-    let start = if let Some(from) = range.from {
-        f64_to_fastfield_u64(from, field_type)
-            .ok_or_else(|| TantivyError::InvalidArgument("invalid field type".to_string()))?
+    if let Some(mask) = &range.mask {
+        if range.from.is_some() || range.to.is_some() {
+            return Err(TantivyError::InvalidArgument(format!(
+                "mask {:?} can't be combined with from and to",
+                mask
+            )));
+        }
+        if *field_type != Type::IpAddr {
+            return Err(TantivyError::InvalidArgument(format!(
+                "mask {:?} is only supported on ip fields",
+                mask
+            )));
+        }
+        return Ok(InternalRangeAggregationRange {
+            key: Some(range.key.clone().unwrap_or_else(|| mask.clone())),
+            range: parse_cidr(mask)?,
+        });
+    }
+
+    let start = if let Some(from) = &range.from {
+        bound_to_u128(from, field_type)?
     } else {
-        u64::MIN
+        u128::MIN
     };
 
-    let end = if let Some(to) = range.to {
-        f64_to_fastfield_u64(to, field_type)
-            .ok_or_else(|| TantivyError::InvalidArgument("invalid field type".to_string()))?
+    let end = if let Some(to) = &range.to {
+        bound_to_u128(to, field_type)?
     } else {
-        u64::MAX
+        u128::MAX
     };
 
     Ok(InternalRangeAggregationRange {
@@ -347,6 +500,54 @@ fn to_u64_range(
     })
 }
 
+fn bound_to_u128(bound: &RangeBound, field_type: &Type) -> crate::Result<u128> {
+    let number = match (bound, field_type) {
+        (RangeBound::Str(ip), Type::IpAddr) => return Ok(u128::from(parse_ip(ip)?)),
+        (RangeBound::Str(date), Type::Date) => {
+            return Ok(u128::from(parse_date_math(date)?.to_u64()))
+        }
+        (RangeBound::Str(number), _) => number.parse().map_err(|_| {
+            TantivyError::InvalidArgument(format!(
+                "could not parse range bound {:?} as number",
+                number
+            ))
+        })?,
+        (RangeBound::Number(number), _) => *number,
+    };
+    f64_to_fastfield_u64(number, field_type)
+        .map(u128::from)
+        .ok_or_else(|| TantivyError::InvalidArgument("invalid field type".to_string()))
+}
+
+fn parse_ip(ip: &str) -> crate::Result<Ipv6Addr> {
+    let ip: IpAddr = ip.parse().map_err(|_| {
+        TantivyError::InvalidArgument(format!("could not parse ip address {:?}", ip))
+    })?;
+    Ok(ip.into_ipv6_addr())
+}
+
+/// Parses a CIDR block like `10.0.0.0/25` into the range of its addresses.
+fn parse_cidr(mask: &str) -> crate::Result<Range<u128>> {
+    let invalid_mask = || {
+        TantivyError::InvalidArgument(format!(
+            "could not parse mask {:?}, expected e.g. 10.0.0.0/25",
+            mask
+        ))
+    };
+    let (ip, prefix_len) = mask.split_once('/').ok_or_else(invalid_mask)?;
+    let prefix_len: u32 = prefix_len.parse().map_err(|_| invalid_mask())?;
+    let (ip, prefix_len) = match ip.parse::<IpAddr>().map_err(|_| invalid_mask())? {
+        // IPv4 addresses are stored as IPv4-mapped IPv6 addresses.
+        IpAddr::V4(ip) if prefix_len <= 32 => (ip.to_ipv6_mapped(), prefix_len + 96),
+        IpAddr::V6(ip) if prefix_len <= 128 => (ip, prefix_len),
+        _ => return Err(invalid_mask()),
+    };
+    let host_mask = u128::MAX.checked_shr(prefix_len).unwrap_or(0);
+    let start = u128::from(ip) & !host_mask;
+    let end = (start | host_mask).saturating_add(1);
+    Ok(start..end)
+}
+
 /// Extends the provided buckets to contain the whole value range, by inserting buckets at the
 /// beginning and end and filling gaps.
 fn extend_validate_ranges(
@@ -355,17 +556,17 @@ fn extend_validate_ranges(
 ) -> crate::Result<Vec<InternalRangeAggregationRange>> {
     let mut converted_buckets = buckets
         .iter()
-        .map(|range| to_u64_range(range, field_type))
+        .map(|range| to_u128_range(range, field_type))
         .collect::<crate::Result<Vec<_>>>()?;
 
     converted_buckets.sort_by_key(|bucket| bucket.range.start);
-    if converted_buckets[0].range.start != u64::MIN {
-        converted_buckets.insert(0, (u64::MIN..converted_buckets[0].range.start).into());
+    if converted_buckets[0].range.start != u128::MIN {
+        converted_buckets.insert(0, (u128::MIN..converted_buckets[0].range.start).into());
     }
 
-    if converted_buckets[converted_buckets.len() - 1].range.end != u64::MAX {
+    if converted_buckets[converted_buckets.len() - 1].range.end != u128::MAX {
         converted_buckets
-            .push((converted_buckets[converted_buckets.len() - 1].range.end..u64::MAX).into());
+            .push((converted_buckets[converted_buckets.len() - 1].range.end..u128::MAX).into());
     }
 
     // fill up holes in the ranges
@@ -393,17 +594,14 @@ fn extend_validate_ranges(
     Ok(converted_buckets)
 }
 
-pub(crate) fn range_to_string(range: &Range<u64>, field_type: &Type) -> crate::Result<String> {
+pub(crate) fn range_to_string(range: &Range<u128>, field_type: &Type) -> crate::Result<String> {
     // is_start is there for malformed requests, e.g. ig the user passes the range u64::MIN..0.0,
     // it should be rendered as "*-0" and not "*-*"
-    let to_str = |val: u64, is_start: bool| {
-        if (is_start && val == u64::MIN) || (!is_start && val == u64::MAX) {
+    let to_str = |val: u128, is_start: bool| {
+        if (is_start && val == u128::MIN) || (!is_start && val == u128::MAX) {
             Ok("*".to_string())
-        } else if *field_type == Type::Date {
-            let val = i64::from_u64(val);
-            format_date(val)
         } else {
-            Ok(f64_from_fastfield_u64(val, field_type).to_string())
+            bound_to_string(val, field_type)
         }
     };
 
@@ -414,10 +612,55 @@ pub(crate) fn range_to_string(range: &Range<u64>, field_type: &Type) -> crate::R
     ))
 }
 
-pub(crate) fn range_to_key(range: &Range<u64>, field_type: &Type) -> crate::Result<Key> {
+pub(crate) fn range_to_key(range: &Range<u128>, field_type: &Type) -> crate::Result<Key> {
     Ok(Key::Str(range_to_string(range, field_type)?))
 }
 
+fn bound_to_string(val: u128, field_type: &Type) -> crate::Result<String> {
+    match field_type {
+        Type::Date => format_date(i64::from_u64(val as u64)),
+        Type::IpAddr => {
+            let ip = Ipv6Addr::from(val);
+            Ok(match ipv4_mapped(&ip) {
+                Some(ip) => ip.to_string(),
+                None => ip.to_string(),
+            })
+        }
+        _ => Ok(f64_from_fastfield_u64(val as u64, field_type).to_string()),
+    }
+}
+
+/// Returns the ipv4 address of an ipv4-mapped ipv6 address, like `Ipv6Addr::to_ipv4_mapped`
+/// which is not available with the minimum supported rust version.
+fn ipv4_mapped(ip: &Ipv6Addr) -> Option<Ipv4Addr> {
+    match ip.octets() {
+        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => Some(Ipv4Addr::new(a, b, c, d)),
+        _ => None,
+    }
+}
+
+/// Returns the bound as `from`/`to` value and as `from_as_string`/`to_as_string` of the bucket.
+///
+/// Ip addresses don't fit into a f64 and only have a string representation.
+fn bound_to_value_and_string(
+    val: u128,
+    field_type: &Type,
+) -> crate::Result<(Option<f64>, Option<String>)> {
+    match field_type {
+        Type::IpAddr => Ok((None, Some(bound_to_string(val, field_type)?))),
+        Type::Date => Ok((
+            Some(f64_from_fastfield_u64(val as u64, field_type)),
+            Some(bound_to_string(val, field_type)?),
+        )),
+        _ => Ok((Some(f64_from_fastfield_u64(val as u64, field_type)), None)),
+    }
+}
+
+/// Parses the `from_as_string` of a bucket on an ip field, to sort the buckets.
+pub(crate) fn parse_ip_bound(ip: &str) -> Option<u128> {
+    parse_ip(ip).ok().map(u128::from)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -430,8 +673,10 @@ mod tests {
     };
     use crate::aggregation::tests::{
         exec_request, exec_request_with_query, get_test_index_2_segments,
-        get_test_index_with_num_docs,
+        get_test_index_with_missing_values, get_test_index_with_num_docs,
     };
+    use crate::schema::{Schema, FAST};
+    use crate::Index;
 
     pub fn get_collector_from_ranges(
         ranges: Vec<RangeAggregationRange>,
@@ -495,6 +740,7 @@ mod tests {
                     field: "fraction_f64".to_string(),
                     ranges: vec![(0f64..0.1f64).into(), (0.1f64..0.2f64).into()],
                     keyed: true,
+                    ..Default::default()
                 }),
                 sub_aggregation: Default::default(),
             }),
@@ -533,16 +779,19 @@ mod tests {
                     ranges: vec![
                         RangeAggregationRange {
                             key: Some("custom-key-0-to-0.1".to_string()),
-                            from: Some(0f64),
-                            to: Some(0.1f64),
+                            from: Some(0f64.into()),
+                            to: Some(0.1f64.into()),
+                            mask: None,
                         },
                         RangeAggregationRange {
                             key: None,
-                            from: Some(0.1f64),
-                            to: Some(0.2f64),
+                            from: Some(0.1f64.into()),
+                            to: Some(0.2f64.into()),
+                            mask: None,
                         },
                     ],
                     keyed: false,
+                    ..Default::default()
                 }),
                 sub_aggregation: Default::default(),
             }),
@@ -591,15 +840,18 @@ mod tests {
                         RangeAggregationRange {
                             key: None,
                             from: None,
-                            to: Some(1546300800000000.0f64),
+                            to: Some(1546300800000000.0f64.into()),
+                            mask: None,
                         },
                         RangeAggregationRange {
                             key: None,
-                            from: Some(1546300800000000.0f64),
-                            to: Some(1546387200000000.0f64),
+                            from: Some(1546300800000000.0f64.into()),
+                            to: Some(1546387200000000.0f64.into()),
+                            mask: None,
                         },
                     ],
                     keyed: false,
+                    ..Default::default()
                 }),
                 sub_aggregation: Default::default(),
             }),
@@ -651,10 +903,12 @@ mod tests {
                     field: "fraction_f64".to_string(),
                     ranges: vec![RangeAggregationRange {
                         key: Some("custom-key-0-to-0.1".to_string()),
-                        from: Some(0f64),
-                        to: Some(0.1f64),
+                        from: Some(0f64.into()),
+                        to: Some(0.1f64.into()),
+                        mask: None,
                     }],
                     keyed: true,
+                    ..Default::default()
                 }),
                 sub_aggregation: Default::default(),
             }),
@@ -686,15 +940,15 @@ mod tests {
         let collector = get_collector_from_ranges(buckets, Type::F64);
 
         let buckets = collector.buckets;
-        assert_eq!(buckets[0].range.start, u64::MIN);
-        assert_eq!(buckets[0].range.end, 10f64.to_u64());
-        assert_eq!(buckets[1].range.start, 10f64.to_u64());
-        assert_eq!(buckets[1].range.end, 20f64.to_u64());
+        assert_eq!(buckets[0].range.start, u128::MIN);
+        assert_eq!(buckets[0].range.end, u128::from(10f64.to_u64()));
+        assert_eq!(buckets[1].range.start, u128::from(10f64.to_u64()));
+        assert_eq!(buckets[1].range.end, u128::from(20f64.to_u64()));
         // Added bucket to fill hole
-        assert_eq!(buckets[2].range.start, 20f64.to_u64());
-        assert_eq!(buckets[2].range.end, 30f64.to_u64());
-        assert_eq!(buckets[3].range.start, 30f64.to_u64());
-        assert_eq!(buckets[3].range.end, 40f64.to_u64());
+        assert_eq!(buckets[2].range.start, u128::from(20f64.to_u64()));
+        assert_eq!(buckets[2].range.end, u128::from(30f64.to_u64()));
+        assert_eq!(buckets[3].range.start, u128::from(30f64.to_u64()));
+        assert_eq!(buckets[3].range.end, u128::from(40f64.to_u64()));
     }
 
     #[test]
@@ -709,12 +963,12 @@ mod tests {
         let collector = get_collector_from_ranges(buckets, Type::F64);
 
         let buckets = collector.buckets;
-        assert_eq!(buckets[0].range.start, u64::MIN);
-        assert_eq!(buckets[0].range.end, 10f64.to_u64());
-        assert_eq!(buckets[1].range.start, 10f64.to_u64());
-        assert_eq!(buckets[1].range.end, 20f64.to_u64());
-        assert_eq!(buckets[2].range.start, 20f64.to_u64());
-        assert_eq!(buckets[2].range.end, u64::MAX);
+        assert_eq!(buckets[0].range.start, u128::MIN);
+        assert_eq!(buckets[0].range.end, u128::from(10f64.to_u64()));
+        assert_eq!(buckets[1].range.start, u128::from(10f64.to_u64()));
+        assert_eq!(buckets[1].range.end, u128::from(20f64.to_u64()));
+        assert_eq!(buckets[2].range.start, u128::from(20f64.to_u64()));
+        assert_eq!(buckets[2].range.end, u128::MAX);
         assert_eq!(buckets.len(), 3);
     }
 
@@ -741,7 +995,7 @@ mod tests {
     fn range_binary_search_test_u64() {
         let check_ranges = |ranges: Vec<RangeAggregationRange>| {
             let collector = get_collector_from_ranges(ranges, Type::U64);
-            let search = |val: u64| collector.get_bucket_pos(val);
+            let search = |val: u64| collector.get_bucket_pos(u128::from(val));
 
             assert_eq!(search(u64::MIN), 0);
             assert_eq!(search(9), 0);
@@ -759,8 +1013,9 @@ mod tests {
         let ranges = vec![
             RangeAggregationRange {
                 key: None,
-                to: Some(10.0),
+                to: Some(10.0.into()),
                 from: None,
+                mask: None,
             },
             (10.0..100.0).into(),
         ];
@@ -769,14 +1024,16 @@ mod tests {
         let ranges = vec![
             RangeAggregationRange {
                 key: None,
-                to: Some(10.0),
+                to: Some(10.0.into()),
                 from: None,
+                mask: None,
             },
             (10.0..100.0).into(),
             RangeAggregationRange {
                 key: None,
                 to: None,
-                from: Some(100.0),
+                from: Some(100.0.into()),
+                mask: None,
             },
         ];
         check_ranges(ranges);
@@ -787,7 +1044,7 @@ mod tests {
         let ranges = vec![(10.0..100.0).into()];
 
         let collector = get_collector_from_ranges(ranges, Type::F64);
-        let search = |val: u64| collector.get_bucket_pos(val);
+        let search = |val: u64| collector.get_bucket_pos(u128::from(val));
 
         assert_eq!(search(u64::MIN), 0);
         assert_eq!(search(9f64.to_u64()), 0);
@@ -798,6 +1055,172 @@ mod tests {
         assert_eq!(search(u64::MAX - 1), 2); // Since the end range is never included,
                                              // the max value
     }
+
+    #[test]
+    fn range_missing_test() -> crate::Result<()> {
+        for merge_segments in [false, true] {
+            let index = get_test_index_with_missing_values(merge_segments)?;
+            let agg_req: Aggregations = vec![(
+                "range".to_string(),
+                Aggregation::Bucket(BucketAggregation {
+                    bucket_agg: BucketAggregationType::Range(RangeAggregation {
+                        field: "score".to_string(),
+                        ranges: vec![(f64::MIN..5f64).into(), (5f64..f64::MAX).into()],
+                        missing: Some(100.0),
+                        ..Default::default()
+                    }),
+                    sub_aggregation: Default::default(),
+                }),
+            )]
+            .into_iter()
+            .collect();
+
+            let res = exec_request(agg_req, &index)?;
+            assert_eq!(res["range"]["buckets"][0]["key"], "*-5");
+            assert_eq!(res["range"]["buckets"][0]["doc_count"], 2);
+            assert_eq!(res["range"]["buckets"][1]["key"], "5-*");
+            assert_eq!(res["range"]["buckets"][1]["doc_count"], 5);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn range_date_string_bounds_test() -> crate::Result<()> {
+        let index = get_test_index_2_segments(false)?;
+
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "date_ranges": {
+                "range": {
+                    "field": "date",
+                    "ranges": [
+                        { "to": "2019-01-01" },
+                        { "from": "2019-01-01T00:00:00Z", "to": "2019-01-01||+1d" },
+                        { "from": "2019-01-01||+1d", "to": "now/d" }
+                    ]
+                }
+            }
+        }))
+        .unwrap();
+
+        let res = exec_request(agg_req, &index)?;
+        let buckets = &res["date_ranges"]["buckets"];
+        assert_eq!(buckets[0]["key"], "*-2019-01-01T00:00:00Z");
+        assert_eq!(buckets[0]["doc_count"], 0);
+        assert_eq!(buckets[1]["from_as_string"], "2019-01-01T00:00:00Z");
+        assert_eq!(buckets[1]["to_as_string"], "2019-01-02T00:00:00Z");
+        assert_eq!(buckets[1]["doc_count"], 1);
+        assert_eq!(buckets[2]["from_as_string"], "2019-01-02T00:00:00Z");
+        assert_eq!(buckets[2]["doc_count"], 8);
+        let today = buckets[2]["to_as_string"].as_str().unwrap();
+        assert!(today.ends_with("T00:00:00Z"), "{}", today);
+        assert_eq!(buckets[3]["from_as_string"], today);
+        assert_eq!(buckets[3]["doc_count"], 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn range_ip_test() -> crate::Result<()> {
+        let mut schema_builder = Schema::builder();
+        let ip_field = schema_builder.add_ip_addr_field("ip", FAST);
+        let index = Index::create_in_ram(schema_builder.build());
+        {
+            let mut index_writer = index.writer_for_tests()?;
+            for ip in [
+                "10.0.0.1",
+                "10.0.0.5",
+                "10.0.0.200",
+                "192.168.1.1",
+                "2001:db8::1",
+            ] {
+                let ip = ip.parse::<IpAddr>().unwrap().into_ipv6_addr();
+                index_writer.add_document(doc!(ip_field => ip))?;
+            }
+            index_writer.commit()?;
+        }
+
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "ip_ranges": {
+                "range": {
+                    "field": "ip",
+                    "ranges": [
+                        { "to": "10.0.0.5" },
+                        { "from": "10.0.0.5", "to": "10.0.1.0" },
+                        { "mask": "192.168.0.0/16" }
+                    ]
+                }
+            }
+        }))
+        .unwrap();
+
+        let res = exec_request(agg_req, &index)?;
+        assert_eq!(
+            res["ip_ranges"]["buckets"],
+            json!([
+                { "key": "*-10.0.0.5", "doc_count": 1, "to_as_string": "10.0.0.5" },
+                {
+                    "key": "10.0.0.5-10.0.1.0",
+                    "doc_count": 2,
+                    "from_as_string": "10.0.0.5",
+                    "to_as_string": "10.0.1.0"
+                },
+                {
+                    "key": "10.0.1.0-192.168.0.0",
+                    "doc_count": 0,
+                    "from_as_string": "10.0.1.0",
+                    "to_as_string": "192.168.0.0"
+                },
+                {
+                    "key": "192.168.0.0/16",
+                    "doc_count": 1,
+                    "from_as_string": "192.168.0.0",
+                    "to_as_string": "192.169.0.0"
+                },
+                { "key": "192.169.0.0-*", "doc_count": 1, "from_as_string": "192.169.0.0" }
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn range_invalid_string_bounds_test() -> crate::Result<()> {
+        let index = get_test_index_2_segments(false)?;
+        let exec_ranges = |field: &str, ranges: Value| {
+            let agg_req: Aggregations = serde_json::from_value(json!({
+                "range": { "range": { "field": field, "ranges": ranges } }
+            }))
+            .unwrap();
+            exec_request(agg_req, &index).unwrap_err().to_string()
+        };
+
+        assert!(exec_ranges("date", json!([{ "from": "yesterday" }])).contains("yesterday"));
+        assert!(exec_ranges("score", json!([{ "from": "abc" }])).contains("abc"));
+        assert!(exec_ranges("score", json!([{ "mask": "10.0.0.0/8" }])).contains("ip fields"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_cidr_test() {
+        let ip = |ip: &str| u128::from(parse_ip(ip).unwrap());
+        assert_eq!(
+            parse_cidr("10.0.0.0/25").unwrap(),
+            ip("10.0.0.0")..ip("10.0.0.128")
+        );
+        assert_eq!(
+            parse_cidr("10.0.0.77/24").unwrap(),
+            ip("10.0.0.0")..ip("10.0.1.0")
+        );
+        assert_eq!(
+            parse_cidr("2001:db8::/32").unwrap(),
+            ip("2001:db8::")..ip("2001:db9::")
+        );
+        assert_eq!(parse_cidr("::/0").unwrap(), u128::MIN..u128::MAX);
+        assert!(parse_cidr("10.0.0.0/33").is_err());
+        assert!(parse_cidr("10.0.0.0").is_err());
+    }
 }
 
 #[cfg(all(test, feature = "unstable"))]
@@ -843,7 +1266,7 @@ mod bench {
         b.iter(|| {
             let mut bucket_pos = 0;
             for val in &vals {
-                bucket_pos = collector.get_bucket_pos(*val);
+                bucket_pos = collector.get_bucket_pos(u128::from(*val));
             }
             bucket_pos
         })
@@ -0,0 +1,438 @@
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use rustc_hash::FxHasher;
+use serde::{Deserialize, Serialize};
+
+use crate::aggregation::agg_req::BucketAggregationType;
+use crate::aggregation::agg_req_with_accessor::BucketAggregationWithAccessor;
+use crate::aggregation::intermediate_agg_result::{
+    IntermediateBucketResult, IntermediateFilterBucketEntry,
+};
+use crate::aggregation::segment_agg_result::{
+    build_segment_agg_collector, SegmentAggregationCollector,
+};
+use crate::{DocId, Score, SegmentReader, TantivyError};
+
+/// Runs the sub-aggregations on the top scoring documents of every segment.
+///
+/// Expensive sub-aggregations, like a `terms` or `significant_terms` aggregation on a high
+/// cardinality field, can be limited to the most relevant documents of a search. The
+/// aggregation collector requests the scores of the documents from the search, if the request
+/// contains a `sampler` aggregation.
+///
+/// The result is a single bucket with the number of sampled documents as `doc_count`.
+///
+/// Result type is [`BucketResult::Filter`](crate::aggregation::agg_result::BucketResult::Filter)
+/// on the `AggregationCollector`.
+///
+/// Result type is
+/// [`IntermediateBucketResult::Filter`](crate::aggregation::intermediate_agg_result::IntermediateBucketResult::Filter)
+/// on the `DistributedAggregationCollector`.
+///
+/// # Limitations/Compatibility
+/// Documents with the same score are sampled in the order of their doc ids. The scores of the
+/// collected documents are kept until the end of the segment.
+///
+/// # Request JSON Format
+/// ```json
+/// {
+///     "sample": {
+///         "sampler": { "shard_size": 200 },
+///         "aggs": {
+///             "keywords": { "significant_terms": { "field": "text" } }
+///         }
+///     }
+/// }
+/// ```
+///
+/// # Response JSON Format
+/// ```json
+/// {
+///     "sample": {
+///         "doc_count": 200,
+///         "keywords": { ... }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SamplerAggregation {
+    /// The number of top scoring documents, which are sampled per segment. Defaults to 100.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        alias = "segment_size"
+    )]
+    pub shard_size: Option<u32>,
+}
+
+const DEFAULT_SAMPLER_SHARD_SIZE: u32 = 100;
+
+impl SamplerAggregation {
+    fn shard_size(&self) -> usize {
+        self.shard_size.unwrap_or(DEFAULT_SAMPLER_SHARD_SIZE) as usize
+    }
+
+    pub(crate) fn validate(&self) -> crate::Result<()> {
+        if self.shard_size == Some(0) {
+            return Err(TantivyError::InvalidArgument(
+                "shard_size of the sampler aggregation must be greater than 0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Runs the sub-aggregations on a random sample of the documents.
+///
+/// Every collected document is sampled with the given probability, so that aggregations over
+/// billions of documents only visit a fraction of them. The sample is deterministic for a seed
+/// and a segment.
+///
+/// The result is a single bucket with the number of sampled documents as `doc_count`.
+///
+/// Result type is [`BucketResult::Filter`](crate::aggregation::agg_result::BucketResult::Filter)
+/// on the `AggregationCollector`.
+///
+/// Result type is
+/// [`IntermediateBucketResult::Filter`](crate::aggregation::intermediate_agg_result::IntermediateBucketResult::Filter)
+/// on the `DistributedAggregationCollector`.
+///
+/// # Limitations/Compatibility
+/// The counts and sums of the sub-aggregations are the ones of the sample. They are not scaled,
+/// divide them by the probability to estimate the values of all documents.
+///
+/// Merging segments changes the sample.
+///
+/// # Request JSON Format
+/// ```json
+/// {
+///     "sample": {
+///         "random_sampler": { "probability": 0.01, "seed": 42 },
+///         "aggs": {
+///             "hosts": { "terms": { "field": "host" } }
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RandomSamplerAggregation {
+    /// The probability of a document to be sampled, in `(0, 1]`.
+    pub probability: f64,
+    /// The seed of the sample. Defaults to 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+impl RandomSamplerAggregation {
+    pub(crate) fn validate(&self) -> crate::Result<()> {
+        if !(self.probability > 0.0 && self.probability <= 1.0) {
+            return Err(TantivyError::InvalidArgument(format!(
+                "probability of the random_sampler aggregation must be in (0, 1], but got {}",
+                self.probability
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the seed of the sample in a segment.
+    pub(crate) fn segment_seed(&self, reader: &SegmentReader) -> u64 {
+        let mut hasher = FxHasher::default();
+        self.seed.unwrap_or(0).hash(&mut hasher);
+        reader.segment_id().hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// The scores of the documents collected in a segment, which are read by the sampler
+/// aggregations.
+///
+/// The aggregation collector records the scores, if the request contains a sampler aggregation,
+/// see [`requires_scoring`](crate::aggregation::agg_req::requires_scoring).
+#[derive(Clone, Default)]
+pub(crate) struct DocScores {
+    scores: Rc<RefCell<Vec<Score>>>,
+}
+
+impl DocScores {
+    pub(crate) fn record(&self, doc: DocId, score: Score) {
+        let mut scores = self.scores.borrow_mut();
+        let doc = doc as usize;
+        if scores.len() <= doc {
+            scores.resize(doc + 1, 0.0);
+        }
+        scores[doc] = score;
+    }
+
+    fn score(&self, doc: DocId) -> Score {
+        self.scores
+            .borrow()
+            .get(doc as usize)
+            .copied()
+            .unwrap_or(0.0)
+    }
+}
+
+/// Maps a document to a uniformly distributed number in `[0, 1)`.
+fn random_unit(segment_seed: u64, doc: DocId) -> f64 {
+    // splitmix64
+    let mut z = segment_seed.wrapping_add((doc as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[derive(Clone, Debug)]
+enum SampleType {
+    /// The top scoring documents, which are only known at the end of the segment.
+    TopScores {
+        shard_size: usize,
+        candidates: Vec<(Score, DocId)>,
+    },
+    Random {
+        probability: f64,
+        segment_seed: u64,
+    },
+}
+
+/// The collector of the sampler and random_sampler aggregations in a segment.
+#[derive(Clone, Debug)]
+pub(crate) struct SegmentSamplerCollector {
+    sample_type: SampleType,
+    doc_count: u64,
+    sub_aggregation: Option<Box<dyn SegmentAggregationCollector>>,
+}
+
+impl SegmentSamplerCollector {
+    pub(crate) fn from_req_and_validate(
+        req: &BucketAggregationWithAccessor,
+    ) -> crate::Result<Self> {
+        let sample_type = match &req.bucket_agg {
+            BucketAggregationType::Sampler(sampler) => SampleType::TopScores {
+                shard_size: sampler.shard_size(),
+                candidates: Vec::new(),
+            },
+            BucketAggregationType::RandomSampler(random_sampler) => SampleType::Random {
+                probability: random_sampler.probability,
+                segment_seed: req
+                    .random_sampler_seed
+                    .expect("internal error: seed not set for random_sampler aggregation"),
+            },
+            _ => {
+                return Err(TantivyError::InternalError(
+                    "unexpected aggregation, expected sampler aggregation".to_string(),
+                ))
+            }
+        };
+        let sub_aggregation = if req.sub_aggregation.is_empty() {
+            None
+        } else {
+            Some(build_segment_agg_collector(&req.sub_aggregation)?)
+        };
+        req.bucket_count.add_count(1);
+        req.bucket_count.validate_bucket_count()?;
+        Ok(SegmentSamplerCollector {
+            sample_type,
+            doc_count: 0,
+            sub_aggregation,
+        })
+    }
+
+    pub(crate) fn into_intermediate_bucket_result(
+        self,
+        agg_with_accessor: &BucketAggregationWithAccessor,
+    ) -> crate::Result<IntermediateBucketResult> {
+        let sub_aggregation = if let Some(sub_aggregation) = self.sub_aggregation {
+            sub_aggregation
+                .into_intermediate_aggregations_result(&agg_with_accessor.sub_aggregation)?
+        } else {
+            Default::default()
+        };
+        Ok(IntermediateBucketResult::Filter(
+            IntermediateFilterBucketEntry {
+                doc_count: self.doc_count,
+                sub_aggregation,
+            },
+        ))
+    }
+
+    #[inline]
+    pub(crate) fn collect_block(
+        &mut self,
+        docs: &[DocId],
+        bucket_with_accessor: &BucketAggregationWithAccessor,
+        force_flush: bool,
+    ) -> crate::Result<()> {
+        let sub_aggregation_accessor = &bucket_with_accessor.sub_aggregation;
+        match &mut self.sample_type {
+            SampleType::TopScores {
+                shard_size,
+                candidates,
+            } => {
+                let doc_scores = bucket_with_accessor
+                    .doc_scores
+                    .as_ref()
+                    .expect("internal error: scores not recorded for sampler aggregation");
+                for &doc in docs {
+                    candidates.push((doc_scores.score(doc), doc));
+                    // Amortize the selection of the top documents.
+                    if candidates.len() >= 2 * *shard_size {
+                        keep_top_scores(candidates, *shard_size);
+                    }
+                }
+                if force_flush {
+                    keep_top_scores(candidates, *shard_size);
+                    candidates.sort_unstable_by_key(|(_score, doc)| *doc);
+                    self.doc_count += candidates.len() as u64;
+                    if let Some(sub_aggregation) = self.sub_aggregation.as_mut() {
+                        for &(_score, doc) in candidates.iter() {
+                            sub_aggregation.collect(doc, sub_aggregation_accessor)?;
+                        }
+                    }
+                    candidates.clear();
+                }
+            }
+            SampleType::Random {
+                probability,
+                segment_seed,
+            } => {
+                for &doc in docs {
+                    if random_unit(*segment_seed, doc) < *probability {
+                        self.doc_count += 1;
+                        if let Some(sub_aggregation) = self.sub_aggregation.as_mut() {
+                            sub_aggregation.collect(doc, sub_aggregation_accessor)?;
+                        }
+                    }
+                }
+            }
+        }
+
+        if force_flush {
+            if let Some(sub_aggregation) = self.sub_aggregation.as_mut() {
+                sub_aggregation.flush_staged_docs(sub_aggregation_accessor, true)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the `shard_size` documents with the highest scores, preferring lower doc ids on ties.
+fn keep_top_scores(candidates: &mut Vec<(Score, DocId)>, shard_size: usize) {
+    if candidates.len() <= shard_size {
+        return;
+    }
+    candidates.select_nth_unstable_by(
+        shard_size - 1,
+        |(left_score, left_doc), (right_score, right_doc)| {
+            right_score
+                .total_cmp(left_score)
+                .then_with(|| left_doc.cmp(right_doc))
+        },
+    );
+    candidates.truncate(shard_size);
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::random_unit;
+    use crate::aggregation::agg_req::Aggregations;
+    use crate::aggregation::tests::{exec_request, get_test_index_from_terms};
+    use crate::aggregation::AggregationCollector;
+    use crate::query::QueryParser;
+    use crate::schema::{Schema, FAST, TEXT};
+    use crate::Index;
+
+    #[test]
+    fn random_unit_test() {
+        let num_sampled = (0..10_000)
+            .filter(|&doc| random_unit(42, doc) < 0.1)
+            .count();
+        assert!((900..1100).contains(&num_sampled), "{}", num_sampled);
+        assert_ne!(random_unit(1, 0), random_unit(2, 0));
+    }
+
+    #[test]
+    fn sampler_aggregation_test() -> crate::Result<()> {
+        let mut schema_builder = Schema::builder();
+        let text = schema_builder.add_text_field("text", TEXT);
+        let category = schema_builder.add_text_field("category", FAST);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer = index.writer_for_tests()?;
+        // The documents with more occurrences of "rust" score higher.
+        let docs = [
+            ("rust rust rust", "lang"),
+            ("rust and more words in a long text", "other"),
+            ("rust rust", "lang"),
+            ("nothing", "none"),
+            ("rust in some long text about something", "other"),
+        ];
+        for (text_val, category_val) in docs {
+            index_writer.add_document(doc!(text => text_val, category => category_val))?;
+        }
+        index_writer.commit()?;
+
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "sample": {
+                "sampler": { "shard_size": 2 },
+                "aggs": { "categories": { "terms": { "field": "category" } } }
+            }
+        }))
+        .unwrap();
+        let collector = AggregationCollector::from_aggs(agg_req, None, index.schema());
+        let query_parser = QueryParser::for_index(&index, vec![text]);
+        let query = query_parser.parse_query("rust")?;
+        let searcher = index.reader()?.searcher();
+        let res = serde_json::to_value(searcher.search(&query, &collector)?)?;
+        assert_eq!(res["sample"]["doc_count"], 2);
+        let buckets = &res["sample"]["categories"]["buckets"];
+        assert_eq!(buckets, &json!([{ "key": "lang", "doc_count": 2 }]));
+
+        let invalid_req: Aggregations = serde_json::from_value(json!({
+            "sample": { "sampler": { "shard_size": 0 } }
+        }))
+        .unwrap();
+        assert!(exec_request(invalid_req, &index).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn random_sampler_aggregation_test() -> crate::Result<()> {
+        let terms: Vec<&str> = (0..2000)
+            .map(|i| if i % 4 == 0 { "a" } else { "b" })
+            .collect();
+        let index = get_test_index_from_terms(false, &[terms])?;
+        let agg_req = |probability: f64, seed: u64| -> Aggregations {
+            serde_json::from_value(json!({
+                "sample": {
+                    "random_sampler": { "probability": probability, "seed": seed },
+                    "aggs": { "terms": { "terms": { "field": "string_id" } } }
+                }
+            }))
+            .unwrap()
+        };
+
+        let res = exec_request(agg_req(0.1, 1), &index)?;
+        let doc_count = res["sample"]["doc_count"].as_u64().unwrap();
+        assert!((150..250).contains(&doc_count), "{}", doc_count);
+        let buckets = &res["sample"]["terms"]["buckets"];
+        assert_eq!(buckets[0]["key"], "b");
+        assert_eq!(
+            buckets[0]["doc_count"].as_u64().unwrap() + buckets[1]["doc_count"].as_u64().unwrap(),
+            doc_count
+        );
+        // The sample is deterministic for a seed.
+        assert_eq!(exec_request(agg_req(0.1, 1), &index)?, res);
+        assert_ne!(exec_request(agg_req(0.1, 2), &index)?, res);
+        let res = exec_request(agg_req(1.0, 1), &index)?;
+        assert_eq!(res["sample"]["doc_count"], 2000);
+
+        assert!(exec_request(agg_req(0.0, 1), &index).is_err());
+        assert!(exec_request(agg_req(1.5, 1), &index).is_err());
+        Ok(())
+    }
+}
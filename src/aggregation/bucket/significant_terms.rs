@@ -0,0 +1,639 @@
+use std::io;
+use std::sync::Arc;
+
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use super::{TermBucketEntry, TermBuckets};
+use crate::aggregation::agg_req_with_accessor::BucketAggregationWithAccessor;
+use crate::aggregation::intermediate_agg_result::{
+    IntermediateBucketResult, IntermediateSignificantTermBucketEntry,
+    IntermediateSignificantTermsBucketResult,
+};
+use crate::aggregation::segment_agg_result::{
+    build_segment_agg_collector, SegmentAggregationCollector,
+};
+use crate::schema::Field;
+use crate::{DocId, InvertedIndexReader, SegmentReader, TantivyError, Term};
+
+/// Creates a bucket for every term, which is unusually frequent in the documents of the
+/// aggregation (the foreground set) compared to all documents of the index (the background set).
+///
+/// The foreground frequencies are collected like in the
+/// [`TermsAggregation`](super::TermsAggregation). The background frequencies are the document
+/// frequencies of the term dictionary, so the field has to be indexed with the same terms as its
+/// fast field, e.g. a `STRING | FAST` field.
+///
+/// The significance of a term is scored by one of the heuristics [`jlh`](Self::jlh) (default),
+/// [`chi_square`](Self::chi_square) or [`mutual_information`](Self::mutual_information). Only
+/// terms with a positive score are returned, sorted by score.
+///
+/// Result type is
+/// [`BucketResult::SignificantTerms`](crate::aggregation::agg_result::BucketResult::SignificantTerms) with
+/// [`SignificantTermBucketEntry`](crate::aggregation::agg_result::SignificantTermBucketEntry) on the
+/// `AggregationCollector`.
+///
+/// Result type is
+/// [`IntermediateBucketResult::SignificantTerms`](crate::aggregation::intermediate_agg_result::IntermediateBucketResult::SignificantTerms) with
+/// [`IntermediateSignificantTermBucketEntry`](crate::aggregation::intermediate_agg_result::IntermediateSignificantTermBucketEntry) on the
+/// `DistributedAggregationCollector`.
+///
+/// # Limitations/Compatibility
+///
+/// Each segment returns up to [segment_size](SignificantTermsAggregation::segment_size) of its
+/// most significant terms. The background frequency of a term only includes the segments, which
+/// returned the term. This is similar to the per shard results of elasticsearch, but segments are
+/// usually much smaller than shards.
+///
+/// The background frequencies include deleted documents. `background_filter` is not supported.
+///
+/// # Request JSON Format
+/// ```json
+/// {
+///     "failure_causes": {
+///         "significant_terms": {
+///             "field": "endpoint",
+///             "chi_square": { "include_negatives": false }
+///         }
+///     }
+/// }
+/// ```
+///
+/// # Response JSON Format
+/// ```json
+/// {
+///     ...
+///     "aggregations": {
+///         "failure_causes": {
+///             "doc_count": 47,
+///             "bg_count": 5064,
+///             "buckets": [
+///                 { "key": "/checkout", "doc_count": 36, "bg_count": 667, "score": 0.37 }
+///             ]
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SignificantTermsAggregation {
+    /// The field to aggregate on.
+    pub field: String,
+    /// By default, the top 10 most significant terms are returned.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub size: Option<u32>,
+    /// Unused by tantivy, see [`TermsAggregation::split_size`](super::TermsAggregation).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    #[serde(alias = "shard_size")]
+    pub split_size: Option<u32>,
+    /// The number of most significant terms fetched from each segment. Defaults to 10 * size.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub segment_size: Option<u32>,
+    /// Filter all terms that occur in fewer than `min_doc_count` documents of the foreground
+    /// set. Defaults to 3.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub min_doc_count: Option<u64>,
+    /// Scores terms with the JLH heuristic, which is the default.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub jlh: Option<JlhHeuristic>,
+    /// Scores terms with the chi-square statistic.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub chi_square: Option<NxyHeuristic>,
+    /// Scores terms with the mutual information between the term and the foreground set.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub mutual_information: Option<NxyHeuristic>,
+}
+
+/// The JLH heuristic multiplies the absolute and the relative change of the frequency of a term
+/// between the background and the foreground set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct JlhHeuristic {}
+
+/// The parameters of the chi-square and mutual information heuristics.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NxyHeuristic {
+    /// Also return terms, which are less frequent in the foreground set than in the background
+    /// set. Defaults to false.
+    #[serde(default)]
+    pub include_negatives: bool,
+    /// Whether the background set contains the foreground set. Defaults to true.
+    #[serde(default = "default_background_is_superset")]
+    pub background_is_superset: bool,
+}
+
+fn default_background_is_superset() -> bool {
+    true
+}
+
+impl Default for NxyHeuristic {
+    fn default() -> Self {
+        NxyHeuristic {
+            include_negatives: false,
+            background_is_superset: default_background_is_superset(),
+        }
+    }
+}
+
+impl SignificantTermsAggregation {
+    pub(crate) fn size(&self) -> usize {
+        self.size.unwrap_or(10) as usize
+    }
+
+    pub(crate) fn segment_size(&self) -> usize {
+        (self.segment_size.unwrap_or(self.size() as u32 * 10) as usize).max(self.size())
+    }
+
+    pub(crate) fn min_doc_count(&self) -> u64 {
+        self.min_doc_count.unwrap_or(3)
+    }
+
+    pub(crate) fn heuristic(&self) -> SignificanceHeuristic {
+        if let Some(params) = self.chi_square {
+            SignificanceHeuristic::ChiSquare(params)
+        } else if let Some(params) = self.mutual_information {
+            SignificanceHeuristic::MutualInformation(params)
+        } else {
+            SignificanceHeuristic::Jlh
+        }
+    }
+
+    fn validate(&self) -> crate::Result<()> {
+        let num_heuristics = [
+            self.jlh.is_some(),
+            self.chi_square.is_some(),
+            self.mutual_information.is_some(),
+        ]
+        .iter()
+        .filter(|is_set| **is_set)
+        .count();
+        if num_heuristics > 1 {
+            return Err(TantivyError::InvalidArgument(
+                "significant_terms aggregation accepts only one of jlh, chi_square and \
+                 mutual_information"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The heuristic to score the significance of a term.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum SignificanceHeuristic {
+    Jlh,
+    ChiSquare(NxyHeuristic),
+    MutualInformation(NxyHeuristic),
+}
+
+impl SignificanceHeuristic {
+    /// Returns the score of a term, which occurs in `subset_freq` of the `subset_size` documents
+    /// of the foreground set and in `superset_freq` of the `superset_size` documents of the
+    /// background set.
+    pub(crate) fn score(
+        &self,
+        subset_freq: u64,
+        subset_size: u64,
+        superset_freq: u64,
+        superset_size: u64,
+    ) -> f64 {
+        // The frequencies are approximations, e.g. the background includes deleted documents, so
+        // we clamp them to be consistent.
+        let subset_freq = subset_freq.min(subset_size);
+        let (superset_freq, superset_size) = if self.background_is_superset() {
+            (
+                superset_freq.max(subset_freq),
+                superset_size.max(subset_size),
+            )
+        } else {
+            (superset_freq, superset_size)
+        };
+        match self {
+            SignificanceHeuristic::Jlh => {
+                if subset_size == 0 || superset_freq == 0 {
+                    return 0.0;
+                }
+                let subset_probability = subset_freq as f64 / subset_size as f64;
+                let superset_probability = superset_freq as f64 / superset_size as f64;
+                let absolute_change = subset_probability - superset_probability;
+                if absolute_change <= 0.0 {
+                    return 0.0;
+                }
+                absolute_change * (subset_probability / superset_probability)
+            }
+            SignificanceHeuristic::ChiSquare(params) => {
+                let freqs = Frequencies::new(
+                    params,
+                    subset_freq,
+                    subset_size,
+                    superset_freq,
+                    superset_size,
+                );
+                if freqs.is_negative() && !params.include_negatives {
+                    return f64::NEG_INFINITY;
+                }
+                freqs.n * (freqs.n11 * freqs.n00 - freqs.n01 * freqs.n10).powi(2)
+                    / (freqs.n_1 * freqs.n1_ * freqs.n0_ * freqs.n_0)
+            }
+            SignificanceHeuristic::MutualInformation(params) => {
+                let freqs = Frequencies::new(
+                    params,
+                    subset_freq,
+                    subset_size,
+                    superset_freq,
+                    superset_size,
+                );
+                if freqs.is_negative() && !params.include_negatives {
+                    return f64::NEG_INFINITY;
+                }
+                let score = (mutual_information_term(freqs.n00, freqs.n0_, freqs.n_0, freqs.n)
+                    + mutual_information_term(freqs.n01, freqs.n0_, freqs.n_1, freqs.n)
+                    + mutual_information_term(freqs.n10, freqs.n1_, freqs.n_0, freqs.n)
+                    + mutual_information_term(freqs.n11, freqs.n1_, freqs.n_1, freqs.n))
+                    / std::f64::consts::LN_2;
+                if score.is_nan() {
+                    f64::NEG_INFINITY
+                } else {
+                    score
+                }
+            }
+        }
+    }
+
+    fn background_is_superset(&self) -> bool {
+        match self {
+            SignificanceHeuristic::Jlh => true,
+            SignificanceHeuristic::ChiSquare(params)
+            | SignificanceHeuristic::MutualInformation(params) => params.background_is_superset,
+        }
+    }
+}
+
+/// Returns the summand of the mutual information for the cell `n_xy` of the contingency table,
+/// with its row sum `n_x` and column sum `n_y`.
+fn mutual_information_term(n_xy: f64, n_x: f64, n_y: f64, n: f64) -> f64 {
+    let numerator = n * n_xy;
+    let denominator = n_x * n_y;
+    let factor = n_xy / n;
+    if numerator < 1e-7 && factor < 1e-7 {
+        return 0.0;
+    }
+    factor * (numerator / denominator).ln()
+}
+
+/// The contingency table of a term. The first index is 1 for documents containing the term, the
+/// second index is 1 for documents of the foreground set. `_` sums over the index.
+struct Frequencies {
+    n00: f64,
+    n01: f64,
+    n10: f64,
+    n11: f64,
+    n0_: f64,
+    n1_: f64,
+    n_0: f64,
+    n_1: f64,
+    n: f64,
+}
+
+impl Frequencies {
+    fn new(
+        params: &NxyHeuristic,
+        subset_freq: u64,
+        subset_size: u64,
+        superset_freq: u64,
+        superset_size: u64,
+    ) -> Self {
+        let (subset_freq, subset_size) = (subset_freq as f64, subset_size as f64);
+        let (superset_freq, superset_size) = (superset_freq as f64, superset_size as f64);
+        // Remove the foreground set from the background set, so that they are disjunct.
+        let (superset_freq, superset_size) = if params.background_is_superset {
+            (superset_freq - subset_freq, superset_size - subset_size)
+        } else {
+            (superset_freq, superset_size)
+        };
+        let n00 = superset_size - superset_freq;
+        let n01 = subset_size - subset_freq;
+        let n10 = superset_freq;
+        let n11 = subset_freq;
+        Frequencies {
+            n00,
+            n01,
+            n10,
+            n11,
+            n0_: n00 + n01,
+            n1_: n10 + n11,
+            n_0: n00 + n10,
+            n_1: n01 + n11,
+            n: n00 + n01 + n10 + n11,
+        }
+    }
+
+    /// Returns true if the term is less frequent in the foreground set than in the rest of the
+    /// background set.
+    fn is_negative(&self) -> bool {
+        self.n11 / self.n_1 < self.n10 / self.n_0
+    }
+}
+
+/// The term dictionary of the field, to look up the background frequencies of the terms in a
+/// segment.
+#[derive(Clone)]
+pub(crate) struct SignificantTermsBackground {
+    inverted_index: Arc<InvertedIndexReader>,
+    field: Field,
+    /// The number of documents in the segment. Like the document frequencies, it includes
+    /// deleted documents.
+    superset_size: u64,
+}
+
+impl SignificantTermsBackground {
+    pub(crate) fn from_req(
+        req: &SignificantTermsAggregation,
+        reader: &SegmentReader,
+    ) -> crate::Result<Self> {
+        let field = reader.schema().get_field(&req.field)?;
+        if !reader.schema().get_field_entry(field).is_indexed() {
+            return Err(TantivyError::InvalidArgument(format!(
+                "significant_terms aggregation requires the field {:?} to be indexed",
+                req.field
+            )));
+        }
+        Ok(SignificantTermsBackground {
+            inverted_index: reader.inverted_index(field)?,
+            field,
+            superset_size: reader.max_doc() as u64,
+        })
+    }
+
+    fn doc_freq(&self, term: &str) -> io::Result<u64> {
+        let term = Term::from_field_text(self.field, term);
+        Ok(self.inverted_index.doc_freq(&term)? as u64)
+    }
+}
+
+/// The collector for the significant_terms aggregation in a segment.
+#[derive(Clone, Debug)]
+pub(crate) struct SegmentSignificantTermsCollector {
+    term_buckets: TermBuckets,
+    req: SignificantTermsAggregation,
+    blueprint: Option<Box<dyn SegmentAggregationCollector>>,
+    /// The number of collected documents, which is the size of the foreground set.
+    subset_size: u64,
+}
+
+impl SegmentSignificantTermsCollector {
+    pub(crate) fn from_req_and_validate(
+        req: &SignificantTermsAggregation,
+        bucket_with_accessor: &BucketAggregationWithAccessor,
+    ) -> crate::Result<Self> {
+        req.validate()?;
+        if bucket_with_accessor.str_dict_column.is_none() {
+            return Err(TantivyError::InvalidArgument(format!(
+                "significant_terms aggregation requires a text field, but {:?} is not",
+                req.field
+            )));
+        }
+        let sub_aggregations = &bucket_with_accessor.sub_aggregation;
+        let blueprint = if sub_aggregations.is_empty() {
+            None
+        } else {
+            Some(build_segment_agg_collector(sub_aggregations)?)
+        };
+        Ok(SegmentSignificantTermsCollector {
+            term_buckets: TermBuckets::default(),
+            req: req.clone(),
+            blueprint,
+            subset_size: 0,
+        })
+    }
+
+    pub(crate) fn into_intermediate_bucket_result(
+        self,
+        agg_with_accessor: &BucketAggregationWithAccessor,
+    ) -> crate::Result<IntermediateBucketResult> {
+        let term_dict = agg_with_accessor
+            .str_dict_column
+            .as_ref()
+            .expect("internal error: term dictionary not loaded for significant_terms");
+        let background = agg_with_accessor
+            .significant_terms_background
+            .as_ref()
+            .expect("internal error: background not loaded for significant_terms");
+        let heuristic = self.req.heuristic();
+
+        let mut entries: Vec<(String, u64, f64, TermBucketEntry)> =
+            Vec::with_capacity(self.term_buckets.entries.len());
+        let mut buffer = String::new();
+        for (term_id, entry) in self.term_buckets.entries {
+            if !term_dict.ord_to_str(term_id as u64, &mut buffer)? {
+                return Err(TantivyError::InternalError(format!(
+                    "Couldn't find term_id {} in dict",
+                    term_id
+                )));
+            }
+            let bg_count = background.doc_freq(&buffer)?;
+            let score = heuristic.score(
+                entry.doc_count,
+                self.subset_size,
+                bg_count,
+                background.superset_size,
+            );
+            entries.push((buffer.to_string(), bg_count, score, entry));
+        }
+
+        // Keep the most significant terms of the segment.
+        entries.sort_unstable_by(|left, right| right.2.total_cmp(&left.2));
+        entries.truncate(self.req.segment_size());
+
+        let bucket_count = &agg_with_accessor.bucket_count;
+        bucket_count.add_count(entries.len() as u32);
+        bucket_count.validate_bucket_count()?;
+
+        let mut buckets = FxHashMap::default();
+        for (key, bg_count, _score, entry) in entries {
+            let entry = entry.into_intermediate_bucket_entry(&agg_with_accessor.sub_aggregation)?;
+            buckets.insert(
+                key,
+                IntermediateSignificantTermBucketEntry {
+                    doc_count: entry.doc_count,
+                    bg_count,
+                    sub_aggregation: entry.sub_aggregation,
+                },
+            );
+        }
+
+        Ok(IntermediateBucketResult::SignificantTerms(
+            IntermediateSignificantTermsBucketResult {
+                buckets,
+                subset_size: self.subset_size,
+                superset_size: background.superset_size,
+            },
+        ))
+    }
+
+    #[inline]
+    pub(crate) fn collect_block(
+        &mut self,
+        docs: &[DocId],
+        bucket_with_accessor: &BucketAggregationWithAccessor,
+        force_flush: bool,
+    ) -> crate::Result<()> {
+        self.subset_size += docs.len() as u64;
+        self.term_buckets
+            .collect_block(docs, bucket_with_accessor, &self.blueprint, force_flush)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::aggregation::agg_req::Aggregations;
+    use crate::aggregation::tests::{exec_request, get_test_index_from_values_and_terms};
+
+    /// 20 documents, the foreground set are the 10 documents with a score of 1 or higher.
+    ///
+    /// "terma" is in 10 documents, 8 of them in the foreground set. "termb" is in 6 documents, 1
+    /// of them in the foreground set. "termc" is in 4 documents, 1 of them in the foreground set.
+    fn get_test_index(merge_segments: bool) -> crate::Result<crate::Index> {
+        let mut docs = Vec::new();
+        for (num_fg, num_bg, term) in [(8, 2, "terma"), (1, 5, "termb"), (1, 3, "termc")] {
+            for i in 0..num_fg + num_bg {
+                let score = if i < num_fg { i as f64 + 1.0 } else { 0.0 };
+                docs.push((score, term.to_string()));
+            }
+        }
+        let segment_and_values: Vec<_> = docs.chunks(7).map(|chunk| chunk.to_vec()).collect();
+        get_test_index_from_values_and_terms(merge_segments, &segment_and_values)
+    }
+
+    fn exec_significant_terms(
+        index: &crate::Index,
+        significant_terms: Value,
+    ) -> crate::Result<Value> {
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "foreground": {
+                "range": { "field": "score", "ranges": [ { "from": 1.0 } ] },
+                "aggs": {
+                    "significant": {
+                        "significant_terms": significant_terms,
+                        "aggs": { "score_stats": { "stats": { "field": "score" } } }
+                    }
+                }
+            }
+        }))
+        .unwrap();
+        let res = exec_request(agg_req, index)?;
+        // The range aggregation adds a bucket for the documents with a score below 1.
+        Ok(res["foreground"]["buckets"][1]["significant"].clone())
+    }
+
+    fn assert_approx(left: &Value, right: f64) {
+        let left = left.as_f64().unwrap();
+        assert!((left - right).abs() < 1e-9, "{} != {}", left, right);
+    }
+
+    #[test]
+    fn significant_terms_aggregation_test_single_segment() -> crate::Result<()> {
+        significant_terms_aggregation_test_merge_segment(true)
+    }
+    #[test]
+    fn significant_terms_aggregation_test() -> crate::Result<()> {
+        significant_terms_aggregation_test_merge_segment(false)
+    }
+    fn significant_terms_aggregation_test_merge_segment(merge_segments: bool) -> crate::Result<()> {
+        let index = get_test_index(merge_segments)?;
+
+        // jlh
+        let res = exec_significant_terms(&index, json!({ "field": "string_id" }))?;
+        assert_eq!(res["doc_count"], 10);
+        assert_eq!(res["bg_count"], 20);
+        assert_eq!(res["buckets"].as_array().unwrap().len(), 1);
+        assert_eq!(res["buckets"][0]["key"], "terma");
+        assert_eq!(res["buckets"][0]["doc_count"], 8);
+        assert_eq!(res["buckets"][0]["bg_count"], 10);
+        // (0.8 - 0.5) * (0.8 / 0.5)
+        assert_approx(&res["buckets"][0]["score"], 0.48);
+        assert_eq!(res["buckets"][0]["score_stats"]["count"], 8);
+        assert_eq!(res["buckets"][0]["score_stats"]["avg"], 4.5);
+
+        let res = exec_significant_terms(
+            &index,
+            json!({ "field": "string_id", "chi_square": {}, "min_doc_count": 1 }),
+        )?;
+        assert_eq!(res["buckets"].as_array().unwrap().len(), 1);
+        assert_eq!(res["buckets"][0]["key"], "terma");
+        // 20 * (8 * 8 - 2 * 2)^2 / (10 * 10 * 10 * 10)
+        assert_approx(&res["buckets"][0]["score"], 7.2);
+
+        let res = exec_significant_terms(
+            &index,
+            json!({ "field": "string_id", "mutual_information": {} }),
+        )?;
+        assert_eq!(res["buckets"][0]["key"], "terma");
+        assert_approx(
+            &res["buckets"][0]["score"],
+            (0.8 * 1.6f64.ln() + 0.2 * 0.4f64.ln()) / std::f64::consts::LN_2,
+        );
+
+        // termb and termc are less frequent in the foreground set, and are only returned with
+        // include_negatives.
+        let res = exec_significant_terms(
+            &index,
+            json!({
+                "field": "string_id",
+                "chi_square": { "include_negatives": true },
+                "min_doc_count": 1
+            }),
+        )?;
+        assert_eq!(res["buckets"].as_array().unwrap().len(), 3);
+        assert_eq!(res["buckets"][0]["key"], "terma");
+
+        let res = exec_significant_terms(&index, json!({ "field": "string_id", "size": 0 }))?;
+        assert_eq!(res["buckets"], json!([]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn significant_terms_aggregation_invalid_requests() -> crate::Result<()> {
+        let index = get_test_index(true)?;
+
+        let res = exec_significant_terms(
+            &index,
+            json!({ "field": "string_id", "jlh": {}, "chi_square": {} }),
+        );
+        assert!(res.is_err());
+
+        // Not indexed.
+        let res = exec_significant_terms(&index, json!({ "field": "score" }));
+        assert!(res.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn significant_terms_heuristics_test() {
+        let jlh = SignificanceHeuristic::Jlh;
+        // 10% in the foreground set, 1% in the background set.
+        assert!((jlh.score(10, 100, 10, 1000) - 0.09 * 10.0).abs() < 1e-12);
+        assert_eq!(jlh.score(1, 100, 100, 1000), 0.0);
+
+        let chi_square = SignificanceHeuristic::ChiSquare(NxyHeuristic::default());
+        assert!(chi_square.score(10, 100, 10, 1000) > 0.0);
+        assert_eq!(chi_square.score(1, 100, 100, 1000), f64::NEG_INFINITY);
+
+        let mutual_information = SignificanceHeuristic::MutualInformation(NxyHeuristic::default());
+        let score = mutual_information.score(10, 100, 10, 1000);
+        assert!(score > 0.0);
+        // A term, which is in every document of the foreground set and in no other document, has
+        // the highest score.
+        assert!(mutual_information.score(100, 100, 100, 1000) > score);
+
+        // The background set doesn't contain the foreground set.
+        let mutual_information = SignificanceHeuristic::MutualInformation(NxyHeuristic {
+            include_negatives: false,
+            background_is_superset: false,
+        });
+        assert!(mutual_information.score(10, 100, 10, 1000) > 0.0);
+    }
+}
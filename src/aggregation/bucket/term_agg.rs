@@ -1,3 +1,4 @@
+use std::collections::hash_map::Entry;
 use std::fmt::Debug;
 
 use columnar::Column;
@@ -11,7 +12,9 @@ use crate::aggregation::agg_req_with_accessor::{
 };
 use crate::aggregation::intermediate_agg_result::{
     IntermediateBucketResult, IntermediateTermBucketEntry, IntermediateTermBucketResult,
+    MergeFruits,
 };
+use crate::aggregation::memory_budget::{blueprint_memory, hash_map_memory};
 use crate::aggregation::segment_agg_result::{
     build_segment_agg_collector, GenericSegmentAggregationResultsCollector,
     SegmentAggregationCollector,
@@ -146,6 +149,13 @@ pub struct TermsAggregation {
     /// { "average_price": "asc" }
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub order: Option<CustomOrder>,
+
+    /// Documents without a value in the field are collected into a bucket with this key. By
+    /// default such documents are ignored.
+    ///
+    /// If the key is also a term of the field, the documents are counted in its bucket.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub missing: Option<String>,
 }
 
 /// Same as TermsAggregation, but with populated defaults.
@@ -176,6 +186,9 @@ pub(crate) struct TermsAggregationInternal {
     pub min_doc_count: u64,
 
     pub order: CustomOrder,
+
+    /// The key of the bucket for documents without a value in the field.
+    pub missing: Option<String>,
 }
 
 impl TermsAggregationInternal {
@@ -195,20 +208,23 @@ impl TermsAggregationInternal {
                 .unwrap_or_else(|| order == CustomOrder::default()),
             min_doc_count: req.min_doc_count.unwrap_or(1),
             order,
+            missing: req.missing.clone(),
         }
     }
 }
 
 #[derive(Clone, Debug, Default)]
 /// Container to store term_ids and their buckets.
-struct TermBuckets {
+pub(crate) struct TermBuckets {
     pub(crate) entries: FxHashMap<u32, TermBucketEntry>,
+    /// The bucket for documents without a term, if requested.
+    pub(crate) missing: Option<TermBucketEntry>,
 }
 
 #[derive(Clone, Default)]
-struct TermBucketEntry {
-    doc_count: u64,
-    sub_aggregations: Option<Box<dyn SegmentAggregationCollector>>,
+pub(crate) struct TermBucketEntry {
+    pub(crate) doc_count: u64,
+    pub(crate) sub_aggregations: Option<Box<dyn SegmentAggregationCollector>>,
 }
 
 impl Debug for TermBucketEntry {
@@ -220,7 +236,7 @@ impl Debug for TermBucketEntry {
 }
 
 impl TermBucketEntry {
-    fn from_blueprint(blueprint: &Option<Box<dyn SegmentAggregationCollector>>) -> Self {
+    pub(crate) fn from_blueprint(blueprint: &Option<Box<dyn SegmentAggregationCollector>>) -> Self {
         Self {
             doc_count: 0,
             sub_aggregations: blueprint.clone(),
@@ -251,11 +267,61 @@ impl TermBuckets {
     ) -> crate::Result<Self> {
         Ok(TermBuckets {
             entries: Default::default(),
+            missing: None,
         })
     }
 
+    /// Counts the term ids of the docs and collects the docs in the sub_aggregations of their
+    /// buckets.
+    #[inline]
+    pub(crate) fn collect_block(
+        &mut self,
+        docs: &[DocId],
+        bucket_with_accessor: &BucketAggregationWithAccessor,
+        blueprint: &Option<Box<dyn SegmentAggregationCollector>>,
+        force_flush: bool,
+    ) -> crate::Result<()> {
+        let accessor = bucket_with_accessor.column();
+        let memory_before = self.memory_consumption(blueprint);
+
+        for doc in docs {
+            if let Some(missing) = self.missing.as_mut() {
+                if !accessor.has_value(*doc) {
+                    missing.doc_count += 1;
+                    if let Some(sub_aggregations) = missing.sub_aggregations.as_mut() {
+                        sub_aggregations.collect(*doc, &bucket_with_accessor.sub_aggregation)?;
+                    }
+                    continue;
+                }
+            }
+            for term_id in accessor.values(*doc) {
+                let entry = self
+                    .entries
+                    .entry(term_id as u32)
+                    .or_insert_with(|| TermBucketEntry::from_blueprint(blueprint));
+                entry.doc_count += 1;
+                if let Some(sub_aggregations) = entry.sub_aggregations.as_mut() {
+                    sub_aggregations.collect(*doc, &bucket_with_accessor.sub_aggregation)?;
+                }
+            }
+        }
+        bucket_with_accessor
+            .memory_account
+            .add_memory_consumed(self.memory_consumption(blueprint) - memory_before)?;
+
+        if force_flush {
+            self.force_flush(&bucket_with_accessor.sub_aggregation)?;
+        }
+        Ok(())
+    }
+
+    /// Estimates the memory of the buckets, which grows with the number of terms.
+    fn memory_consumption(&self, blueprint: &Option<Box<dyn SegmentAggregationCollector>>) -> u64 {
+        hash_map_memory(&self.entries) + self.entries.len() as u64 * blueprint_memory(blueprint)
+    }
+
     fn force_flush(&mut self, agg_with_accessor: &AggregationsWithAccessor) -> crate::Result<()> {
-        for entry in &mut self.entries.values_mut() {
+        for entry in self.entries.values_mut().chain(self.missing.as_mut()) {
             if let Some(sub_aggregations) = entry.sub_aggregations.as_mut() {
                 sub_aggregations.flush_staged_docs(agg_with_accessor, false)?;
             }
@@ -284,8 +350,6 @@ impl SegmentTermCollector {
         req: &TermsAggregation,
         sub_aggregations: &AggregationsWithAccessor,
     ) -> crate::Result<Self> {
-        let term_buckets = TermBuckets::default();
-
         if let Some(custom_order) = req.order.as_ref() {
             // Validate sub aggregtion exists
             if let OrderTarget::SubAggregation(sub_agg_name) = &custom_order.target {
@@ -308,6 +372,11 @@ impl SegmentTermCollector {
             None
         };
 
+        let mut term_buckets = TermBuckets::default();
+        if req.missing.is_some() {
+            term_buckets.missing = Some(TermBucketEntry::from_blueprint(&blueprint));
+        }
+
         Ok(SegmentTermCollector {
             req: TermsAggregationInternal::from_req(req),
             term_buckets,
@@ -319,6 +388,7 @@ impl SegmentTermCollector {
         self,
         agg_with_accessor: &BucketAggregationWithAccessor,
     ) -> crate::Result<IntermediateBucketResult> {
+        let missing_entry = self.term_buckets.missing;
         let mut entries: Vec<(u32, TermBucketEntry)> =
             self.term_buckets.entries.into_iter().collect();
 
@@ -356,38 +426,49 @@ impl SegmentTermCollector {
             cut_off_buckets(&mut entries, self.req.segment_size as usize)
         };
 
-        let inverted_index = agg_with_accessor
-            .str_dict_column
-            .as_ref()
-            .expect("internal error: inverted index not loaded for term aggregation");
-        let term_dict = inverted_index;
-
         let mut dict: FxHashMap<String, IntermediateTermBucketEntry> = Default::default();
-        let mut buffer = String::new();
-        for (term_id, entry) in entries {
-            if !term_dict.ord_to_str(term_id as u64, &mut buffer)? {
-                return Err(TantivyError::InternalError(format!(
-                    "Couldn't find term_id {} in dict",
-                    term_id
-                )));
+        // A json path without text values in the segment has no dictionary and no entries.
+        if let Some(term_dict) = agg_with_accessor.str_dict_column.as_ref() {
+            let mut buffer = String::new();
+            for (term_id, entry) in entries {
+                if !term_dict.ord_to_str(term_id as u64, &mut buffer)? {
+                    return Err(TantivyError::InternalError(format!(
+                        "Couldn't find term_id {} in dict",
+                        term_id
+                    )));
+                }
+                dict.insert(
+                    buffer.to_string(),
+                    entry.into_intermediate_bucket_entry(&agg_with_accessor.sub_aggregation)?,
+                );
             }
-            dict.insert(
-                buffer.to_string(),
-                entry.into_intermediate_bucket_entry(&agg_with_accessor.sub_aggregation)?,
-            );
-        }
-        if self.req.min_doc_count == 0 {
-            // TODO: Handle rev streaming for descending sorting by keys
-            let mut stream = term_dict.dictionary().stream()?;
-            while let Some((key, _ord)) = stream.next() {
-                if dict.len() >= self.req.segment_size as usize {
-                    break;
+            if self.req.min_doc_count == 0 {
+                // TODO: Handle rev streaming for descending sorting by keys
+                let mut stream = term_dict.dictionary().stream()?;
+                while let Some((key, _ord)) = stream.next() {
+                    if dict.len() >= self.req.segment_size as usize {
+                        break;
+                    }
+
+                    let key = std::str::from_utf8(key)
+                        .map_err(|utf8_err| DataCorruption::comment_only(utf8_err.to_string()))?;
+                    if !dict.contains_key(key) {
+                        dict.insert(key.to_owned(), Default::default());
+                    }
                 }
-
-                let key = std::str::from_utf8(key)
-                    .map_err(|utf8_err| DataCorruption::comment_only(utf8_err.to_string()))?;
-                if !dict.contains_key(key) {
-                    dict.insert(key.to_owned(), Default::default());
+            }
+        }
+        // The missing bucket is not subject to the segment_size cut off, since it has no term
+        // ordinal to sort by.
+        if let (Some(key), Some(entry)) = (self.req.missing, missing_entry) {
+            if entry.doc_count > 0 {
+                let entry =
+                    entry.into_intermediate_bucket_entry(&agg_with_accessor.sub_aggregation)?;
+                match dict.entry(key) {
+                    Entry::Occupied(mut occupied) => occupied.get_mut().merge_fruits(entry)?,
+                    Entry::Vacant(vacant) => {
+                        vacant.insert(entry);
+                    }
                 }
             }
         }
@@ -408,27 +489,8 @@ impl SegmentTermCollector {
         bucket_with_accessor: &BucketAggregationWithAccessor,
         force_flush: bool,
     ) -> crate::Result<()> {
-        let accessor = &bucket_with_accessor.accessor;
-
-        for doc in docs {
-            for term_id in accessor.values(*doc) {
-                let entry = self
-                    .term_buckets
-                    .entries
-                    .entry(term_id as u32)
-                    .or_insert_with(|| TermBucketEntry::from_blueprint(&self.blueprint));
-                entry.doc_count += 1;
-                if let Some(sub_aggregations) = entry.sub_aggregations.as_mut() {
-                    sub_aggregations.collect(*doc, &bucket_with_accessor.sub_aggregation)?;
-                }
-            }
-        }
-
-        if force_flush {
-            self.term_buckets
-                .force_flush(&bucket_with_accessor.sub_aggregation)?;
-        }
-        Ok(())
+        self.term_buckets
+            .collect_block(docs, bucket_with_accessor, &self.blueprint, force_flush)
     }
 }
 
@@ -474,7 +536,7 @@ mod tests {
     use crate::aggregation::metric::{AverageAggregation, StatsAggregation};
     use crate::aggregation::tests::{
         exec_request, exec_request_with_query, get_test_index_from_terms,
-        get_test_index_from_values_and_terms,
+        get_test_index_from_values_and_terms, get_test_index_with_missing_values,
     };
 
     #[test]
@@ -1272,4 +1334,74 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn terms_aggregation_missing() -> crate::Result<()> {
+        for merge_segments in [false, true] {
+            let index = get_test_index_with_missing_values(merge_segments)?;
+            let get_buckets = |missing: &str| -> crate::Result<Vec<(String, u64)>> {
+                let agg_req: Aggregations = vec![(
+                    "countries".to_string(),
+                    Aggregation::Bucket(BucketAggregation {
+                        bucket_agg: BucketAggregationType::Terms(TermsAggregation {
+                            field: "country".to_string(),
+                            missing: Some(missing.to_string()),
+                            ..Default::default()
+                        }),
+                        sub_aggregation: vec![(
+                            "avg_score".to_string(),
+                            Aggregation::Metric(MetricAggregation::Average(
+                                AverageAggregation::from_field_name("score".to_string()),
+                            )),
+                        )]
+                        .into_iter()
+                        .collect(),
+                    }),
+                )]
+                .into_iter()
+                .collect();
+                let res = exec_request(agg_req, &index)?;
+                let mut buckets: Vec<(String, u64)> = res["countries"]["buckets"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|bucket| {
+                        (
+                            bucket["key"].as_str().unwrap().to_string(),
+                            bucket["doc_count"].as_u64().unwrap(),
+                        )
+                    })
+                    .collect();
+                buckets.sort();
+                if missing == "unknown" {
+                    let unknown_bucket = res["countries"]["buckets"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .find(|bucket| bucket["key"] == "unknown")
+                        .unwrap();
+                    assert_eq!(unknown_bucket["avg_score"]["value"], 8.5);
+                }
+                Ok(buckets)
+            };
+
+            let as_buckets = |buckets: &[(&str, u64)]| {
+                buckets
+                    .iter()
+                    .map(|(key, doc_count)| (key.to_string(), *doc_count))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(
+                get_buckets("unknown")?,
+                as_buckets(&[("de", 2), ("fr", 1), ("unknown", 2), ("us", 2)])
+            );
+            // The missing documents are counted in the bucket of an existing term.
+            assert_eq!(
+                get_buckets("de")?,
+                as_buckets(&[("de", 4), ("fr", 1), ("us", 2)])
+            );
+        }
+
+        Ok(())
+    }
 }
@@ -1,17 +1,22 @@
 use std::rc::Rc;
 
-use super::agg_req::Aggregations;
+use super::agg_req::{requires_scoring, resolve_date_math_now, Aggregations};
 use super::agg_req_with_accessor::AggregationsWithAccessor;
 use super::agg_result::AggregationResults;
+use super::bucket::DocScores;
+use super::date::now_micros;
 use super::intermediate_agg_result::IntermediateAggregationResults;
 use super::segment_agg_result::{
     build_segment_agg_collector, GenericSegmentAggregationResultsCollector,
     SegmentAggregationCollector,
 };
+use super::memory_budget::MemoryAccount;
+use super::MemoryBudget;
 use crate::aggregation::agg_req_with_accessor::get_aggs_with_accessor_and_validate;
-use crate::collector::{Collector, SegmentCollector};
+use crate::collector::{collect_segment_with, Collector, SegmentCollector};
+use crate::query::Weight;
 use crate::schema::Schema;
-use crate::{SegmentReader, TantivyError};
+use crate::{Executor, SegmentOrdinal, SegmentReader, TantivyError};
 
 /// The default max bucket count, before the aggregation fails.
 pub const MAX_BUCKET_COUNT: u32 = 65000;
@@ -23,6 +28,7 @@ pub struct AggregationCollector {
     schema: Schema,
     agg: Aggregations,
     max_bucket_count: u32,
+    memory_budget: MemoryBudget,
 }
 
 impl AggregationCollector {
@@ -30,13 +36,25 @@ impl AggregationCollector {
     ///
     /// Aggregation fails when the total bucket count is higher than max_bucket_count.
     /// max_bucket_count will default to `MAX_BUCKET_COUNT` (65000) when unset
-    pub fn from_aggs(agg: Aggregations, max_bucket_count: Option<u32>, schema: Schema) -> Self {
+    pub fn from_aggs(mut agg: Aggregations, max_bucket_count: Option<u32>, schema: Schema) -> Self {
+        resolve_date_math_now(&mut agg, now_micros());
         Self {
             schema,
             agg,
             max_bucket_count: max_bucket_count.unwrap_or(MAX_BUCKET_COUNT),
+            memory_budget: MemoryBudget::default(),
         }
     }
+
+    /// Sets the memory budget of the aggregation request.
+    ///
+    /// Aggregation fails when the memory of the segment collectors exceeds the memory limit of the
+    /// budget. The memory limit defaults to `DEFAULT_MEMORY_LIMIT` (500MB). The memory used by
+    /// the last search can be read from a clone of the budget.
+    pub fn with_memory_budget(mut self, memory_budget: MemoryBudget) -> Self {
+        self.memory_budget = memory_budget;
+        self
+    }
 }
 
 /// Collector for distributed aggregations.
@@ -50,18 +68,32 @@ impl AggregationCollector {
 pub struct DistributedAggregationCollector {
     agg: Aggregations,
     max_bucket_count: u32,
+    memory_budget: MemoryBudget,
 }
 
 impl DistributedAggregationCollector {
     /// Create collector from aggregation request.
     ///
     /// max_bucket_count will default to `MAX_BUCKET_COUNT` (65000) when unset
-    pub fn from_aggs(agg: Aggregations, max_bucket_count: Option<u32>) -> Self {
+    pub fn from_aggs(mut agg: Aggregations, max_bucket_count: Option<u32>) -> Self {
+        resolve_date_math_now(&mut agg, now_micros());
         Self {
             agg,
             max_bucket_count: max_bucket_count.unwrap_or(MAX_BUCKET_COUNT),
+            memory_budget: MemoryBudget::default(),
         }
     }
+
+    /// Sets the memory budget of the aggregation request.
+    ///
+    /// Aggregation fails when the memory of the segment collectors exceeds the memory limit of the
+    /// budget. The memory limit defaults to `DEFAULT_MEMORY_LIMIT` (500MB). The memory used is
+    /// reported by [`IntermediateAggregationResults::memory_used`], and the memory used by the
+    /// last search can be read from a clone of the budget.
+    pub fn with_memory_budget(mut self, memory_budget: MemoryBudget) -> Self {
+        self.memory_budget = memory_budget;
+        self
+    }
 }
 
 impl Collector for DistributedAggregationCollector {
@@ -78,18 +110,37 @@ impl Collector for DistributedAggregationCollector {
             &self.agg,
             reader,
             self.max_bucket_count,
+            &self.memory_budget,
         )
     }
 
     fn requires_scoring(&self) -> bool {
-        false
+        requires_scoring(&self.agg)
     }
 
     fn merge_fruits(
         &self,
         segment_fruits: Vec<<Self::Child as SegmentCollector>::Fruit>,
     ) -> crate::Result<Self::Fruit> {
-        merge_fruits(segment_fruits)
+        let fruit = merge_fruits(segment_fruits)?;
+        self.memory_budget.record_memory_used(fruit.memory_used);
+        Ok(fruit)
+    }
+
+    fn collect_segments(
+        &self,
+        weight: &dyn Weight,
+        segment_readers: Vec<(SegmentOrdinal, &SegmentReader)>,
+        executor: &Executor,
+    ) -> crate::Result<Vec<<Self::Child as SegmentCollector>::Fruit>> {
+        collect_segments_with_search_memory(
+            &self.agg,
+            self.max_bucket_count,
+            &self.memory_budget,
+            weight,
+            segment_readers,
+            executor,
+        )
     }
 }
 
@@ -107,11 +158,12 @@ impl Collector for AggregationCollector {
             &self.agg,
             reader,
             self.max_bucket_count,
+            &self.memory_budget,
         )
     }
 
     fn requires_scoring(&self) -> bool {
-        false
+        requires_scoring(&self.agg)
     }
 
     fn merge_fruits(
@@ -119,8 +171,57 @@ impl Collector for AggregationCollector {
         segment_fruits: Vec<<Self::Child as SegmentCollector>::Fruit>,
     ) -> crate::Result<Self::Fruit> {
         let res = merge_fruits(segment_fruits)?;
+        self.memory_budget.record_memory_used(res.memory_used);
         res.into_final_bucket_result(self.agg.clone(), &self.schema)
     }
+
+    fn collect_segments(
+        &self,
+        weight: &dyn Weight,
+        segment_readers: Vec<(SegmentOrdinal, &SegmentReader)>,
+        executor: &Executor,
+    ) -> crate::Result<Vec<<Self::Child as SegmentCollector>::Fruit>> {
+        collect_segments_with_search_memory(
+            &self.agg,
+            self.max_bucket_count,
+            &self.memory_budget,
+            weight,
+            segment_readers,
+            executor,
+        )
+    }
+}
+
+/// Collects the segments of a search, whose segment collectors share the memory limit of
+/// `memory_budget`.
+///
+/// The segments for which [`Weight::may_match`] returns `false` are harvested without
+/// iterating over their documents. They are not skipped altogether, as the aggregations may
+/// create buckets without any document, e.g. the terms of the segment with `min_doc_count: 0`.
+fn collect_segments_with_search_memory(
+    agg: &Aggregations,
+    max_bucket_count: u32,
+    memory_budget: &MemoryBudget,
+    weight: &dyn Weight,
+    segment_readers: Vec<(SegmentOrdinal, &SegmentReader)>,
+    executor: &Executor,
+) -> crate::Result<Vec<crate::Result<IntermediateAggregationResults>>> {
+    let search_memory = memory_budget.new_search();
+    executor.map(
+        |(_segment_ord, segment_reader)| {
+            let segment_collector = AggregationSegmentCollector::from_agg_req_and_memory_account(
+                agg,
+                segment_reader,
+                max_bucket_count,
+                search_memory.new_segment(),
+            )?;
+            if !weight.may_match(segment_reader) {
+                return Ok(segment_collector.harvest());
+            }
+            collect_segment_with(segment_collector, weight, segment_reader, requires_scoring(agg))
+        },
+        segment_readers.into_iter(),
+    )
 }
 
 fn merge_fruits(
@@ -129,7 +230,7 @@ fn merge_fruits(
     if let Some(fruit) = segment_fruits.pop() {
         let mut fruit = fruit?;
         for next_fruit in segment_fruits {
-            fruit.merge_fruits(next_fruit?);
+            fruit.merge_fruits(next_fruit?)?;
         }
         Ok(fruit)
     } else {
@@ -141,23 +242,53 @@ fn merge_fruits(
 pub struct AggregationSegmentCollector {
     aggs_with_accessor: AggregationsWithAccessor,
     result: Box<dyn SegmentAggregationCollector>,
+    /// The scores of the collected documents, if the request contains a sampler aggregation.
+    doc_scores: Option<DocScores>,
+    memory_account: MemoryAccount,
     error: Option<TantivyError>,
 }
 
 impl AggregationSegmentCollector {
     /// Creates an `AggregationSegmentCollector from` an [`Aggregations`] request and a segment
     /// reader. Also includes validation, e.g. checking field types and existence.
+    ///
+    /// The memory limit of `memory_budget` applies to this segment collector on its own.
     pub fn from_agg_req_and_reader(
         agg: &Aggregations,
         reader: &SegmentReader,
         max_bucket_count: u32,
+        memory_budget: &MemoryBudget,
+    ) -> crate::Result<Self> {
+        Self::from_agg_req_and_memory_account(
+            agg,
+            reader,
+            max_bucket_count,
+            memory_budget.new_search().new_segment(),
+        )
+    }
+
+    /// Creates an `AggregationSegmentCollector`, which accounts its memory in `memory_account`.
+    pub(crate) fn from_agg_req_and_memory_account(
+        agg: &Aggregations,
+        reader: &SegmentReader,
+        max_bucket_count: u32,
+        memory_account: MemoryAccount,
     ) -> crate::Result<Self> {
-        let aggs_with_accessor =
-            get_aggs_with_accessor_and_validate(agg, reader, Rc::default(), max_bucket_count)?;
+        let doc_scores = DocScores::default();
+        let aggs_with_accessor = get_aggs_with_accessor_and_validate(
+            agg,
+            reader,
+            Rc::default(),
+            max_bucket_count,
+            &memory_account,
+            &doc_scores,
+        )?;
         let result = build_segment_agg_collector(&aggs_with_accessor)?;
         Ok(AggregationSegmentCollector {
             aggs_with_accessor,
             result,
+            doc_scores: requires_scoring(agg).then_some(doc_scores),
+            memory_account,
             error: None,
         })
     }
@@ -167,10 +298,13 @@ impl SegmentCollector for AggregationSegmentCollector {
     type Fruit = crate::Result<IntermediateAggregationResults>;
 
     #[inline]
-    fn collect(&mut self, doc: crate::DocId, _score: crate::Score) {
+    fn collect(&mut self, doc: crate::DocId, score: crate::Score) {
         if self.error.is_some() {
             return;
         }
+        if let Some(doc_scores) = &self.doc_scores {
+            doc_scores.record(doc, score);
+        }
         if let Err(err) = self.result.collect(doc, &self.aggs_with_accessor) {
             self.error = Some(err);
         }
@@ -182,7 +316,10 @@ impl SegmentCollector for AggregationSegmentCollector {
         }
         self.result
             .flush_staged_docs(&self.aggs_with_accessor, true)?;
-        self.result
-            .into_intermediate_aggregations_result(&self.aggs_with_accessor)
+        let mut res = self
+            .result
+            .into_intermediate_aggregations_result(&self.aggs_with_accessor)?;
+        res.memory_used = self.memory_account.memory_used();
+        Ok(res)
     }
 }
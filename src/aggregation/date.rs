@@ -1,9 +1,29 @@
 use time::format_description::well_known::Rfc3339;
-use time::OffsetDateTime;
+use time::{Date, Month, OffsetDateTime, UtcOffset};
 
+use crate::aggregation::bucket::CalendarInterval;
 use crate::TantivyError;
 
+pub(crate) const MICROS_PER_SECOND: i64 = 1_000_000;
+pub(crate) const MICROS_PER_DAY: i64 = 86_400 * MICROS_PER_SECOND;
+
+/// Returns the current time in microseconds.
+pub(crate) fn now_micros() -> i64 {
+    (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000) as i64
+}
+
 pub(crate) fn format_date(val: i64) -> crate::Result<String> {
+    format_date_with_offset(val, 0)
+}
+
+/// Formats the timestamp in microseconds as RFC3339 in the given UTC offset in seconds.
+pub(crate) fn format_date_with_offset(val: i64, utc_offset: i32) -> crate::Result<String> {
+    let offset = UtcOffset::from_whole_seconds(utc_offset).map_err(|err| {
+        TantivyError::InvalidArgument(format!(
+            "Could not convert {:?} to UtcOffset, err {:?}",
+            utc_offset, err
+        ))
+    })?;
     let datetime =
         OffsetDateTime::from_unix_timestamp_nanos(1_000 * (val as i128)).map_err(|err| {
             TantivyError::InvalidArgument(format!(
@@ -12,7 +32,229 @@ pub(crate) fn format_date(val: i64) -> crate::Result<String> {
             ))
         })?;
     let key_as_string = datetime
+        .to_offset(offset)
         .format(&Rfc3339)
         .map_err(|_err| TantivyError::InvalidArgument("Could not serialize date".to_string()))?;
     Ok(key_as_string)
 }
+
+/// Returns the number of days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Inverse of `days_from_civil`, returns `(year, month, day)`.
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Parses a date into a timestamp in microseconds.
+///
+/// Accepts RFC3339 dates like `2019-01-01T00:00:00Z`, plain dates like `2019-01-01` and date math
+/// expressions like `now-7d/d` or `2019-01-01||+1M/d`. A date math expression starts with an
+/// anchor, which is either `now` or a date followed by `||`, and continues with any number of
+/// operations:
+/// - `+<n><unit>` and `-<n><unit>` add or subtract `n` units.
+/// - `/<unit>` rounds down to the start of the unit, in UTC.
+///
+/// The units are `y` (years), `M` (months), `w` (weeks), `d` (days), `h` or `H` (hours), `m`
+/// (minutes) and `s` (seconds).
+pub(crate) fn parse_date_math(input: &str) -> crate::Result<i64> {
+    let (mut timestamp, mut math) = if let Some(math) = input.strip_prefix("now") {
+        (now_micros(), math)
+    } else if let Some((date, math)) = input.split_once("||") {
+        (parse_date(date)?, math)
+    } else {
+        return parse_date(input);
+    };
+    let invalid_math = || {
+        TantivyError::InvalidArgument(format!(
+            "could not parse date math {:?}, expected e.g. now-7d/d",
+            input
+        ))
+    };
+    while !math.is_empty() {
+        let (operation, rest) = math.split_at(1);
+        let num_digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let (number, rest) = rest.split_at(num_digits);
+        let mut unit_chars = rest.chars();
+        let unit = unit_chars
+            .next()
+            .and_then(DateMathUnit::from_char)
+            .ok_or_else(invalid_math)?;
+        math = unit_chars.as_str();
+        let number: i64 = if number.is_empty() {
+            1
+        } else {
+            number.parse().map_err(|_| invalid_math())?
+        };
+        timestamp = match operation {
+            "+" => unit.add(timestamp, number),
+            "-" => unit.add(timestamp, -number),
+            "/" if num_digits == 0 => unit.round_down(timestamp),
+            _ => return Err(invalid_math()),
+        }
+        .ok_or_else(invalid_math)?;
+    }
+    Ok(timestamp)
+}
+
+/// Parses a RFC3339 date or a plain date like `2019-01-01` into a timestamp in microseconds.
+fn parse_date(date: &str) -> crate::Result<i64> {
+    if let Ok(date_time) = OffsetDateTime::parse(date, &Rfc3339) {
+        return Ok((date_time.unix_timestamp_nanos() / 1_000) as i64);
+    }
+    let plain_date = || {
+        let (year, month_and_day) = date.split_once('-')?;
+        let (month, day) = month_and_day.split_once('-')?;
+        if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+            return None;
+        }
+        let month = Month::try_from(month.parse::<u8>().ok()?).ok()?;
+        Date::from_calendar_date(year.parse().ok()?, month, day.parse().ok()?).ok()
+    };
+    let date = plain_date().ok_or_else(|| {
+        TantivyError::InvalidArgument(format!(
+            "could not parse date {:?}, expected RFC3339, e.g. 2019-01-01T00:00:00Z",
+            date
+        ))
+    })?;
+    Ok(date.midnight().assume_utc().unix_timestamp() * MICROS_PER_SECOND)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DateMathUnit {
+    Year,
+    Month,
+    Week,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+impl DateMathUnit {
+    fn from_char(unit: char) -> Option<Self> {
+        match unit {
+            'y' => Some(DateMathUnit::Year),
+            'M' => Some(DateMathUnit::Month),
+            'w' => Some(DateMathUnit::Week),
+            'd' => Some(DateMathUnit::Day),
+            'h' | 'H' => Some(DateMathUnit::Hour),
+            'm' => Some(DateMathUnit::Minute),
+            's' => Some(DateMathUnit::Second),
+            _ => None,
+        }
+    }
+
+    /// Adds `number` units to the timestamp. Adding months keeps the day of the month, unless
+    /// the resulting month is shorter.
+    fn add(self, timestamp: i64, number: i64) -> Option<i64> {
+        let fixed = |unit: i64| timestamp.checked_add(number.checked_mul(unit)?);
+        match self {
+            DateMathUnit::Year => add_months(timestamp, number.checked_mul(12)?),
+            DateMathUnit::Month => add_months(timestamp, number),
+            DateMathUnit::Week => fixed(7 * MICROS_PER_DAY),
+            DateMathUnit::Day => fixed(MICROS_PER_DAY),
+            DateMathUnit::Hour => fixed(3_600 * MICROS_PER_SECOND),
+            DateMathUnit::Minute => fixed(60 * MICROS_PER_SECOND),
+            DateMathUnit::Second => fixed(MICROS_PER_SECOND),
+        }
+    }
+
+    fn round_down(self, timestamp: i64) -> Option<i64> {
+        let interval = match self {
+            DateMathUnit::Year => CalendarInterval::Year,
+            DateMathUnit::Month => CalendarInterval::Month,
+            DateMathUnit::Week => CalendarInterval::Week,
+            DateMathUnit::Day => CalendarInterval::Day,
+            DateMathUnit::Hour => CalendarInterval::Hour,
+            DateMathUnit::Minute => CalendarInterval::Minute,
+            DateMathUnit::Second => {
+                return Some(timestamp - timestamp.rem_euclid(MICROS_PER_SECOND))
+            }
+        };
+        Some(interval.round_down(timestamp))
+    }
+}
+
+fn add_months(timestamp: i64, months: i64) -> Option<i64> {
+    let days = timestamp.div_euclid(MICROS_PER_DAY);
+    let time_of_day = timestamp.rem_euclid(MICROS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    let month_index = (year * 12 + month - 1).checked_add(months)?;
+    let (year, month) = (month_index.div_euclid(12), month_index.rem_euclid(12) + 1);
+    let next_month_index = month_index + 1;
+    let days_in_month = days_from_civil(
+        next_month_index.div_euclid(12),
+        next_month_index.rem_euclid(12) + 1,
+        1,
+    ) - days_from_civil(year, month, 1);
+    let days = days_from_civil(year, month, day.min(days_in_month));
+    days.checked_mul(MICROS_PER_DAY)?.checked_add(time_of_day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> String {
+        format_date(parse_date_math(input).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_date_math() {
+        assert_eq!(parse("2019-01-01"), "2019-01-01T00:00:00Z");
+        assert_eq!(parse("2019-01-31T10:00:00+02:00"), "2019-01-31T08:00:00Z");
+        assert_eq!(parse("2019-01-31T10:00:00Z||+1M"), "2019-02-28T10:00:00Z");
+        assert_eq!(parse("2020-01-31||+1M"), "2020-02-29T00:00:00Z");
+        assert_eq!(parse("2019-03-15T10:30:00Z||-1y/M"), "2018-03-01T00:00:00Z");
+        assert_eq!(
+            parse("2019-03-15T10:30:00Z||+1d-2h/h"),
+            "2019-03-16T08:00:00Z"
+        );
+        assert_eq!(parse("2019-03-15T10:30:00Z||/w"), "2019-03-11T00:00:00Z");
+        assert_eq!(
+            parse("2019-03-15T10:30:10.5Z||/s+90s"),
+            "2019-03-15T10:31:40Z"
+        );
+
+        let now = now_micros();
+        let today = now - now.rem_euclid(MICROS_PER_DAY);
+        let week_ago = parse_date_math("now-7d/d").unwrap();
+        assert!(week_ago == today - 7 * MICROS_PER_DAY || week_ago == today - 6 * MICROS_PER_DAY);
+
+        for invalid in [
+            "2019-1-1",
+            "2019-02-30",
+            "yesterday",
+            "now+",
+            "now-7",
+            "now/7d",
+            "now*2d",
+            "now-7x",
+            "2019-01-01||+1q",
+        ] {
+            assert!(parse_date_math(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use thiserror::Error;
+
+/// Errors which abort the collection of an aggregation request.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AggregationError {
+    /// The estimated memory of the segment collectors exceeded the memory limit of the
+    /// [`MemoryBudget`](super::MemoryBudget).
+    #[error(
+        "Aborting aggregation because the memory limit was exceeded. Limit: {limit} bytes, \
+         used: {used} bytes"
+    )]
+    MemoryLimitExceeded {
+        /// The memory limit in bytes.
+        limit: u64,
+        /// The memory in bytes, which would have been used by the aggregation.
+        used: u64,
+    },
+    /// The encoded intermediate aggregation result has a format version, which is not supported
+    /// by this version of tantivy.
+    #[error(
+        "Unsupported format version {version} of the intermediate aggregation result, the \
+         maximum supported version is {max_supported}"
+    )]
+    UnsupportedFormatVersion {
+        /// The format version of the encoded result.
+        version: u16,
+        /// The maximum format version supported by this version of tantivy.
+        max_supported: u16,
+    },
+    /// The intermediate aggregation results were collected for different aggregation requests.
+    #[error("The intermediate aggregation results were collected for different requests")]
+    RequestMismatch,
+    /// The intermediate aggregation results cannot be merged or converted to the final result,
+    /// as their aggregations do not match, e.g. because an encoded result was corrupted.
+    #[error("Incompatible intermediate aggregation results: {0}")]
+    IncompatibleResults(String),
+}
@@ -0,0 +1,299 @@
+//! A small arithmetic expression language, used where elasticsearch would accept a script.
+//!
+//! Expressions operate on `f64` values and support numbers, variables, the arithmetic operators
+//! `+ - * / %`, the comparison operators `< <= > >= == !=`, the boolean operators `&& || !` and
+//! parentheses. Comparisons and boolean operators return `1.0` for true and `0.0` for false, any
+//! non-zero value is considered true.
+//!
+//! Variables are identifiers, which may contain dots, e.g. `params.total_sales`.
+
+use crate::TantivyError;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    fn apply(self, left: f64, right: f64) -> f64 {
+        let from_bool = |val: bool| if val { 1.0 } else { 0.0 };
+        match self {
+            BinaryOp::Add => left + right,
+            BinaryOp::Sub => left - right,
+            BinaryOp::Mul => left * right,
+            BinaryOp::Div => left / right,
+            BinaryOp::Rem => left % right,
+            BinaryOp::Lt => from_bool(left < right),
+            BinaryOp::Le => from_bool(left <= right),
+            BinaryOp::Gt => from_bool(left > right),
+            BinaryOp::Ge => from_bool(left >= right),
+            BinaryOp::Eq => from_bool(left == right),
+            BinaryOp::Ne => from_bool(left != right),
+            BinaryOp::And => from_bool(left != 0.0 && right != 0.0),
+            BinaryOp::Or => from_bool(left != 0.0 || right != 0.0),
+        }
+    }
+}
+
+/// A parsed expression.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Expression {
+    Number(f64),
+    Variable(String),
+    Unary(UnaryOp, Box<Expression>),
+    Binary(BinaryOp, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    /// Parses the expression.
+    pub(crate) fn parse(input: &str) -> crate::Result<Expression> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser {
+            input,
+            tokens,
+            pos: 0,
+        };
+        let expression = parser.parse_binary(0)?;
+        if parser.pos != parser.tokens.len() {
+            return Err(parser.error("unexpected token"));
+        }
+        Ok(expression)
+    }
+
+    /// Returns the names of all variables in the expression.
+    pub(crate) fn variables(&self) -> Vec<&str> {
+        let mut variables = Vec::new();
+        self.collect_variables(&mut variables);
+        variables
+    }
+
+    fn collect_variables<'a>(&'a self, variables: &mut Vec<&'a str>) {
+        match self {
+            Expression::Number(_) => {}
+            Expression::Variable(name) => variables.push(name),
+            Expression::Unary(_, expr) => expr.collect_variables(variables),
+            Expression::Binary(_, left, right) => {
+                left.collect_variables(variables);
+                right.collect_variables(variables);
+            }
+        }
+    }
+
+    /// Evaluates the expression. Returns `None` if `resolve` returns `None` for a variable.
+    pub(crate) fn eval(&self, resolve: &impl Fn(&str) -> Option<f64>) -> Option<f64> {
+        match self {
+            Expression::Number(val) => Some(*val),
+            Expression::Variable(name) => resolve(name),
+            Expression::Unary(UnaryOp::Neg, expr) => expr.eval(resolve).map(|val| -val),
+            Expression::Unary(UnaryOp::Not, expr) => {
+                expr.eval(resolve)
+                    .map(|val| if val == 0.0 { 1.0 } else { 0.0 })
+            }
+            Expression::Binary(op, left, right) => {
+                Some(op.apply(left.eval(resolve)?, right.eval(resolve)?))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(&'static str),
+    OpenParen,
+    CloseParen,
+}
+
+const OPERATORS: [&str; 15] = [
+    "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "!", "=",
+];
+
+fn tokenize(input: &str) -> crate::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+    while let Some(first) = rest.chars().next() {
+        let len = if first.is_ascii_digit() || first == '.' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            let number = rest[..len].parse().map_err(|_| {
+                TantivyError::InvalidArgument(format!(
+                    "could not parse number {:?} in expression {:?}",
+                    &rest[..len],
+                    input
+                ))
+            })?;
+            tokens.push(Token::Number(number));
+            len
+        } else if first.is_ascii_alphabetic() || first == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..len].to_string()));
+            len
+        } else if first == '(' {
+            tokens.push(Token::OpenParen);
+            1
+        } else if first == ')' {
+            tokens.push(Token::CloseParen);
+            1
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                // A single `=` is a common typo for `==`, which we don't want to accept silently.
+                .filter(|op| **op != "=")
+                .ok_or_else(|| {
+                    TantivyError::InvalidArgument(format!(
+                        "unexpected character {:?} in expression {:?}",
+                        first, input
+                    ))
+                })?;
+            tokens.push(Token::Op(op));
+            op.len()
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Returns the binding power of the binary operator, higher binds stronger.
+fn binary_op(op: &str) -> Option<(BinaryOp, u8)> {
+    let op = match op {
+        "||" => (BinaryOp::Or, 1),
+        "&&" => (BinaryOp::And, 2),
+        "==" => (BinaryOp::Eq, 3),
+        "!=" => (BinaryOp::Ne, 3),
+        "<" => (BinaryOp::Lt, 4),
+        "<=" => (BinaryOp::Le, 4),
+        ">" => (BinaryOp::Gt, 4),
+        ">=" => (BinaryOp::Ge, 4),
+        "+" => (BinaryOp::Add, 5),
+        "-" => (BinaryOp::Sub, 5),
+        "*" => (BinaryOp::Mul, 6),
+        "/" => (BinaryOp::Div, 6),
+        "%" => (BinaryOp::Rem, 6),
+        _ => return None,
+    };
+    Some(op)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, msg: &str) -> TantivyError {
+        TantivyError::InvalidArgument(format!("{} in expression {:?}", msg, self.input))
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// Parses binary operators with a binding power greater than `min_power`, which makes them
+    /// left associative.
+    fn parse_binary(&mut self, min_power: u8) -> crate::Result<Expression> {
+        let mut left = self.parse_unary()?;
+        while let Some(Token::Op(op)) = self.tokens.get(self.pos) {
+            let (op, power) = match binary_op(op) {
+                Some((op, power)) if power > min_power => (op, power),
+                _ => break,
+            };
+            self.pos += 1;
+            let right = self.parse_binary(power)?;
+            left = Expression::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> crate::Result<Expression> {
+        match self.next() {
+            Some(Token::Number(val)) => Ok(Expression::Number(val)),
+            Some(Token::Ident(name)) => Ok(Expression::Variable(name)),
+            Some(Token::Op("-")) => Ok(Expression::Unary(
+                UnaryOp::Neg,
+                Box::new(self.parse_unary()?),
+            )),
+            Some(Token::Op("!")) => Ok(Expression::Unary(
+                UnaryOp::Not,
+                Box::new(self.parse_unary()?),
+            )),
+            Some(Token::OpenParen) => {
+                let expression = self.parse_binary(0)?;
+                if self.next() != Some(Token::CloseParen) {
+                    return Err(self.error("missing closing parenthesis"));
+                }
+                Ok(expression)
+            }
+            Some(_) => Err(self.error("unexpected token")),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(input: &str) -> Option<f64> {
+        let resolve = |name: &str| match name {
+            "params.a" => Some(6.0),
+            "params.b" => Some(4.0),
+            _ => None,
+        };
+        Expression::parse(input).unwrap().eval(&resolve)
+    }
+
+    #[test]
+    fn expression_eval_test() {
+        assert_eq!(eval("1 + 2 * 3"), Some(7.0));
+        assert_eq!(eval("(1 + 2) * 3"), Some(9.0));
+        assert_eq!(eval("10 - 4 - 3"), Some(3.0));
+        assert_eq!(eval("params.a / params.b * 100"), Some(150.0));
+        assert_eq!(eval("-params.a % 4"), Some(-2.0));
+        assert_eq!(eval("params.a > 5 && params.b <= 4"), Some(1.0));
+        assert_eq!(eval("params.a < 5 || !(params.b == 4)"), Some(0.0));
+        assert_eq!(eval("params.a + params.missing"), None);
+        assert_eq!(eval("0.5 * 3"), Some(1.5));
+    }
+
+    #[test]
+    fn expression_variables_test() {
+        let expression = Expression::parse("params.a / (params.b + params.a)").unwrap();
+        assert_eq!(
+            expression.variables(),
+            vec!["params.a", "params.b", "params.a"]
+        );
+    }
+
+    #[test]
+    fn expression_parse_error_test() {
+        for input in ["", "1 +", "(1 + 2", "1 2", "a = 1", "1 $ 2", "1..2", ")"] {
+            assert!(Expression::parse(input).is_err(), "{}", input);
+        }
+    }
+}
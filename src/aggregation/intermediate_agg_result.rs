@@ -10,22 +10,35 @@ use serde::{Deserialize, Serialize};
 
 use super::agg_req::{
     Aggregations, AggregationsInternal, BucketAggregationInternal, BucketAggregationType,
-    MetricAggregation, RangeAggregation,
+    MetricAggregation,
+};
+use super::agg_result::{
+    AggregationResult, BucketResult, CompositeBucketEntry, FilterBucketEntry, GeoGridBucketEntry,
+    MultiTermsBucketEntry, RangeBucketEntry, SignificantTermBucketEntry,
 };
-use super::agg_result::{AggregationResult, BucketResult, RangeBucketEntry};
 use super::bucket::{
-    cut_off_buckets, get_agg_name_and_property, intermediate_histogram_buckets_to_final_buckets,
-    GetDocCount, Order, OrderTarget, SegmentHistogramBucketEntry, TermsAggregation,
+    auto_interval_name, auto_interval_pos, auto_interval_roundings, cut_off_buckets,
+    final_interval_pos, get_agg_name_and_property,
+    intermediate_date_histogram_buckets_to_final_buckets,
+    intermediate_histogram_buckets_to_final_buckets, parse_ip_bound, rebucket,
+    AutoDateHistogramAggregation, CompositeAggregation, CustomOrder, DateRounding,
+    GeoGridAggregation, GeoPoint, MultiTermsAggregation, Order, OrderTarget,
+    SegmentHistogramBucketEntry, SignificantTermsAggregation, TermsAggregation,
 };
 use super::metric::{
-    IntermediateAverage, IntermediateCount, IntermediateMax, IntermediateMin, IntermediateStats,
-    IntermediateSum,
+    IntermediateAverage, IntermediateCount, IntermediateExtendedStats, IntermediateMax,
+    IntermediateMin, IntermediateStats, IntermediateSum, IntermediateWeightedAverage,
+};
+use super::pipeline::{
+    apply_parent_pipelines, apply_sibling_pipelines, validate_no_parent_pipelines, PipelineBucket,
 };
 use super::segment_agg_result::SegmentMetricResultCollector;
-use super::{format_date, Key, SerializedKey, VecWithNames};
+use super::{Key, SerializedKey, VecWithNames};
 use crate::aggregation::agg_result::{AggregationResults, BucketEntries, BucketEntry};
 use crate::aggregation::bucket::TermsAggregationInternal;
-use crate::schema::Schema;
+use crate::schema::{Schema, Type};
+use crate::aggregation::AggregationError;
+use crate::TantivyError;
 
 /// Contains the intermediate aggregation result, which is optimized to be merged with other
 /// intermediate results.
@@ -35,16 +48,33 @@ pub struct IntermediateAggregationResults {
     pub(crate) metrics: Option<VecWithNames<IntermediateMetricResult>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) buckets: Option<VecWithNames<IntermediateBucketResult>>,
+    /// The estimated memory in bytes used by the segment collectors. Only set on the top level.
+    #[serde(skip_serializing_if = "is_zero", default)]
+    pub(crate) memory_used: u64,
+}
+
+fn is_zero(val: &u64) -> bool {
+    *val == 0
 }
 
 impl IntermediateAggregationResults {
+    /// Returns the estimated memory in bytes, which was used by the segment collectors to compute
+    /// the result.
+    ///
+    /// The memory of results merged via `merge_fruits` is added up.
+    pub fn memory_used(&self) -> u64 {
+        self.memory_used
+    }
+
     /// Convert intermediate result and its aggregation request to the final result.
     pub fn into_final_bucket_result(
         self,
         req: Aggregations,
         schema: &Schema,
     ) -> crate::Result<AggregationResults> {
-        self.into_final_bucket_result_internal(&(req.into()), schema)
+        let req: AggregationsInternal = req.into();
+        validate_no_parent_pipelines(&req, "the top level")?;
+        self.into_final_bucket_result_internal(&req, schema)
     }
 
     /// Convert intermediate result and its aggregation request to the final result.
@@ -77,6 +107,8 @@ impl IntermediateAggregationResults {
             add_empty_final_metrics_to_result(&mut results, &req.metrics)?;
         }
 
+        apply_sibling_pipelines(&mut results, &req.pipelines)?;
+
         Ok(AggregationResults(results))
     }
 
@@ -113,32 +145,71 @@ impl IntermediateAggregationResults {
             Some(VecWithNames::from_entries(buckets))
         };
 
-        Self { metrics, buckets }
+        Self {
+            metrics,
+            buckets,
+            memory_used: 0,
+        }
     }
 
     /// Merge another intermediate aggregation result into this result.
     ///
-    /// The order of the values need to be the same on both results. This is ensured when the same
-    /// (key values) are present on the underlying `VecWithNames` struct.
-    pub fn merge_fruits(&mut self, other: IntermediateAggregationResults) {
-        if let (Some(buckets_left), Some(buckets_right)) = (&mut self.buckets, other.buckets) {
-            for (bucket_left, bucket_right) in
-                buckets_left.values_mut().zip(buckets_right.into_values())
-            {
-                bucket_left.merge_fruits(bucket_right);
+    /// Both results need to be collected for the same request, so that they have the same
+    /// aggregations. Otherwise [`AggregationError::IncompatibleResults`] is returned, and this
+    /// result is left partially merged.
+    pub fn merge_fruits(&mut self, other: IntermediateAggregationResults) -> crate::Result<()> {
+        self.memory_used += other.memory_used;
+        // An empty result, e.g. of an index without segments, has neither buckets nor metrics.
+        match (&mut self.buckets, other.buckets) {
+            (Some(buckets_left), Some(buckets_right)) => {
+                check_same_keys(buckets_left, &buckets_right)?;
+                for (bucket_left, bucket_right) in
+                    buckets_left.values_mut().zip(buckets_right.into_values())
+                {
+                    bucket_left.merge_fruits(bucket_right)?;
+                }
             }
+            (buckets_left @ None, buckets_right) => *buckets_left = buckets_right,
+            (Some(_), None) => {}
         }
 
-        if let (Some(metrics_left), Some(metrics_right)) = (&mut self.metrics, other.metrics) {
-            for (metric_left, metric_right) in
-                metrics_left.values_mut().zip(metrics_right.into_values())
-            {
-                metric_left.merge_fruits(metric_right);
+        match (&mut self.metrics, other.metrics) {
+            (Some(metrics_left), Some(metrics_right)) => {
+                check_same_keys(metrics_left, &metrics_right)?;
+                for (metric_left, metric_right) in
+                    metrics_left.values_mut().zip(metrics_right.into_values())
+                {
+                    metric_left.merge_fruits(metric_right)?;
+                }
             }
+            (metrics_left @ None, metrics_right) => *metrics_left = metrics_right,
+            (Some(_), None) => {}
         }
+        Ok(())
     }
 }
 
+/// Checks that two results have the same aggregations, which are merged by position.
+fn check_same_keys<T: Clone>(left: &VecWithNames<T>, right: &VecWithNames<T>) -> crate::Result<()> {
+    if left.keys().eq(right.keys()) {
+        Ok(())
+    } else {
+        Err(incompatible_results(format!(
+            "aggregations {:?} and {:?}",
+            left.keys().collect_vec(),
+            right.keys().collect_vec()
+        )))
+    }
+}
+
+fn incompatible_results(reason: String) -> TantivyError {
+    AggregationError::IncompatibleResults(reason).into()
+}
+
+fn request_mismatch(name: &str) -> TantivyError {
+    incompatible_results(format!("bucket aggregation {} does not match the request", name))
+}
+
 fn convert_and_add_final_metrics_to_result(
     results: &mut FxHashMap<String, AggregationResult>,
     metrics: VecWithNames<IntermediateMetricResult>,
@@ -184,7 +255,13 @@ fn convert_and_add_final_buckets_to_result(
     req_buckets: &VecWithNames<BucketAggregationInternal>,
     schema: &Schema,
 ) -> crate::Result<()> {
-    assert_eq!(buckets.len(), req_buckets.len());
+    if !buckets.keys().eq(req_buckets.keys()) {
+        return Err(incompatible_results(format!(
+            "aggregations {:?} do not match the request {:?}",
+            buckets.keys().collect_vec(),
+            req_buckets.keys().collect_vec()
+        )));
+    }
 
     let buckets_with_request = buckets.into_iter().zip(req_buckets.values());
     for ((key, bucket), req) in buckets_with_request {
@@ -216,8 +293,12 @@ pub enum IntermediateMetricResult {
     Min(IntermediateMin),
     /// Intermediate stats result.
     Stats(IntermediateStats),
+    /// Intermediate extended stats result.
+    ExtendedStats(IntermediateExtendedStats),
     /// Intermediate sum result.
     Sum(IntermediateSum),
+    /// Intermediate weighted average result.
+    WeightedAverage(IntermediateWeightedAverage),
 }
 
 impl From<SegmentMetricResultCollector> for IntermediateMetricResult {
@@ -242,6 +323,12 @@ impl From<SegmentMetricResultCollector> for IntermediateMetricResult {
                     IntermediateMetricResult::Sum(IntermediateSum::from_collector(collector))
                 }
             },
+            SegmentMetricResultCollector::ExtendedStats(collector) => {
+                IntermediateMetricResult::ExtendedStats(collector.stats)
+            }
+            SegmentMetricResultCollector::WeightedAverage(collector) => {
+                IntermediateMetricResult::WeightedAverage(collector.weighted_average)
+            }
         }
     }
 }
@@ -260,10 +347,18 @@ impl IntermediateMetricResult {
             MetricAggregation::Stats(_) => {
                 IntermediateMetricResult::Stats(IntermediateStats::default())
             }
+            MetricAggregation::ExtendedStats(extended_stats) => {
+                IntermediateMetricResult::ExtendedStats(IntermediateExtendedStats::with_sigma(
+                    extended_stats.sigma(),
+                ))
+            }
             MetricAggregation::Sum(_) => IntermediateMetricResult::Sum(IntermediateSum::default()),
+            MetricAggregation::WeightedAverage(_) => {
+                IntermediateMetricResult::WeightedAverage(IntermediateWeightedAverage::default())
+            }
         }
     }
-    fn merge_fruits(&mut self, other: IntermediateMetricResult) {
+    fn merge_fruits(&mut self, other: IntermediateMetricResult) -> crate::Result<()> {
         match (self, other) {
             (
                 IntermediateMetricResult::Average(avg_left),
@@ -289,12 +384,42 @@ impl IntermediateMetricResult {
             ) => {
                 stats_left.merge_fruits(stats_right);
             }
+            (
+                IntermediateMetricResult::ExtendedStats(extended_stats_left),
+                IntermediateMetricResult::ExtendedStats(extended_stats_right),
+            ) => {
+                extended_stats_left.merge_fruits(extended_stats_right);
+            }
             (IntermediateMetricResult::Sum(sum_left), IntermediateMetricResult::Sum(sum_right)) => {
                 sum_left.merge_fruits(sum_right);
             }
-            _ => {
-                panic!("incompatible fruit types in tree");
+            (
+                IntermediateMetricResult::WeightedAverage(weighted_average_left),
+                IntermediateMetricResult::WeightedAverage(weighted_average_right),
+            ) => {
+                weighted_average_left.merge_fruits(weighted_average_right);
             }
+            (left, right) => {
+                return Err(incompatible_results(format!(
+                    "metric {} and metric {}",
+                    left.name(),
+                    right.name()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            IntermediateMetricResult::Average(_) => "avg",
+            IntermediateMetricResult::Count(_) => "value_count",
+            IntermediateMetricResult::Max(_) => "max",
+            IntermediateMetricResult::Min(_) => "min",
+            IntermediateMetricResult::Stats(_) => "stats",
+            IntermediateMetricResult::ExtendedStats(_) => "extended_stats",
+            IntermediateMetricResult::Sum(_) => "sum",
+            IntermediateMetricResult::WeightedAverage(_) => "weighted_avg",
         }
     }
 }
@@ -312,8 +437,22 @@ pub enum IntermediateBucketResult {
         /// The buckets
         buckets: Vec<IntermediateHistogramBucketEntry>,
     },
+    /// Auto date histogram aggregation
+    AutoDateHistogram(IntermediateAutoDateHistogramBucketResult),
     /// Term aggregation
     Terms(IntermediateTermBucketResult),
+    /// Multi terms aggregation
+    MultiTerms(IntermediateMultiTermsBucketResult),
+    /// Significant terms aggregation
+    SignificantTerms(IntermediateSignificantTermsBucketResult),
+    /// Filter aggregation, which is a single bucket.
+    Filter(IntermediateFilterBucketEntry),
+    /// Filters aggregation
+    Filters(IntermediateFiltersBucketResult),
+    /// Composite aggregation
+    Composite(IntermediateCompositeBucketResult),
+    /// Geohash grid or geotile grid aggregation
+    GeoGrid(IntermediateGeoGridBucketResult),
 }
 
 impl IntermediateBucketResult {
@@ -322,31 +461,35 @@ impl IntermediateBucketResult {
         req: &BucketAggregationInternal,
         schema: &Schema,
     ) -> crate::Result<BucketResult> {
+        let name = self.name();
         match self {
             IntermediateBucketResult::Range(range_res) => {
                 let mut buckets: Vec<RangeBucketEntry> = range_res
                     .buckets
                     .into_values()
-                    .map(|bucket| {
-                        bucket.into_final_bucket_entry(
-                            &req.sub_aggregation,
-                            schema,
-                            req.as_range()
-                                .expect("unexpected aggregation, expected histogram aggregation"),
-                        )
-                    })
+                    .map(|bucket| bucket.into_final_bucket_entry(&req.sub_aggregation, schema))
                     .collect::<crate::Result<Vec<_>>>()?;
 
-                buckets.sort_by(|left, right| {
-                    left.from
-                        .unwrap_or(f64::MIN)
-                        .total_cmp(&right.from.unwrap_or(f64::MIN))
+                let range_req = req.as_range().ok_or_else(|| request_mismatch(name))?;
+                // Json paths are not in the schema, they have no ip values.
+                let is_ip_field = schema.get_field(&range_req.field).map_or(false, |field| {
+                    schema.get_field_entry(field).field_type().value_type() == Type::IpAddr
                 });
+                if is_ip_field {
+                    // Ip ranges have no `from` value, only `from_as_string`.
+                    buckets.sort_by_cached_key(|bucket| {
+                        bucket.from_as_string.as_deref().and_then(parse_ip_bound)
+                    });
+                } else {
+                    buckets.sort_by(|left, right| {
+                        left.from
+                            .unwrap_or(f64::MIN)
+                            .total_cmp(&right.from.unwrap_or(f64::MIN))
+                    });
+                }
+                apply_parent_pipelines(&mut buckets, &req.sub_aggregation, false)?;
 
-                let is_keyed = req
-                    .as_range()
-                    .expect("unexpected aggregation, expected range aggregation")
-                    .keyed;
+                let is_keyed = range_req.keyed;
                 let buckets = if is_keyed {
                     let mut bucket_map =
                         FxHashMap::with_capacity_and_hasher(buckets.len(), Default::default());
@@ -360,19 +503,39 @@ impl IntermediateBucketResult {
                 Ok(BucketResult::Range { buckets })
             }
             IntermediateBucketResult::Histogram { buckets } => {
-                let buckets = intermediate_histogram_buckets_to_final_buckets(
-                    buckets,
-                    req.as_histogram()
-                        .expect("unexpected aggregation, expected histogram aggregation"),
-                    &req.sub_aggregation,
-                    schema,
-                )?;
+                let (mut buckets, keyed) = if let Some(date_histogram) = req.as_date_histogram() {
+                    let buckets = intermediate_date_histogram_buckets_to_final_buckets(
+                        buckets,
+                        &date_histogram.rounding()?,
+                        date_histogram.min_doc_count(),
+                        &req.sub_aggregation,
+                        schema,
+                    )?;
+                    (buckets, date_histogram.keyed)
+                } else {
+                    let histogram = req.as_histogram().ok_or_else(|| request_mismatch(name))?;
+                    let buckets = intermediate_histogram_buckets_to_final_buckets(
+                        buckets,
+                        histogram,
+                        &req.sub_aggregation,
+                        schema,
+                    )?;
+                    (buckets, histogram.keyed)
+                };
+                apply_parent_pipelines(&mut buckets, &req.sub_aggregation, true)?;
 
-                let buckets = if req.as_histogram().unwrap().keyed {
+                let buckets = if keyed {
                     let mut bucket_map =
                         FxHashMap::with_capacity_and_hasher(buckets.len(), Default::default());
                     for bucket in buckets {
-                        bucket_map.insert(bucket.key.to_string(), bucket);
+                        // Date histogram buckets are keyed by their formatted date.
+                        let key = match &bucket.key_as_string {
+                            Some(key_as_string) if req.as_date_histogram().is_some() => {
+                                key_as_string.to_string()
+                            }
+                            _ => bucket.key.to_string(),
+                        };
+                        bucket_map.insert(key, bucket);
                     }
                     BucketEntries::HashMap(bucket_map)
                 } else {
@@ -380,9 +543,61 @@ impl IntermediateBucketResult {
                 };
                 Ok(BucketResult::Histogram { buckets })
             }
+            IntermediateBucketResult::AutoDateHistogram(auto_date_histogram) => auto_date_histogram
+                .into_final_result(
+                    req.as_auto_date_histogram().ok_or_else(|| request_mismatch(name))?,
+                    &req.sub_aggregation,
+                    schema,
+                ),
             IntermediateBucketResult::Terms(terms) => terms.into_final_result(
-                req.as_term()
-                    .expect("unexpected aggregation, expected term aggregation"),
+                req.as_term().ok_or_else(|| request_mismatch(name))?,
+                &req.sub_aggregation,
+                schema,
+            ),
+            IntermediateBucketResult::MultiTerms(multi_terms) => multi_terms.into_final_result(
+                req.as_multi_terms().ok_or_else(|| request_mismatch(name))?,
+                &req.sub_aggregation,
+                schema,
+            ),
+            IntermediateBucketResult::SignificantTerms(significant_terms) => significant_terms
+                .into_final_result(
+                    req.as_significant_terms().ok_or_else(|| request_mismatch(name))?,
+                    &req.sub_aggregation,
+                    schema,
+                ),
+            IntermediateBucketResult::Filter(bucket) => {
+                let agg_name = match &req.bucket_agg {
+                    BucketAggregationType::Nested(_) => "a nested aggregation",
+                    BucketAggregationType::Sampler(_) => "a sampler aggregation",
+                    BucketAggregationType::RandomSampler(_) => "a random_sampler aggregation",
+                    _ => "a filter aggregation",
+                };
+                validate_no_parent_pipelines(&req.sub_aggregation, agg_name)?;
+                Ok(BucketResult::Filter(
+                    bucket.into_final_bucket_entry(&req.sub_aggregation, schema)?,
+                ))
+            }
+            IntermediateBucketResult::Filters(filters) => {
+                validate_no_parent_pipelines(&req.sub_aggregation, "a filters aggregation")?;
+                let buckets = filters
+                    .buckets
+                    .into_iter()
+                    .map(|(key, bucket)| {
+                        Ok((
+                            key,
+                            bucket.into_final_bucket_entry(&req.sub_aggregation, schema)?,
+                        ))
+                    })
+                    .collect::<crate::Result<_>>()?;
+                Ok(BucketResult::Filters { buckets })
+            }
+            IntermediateBucketResult::Composite(composite) => composite.into_final_result(
+                req.as_composite().ok_or_else(|| request_mismatch(name))?,
+                &req.sub_aggregation,
+                schema,
+            ),
+            IntermediateBucketResult::GeoGrid(geo_grid) => geo_grid.into_final_result(
+                req.as_geo_grid().ok_or_else(|| request_mismatch(name))?,
                 &req.sub_aggregation,
                 schema,
             ),
@@ -392,29 +607,112 @@ impl IntermediateBucketResult {
     pub(crate) fn empty_from_req(req: &BucketAggregationType) -> Self {
         match req {
             BucketAggregationType::Terms(_) => IntermediateBucketResult::Terms(Default::default()),
+            BucketAggregationType::MultiTerms(_) => {
+                IntermediateBucketResult::MultiTerms(Default::default())
+            }
+            BucketAggregationType::SignificantTerms(_) => {
+                IntermediateBucketResult::SignificantTerms(Default::default())
+            }
             BucketAggregationType::Range(_) => IntermediateBucketResult::Range(Default::default()),
-            BucketAggregationType::Histogram(_) => {
+            BucketAggregationType::Histogram(_) | BucketAggregationType::DateHistogram(_) => {
                 IntermediateBucketResult::Histogram { buckets: vec![] }
             }
+            BucketAggregationType::AutoDateHistogram(auto_date_histogram) => {
+                IntermediateBucketResult::AutoDateHistogram(
+                    IntermediateAutoDateHistogramBucketResult {
+                        buckets: vec![],
+                        interval: auto_interval_name(auto_date_histogram.min_interval_pos())
+                            .to_string(),
+                        time_zone: auto_date_histogram.time_zone.clone(),
+                    },
+                )
+            }
+            BucketAggregationType::Filter(_)
+            | BucketAggregationType::Nested(_)
+            | BucketAggregationType::Sampler(_)
+            | BucketAggregationType::RandomSampler(_) => {
+                IntermediateBucketResult::Filter(Default::default())
+            }
+            BucketAggregationType::Filters(filters) => {
+                // Like elasticsearch, we return every requested filter bucket, even if empty.
+                let buckets = filters
+                    .filters
+                    .keys()
+                    .map(String::as_str)
+                    .chain(filters.other_bucket_key())
+                    .map(|key| (key.to_string(), Default::default()))
+                    .collect();
+                IntermediateBucketResult::Filters(IntermediateFiltersBucketResult { buckets })
+            }
+            BucketAggregationType::Composite(_) => {
+                IntermediateBucketResult::Composite(Default::default())
+            }
+            BucketAggregationType::GeoHashGrid(_) | BucketAggregationType::GeoTileGrid(_) => {
+                IntermediateBucketResult::GeoGrid(Default::default())
+            }
         }
     }
-    fn merge_fruits(&mut self, other: IntermediateBucketResult) {
+    fn merge_fruits(&mut self, other: IntermediateBucketResult) -> crate::Result<()> {
         match (self, other) {
             (
                 IntermediateBucketResult::Terms(term_res_left),
                 IntermediateBucketResult::Terms(term_res_right),
             ) => {
-                merge_maps(&mut term_res_left.entries, term_res_right.entries);
+                merge_maps(&mut term_res_left.entries, term_res_right.entries)?;
                 term_res_left.sum_other_doc_count += term_res_right.sum_other_doc_count;
                 term_res_left.doc_count_error_upper_bound +=
                     term_res_right.doc_count_error_upper_bound;
             }
+            (
+                IntermediateBucketResult::MultiTerms(multi_terms_left),
+                IntermediateBucketResult::MultiTerms(multi_terms_right),
+            ) => {
+                merge_maps(&mut multi_terms_left.buckets, multi_terms_right.buckets)?;
+                multi_terms_left.sum_other_doc_count += multi_terms_right.sum_other_doc_count;
+                multi_terms_left.doc_count_error_upper_bound +=
+                    multi_terms_right.doc_count_error_upper_bound;
+            }
+            (
+                IntermediateBucketResult::SignificantTerms(significant_terms_left),
+                IntermediateBucketResult::SignificantTerms(significant_terms_right),
+            ) => {
+                merge_maps(
+                    &mut significant_terms_left.buckets,
+                    significant_terms_right.buckets,
+                )?;
+                significant_terms_left.subset_size += significant_terms_right.subset_size;
+                significant_terms_left.superset_size += significant_terms_right.superset_size;
+            }
 
             (
                 IntermediateBucketResult::Range(range_res_left),
                 IntermediateBucketResult::Range(range_res_right),
             ) => {
-                merge_maps(&mut range_res_left.buckets, range_res_right.buckets);
+                merge_maps(&mut range_res_left.buckets, range_res_right.buckets)?;
+            }
+            (
+                IntermediateBucketResult::Filter(filter_left),
+                IntermediateBucketResult::Filter(filter_right),
+            ) => {
+                filter_left.merge_fruits(filter_right)?;
+            }
+            (
+                IntermediateBucketResult::Filters(filters_left),
+                IntermediateBucketResult::Filters(filters_right),
+            ) => {
+                merge_maps(&mut filters_left.buckets, filters_right.buckets)?;
+            }
+            (
+                IntermediateBucketResult::Composite(composite_left),
+                IntermediateBucketResult::Composite(composite_right),
+            ) => {
+                merge_maps(&mut composite_left.buckets, composite_right.buckets)?;
+            }
+            (
+                IntermediateBucketResult::GeoGrid(geo_grid_left),
+                IntermediateBucketResult::GeoGrid(geo_grid_right),
+            ) => {
+                merge_maps(&mut geo_grid_left.buckets, geo_grid_right.buckets)?;
             }
             (
                 IntermediateBucketResult::Histogram {
@@ -426,33 +724,161 @@ impl IntermediateBucketResult {
                     ..
                 },
             ) => {
-                let buckets = buckets_left
-                    .drain(..)
-                    .merge_join_by(buckets_right.into_iter(), |left, right| {
-                        left.key.partial_cmp(&right.key).unwrap_or(Ordering::Equal)
-                    })
-                    .map(|either| match either {
-                        itertools::EitherOrBoth::Both(mut left, right) => {
-                            left.merge_fruits(right);
-                            left
-                        }
-                        itertools::EitherOrBoth::Left(left) => left,
-                        itertools::EitherOrBoth::Right(right) => right,
-                    })
-                    .collect();
-
-                *buckets_left = buckets;
+                merge_histogram_buckets(buckets_left, buckets_right)?;
             }
-            (IntermediateBucketResult::Range(_), _) => {
-                panic!("try merge on different types")
+            (
+                IntermediateBucketResult::AutoDateHistogram(auto_date_histogram_left),
+                IntermediateBucketResult::AutoDateHistogram(auto_date_histogram_right),
+            ) => {
+                auto_date_histogram_left.merge_fruits(auto_date_histogram_right)?;
             }
-            (IntermediateBucketResult::Histogram { .. }, _) => {
-                panic!("try merge on different types")
+            (left, right) => {
+                return Err(incompatible_results(format!(
+                    "bucket aggregation {} and bucket aggregation {}",
+                    left.name(),
+                    right.name()
+                )));
             }
-            (IntermediateBucketResult::Terms { .. }, _) => {
-                panic!("try merge on different types")
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            IntermediateBucketResult::Range(_) => "range",
+            IntermediateBucketResult::Histogram { .. } => "histogram",
+            IntermediateBucketResult::AutoDateHistogram(_) => "auto_date_histogram",
+            IntermediateBucketResult::Terms(_) => "terms",
+            IntermediateBucketResult::MultiTerms(_) => "multi_terms",
+            IntermediateBucketResult::SignificantTerms(_) => "significant_terms",
+            IntermediateBucketResult::Filter(_) => "filter",
+            IntermediateBucketResult::Filters(_) => "filters",
+            IntermediateBucketResult::Composite(_) => "composite",
+            IntermediateBucketResult::GeoGrid(_) => "geo grid",
+        }
+    }
+}
+
+/// Merges the histogram buckets sorted by key into the buckets on the left.
+fn merge_histogram_buckets(
+    buckets_left: &mut Vec<IntermediateHistogramBucketEntry>,
+    buckets_right: Vec<IntermediateHistogramBucketEntry>,
+) -> crate::Result<()> {
+    let buckets = buckets_left
+        .drain(..)
+        .merge_join_by(buckets_right.into_iter(), |left, right| {
+            left.key.partial_cmp(&right.key).unwrap_or(Ordering::Equal)
+        })
+        .map(|either| match either {
+            itertools::EitherOrBoth::Both(mut left, right) => {
+                left.merge_fruits(right)?;
+                Ok(left)
+            }
+            itertools::EitherOrBoth::Left(left) => Ok(left),
+            itertools::EitherOrBoth::Right(right) => Ok(right),
+        })
+        .collect::<crate::Result<_>>()?;
+
+    *buckets_left = buckets;
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// Auto date histogram aggregation, with the buckets of the interval of the segment with the
+/// coarsest interval
+pub struct IntermediateAutoDateHistogramBucketResult {
+    /// The buckets sorted by key.
+    pub(crate) buckets: Vec<IntermediateHistogramBucketEntry>,
+    /// The name of the interval of the buckets, like `1d`.
+    pub(crate) interval: String,
+    /// The time zone of the request, to round the buckets into a coarser interval.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) time_zone: Option<String>,
+}
+
+impl IntermediateAutoDateHistogramBucketResult {
+    fn interval_pos(&self) -> crate::Result<usize> {
+        auto_interval_pos(&self.interval).ok_or_else(|| {
+            TantivyError::InvalidArgument(format!(
+                "unknown interval {:?} of auto_date_histogram",
+                self.interval
+            ))
+        })
+    }
+
+    /// Rounds the buckets into the coarser interval at `interval_pos`.
+    fn increase_interval(
+        &mut self,
+        interval_pos: usize,
+        roundings: &[DateRounding],
+    ) -> crate::Result<()> {
+        let buckets = std::mem::take(&mut self.buckets);
+        self.buckets = rebucket(buckets, &roundings[interval_pos])?;
+        self.interval = auto_interval_name(interval_pos).to_string();
+        Ok(())
+    }
+
+    pub(crate) fn into_final_result(
+        mut self,
+        req: &AutoDateHistogramAggregation,
+        sub_aggregation_req: &AggregationsInternal,
+        schema: &Schema,
+    ) -> crate::Result<BucketResult> {
+        let roundings = auto_interval_roundings(req.time_zone.as_deref())?;
+        let interval_pos = final_interval_pos(
+            &self.buckets,
+            self.interval_pos()?,
+            req.target_buckets() as usize,
+            &roundings,
+        );
+        self.increase_interval(interval_pos, &roundings)?;
+        let mut buckets = intermediate_date_histogram_buckets_to_final_buckets(
+            self.buckets,
+            &roundings[interval_pos],
+            0,
+            sub_aggregation_req,
+            schema,
+        )?;
+        apply_parent_pipelines(&mut buckets, sub_aggregation_req, true)?;
+
+        let buckets = if req.keyed {
+            let mut bucket_map =
+                FxHashMap::with_capacity_and_hasher(buckets.len(), Default::default());
+            for bucket in buckets {
+                let key = match &bucket.key_as_string {
+                    Some(key_as_string) => key_as_string.to_string(),
+                    None => bucket.key.to_string(),
+                };
+                bucket_map.insert(key, bucket);
+            }
+            BucketEntries::HashMap(bucket_map)
+        } else {
+            BucketEntries::Vec(buckets)
+        };
+        Ok(BucketResult::AutoDateHistogram {
+            buckets,
+            interval: self.interval,
+        })
+    }
+}
+
+impl MergeFruits for IntermediateAutoDateHistogramBucketResult {
+    fn merge_fruits(
+        &mut self,
+        mut other: IntermediateAutoDateHistogramBucketResult,
+    ) -> crate::Result<()> {
+        let left_pos = self.interval_pos()?;
+        let right_pos = other.interval_pos()?;
+        if left_pos != right_pos {
+            // The finer buckets nest into the buckets of the coarser interval.
+            let roundings = auto_interval_roundings(self.time_zone.as_deref())?;
+            if left_pos < right_pos {
+                self.increase_interval(right_pos, &roundings)?;
+            } else {
+                other.increase_interval(left_pos, &roundings)?;
             }
         }
+        merge_histogram_buckets(&mut self.buckets, other.buckets)
     }
 }
 
@@ -462,6 +888,75 @@ pub struct IntermediateRangeBucketResult {
     pub(crate) buckets: FxHashMap<SerializedKey, IntermediateRangeBucketEntry>,
 }
 
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// Filters aggregation, with one bucket per filter name
+pub struct IntermediateFiltersBucketResult {
+    pub(crate) buckets: FxHashMap<SerializedKey, IntermediateFilterBucketEntry>,
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// Composite aggregation, with the first `size` buckets of every segment
+pub struct IntermediateCompositeBucketResult {
+    pub(crate) buckets: FxHashMap<SerializedKey, IntermediateCompositeBucketEntry>,
+}
+
+impl IntermediateCompositeBucketResult {
+    pub(crate) fn into_final_result(
+        self,
+        req: &CompositeAggregation,
+        sub_aggregation_req: &AggregationsInternal,
+        schema: &Schema,
+    ) -> crate::Result<BucketResult> {
+        let mut entries: Vec<IntermediateCompositeBucketEntry> =
+            self.buckets.into_values().collect();
+        entries.sort_by(|left, right| req.cmp_keys(&left.key, &right.key));
+        entries.truncate(req.size());
+
+        let mut buckets: Vec<CompositeBucketEntry> = entries
+            .into_iter()
+            .map(|entry| entry.into_final_bucket_entry(req, sub_aggregation_req, schema))
+            .collect::<crate::Result<_>>()?;
+        // The after key is taken before pipeline aggregations, so that the next page starts
+        // after all buckets of this page.
+        let after_key = buckets.last().map(|bucket| bucket.key.clone());
+        apply_parent_pipelines(&mut buckets, sub_aggregation_req, false)?;
+        Ok(BucketResult::Composite { after_key, buckets })
+    }
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// Geohash grid or geotile grid aggregation, with the top cells of every segment
+pub struct IntermediateGeoGridBucketResult {
+    pub(crate) buckets: FxHashMap<SerializedKey, IntermediateGeoGridBucketEntry>,
+}
+
+impl IntermediateGeoGridBucketResult {
+    pub(crate) fn into_final_result(
+        self,
+        req: &GeoGridAggregation,
+        sub_aggregation_req: &AggregationsInternal,
+        schema: &Schema,
+    ) -> crate::Result<BucketResult> {
+        let mut entries: Vec<(SerializedKey, IntermediateGeoGridBucketEntry)> =
+            self.buckets.into_iter().collect();
+        entries.sort_by(|left, right| {
+            right
+                .1
+                .doc_count
+                .cmp(&left.1.doc_count)
+                .then_with(|| left.0.cmp(&right.0))
+        });
+        entries.truncate(req.size() as usize);
+
+        let mut buckets: Vec<GeoGridBucketEntry> = entries
+            .into_iter()
+            .map(|(key, entry)| entry.into_final_bucket_entry(key, sub_aggregation_req, schema))
+            .collect::<crate::Result<_>>()?;
+        apply_parent_pipelines(&mut buckets, sub_aggregation_req, false)?;
+        Ok(BucketResult::GeoGrid { buckets })
+    }
+}
+
 #[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
 /// Term aggregation including error counts
 pub struct IntermediateTermBucketResult {
@@ -478,7 +973,7 @@ impl IntermediateTermBucketResult {
         schema: &Schema,
     ) -> crate::Result<BucketResult> {
         let req = TermsAggregationInternal::from_req(req);
-        let mut buckets: Vec<BucketEntry> = self
+        let buckets: Vec<BucketEntry> = self
             .entries
             .into_iter()
             .filter(|bucket| bucket.1.doc_count >= req.min_doc_count)
@@ -494,54 +989,18 @@ impl IntermediateTermBucketResult {
             })
             .collect::<crate::Result<_>>()?;
 
-        let order = req.order.order;
-        match req.order.target {
-            OrderTarget::Key => {
-                buckets.sort_by(|left, right| {
-                    if req.order.order == Order::Asc {
-                        left.key.partial_cmp(&right.key)
-                    } else {
-                        right.key.partial_cmp(&left.key)
-                    }
-                    .expect("expected type string, which is always sortable")
-                });
-            }
-            OrderTarget::Count => {
-                if req.order.order == Order::Desc {
-                    buckets.sort_unstable_by_key(|bucket| std::cmp::Reverse(bucket.doc_count()));
-                } else {
-                    buckets.sort_unstable_by_key(|bucket| bucket.doc_count());
-                }
-            }
-            OrderTarget::SubAggregation(name) => {
-                let (agg_name, agg_property) = get_agg_name_and_property(&name);
-                let mut buckets_with_val = buckets
-                    .into_iter()
-                    .map(|bucket| {
-                        let val = bucket
-                            .sub_aggregation
-                            .get_value_from_aggregation(agg_name, agg_property)?
-                            .unwrap_or(f64::NAN);
-                        Ok((bucket, val))
-                    })
-                    .collect::<crate::Result<Vec<_>>>()?;
-
-                buckets_with_val.sort_by(|(_, val1), (_, val2)| match &order {
-                    Order::Desc => val2.total_cmp(val1),
-                    Order::Asc => val1.total_cmp(val2),
-                });
-                buckets = buckets_with_val
-                    .into_iter()
-                    .map(|(bucket, _val)| bucket)
-                    .collect_vec();
-            }
-        }
+        let buckets = sort_term_buckets(buckets, &req.order, |left, right| {
+            left.key
+                .partial_cmp(&right.key)
+                .expect("expected type string, which is always sortable")
+        })?;
 
         // We ignore _term_doc_count_before_cutoff here, because it increases the upperbound error
         // only for terms that didn't make it into the top N.
         //
         // This can be interesting, as a value of quality of the results, but not good to check the
         // actual error count for the returned terms.
+        let mut buckets = buckets;
         let (_term_doc_count_before_cutoff, sum_other_doc_count) =
             cut_off_buckets(&mut buckets, req.size as usize);
 
@@ -551,6 +1010,8 @@ impl IntermediateTermBucketResult {
             None
         };
 
+        apply_parent_pipelines(&mut buckets, sub_aggregation_req, false)?;
+
         Ok(BucketResult::Terms {
             buckets,
             sum_other_doc_count: self.sum_other_doc_count + sum_other_doc_count,
@@ -559,23 +1020,181 @@ impl IntermediateTermBucketResult {
     }
 }
 
-trait MergeFruits {
-    fn merge_fruits(&mut self, other: Self);
+/// Sorts the buckets of a terms or multi_terms aggregation in the requested order. `cmp_keys`
+/// compares the keys of two buckets in ascending order.
+fn sort_term_buckets<B: PipelineBucket>(
+    mut buckets: Vec<B>,
+    order: &CustomOrder,
+    cmp_keys: impl Fn(&B, &B) -> Ordering,
+) -> crate::Result<Vec<B>> {
+    match &order.target {
+        OrderTarget::Key => {
+            buckets.sort_by(|left, right| {
+                if order.order == Order::Asc {
+                    cmp_keys(left, right)
+                } else {
+                    cmp_keys(right, left)
+                }
+            });
+        }
+        OrderTarget::Count => {
+            if order.order == Order::Desc {
+                buckets.sort_unstable_by_key(|bucket| std::cmp::Reverse(bucket.doc_count()));
+            } else {
+                buckets.sort_unstable_by_key(|bucket| bucket.doc_count());
+            }
+        }
+        OrderTarget::SubAggregation(name) => {
+            let (agg_name, agg_property) = get_agg_name_and_property(name);
+            let mut buckets_with_val = buckets
+                .into_iter()
+                .map(|bucket| {
+                    let val = bucket
+                        .sub_aggregation()
+                        .get_value_from_aggregation(agg_name, agg_property)?
+                        .unwrap_or(f64::NAN);
+                    Ok((bucket, val))
+                })
+                .collect::<crate::Result<Vec<_>>>()?;
+
+            buckets_with_val.sort_by(|(_, val1), (_, val2)| match &order.order {
+                Order::Desc => val2.total_cmp(val1),
+                Order::Asc => val1.total_cmp(val2),
+            });
+            buckets = buckets_with_val
+                .into_iter()
+                .map(|(bucket, _val)| bucket)
+                .collect_vec();
+        }
+    }
+    Ok(buckets)
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// Multi terms aggregation including error counts
+pub struct IntermediateMultiTermsBucketResult {
+    pub(crate) buckets: FxHashMap<SerializedKey, IntermediateMultiTermsBucketEntry>,
+    pub(crate) sum_other_doc_count: u64,
+    pub(crate) doc_count_error_upper_bound: u64,
+}
+
+impl IntermediateMultiTermsBucketResult {
+    pub(crate) fn into_final_result(
+        self,
+        req: &MultiTermsAggregation,
+        sub_aggregation_req: &AggregationsInternal,
+        schema: &Schema,
+    ) -> crate::Result<BucketResult> {
+        let req = req.terms_internal();
+        let buckets: Vec<MultiTermsBucketEntry> = self
+            .buckets
+            .into_values()
+            .filter(|bucket| bucket.doc_count >= req.min_doc_count)
+            .map(|bucket| bucket.into_final_bucket_entry(sub_aggregation_req, schema))
+            .collect::<crate::Result<_>>()?;
+
+        let mut buckets = sort_term_buckets(buckets, &req.order, |left, right| {
+            left.key.partial_cmp(&right.key).unwrap_or(Ordering::Equal)
+        })?;
+
+        let (_term_doc_count_before_cutoff, sum_other_doc_count) =
+            cut_off_buckets(&mut buckets, req.size as usize);
+
+        let doc_count_error_upper_bound = if req.show_term_doc_count_error {
+            Some(self.doc_count_error_upper_bound)
+        } else {
+            None
+        };
+
+        apply_parent_pipelines(&mut buckets, sub_aggregation_req, false)?;
+
+        Ok(BucketResult::MultiTerms {
+            buckets,
+            sum_other_doc_count: self.sum_other_doc_count + sum_other_doc_count,
+            doc_count_error_upper_bound,
+        })
+    }
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// Significant terms aggregation, with the most significant terms of every segment
+pub struct IntermediateSignificantTermsBucketResult {
+    pub(crate) buckets: FxHashMap<SerializedKey, IntermediateSignificantTermBucketEntry>,
+    /// The number of documents in the foreground set.
+    pub(crate) subset_size: u64,
+    /// The number of documents in the background set.
+    pub(crate) superset_size: u64,
+}
+
+impl IntermediateSignificantTermsBucketResult {
+    pub(crate) fn into_final_result(
+        self,
+        req: &SignificantTermsAggregation,
+        sub_aggregation_req: &AggregationsInternal,
+        schema: &Schema,
+    ) -> crate::Result<BucketResult> {
+        let heuristic = req.heuristic();
+        let mut buckets: Vec<SignificantTermBucketEntry> = self
+            .buckets
+            .into_iter()
+            .filter(|(_key, bucket)| bucket.doc_count >= req.min_doc_count())
+            .map(|(key, bucket)| {
+                let score = heuristic.score(
+                    bucket.doc_count,
+                    self.subset_size,
+                    bucket.bg_count,
+                    self.superset_size,
+                );
+                Ok(SignificantTermBucketEntry {
+                    key: Key::Str(key),
+                    doc_count: bucket.doc_count,
+                    bg_count: bucket.bg_count,
+                    score,
+                    sub_aggregation: bucket
+                        .sub_aggregation
+                        .into_final_bucket_result_internal(sub_aggregation_req, schema)?,
+                })
+            })
+            .collect::<crate::Result<_>>()?;
+        // Like elasticsearch, only terms with a positive score are significant.
+        buckets.retain(|bucket| bucket.score > 0.0);
+
+        buckets.sort_by(|left, right| {
+            right
+                .score
+                .total_cmp(&left.score)
+                .then_with(|| left.key.partial_cmp(&right.key).unwrap_or(Ordering::Equal))
+        });
+        buckets.truncate(req.size());
+
+        apply_parent_pipelines(&mut buckets, sub_aggregation_req, false)?;
+
+        Ok(BucketResult::SignificantTerms {
+            doc_count: self.subset_size,
+            bg_count: self.superset_size,
+            buckets,
+        })
+    }
+}
+
+pub(crate) trait MergeFruits {
+    fn merge_fruits(&mut self, other: Self) -> crate::Result<()>;
 }
 
 fn merge_maps<V: MergeFruits + Clone>(
     entries_left: &mut FxHashMap<SerializedKey, V>,
     mut entries_right: FxHashMap<SerializedKey, V>,
-) {
+) -> crate::Result<()> {
     for (name, entry_left) in entries_left.iter_mut() {
         if let Some(entry_right) = entries_right.remove(name) {
-            entry_left.merge_fruits(entry_right);
+            entry_left.merge_fruits(entry_right)?;
         }
     }
 
     for (key, res) in entries_right.into_iter() {
         entries_left.entry(key).or_insert(res);
     }
+    Ok(())
 }
 
 /// This is the histogram entry for a bucket, which contains a key, count, and optionally
@@ -633,6 +1252,12 @@ pub struct IntermediateRangeBucketEntry {
     /// The to range of the bucket. Equals `f64::MAX` when `None`.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub to: Option<f64>,
+    /// The from range of the bucket as string on date and ip fields.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub from_as_string: Option<String>,
+    /// The to range of the bucket as string on date and ip fields.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub to_as_string: Option<String>,
 }
 
 impl IntermediateRangeBucketEntry {
@@ -640,9 +1265,8 @@ impl IntermediateRangeBucketEntry {
         self,
         req: &AggregationsInternal,
         schema: &Schema,
-        range_req: &RangeAggregation,
     ) -> crate::Result<RangeBucketEntry> {
-        let mut range_bucket_entry = RangeBucketEntry {
+        Ok(RangeBucketEntry {
             key: self.key,
             doc_count: self.doc_count,
             sub_aggregation: self
@@ -650,25 +1274,9 @@ impl IntermediateRangeBucketEntry {
                 .into_final_bucket_result_internal(req, schema)?,
             to: self.to,
             from: self.from,
-            to_as_string: None,
-            from_as_string: None,
-        };
-
-        // If we have a date type on the histogram buckets, we add the `key_as_string` field as
-        // rfc339
-        let field = schema.get_field(&range_req.field)?;
-        if schema.get_field_entry(field).field_type().is_date() {
-            if let Some(val) = range_bucket_entry.to {
-                let key_as_string = format_date(val as i64)?;
-                range_bucket_entry.to_as_string = Some(key_as_string);
-            }
-            if let Some(val) = range_bucket_entry.from {
-                let key_as_string = format_date(val as i64)?;
-                range_bucket_entry.from_as_string = Some(key_as_string);
-            }
-        }
-
-        Ok(range_bucket_entry)
+            to_as_string: self.to_as_string,
+            from_as_string: self.from_as_string,
+        })
     }
 }
 
@@ -682,24 +1290,203 @@ pub struct IntermediateTermBucketEntry {
     pub sub_aggregation: IntermediateAggregationResults,
 }
 
+/// This is the filter entry for a bucket, which contains a count, and optionally
+/// sub_aggregations.
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IntermediateFilterBucketEntry {
+    /// The number of documents in the bucket.
+    pub doc_count: u64,
+    /// The sub_aggregation in this bucket.
+    pub sub_aggregation: IntermediateAggregationResults,
+}
+
+impl IntermediateFilterBucketEntry {
+    pub(crate) fn into_final_bucket_entry(
+        self,
+        req: &AggregationsInternal,
+        schema: &Schema,
+    ) -> crate::Result<FilterBucketEntry> {
+        Ok(FilterBucketEntry {
+            doc_count: self.doc_count,
+            sub_aggregation: self
+                .sub_aggregation
+                .into_final_bucket_result_internal(req, schema)?,
+        })
+    }
+}
+
+/// This is the composite entry for a bucket, which contains a key with one value per source, a
+/// count, and optionally sub_aggregations.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IntermediateCompositeBucketEntry {
+    /// The key of the bucket, in the order of the sources.
+    pub key: Vec<Key>,
+    /// The number of documents in the bucket.
+    pub doc_count: u64,
+    /// The sub_aggregation in this bucket.
+    pub sub_aggregation: IntermediateAggregationResults,
+}
+
+impl IntermediateCompositeBucketEntry {
+    pub(crate) fn into_final_bucket_entry(
+        self,
+        composite_req: &CompositeAggregation,
+        req: &AggregationsInternal,
+        schema: &Schema,
+    ) -> crate::Result<CompositeBucketEntry> {
+        let key = composite_req
+            .sources
+            .iter()
+            .map(|source| source.name.to_string())
+            .zip(self.key)
+            .collect();
+        Ok(CompositeBucketEntry {
+            key,
+            doc_count: self.doc_count,
+            sub_aggregation: self
+                .sub_aggregation
+                .into_final_bucket_result_internal(req, schema)?,
+        })
+    }
+}
+
+/// This is the geo grid entry for a bucket, which contains a count, the sums of the coordinates of
+/// the points in the cell, and optionally sub_aggregations.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IntermediateGeoGridBucketEntry {
+    /// The number of documents in the bucket.
+    pub doc_count: u64,
+    /// The number of points in the bucket.
+    pub point_count: u64,
+    /// The sum of the latitudes of the points.
+    pub lat_sum: f64,
+    /// The sum of the longitudes of the points.
+    pub lon_sum: f64,
+    /// The sub_aggregation in this bucket.
+    pub sub_aggregation: IntermediateAggregationResults,
+}
+
+impl IntermediateGeoGridBucketEntry {
+    pub(crate) fn into_final_bucket_entry(
+        self,
+        key: SerializedKey,
+        req: &AggregationsInternal,
+        schema: &Schema,
+    ) -> crate::Result<GeoGridBucketEntry> {
+        let point_count = self.point_count.max(1) as f64;
+        Ok(GeoGridBucketEntry {
+            key,
+            doc_count: self.doc_count,
+            centroid: GeoPoint {
+                lat: self.lat_sum / point_count,
+                lon: self.lon_sum / point_count,
+            },
+            sub_aggregation: self
+                .sub_aggregation
+                .into_final_bucket_result_internal(req, schema)?,
+        })
+    }
+}
+
+/// This is the multi terms entry for a bucket, which contains a key with one term per field, a
+/// count, and optionally sub_aggregations.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IntermediateMultiTermsBucketEntry {
+    /// The key of the bucket, in the order of the fields.
+    pub key: Vec<Key>,
+    /// The number of documents in the bucket.
+    pub doc_count: u64,
+    /// The sub_aggregation in this bucket.
+    pub sub_aggregation: IntermediateAggregationResults,
+}
+
+impl IntermediateMultiTermsBucketEntry {
+    pub(crate) fn into_final_bucket_entry(
+        self,
+        req: &AggregationsInternal,
+        schema: &Schema,
+    ) -> crate::Result<MultiTermsBucketEntry> {
+        let key_as_string = self.key.iter().map(|key| key.to_string()).join("|");
+        Ok(MultiTermsBucketEntry {
+            key: self.key,
+            key_as_string,
+            doc_count: self.doc_count,
+            sub_aggregation: self
+                .sub_aggregation
+                .into_final_bucket_result_internal(req, schema)?,
+        })
+    }
+}
+
+/// This is the significant terms entry for a bucket, which contains the foreground and background
+/// counts, and optionally sub_aggregations.
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IntermediateSignificantTermBucketEntry {
+    /// The number of documents of the foreground set in the bucket.
+    pub doc_count: u64,
+    /// The number of documents of the background set in the bucket.
+    pub bg_count: u64,
+    /// The sub_aggregation in this bucket.
+    pub sub_aggregation: IntermediateAggregationResults,
+}
+
+impl MergeFruits for IntermediateSignificantTermBucketEntry {
+    fn merge_fruits(&mut self, other: IntermediateSignificantTermBucketEntry) -> crate::Result<()> {
+        self.doc_count += other.doc_count;
+        self.bg_count += other.bg_count;
+        self.sub_aggregation.merge_fruits(other.sub_aggregation)
+    }
+}
+
+impl MergeFruits for IntermediateMultiTermsBucketEntry {
+    fn merge_fruits(&mut self, other: IntermediateMultiTermsBucketEntry) -> crate::Result<()> {
+        self.doc_count += other.doc_count;
+        self.sub_aggregation.merge_fruits(other.sub_aggregation)
+    }
+}
+
+impl MergeFruits for IntermediateGeoGridBucketEntry {
+    fn merge_fruits(&mut self, other: IntermediateGeoGridBucketEntry) -> crate::Result<()> {
+        self.doc_count += other.doc_count;
+        self.point_count += other.point_count;
+        self.lat_sum += other.lat_sum;
+        self.lon_sum += other.lon_sum;
+        self.sub_aggregation.merge_fruits(other.sub_aggregation)
+    }
+}
+
+impl MergeFruits for IntermediateCompositeBucketEntry {
+    fn merge_fruits(&mut self, other: IntermediateCompositeBucketEntry) -> crate::Result<()> {
+        self.doc_count += other.doc_count;
+        self.sub_aggregation.merge_fruits(other.sub_aggregation)
+    }
+}
+
+impl MergeFruits for IntermediateFilterBucketEntry {
+    fn merge_fruits(&mut self, other: IntermediateFilterBucketEntry) -> crate::Result<()> {
+        self.doc_count += other.doc_count;
+        self.sub_aggregation.merge_fruits(other.sub_aggregation)
+    }
+}
+
 impl MergeFruits for IntermediateTermBucketEntry {
-    fn merge_fruits(&mut self, other: IntermediateTermBucketEntry) {
+    fn merge_fruits(&mut self, other: IntermediateTermBucketEntry) -> crate::Result<()> {
         self.doc_count += other.doc_count;
-        self.sub_aggregation.merge_fruits(other.sub_aggregation);
+        self.sub_aggregation.merge_fruits(other.sub_aggregation)
     }
 }
 
 impl MergeFruits for IntermediateRangeBucketEntry {
-    fn merge_fruits(&mut self, other: IntermediateRangeBucketEntry) {
+    fn merge_fruits(&mut self, other: IntermediateRangeBucketEntry) -> crate::Result<()> {
         self.doc_count += other.doc_count;
-        self.sub_aggregation.merge_fruits(other.sub_aggregation);
+        self.sub_aggregation.merge_fruits(other.sub_aggregation)
     }
 }
 
 impl MergeFruits for IntermediateHistogramBucketEntry {
-    fn merge_fruits(&mut self, other: IntermediateHistogramBucketEntry) {
+    fn merge_fruits(&mut self, other: IntermediateHistogramBucketEntry) -> crate::Result<()> {
         self.doc_count += other.doc_count;
-        self.sub_aggregation.merge_fruits(other.sub_aggregation);
+        self.sub_aggregation.merge_fruits(other.sub_aggregation)
     }
 }
 
@@ -723,6 +1510,8 @@ mod tests {
                     sub_aggregation: Default::default(),
                     from: None,
                     to: None,
+                    from_as_string: None,
+                    to_as_string: None,
                 },
             );
         }
@@ -733,6 +1522,7 @@ mod tests {
         IntermediateAggregationResults {
             buckets: Some(VecWithNames::from_entries(map.into_iter().collect())),
             metrics: Default::default(),
+            memory_used: 0,
         }
     }
 
@@ -749,6 +1539,8 @@ mod tests {
                     doc_count: *doc_count,
                     from: None,
                     to: None,
+                    from_as_string: None,
+                    to_as_string: None,
                     sub_aggregation: get_sub_test_tree(&[(
                         sub_aggregation_key.to_string(),
                         *sub_aggregation_count,
@@ -763,6 +1555,7 @@ mod tests {
         IntermediateAggregationResults {
             buckets: Some(VecWithNames::from_entries(map.into_iter().collect())),
             metrics: Default::default(),
+            memory_used: 0,
         }
     }
 
@@ -777,7 +1570,7 @@ mod tests {
             ("blue".to_string(), 25, "1900".to_string(), 50),
         ]);
 
-        tree_left.merge_fruits(tree_right);
+        tree_left.merge_fruits(tree_right).unwrap();
 
         let tree_expected = get_intermediat_tree_with_ranges(&[
             ("red".to_string(), 110, "1900".to_string(), 55),
@@ -798,7 +1591,7 @@ mod tests {
             ("green".to_string(), 25, "1900".to_string(), 50),
         ]);
 
-        tree_left.merge_fruits(tree_right);
+        tree_left.merge_fruits(tree_right).unwrap();
 
         let tree_expected = get_intermediat_tree_with_ranges(&[
             ("red".to_string(), 110, "1900".to_string(), 55),
@@ -818,7 +1611,9 @@ mod tests {
 
         let orig = tree_left.clone();
 
-        tree_left.merge_fruits(IntermediateAggregationResults::default());
+        tree_left
+            .merge_fruits(IntermediateAggregationResults::default())
+            .unwrap();
 
         assert_eq!(tree_left, orig);
     }
@@ -0,0 +1,177 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use super::segment_agg_result::SegmentAggregationCollector;
+use super::AggregationError;
+
+/// The default memory limit of an aggregation request in bytes, before the aggregation fails.
+pub const DEFAULT_MEMORY_LIMIT: u64 = 500_000_000;
+
+/// The memory budget of an aggregation request.
+///
+/// The segment collectors account the memory of their growing data structures, like the buckets
+/// of a terms aggregation, the buckets of a histogram and the sub aggregations of the buckets.
+/// The collection is aborted with [`AggregationError::MemoryLimitExceeded`] when the memory
+/// of all segment collectors of a search exceeds the limit. When the aggregation collector is
+/// wrapped into another collector, e.g. a tuple of collectors, the limit applies to each segment
+/// collector on its own.
+///
+/// The memory is an estimation, it doesn't include e.g. the memory of the fast field readers.
+/// The memory is accounted per search, so a collector can be reused for several searches.
+/// Clones share the memory used by the last search, so a clone can be used to read it after the
+/// search.
+#[derive(Clone, Debug)]
+pub struct MemoryBudget {
+    last_memory_used: Arc<AtomicU64>,
+    memory_limit: u64,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::new(DEFAULT_MEMORY_LIMIT)
+    }
+}
+
+impl MemoryBudget {
+    /// Creates a memory budget with the memory limit in bytes.
+    pub fn new(memory_limit: u64) -> Self {
+        Self {
+            last_memory_used: Default::default(),
+            memory_limit,
+        }
+    }
+
+    /// Returns the memory limit in bytes.
+    pub fn memory_limit(&self) -> u64 {
+        self.memory_limit
+    }
+
+    /// Returns the memory in bytes, which was used by the segment collectors of the last
+    /// successful search.
+    pub fn memory_used(&self) -> u64 {
+        self.last_memory_used.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_memory_used(&self, memory_used: u64) {
+        self.last_memory_used.store(memory_used, Ordering::Relaxed);
+    }
+
+    /// Starts the accounting of a new search.
+    pub(crate) fn new_search(&self) -> SearchMemory {
+        SearchMemory {
+            memory_used: Default::default(),
+            memory_limit: self.memory_limit,
+        }
+    }
+}
+
+/// The memory used by the segment collectors of one search.
+#[derive(Clone, Debug)]
+pub(crate) struct SearchMemory {
+    memory_used: Arc<AtomicU64>,
+    memory_limit: u64,
+}
+
+impl SearchMemory {
+    /// Starts the accounting of a segment collector of the search.
+    pub(crate) fn new_segment(&self) -> MemoryAccount {
+        MemoryAccount {
+            search_memory: self.clone(),
+            segment_memory_used: Default::default(),
+        }
+    }
+}
+
+/// The memory used by a segment collector, which is shared between its aggregations.
+#[derive(Clone, Debug)]
+pub(crate) struct MemoryAccount {
+    search_memory: SearchMemory,
+    segment_memory_used: Rc<Cell<u64>>,
+}
+
+impl MemoryAccount {
+    /// Returns the memory in bytes, which has been used by the segment collector so far.
+    pub(crate) fn memory_used(&self) -> u64 {
+        self.segment_memory_used.get()
+    }
+
+    /// Accounts `num_bytes` of additional memory and returns an error if the memory limit of the
+    /// search is exceeded.
+    pub(crate) fn add_memory_consumed(&self, num_bytes: u64) -> crate::Result<()> {
+        if num_bytes == 0 {
+            return Ok(());
+        }
+        self.segment_memory_used
+            .set(self.segment_memory_used.get().saturating_add(num_bytes));
+        let previous = self
+            .search_memory
+            .memory_used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used.saturating_add(num_bytes))
+            })
+            .expect("the update function always returns a value");
+        let used = previous.saturating_add(num_bytes);
+        if used > self.search_memory.memory_limit {
+            return Err(AggregationError::MemoryLimitExceeded {
+                limit: self.search_memory.memory_limit,
+                used,
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Estimates the memory of the table of a hash map, without the memory owned by the entries.
+pub(crate) fn hash_map_memory<K, V, S>(map: &HashMap<K, V, S>) -> u64 {
+    // The table stores the entries and one control byte per entry.
+    (map.capacity() * (mem::size_of::<(K, V)>() + 1)) as u64
+}
+
+/// Estimates the memory of one copy of the sub aggregation blueprint of a bucket.
+pub(crate) fn blueprint_memory(blueprint: &Option<Box<dyn SegmentAggregationCollector>>) -> u64 {
+    blueprint
+        .as_ref()
+        .map(|blueprint| mem::size_of_val(&**blueprint) as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TantivyError;
+
+    #[test]
+    fn test_memory_budget() {
+        let budget = MemoryBudget::new(100);
+        let search_memory = budget.new_search();
+        let account = search_memory.new_segment();
+        let other_account = search_memory.new_segment();
+        account.add_memory_consumed(60).unwrap();
+        other_account.add_memory_consumed(40).unwrap();
+        assert_eq!(account.memory_used(), 60);
+        assert_eq!(other_account.memory_used(), 40);
+
+        let err = other_account.add_memory_consumed(1).unwrap_err();
+        assert!(matches!(
+            err,
+            TantivyError::AggregationError(AggregationError::MemoryLimitExceeded {
+                limit: 100,
+                used: 101
+            })
+        ));
+        assert_eq!(other_account.memory_used(), 41);
+
+        account.add_memory_consumed(u64::MAX).unwrap_err();
+        assert_eq!(account.memory_used(), u64::MAX);
+
+        // A new search starts from scratch.
+        let account = budget.new_search().new_segment();
+        account.add_memory_consumed(100).unwrap();
+        assert_eq!(account.memory_used(), 100);
+    }
+}
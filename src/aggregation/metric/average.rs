@@ -19,13 +19,26 @@ use super::{IntermediateStats, SegmentStatsCollector};
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct AverageAggregation {
     /// The field name to compute the average on.
+    #[serde(skip_serializing_if = "String::is_empty", default)]
     pub field: String,
+    /// An expression over numeric fast fields, which is used instead of `field`. See
+    /// [scripts](super#scripts).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub script: Option<String>,
+    /// The value to use for documents without a value in the field. By default such documents
+    /// are ignored.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub missing: Option<f64>,
 }
 
 impl AverageAggregation {
     /// Creates a new [`AverageAggregation`] instance from a field name.
     pub fn from_field_name(field_name: String) -> Self {
-        Self { field: field_name }
+        Self {
+            field: field_name,
+            script: None,
+            missing: None,
+        }
     }
     /// Returns the field name the aggregation is computed on.
     pub fn field_name(&self) -> &str {
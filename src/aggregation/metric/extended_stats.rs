@@ -0,0 +1,458 @@
+use serde::{Deserialize, Serialize};
+
+use super::IntermediateStats;
+use crate::aggregation::agg_req_with_accessor::{AggregationsWithAccessor, MetricValueAccessor};
+use crate::aggregation::intermediate_agg_result::{
+    IntermediateAggregationResults, IntermediateMetricResult,
+};
+use crate::aggregation::segment_agg_result::SegmentAggregationCollector;
+use crate::aggregation::VecWithNames;
+use crate::{DocId, TantivyError};
+
+/// A multi-value metric aggregation that computes the same statistics as the
+/// [`StatsAggregation`](super::StatsAggregation), extended with the sum of squares, the variance,
+/// the standard deviation and the bounds of `sigma` standard deviations around the average.
+/// See [`ExtendedStats`] for returned statistics.
+///
+/// # JSON Format
+/// ```json
+/// {
+///     "extended_stats": {
+///         "field": "score",
+///         "sigma": 3
+///     }
+///  }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExtendedStatsAggregation {
+    /// The field name to compute the stats on.
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    pub field: String,
+    /// An expression over numeric fast fields, which is used instead of `field`. See
+    /// [scripts](super#scripts).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub script: Option<String>,
+    /// The number of standard deviations above and below the average for the
+    /// `std_deviation_bounds`. Defaults to 2.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub sigma: Option<f64>,
+    /// The value to use for documents without a value in the field. By default such documents
+    /// are ignored.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub missing: Option<f64>,
+}
+
+impl ExtendedStatsAggregation {
+    /// Creates a new [`ExtendedStatsAggregation`] instance from a field name.
+    pub fn from_field_name(field_name: String) -> Self {
+        ExtendedStatsAggregation {
+            field: field_name,
+            script: None,
+            sigma: None,
+            missing: None,
+        }
+    }
+    /// Returns the field name the aggregation is computed on.
+    pub fn field_name(&self) -> &str {
+        &self.field
+    }
+    /// Returns the number of standard deviations used for the bounds.
+    pub fn sigma(&self) -> f64 {
+        self.sigma.unwrap_or(2.0)
+    }
+
+    pub(crate) fn validate(&self) -> crate::Result<()> {
+        let sigma = self.sigma();
+        if !sigma.is_finite() || sigma < 0.0 {
+            return Err(TantivyError::InvalidArgument(format!(
+                "sigma must be a non-negative number, but got {}",
+                sigma
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// ExtendedStats contains the [`Stats`](super::Stats) and additional statistics on the spread of
+/// the values.
+///
+/// The population variance divides by the number of values, the sampling variance by the number of
+/// values minus one. `variance` and `std_deviation` are the population variants.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExtendedStats {
+    /// The number of documents.
+    pub count: u64,
+    /// The sum of the fast field values.
+    pub sum: f64,
+    /// The min value of the fast field values.
+    pub min: Option<f64>,
+    /// The max value of the fast field values.
+    pub max: Option<f64>,
+    /// The average of the fast field values. `None` if count equals zero.
+    pub avg: Option<f64>,
+    /// The sum of the squares of the fast field values.
+    pub sum_of_squares: f64,
+    /// The population variance. `None` if count equals zero.
+    pub variance: Option<f64>,
+    /// The population variance. `None` if count equals zero.
+    pub variance_population: Option<f64>,
+    /// The sampling variance. `None` if count is smaller than two.
+    pub variance_sampling: Option<f64>,
+    /// The population standard deviation. `None` if count equals zero.
+    pub std_deviation: Option<f64>,
+    /// The population standard deviation. `None` if count equals zero.
+    pub std_deviation_population: Option<f64>,
+    /// The sampling standard deviation. `None` if count is smaller than two.
+    pub std_deviation_sampling: Option<f64>,
+    /// The average plus and minus `sigma` standard deviations.
+    pub std_deviation_bounds: StdDeviationBounds,
+}
+
+/// The bounds of `sigma` standard deviations around the average.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StdDeviationBounds {
+    /// Upper bound using the population standard deviation.
+    pub upper: Option<f64>,
+    /// Lower bound using the population standard deviation.
+    pub lower: Option<f64>,
+    /// Upper bound using the population standard deviation.
+    pub upper_population: Option<f64>,
+    /// Lower bound using the population standard deviation.
+    pub lower_population: Option<f64>,
+    /// Upper bound using the sampling standard deviation.
+    pub upper_sampling: Option<f64>,
+    /// Lower bound using the sampling standard deviation.
+    pub lower_sampling: Option<f64>,
+}
+
+impl ExtendedStats {
+    pub(crate) fn get_value(&self, agg_property: &str) -> crate::Result<Option<f64>> {
+        let bounds = &self.std_deviation_bounds;
+        match agg_property {
+            "count" => Ok(Some(self.count as f64)),
+            "sum" => Ok(Some(self.sum)),
+            "min" => Ok(self.min),
+            "max" => Ok(self.max),
+            "avg" => Ok(self.avg),
+            "sum_of_squares" => Ok(Some(self.sum_of_squares)),
+            "variance" => Ok(self.variance),
+            "variance_population" => Ok(self.variance_population),
+            "variance_sampling" => Ok(self.variance_sampling),
+            "std_deviation" => Ok(self.std_deviation),
+            "std_deviation_population" => Ok(self.std_deviation_population),
+            "std_deviation_sampling" => Ok(self.std_deviation_sampling),
+            "std_upper" => Ok(bounds.upper),
+            "std_lower" => Ok(bounds.lower),
+            "std_upper_population" => Ok(bounds.upper_population),
+            "std_lower_population" => Ok(bounds.lower_population),
+            "std_upper_sampling" => Ok(bounds.upper_sampling),
+            "std_lower_sampling" => Ok(bounds.lower_sampling),
+            _ => Err(TantivyError::InvalidArgument(format!(
+                "Unknown property {} on extended_stats metric aggregation",
+                agg_property
+            ))),
+        }
+    }
+}
+
+/// Intermediate result of the extended stats aggregation that can be combined with other
+/// intermediate results.
+///
+/// Instead of the naive sum of squares, the variance is derived from the sum of squared
+/// differences from the mean, which is updated with Welford's algorithm and merged with the
+/// parallel algorithm of Chan et al. This avoids the catastrophic cancellation of
+/// `sum_of_squares / count - avg * avg` for values with a large mean and a small spread.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IntermediateExtendedStats {
+    stats: IntermediateStats,
+    /// The sum of the squares of the extracted values.
+    sum_of_squares: f64,
+    /// The running mean of the extracted values.
+    mean: f64,
+    /// The sum of squared differences from the running mean.
+    m2: f64,
+    sigma: f64,
+}
+
+impl Default for IntermediateExtendedStats {
+    fn default() -> Self {
+        Self::with_sigma(2.0)
+    }
+}
+
+impl IntermediateExtendedStats {
+    pub(crate) fn with_sigma(sigma: f64) -> Self {
+        Self {
+            stats: IntermediateStats::default(),
+            sum_of_squares: 0.0,
+            mean: 0.0,
+            m2: 0.0,
+            sigma,
+        }
+    }
+
+    /// Merges the other extended stats intermediate result into self.
+    pub fn merge_fruits(&mut self, other: IntermediateExtendedStats) {
+        let count_left = self.stats.count() as f64;
+        let count_right = other.stats.count() as f64;
+        if count_right > 0.0 {
+            let count = count_left + count_right;
+            let delta = other.mean - self.mean;
+            self.mean += delta * count_right / count;
+            self.m2 += other.m2 + delta * delta * count_left * count_right / count;
+        }
+        self.sum_of_squares += other.sum_of_squares;
+        self.stats.merge_fruits(other.stats);
+    }
+
+    /// Computes the final extended stats value.
+    pub fn finalize(&self) -> ExtendedStats {
+        let stats = self.stats.finalize();
+        let count = stats.count as f64;
+        let variance_population = if stats.count == 0 {
+            None
+        } else {
+            Some(self.m2 / count)
+        };
+        let variance_sampling = if stats.count < 2 {
+            None
+        } else {
+            Some(self.m2 / (count - 1.0))
+        };
+        let std_deviation_population = variance_population.map(f64::sqrt);
+        let std_deviation_sampling = variance_sampling.map(f64::sqrt);
+        let bound = |std_deviation: Option<f64>, sign: f64| {
+            Some(stats.avg? + sign * self.sigma * std_deviation?)
+        };
+        ExtendedStats {
+            count: stats.count,
+            sum: stats.sum,
+            min: stats.min,
+            max: stats.max,
+            avg: stats.avg,
+            sum_of_squares: self.sum_of_squares,
+            variance: variance_population,
+            variance_population,
+            variance_sampling,
+            std_deviation: std_deviation_population,
+            std_deviation_population,
+            std_deviation_sampling,
+            std_deviation_bounds: StdDeviationBounds {
+                upper: bound(std_deviation_population, 1.0),
+                lower: bound(std_deviation_population, -1.0),
+                upper_population: bound(std_deviation_population, 1.0),
+                lower_population: bound(std_deviation_population, -1.0),
+                upper_sampling: bound(std_deviation_sampling, 1.0),
+                lower_sampling: bound(std_deviation_sampling, -1.0),
+            },
+        }
+    }
+
+    #[inline]
+    fn collect(&mut self, value: f64) {
+        self.stats.collect(value);
+        self.sum_of_squares += value * value;
+        let delta = value - self.mean;
+        self.mean += delta / self.stats.count() as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SegmentExtendedStatsCollector {
+    pub(crate) stats: IntermediateExtendedStats,
+}
+
+impl SegmentExtendedStatsCollector {
+    pub fn from_req(sigma: f64) -> Self {
+        Self {
+            stats: IntermediateExtendedStats::with_sigma(sigma),
+        }
+    }
+    pub(crate) fn collect_block(&mut self, docs: &[DocId], values: &MetricValueAccessor) {
+        for doc in docs {
+            self.collect_doc(*doc, values);
+        }
+    }
+
+    #[inline]
+    fn collect_doc(&mut self, doc: DocId, values: &MetricValueAccessor) {
+        let stats = &mut self.stats;
+        values.collect_values(doc, |value| stats.collect(value));
+    }
+}
+
+impl SegmentAggregationCollector for SegmentExtendedStatsCollector {
+    fn into_intermediate_aggregations_result(
+        self: Box<Self>,
+        agg_with_accessor: &AggregationsWithAccessor,
+    ) -> crate::Result<IntermediateAggregationResults> {
+        let name = agg_with_accessor.metrics.keys[0].to_string();
+        let metrics = Some(VecWithNames::from_entries(vec![(
+            name,
+            IntermediateMetricResult::ExtendedStats(self.stats),
+        )]));
+
+        Ok(IntermediateAggregationResults {
+            metrics,
+            buckets: None,
+            memory_used: 0,
+        })
+    }
+
+    fn collect(
+        &mut self,
+        doc: crate::DocId,
+        agg_with_accessor: &AggregationsWithAccessor,
+    ) -> crate::Result<()> {
+        let metric = &agg_with_accessor.metrics.values[0];
+        self.collect_doc(doc, &metric.values);
+
+        Ok(())
+    }
+
+    fn flush_staged_docs(
+        &mut self,
+        _agg_with_accessor: &AggregationsWithAccessor,
+        _force_flush: bool,
+    ) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregation::agg_req::{Aggregation, Aggregations, MetricAggregation};
+    use crate::aggregation::tests::{exec_request, get_test_index_from_values};
+
+    fn intermediate_from_values(values: &[f64]) -> IntermediateExtendedStats {
+        let mut stats = IntermediateExtendedStats::default();
+        for val in values {
+            stats.collect(*val);
+        }
+        stats
+    }
+
+    #[test]
+    fn test_extended_stats_merge_is_stable() {
+        // A large mean and a small spread, where `sum_of_squares / count - avg * avg` is
+        // dominated by rounding errors.
+        let values: Vec<f64> = (0..1000).map(|i| 1e9 + (i % 10) as f64).collect();
+        let mut merged = IntermediateExtendedStats::default();
+        for chunk in values.chunks(137) {
+            merged.merge_fruits(intermediate_from_values(chunk));
+        }
+        merged.merge_fruits(IntermediateExtendedStats::default());
+        let single = intermediate_from_values(&values).finalize();
+        let merged = merged.finalize();
+
+        assert_eq!(merged.count, 1000);
+        assert_eq!(merged.min, Some(1e9));
+        assert_eq!(merged.max, Some(1e9 + 9.0));
+        assert!((merged.variance.unwrap() - 8.25).abs() < 1e-6);
+        assert!((single.variance.unwrap() - 8.25).abs() < 1e-6);
+        assert!((merged.variance_sampling.unwrap() - 8.25 * 1000.0 / 999.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_extended_stats_empty_and_single_value() {
+        let empty = IntermediateExtendedStats::default().finalize();
+        assert_eq!(empty.count, 0);
+        assert_eq!(empty.sum_of_squares, 0.0);
+        assert_eq!(empty.variance, None);
+        assert_eq!(empty.std_deviation_bounds.upper, None);
+
+        let single = intermediate_from_values(&[3.0]).finalize();
+        assert_eq!(single.variance, Some(0.0));
+        assert_eq!(single.variance_sampling, None);
+        assert_eq!(single.std_deviation_bounds.upper, Some(3.0));
+        assert_eq!(single.std_deviation_bounds.upper_sampling, None);
+    }
+
+    #[test]
+    fn test_aggregation_extended_stats() -> crate::Result<()> {
+        // Every value gets its own segment, so the result is merged across segments.
+        let values = vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let index = get_test_index_from_values(false, &values)?;
+
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "extended_stats": { "extended_stats": { "field": "score", "sigma": 1 } },
+            "range": {
+                "range": {
+                    "field": "score",
+                    "ranges": [ { "to": 3.0 }, { "from": 3.0 } ]
+                },
+                "aggs": {
+                    "extended_stats": { "extended_stats": { "field": "score_f64" } }
+                }
+            }
+        }))
+        .unwrap();
+
+        let res = exec_request(agg_req, &index)?;
+        let stats = &res["extended_stats"];
+        assert_eq!(stats["count"], 6);
+        assert_eq!(stats["sum"], 21.0);
+        assert_eq!(stats["min"], 1.0);
+        assert_eq!(stats["max"], 6.0);
+        assert_eq!(stats["avg"], 3.5);
+        assert_eq!(stats["sum_of_squares"], 91.0);
+        assert_eq!(stats["variance_sampling"], 3.5);
+
+        // The values are parsed back from json, which may be off by one ulp.
+        let assert_approx = |path: &[&str], expected: f64| {
+            let value = path.iter().fold(stats, |value, key| &value[key]);
+            let value = value.as_f64().unwrap();
+            assert!((value - expected).abs() < 1e-12, "{:?}: {}", path, value);
+        };
+        let variance = 17.5 / 6.0;
+        let std_deviation = f64::sqrt(variance);
+        let std_deviation_sampling = f64::sqrt(3.5);
+        assert_approx(&["variance"], variance);
+        assert_approx(&["variance_population"], variance);
+        assert_approx(&["std_deviation"], std_deviation);
+        assert_approx(&["std_deviation_population"], std_deviation);
+        assert_approx(&["std_deviation_sampling"], std_deviation_sampling);
+        // sigma is 1
+        let bounds = "std_deviation_bounds";
+        assert_approx(&[bounds, "upper"], 3.5 + std_deviation);
+        assert_approx(&[bounds, "lower"], 3.5 - std_deviation);
+        assert_approx(&[bounds, "upper_population"], 3.5 + std_deviation);
+        assert_approx(&[bounds, "lower_population"], 3.5 - std_deviation);
+        assert_approx(&[bounds, "upper_sampling"], 3.5 + std_deviation_sampling);
+        assert_approx(&[bounds, "lower_sampling"], 3.5 - std_deviation_sampling);
+
+        let bucket = &res["range"]["buckets"][0]["extended_stats"];
+        assert_eq!(bucket["count"], 2);
+        assert_eq!(bucket["variance"], 0.25);
+        assert_eq!(bucket["variance_sampling"], 0.5);
+        assert_eq!(bucket["std_deviation_bounds"]["upper"], 2.5);
+        assert_eq!(bucket["std_deviation_bounds"]["lower"], 0.5);
+
+        let agg_req: Aggregations = vec![(
+            "extended_stats".to_string(),
+            Aggregation::Metric(MetricAggregation::ExtendedStats(ExtendedStatsAggregation {
+                field: "score".to_string(),
+                script: None,
+                sigma: Some(-1.0),
+                missing: None,
+            })),
+        )]
+        .into_iter()
+        .collect();
+        let res = exec_request(agg_req, &index);
+        assert!(res.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extended_stats_get_value() {
+        let stats = intermediate_from_values(&[1.0, 3.0]).finalize();
+        assert_eq!(stats.get_value("variance").unwrap(), Some(1.0));
+        assert_eq!(stats.get_value("std_upper").unwrap(), Some(4.0));
+        assert_eq!(stats.get_value("sum_of_squares").unwrap(), Some(10.0));
+        assert!(stats.get_value("nope").is_err());
+    }
+}
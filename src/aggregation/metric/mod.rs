@@ -2,19 +2,38 @@
 //!
 //! The aggregations in this family compute metrics, see [super::agg_req::MetricAggregation] for
 //! details.
+//!
+//! # Scripts
+//! Instead of a `field`, the metric aggregations accept a `script` to compute their values from
+//! several numeric fast fields, e.g. the revenue of the documents in a bucket:
+//! ```json
+//! {
+//!     "revenue": {
+//!         "sum": { "script": "price * quantity" }
+//!     }
+//! }
+//! ```
+//! The script is an arithmetic expression with the operators `+ - * / %` and parentheses, where
+//! variables are field names. Each variable takes the first value of the field in the document.
+//! Documents where a field has no value or the result is not a finite number, e.g. because of a
+//! division by zero, are treated like documents without a value, see `missing`.
 mod average;
 mod count;
+mod extended_stats;
 mod max;
 mod min;
 mod stats;
 mod sum;
+mod weighted_average;
 pub use average::*;
 pub use count::*;
+pub use extended_stats::*;
 pub use max::*;
 pub use min::*;
 use serde::{Deserialize, Serialize};
 pub use stats::*;
 pub use sum::*;
+pub use weighted_average::*;
 
 /// Single-metric aggregations use this common result structure.
 ///
@@ -78,6 +97,7 @@ mod tests {
             "price_max": { "max": { "field": "price" } },
             "price_min": { "min": { "field": "price" } },
             "price_stats": { "stats": { "field": "price" } },
+            "price_extended_stats": { "extended_stats": { "field": "price" } },
             "price_sum": { "sum": { "field": "price" } }
         }"#;
         let aggregations: Aggregations = serde_json::from_str(aggregations_json).unwrap();
@@ -92,5 +112,9 @@ mod tests {
         assert_eq!(aggregations_res_json["price_max"]["value"], 5.0);
         assert_eq!(aggregations_res_json["price_min"]["value"], 0.0);
         assert_eq!(aggregations_res_json["price_sum"]["value"], 15.0);
+        assert_eq!(
+            aggregations_res_json["price_extended_stats"]["sum_of_squares"],
+            55.0
+        );
     }
 }
@@ -1,14 +1,12 @@
-use columnar::Column;
 use serde::{Deserialize, Serialize};
 
 use super::*;
-use crate::aggregation::agg_req_with_accessor::AggregationsWithAccessor;
+use crate::aggregation::agg_req_with_accessor::{AggregationsWithAccessor, MetricValueAccessor};
 use crate::aggregation::intermediate_agg_result::{
     IntermediateAggregationResults, IntermediateMetricResult,
 };
 use crate::aggregation::segment_agg_result::SegmentAggregationCollector;
-use crate::aggregation::{f64_from_fastfield_u64, VecWithNames};
-use crate::schema::Type;
+use crate::aggregation::VecWithNames;
 use crate::{DocId, TantivyError};
 
 /// A multi-value metric aggregation that computes a collection of statistics on numeric values that
@@ -27,13 +25,26 @@ use crate::{DocId, TantivyError};
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct StatsAggregation {
     /// The field name to compute the stats on.
+    #[serde(skip_serializing_if = "String::is_empty", default)]
     pub field: String,
+    /// An expression over numeric fast fields, which is used instead of `field`. See
+    /// [scripts](super#scripts).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub script: Option<String>,
+    /// The value to use for documents without a value in the field. By default such documents
+    /// are ignored.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub missing: Option<f64>,
 }
 
 impl StatsAggregation {
     /// Creates a new [`StatsAggregation`] instance from a field name.
     pub fn from_field_name(field_name: String) -> Self {
-        StatsAggregation { field: field_name }
+        StatsAggregation {
+            field: field_name,
+            script: None,
+            missing: None,
+        }
     }
     /// Returns the field name the aggregation is computed on.
     pub fn field_name(&self) -> &str {
@@ -132,8 +143,12 @@ impl IntermediateStats {
         }
     }
 
+    pub(crate) fn count(&self) -> u64 {
+        self.count
+    }
+
     #[inline]
-    fn collect(&mut self, value: f64) {
+    pub(crate) fn collect(&mut self, value: f64) {
         self.count += 1;
         self.sum += value;
         self.min = self.min.min(value);
@@ -153,28 +168,29 @@ pub(crate) enum SegmentStatsType {
 
 #[derive(Clone, Debug, PartialEq)]
 pub(crate) struct SegmentStatsCollector {
-    field_type: Type,
     pub(crate) collecting_for: SegmentStatsType,
     pub(crate) stats: IntermediateStats,
 }
 
 impl SegmentStatsCollector {
-    pub fn from_req(field_type: Type, collecting_for: SegmentStatsType) -> Self {
+    pub fn from_req(collecting_for: SegmentStatsType) -> Self {
         Self {
-            field_type,
             collecting_for,
             stats: IntermediateStats::default(),
         }
     }
-    pub(crate) fn collect_block(&mut self, docs: &[DocId], field: &Column<u64>) {
+    pub(crate) fn collect_block(&mut self, docs: &[DocId], values: &MetricValueAccessor) {
         // TODO special case for Required, Optional column type
         for doc in docs {
-            for val in field.values(*doc) {
-                let val1 = f64_from_fastfield_u64(val, &self.field_type);
-                self.stats.collect(val1);
-            }
+            self.collect_doc(*doc, values);
         }
     }
+
+    #[inline]
+    fn collect_doc(&mut self, doc: DocId, values: &MetricValueAccessor) {
+        let stats = &mut self.stats;
+        values.collect_values(doc, |value| stats.collect(value));
+    }
 }
 
 impl SegmentAggregationCollector for SegmentStatsCollector {
@@ -211,6 +227,7 @@ impl SegmentAggregationCollector for SegmentStatsCollector {
         Ok(IntermediateAggregationResults {
             metrics,
             buckets: None,
+            memory_used: 0,
         })
     }
 
@@ -219,11 +236,8 @@ impl SegmentAggregationCollector for SegmentStatsCollector {
         doc: crate::DocId,
         agg_with_accessor: &AggregationsWithAccessor,
     ) -> crate::Result<()> {
-        let accessor = &agg_with_accessor.metrics.values[0].accessor;
-        for val in accessor.values(doc) {
-            let val1 = f64_from_fastfield_u64(val, &self.field_type);
-            self.stats.collect(val1);
-        }
+        let metric = &agg_with_accessor.metrics.values[0];
+        self.collect_doc(doc, &metric.values);
 
         Ok(())
     }
@@ -249,8 +263,11 @@ mod tests {
         RangeAggregation,
     };
     use crate::aggregation::agg_result::AggregationResults;
-    use crate::aggregation::metric::StatsAggregation;
-    use crate::aggregation::tests::{get_test_index_2_segments, get_test_index_from_values};
+    use crate::aggregation::metric::{MaxAggregation, StatsAggregation};
+    use crate::aggregation::tests::{
+        exec_request, get_test_index_2_segments, get_test_index_from_values,
+        get_test_index_with_missing_values,
+    };
     use crate::aggregation::AggregationCollector;
     use crate::query::{AllQuery, TermQuery};
     use crate::schema::IndexRecordOption;
@@ -412,4 +429,59 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_aggregation_stats_missing() -> crate::Result<()> {
+        for merge_segments in [false, true] {
+            let index = get_test_index_with_missing_values(merge_segments)?;
+            let agg_req: Aggregations = vec![
+                (
+                    "stats".to_string(),
+                    Aggregation::Metric(MetricAggregation::Stats(StatsAggregation {
+                        field: "score".to_string(),
+                        script: None,
+                        missing: Some(0.0),
+                    })),
+                ),
+                (
+                    "stats_without_missing".to_string(),
+                    Aggregation::Metric(MetricAggregation::Stats(
+                        StatsAggregation::from_field_name("score".to_string()),
+                    )),
+                ),
+            ]
+            .into_iter()
+            .collect();
+
+            let res = exec_request(agg_req, &index)?;
+            assert_eq!(
+                res["stats"],
+                json!({
+                    "avg": 27.0 / 7.0,
+                    "count": 7,
+                    "max": 12.0,
+                    "min": 0.0,
+                    "sum": 27.0
+                })
+            );
+            assert_eq!(res["stats_without_missing"]["count"], 5);
+            assert_eq!(res["stats_without_missing"]["min"], 1.0);
+
+            // The single metric collector takes a different code path.
+            let agg_req: Aggregations = vec![(
+                "max".to_string(),
+                Aggregation::Metric(MetricAggregation::Max(MaxAggregation {
+                    field: "score_f64".to_string(),
+                    script: None,
+                    missing: Some(100.0),
+                })),
+            )]
+            .into_iter()
+            .collect();
+            let res = exec_request(agg_req, &index)?;
+            assert_eq!(res["max"]["value"], 100.0);
+        }
+
+        Ok(())
+    }
 }
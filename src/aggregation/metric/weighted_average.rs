@@ -0,0 +1,293 @@
+use std::fmt::Debug;
+
+use serde::{Deserialize, Serialize};
+
+use crate::aggregation::agg_req_with_accessor::{AggregationsWithAccessor, MetricValueAccessor};
+use crate::aggregation::intermediate_agg_result::{
+    IntermediateAggregationResults, IntermediateMetricResult,
+};
+use crate::aggregation::segment_agg_result::SegmentAggregationCollector;
+use crate::aggregation::VecWithNames;
+use crate::DocId;
+
+/// A single-value metric aggregation that computes the weighted average of numeric values that
+/// are extracted from the aggregated documents, where the weight of each value is extracted from
+/// the same document.
+/// See [super::SingleMetricResult] for return value.
+///
+/// Documents without a weight are ignored. The weight is read from the first value of the
+/// document, all values of the document are weighted with it.
+///
+/// # JSON Format
+/// ```json
+/// {
+///     "weighted_avg": {
+///         "value": { "field": "price" },
+///         "weight": { "field": "quantity", "missing": 1 }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WeightedAverageAggregation {
+    /// The source of the values to average.
+    pub value: WeightedAverageSource,
+    /// The source of the weights of the values.
+    pub weight: WeightedAverageSource,
+}
+
+impl WeightedAverageAggregation {
+    /// Creates a new [`WeightedAverageAggregation`] instance from the field names of the values
+    /// and the weights.
+    pub fn from_field_names(value_field_name: String, weight_field_name: String) -> Self {
+        Self {
+            value: WeightedAverageSource::from_field_name(value_field_name),
+            weight: WeightedAverageSource::from_field_name(weight_field_name),
+        }
+    }
+}
+
+/// The values or weights of a [`WeightedAverageAggregation`], either of a field or of a
+/// [script](super#scripts).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WeightedAverageSource {
+    /// The field name to read the values from.
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    pub field: String,
+    /// An expression over numeric fast fields, which is used instead of `field`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub script: Option<String>,
+    /// The value to use for documents without a value. By default such documents are ignored.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub missing: Option<f64>,
+}
+
+impl WeightedAverageSource {
+    /// Creates a new [`WeightedAverageSource`] instance from a field name.
+    pub fn from_field_name(field_name: String) -> Self {
+        Self {
+            field: field_name,
+            script: None,
+            missing: None,
+        }
+    }
+}
+
+/// Intermediate result of the weighted average aggregation that can be combined with other
+/// intermediate results.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IntermediateWeightedAverage {
+    /// The sum of the values multiplied with their weight.
+    weighted_sum: f64,
+    /// The sum of the weights.
+    weight_sum: f64,
+}
+
+impl IntermediateWeightedAverage {
+    /// Merges the other intermediate result into self.
+    pub fn merge_fruits(&mut self, other: IntermediateWeightedAverage) {
+        self.weighted_sum += other.weighted_sum;
+        self.weight_sum += other.weight_sum;
+    }
+    /// Computes the final weighted average value. `None` if the sum of the weights is zero.
+    pub fn finalize(&self) -> Option<f64> {
+        if self.weight_sum == 0.0 {
+            None
+        } else {
+            Some(self.weighted_sum / self.weight_sum)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct SegmentWeightedAverageCollector {
+    pub(crate) weighted_average: IntermediateWeightedAverage,
+}
+
+impl SegmentWeightedAverageCollector {
+    pub(crate) fn collect_block(
+        &mut self,
+        docs: &[DocId],
+        values: &MetricValueAccessor,
+        weights: &MetricValueAccessor,
+    ) {
+        for doc in docs {
+            self.collect_doc(*doc, values, weights);
+        }
+    }
+
+    #[inline]
+    fn collect_doc(
+        &mut self,
+        doc: DocId,
+        values: &MetricValueAccessor,
+        weights: &MetricValueAccessor,
+    ) {
+        let Some(weight) = weights.first_value(doc) else {
+            return;
+        };
+        let weighted_average = &mut self.weighted_average;
+        values.collect_values(doc, |value| {
+            weighted_average.weighted_sum += value * weight;
+            weighted_average.weight_sum += weight;
+        });
+    }
+}
+
+impl SegmentAggregationCollector for SegmentWeightedAverageCollector {
+    fn into_intermediate_aggregations_result(
+        self: Box<Self>,
+        agg_with_accessor: &AggregationsWithAccessor,
+    ) -> crate::Result<IntermediateAggregationResults> {
+        let name = agg_with_accessor.metrics.keys[0].to_string();
+        let metrics = Some(VecWithNames::from_entries(vec![(
+            name,
+            IntermediateMetricResult::WeightedAverage(self.weighted_average),
+        )]));
+
+        Ok(IntermediateAggregationResults {
+            metrics,
+            buckets: None,
+            memory_used: 0,
+        })
+    }
+
+    fn collect(
+        &mut self,
+        doc: crate::DocId,
+        agg_with_accessor: &AggregationsWithAccessor,
+    ) -> crate::Result<()> {
+        let metric = &agg_with_accessor.metrics.values[0];
+        self.collect_doc(doc, &metric.values, metric.weights());
+
+        Ok(())
+    }
+
+    fn flush_staged_docs(
+        &mut self,
+        _agg_with_accessor: &AggregationsWithAccessor,
+        _force_flush: bool,
+    ) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::aggregation::agg_req::Aggregations;
+    use crate::aggregation::tests::{exec_request, get_test_index_from_values};
+    use crate::schema::{Schema, FAST};
+    use crate::Index;
+
+    fn get_test_index(docs: &[(Option<f64>, Option<u64>)]) -> crate::Result<Index> {
+        let mut schema_builder = Schema::builder();
+        let price = schema_builder.add_f64_field("price", FAST);
+        let quantity = schema_builder.add_u64_field("quantity", FAST);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer = index.writer_for_tests()?;
+        for (i, (price_val, quantity_val)) in docs.iter().enumerate() {
+            let mut doc = doc!();
+            if let Some(price_val) = price_val {
+                doc.add_f64(price, *price_val);
+            }
+            if let Some(quantity_val) = quantity_val {
+                doc.add_u64(quantity, *quantity_val);
+            }
+            index_writer.add_document(doc)?;
+            if i % 2 == 1 {
+                index_writer.commit()?;
+            }
+        }
+        index_writer.commit()?;
+        Ok(index)
+    }
+
+    #[test]
+    fn test_weighted_average() -> crate::Result<()> {
+        let index = get_test_index(&[
+            (Some(10.0), Some(1)),
+            (Some(20.0), Some(3)),
+            (Some(30.0), None),
+            (None, Some(5)),
+            (Some(40.0), Some(0)),
+        ])?;
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "weighted": {
+                "weighted_avg": { "value": { "field": "price" }, "weight": { "field": "quantity" } }
+            },
+            "weighted_missing": {
+                "weighted_avg": {
+                    "value": { "field": "price", "missing": 50.0 },
+                    "weight": { "field": "quantity", "missing": 1.0 }
+                }
+            },
+            "weighted_script": {
+                "weighted_avg": {
+                    "value": { "script": "price / 10" },
+                    "weight": { "script": "quantity * 2" }
+                }
+            },
+            "no_weights": {
+                "weighted_avg": { "value": { "field": "price" }, "weight": { "script": "quantity * 0" } }
+            }
+        }))
+        .unwrap();
+        let res = exec_request(agg_req, &index)?;
+        assert_eq!(res["weighted"]["value"], (10.0 + 60.0) / 4.0);
+        assert_eq!(
+            res["weighted_missing"]["value"],
+            (10.0 + 60.0 + 30.0 + 250.0) / 10.0
+        );
+        assert_eq!(res["weighted_script"]["value"], (2.0 + 12.0) / 8.0);
+        assert_eq!(res["no_weights"]["value"], serde_json::Value::Null);
+        Ok(())
+    }
+
+    #[test]
+    fn test_metrics_on_script() -> crate::Result<()> {
+        let index = get_test_index(&[
+            (Some(2.5), Some(2)),
+            (Some(10.0), Some(3)),
+            (Some(4.0), None),
+            (None, Some(7)),
+        ])?;
+        let agg_req: Aggregations = serde_json::from_value(json!({
+            "revenue": { "sum": { "script": "price * quantity" } },
+            "revenue_missing": { "sum": { "script": "price * quantity", "missing": 100.0 } },
+            "revenue_stats": { "stats": { "script": "price * quantity" } },
+            "ratio": { "max": { "script": "quantity / (price - 2.5)" } },
+            "range": {
+                "range": { "field": "quantity", "ranges": [ { "to": 3.0 }, { "from": 3.0 } ] },
+                "aggs": { "revenue": { "avg": { "script": "price * quantity" } } }
+            }
+        }))
+        .unwrap();
+        let res = exec_request(agg_req, &index)?;
+        assert_eq!(res["revenue"]["value"], 35.0);
+        assert_eq!(res["revenue_missing"]["value"], 235.0);
+        assert_eq!(res["revenue_stats"]["count"], 2);
+        assert_eq!(res["revenue_stats"]["max"], 30.0);
+        // The division by zero of the first document is ignored.
+        assert_eq!(res["ratio"]["value"], 3.0 / 7.5);
+        assert_eq!(res["range"]["buckets"][0]["revenue"]["value"], 5.0);
+        assert_eq!(res["range"]["buckets"][1]["revenue"]["value"], 30.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_metric_value_source_errors() -> crate::Result<()> {
+        let index = get_test_index_from_values(false, &[1.0])?;
+        for req in [
+            json!({ "sum": { "field": "score", "script": "score * 2" } }),
+            json!({ "sum": {} }),
+            json!({ "sum": { "script": "score *" } }),
+            json!({ "sum": { "script": "score * unknown_field" } }),
+            json!({ "weighted_avg": { "value": { "field": "score" }, "weight": {} } }),
+        ] {
+            let agg_req: Aggregations = serde_json::from_value(json!({ "metric": req })).unwrap();
+            assert!(exec_request(agg_req, &index).is_err(), "{}", req);
+        }
+        Ok(())
+    }
+}
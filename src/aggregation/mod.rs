@@ -35,15 +35,37 @@
 //! ## Supported Aggregations
 //! - [Bucket](bucket)
 //!     - [Histogram](bucket::HistogramAggregation)
+//!     - [DateHistogram](bucket::DateHistogramAggregationReq)
+//!     - [AutoDateHistogram](bucket::AutoDateHistogramAggregation)
 //!     - [Range](bucket::RangeAggregation)
 //!     - [Terms](bucket::TermsAggregation)
+//!     - [MultiTerms](bucket::MultiTermsAggregation)
+//!     - [SignificantTerms](bucket::SignificantTermsAggregation)
+//!     - [Filter](bucket::FilterAggregation)
+//!     - [Filters](bucket::FiltersAggregation)
+//!     - [Composite](bucket::CompositeAggregation)
+//!     - [GeoHashGrid and GeoTileGrid](bucket::GeoGridAggregation)
+//!     - [Nested](bucket::NestedAggregation)
+//!     - [Sampler](bucket::SamplerAggregation)
+//!     - [RandomSampler](bucket::RandomSamplerAggregation)
 //! - [Metric](metric)
 //!     - [Average](metric::AverageAggregation)
 //!     - [Stats](metric::StatsAggregation)
+//!     - [ExtendedStats](metric::ExtendedStatsAggregation)
 //!     - [Min](metric::MinAggregation)
 //!     - [Max](metric::MaxAggregation)
 //!     - [Sum](metric::SumAggregation)
 //!     - [Count](metric::CountAggregation)
+//!     - [WeightedAverage](metric::WeightedAverageAggregation)
+//! - [Pipeline](pipeline)
+//!     - [Derivative](pipeline::DerivativeAggregation)
+//!     - [CumulativeSum](pipeline::CumulativeSumAggregation)
+//!     - [MovingAvg](pipeline::MovingAvgAggregation)
+//!     - [BucketScript](pipeline::BucketScriptAggregation)
+//!     - [BucketSelector](pipeline::BucketSelectorAggregation)
+//!     - [BucketSort](pipeline::BucketSortAggregation)
+//!     - [AvgBucket](pipeline::AvgBucketAggregation)
+//!     - [MaxBucket](pipeline::MaxBucketAggregation)
 //!
 //! # Example
 //! Compute the average metric, by building [`agg_req::Aggregations`], which is built from an
@@ -107,6 +129,13 @@
 //! let agg_req: Aggregations =
 //!     serde_json::from_str(elasticsearch_compatible_json_req).unwrap();
 //! ```
+//! # Json Fields
+//!
+//! Aggregations can also run on a path into a fast json field, by joining the path to the field
+//! name with `.`, e.g. `attributes.size`. Dots that are part of a key of the json object need to
+//! be escaped. Every value of a multi-valued field or path is aggregated. If the numbers of a json
+//! path have different types, they are coerced to a common type.
+//!
 //! # Code Organization
 //!
 //! Check the [README](https://github.com/quickwit-oss/tantivy/tree/main/src/aggregation#readme) on github to see how the code is organized.
@@ -136,6 +165,7 @@
 //!                 field: "score".to_string(),
 //!                 ranges: vec![(3f64..7f64).into(), (7f64..20f64).into()],
 //!                 keyed: false,
+//!                 missing: None,
 //!             }),
 //!             sub_aggregation: sub_agg_req_1.clone(),
 //!         }),
@@ -155,6 +185,12 @@
 //! to merge multiple results. The merged result can then be converted into
 //! [`AggregationResults`](agg_result::AggregationResults) via the
 //! [`into_final_bucket_result`](intermediate_agg_result::IntermediateAggregationResults::into_final_bucket_result) method.
+//!
+//! To send them between nodes, `IntermediateAggregationResults` are encoded in a versioned binary
+//! format via [`to_bytes`](intermediate_agg_result::IntermediateAggregationResults::to_bytes).
+//! Encoded results can be merged without the aggregation request via
+//! [`merge_bytes`](intermediate_agg_result::IntermediateAggregationResults::merge_bytes), which
+//! decodes them, merges them and encodes the merged result again.
 
 pub mod agg_req;
 mod agg_req_with_accessor;
@@ -162,9 +198,14 @@ pub mod agg_result;
 pub mod bucket;
 mod collector;
 mod date;
+mod error;
+mod expression;
 pub mod intermediate_agg_result;
+mod memory_budget;
 pub mod metric;
+pub mod pipeline;
 mod segment_agg_result;
+mod wire_format;
 use std::collections::HashMap;
 use std::fmt::Display;
 
@@ -173,9 +214,12 @@ pub use collector::{
     MAX_BUCKET_COUNT,
 };
 use columnar::MonotonicallyMappableToU64;
-pub(crate) use date::format_date;
+pub(crate) use date::{format_date, format_date_with_offset};
+pub use error::AggregationError;
 use itertools::Itertools;
+pub use memory_budget::{MemoryBudget, DEFAULT_MEMORY_LIMIT};
 use serde::{Deserialize, Serialize};
+pub use wire_format::INTERMEDIATE_RESULT_FORMAT_VERSION;
 
 use crate::schema::Type;
 
@@ -332,11 +376,11 @@ mod tests {
     use crate::aggregation::bucket::TermsAggregation;
     use crate::aggregation::intermediate_agg_result::IntermediateAggregationResults;
     use crate::aggregation::segment_agg_result::DOC_BLOCK_SIZE;
-    use crate::aggregation::DistributedAggregationCollector;
+    use crate::aggregation::{AggregationError, DistributedAggregationCollector, MemoryBudget};
     use crate::indexer::NoMergePolicy;
-    use crate::query::{AllQuery, TermQuery};
+    use crate::query::{AllQuery, RangeQuery, TermQuery};
     use crate::schema::{IndexRecordOption, Schema, TextFieldIndexing, FAST, STRING};
-    use crate::{DateTime, Index, Term};
+    use crate::{DateTime, Index, TantivyError, Term};
 
     fn get_avg_req(field_name: &str) -> Aggregation {
         Aggregation::Metric(MetricAggregation::Average(
@@ -438,6 +482,7 @@ mod tests {
             "fraction_f64",
             crate::schema::NumericOptions::default().set_fast(),
         );
+        let date_field = schema_builder.add_date_field("date", FAST);
         let index = Index::create_in_ram(schema_builder.build());
         {
             // let mut index_writer = index.writer_for_tests()?;
@@ -455,6 +500,7 @@ mod tests {
                         score_field_f64 => i,
                         score_field_i64 => i as i64,
                         fraction_field => i/100.0,
+                        date_field => DateTime::from_timestamp_secs(i as i64 * 86_400),
                     ))?;
                 }
                 index_writer.commit()?;
@@ -474,6 +520,48 @@ mod tests {
         Ok(index)
     }
 
+    /// Creates an index where some documents have no value in the `country` field and some have
+    /// no value in the `score` and `score_f64` fields.
+    ///
+    /// countries: de: 2, fr: 1, us: 2, missing: 2
+    /// scores: 1, 2, 5, 7, 12, missing: 2
+    pub fn get_test_index_with_missing_values(merge_segments: bool) -> crate::Result<Index> {
+        let mut schema_builder = Schema::builder();
+        let country_field = schema_builder.add_text_field("country", STRING | FAST);
+        let score_field = schema_builder.add_u64_field("score", FAST);
+        let score_field_f64 = schema_builder.add_f64_field("score_f64", FAST);
+        let index = Index::create_in_ram(schema_builder.build());
+        {
+            let mut index_writer = index.writer_with_num_threads(1, 30_000_000)?;
+            index_writer.set_merge_policy(Box::new(NoMergePolicy));
+            index_writer.add_document(
+                doc!(country_field => "de", score_field => 1u64, score_field_f64 => 1f64),
+            )?;
+            index_writer.add_document(
+                doc!(country_field => "fr", score_field => 7u64, score_field_f64 => 7f64),
+            )?;
+            index_writer.add_document(doc!(country_field => "de"))?;
+            index_writer.add_document(doc!(score_field => 5u64, score_field_f64 => 5f64))?;
+            index_writer.commit()?;
+            index_writer.add_document(
+                doc!(country_field => "us", score_field => 2u64, score_field_f64 => 2f64),
+            )?;
+            index_writer.add_document(doc!(score_field => 12u64, score_field_f64 => 12f64))?;
+            index_writer.add_document(doc!(country_field => "us"))?;
+            index_writer.commit()?;
+        }
+        if merge_segments {
+            let segment_ids = index
+                .searchable_segment_ids()
+                .expect("Searchable segments failed.");
+            let mut index_writer = index.writer_for_tests()?;
+            index_writer.merge(&segment_ids).wait()?;
+            index_writer.wait_merging_threads()?;
+        }
+
+        Ok(index)
+    }
+
     // *** EVERY BUCKET-TYPE SHOULD BE TESTED HERE ***
     fn test_aggregation_flushing(
         merge_segments: bool,
@@ -543,6 +631,92 @@ mod tests {
                     }
                 }
             }
+        },
+        "multi_terms_test":{
+            "multi_terms": {
+                "terms": [ { "field": "string_id" }, { "field": "text_id" } ]
+            },
+            "aggs": {
+                "bucketsL2": {
+                    "histogram": {
+                        "field": "score",
+                        "interval":  70.0
+                    }
+                }
+            }
+        },
+        "significant_terms_test":{
+            "range": {
+                "field": "score",
+                "ranges": [ { "to": 70.0f64 } ]
+            },
+            "aggs": {
+                "bucketsL2": {
+                    "significant_terms": {
+                        "field": "string_id"
+                    },
+                    "aggs": {
+                        "bucketsL3": {
+                            "histogram": {
+                                "field": "score",
+                                "interval":  70.0
+                            }
+                        }
+                    }
+                }
+            }
+        },
+        "filters_test":{
+            "filters": {
+                "filters": {
+                    "terma": "string_id:terma",
+                    "termb": "string_id:termb"
+                }
+            },
+            "aggs": {
+                "bucketsL2": {
+                    "filter": "string_id:terma",
+                    "aggs": {
+                        "bucketsL3": {
+                            "range": {
+                                "field": "score",
+                                "ranges": [ { "to": 70.0f64 }, { "from": 70.0f64 } ]
+                            }
+                        }
+                    }
+                }
+            }
+        },
+        "composite_test":{
+            "composite": {
+                "sources": [
+                    { "term": { "terms": { "field": "string_id" } } },
+                    { "histogram": { "histogram": { "field": "score", "interval": 70.0 } } }
+                ]
+            },
+            "aggs": {
+                "bucketsL2": {
+                    "composite": {
+                        "sources": [
+                            { "histogram": { "histogram": { "field": "score", "interval": 30.0 } } }
+                        ]
+                    }
+                }
+            }
+        },
+        "date_histogram_test":{
+            "date_histogram": {
+                "field": "date",
+                "calendar_interval": "month"
+            },
+            "aggs": {
+                "bucketsL2": {
+                    "date_histogram": {
+                        "field": "date",
+                        "calendar_interval": "week"
+                    }
+                }
+            }
         }
         });
 
@@ -632,6 +806,109 @@ mod tests {
             )
         );
 
+        assert_eq!(
+            res["multi_terms_test"]["buckets"][0],
+            json!({
+                "key": ["terma", "terma"],
+                "key_as_string": "terma|terma",
+                "doc_count": 79,
+                "bucketsL2": {
+                    "buckets": [
+                        { "key": 0.0, "doc_count": 70 },
+                        { "key": 70.0, "doc_count": 9 }
+                    ]
+                }
+            })
+        );
+        assert_eq!(
+            res["multi_terms_test"]["buckets"][1]["key"],
+            json!(["termb", "termb"])
+        );
+        assert_eq!(res["multi_terms_test"]["buckets"][1]["doc_count"], 1);
+
+        // The background count of terma depends on the segments, since every segment only
+        // returns the background count of its own foreground terms.
+        let significant_terms_res = &res["significant_terms_test"]["buckets"][0]["bucketsL2"];
+        assert_eq!(significant_terms_res["doc_count"], 70);
+        assert_eq!(significant_terms_res["bg_count"], 80);
+        assert_eq!(significant_terms_res["buckets"][0]["key"], "terma");
+        assert_eq!(significant_terms_res["buckets"][0]["doc_count"], 70);
+        assert_eq!(
+            significant_terms_res["buckets"][0]["bucketsL3"]["buckets"],
+            json!([{ "key": 0.0, "doc_count": 70 }])
+        );
+        assert_eq!(significant_terms_res["buckets"][1], Value::Null);
+
+        assert_eq!(res["filters_test"]["buckets"]["terma"]["doc_count"], 79);
+        assert_eq!(
+            res["filters_test"]["buckets"]["terma"]["bucketsL2"]["doc_count"],
+            79
+        );
+        assert_eq!(
+            res["filters_test"]["buckets"]["terma"]["bucketsL2"]["bucketsL3"]["buckets"][0]
+                ["doc_count"],
+            70
+        );
+        assert_eq!(
+            res["filters_test"]["buckets"]["terma"]["bucketsL2"]["bucketsL3"]["buckets"][1]
+                ["doc_count"],
+            9
+        );
+        assert_eq!(res["filters_test"]["buckets"]["termb"]["doc_count"], 1);
+        assert_eq!(
+            res["filters_test"]["buckets"]["termb"]["bucketsL2"]["doc_count"],
+            0
+        );
+
+        assert_eq!(
+            res["composite_test"]["after_key"],
+            json!({ "term": "termb", "histogram": 70.0 })
+        );
+        assert_eq!(
+            res["composite_test"]["buckets"][0],
+            json!({
+                "key": { "term": "terma", "histogram": 0.0 },
+                "doc_count": 70,
+                "bucketsL2": {
+                    "after_key": { "histogram": 60.0 },
+                    "buckets": [
+                        { "key": { "histogram": 0.0 }, "doc_count": 30 },
+                        { "key": { "histogram": 30.0 }, "doc_count": 30 },
+                        { "key": { "histogram": 60.0 }, "doc_count": 10 }
+                    ]
+                }
+            })
+        );
+        assert_eq!(
+            res["composite_test"]["buckets"][1]["key"],
+            json!({ "term": "terma", "histogram": 70.0 })
+        );
+        assert_eq!(res["composite_test"]["buckets"][1]["doc_count"], 9);
+        assert_eq!(res["composite_test"]["buckets"][2]["doc_count"], 1);
+        assert_eq!(res["composite_test"]["buckets"][3], Value::Null);
+
+        let date_histogram_buckets = res["date_histogram_test"]["buckets"].as_array().unwrap();
+        let doc_counts: Vec<_> = date_histogram_buckets
+            .iter()
+            .map(|bucket| bucket["doc_count"].as_u64().unwrap())
+            .collect();
+        assert_eq!(doc_counts, vec![31, 28, 21]);
+        assert_eq!(
+            date_histogram_buckets[1]["key_as_string"],
+            "1970-02-01T00:00:00Z"
+        );
+        let week_doc_counts: Vec<_> = date_histogram_buckets[0]["bucketsL2"]["buckets"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|bucket| bucket["doc_count"].as_u64().unwrap())
+            .collect();
+        assert_eq!(week_doc_counts, vec![4, 7, 7, 7, 6]);
+        assert_eq!(
+            date_histogram_buckets[0]["bucketsL2"]["buckets"][0]["key_as_string"],
+            "1969-12-29T00:00:00Z"
+        );
+
         Ok(())
     }
 
@@ -1155,6 +1432,181 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_aggregation_on_json_object() -> crate::Result<()> {
+        let mut schema_builder = Schema::builder();
+        let json = schema_builder.add_json_field("json", FAST);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.set_merge_policy(Box::new(NoMergePolicy));
+        let json_doc =
+            |json_value: serde_json::Value| doc!(json=>json_value.as_object().unwrap().clone());
+        index_writer.add_document(json_doc(serde_json::json!({
+            "attributes": { "size": 1, "color": "red" }
+        })))?;
+        index_writer.add_document(json_doc(serde_json::json!({
+            "attributes": { "size": [2, 3], "color": ["red", "blue"] }
+        })))?;
+        index_writer.commit()?;
+        // The sizes of this segment are stored as f64.
+        index_writer.add_document(json_doc(serde_json::json!({
+            "attributes": { "size": 2.5, "color": "blue" }
+        })))?;
+        index_writer.add_document(json_doc(serde_json::json!({
+            "attributes": { "size": 10 }
+        })))?;
+        index_writer.commit()?;
+        // The paths are missing in this segment.
+        index_writer.add_document(json_doc(serde_json::json!({ "other": 1 })))?;
+        index_writer.commit()?;
+
+        let agg_req: Aggregations = serde_json::from_value(serde_json::json!({
+            "histogram": {
+                "histogram": { "field": "json.attributes.size", "interval": 5.0 }
+            },
+            "range": {
+                "range": {
+                    "field": "json.attributes.size",
+                    "ranges": [ { "to": 2.0 }, { "from": 2.0, "to": 10.0 }, { "from": 10.0 } ]
+                }
+            },
+            "colors": {
+                "terms": { "field": "json.attributes.color" },
+                "aggs": { "avg_size": { "avg": { "field": "json.attributes.size" } } }
+            },
+            "stats": { "stats": { "field": "json.attributes.size" } },
+            "unknown": { "avg": { "field": "json.attributes.unknown" } }
+        }))
+        .unwrap();
+        let res = exec_request(agg_req, &index)?;
+        assert_eq!(res["histogram"]["buckets"][0]["doc_count"], 4);
+        assert_eq!(res["histogram"]["buckets"][1]["doc_count"], 0);
+        assert_eq!(res["histogram"]["buckets"][2]["doc_count"], 1);
+        assert_eq!(res["range"]["buckets"][0]["doc_count"], 1);
+        assert_eq!(res["range"]["buckets"][1]["doc_count"], 3);
+        assert_eq!(res["range"]["buckets"][2]["doc_count"], 1);
+        assert_eq!(res["colors"]["buckets"][0]["key"], "blue");
+        assert_eq!(res["colors"]["buckets"][0]["doc_count"], 2);
+        assert_eq!(
+            res["colors"]["buckets"][0]["avg_size"]["value"],
+            (2.0 + 3.0 + 2.5) / 3.0
+        );
+        assert_eq!(res["colors"]["buckets"][1]["key"], "red");
+        assert_eq!(res["colors"]["buckets"][1]["doc_count"], 2);
+        assert_eq!(res["stats"]["count"], 5);
+        assert_eq!(res["stats"]["sum"], 18.5);
+        assert_eq!(res["stats"]["max"], 10.0);
+        assert_eq!(res["unknown"]["value"], Value::Null);
+
+        let agg_req: Aggregations = serde_json::from_value(serde_json::json!({
+            "terms": { "terms": { "field": "unknown.attributes" } }
+        }))
+        .unwrap();
+        let err = exec_request(agg_req, &index).unwrap_err();
+        assert!(matches!(err, TantivyError::FieldNotFound(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregation_not_affected_by_segment_pruning() -> crate::Result<()> {
+        let index = get_test_index_from_values(false, &[1.0, 2.0, 100.0, 101.0, 3.0])?;
+        let searcher = index.reader()?.searcher();
+        let agg_req: Aggregations = serde_json::from_value(serde_json::json!({
+            "stats": { "stats": { "field": "score" } },
+            "range": { "range": { "field": "score", "ranges": [{ "to": 50.0 }, { "from": 50.0 }] } },
+            "terms": { "terms": { "field": "string_id", "min_doc_count": 0 } }
+        }))
+        .unwrap();
+        let collector = AggregationCollector::from_aggs(agg_req, None, index.schema());
+
+        // The segments which cannot match the query are still harvested, so that the terms of
+        // all of the segments are returned.
+        let query = RangeQuery::new_i64("score_i64".to_string(), 100..1000);
+        let res: Value = serde_json::to_value(searcher.search(&query, &collector)?)?;
+        assert_eq!(res["stats"]["count"], 2);
+        assert_eq!(res["stats"]["min"], 100.0);
+        assert_eq!(res["range"]["buckets"][0]["doc_count"], 0);
+        assert_eq!(res["range"]["buckets"][1]["doc_count"], 2);
+        let buckets = res["terms"]["buckets"].as_array().unwrap();
+        assert_eq!(buckets.len(), 5);
+        assert_eq!(
+            buckets
+                .iter()
+                .map(|bucket| bucket["doc_count"].as_u64().unwrap())
+                .sum::<u64>(),
+            2
+        );
+
+        let query = RangeQuery::new_i64("score_i64".to_string(), 200..1000);
+        let res: Value = serde_json::to_value(searcher.search(&query, &collector)?)?;
+        assert_eq!(res["stats"]["count"], 0);
+        assert_eq!(res["range"]["buckets"][0]["doc_count"], 0);
+        assert_eq!(res["range"]["buckets"][1]["doc_count"], 0);
+        assert_eq!(res["terms"]["buckets"].as_array().unwrap().len(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregation_memory_budget() -> crate::Result<()> {
+        let segment_and_values: Vec<Vec<(f64, String)>> = (0..4)
+            .map(|segment| {
+                (0..500)
+                    .map(|i| (i as f64, format!("term{}", segment * 500 + i)))
+                    .collect()
+            })
+            .collect();
+        let index = get_test_index_from_values_and_terms(false, &segment_and_values)?;
+        let searcher = index.reader()?.searcher();
+
+        let agg_req: Aggregations = serde_json::from_value(serde_json::json!({
+            "terms": {
+                "terms": { "field": "string_id" },
+                "aggs": { "avg": { "avg": { "field": "score" } } }
+            }
+        }))
+        .unwrap();
+
+        let memory_budget = MemoryBudget::new(10_000);
+        let collector = AggregationCollector::from_aggs(agg_req.clone(), None, index.schema())
+            .with_memory_budget(memory_budget.clone());
+        let err = searcher.search(&AllQuery, &collector).unwrap_err();
+        assert!(matches!(
+            err,
+            TantivyError::AggregationError(AggregationError::MemoryLimitExceeded {
+                limit: 10_000,
+                ..
+            })
+        ));
+        // The memory used is only recorded for successful searches.
+        assert_eq!(memory_budget.memory_used(), 0);
+
+        let memory_budget = MemoryBudget::default();
+        let collector = DistributedAggregationCollector::from_aggs(agg_req.clone(), None)
+            .with_memory_budget(memory_budget.clone());
+        let mut res = searcher.search(&AllQuery, &collector)?;
+        let memory_used = res.memory_used();
+        assert!(memory_used > 10_000);
+        assert_eq!(memory_used, memory_budget.memory_used());
+
+        // The memory is accounted per search, so a reused collector doesn't inherit the memory
+        // used by the previous searches.
+        let memory_budget = MemoryBudget::new(memory_used + memory_used / 2);
+        let collector = AggregationCollector::from_aggs(agg_req.clone(), None, index.schema())
+            .with_memory_budget(memory_budget.clone());
+        for _ in 0..3 {
+            searcher.search(&AllQuery, &collector)?;
+            assert_eq!(memory_budget.memory_used(), memory_used);
+        }
+
+        // The memory used survives the serialization and adds up when merging.
+        let other: IntermediateAggregationResults =
+            serde_json::from_str(&serde_json::to_string(&res)?)?;
+        res.merge_fruits(other)?;
+        assert_eq!(res.memory_used(), 2 * memory_used);
+
+        Ok(())
+    }
+
     #[cfg(all(test, feature = "unstable"))]
     mod bench {
 
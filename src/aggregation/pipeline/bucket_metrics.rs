@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+
+use super::GapPolicy;
+
+/// A sibling pipeline aggregation, which computes the average of a metric over all buckets of a
+/// multi-bucket aggregation.
+///
+/// # JSON Format
+/// ```json
+/// {
+///     "avg_monthly_sales": {
+///         "avg_bucket": {
+///             "buckets_path": "sales_per_month>sales"
+///         }
+///     }
+/// }
+/// ```
+///
+/// # Response
+/// See [`SingleMetricResult`](crate::aggregation::metric::SingleMetricResult)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AvgBucketAggregation {
+    /// The path to the multi-bucket aggregation and the value of each bucket, see the
+    /// [module docs](super).
+    pub buckets_path: String,
+    /// How buckets without a value are handled.
+    #[serde(default)]
+    pub gap_policy: GapPolicy,
+}
+
+impl AvgBucketAggregation {
+    pub(crate) fn compute(
+        &self,
+        values: impl Iterator<Item = (String, Option<f64>)>,
+    ) -> Option<f64> {
+        let (count, sum) = values
+            .filter_map(|(_, value)| value)
+            .fold((0u64, 0.0), |(count, sum), value| (count + 1, sum + value));
+        if count == 0 {
+            None
+        } else {
+            Some(sum / count as f64)
+        }
+    }
+}
+
+/// A sibling pipeline aggregation, which finds the maximum of a metric over all buckets of a
+/// multi-bucket aggregation, and the keys of the buckets with the maximum.
+///
+/// # JSON Format
+/// ```json
+/// {
+///     "best_month": {
+///         "max_bucket": {
+///             "buckets_path": "sales_per_month>sales"
+///         }
+///     }
+/// }
+/// ```
+///
+/// # Response
+/// See [`BucketMetricValue`]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MaxBucketAggregation {
+    /// The path to the multi-bucket aggregation and the value of each bucket, see the
+    /// [module docs](super).
+    pub buckets_path: String,
+    /// How buckets without a value are handled.
+    #[serde(default)]
+    pub gap_policy: GapPolicy,
+}
+
+impl MaxBucketAggregation {
+    pub(crate) fn compute(
+        &self,
+        values: impl Iterator<Item = (String, Option<f64>)>,
+    ) -> BucketMetricValue {
+        let mut max = BucketMetricValue {
+            value: None,
+            keys: Vec::new(),
+        };
+        for (key, value) in values {
+            let value = match value {
+                Some(value) => value,
+                None => continue,
+            };
+            match max.value {
+                Some(max_value) if value < max_value => {}
+                Some(max_value) if value == max_value => max.keys.push(key),
+                _ => {
+                    max.value = Some(value);
+                    max.keys = vec![key];
+                }
+            }
+        }
+        max
+    }
+}
+
+/// The result of a sibling pipeline aggregation, which selects buckets, like `max_bucket`.
+///
+/// # JSON Format
+/// ```json
+/// {
+///     "value": 1024.0,
+///     "keys": ["2019-03-01T00:00:00Z"]
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BucketMetricValue {
+    /// The value of the selected buckets.
+    pub value: Option<f64>,
+    /// The keys of the selected buckets.
+    pub keys: Vec<String>,
+}
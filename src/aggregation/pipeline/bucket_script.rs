@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::GapPolicy;
+use crate::aggregation::expression::Expression;
+use crate::TantivyError;
+
+/// A parent pipeline aggregation, which computes a value for each bucket of the parent
+/// multi-bucket aggregation with a script.
+///
+/// The script is an arithmetic expression, see below. The values of `buckets_path` are available
+/// in the script as `params.<name>`. With the `skip` gap policy, buckets where a value is missing
+/// get no value.
+///
+/// # Script
+/// The script supports numbers, the operators `+ - * / %`, the comparisons
+/// `< <= > >= == !=`, the boolean operators `&& || !` and parentheses. Comparisons and boolean
+/// operators return 1 for true and 0 for false.
+///
+/// # JSON Format
+/// ```json
+/// {
+///     "avg_price": {
+///         "bucket_script": {
+///             "buckets_path": { "revenue": "sum_revenue", "count": "_count" },
+///             "script": "params.revenue / params.count"
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BucketScriptAggregation {
+    /// The paths to the values of each bucket by script variable name, see the
+    /// [module docs](super).
+    pub buckets_path: HashMap<String, String>,
+    /// The script, which computes the value of the bucket.
+    pub script: String,
+    /// How buckets without a value are handled.
+    #[serde(default)]
+    pub gap_policy: GapPolicy,
+}
+
+impl BucketScriptAggregation {
+    pub(crate) fn expression(&self) -> crate::Result<Expression> {
+        parse_script(&self.script, &self.buckets_path)
+    }
+
+    /// Executes the script for a bucket, `resolve` returns the value of a buckets path.
+    pub(crate) fn execute(
+        &self,
+        expression: &Expression,
+        resolve: impl Fn(&str) -> crate::Result<Option<f64>>,
+    ) -> crate::Result<Option<f64>> {
+        execute_script(expression, &self.buckets_path, resolve)
+    }
+}
+
+/// Parses the script and validates that all variables are defined in `buckets_path`.
+pub(crate) fn parse_script(
+    script: &str,
+    buckets_path: &HashMap<String, String>,
+) -> crate::Result<Expression> {
+    let expression = Expression::parse(script)?;
+    for variable in expression.variables() {
+        let is_defined = variable
+            .strip_prefix("params.")
+            .map(|name| buckets_path.contains_key(name))
+            .unwrap_or(false);
+        if !is_defined {
+            return Err(TantivyError::InvalidArgument(format!(
+                "unknown variable {:?} in script {:?}, expected one of {:?}",
+                variable,
+                script,
+                buckets_path
+                    .keys()
+                    .map(|name| format!("params.{}", name))
+                    .collect::<Vec<_>>()
+            )));
+        }
+    }
+    Ok(expression)
+}
+
+pub(crate) fn execute_script(
+    expression: &Expression,
+    buckets_path: &HashMap<String, String>,
+    resolve: impl Fn(&str) -> crate::Result<Option<f64>>,
+) -> crate::Result<Option<f64>> {
+    let params = buckets_path
+        .iter()
+        .map(|(name, path)| Ok((format!("params.{}", name), resolve(path)?)))
+        .collect::<crate::Result<HashMap<String, Option<f64>>>>()?;
+    Ok(expression.eval(&|variable| params.get(variable).copied().flatten()))
+}
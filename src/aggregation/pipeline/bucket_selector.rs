@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::bucket_script::{execute_script, parse_script};
+use super::GapPolicy;
+use crate::aggregation::expression::Expression;
+
+/// A parent pipeline aggregation, which only keeps the buckets of the parent multi-bucket
+/// aggregation for which the script returns a non-zero value.
+///
+/// The script has the same format as in
+/// [`BucketScriptAggregation`](super::BucketScriptAggregation). With the `skip` gap policy,
+/// buckets where a value is missing are removed.
+///
+/// Bucket selectors are applied after all parent pipeline aggregations computing values, so the
+/// values of e.g. a `derivative` are computed on all buckets.
+///
+/// # JSON Format
+/// ```json
+/// {
+///     "busy_days": {
+///         "bucket_selector": {
+///             "buckets_path": { "count": "_count" },
+///             "script": "params.count > 1000"
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BucketSelectorAggregation {
+    /// The paths to the values of each bucket by script variable name, see the
+    /// [module docs](super).
+    pub buckets_path: HashMap<String, String>,
+    /// The script, which decides if the bucket is kept.
+    pub script: String,
+    /// How buckets without a value are handled.
+    #[serde(default)]
+    pub gap_policy: GapPolicy,
+}
+
+impl BucketSelectorAggregation {
+    pub(crate) fn expression(&self) -> crate::Result<Expression> {
+        parse_script(&self.script, &self.buckets_path)
+    }
+
+    /// Returns whether the bucket is kept, `resolve` returns the value of a buckets path.
+    pub(crate) fn execute(
+        &self,
+        expression: &Expression,
+        resolve: impl Fn(&str) -> crate::Result<Option<f64>>,
+    ) -> crate::Result<bool> {
+        let value = execute_script(expression, &self.buckets_path, resolve)?;
+        Ok(value.map(|value| value != 0.0).unwrap_or(false))
+    }
+}
@@ -0,0 +1,189 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{GapPolicy, PipelineBucket};
+use crate::aggregation::bucket::Order;
+use crate::TantivyError;
+
+/// A parent pipeline aggregation, which sorts the buckets of the parent multi-bucket
+/// aggregation and returns the page from `from` to `from + size`.
+///
+/// Without `sort`, the buckets are only truncated. With the `skip` gap policy, buckets where a
+/// sort value is missing are removed.
+///
+/// Bucket sorts are applied after all other parent pipeline aggregations.
+///
+/// # JSON Format
+/// ```json
+/// {
+///     "top_sales": {
+///         "bucket_sort": {
+///             "sort": [ { "sales": { "order": "desc" } }, "_key" ],
+///             "from": 0,
+///             "size": 3
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BucketSortAggregation {
+    /// The fields to sort by, later fields break ties of earlier ones.
+    #[serde(default)]
+    pub sort: Vec<BucketSortField>,
+    /// The number of buckets to skip after sorting.
+    #[serde(default)]
+    pub from: usize,
+    /// The number of buckets to return, all by default.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub size: Option<usize>,
+    /// How buckets without a value are handled.
+    #[serde(default)]
+    pub gap_policy: GapPolicy,
+}
+
+/// A sort field of the [`BucketSortAggregation`].
+///
+/// De/Serializes from/to `{ "<path>": { "order": "desc" } }`, a plain path sorts ascending.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BucketSortField {
+    /// The path to the value of each bucket, see the [module docs](super).
+    pub path: String,
+    /// The sort order.
+    pub order: Order,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BucketSortFieldOrder {
+    #[serde(default = "default_order")]
+    order: Order,
+}
+
+fn default_order() -> Order {
+    Order::Asc
+}
+
+impl Serialize for BucketSortField {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer {
+        let map: HashMap<&str, BucketSortFieldOrder> = std::iter::once((
+            self.path.as_str(),
+            BucketSortFieldOrder { order: self.order },
+        ))
+        .collect();
+        map.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BucketSortField {
+    fn deserialize<D>(deserializer: D) -> Result<BucketSortField, D::Error>
+    where D: Deserializer<'de> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum BucketSortFieldRepr {
+            Path(String),
+            WithOrder(HashMap<String, BucketSortFieldOrder>),
+        }
+        match BucketSortFieldRepr::deserialize(deserializer)? {
+            BucketSortFieldRepr::Path(path) => Ok(BucketSortField {
+                path,
+                order: default_order(),
+            }),
+            BucketSortFieldRepr::WithOrder(map) => {
+                if map.len() != 1 {
+                    return Err(de::Error::custom(format!(
+                        "expected exactly one path in bucket_sort field, but got {}",
+                        map.len()
+                    )));
+                }
+                let (path, field_order) = map.into_iter().next().unwrap();
+                Ok(BucketSortField {
+                    path,
+                    order: field_order.order,
+                })
+            }
+        }
+    }
+}
+
+impl BucketSortAggregation {
+    pub(crate) fn apply<B: PipelineBucket>(&self, buckets: &mut Vec<B>) -> crate::Result<()> {
+        if self.size == Some(0) {
+            return Err(TantivyError::InvalidArgument(
+                "bucket_sort size must be greater than 0".to_string(),
+            ));
+        }
+        if !self.sort.is_empty() {
+            let mut buckets_with_values = Vec::with_capacity(buckets.len());
+            for bucket in buckets.drain(..) {
+                let values = self
+                    .sort
+                    .iter()
+                    .map(|field| Ok(self.gap_policy.apply(bucket.resolve(&field.path)?)))
+                    .collect::<crate::Result<Option<Vec<f64>>>>()?;
+                // With the skip gap policy, buckets with a missing value are removed.
+                if let Some(values) = values {
+                    buckets_with_values.push((bucket, values));
+                }
+            }
+            buckets_with_values.sort_by(|(_, left), (_, right)| {
+                self.sort
+                    .iter()
+                    .zip(left.iter().zip(right.iter()))
+                    .map(|(field, (left, right))| match field.order {
+                        Order::Asc => left.total_cmp(right),
+                        Order::Desc => right.total_cmp(left),
+                    })
+                    .find(|ordering| *ordering != Ordering::Equal)
+                    .unwrap_or(Ordering::Equal)
+            });
+            buckets.extend(buckets_with_values.into_iter().map(|(bucket, _)| bucket));
+        }
+        buckets.drain(..self.from.min(buckets.len()));
+        if let Some(size) = self.size {
+            buckets.truncate(size);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_sort_serde_test() {
+        let req: BucketSortAggregation = serde_json::from_str(
+            r#"{ "sort": [ { "sales": { "order": "desc" } }, "_key", { "_count": {} } ], "size": 3 }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            req.sort,
+            vec![
+                BucketSortField {
+                    path: "sales".to_string(),
+                    order: Order::Desc
+                },
+                BucketSortField {
+                    path: "_key".to_string(),
+                    order: Order::Asc
+                },
+                BucketSortField {
+                    path: "_count".to_string(),
+                    order: Order::Asc
+                },
+            ]
+        );
+        assert_eq!(req.from, 0);
+        assert_eq!(req.size, Some(3));
+
+        let roundtrip: BucketSortAggregation =
+            serde_json::from_str(&serde_json::to_string(&req).unwrap()).unwrap();
+        assert_eq!(roundtrip, req);
+
+        let invalid: serde_json::Result<BucketSortField> =
+            serde_json::from_str(r#"{ "a": {}, "b": {} }"#);
+        assert!(invalid.is_err());
+    }
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+/// A parent pipeline aggregation, which computes the cumulative sum of a metric over the buckets
+/// of the parent `histogram` or `date_histogram`.
+///
+/// Buckets without a value don't change the sum.
+///
+/// # JSON Format
+/// ```json
+/// {
+///     "cumulative_sales": {
+///         "cumulative_sum": {
+///             "buckets_path": "sales"
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CumulativeSumAggregation {
+    /// The path to the value of each bucket, see the [module docs](super).
+    pub buckets_path: String,
+}
+
+impl CumulativeSumAggregation {
+    pub(crate) fn compute(&self, values: &[Option<f64>]) -> Vec<Option<f64>> {
+        let mut sum = 0.0;
+        values
+            .iter()
+            .map(|value| {
+                sum += value.unwrap_or(0.0);
+                Some(sum)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+
+use super::GapPolicy;
+
+/// A parent pipeline aggregation, which computes the difference of a metric to the previous
+/// bucket of the parent `histogram` or `date_histogram`.
+///
+/// The first bucket has no value. With the `skip` gap policy, buckets without a value are
+/// skipped and the difference is computed to the last bucket with a value.
+///
+/// # JSON Format
+/// ```json
+/// {
+///     "sales_deriv": {
+///         "derivative": {
+///             "buckets_path": "sales"
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DerivativeAggregation {
+    /// The path to the value of each bucket, see the [module docs](super).
+    pub buckets_path: String,
+    /// How buckets without a value are handled.
+    #[serde(default)]
+    pub gap_policy: GapPolicy,
+}
+
+impl DerivativeAggregation {
+    pub(crate) fn compute(&self, values: &[Option<f64>]) -> Vec<Option<f64>> {
+        let mut previous = None;
+        values
+            .iter()
+            .map(|value| {
+                let derivative = match (value, previous) {
+                    (Some(value), Some(previous)) => Some(value - previous),
+                    _ => None,
+                };
+                if value.is_some() {
+                    previous = *value;
+                }
+                derivative
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,775 @@
+//! Module for all pipeline aggregations.
+//!
+//! Pipeline aggregations don't collect documents, but compute values from the final results of
+//! other aggregations, see [super::agg_req::PipelineAggregation] for details. They are applied
+//! when the [`IntermediateAggregationResults`](super::intermediate_agg_result::IntermediateAggregationResults)
+//! are converted to the final [`AggregationResults`].
+//!
+//! There are two families of pipeline aggregations:
+//! - Parent pipeline aggregations are defined in the sub-aggregations of a multi-bucket
+//!   aggregation and compute a value for each bucket of it, or filter and sort its buckets.
+//! - Sibling pipeline aggregations are defined next to a multi-bucket aggregation and compute a
+//!   single value from all of its buckets.
+//!
+//! # Buckets path
+//! Pipeline aggregations reference their input via a `buckets_path` relative to the bucket:
+//! - `_count`: The doc count of the bucket.
+//! - `_key`: The numeric key of the bucket.
+//! - `my_avg` or `my_stats.max`: The value of a metric or pipeline sub-aggregation.
+//! - `my_filter>my_avg`: The value of a metric inside of a `filter` sub-aggregation.
+//!
+//! The `buckets_path` of sibling pipeline aggregations starts with the name of the multi-bucket
+//! aggregation, e.g. `sales_per_month>_count`.
+mod bucket_metrics;
+mod bucket_script;
+mod bucket_selector;
+mod bucket_sort;
+mod cumulative_sum;
+mod derivative;
+mod moving_avg;
+pub use bucket_metrics::*;
+pub use bucket_script::*;
+pub use bucket_selector::*;
+pub use bucket_sort::*;
+pub use cumulative_sum::*;
+pub use derivative::*;
+pub use moving_avg::*;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use super::agg_req::{AggregationsInternal, PipelineAggregation};
+use super::agg_result::{
+    AggregationResult, AggregationResults, BucketEntries, BucketEntry, BucketResult,
+    CompositeBucketEntry, GeoGridBucketEntry, MetricResult, MultiTermsBucketEntry,
+    RangeBucketEntry, SignificantTermBucketEntry,
+};
+use super::bucket::get_agg_name_and_property;
+use super::{Key, VecWithNames};
+use crate::TantivyError;
+
+/// Defines how pipeline aggregations handle buckets without a value, e.g. the average of an empty
+/// bucket.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GapPolicy {
+    /// Ignore buckets without a value.
+    #[default]
+    #[serde(rename = "skip")]
+    Skip,
+    /// Use 0 as value for buckets without a value.
+    #[serde(rename = "insert_zeros")]
+    InsertZeros,
+}
+
+impl GapPolicy {
+    fn apply(self, value: Option<f64>) -> Option<f64> {
+        let value = value.filter(|value| !value.is_nan());
+        match self {
+            GapPolicy::Skip => value,
+            GapPolicy::InsertZeros => Some(value.unwrap_or(0.0)),
+        }
+    }
+}
+
+impl PipelineAggregation {
+    /// Sibling pipeline aggregations compute a single value from the buckets of a sibling
+    /// aggregation, all others work on the buckets of their parent aggregation.
+    fn is_sibling(&self) -> bool {
+        matches!(
+            self,
+            PipelineAggregation::AvgBucket(_) | PipelineAggregation::MaxBucket(_)
+        )
+    }
+
+    fn buckets_paths(&self) -> Vec<&str> {
+        match self {
+            PipelineAggregation::Derivative(derivative) => vec![&derivative.buckets_path],
+            PipelineAggregation::CumulativeSum(cumulative_sum) => {
+                vec![&cumulative_sum.buckets_path]
+            }
+            PipelineAggregation::MovingAvg(moving_avg) => vec![&moving_avg.buckets_path],
+            PipelineAggregation::BucketScript(script) => {
+                script.buckets_path.values().map(String::as_str).collect()
+            }
+            PipelineAggregation::BucketSelector(selector) => {
+                selector.buckets_path.values().map(String::as_str).collect()
+            }
+            PipelineAggregation::BucketSort(sort) => {
+                sort.sort.iter().map(|field| field.path.as_str()).collect()
+            }
+            PipelineAggregation::AvgBucket(avg_bucket) => vec![&avg_bucket.buckets_path],
+            PipelineAggregation::MaxBucket(max_bucket) => vec![&max_bucket.buckets_path],
+        }
+    }
+
+    /// Returns whether the pipeline aggregation only works on ordered buckets of a `histogram`
+    /// or `date_histogram`.
+    fn requires_histogram(&self) -> bool {
+        matches!(
+            self,
+            PipelineAggregation::Derivative(_)
+                | PipelineAggregation::CumulativeSum(_)
+                | PipelineAggregation::MovingAvg(_)
+        )
+    }
+
+    /// Returns the position in which parent pipeline aggregations are applied. Pipeline
+    /// aggregations which compute values come first, then buckets are filtered and sorted.
+    fn phase(&self) -> u8 {
+        match self {
+            PipelineAggregation::BucketSelector(_) => 1,
+            PipelineAggregation::BucketSort(_) => 2,
+            _ => 0,
+        }
+    }
+}
+
+/// A bucket of a multi-bucket aggregation result, which pipeline aggregations can read values
+/// from and write values to.
+pub(crate) trait PipelineBucket {
+    fn doc_count(&self) -> u64;
+    fn key(&self) -> Option<&Key>;
+    /// The key of the bucket as returned by sibling pipeline aggregations.
+    fn key_as_string(&self) -> String;
+    fn sub_aggregation(&self) -> &AggregationResults;
+    fn sub_aggregation_mut(&mut self) -> &mut AggregationResults;
+
+    fn resolve(&self, path: &str) -> crate::Result<Option<f64>> {
+        resolve_bucket_path(path, self.doc_count(), self.key(), self.sub_aggregation())
+    }
+}
+
+impl PipelineBucket for BucketEntry {
+    fn doc_count(&self) -> u64 {
+        self.doc_count
+    }
+    fn key(&self) -> Option<&Key> {
+        Some(&self.key)
+    }
+    fn key_as_string(&self) -> String {
+        self.key_as_string
+            .clone()
+            .unwrap_or_else(|| self.key.to_string())
+    }
+    fn sub_aggregation(&self) -> &AggregationResults {
+        &self.sub_aggregation
+    }
+    fn sub_aggregation_mut(&mut self) -> &mut AggregationResults {
+        &mut self.sub_aggregation
+    }
+}
+
+impl PipelineBucket for RangeBucketEntry {
+    fn doc_count(&self) -> u64 {
+        self.doc_count
+    }
+    fn key(&self) -> Option<&Key> {
+        Some(&self.key)
+    }
+    fn key_as_string(&self) -> String {
+        self.key.to_string()
+    }
+    fn sub_aggregation(&self) -> &AggregationResults {
+        &self.sub_aggregation
+    }
+    fn sub_aggregation_mut(&mut self) -> &mut AggregationResults {
+        &mut self.sub_aggregation
+    }
+}
+
+impl PipelineBucket for MultiTermsBucketEntry {
+    fn doc_count(&self) -> u64 {
+        self.doc_count
+    }
+    fn key(&self) -> Option<&Key> {
+        None
+    }
+    fn key_as_string(&self) -> String {
+        self.key_as_string.clone()
+    }
+    fn sub_aggregation(&self) -> &AggregationResults {
+        &self.sub_aggregation
+    }
+    fn sub_aggregation_mut(&mut self) -> &mut AggregationResults {
+        &mut self.sub_aggregation
+    }
+}
+
+impl PipelineBucket for GeoGridBucketEntry {
+    fn doc_count(&self) -> u64 {
+        self.doc_count
+    }
+    fn key(&self) -> Option<&Key> {
+        None
+    }
+    fn key_as_string(&self) -> String {
+        self.key.clone()
+    }
+    fn sub_aggregation(&self) -> &AggregationResults {
+        &self.sub_aggregation
+    }
+    fn sub_aggregation_mut(&mut self) -> &mut AggregationResults {
+        &mut self.sub_aggregation
+    }
+}
+
+impl PipelineBucket for SignificantTermBucketEntry {
+    fn doc_count(&self) -> u64 {
+        self.doc_count
+    }
+    fn key(&self) -> Option<&Key> {
+        Some(&self.key)
+    }
+    fn key_as_string(&self) -> String {
+        self.key.to_string()
+    }
+    fn sub_aggregation(&self) -> &AggregationResults {
+        &self.sub_aggregation
+    }
+    fn sub_aggregation_mut(&mut self) -> &mut AggregationResults {
+        &mut self.sub_aggregation
+    }
+}
+
+impl PipelineBucket for CompositeBucketEntry {
+    fn doc_count(&self) -> u64 {
+        self.doc_count
+    }
+    fn key(&self) -> Option<&Key> {
+        None
+    }
+    fn key_as_string(&self) -> String {
+        let key: std::collections::BTreeMap<&String, &Key> = self.key.iter().collect();
+        serde_json::to_string(&key).unwrap_or_default()
+    }
+    fn sub_aggregation(&self) -> &AggregationResults {
+        &self.sub_aggregation
+    }
+    fn sub_aggregation_mut(&mut self) -> &mut AggregationResults {
+        &mut self.sub_aggregation
+    }
+}
+
+/// Resolves the `buckets_path` relative to a bucket.
+fn resolve_bucket_path(
+    path: &str,
+    doc_count: u64,
+    key: Option<&Key>,
+    sub_aggregation: &AggregationResults,
+) -> crate::Result<Option<f64>> {
+    if let Some((single_bucket_name, rest)) = path.split_once('>') {
+        return match sub_aggregation.0.get(single_bucket_name) {
+            Some(AggregationResult::BucketResult(BucketResult::Filter(filter))) => {
+                resolve_bucket_path(rest, filter.doc_count, None, &filter.sub_aggregation)
+            }
+            _ => Err(TantivyError::InvalidArgument(format!(
+                "buckets_path {:?}: {:?} is not a filter aggregation",
+                path, single_bucket_name
+            ))),
+        };
+    }
+    match path {
+        "_count" => Ok(Some(doc_count as f64)),
+        "_key" => match key {
+            Some(Key::F64(key)) => Ok(Some(*key)),
+            _ => Err(TantivyError::InvalidArgument(format!(
+                "buckets_path {:?} requires a numeric bucket key",
+                path
+            ))),
+        },
+        _ => {
+            let (agg_name, agg_property) = get_agg_name_and_property(path);
+            match sub_aggregation.0.get(agg_name) {
+                Some(AggregationResult::MetricResult(metric)) => metric.get_value(agg_property),
+                Some(AggregationResult::BucketResult(_)) => {
+                    Err(TantivyError::InvalidArgument(format!(
+                        "buckets_path {:?} must point to a metric, but {:?} is a bucket \
+                         aggregation",
+                        path, agg_name
+                    )))
+                }
+                None => Err(TantivyError::InvalidArgument(format!(
+                    "buckets_path {:?}: could not find aggregation {:?}",
+                    path, agg_name
+                ))),
+            }
+        }
+    }
+}
+
+/// Returns the name of the aggregation the `buckets_path` points to.
+fn referenced_aggregation(path: &str) -> &str {
+    let first_element = path.split('>').next().unwrap_or(path);
+    get_agg_name_and_property(first_element).0
+}
+
+/// Returns the parent pipeline aggregations in the order they need to be applied, so that
+/// pipeline aggregations referencing other pipeline aggregations come after them.
+fn ordered_parent_pipelines(
+    pipelines: &VecWithNames<PipelineAggregation>,
+) -> crate::Result<Vec<(&str, &PipelineAggregation)>> {
+    let mut pending: Vec<(&str, &PipelineAggregation)> = pipelines
+        .iter()
+        .filter(|(_, pipeline)| !pipeline.is_sibling())
+        .collect();
+    // The stable sort keeps the order by name within a phase.
+    pending.sort_by_key(|(_, pipeline)| pipeline.phase());
+    let mut ordered = Vec::with_capacity(pending.len());
+    while !pending.is_empty() {
+        let is_ready = |pipeline: &PipelineAggregation| {
+            pipeline.buckets_paths().into_iter().all(|path| {
+                let referenced = referenced_aggregation(path);
+                pending.iter().all(|(name, _)| *name != referenced)
+            })
+        };
+        let pos = pending
+            .iter()
+            .position(|(_, pipeline)| is_ready(pipeline))
+            .ok_or_else(|| {
+                TantivyError::InvalidArgument(format!(
+                    "pipeline aggregations {:?} reference each other",
+                    pending.iter().map(|(name, _)| *name).collect::<Vec<_>>()
+                ))
+            })?;
+        ordered.push(pending.remove(pos));
+    }
+    Ok(ordered)
+}
+
+/// Returns an error if there are parent pipeline aggregations in `sub_aggregation`, which the
+/// aggregation `agg_type` doesn't support.
+pub(crate) fn validate_no_parent_pipelines(
+    sub_aggregation: &AggregationsInternal,
+    agg_type: &str,
+) -> crate::Result<()> {
+    if let Some((name, _)) = sub_aggregation
+        .pipelines
+        .iter()
+        .find(|(_, pipeline)| !pipeline.is_sibling())
+    {
+        return Err(TantivyError::InvalidArgument(format!(
+            "pipeline aggregation {:?} requires a multi-bucket parent aggregation, but is used in \
+             {}",
+            name, agg_type
+        )));
+    }
+    Ok(())
+}
+
+/// Applies the parent pipeline aggregations in `sub_aggregation` to the buckets of a multi-bucket
+/// aggregation. `is_histogram` is true for the ordered buckets of a `histogram` or
+/// `date_histogram`.
+pub(crate) fn apply_parent_pipelines<B: PipelineBucket>(
+    buckets: &mut Vec<B>,
+    sub_aggregation: &AggregationsInternal,
+    is_histogram: bool,
+) -> crate::Result<()> {
+    for (name, pipeline) in ordered_parent_pipelines(&sub_aggregation.pipelines)? {
+        if pipeline.requires_histogram() && !is_histogram {
+            return Err(TantivyError::InvalidArgument(format!(
+                "pipeline aggregation {:?} requires a histogram or date_histogram parent \
+                 aggregation",
+                name
+            )));
+        }
+        let values_for_path = |buckets: &Vec<B>, path: &str, gap_policy: GapPolicy| {
+            buckets
+                .iter()
+                .map(|bucket| Ok(gap_policy.apply(bucket.resolve(path)?)))
+                .collect::<crate::Result<Vec<_>>>()
+        };
+        let metric_results: Vec<MetricResult> = match pipeline {
+            PipelineAggregation::Derivative(derivative) => {
+                let values =
+                    values_for_path(buckets, &derivative.buckets_path, derivative.gap_policy)?;
+                derivative
+                    .compute(&values)
+                    .into_iter()
+                    .map(|value| MetricResult::Derivative(value.into()))
+                    .collect()
+            }
+            PipelineAggregation::CumulativeSum(cumulative_sum) => {
+                let values =
+                    values_for_path(buckets, &cumulative_sum.buckets_path, GapPolicy::Skip)?;
+                cumulative_sum
+                    .compute(&values)
+                    .into_iter()
+                    .map(|value| MetricResult::CumulativeSum(value.into()))
+                    .collect()
+            }
+            PipelineAggregation::MovingAvg(moving_avg) => {
+                let values =
+                    values_for_path(buckets, &moving_avg.buckets_path, moving_avg.gap_policy)?;
+                moving_avg
+                    .compute(&values)?
+                    .into_iter()
+                    .map(|value| MetricResult::MovingAvg(value.into()))
+                    .collect()
+            }
+            PipelineAggregation::BucketScript(script) => {
+                let expression = script.expression()?;
+                buckets
+                    .iter()
+                    .map(|bucket| {
+                        let value = script.execute(&expression, |path| {
+                            Ok(script.gap_policy.apply(bucket.resolve(path)?))
+                        })?;
+                        Ok(MetricResult::BucketScript(value.into()))
+                    })
+                    .collect::<crate::Result<_>>()?
+            }
+            PipelineAggregation::BucketSelector(selector) => {
+                let expression = selector.expression()?;
+                let keep = buckets
+                    .iter()
+                    .map(|bucket| {
+                        selector.execute(&expression, |path| {
+                            Ok(selector.gap_policy.apply(bucket.resolve(path)?))
+                        })
+                    })
+                    .collect::<crate::Result<Vec<bool>>>()?;
+                let mut keep = keep.into_iter();
+                buckets.retain(|_| keep.next().unwrap_or(false));
+                continue;
+            }
+            PipelineAggregation::BucketSort(sort) => {
+                sort.apply(buckets)?;
+                continue;
+            }
+            PipelineAggregation::AvgBucket(_) | PipelineAggregation::MaxBucket(_) => {
+                unreachable!("sibling pipeline aggregations are filtered out")
+            }
+        };
+        for (bucket, metric_result) in buckets.iter_mut().zip(metric_results) {
+            bucket.sub_aggregation_mut().0.insert(
+                name.to_string(),
+                AggregationResult::MetricResult(metric_result),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Returns the key and value of every bucket of the multi-bucket aggregation result.
+fn bucket_values(
+    bucket_result: &BucketResult,
+    path: &str,
+) -> crate::Result<Vec<(String, Option<f64>)>> {
+    fn from_buckets<'a, B: PipelineBucket + 'a>(
+        buckets: impl Iterator<Item = &'a B>,
+        path: &str,
+    ) -> crate::Result<Vec<(String, Option<f64>)>> {
+        buckets
+            .map(|bucket| Ok((bucket.key_as_string(), bucket.resolve(path)?)))
+            .collect()
+    }
+    fn from_entries<'a, B: PipelineBucket + 'a>(
+        entries: &'a BucketEntries<B>,
+        path: &str,
+    ) -> crate::Result<Vec<(String, Option<f64>)>> {
+        match entries {
+            BucketEntries::Vec(buckets) => from_buckets(buckets.iter(), path),
+            BucketEntries::HashMap(buckets) => {
+                let mut buckets: Vec<_> = buckets.iter().collect();
+                buckets.sort_by(|left, right| left.0.cmp(right.0));
+                from_buckets(buckets.into_iter().map(|(_, bucket)| bucket), path)
+            }
+        }
+    }
+    match bucket_result {
+        BucketResult::Range { buckets } => from_entries(buckets, path),
+        BucketResult::Histogram { buckets } => from_entries(buckets, path),
+        BucketResult::AutoDateHistogram { buckets, .. } => from_entries(buckets, path),
+        BucketResult::Terms { buckets, .. } => from_buckets(buckets.iter(), path),
+        BucketResult::MultiTerms { buckets, .. } => from_buckets(buckets.iter(), path),
+        BucketResult::SignificantTerms { buckets, .. } => from_buckets(buckets.iter(), path),
+        BucketResult::Composite { buckets, .. } => from_buckets(buckets.iter(), path),
+        BucketResult::GeoGrid { buckets } => from_buckets(buckets.iter(), path),
+        BucketResult::Filters { buckets } => {
+            let mut buckets: Vec<_> = buckets.iter().collect();
+            buckets.sort_by(|left, right| left.0.cmp(right.0));
+            buckets
+                .into_iter()
+                .map(|(key, bucket)| {
+                    let value =
+                        resolve_bucket_path(path, bucket.doc_count, None, &bucket.sub_aggregation)?;
+                    Ok((key.to_string(), value))
+                })
+                .collect()
+        }
+        BucketResult::Filter(_) => Err(TantivyError::InvalidArgument(
+            "sibling pipeline aggregations require a multi-bucket aggregation, but got a filter \
+             aggregation"
+                .to_string(),
+        )),
+    }
+}
+
+/// Applies the sibling pipeline aggregations to the results of their sibling aggregations.
+pub(crate) fn apply_sibling_pipelines(
+    results: &mut FxHashMap<String, AggregationResult>,
+    pipelines: &VecWithNames<PipelineAggregation>,
+) -> crate::Result<()> {
+    for (name, pipeline) in pipelines.iter() {
+        let (buckets_path, gap_policy) = match pipeline {
+            PipelineAggregation::AvgBucket(avg_bucket) => {
+                (&avg_bucket.buckets_path, avg_bucket.gap_policy)
+            }
+            PipelineAggregation::MaxBucket(max_bucket) => {
+                (&max_bucket.buckets_path, max_bucket.gap_policy)
+            }
+            _ => continue,
+        };
+        let (agg_name, path) = buckets_path.split_once('>').ok_or_else(|| {
+            TantivyError::InvalidArgument(format!(
+                "buckets_path {:?} of {:?} must start with a multi-bucket aggregation, e.g. \
+                 \"my_histogram>_count\"",
+                buckets_path, name
+            ))
+        })?;
+        let values = match results.get(agg_name) {
+            Some(AggregationResult::BucketResult(bucket_result)) => {
+                bucket_values(bucket_result, path)?
+            }
+            _ => {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "buckets_path {:?}: could not find bucket aggregation {:?}",
+                    buckets_path, agg_name
+                )))
+            }
+        };
+        let values = values
+            .into_iter()
+            .map(|(key, value)| (key, gap_policy.apply(value)));
+        let metric_result = match pipeline {
+            PipelineAggregation::AvgBucket(avg_bucket) => {
+                MetricResult::AvgBucket(avg_bucket.compute(values).into())
+            }
+            PipelineAggregation::MaxBucket(max_bucket) => {
+                MetricResult::MaxBucket(max_bucket.compute(values))
+            }
+            _ => unreachable!(),
+        };
+        results.insert(
+            name.to_string(),
+            AggregationResult::MetricResult(metric_result),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use crate::aggregation::agg_req::Aggregations;
+    use crate::aggregation::tests::{exec_request, get_test_index_from_values_and_terms};
+
+    fn exec(agg_req: Value) -> crate::Result<Value> {
+        // Scores 0..10 with 10 docs each, in two segments.
+        let values: Vec<(f64, String)> = (0..100)
+            .map(|i| ((i / 10) as f64, format!("term{}", i % 3)))
+            .collect();
+        let index = get_test_index_from_values_and_terms(
+            false,
+            &[values[..50].to_vec(), values[50..].to_vec()],
+        )?;
+        let agg_req: Aggregations = serde_json::from_value(agg_req).unwrap();
+        exec_request(agg_req, &index)
+    }
+
+    fn values(res: &Value, agg_name: &str) -> Vec<Value> {
+        res["histogram"]["buckets"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|bucket| bucket[agg_name]["value"].clone())
+            .collect()
+    }
+
+    #[test]
+    fn parent_pipeline_aggregations_test() -> crate::Result<()> {
+        let res = exec(json!({
+            "histogram": {
+                "histogram": { "field": "score", "interval": 2.0 },
+                "aggs": {
+                    "sum_score": { "sum": { "field": "score" } },
+                    "sum_deriv": { "derivative": { "buckets_path": "sum_score" } },
+                    "count_cumsum": { "cumulative_sum": { "buckets_path": "_count" } },
+                    "sum_moving": { "moving_avg": { "buckets_path": "sum_score", "window": 2 } },
+                    "avg_score": {
+                        "bucket_script": {
+                            "buckets_path": { "sum": "sum_score", "count": "_count" },
+                            "script": "params.sum / params.count"
+                        }
+                    },
+                    "avg_deriv": { "derivative": { "buckets_path": "avg_score" } }
+                }
+            }
+        }))?;
+        // Buckets 0, 2, 4, 6, 8 with 20 docs each.
+        assert_eq!(
+            values(&res, "sum_score"),
+            vec![
+                json!(10.0),
+                json!(50.0),
+                json!(90.0),
+                json!(130.0),
+                json!(170.0)
+            ]
+        );
+        assert_eq!(
+            values(&res, "sum_deriv"),
+            vec![
+                Value::Null,
+                json!(40.0),
+                json!(40.0),
+                json!(40.0),
+                json!(40.0)
+            ]
+        );
+        assert_eq!(
+            values(&res, "count_cumsum"),
+            vec![
+                json!(20.0),
+                json!(40.0),
+                json!(60.0),
+                json!(80.0),
+                json!(100.0)
+            ]
+        );
+        assert_eq!(
+            values(&res, "sum_moving"),
+            vec![
+                Value::Null,
+                json!(10.0),
+                json!(30.0),
+                json!(70.0),
+                json!(110.0)
+            ]
+        );
+        assert_eq!(
+            values(&res, "avg_score"),
+            vec![json!(0.5), json!(2.5), json!(4.5), json!(6.5), json!(8.5)]
+        );
+        assert_eq!(
+            values(&res, "avg_deriv"),
+            vec![Value::Null, json!(2.0), json!(2.0), json!(2.0), json!(2.0)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn bucket_selector_and_sort_test() -> crate::Result<()> {
+        let res = exec(json!({
+            "histogram": {
+                "histogram": { "field": "score", "interval": 1.0 },
+                "aggs": {
+                    "sum_score": { "sum": { "field": "score" } },
+                    "sum_deriv": { "derivative": { "buckets_path": "sum_score" } },
+                    "odd_scores": {
+                        "bucket_selector": {
+                            "buckets_path": { "sum": "sum_score" },
+                            "script": "params.sum % 20 == 10"
+                        }
+                    },
+                    "top": {
+                        "bucket_sort": {
+                            "sort": [ { "sum_score": { "order": "desc" } } ],
+                            "from": 1,
+                            "size": 2
+                        }
+                    }
+                }
+            }
+        }))?;
+        // The derivative is computed before the buckets are filtered.
+        assert_eq!(
+            res["histogram"]["buckets"],
+            json!([
+                {
+                    "key": 7.0,
+                    "doc_count": 10,
+                    "sum_score": { "value": 70.0 },
+                    "sum_deriv": { "value": 10.0 }
+                },
+                {
+                    "key": 5.0,
+                    "doc_count": 10,
+                    "sum_score": { "value": 50.0 },
+                    "sum_deriv": { "value": 10.0 }
+                }
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sibling_pipeline_aggregations_test() -> crate::Result<()> {
+        let res = exec(json!({
+            "terms": {
+                "terms": { "field": "string_id" },
+                "aggs": { "max_score": { "max": { "field": "score" } } }
+            },
+            "avg_docs": { "avg_bucket": { "buckets_path": "terms>_count" } },
+            "max_docs": { "max_bucket": { "buckets_path": "terms>_count" } },
+            "histogram": {
+                "histogram": { "field": "score", "interval": 5.0 },
+                "aggs": {
+                    "per_term": {
+                        "terms": { "field": "string_id" },
+                        "aggs": { "sum_score": { "sum": { "field": "score" } } }
+                    },
+                    "max_term_sum": { "max_bucket": { "buckets_path": "per_term>sum_score" } }
+                }
+            }
+        }))?;
+        assert_eq!(res["avg_docs"]["value"].as_f64().unwrap(), 100.0 / 3.0);
+        assert_eq!(res["max_docs"], json!({ "value": 34.0, "keys": ["term0"] }));
+        assert_eq!(
+            res["histogram"]["buckets"][0]["max_term_sum"],
+            json!({ "value": 35.0, "keys": ["term1"] })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pipeline_aggregations_invalid_requests_test() -> crate::Result<()> {
+        let histogram_with = |pipeline: Value| {
+            json!({
+                "histogram": {
+                    "histogram": { "field": "score", "interval": 1.0 },
+                    "aggs": { "pipeline": pipeline }
+                }
+            })
+        };
+        let invalid_requests = vec![
+            // Unknown aggregation in path.
+            histogram_with(json!({ "derivative": { "buckets_path": "missing" } })),
+            // Unknown variable in the script.
+            histogram_with(json!({
+                "bucket_script": { "buckets_path": { "count": "_count" }, "script": "params.x" }
+            })),
+            // Invalid script.
+            histogram_with(json!({
+                "bucket_selector": { "buckets_path": { "count": "_count" }, "script": "params.count >" }
+            })),
+            // Pipeline referencing itself.
+            histogram_with(json!({ "cumulative_sum": { "buckets_path": "pipeline" } })),
+            // Derivative on terms buckets.
+            json!({
+                "terms": {
+                    "terms": { "field": "string_id" },
+                    "aggs": { "deriv": { "derivative": { "buckets_path": "_count" } } }
+                }
+            }),
+            // Parent pipeline aggregation without parent.
+            json!({ "deriv": { "derivative": { "buckets_path": "_count" } } }),
+            // Sibling pipeline aggregation without multi-bucket aggregation in path.
+            json!({ "avg": { "avg_bucket": { "buckets_path": "_count" } } }),
+        ];
+        for agg_req in invalid_requests {
+            assert!(exec(agg_req.clone()).is_err(), "{}", agg_req);
+        }
+        Ok(())
+    }
+}
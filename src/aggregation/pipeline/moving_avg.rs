@@ -0,0 +1,189 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use super::GapPolicy;
+use crate::TantivyError;
+
+/// A parent pipeline aggregation, which computes the average of a metric over a sliding window
+/// of the previous buckets of the parent `histogram` or `date_histogram`.
+///
+/// The window doesn't include the current bucket, so the first bucket has no value. With the
+/// `skip` gap policy, buckets without a value are not added to the window.
+///
+/// # JSON Format
+/// ```json
+/// {
+///     "sales_moving_avg": {
+///         "moving_avg": {
+///             "buckets_path": "sales",
+///             "window": 7,
+///             "model": "ewma",
+///             "settings": { "alpha": 0.5 }
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MovingAvgAggregation {
+    /// The path to the value of each bucket, see the [module docs](super).
+    pub buckets_path: String,
+    /// The number of buckets in the window. Defaults to 5.
+    #[serde(default = "default_window")]
+    pub window: usize,
+    /// How the values in the window are weighted.
+    #[serde(default)]
+    pub model: MovingAvgModel,
+    /// The settings of the model.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub settings: Option<MovingAvgSettings>,
+    /// How buckets without a value are handled.
+    #[serde(default)]
+    pub gap_policy: GapPolicy,
+}
+
+fn default_window() -> usize {
+    5
+}
+
+/// The weighting of the values in the window of a [`MovingAvgAggregation`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MovingAvgModel {
+    /// All values have the same weight.
+    #[default]
+    #[serde(rename = "simple")]
+    Simple,
+    /// The weight increases linearly, so that older values have a lower weight.
+    #[serde(rename = "linear")]
+    Linear,
+    /// Exponentially weighted moving average, the weight of older values decreases
+    /// exponentially depending on `alpha`.
+    #[serde(rename = "ewma")]
+    Ewma,
+}
+
+/// The settings of the [`MovingAvgModel`].
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MovingAvgSettings {
+    /// The decay of the `ewma` model between 0 and 1. Defaults to 0.3. Higher values give recent
+    /// values more weight.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub alpha: Option<f64>,
+}
+
+impl MovingAvgAggregation {
+    fn alpha(&self) -> crate::Result<f64> {
+        let alpha = self
+            .settings
+            .as_ref()
+            .and_then(|settings| settings.alpha)
+            .unwrap_or(0.3);
+        if !(alpha > 0.0 && alpha <= 1.0) {
+            return Err(TantivyError::InvalidArgument(format!(
+                "moving_avg alpha must be in (0, 1], but got {}",
+                alpha
+            )));
+        }
+        Ok(alpha)
+    }
+
+    fn average(&self, window: &VecDeque<f64>, alpha: f64) -> Option<f64> {
+        if window.is_empty() {
+            return None;
+        }
+        let average = match self.model {
+            MovingAvgModel::Simple => window.iter().sum::<f64>() / window.len() as f64,
+            MovingAvgModel::Linear => {
+                let weighted_sum: f64 = window
+                    .iter()
+                    .enumerate()
+                    .map(|(pos, value)| (pos + 1) as f64 * value)
+                    .sum();
+                let weights = (window.len() * (window.len() + 1) / 2) as f64;
+                weighted_sum / weights
+            }
+            MovingAvgModel::Ewma => window.iter().skip(1).fold(window[0], |average, value| {
+                alpha * value + (1.0 - alpha) * average
+            }),
+        };
+        Some(average)
+    }
+
+    pub(crate) fn compute(&self, values: &[Option<f64>]) -> crate::Result<Vec<Option<f64>>> {
+        if self.window == 0 {
+            return Err(TantivyError::InvalidArgument(
+                "moving_avg window must be greater than 0".to_string(),
+            ));
+        }
+        let alpha = self.alpha()?;
+        let mut window = VecDeque::with_capacity(self.window + 1);
+        let moving_averages = values
+            .iter()
+            .map(|value| {
+                let average = self.average(&window, alpha);
+                if let Some(value) = value {
+                    window.push_back(*value);
+                    if window.len() > self.window {
+                        window.pop_front();
+                    }
+                }
+                average
+            })
+            .collect();
+        Ok(moving_averages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moving_avg(model: MovingAvgModel, values: &[Option<f64>]) -> Vec<Option<f64>> {
+        let req = MovingAvgAggregation {
+            buckets_path: "_count".to_string(),
+            window: 3,
+            model,
+            settings: Some(MovingAvgSettings { alpha: Some(0.5) }),
+            gap_policy: GapPolicy::Skip,
+        };
+        req.compute(values).unwrap()
+    }
+
+    #[test]
+    fn moving_avg_models_test() {
+        let values = [Some(1.0), Some(2.0), None, Some(4.0), Some(8.0)];
+        assert_eq!(
+            moving_avg(MovingAvgModel::Simple, &values),
+            vec![None, Some(1.0), Some(1.5), Some(1.5), Some(7.0 / 3.0)]
+        );
+        assert_eq!(
+            moving_avg(MovingAvgModel::Linear, &values),
+            vec![
+                None,
+                Some(1.0),
+                Some(5.0 / 3.0),
+                Some(5.0 / 3.0),
+                Some(17.0 / 6.0)
+            ]
+        );
+        assert_eq!(
+            moving_avg(MovingAvgModel::Ewma, &values),
+            vec![None, Some(1.0), Some(1.5), Some(1.5), Some(2.75)]
+        );
+    }
+
+    #[test]
+    fn moving_avg_serde_test() {
+        let req: MovingAvgAggregation =
+            serde_json::from_str(r#"{ "buckets_path": "sales" }"#).unwrap();
+        assert_eq!(req.window, 5);
+        assert_eq!(req.model, MovingAvgModel::Simple);
+        assert_eq!(req.gap_policy, GapPolicy::Skip);
+        let req: MovingAvgAggregation = serde_json::from_str(
+            r#"{ "buckets_path": "sales", "model": "ewma", "gap_policy": "insert_zeros" }"#,
+        )
+        .unwrap();
+        assert_eq!(req.model, MovingAvgModel::Ewma);
+        assert_eq!(req.gap_policy, GapPolicy::InsertZeros);
+    }
+}
@@ -11,12 +11,19 @@ use super::agg_req::MetricAggregation;
 use super::agg_req_with_accessor::{
     AggregationsWithAccessor, BucketAggregationWithAccessor, MetricAggregationWithAccessor,
 };
-use super::bucket::{SegmentHistogramCollector, SegmentRangeCollector, SegmentTermCollector};
+use super::bucket::{
+    GeoGridType, SegmentAutoDateHistogramCollector, SegmentCompositeCollector,
+    SegmentDateHistogramCollector, SegmentFilterCollector, SegmentGeoGridCollector,
+    SegmentHistogramCollector, SegmentMultiTermsCollector, SegmentNestedCollector,
+    SegmentRangeCollector, SegmentSamplerCollector, SegmentSignificantTermsCollector,
+    SegmentTermCollector,
+};
 use super::collector::MAX_BUCKET_COUNT;
 use super::intermediate_agg_result::{IntermediateAggregationResults, IntermediateBucketResult};
 use super::metric::{
-    AverageAggregation, CountAggregation, MaxAggregation, MinAggregation, SegmentStatsCollector,
-    SegmentStatsType, StatsAggregation, SumAggregation,
+    AverageAggregation, CountAggregation, MaxAggregation, MinAggregation,
+    SegmentExtendedStatsCollector, SegmentStatsCollector, SegmentStatsType,
+    SegmentWeightedAverageCollector, StatsAggregation, SumAggregation,
 };
 use super::VecWithNames;
 use crate::aggregation::agg_req::BucketAggregationType;
@@ -70,22 +77,30 @@ pub(crate) fn build_segment_agg_collector(
         let req = &req.metrics.values[0];
         let stats_collector = match &req.metric {
             MetricAggregation::Average(AverageAggregation { .. }) => {
-                SegmentStatsCollector::from_req(req.field_type, SegmentStatsType::Average)
+                SegmentStatsCollector::from_req(SegmentStatsType::Average)
             }
             MetricAggregation::Count(CountAggregation { .. }) => {
-                SegmentStatsCollector::from_req(req.field_type, SegmentStatsType::Count)
+                SegmentStatsCollector::from_req(SegmentStatsType::Count)
             }
             MetricAggregation::Max(MaxAggregation { .. }) => {
-                SegmentStatsCollector::from_req(req.field_type, SegmentStatsType::Max)
+                SegmentStatsCollector::from_req(SegmentStatsType::Max)
             }
             MetricAggregation::Min(MinAggregation { .. }) => {
-                SegmentStatsCollector::from_req(req.field_type, SegmentStatsType::Min)
+                SegmentStatsCollector::from_req(SegmentStatsType::Min)
             }
             MetricAggregation::Stats(StatsAggregation { .. }) => {
-                SegmentStatsCollector::from_req(req.field_type, SegmentStatsType::Stats)
+                SegmentStatsCollector::from_req(SegmentStatsType::Stats)
+            }
+            MetricAggregation::ExtendedStats(extended_stats) => {
+                return Ok(Box::new(SegmentExtendedStatsCollector::from_req(
+                    extended_stats.sigma(),
+                )));
             }
             MetricAggregation::Sum(SumAggregation { .. }) => {
-                SegmentStatsCollector::from_req(req.field_type, SegmentStatsType::Sum)
+                SegmentStatsCollector::from_req(SegmentStatsType::Sum)
+            }
+            MetricAggregation::WeightedAverage(_) => {
+                return Ok(Box::<SegmentWeightedAverageCollector>::default());
             }
         };
 
@@ -146,7 +161,11 @@ impl SegmentAggregationCollector for GenericSegmentAggregationResultsCollector {
         };
         let metrics = self.metrics.map(VecWithNames::from_other);
 
-        Ok(IntermediateAggregationResults { metrics, buckets })
+        Ok(IntermediateAggregationResults {
+            metrics,
+            buckets,
+            memory_used: 0,
+        })
     }
 
     fn collect(
@@ -213,7 +232,11 @@ impl GenericSegmentAggregationResultsCollector {
         };
         let metrics = self.metrics.map(VecWithNames::from_other);
 
-        Ok(IntermediateAggregationResults { metrics, buckets })
+        Ok(IntermediateAggregationResults {
+            metrics,
+            buckets,
+            memory_used: 0,
+        })
     }
 
     pub(crate) fn from_req_and_validate(req: &AggregationsWithAccessor) -> crate::Result<Self> {
@@ -259,6 +282,8 @@ impl GenericSegmentAggregationResultsCollector {
 #[derive(Clone, Debug, PartialEq)]
 pub(crate) enum SegmentMetricResultCollector {
     Stats(SegmentStatsCollector),
+    ExtendedStats(SegmentExtendedStatsCollector),
+    WeightedAverage(SegmentWeightedAverageCollector),
 }
 
 impl SegmentMetricResultCollector {
@@ -266,40 +291,54 @@ impl SegmentMetricResultCollector {
         match &req.metric {
             MetricAggregation::Average(AverageAggregation { .. }) => {
                 Ok(SegmentMetricResultCollector::Stats(
-                    SegmentStatsCollector::from_req(req.field_type, SegmentStatsType::Average),
+                    SegmentStatsCollector::from_req(SegmentStatsType::Average),
                 ))
             }
             MetricAggregation::Count(CountAggregation { .. }) => {
                 Ok(SegmentMetricResultCollector::Stats(
-                    SegmentStatsCollector::from_req(req.field_type, SegmentStatsType::Count),
+                    SegmentStatsCollector::from_req(SegmentStatsType::Count),
                 ))
             }
             MetricAggregation::Max(MaxAggregation { .. }) => {
                 Ok(SegmentMetricResultCollector::Stats(
-                    SegmentStatsCollector::from_req(req.field_type, SegmentStatsType::Max),
+                    SegmentStatsCollector::from_req(SegmentStatsType::Max),
                 ))
             }
             MetricAggregation::Min(MinAggregation { .. }) => {
                 Ok(SegmentMetricResultCollector::Stats(
-                    SegmentStatsCollector::from_req(req.field_type, SegmentStatsType::Min),
+                    SegmentStatsCollector::from_req(SegmentStatsType::Min),
                 ))
             }
             MetricAggregation::Stats(StatsAggregation { .. }) => {
                 Ok(SegmentMetricResultCollector::Stats(
-                    SegmentStatsCollector::from_req(req.field_type, SegmentStatsType::Stats),
+                    SegmentStatsCollector::from_req(SegmentStatsType::Stats),
+                ))
+            }
+            MetricAggregation::ExtendedStats(extended_stats) => {
+                Ok(SegmentMetricResultCollector::ExtendedStats(
+                    SegmentExtendedStatsCollector::from_req(extended_stats.sigma()),
                 ))
             }
             MetricAggregation::Sum(SumAggregation { .. }) => {
                 Ok(SegmentMetricResultCollector::Stats(
-                    SegmentStatsCollector::from_req(req.field_type, SegmentStatsType::Sum),
+                    SegmentStatsCollector::from_req(SegmentStatsType::Sum),
                 ))
             }
+            MetricAggregation::WeightedAverage(_) => Ok(
+                SegmentMetricResultCollector::WeightedAverage(Default::default()),
+            ),
         }
     }
     pub(crate) fn collect_block(&mut self, doc: &[DocId], metric: &MetricAggregationWithAccessor) {
         match self {
             SegmentMetricResultCollector::Stats(stats_collector) => {
-                stats_collector.collect_block(doc, &metric.accessor);
+                stats_collector.collect_block(doc, &metric.values);
+            }
+            SegmentMetricResultCollector::ExtendedStats(extended_stats_collector) => {
+                extended_stats_collector.collect_block(doc, &metric.values);
+            }
+            SegmentMetricResultCollector::WeightedAverage(weighted_average_collector) => {
+                weighted_average_collector.collect_block(doc, &metric.values, metric.weights());
             }
         }
     }
@@ -313,7 +352,16 @@ impl SegmentMetricResultCollector {
 pub(crate) enum SegmentBucketResultCollector {
     Range(SegmentRangeCollector),
     Histogram(Box<SegmentHistogramCollector>),
+    DateHistogram(Box<SegmentDateHistogramCollector>),
+    AutoDateHistogram(Box<SegmentAutoDateHistogramCollector>),
     Terms(Box<SegmentTermCollector>),
+    MultiTerms(Box<SegmentMultiTermsCollector>),
+    SignificantTerms(Box<SegmentSignificantTermsCollector>),
+    Filter(SegmentFilterCollector),
+    Composite(Box<SegmentCompositeCollector>),
+    GeoGrid(Box<SegmentGeoGridCollector>),
+    Nested(SegmentNestedCollector),
+    Sampler(Box<SegmentSamplerCollector>),
 }
 
 impl SegmentBucketResultCollector {
@@ -325,12 +373,39 @@ impl SegmentBucketResultCollector {
             SegmentBucketResultCollector::Terms(terms) => {
                 terms.into_intermediate_bucket_result(agg_with_accessor)
             }
+            SegmentBucketResultCollector::MultiTerms(multi_terms) => {
+                multi_terms.into_intermediate_bucket_result(agg_with_accessor)
+            }
+            SegmentBucketResultCollector::SignificantTerms(significant_terms) => {
+                significant_terms.into_intermediate_bucket_result(agg_with_accessor)
+            }
             SegmentBucketResultCollector::Range(range) => {
                 range.into_intermediate_bucket_result(agg_with_accessor)
             }
             SegmentBucketResultCollector::Histogram(histogram) => {
                 histogram.into_intermediate_bucket_result(agg_with_accessor)
             }
+            SegmentBucketResultCollector::DateHistogram(histogram) => {
+                histogram.into_intermediate_bucket_result(agg_with_accessor)
+            }
+            SegmentBucketResultCollector::AutoDateHistogram(histogram) => {
+                histogram.into_intermediate_bucket_result(agg_with_accessor)
+            }
+            SegmentBucketResultCollector::Filter(filter) => {
+                filter.into_intermediate_bucket_result(agg_with_accessor)
+            }
+            SegmentBucketResultCollector::Composite(composite) => {
+                composite.into_intermediate_bucket_result(agg_with_accessor)
+            }
+            SegmentBucketResultCollector::GeoGrid(geo_grid) => {
+                geo_grid.into_intermediate_bucket_result(agg_with_accessor)
+            }
+            SegmentBucketResultCollector::Nested(nested) => {
+                nested.into_intermediate_bucket_result(agg_with_accessor)
+            }
+            SegmentBucketResultCollector::Sampler(sampler) => {
+                sampler.into_intermediate_bucket_result(agg_with_accessor)
+            }
         }
     }
 
@@ -339,6 +414,17 @@ impl SegmentBucketResultCollector {
             BucketAggregationType::Terms(terms_req) => Ok(Self::Terms(Box::new(
                 SegmentTermCollector::from_req_and_validate(terms_req, &req.sub_aggregation)?,
             ))),
+            BucketAggregationType::MultiTerms(multi_terms) => Ok(Self::MultiTerms(Box::new(
+                SegmentMultiTermsCollector::from_req_and_validate(multi_terms, req)?,
+            ))),
+            BucketAggregationType::SignificantTerms(significant_terms) => {
+                Ok(Self::SignificantTerms(Box::new(
+                    SegmentSignificantTermsCollector::from_req_and_validate(
+                        significant_terms,
+                        req,
+                    )?,
+                )))
+            }
             BucketAggregationType::Range(range_req) => {
                 Ok(Self::Range(SegmentRangeCollector::from_req_and_validate(
                     range_req,
@@ -352,9 +438,55 @@ impl SegmentBucketResultCollector {
                     histogram,
                     &req.sub_aggregation,
                     req.field_type,
-                    &req.accessor,
+                    req.column(),
+                    &req.memory_account,
+                )?,
+            ))),
+            BucketAggregationType::DateHistogram(histogram) => Ok(Self::DateHistogram(Box::new(
+                SegmentDateHistogramCollector::from_req_and_validate(
+                    histogram,
+                    &req.sub_aggregation,
+                    req.field_type,
+                )?,
+            ))),
+            BucketAggregationType::AutoDateHistogram(histogram) => Ok(Self::AutoDateHistogram(
+                Box::new(SegmentAutoDateHistogramCollector::from_req_and_validate(
+                    histogram,
+                    &req.sub_aggregation,
+                    req.field_type,
+                )?),
+            )),
+            BucketAggregationType::Filter(_) | BucketAggregationType::Filters(_) => {
+                Ok(Self::Filter(SegmentFilterCollector::from_req_and_validate(
+                    req,
+                    &req.bucket_count,
+                )?))
+            }
+            BucketAggregationType::Composite(composite) => Ok(Self::Composite(Box::new(
+                SegmentCompositeCollector::from_req_and_validate(composite, req)?,
+            ))),
+            BucketAggregationType::GeoHashGrid(geo_grid) => Ok(Self::GeoGrid(Box::new(
+                SegmentGeoGridCollector::from_req_and_validate(
+                    GeoGridType::GeoHash,
+                    geo_grid,
+                    req,
                 )?,
             ))),
+            BucketAggregationType::GeoTileGrid(geo_grid) => Ok(Self::GeoGrid(Box::new(
+                SegmentGeoGridCollector::from_req_and_validate(
+                    GeoGridType::GeoTile,
+                    geo_grid,
+                    req,
+                )?,
+            ))),
+            BucketAggregationType::Nested(_) => Ok(Self::Nested(
+                SegmentNestedCollector::from_req_and_validate(req)?,
+            )),
+            BucketAggregationType::Sampler(_) | BucketAggregationType::RandomSampler(_) => {
+                Ok(Self::Sampler(Box::new(
+                    SegmentSamplerCollector::from_req_and_validate(req)?,
+                )))
+            }
         }
     }
 
@@ -372,9 +504,36 @@ impl SegmentBucketResultCollector {
             SegmentBucketResultCollector::Histogram(histogram) => {
                 histogram.collect_block(doc, bucket_with_accessor, force_flush)?;
             }
+            SegmentBucketResultCollector::DateHistogram(histogram) => {
+                histogram.collect_block(doc, bucket_with_accessor, force_flush)?;
+            }
+            SegmentBucketResultCollector::AutoDateHistogram(histogram) => {
+                histogram.collect_block(doc, bucket_with_accessor, force_flush)?;
+            }
             SegmentBucketResultCollector::Terms(terms) => {
                 terms.collect_block(doc, bucket_with_accessor, force_flush)?;
             }
+            SegmentBucketResultCollector::MultiTerms(multi_terms) => {
+                multi_terms.collect_block(doc, bucket_with_accessor, force_flush)?;
+            }
+            SegmentBucketResultCollector::SignificantTerms(significant_terms) => {
+                significant_terms.collect_block(doc, bucket_with_accessor, force_flush)?;
+            }
+            SegmentBucketResultCollector::Filter(filter) => {
+                filter.collect_block(doc, bucket_with_accessor, force_flush)?;
+            }
+            SegmentBucketResultCollector::Composite(composite) => {
+                composite.collect_block(doc, bucket_with_accessor, force_flush)?;
+            }
+            SegmentBucketResultCollector::GeoGrid(geo_grid) => {
+                geo_grid.collect_block(doc, bucket_with_accessor, force_flush)?;
+            }
+            SegmentBucketResultCollector::Nested(nested) => {
+                nested.collect_block(doc, bucket_with_accessor, force_flush)?;
+            }
+            SegmentBucketResultCollector::Sampler(sampler) => {
+                sampler.collect_block(doc, bucket_with_accessor, force_flush)?;
+            }
         }
         Ok(())
     }
@@ -0,0 +1,350 @@
+//! The versioned binary format of [`IntermediateAggregationResults`], to exchange them between
+//! the nodes of a distributed search.
+//!
+//! # Layout
+//! - magic number `TAGG` (4 bytes)
+//! - format version (u16, little endian)
+//! - fingerprint of the aggregation request (u32, little endian)
+//! - the result encoded as [CBOR](https://www.rfc-editor.org/rfc/rfc8949)
+//!
+//! # Compatibility
+//! Encoded results can be decoded by every tantivy version which supports their format version.
+//! A tantivy version supports all format versions up to [`INTERMEDIATE_RESULT_FORMAT_VERSION`],
+//! so nodes must be upgraded before they receive results of a newer format version. Changes which
+//! can't be read by older versions, like a new required field, increase the format version.
+//! Optional fields may be added without a new format version, since older versions skip unknown
+//! fields.
+//!
+//! New enum variants, e.g. a new kind of aggregation or of metric result, are not covered by
+//! this: older versions fail to decode the payloads which contain them, even if their format
+//! version is supported. The nodes must then be upgraded before such aggregations are requested.
+//!
+//! The fingerprint ensures that only results of the same aggregation request are merged, since
+//! the merge relies on the identical structure of the results.
+
+use common::BinarySerializable;
+
+use super::agg_req::Aggregations;
+use super::intermediate_agg_result::IntermediateAggregationResults;
+use super::AggregationError;
+use crate::error::DataCorruption;
+use crate::TantivyError;
+
+/// The format version of the binary encoding of [`IntermediateAggregationResults`], which is
+/// written by this version of tantivy.
+pub const INTERMEDIATE_RESULT_FORMAT_VERSION: u16 = 1;
+
+const MAGIC_NUMBER: [u8; 4] = *b"TAGG";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Header {
+    version: u16,
+    request_fingerprint: u32,
+}
+
+impl Header {
+    fn write(&self, output: &mut Vec<u8>) -> crate::Result<()> {
+        output.extend_from_slice(&MAGIC_NUMBER);
+        self.version.serialize(output)?;
+        self.request_fingerprint.serialize(output)?;
+        Ok(())
+    }
+
+    /// Reads the header and advances `bytes` to the payload.
+    fn read(bytes: &mut &[u8]) -> crate::Result<Header> {
+        let corrupted =
+            |_| DataCorruption::comment_only("truncated header of intermediate aggregation result");
+        if !bytes.starts_with(&MAGIC_NUMBER) {
+            return Err(DataCorruption::comment_only(
+                "bytes are not an encoded intermediate aggregation result",
+            )
+            .into());
+        }
+        *bytes = &bytes[MAGIC_NUMBER.len()..];
+        let version = u16::deserialize(bytes).map_err(corrupted)?;
+        let request_fingerprint = u32::deserialize(bytes).map_err(corrupted)?;
+        if version == 0 || version > INTERMEDIATE_RESULT_FORMAT_VERSION {
+            return Err(AggregationError::UnsupportedFormatVersion {
+                version,
+                max_supported: INTERMEDIATE_RESULT_FORMAT_VERSION,
+            }
+            .into());
+        }
+        Ok(Header {
+            version,
+            request_fingerprint,
+        })
+    }
+}
+
+/// Computes a fingerprint of the aggregation request, which is stable across processes.
+fn request_fingerprint(req: &Aggregations) -> crate::Result<u32> {
+    // The conversion to `Value` sorts the keys of the request.
+    let canonical = serde_json::to_value(req)
+        .and_then(|value| serde_json::to_vec(&value))
+        .map_err(|err| {
+            TantivyError::InvalidArgument(format!("Could not serialize aggregation request: {err}"))
+        })?;
+    Ok(murmurhash32::murmurhash2(&canonical))
+}
+
+fn encode(header: Header, result: &IntermediateAggregationResults) -> crate::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    header.write(&mut output)?;
+    ciborium::into_writer(result, &mut output).map_err(|err| {
+        TantivyError::InternalError(format!(
+            "Could not encode intermediate aggregation result: {err}"
+        ))
+    })?;
+    Ok(output)
+}
+
+/// Decodes the result and returns it with its header.
+fn decode(mut bytes: &[u8]) -> crate::Result<(Header, IntermediateAggregationResults)> {
+    let header = Header::read(&mut bytes)?;
+    let result = ciborium::from_reader(bytes).map_err(|err| {
+        DataCorruption::comment_only(format!(
+            "Could not decode intermediate aggregation result: {err}"
+        ))
+    })?;
+    Ok((header, result))
+}
+
+impl IntermediateAggregationResults {
+    /// Encodes the result in the versioned binary format, to be sent to another node.
+    ///
+    /// `req` is the aggregation request the result was collected for. It is only used to compute
+    /// a fingerprint, which prevents merging results of different requests.
+    pub fn to_bytes(&self, req: &Aggregations) -> crate::Result<Vec<u8>> {
+        let header = Header {
+            version: INTERMEDIATE_RESULT_FORMAT_VERSION,
+            request_fingerprint: request_fingerprint(req)?,
+        };
+        encode(header, self)
+    }
+
+    /// Decodes a result, which was encoded via [`to_bytes`](Self::to_bytes) for the same
+    /// aggregation request.
+    ///
+    /// Returns [`AggregationError::RequestMismatch`] if the result was encoded for another request
+    /// and [`AggregationError::UnsupportedFormatVersion`] if the result was encoded by a newer
+    /// tantivy version with an unknown format version.
+    pub fn from_bytes(bytes: &[u8], req: &Aggregations) -> crate::Result<Self> {
+        let (header, result) = decode(bytes)?;
+        if header.request_fingerprint != request_fingerprint(req)? {
+            return Err(AggregationError::RequestMismatch.into());
+        }
+        Ok(result)
+    }
+
+    /// Merges encoded results and returns the merged result in the current format version.
+    ///
+    /// The results are fully decoded, merged, and the merged result is encoded again.
+    ///
+    /// The merge doesn't require the aggregation request, e.g. for an intermediate node in a
+    /// scatter-gather tree. All results need to be encoded for the same request, otherwise
+    /// [`AggregationError::RequestMismatch`] is returned.
+    pub fn merge_bytes<B: AsRef<[u8]>>(encoded_results: &[B]) -> crate::Result<Vec<u8>> {
+        let mut merged: Option<(Header, IntermediateAggregationResults)> = None;
+        for bytes in encoded_results {
+            let (header, result) = decode(bytes.as_ref())?;
+            match merged.as_mut() {
+                Some((merged_header, merged_result)) => {
+                    if merged_header.request_fingerprint != header.request_fingerprint {
+                        return Err(AggregationError::RequestMismatch.into());
+                    }
+                    merged_result.merge_fruits(result)?;
+                }
+                None => merged = Some((header, result)),
+            }
+        }
+        let (header, result) = merged.ok_or_else(|| {
+            TantivyError::InvalidArgument("No intermediate results to merge".to_string())
+        })?;
+        let header = Header {
+            version: INTERMEDIATE_RESULT_FORMAT_VERSION,
+            ..header
+        };
+        encode(header, &result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::aggregation::tests::get_test_index_2_segments;
+    use crate::aggregation::DistributedAggregationCollector;
+    use crate::query::{AllQuery, TermQuery};
+    use crate::schema::IndexRecordOption;
+    use crate::{Index, Term};
+
+    fn get_request() -> Aggregations {
+        serde_json::from_value(json!({
+            "terms": {
+                "terms": { "field": "text" },
+                "aggs": {
+                    "stats": { "stats": { "field": "score" } },
+                    "histogram": { "histogram": { "field": "score", "interval": 10.0 } }
+                }
+            },
+            "range": {
+                "range": { "field": "score", "ranges": [ { "to": 10.0 }, { "from": 10.0 } ] },
+                "aggs": { "avg": { "avg": { "field": "score_f64" } } }
+            },
+            "max": { "max": { "field": "score_i64" } }
+        }))
+        .unwrap()
+    }
+
+    fn collect(
+        index: &Index,
+        req: &Aggregations,
+        term: Option<&str>,
+    ) -> crate::Result<IntermediateAggregationResults> {
+        let collector = DistributedAggregationCollector::from_aggs(req.clone(), None);
+        let searcher = index.reader()?.searcher();
+        if let Some(term) = term {
+            let text_field = index.schema().get_field("text").unwrap();
+            let term_query = TermQuery::new(
+                Term::from_field_text(text_field, term),
+                IndexRecordOption::Basic,
+            );
+            searcher.search(&term_query, &collector)
+        } else {
+            searcher.search(&AllQuery, &collector)
+        }
+    }
+
+    #[test]
+    fn test_intermediate_result_roundtrip() -> crate::Result<()> {
+        let index = get_test_index_2_segments(false)?;
+        let req = get_request();
+        let result = collect(&index, &req, None)?;
+
+        let bytes = result.to_bytes(&req)?;
+        assert_eq!(&bytes[..4], b"TAGG");
+        assert_eq!(
+            IntermediateAggregationResults::from_bytes(&bytes, &req)?,
+            result
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_intermediate_result_merge_bytes() -> crate::Result<()> {
+        let index = get_test_index_2_segments(false)?;
+        let req = get_request();
+        let cool = collect(&index, &req, Some("cool"))?;
+        let nohit = collect(&index, &req, Some("nohit"))?;
+        // A node without segments returns an empty result.
+        let empty = IntermediateAggregationResults::default();
+
+        let merged_bytes = IntermediateAggregationResults::merge_bytes(&[
+            empty.to_bytes(&req)?,
+            cool.to_bytes(&req)?,
+            nohit.to_bytes(&req)?,
+        ])?;
+        let merged = IntermediateAggregationResults::from_bytes(&merged_bytes, &req)?;
+
+        let expected = collect(&index, &req, None)?;
+        let schema = index.schema();
+        assert_eq!(
+            serde_json::to_value(merged.into_final_bucket_result(req.clone(), &schema)?)?,
+            serde_json::to_value(expected.into_final_bucket_result(req, &schema)?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_intermediate_result_request_mismatch() -> crate::Result<()> {
+        let index = get_test_index_2_segments(false)?;
+        let req = get_request();
+        let mut other_req = get_request();
+        other_req.remove("max");
+        let bytes = collect(&index, &req, None)?.to_bytes(&req)?;
+        let other_bytes = collect(&index, &other_req, None)?.to_bytes(&other_req)?;
+
+        let err = IntermediateAggregationResults::from_bytes(&bytes, &other_req).unwrap_err();
+        assert!(matches!(
+            err,
+            TantivyError::AggregationError(AggregationError::RequestMismatch)
+        ));
+        let err = IntermediateAggregationResults::merge_bytes(&[bytes, other_bytes]).unwrap_err();
+        assert!(matches!(
+            err,
+            TantivyError::AggregationError(AggregationError::RequestMismatch)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_intermediate_result_mismatched_trees() -> crate::Result<()> {
+        let index = get_test_index_2_segments(false)?;
+        let req = get_request();
+        // The same aggregation names with other aggregation types.
+        let other_req: Aggregations = serde_json::from_value(json!({
+            "terms": { "histogram": { "field": "score", "interval": 10.0 } },
+            "range": { "terms": { "field": "text" } },
+            "max": { "min": { "field": "score_i64" } }
+        }))
+        .unwrap();
+        let mut fewer_req = get_request();
+        fewer_req.remove("range");
+        // The results are encoded with the fingerprint of `req`, like corrupted payloads.
+        let bytes = collect(&index, &req, None)?.to_bytes(&req)?;
+        let other_bytes = collect(&index, &other_req, None)?.to_bytes(&req)?;
+        let fewer_bytes = collect(&index, &fewer_req, None)?.to_bytes(&req)?;
+
+        for invalid in [&other_bytes, &fewer_bytes] {
+            let err = IntermediateAggregationResults::merge_bytes(&[&bytes, invalid]).unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    TantivyError::AggregationError(AggregationError::IncompatibleResults(_))
+                ),
+                "{:?}",
+                err
+            );
+            let err = IntermediateAggregationResults::from_bytes(invalid, &req)?
+                .into_final_bucket_result(req.clone(), &index.schema())
+                .unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    TantivyError::AggregationError(AggregationError::IncompatibleResults(_))
+                ),
+                "{:?}",
+                err
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_intermediate_result_invalid_bytes() -> crate::Result<()> {
+        let req = get_request();
+        let bytes = IntermediateAggregationResults::default().to_bytes(&req)?;
+
+        let mut newer_version = bytes.clone();
+        newer_version[4..6]
+            .copy_from_slice(&(INTERMEDIATE_RESULT_FORMAT_VERSION + 1).to_le_bytes());
+        let err = IntermediateAggregationResults::from_bytes(&newer_version, &req).unwrap_err();
+        assert!(matches!(
+            err,
+            TantivyError::AggregationError(AggregationError::UnsupportedFormatVersion {
+                max_supported: INTERMEDIATE_RESULT_FORMAT_VERSION,
+                ..
+            })
+        ));
+
+        for invalid in [&b"TAG"[..], &bytes[..7], b"{\"metrics\":null}"] {
+            let err = IntermediateAggregationResults::from_bytes(invalid, &req).unwrap_err();
+            assert!(matches!(err, TantivyError::DataCorruption(_)), "{:?}", err);
+        }
+        let err = IntermediateAggregationResults::merge_bytes::<Vec<u8>>(&[]).unwrap_err();
+        assert!(matches!(err, TantivyError::InvalidArgument(_)));
+        Ok(())
+    }
+}
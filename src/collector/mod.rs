@@ -83,7 +83,7 @@
 
 use downcast_rs::impl_downcast;
 
-use crate::{DocId, Score, SegmentOrdinal, SegmentReader};
+use crate::{DocId, Executor, Score, SegmentOrdinal, SegmentReader};
 
 mod count_collector;
 pub use self::count_collector::Count;
@@ -169,37 +169,73 @@ pub trait Collector: Sync + Send {
         segment_ord: u32,
         reader: &SegmentReader,
     ) -> crate::Result<<Self::Child as SegmentCollector>::Fruit> {
-        let mut segment_collector = self.for_segment(segment_ord, reader)?;
-
-        match (reader.alive_bitset(), self.requires_scoring()) {
-            (Some(alive_bitset), true) => {
-                weight.for_each(reader, &mut |doc, score| {
-                    if alive_bitset.is_alive(doc) {
-                        segment_collector.collect(doc, score);
-                    }
-                })?;
-            }
-            (Some(alive_bitset), false) => {
-                weight.for_each_no_score(reader, &mut |doc| {
-                    if alive_bitset.is_alive(doc) {
-                        segment_collector.collect(doc, 0.0);
-                    }
-                })?;
-            }
-            (None, true) => {
-                weight.for_each(reader, &mut |doc, score| {
+        let segment_collector = self.for_segment(segment_ord, reader)?;
+        collect_segment_with(segment_collector, weight, reader, self.requires_scoring())
+    }
+
+    /// Collects the given segments on the executor, and returns their fruits in the same order.
+    ///
+    /// By default, each segment is collected independently with
+    /// [`Collector::collect_segment`]. Collectors able to skip a segment, given the fruits of
+    /// the segments already collected, can override this method to share some state for the
+    /// duration of the search.
+    ///
+    /// Collectors whose fruit is not changed by a segment without any match, like the top-K
+    /// collectors, can also skip the segments for which [`Weight::may_match`] returns `false`.
+    /// This is opt-in, as the fruit of some collectors depends on the set of segments which
+    /// were collected. The aggregation collectors, for instance, still harvest a segment
+    /// collector for such segments, but don't iterate over their documents.
+    fn collect_segments(
+        &self,
+        weight: &dyn Weight,
+        segment_readers: Vec<(SegmentOrdinal, &SegmentReader)>,
+        executor: &Executor,
+    ) -> crate::Result<Vec<<Self::Child as SegmentCollector>::Fruit>> {
+        executor.map(
+            |(segment_ord, segment_reader)| {
+                self.collect_segment(weight, segment_ord, segment_reader)
+            },
+            segment_readers.into_iter(),
+        )
+    }
+}
+
+/// Collects the documents of `weight` in the segment with `segment_collector`, and returns its
+/// fruit.
+pub(crate) fn collect_segment_with<TSegmentCollector: SegmentCollector>(
+    mut segment_collector: TSegmentCollector,
+    weight: &dyn Weight,
+    reader: &SegmentReader,
+    requires_scoring: bool,
+) -> crate::Result<TSegmentCollector::Fruit> {
+    match (reader.alive_bitset(), requires_scoring) {
+        (Some(alive_bitset), true) => {
+            weight.for_each(reader, &mut |doc, score| {
+                if alive_bitset.is_alive(doc) {
                     segment_collector.collect(doc, score);
-                })?;
-            }
-            (None, false) => {
-                weight.for_each_no_score(reader, &mut |doc| {
+                }
+            })?;
+        }
+        (Some(alive_bitset), false) => {
+            weight.for_each_no_score(reader, &mut |doc| {
+                if alive_bitset.is_alive(doc) {
                     segment_collector.collect(doc, 0.0);
-                })?;
-            }
+                }
+            })?;
+        }
+        (None, true) => {
+            weight.for_each(reader, &mut |doc, score| {
+                segment_collector.collect(doc, score);
+            })?;
+        }
+        (None, false) => {
+            weight.for_each_no_score(reader, &mut |doc| {
+                segment_collector.collect(doc, 0.0);
+            })?;
         }
-
-        Ok(segment_collector.harvest())
     }
+
+    Ok(segment_collector.harvest())
 }
 
 impl<TSegmentCollector: SegmentCollector> SegmentCollector for Option<TSegmentCollector> {
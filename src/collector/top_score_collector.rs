@@ -1,6 +1,8 @@
+use std::cmp::Reverse;
 use std::collections::BinaryHeap;
 use std::fmt;
 use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 use columnar::ColumnValues;
@@ -14,7 +16,7 @@ use crate::collector::{
 };
 use crate::fastfield::{FastFieldNotAvailableError, FastValue};
 use crate::query::Weight;
-use crate::{DocAddress, DocId, Score, SegmentOrdinal, SegmentReader, TantivyError};
+use crate::{DocAddress, DocId, Executor, Score, SegmentOrdinal, SegmentReader, TantivyError};
 
 struct FastFieldConvertCollector<
     TCollector: Collector<Fruit = Vec<(u64, DocAddress)>>,
@@ -39,6 +41,46 @@ where
         segment_local_id: crate::SegmentOrdinal,
         segment: &SegmentReader,
     ) -> crate::Result<Self::Child> {
+        self.check_field(segment)?;
+        self.collector.for_segment(segment_local_id, segment)
+    }
+
+    fn requires_scoring(&self) -> bool {
+        self.collector.requires_scoring()
+    }
+
+    fn merge_fruits(
+        &self,
+        segment_fruits: Vec<<Self::Child as SegmentCollector>::Fruit>,
+    ) -> crate::Result<Self::Fruit> {
+        let raw_result = self.collector.merge_fruits(segment_fruits)?;
+        let transformed_result = raw_result
+            .into_iter()
+            .map(|(score, doc_address)| (TFastValue::from_u64(score), doc_address))
+            .collect::<Vec<_>>();
+        Ok(transformed_result)
+    }
+
+    fn collect_segments(
+        &self,
+        weight: &dyn Weight,
+        segment_readers: Vec<(SegmentOrdinal, &SegmentReader)>,
+        executor: &Executor,
+    ) -> crate::Result<Vec<<Self::Child as SegmentCollector>::Fruit>> {
+        for (_, segment_reader) in &segment_readers {
+            self.check_field(segment_reader)?;
+        }
+        self.collector
+            .collect_segments(weight, segment_readers, executor)
+    }
+}
+
+impl<TCollector, TFastValue> FastFieldConvertCollector<TCollector, TFastValue>
+where
+    TCollector: Collector<Fruit = Vec<(u64, DocAddress)>>,
+    TFastValue: FastValue,
+{
+    fn check_field(&self, segment: &SegmentReader) -> crate::Result<()> {
         let schema = segment.schema();
         let field = schema.get_field(&self.field)?;
         let field_entry = schema.get_field_entry(field);
@@ -58,6 +100,54 @@ where
                 requested_type
             )));
         }
+        Ok(())
+    }
+}
+
+/// Returns the segments which may contain a document matching `weight`.
+///
+/// A segment without any match does not change the top-K documents, so the top-K collectors do
+/// not need to collect it.
+fn matching_segments<'a>(
+    weight: &dyn Weight,
+    segment_readers: Vec<(SegmentOrdinal, &'a SegmentReader)>,
+) -> Vec<(SegmentOrdinal, &'a SegmentReader)> {
+    segment_readers
+        .into_iter()
+        .filter(|(_, segment_reader)| weight.may_match(segment_reader))
+        .collect()
+}
+
+/// Collects the top-K documents by the `u64` representation of a fast field.
+///
+/// The segments which cannot match the query are skipped. The other segments are visited by decreasing maximum value of the field, as recorded in their fast
+/// field statistics. A segment whose maximum value is lower than the K-th value of a segment
+/// already collected cannot contribute to the top-K, and is skipped.
+struct TopDocsByFastField {
+    collector: CustomScoreTopCollector<ScorerByField, u64>,
+    field: String,
+    num_docs: usize,
+}
+
+impl TopDocsByFastField {
+    fn max_value(&self, segment_reader: &SegmentReader) -> u64 {
+        segment_reader
+            .fast_field_stats(&self.field)
+            .map(|stats| stats.max_value)
+            .unwrap_or(u64::MAX)
+    }
+}
+
+impl Collector for TopDocsByFastField {
+    type Fruit = Vec<(u64, DocAddress)>;
+
+    type Child = <CustomScoreTopCollector<ScorerByField, u64> as Collector>::Child;
+
+    fn for_segment(
+        &self,
+        segment_local_id: SegmentOrdinal,
+        segment: &SegmentReader,
+    ) -> crate::Result<Self::Child> {
         self.collector.for_segment(segment_local_id, segment)
     }
 
@@ -65,16 +155,37 @@ where
         self.collector.requires_scoring()
     }
 
-    fn merge_fruits(
+    fn merge_fruits(&self, segment_fruits: Vec<Self::Fruit>) -> crate::Result<Self::Fruit> {
+        self.collector.merge_fruits(segment_fruits)
+    }
+
+    fn collect_segments(
         &self,
-        segment_fruits: Vec<<Self::Child as SegmentCollector>::Fruit>,
-    ) -> crate::Result<Self::Fruit> {
-        let raw_result = self.collector.merge_fruits(segment_fruits)?;
-        let transformed_result = raw_result
-            .into_iter()
-            .map(|(score, doc_address)| (TFastValue::from_u64(score), doc_address))
-            .collect::<Vec<_>>();
-        Ok(transformed_result)
+        weight: &dyn Weight,
+        segment_readers: Vec<(SegmentOrdinal, &SegmentReader)>,
+        executor: &Executor,
+    ) -> crate::Result<Vec<Self::Fruit>> {
+        let mut segment_readers = matching_segments(weight, segment_readers);
+        segment_readers.sort_by_key(|(_, segment_reader)| Reverse(self.max_value(segment_reader)));
+        let threshold = AtomicU64::new(0);
+        let mut fruits = executor.map(
+            |(segment_ord, segment_reader)| {
+                if self.max_value(segment_reader) < threshold.load(Ordering::Relaxed) {
+                    return Ok((segment_ord, Vec::new()));
+                }
+                let fruit = self
+                    .collector
+                    .collect_segment(weight, segment_ord, segment_reader)?;
+                if let Some((kth_value, _)) = fruit.get(self.num_docs - 1) {
+                    threshold.fetch_max(*kth_value, Ordering::Relaxed);
+                }
+                Ok((segment_ord, fruit))
+            },
+            segment_readers.into_iter(),
+        )?;
+        // Ties are broken by the order of the fruits, so they are returned in the segment order.
+        fruits.sort_by_key(|(segment_ord, _)| *segment_ord);
+        Ok(fruits.into_iter().map(|(_, fruit)| fruit).collect())
     }
 }
 
@@ -293,12 +404,17 @@ impl TopDocs {
         self,
         field: impl ToString,
     ) -> impl Collector<Fruit = Vec<(u64, DocAddress)>> {
-        CustomScoreTopCollector::new(
-            ScorerByField {
-                field: field.to_string(),
-            },
-            self.0.into_tscore(),
-        )
+        let num_docs = self.0.limit + self.0.offset;
+        TopDocsByFastField {
+            collector: CustomScoreTopCollector::new(
+                ScorerByField {
+                    field: field.to_string(),
+                },
+                self.0.into_tscore(),
+            ),
+            field: field.to_string(),
+            num_docs,
+        }
     }
 
     /// Set top-K to rank documents by a given fast field.
@@ -696,6 +812,19 @@ impl Collector for TopDocs {
             .collect();
         Ok(fruit)
     }
+    fn collect_segments(
+        &self,
+        weight: &dyn Weight,
+        segment_readers: Vec<(SegmentOrdinal, &SegmentReader)>,
+        executor: &Executor,
+    ) -> crate::Result<Vec<<Self::Child as SegmentCollector>::Fruit>> {
+        executor.map(
+            |(segment_ord, segment_reader)| {
+                self.collect_segment(weight, segment_ord, segment_reader)
+            },
+            matching_segments(weight, segment_readers).into_iter(),
+        )
+    }
 }
 
 /// Segment Collector associated with `TopDocs`.
@@ -715,13 +844,16 @@ impl SegmentCollector for TopScoreSegmentCollector {
 
 #[cfg(test)]
 mod tests {
+    use std::cmp::Reverse;
+
     use super::TopDocs;
     use crate::collector::Collector;
+    use crate::indexer::NoMergePolicy;
     use crate::query::{AllQuery, Query, QueryParser};
     use crate::schema::{Field, Schema, FAST, STORED, TEXT};
     use crate::time::format_description::well_known::Rfc3339;
     use crate::time::OffsetDateTime;
-    use crate::{DateTime, DocAddress, DocId, Index, IndexWriter, Score, SegmentReader};
+    use crate::{DateTime, DocAddress, DocId, Executor, Index, IndexWriter, Score, SegmentReader};
 
     fn make_index() -> crate::Result<Index> {
         let mut schema_builder = Schema::builder();
@@ -993,6 +1125,50 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_top_field_collector_prunes_segments() -> crate::Result<()> {
+        let mut schema_builder = Schema::builder();
+        let size = schema_builder.add_i64_field(SIZE, FAST);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.set_merge_policy(Box::new(NoMergePolicy));
+        for segment_values in [&[1i64, 2, 3][..], &[10, -4, 12], &[5, 12], &[], &[7, 3]] {
+            for &value in segment_values {
+                index_writer.add_document(doc!(size => value))?;
+            }
+            // A document without any value is ranked with the default value.
+            index_writer.add_document(doc!())?;
+            index_writer.commit()?;
+        }
+        let searcher = index.reader()?.searcher();
+        let mut all_docs = Vec::new();
+        for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+            let column = segment_reader.fast_fields().column_opt::<i64>(SIZE)?;
+            for doc_id in 0..segment_reader.max_doc() {
+                let value = column
+                    .as_ref()
+                    .and_then(|column| column.first(doc_id))
+                    .unwrap_or(i64::MIN);
+                all_docs.push((value, DocAddress::new(segment_ord as u32, doc_id)));
+            }
+        }
+        all_docs.sort_by_key(|(value, doc_address)| (Reverse(*value), *doc_address));
+        let executor = Executor::multi_thread(2, "test-top-field-")?;
+        for (limit, offset) in [(1, 0), (2, 0), (2, 1), (3, 2), (20, 0)] {
+            let expected: Vec<(i64, DocAddress)> =
+                all_docs.iter().skip(offset).take(limit).cloned().collect();
+            let top_collector = TopDocs::with_limit(limit)
+                .and_offset(offset)
+                .order_by_fast_field::<i64>(SIZE);
+            assert_eq!(searcher.search(&AllQuery, &top_collector)?, expected);
+            assert_eq!(
+                searcher.search_with_executor(&AllQuery, &top_collector, &executor)?,
+                expected
+            );
+        }
+        Ok(())
+    }
+
     #[test]
     #[should_panic]
     fn test_field_does_not_exist() {
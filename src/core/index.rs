@@ -16,6 +16,7 @@ use crate::directory::error::OpenReadError;
 use crate::directory::MmapDirectory;
 use crate::directory::{Directory, ManagedDirectory, RamDirectory, INDEX_WRITER_LOCK};
 use crate::error::{DataCorruption, TantivyError};
+use crate::indexer::doc_id_mapping::is_type_valid_for_index_sort;
 use crate::indexer::index_writer::{MAX_NUM_THREAD, MEMORY_ARENA_NUM_BYTES_MIN};
 use crate::indexer::segment_updater::save_metas;
 use crate::reader::{IndexReader, IndexReaderBuilder};
@@ -98,10 +99,10 @@ fn save_new_metas(
 ///
 /// let schema = schema_builder.build();
 /// let settings = IndexSettings{
-///     sort_by_field: Some(IndexSortByField{
+///     sort_by_fields: vec![IndexSortByField{
 ///         field: "number".to_string(),
 ///         order: Order::Asc
-///     }),
+///     }],
 ///     ..Default::default()
 /// };
 /// let index = Index::builder().schema(schema).settings(settings).create_in_ram();
@@ -230,7 +231,7 @@ impl IndexBuilder {
 
     fn validate(&self) -> crate::Result<()> {
         if let Some(schema) = self.schema.as_ref() {
-            if let Some(sort_by_field) = self.index_settings.sort_by_field.as_ref() {
+            for sort_by_field in &self.index_settings.sort_by_fields {
                 let schema_field = schema.get_field(&sort_by_field.field).map_err(|_| {
                     TantivyError::InvalidArgument(format!(
                         "Field to sort index {} not found in schema",
@@ -245,6 +246,29 @@ impl IndexBuilder {
                         sort_by_field.field
                     )));
                 }
+                if !is_type_valid_for_index_sort(entry.field_type().value_type()) {
+                    return Err(TantivyError::InvalidArgument(format!(
+                        "Field {} of type {:?} cannot be used to sort an index",
+                        sort_by_field.field,
+                        entry.field_type().value_type()
+                    )));
+                }
+            }
+            if let Some(retention) = self.index_settings.retention.as_ref() {
+                let schema_field = schema.get_field(&retention.field).map_err(|_| {
+                    TantivyError::InvalidArgument(format!(
+                        "Field to expire documents {} not found in schema",
+                        retention.field
+                    ))
+                })?;
+                let entry = schema.get_field_entry(schema_field);
+                if !matches!(entry.field_type(), FieldType::Date(_)) || !entry.is_fast() {
+                    return Err(TantivyError::InvalidArgument(format!(
+                        "Field {} is no date fast field. Field needs to be a date fast field to \
+                         be used to expire documents",
+                        retention.field
+                    )));
+                }
             }
             Ok(())
         } else {
@@ -962,4 +986,21 @@ mod tests {
         assert_eq!(count, 10);
         Ok(())
     }
+
+    #[test]
+    fn test_single_segment_index_writer_num_bytes() -> crate::Result<()> {
+        let mut schema_builder = Schema::builder();
+        let text_field = schema_builder.add_text_field("text", TEXT);
+        let schema = schema_builder.build();
+        let directory = RamDirectory::default();
+        let mut single_segment_index_writer = Index::builder()
+            .schema(schema)
+            .single_segment_index_writer(directory, 10_000_000)?;
+        single_segment_index_writer.add_document(doc!(text_field=>"hello"))?;
+        let index = single_segment_index_writer.finalize()?;
+        let segment_metas = index.searchable_segment_metas()?;
+        assert_eq!(segment_metas.len(), 1);
+        assert!(segment_metas[0].num_bytes().unwrap() > 0);
+        Ok(())
+    }
 }
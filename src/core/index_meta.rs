@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
@@ -8,9 +8,10 @@ use serde::{Deserialize, Serialize};
 
 use super::SegmentComponent;
 use crate::core::SegmentId;
+use crate::fastfield::FastFieldStats;
 use crate::schema::Schema;
 use crate::store::Compressor;
-use crate::{Inventory, Opstamp, TrackedObject};
+use crate::{DateTime, Inventory, Opstamp, TrackedObject};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct DeleteMeta {
@@ -39,6 +40,8 @@ impl SegmentMetaInventory {
             max_doc,
             include_temp_doc_store: Arc::new(AtomicBool::new(true)),
             deletes: None,
+            fast_field_stats: BTreeMap::new(),
+            num_bytes: None,
         };
         SegmentMeta::from(self.inventory.track(inner))
     }
@@ -187,6 +190,58 @@ impl SegmentMeta {
             max_doc,
             deletes: None,
             include_temp_doc_store: Arc::new(AtomicBool::new(true)),
+            fast_field_stats: BTreeMap::new(),
+            num_bytes: None,
+        });
+        SegmentMeta { tracked }
+    }
+
+    /// Returns the statistics of the fast field `field_name`.
+    ///
+    /// Returns `None` if the field is not a numerical fast field, or if none of the
+    /// documents of the segment have a value for it, or if the segment was written
+    /// before statistics were recorded.
+    pub fn fast_field_stats(&self, field_name: &str) -> Option<&FastFieldStats> {
+        self.tracked.fast_field_stats.get(field_name)
+    }
+
+    pub(crate) fn all_fast_field_stats(&self) -> &BTreeMap<String, FastFieldStats> {
+        &self.tracked.fast_field_stats
+    }
+
+    /// Records the statistics of the fast fields, as the segment is finalized.
+    pub(crate) fn with_fast_field_stats(
+        self,
+        fast_field_stats: BTreeMap<String, FastFieldStats>,
+    ) -> SegmentMeta {
+        let tracked = self.tracked.map(move |inner_meta| InnerSegmentMeta {
+            segment_id: inner_meta.segment_id,
+            max_doc: inner_meta.max_doc,
+            deletes: inner_meta.deletes.clone(),
+            include_temp_doc_store: inner_meta.include_temp_doc_store.clone(),
+            fast_field_stats,
+            num_bytes: inner_meta.num_bytes,
+        });
+        SegmentMeta { tracked }
+    }
+
+    /// Returns the number of bytes of the files of the segment, deleted documents included.
+    ///
+    /// The file holding the alive bitset is not accounted for.
+    /// Returns `None` if the segment was written before its size was recorded.
+    pub fn num_bytes(&self) -> Option<u64> {
+        self.tracked.num_bytes
+    }
+
+    /// Records the number of bytes of the files of the segment, as the segment is finalized.
+    pub(crate) fn with_num_bytes(self, num_bytes: u64) -> SegmentMeta {
+        let tracked = self.tracked.map(move |inner_meta| InnerSegmentMeta {
+            segment_id: inner_meta.segment_id,
+            max_doc: inner_meta.max_doc,
+            deletes: inner_meta.deletes.clone(),
+            include_temp_doc_store: inner_meta.include_temp_doc_store.clone(),
+            fast_field_stats: inner_meta.fast_field_stats.clone(),
+            num_bytes: Some(num_bytes),
         });
         SegmentMeta { tracked }
     }
@@ -207,6 +262,8 @@ impl SegmentMeta {
             max_doc: inner_meta.max_doc,
             include_temp_doc_store: Arc::new(AtomicBool::new(true)),
             deletes: Some(delete_meta),
+            fast_field_stats: inner_meta.fast_field_stats.clone(),
+            num_bytes: inner_meta.num_bytes,
         });
         SegmentMeta { tracked }
     }
@@ -222,6 +279,12 @@ struct InnerSegmentMeta {
     #[serde(skip)]
     #[serde(default = "default_temp_store")]
     pub(crate) include_temp_doc_store: Arc<AtomicBool>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    fast_field_stats: BTreeMap<String, FastFieldStats>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_bytes: Option<u64>,
 }
 fn default_temp_store() -> Arc<AtomicBool> {
     Arc::new(AtomicBool::new(false))
@@ -249,10 +312,19 @@ fn is_true(val: &bool) -> bool {
 /// index, like presort documents.
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub struct IndexSettings {
-    /// Sorts the documents by information
-    /// provided in `IndexSortByField`
+    /// Sorts the documents by information provided in `IndexSortByField`,
+    /// compared in order: the first field is the primary sort key, ties are
+    /// broken by the next fields. Empty if the index is not sorted.
+    ///
+    /// A single field is serialized as `sort_by_field`, like in the
+    /// previous versions of tantivy.
+    #[serde(flatten, with = "sort_by_fields_serde")]
+    pub sort_by_fields: Vec<IndexSortByField>,
+    /// Expires the documents according to the date
+    /// provided in `IndexRetention`
+    #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub sort_by_field: Option<IndexSortByField>,
+    pub retention: Option<IndexRetention>,
     /// The `Compressor` used to compress the doc store.
     #[serde(default)]
     pub docstore_compression: Compressor,
@@ -265,6 +337,34 @@ pub struct IndexSettings {
     #[serde(default = "default_docstore_blocksize")]
     /// The size of each block that will be compressed and written to disk
     pub docstore_blocksize: usize,
+    /// If set, the index writer journals the operations in a write-ahead log before indexing
+    /// them, and replays the operations which were not committed when it is opened. The
+    /// durability level defines when the log is synced to the storage.
+    /// (defaults: None, i.e. no write-ahead log)
+    ///
+    /// Operations based on a query, `IndexWriter::delete_query` and
+    /// `IndexWriter::update_query`, cannot be journaled. They are not available while the log
+    /// is enabled, and return an `InvalidArgument` error.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub write_ahead_log: Option<WriteAheadLogDurability>,
+}
+
+impl IndexSettings {
+    /// Creates the default settings of an index sorted by a single field.
+    #[deprecated(since = "0.20.0", note = "Set `sort_by_fields` instead.")]
+    pub fn with_sort_by_field(sort_by_field: IndexSortByField) -> IndexSettings {
+        IndexSettings {
+            sort_by_fields: vec![sort_by_field],
+            ..Default::default()
+        }
+    }
+
+    /// Returns the primary sort field, i.e. the first of `sort_by_fields`.
+    #[deprecated(since = "0.20.0", note = "Use `sort_by_fields` instead.")]
+    pub fn sort_by_field(&self) -> Option<&IndexSortByField> {
+        self.sort_by_fields.first()
+    }
 }
 
 /// Must be a function to be compatible with serde defaults
@@ -275,10 +375,64 @@ fn default_docstore_blocksize() -> usize {
 impl Default for IndexSettings {
     fn default() -> Self {
         Self {
-            sort_by_field: None,
+            sort_by_fields: Vec::new(),
+            retention: None,
             docstore_compression: Compressor::default(),
             docstore_blocksize: default_docstore_blocksize(),
             docstore_compress_dedicated_thread: true,
+            write_ahead_log: None,
+        }
+    }
+}
+
+/// (De)serializes the sort fields of the index settings as `sort_by_field` if there
+/// is only one of them, and as `sort_by_fields` otherwise.
+mod sort_by_fields_serde {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::IndexSortByField;
+
+    #[derive(Serialize, Deserialize)]
+    struct SortByFields {
+        #[serde(default)]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sort_by_field: Option<IndexSortByField>,
+        #[serde(default)]
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        sort_by_fields: Vec<IndexSortByField>,
+    }
+
+    pub fn serialize<S>(
+        sort_by_fields: &[IndexSortByField],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where S: Serializer {
+        let repr = if let [sort_by_field] = sort_by_fields {
+            SortByFields {
+                sort_by_field: Some(sort_by_field.clone()),
+                sort_by_fields: Vec::new(),
+            }
+        } else {
+            SortByFields {
+                sort_by_field: None,
+                sort_by_fields: sort_by_fields.to_vec(),
+            }
+        };
+        repr.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<IndexSortByField>, D::Error>
+    where D: Deserializer<'de> {
+        let repr = SortByFields::deserialize(deserializer)?;
+        match (repr.sort_by_field, repr.sort_by_fields) {
+            (Some(sort_by_field), sort_by_fields) if sort_by_fields.is_empty() => {
+                Ok(vec![sort_by_field])
+            }
+            (None, sort_by_fields) => Ok(sort_by_fields),
+            (Some(_), _) => Err(D::Error::custom(
+                "Only one of sort_by_field and sort_by_fields can be set",
+            )),
         }
     }
 }
@@ -288,6 +442,12 @@ impl Default for IndexSettings {
 /// Presorting documents can greatly improve performance
 /// in some scenarios, by applying top n
 /// optimizations.
+///
+/// The field can be a numerical, bool, date, text or bytes
+/// fast field. Text and bytes fields are sorted by their
+/// bytes. For multivalued fields, the first value of the
+/// document is used, and documents without any value
+/// come first in ascending order.
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub struct IndexSortByField {
     /// The field to sort the documents by
@@ -295,6 +455,61 @@ pub struct IndexSortByField {
     /// The order to sort the documents by
     pub order: Order,
 }
+
+/// Defines when the records of the write-ahead log are synced to the storage.
+///
+/// The records which are not synced yet are still written to the operating system, so that
+/// they survive a crash of the process, but they may be lost if the operating system crashes.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteAheadLogDurability {
+    /// Every call of the index writer, like `add_document` or `run`, syncs its operations
+    /// before it returns.
+    PerOperation,
+    /// The groups of operations sent with `IndexWriter::run` are synced before it returns,
+    /// along with the operations which were journaled before them. The other operations are
+    /// synced with the next group or when a commit is prepared.
+    PerBatch,
+    /// The log is only synced when a commit is prepared.
+    OnCommit,
+}
+
+/// Settings to expire the documents of an index
+///
+/// A document expires once the date in `field` is older than
+/// `retention_secs` seconds. Expired documents are dropped when their
+/// segment is merged, and segments only holding expired documents are
+/// removed on commit.
+///
+/// Expiry is bound to the creation of the searchers: the expired
+/// documents are excluded from the searchers created by
+/// `IndexReader::reload`, or by `IndexReader::searcher` once the
+/// cutoff moved since the last searcher was created, with a granularity
+/// of one minute. A searcher which is kept around keeps serving the
+/// documents which expire after its creation. Segments only holding
+/// expired documents are left out of the searchers, which shifts the
+/// segment ordinals of the other segments.
+///
+/// Documents without a date never expire.
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct IndexRetention {
+    /// The date fast field holding the age of the documents
+    pub field: String,
+    /// The number of seconds documents are kept for
+    pub retention_secs: u64,
+}
+
+impl IndexRetention {
+    /// Returns the date before which documents are expired, at the time `now`.
+    pub fn cutoff(&self, now: DateTime) -> DateTime {
+        let retention_micros = self.retention_secs.saturating_mul(1_000_000);
+        let retention_micros = i64::try_from(retention_micros).unwrap_or(i64::MAX);
+        DateTime::from_timestamp_micros(
+            now.into_timestamp_micros().saturating_sub(retention_micros),
+        )
+    }
+}
+
 /// The order to sort by
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub enum Order {
@@ -411,7 +626,7 @@ mod tests {
     use crate::core::index_meta::UntrackedIndexMeta;
     use crate::schema::{Schema, TEXT};
     use crate::store::{Compressor, ZstdCompressor};
-    use crate::{IndexSettings, IndexSortByField, Order};
+    use crate::{IndexSettings, IndexSortByField, Order, WriteAheadLogDurability};
 
     #[test]
     fn test_serialize_metas() {
@@ -422,10 +637,10 @@ mod tests {
         };
         let index_metas = IndexMeta {
             index_settings: IndexSettings {
-                sort_by_field: Some(IndexSortByField {
+                sort_by_fields: vec![IndexSortByField {
                     field: "text".to_string(),
                     order: Order::Asc,
-                }),
+                }],
                 ..Default::default()
             },
             segments: Vec::new(),
@@ -454,15 +669,17 @@ mod tests {
         };
         let index_metas = IndexMeta {
             index_settings: IndexSettings {
-                sort_by_field: Some(IndexSortByField {
+                sort_by_fields: vec![IndexSortByField {
                     field: "text".to_string(),
                     order: Order::Asc,
-                }),
+                }],
+                retention: None,
                 docstore_compression: crate::store::Compressor::Zstd(ZstdCompressor {
                     compression_level: Some(4),
                 }),
                 docstore_blocksize: 1_000_000,
                 docstore_compress_dedicated_thread: true,
+                write_ahead_log: None,
             },
             segments: Vec::new(),
             schema,
@@ -481,6 +698,71 @@ mod tests {
         assert_eq!(index_metas.opstamp, deser_meta.opstamp);
     }
 
+    #[test]
+    #[allow(deprecated)]
+    fn test_index_settings_single_sort_by_field() {
+        let sort_by_field = IndexSortByField {
+            field: "timestamp".to_string(),
+            order: Order::Desc,
+        };
+        let settings = IndexSettings::with_sort_by_field(sort_by_field.clone());
+        assert_eq!(settings.sort_by_fields, vec![sort_by_field.clone()]);
+        assert_eq!(settings.sort_by_field(), Some(&sort_by_field));
+        assert_eq!(IndexSettings::default().sort_by_field(), None);
+    }
+
+    #[test]
+    fn test_serialize_index_settings_sort_by_fields() {
+        let sort_by_field = |field: &str| IndexSortByField {
+            field: field.to_string(),
+            order: Order::Asc,
+        };
+        let index_settings = IndexSettings {
+            sort_by_fields: vec![sort_by_field("tenant"), sort_by_field("timestamp")],
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&index_settings).unwrap();
+        assert_eq!(
+            json["sort_by_fields"],
+            serde_json::json!([
+                {"field": "tenant", "order": "Asc"},
+                {"field": "timestamp", "order": "Asc"},
+            ])
+        );
+        assert!(json.get("sort_by_field").is_none());
+        let deser_settings: IndexSettings = serde_json::from_value(json).unwrap();
+        assert_eq!(deser_settings, index_settings);
+
+        let deser_settings: IndexSettings = serde_json::from_str(
+            r#"{"sort_by_field":{"field":"tenant","order":"Asc"},"docstore_compression":"none"}"#,
+        )
+        .unwrap();
+        assert_eq!(deser_settings.sort_by_fields, vec![sort_by_field("tenant")]);
+
+        let err = serde_json::from_str::<IndexSettings>(
+            r#"{"sort_by_field":{"field":"tenant","order":"Asc"},"sort_by_fields":[{"field":"timestamp","order":"Asc"}]}"#,
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Only one of sort_by_field and sort_by_fields can be set"));
+    }
+
+    #[test]
+    fn test_serialize_index_settings_write_ahead_log() {
+        let index_settings = IndexSettings {
+            write_ahead_log: Some(WriteAheadLogDurability::PerBatch),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&index_settings).unwrap();
+        assert_eq!(json["write_ahead_log"], "per_batch");
+        let deser_settings: IndexSettings = serde_json::from_value(json).unwrap();
+        assert_eq!(deser_settings, index_settings);
+
+        let json = serde_json::to_value(&IndexSettings::default()).unwrap();
+        assert!(json.get("write_ahead_log").is_none());
+    }
+
     #[test]
     fn test_serialize_metas_invalid_comp() {
         let json = r#"{"index_settings":{"sort_by_field":{"field":"text","order":"Asc"},"docstore_compression":"zsstd","docstore_blocksize":1000000},"segments":[],"schema":[{"name":"text","type":"text","options":{"indexing":{"record":"position","fieldnorms":true,"tokenizer":"default"},"stored":false,"fast":false}}],"opstamp":0}"#;
@@ -509,10 +791,12 @@ mod tests {
         assert_eq!(
             index_settings,
             IndexSettings {
-                sort_by_field: None,
+                sort_by_fields: Vec::new(),
+                retention: None,
                 docstore_compression: Compressor::default(),
                 docstore_compress_dedicated_thread: true,
-                docstore_blocksize: 16_384
+                docstore_blocksize: 16_384,
+                write_ahead_log: None,
             }
         );
         {
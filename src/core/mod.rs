@@ -2,6 +2,7 @@ mod executor;
 pub mod index;
 mod index_meta;
 mod inverted_index_reader;
+mod retention;
 pub mod searcher;
 mod segment;
 mod segment_component;
@@ -16,9 +17,14 @@ use once_cell::sync::Lazy;
 pub use self::executor::Executor;
 pub use self::index::{Index, IndexBuilder};
 pub use self::index_meta::{
-    IndexMeta, IndexSettings, IndexSortByField, Order, SegmentMeta, SegmentMetaInventory,
+    IndexMeta, IndexRetention, IndexSettings, IndexSortByField, Order, SegmentMeta,
+    SegmentMetaInventory, WriteAheadLogDurability,
 };
 pub use self::inverted_index_reader::InvertedIndexReader;
+pub(crate) use self::retention::{
+    apply_retention, current_cutoff, is_segment_expired, is_segment_meta_expired,
+    reader_cutoff_micros, RetentionFilterCache,
+};
 pub use self::searcher::{Searcher, SearcherGeneration};
 pub use self::segment::Segment;
 pub use self::segment_component::SegmentComponent;
@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use columnar::{Cardinality, Column, ColumnValues, MonotonicallyMappableToU64};
+use common::BitSet;
+use time::OffsetDateTime;
+
+use crate::core::{IndexRetention, SegmentMeta, SegmentReader};
+use crate::fastfield::{AliveBitSet, FastFieldReaders};
+use crate::{DateTime, DocId, Opstamp, SegmentId};
+
+/// The granularity of the cutoff used by the index readers, so that their retention filters can
+/// be reused across reloads.
+const READER_CUTOFF_GRANULARITY_MICROS: i64 = 60 * 1_000_000;
+
+/// The documents of a segment which are not expired.
+#[derive(Clone)]
+pub(crate) enum RetentionFilter {
+    /// None of the documents are expired.
+    KeepAll,
+    /// All of the documents are expired.
+    DropAll,
+    /// Only the documents of the bitset are not expired.
+    Alive(AliveBitSet),
+}
+
+/// Returns the date before which documents are expired, as of now.
+pub(crate) fn current_cutoff(retention: &IndexRetention) -> DateTime {
+    retention.cutoff(DateTime::from_utc(OffsetDateTime::now_utc()))
+}
+
+/// Returns the cutoff used by the index readers as of now, in microseconds, rounded down to
+/// their granularity.
+pub(crate) fn reader_cutoff_micros(retention: &IndexRetention) -> i64 {
+    let cutoff_micros = current_cutoff(retention).into_timestamp_micros();
+    cutoff_micros.saturating_sub(cutoff_micros.rem_euclid(READER_CUTOFF_GRANULARITY_MICROS))
+}
+
+fn date_column(
+    fast_field_readers: &FastFieldReaders,
+    retention: &IndexRetention,
+) -> crate::Result<Option<Column<columnar::DateTime>>> {
+    fast_field_readers.column_opt::<columnar::DateTime>(&retention.field)
+}
+
+/// Returns true if the min and max values of the date column are enough to tell that all of
+/// the documents are expired.
+fn is_column_expired(column: &Column<columnar::DateTime>, cutoff: columnar::DateTime) -> bool {
+    column.get_cardinality() == Cardinality::Full
+        && column.values.num_vals() > 0
+        && column.max_value() < cutoff
+}
+
+/// Returns true if all of the documents of a segment are expired at the date `cutoff`.
+///
+/// Only the min and max values of the date column are checked, so that this is cheap
+/// enough to be called on every commit. A segment with some documents without a date is
+/// never reported as expired.
+pub(crate) fn is_segment_expired(
+    fast_field_readers: &FastFieldReaders,
+    retention: &IndexRetention,
+    cutoff: DateTime,
+) -> crate::Result<bool> {
+    let Some(column) = date_column(fast_field_readers, retention)? else {
+        return Ok(false);
+    };
+    Ok(is_column_expired(&column, cutoff.into()))
+}
+
+/// Returns true if all of the documents of a segment are expired at the date `cutoff`, as
+/// told by the fast field statistics recorded in its meta.
+///
+/// Returns `None` if the segment meta has no statistics for the date field.
+pub(crate) fn is_segment_meta_expired(
+    segment_meta: &SegmentMeta,
+    retention: &IndexRetention,
+    cutoff: DateTime,
+) -> Option<bool> {
+    let stats = segment_meta.fast_field_stats(&retention.field)?;
+    let cutoff = columnar::DateTime::from(cutoff).to_u64();
+    Some(stats.num_nulls == 0 && stats.max_value < cutoff)
+}
+
+/// Computes the documents of a segment which are not expired at the date `cutoff`.
+///
+/// The min and max values of the date column are checked first, so that the documents are
+/// only scanned if the segment is partially expired.
+pub(crate) fn retention_filter(
+    fast_field_readers: &FastFieldReaders,
+    max_doc: DocId,
+    retention: &IndexRetention,
+    cutoff: DateTime,
+) -> crate::Result<RetentionFilter> {
+    let Some(column) = date_column(fast_field_readers, retention)? else {
+        return Ok(RetentionFilter::KeepAll);
+    };
+    let cutoff = columnar::DateTime::from(cutoff);
+    if column.values.num_vals() == 0 || column.min_value() >= cutoff {
+        return Ok(RetentionFilter::KeepAll);
+    }
+    if is_column_expired(&column, cutoff) {
+        return Ok(RetentionFilter::DropAll);
+    }
+    let mut alive_bitset = BitSet::with_max_value_and_full(max_doc);
+    for doc in 0..max_doc {
+        let mut dates = column.values(doc).peekable();
+        if dates.peek().is_some() && dates.all(|date| date < cutoff) {
+            alive_bitset.remove(doc);
+        }
+    }
+    Ok(match alive_bitset.len() {
+        0 => RetentionFilter::DropAll,
+        num_alive_docs if num_alive_docs == max_doc as usize => RetentionFilter::KeepAll,
+        _ => RetentionFilter::Alive(AliveBitSet::from_bitset(&alive_bitset)),
+    })
+}
+
+/// Excludes the expired documents from the segment reader.
+///
+/// Returns `None` if all of the documents of the segment are expired.
+pub(crate) fn apply_retention(
+    segment_reader: SegmentReader,
+    retention: &IndexRetention,
+    cutoff: DateTime,
+) -> crate::Result<Option<SegmentReader>> {
+    let max_doc = segment_reader.max_doc();
+    let filter = retention_filter(segment_reader.fast_fields(), max_doc, retention, cutoff)?;
+    Ok(apply_retention_filter(segment_reader, filter))
+}
+
+fn apply_retention_filter(
+    mut segment_reader: SegmentReader,
+    filter: RetentionFilter,
+) -> Option<SegmentReader> {
+    match filter {
+        RetentionFilter::KeepAll => Some(segment_reader),
+        RetentionFilter::DropAll => None,
+        RetentionFilter::Alive(alive_bitset) => {
+            segment_reader.intersect_alive_bitset(alive_bitset);
+            Some(segment_reader)
+        }
+    }
+}
+
+/// The key of a retention filter: the segment, its delete opstamp and the cutoff in
+/// microseconds.
+type RetentionFilterKey = (SegmentId, Option<Opstamp>, i64);
+
+/// Retention filters computed by an index reader, so that the documents of the segments which
+/// are partially expired are not scanned again on every reload.
+///
+/// The cutoff is rounded down to a granularity of one minute, so that the filters can be
+/// reused until it changes. Expired documents may hence be visible for up to one more minute.
+#[derive(Default)]
+pub(crate) struct RetentionFilterCache {
+    filters: Mutex<HashMap<RetentionFilterKey, RetentionFilter>>,
+}
+
+impl RetentionFilterCache {
+    /// Excludes the documents expired at the cutoff, as returned by [`reader_cutoff_micros`],
+    /// from the segment readers, and drops the segments whose documents are all expired.
+    ///
+    /// Only the filters of the given segment readers are kept in the cache.
+    pub(crate) fn apply_retention(
+        &self,
+        segment_readers: Vec<SegmentReader>,
+        retention: &IndexRetention,
+        cutoff_micros: i64,
+    ) -> crate::Result<Vec<SegmentReader>> {
+        let cutoff = DateTime::from_timestamp_micros(cutoff_micros);
+        let mut filters = self.filters.lock().unwrap();
+        let mut new_filters = HashMap::with_capacity(segment_readers.len());
+        let mut alive_segment_readers = Vec::with_capacity(segment_readers.len());
+        for segment_reader in segment_readers {
+            let key = (
+                segment_reader.segment_id(),
+                segment_reader.delete_opstamp(),
+                cutoff_micros,
+            );
+            let filter = match filters.remove(&key) {
+                Some(filter) => filter,
+                None => retention_filter(
+                    segment_reader.fast_fields(),
+                    segment_reader.max_doc(),
+                    retention,
+                    cutoff,
+                )?,
+            };
+            new_filters.insert(key, filter.clone());
+            alive_segment_readers.extend(apply_retention_filter(segment_reader, filter));
+        }
+        *filters = new_filters;
+        Ok(alive_segment_readers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, OffsetDateTime};
+
+    use super::{reader_cutoff_micros, RetentionFilterCache};
+    use crate::collector::Count;
+    use crate::indexer::NoMergePolicy;
+    use crate::query::TermQuery;
+    use crate::schema::{Field, IndexRecordOption, Schema, FAST, STRING};
+    use crate::{
+        DateTime, Index, IndexRetention, IndexSettings, IndexWriter, SegmentId, SegmentReader,
+        TantivyError, Term,
+    };
+
+    const DAY_SECS: u64 = 24 * 3600;
+
+    fn days_ago(days: i64) -> DateTime {
+        DateTime::from_utc(OffsetDateTime::now_utc() - Duration::days(days))
+    }
+
+    fn retention_index() -> crate::Result<(Index, Field, Field)> {
+        let mut schema_builder = Schema::builder();
+        let id_field = schema_builder.add_text_field("id", STRING);
+        let date_field = schema_builder.add_date_field("timestamp", FAST);
+        let index = Index::builder()
+            .schema(schema_builder.build())
+            .settings(IndexSettings {
+                retention: Some(IndexRetention {
+                    field: "timestamp".to_string(),
+                    retention_secs: 5 * DAY_SECS,
+                }),
+                ..Default::default()
+            })
+            .create_in_ram()?;
+        Ok((index, id_field, date_field))
+    }
+
+    fn writer(index: &Index) -> crate::Result<IndexWriter> {
+        let index_writer = index.writer_for_tests()?;
+        index_writer.set_merge_policy(Box::new(NoMergePolicy));
+        Ok(index_writer)
+    }
+
+    fn num_docs_with_id(index: &Index, id_field: Field, id: &str) -> crate::Result<usize> {
+        let query = TermQuery::new(
+            Term::from_field_text(id_field, id),
+            IndexRecordOption::Basic,
+        );
+        index.reader()?.searcher().search(&query, &Count)
+    }
+
+    #[test]
+    fn test_retention_cutoff() {
+        let retention = IndexRetention {
+            field: "timestamp".to_string(),
+            retention_secs: 10,
+        };
+        assert_eq!(
+            retention.cutoff(DateTime::from_timestamp_secs(100)),
+            DateTime::from_timestamp_secs(90)
+        );
+        let retention = IndexRetention {
+            field: "timestamp".to_string(),
+            retention_secs: u64::MAX,
+        };
+        assert_eq!(
+            retention.cutoff(DateTime::from_timestamp_secs(100)),
+            DateTime::from_timestamp_micros(100_000_000 - i64::MAX)
+        );
+    }
+
+    #[test]
+    fn test_retention_excludes_expired_docs_from_search() -> crate::Result<()> {
+        let (index, id_field, date_field) = retention_index()?;
+        let mut index_writer = writer(&index)?;
+        index_writer.add_document(doc!(id_field => "a", date_field => days_ago(10)))?;
+        index_writer.add_document(doc!(id_field => "b", date_field => days_ago(1)))?;
+        index_writer.add_document(doc!(id_field => "c"))?;
+        index_writer.commit()?;
+
+        assert_eq!(index.reader()?.searcher().num_docs(), 2);
+        assert_eq!(num_docs_with_id(&index, id_field, "a")?, 0);
+        assert_eq!(num_docs_with_id(&index, id_field, "b")?, 1);
+        assert_eq!(num_docs_with_id(&index, id_field, "c")?, 1);
+        // The expired document is not physically removed.
+        let segment_metas = index.searchable_segment_metas()?;
+        assert_eq!(segment_metas.len(), 1);
+        assert_eq!(segment_metas[0].num_docs(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_retention_filter_cache() -> crate::Result<()> {
+        let (index, id_field, date_field) = retention_index()?;
+        let mut index_writer = writer(&index)?;
+        index_writer.add_document(doc!(id_field => "a", date_field => days_ago(10)))?;
+        index_writer.add_document(doc!(id_field => "b", date_field => days_ago(1)))?;
+        index_writer.commit()?;
+        index_writer.add_document(doc!(id_field => "c", date_field => days_ago(1)))?;
+        index_writer.commit()?;
+
+        let retention = index.settings().retention.clone().unwrap();
+        let cutoff_micros = reader_cutoff_micros(&retention);
+        let cache = RetentionFilterCache::default();
+        let segment_readers = || -> crate::Result<Vec<SegmentReader>> {
+            index
+                .searchable_segments()?
+                .iter()
+                .map(SegmentReader::open)
+                .collect()
+        };
+        let alive_segment_readers = cache.apply_retention(segment_readers()?, &retention, cutoff_micros)?;
+        assert_eq!(alive_segment_readers.len(), 2);
+        let num_docs: u32 = alive_segment_readers
+            .iter()
+            .map(SegmentReader::num_docs)
+            .sum();
+        assert_eq!(num_docs, 2);
+        assert_eq!(cache.filters.lock().unwrap().len(), 2);
+
+        // The filter of the segment, which was removed with its last document, is evicted.
+        index_writer.delete_term(Term::from_field_text(id_field, "c"));
+        index_writer.commit()?;
+        let alive_segment_readers = cache.apply_retention(segment_readers()?, &retention, cutoff_micros)?;
+        assert_eq!(alive_segment_readers.len(), 1);
+        assert_eq!(alive_segment_readers[0].num_docs(), 1);
+        let filters = cache.filters.lock().unwrap();
+        assert_eq!(filters.len(), 1);
+        let (segment_id, _, _) = filters.keys().next().unwrap();
+        assert_eq!(*segment_id, alive_segment_readers[0].segment_id());
+        Ok(())
+    }
+
+    #[test]
+    fn test_retention_removes_expired_segments_on_commit() -> crate::Result<()> {
+        let (index, id_field, date_field) = retention_index()?;
+        let mut index_writer = writer(&index)?;
+        index_writer.add_document(doc!(id_field => "a", date_field => days_ago(10)))?;
+        index_writer.add_document(doc!(id_field => "b", date_field => days_ago(20)))?;
+        index_writer.commit()?;
+        assert!(index.searchable_segment_metas()?.is_empty());
+
+        index_writer.add_document(doc!(id_field => "c", date_field => days_ago(10)))?;
+        index_writer.add_document(doc!(id_field => "d", date_field => days_ago(1)))?;
+        index_writer.commit()?;
+        assert_eq!(index.searchable_segment_metas()?.len(), 1);
+        assert_eq!(index.reader()?.searcher().num_docs(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_retention_drops_expired_docs_on_merge() -> crate::Result<()> {
+        let (index, id_field, date_field) = retention_index()?;
+        let mut index_writer = writer(&index)?;
+        index_writer.add_document(doc!(id_field => "a", date_field => days_ago(10)))?;
+        index_writer.add_document(doc!(id_field => "b", date_field => days_ago(1)))?;
+        index_writer.commit()?;
+        index_writer.add_document(doc!(id_field => "c", date_field => days_ago(1)))?;
+        index_writer.add_document(doc!(id_field => "d", date_field => days_ago(6)))?;
+        index_writer.add_document(doc!(id_field => "e"))?;
+        index_writer.commit()?;
+
+        let segment_ids: Vec<SegmentId> = index.searchable_segment_ids()?;
+        assert_eq!(segment_ids.len(), 2);
+        let merged_segment_meta = index_writer.merge(&segment_ids).wait()?.unwrap();
+        assert_eq!(merged_segment_meta.max_doc(), 3);
+        assert_eq!(merged_segment_meta.num_docs(), 3);
+        for id in ["b", "c", "e"] {
+            assert_eq!(num_docs_with_id(&index, id_field, id)?, 1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_retention_invalid_field() {
+        let mut schema_builder = Schema::builder();
+        schema_builder.add_text_field("id", STRING);
+        let schema = schema_builder.build();
+        for field in ["id", "timestamp"] {
+            let err = Index::builder()
+                .schema(schema.clone())
+                .settings(IndexSettings {
+                    retention: Some(IndexRetention {
+                        field: field.to_string(),
+                        retention_secs: DAY_SECS,
+                    }),
+                    ..Default::default()
+                })
+                .create_in_ram()
+                .unwrap_err();
+            assert!(matches!(err, TantivyError::InvalidArgument(_)));
+        }
+    }
+}
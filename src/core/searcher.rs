@@ -8,7 +8,7 @@ use crate::query::{EnableScoring, Query};
 use crate::schema::{Document, Schema, Term};
 use crate::space_usage::SearcherSpaceUsage;
 use crate::store::{CacheStats, StoreReader};
-use crate::{DocAddress, Index, Opstamp, SegmentId, TrackedObject};
+use crate::{DocAddress, Index, Opstamp, SegmentId, SegmentOrdinal, TrackedObject};
 
 /// Identifies the searcher generation accessed by a [`Searcher`].
 ///
@@ -204,13 +204,13 @@ impl Searcher {
             EnableScoring::disabled_from_searcher(self)
         };
         let weight = query.weight(enabled_scoring)?;
-        let segment_readers = self.segment_readers();
-        let fruits = executor.map(
-            |(segment_ord, segment_reader)| {
-                collector.collect_segment(weight.as_ref(), segment_ord as u32, segment_reader)
-            },
-            segment_readers.iter().enumerate(),
-        )?;
+        let segment_readers = self
+            .segment_readers()
+            .iter()
+            .enumerate()
+            .map(|(segment_ord, segment_reader)| (segment_ord as SegmentOrdinal, segment_reader))
+            .collect();
+        let fruits = collector.collect_segments(weight.as_ref(), segment_readers, executor)?;
         collector.merge_fruits(fruits)
     }
 
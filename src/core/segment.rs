@@ -1,10 +1,13 @@
 use std::fmt;
 use std::path::PathBuf;
 
+use common::HasLen;
+
 use super::SegmentComponent;
 use crate::core::{Index, SegmentId, SegmentMeta};
 use crate::directory::error::{OpenReadError, OpenWriteError};
 use crate::directory::{Directory, FileSlice, WritePtr};
+use crate::fastfield::compute_fast_field_stats;
 use crate::schema::Schema;
 use crate::Opstamp;
 
@@ -53,6 +56,41 @@ impl Segment {
         }
     }
 
+    /// Records the statistics of the fast fields in the `SegmentMeta`.
+    ///
+    /// This method is called once the fast fields of the segment are written.
+    pub(crate) fn with_fast_field_stats(self) -> crate::Result<Segment> {
+        let fast_field_stats = compute_fast_field_stats(&self)?;
+        Ok(Segment {
+            index: self.index,
+            meta: self.meta.with_fast_field_stats(fast_field_stats),
+        })
+    }
+
+    /// Records the number of bytes of the files of the segment in the `SegmentMeta`.
+    ///
+    /// This method is called once all of the files of the segment are written.
+    pub(crate) fn with_num_bytes(self) -> crate::Result<Segment> {
+        let mut num_bytes = 0u64;
+        for &component in SegmentComponent::iterator() {
+            if matches!(
+                component,
+                SegmentComponent::TempStore | SegmentComponent::Delete
+            ) {
+                continue;
+            }
+            match self.open_read(component) {
+                Ok(file_slice) => num_bytes += file_slice.len() as u64,
+                Err(OpenReadError::FileDoesNotExist(_)) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(Segment {
+            index: self.index,
+            meta: self.meta.with_num_bytes(num_bytes),
+        })
+    }
+
     #[doc(hidden)]
     #[must_use]
     pub fn with_delete_meta(self, num_deleted_docs: u32, opstamp: Opstamp) -> Segment {
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, RwLock};
 use std::{fmt, io};
 
@@ -7,12 +7,15 @@ use fail::fail_point;
 use crate::core::{InvertedIndexReader, Segment, SegmentComponent, SegmentId};
 use crate::directory::{CompositeFile, FileSlice};
 use crate::error::DataCorruption;
-use crate::fastfield::{intersect_alive_bitsets, AliveBitSet, FacetReader, FastFieldReaders};
+use crate::fastfield::{
+    intersect_alive_bitsets, AliveBitSet, FacetReader, FastFieldReaders, FastFieldStats,
+};
 use crate::fieldnorm::{FieldNormReader, FieldNormReaders};
 use crate::schema::{Field, IndexRecordOption, Schema, Type};
 use crate::space_usage::SegmentSpaceUsage;
 use crate::store::StoreReader;
 use crate::termdict::TermDictionary;
+use crate::tokenizer::TokenizerManager;
 use crate::{DocId, Opstamp};
 
 /// Entry point to access all of the datastructures of the `Segment`
@@ -31,6 +34,7 @@ pub struct SegmentReader {
 
     segment_id: SegmentId,
     delete_opstamp: Option<Opstamp>,
+    fast_field_stats: Arc<BTreeMap<String, FastFieldStats>>,
 
     max_doc: DocId,
     num_docs: DocId,
@@ -44,6 +48,7 @@ pub struct SegmentReader {
     store_file: FileSlice,
     alive_bitset_opt: Option<AliveBitSet>,
     schema: Schema,
+    tokenizers: TokenizerManager,
 }
 
 impl SegmentReader {
@@ -64,6 +69,11 @@ impl SegmentReader {
         &self.schema
     }
 
+    /// Returns the tokenizers of the index this segment belongs to.
+    pub(crate) fn tokenizers(&self) -> &TokenizerManager {
+        &self.tokenizers
+    }
+
     /// Return the number of documents that have been
     /// deleted in the segment.
     pub fn num_deleted_docs(&self) -> DocId {
@@ -89,6 +99,26 @@ impl SegmentReader {
         &self.fast_fields_readers
     }
 
+    /// Returns a reader of `max_doc` documents without deletes, whose fast fields are replaced,
+    /// e.g. by the fast fields of the nested documents of this segment.
+    ///
+    /// The other data structures still belong to the documents of this segment and must not be
+    /// used with the new documents.
+    pub(crate) fn with_fast_fields(
+        &self,
+        fast_fields: FastFieldReaders,
+        max_doc: DocId,
+    ) -> SegmentReader {
+        SegmentReader {
+            max_doc,
+            num_docs: max_doc,
+            fast_fields_readers: Arc::new(fast_fields),
+            fast_field_stats: Default::default(),
+            alive_bitset_opt: None,
+            ..self.clone()
+        }
+    }
+
     /// Accessor to the `FacetReader` associated with a given `Field`.
     pub fn facet_reader(&self, field_name: &str) -> crate::Result<FacetReader> {
         let schema = self.schema();
@@ -167,7 +197,8 @@ impl SegmentReader {
         let schema = segment.schema();
 
         let fast_fields_data = segment.open_read(SegmentComponent::FastFields)?;
-        let fast_fields_readers = Arc::new(FastFieldReaders::open(fast_fields_data)?);
+        let fast_fields_readers =
+            Arc::new(FastFieldReaders::open(fast_fields_data, schema.clone())?);
         let fieldnorm_data = segment.open_read(SegmentComponent::FieldNorms)?;
         let fieldnorm_readers = FieldNormReaders::open(fieldnorm_data)?;
 
@@ -197,10 +228,12 @@ impl SegmentReader {
             fieldnorm_readers,
             segment_id: segment.id(),
             delete_opstamp: segment.meta().delete_opstamp(),
+            fast_field_stats: Arc::new(segment.meta().all_fast_field_stats().clone()),
             store_file,
             alive_bitset_opt,
             positions_composite,
             schema,
+            tokenizers: segment.index().tokenizers().clone(),
         })
     }
 
@@ -292,11 +325,30 @@ impl SegmentReader {
         self.delete_opstamp
     }
 
+    /// Returns the statistics of the fast field `field_name`, recorded in the segment meta.
+    ///
+    /// See [`SegmentMeta::fast_field_stats`](crate::SegmentMeta::fast_field_stats).
+    pub fn fast_field_stats(&self, field_name: &str) -> Option<&FastFieldStats> {
+        self.fast_field_stats.get(field_name)
+    }
+
     /// Returns the bitset representing the alive `DocId`s.
     pub fn alive_bitset(&self) -> Option<&AliveBitSet> {
         self.alive_bitset_opt.as_ref()
     }
 
+    /// Excludes the documents which are not in `alive_bitset`, in addition to the deleted
+    /// documents.
+    pub(crate) fn intersect_alive_bitset(&mut self, alive_bitset: AliveBitSet) {
+        let alive_bitset_opt =
+            intersect_alive_bitset(self.alive_bitset_opt.take(), Some(alive_bitset));
+        self.num_docs = alive_bitset_opt
+            .as_ref()
+            .map(|alive_bitset| alive_bitset.num_alive_docs() as u32)
+            .unwrap_or(self.max_doc);
+        self.alive_bitset_opt = alive_bitset_opt;
+    }
+
     /// Returns true if the `doc` is marked
     /// as deleted.
     pub fn is_deleted(&self, doc: DocId) -> bool {
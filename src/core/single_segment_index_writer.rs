@@ -35,7 +35,11 @@ impl SingleSegmentIndexWriter {
     pub fn finalize(self) -> crate::Result<Index> {
         let max_doc = self.segment_writer.max_doc();
         self.segment_writer.finalize()?;
-        let segment: Segment = self.segment.with_max_doc(max_doc);
+        let segment: Segment = self
+            .segment
+            .with_max_doc(max_doc)
+            .with_fast_field_stats()?
+            .with_num_bytes()?;
         let index = segment.index();
         let index_meta = IndexMeta {
             index_settings: index.settings().clone(),
@@ -279,6 +279,10 @@ impl TerminatingWrite for SafeFileWriter {
         self.0.sync_data()?;
         Ok(())
     }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.0.sync_data()
+    }
 }
 
 #[derive(Clone)]
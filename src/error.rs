@@ -6,6 +6,7 @@ use std::{fmt, io};
 
 use thiserror::Error;
 
+use crate::aggregation::AggregationError;
 use crate::directory::error::{
     Incompatibility, LockError, OpenDirectoryError, OpenReadError, OpenWriteError,
 };
@@ -102,6 +103,9 @@ pub enum TantivyError {
     /// e.g. a datastructure is incorrectly inititalized.
     #[error("Internal error: '{0}'")]
     InternalError(String),
+    /// The collection of an aggregation was aborted.
+    #[error("Aggregation error: '{0}'")]
+    AggregationError(#[from] AggregationError),
 }
 
 impl From<io::Error> for TantivyError {
@@ -27,7 +27,10 @@ use columnar::MonotonicallyMappableToU64;
 pub use self::alive_bitset::{intersect_alive_bitsets, write_alive_bitset, AliveBitSet};
 pub use self::error::{FastFieldNotAvailableError, Result};
 pub use self::facet_reader::FacetReader;
+pub(crate) use self::nested::NestedDocuments;
 pub use self::readers::FastFieldReaders;
+pub(crate) use self::stats::compute_fast_field_stats;
+pub use self::stats::FastFieldStats;
 pub use self::writer::FastFieldsWriter;
 use crate::schema::Type;
 use crate::DateTime;
@@ -35,7 +38,9 @@ use crate::DateTime;
 mod alive_bitset;
 mod error;
 mod facet_reader;
+mod nested;
 mod readers;
+mod stats;
 mod writer;
 
 /// Trait for types that provide a zero value.
@@ -172,7 +177,7 @@ mod tests {
         let file = directory.open_read(path).unwrap();
 
         assert_eq!(file.len(), 161);
-        let fast_field_readers = FastFieldReaders::open(file).unwrap();
+        let fast_field_readers = FastFieldReaders::open(file, SCHEMA.clone()).unwrap();
         let column = fast_field_readers.u64("field").unwrap();
         assert_eq!(column.get_val(0), 13u64);
         assert_eq!(column.get_val(1), 14u64);
@@ -219,7 +224,7 @@ mod tests {
         }
         let file = directory.open_read(path).unwrap();
         assert_eq!(file.len(), 189);
-        let fast_field_readers = FastFieldReaders::open(file).unwrap();
+        let fast_field_readers = FastFieldReaders::open(file, SCHEMA.clone()).unwrap();
         let col = fast_field_readers.u64("field").unwrap();
         assert_eq!(col.get_val(0), 4u64);
         assert_eq!(col.get_val(1), 14_082_001u64);
@@ -249,7 +254,7 @@ mod tests {
         }
         let file = directory.open_read(path).unwrap();
         assert_eq!(file.len(), 162);
-        let fast_field_readers = FastFieldReaders::open(file).unwrap();
+        let fast_field_readers = FastFieldReaders::open(file, SCHEMA.clone()).unwrap();
         let fast_field_reader = fast_field_readers.u64("field").unwrap();
         for doc in 0..10_000 {
             assert_eq!(fast_field_reader.get_val(doc), 100_000u64);
@@ -279,7 +284,7 @@ mod tests {
         let file = directory.open_read(path).unwrap();
         assert_eq!(file.len(), 4557);
         {
-            let fast_field_readers = FastFieldReaders::open(file).unwrap();
+            let fast_field_readers = FastFieldReaders::open(file, SCHEMA.clone()).unwrap();
             let col = fast_field_readers.u64("field").unwrap();
             for doc in 1..10_000 {
                 assert_eq!(col.get_val(doc), 5_000_000_000_000_000_000u64 + doc as u64);
@@ -310,7 +315,7 @@ mod tests {
         assert_eq!(file.len(), 333_usize);
 
         {
-            let fast_field_readers = FastFieldReaders::open(file).unwrap();
+            let fast_field_readers = FastFieldReaders::open(file, schema).unwrap();
             let col = fast_field_readers.i64("field").unwrap();
             assert_eq!(col.min_value(), -100i64);
             assert_eq!(col.max_value(), 9_999i64);
@@ -344,7 +349,7 @@ mod tests {
         }
 
         let file = directory.open_read(path).unwrap();
-        let fast_field_readers = FastFieldReaders::open(file).unwrap();
+        let fast_field_readers = FastFieldReaders::open(file, schema).unwrap();
         let col = fast_field_readers.i64("field").unwrap();
         assert_eq!(col.get_val(0), 0i64);
     }
@@ -366,7 +371,7 @@ mod tests {
         }
 
         let file = directory.open_read(path).unwrap();
-        let fast_field_readers = FastFieldReaders::open(file).unwrap();
+        let fast_field_readers = FastFieldReaders::open(file, schema).unwrap();
         let col = fast_field_readers.date("date").unwrap();
         assert_eq!(col.get_val(0), columnar::DateTime::default());
     }
@@ -399,7 +404,7 @@ mod tests {
             write.terminate().unwrap();
         }
         let file = directory.open_read(path).unwrap();
-        let fast_field_readers = FastFieldReaders::open(file).unwrap();
+        let fast_field_readers = FastFieldReaders::open(file, SCHEMA.clone()).unwrap();
         let col = fast_field_readers.u64("field").unwrap();
         for a in 0..n {
             assert_eq!(col.get_val(a as u32), permutation[a]);
@@ -782,7 +787,7 @@ mod tests {
         }
         let file = directory.open_read(path).unwrap();
         assert_eq!(file.len(), 175);
-        let fast_field_readers = FastFieldReaders::open(file).unwrap();
+        let fast_field_readers = FastFieldReaders::open(file, schema).unwrap();
         let bool_col = fast_field_readers.bool("field_bool").unwrap();
         assert_eq!(bool_col.get_val(0), true);
         assert_eq!(bool_col.get_val(1), false);
@@ -814,7 +819,7 @@ mod tests {
         }
         let file = directory.open_read(path).unwrap();
         assert_eq!(file.len(), 187);
-        let readers = FastFieldReaders::open(file).unwrap();
+        let readers = FastFieldReaders::open(file, schema).unwrap();
         let bool_col = readers.bool("field_bool").unwrap();
         for i in 0..25 {
             assert_eq!(bool_col.get_val(i * 2), true);
@@ -839,7 +844,7 @@ mod tests {
         }
         let file = directory.open_read(path).unwrap();
         assert_eq!(file.len(), 177);
-        let fastfield_readers = FastFieldReaders::open(file).unwrap();
+        let fastfield_readers = FastFieldReaders::open(file, schema).unwrap();
         let col = fastfield_readers.bool("field_bool").unwrap();
         assert_eq!(col.get_val(0), false);
     }
@@ -891,7 +896,7 @@ mod tests {
         let directory = get_index(&docs[..], &schema).unwrap();
         let path = Path::new("test");
         let file = directory.open_read(path).unwrap();
-        let readers = FastFieldReaders::open(file).unwrap();
+        let readers = FastFieldReaders::open(file, schema).unwrap();
         let col = readers.date("field").unwrap();
 
         for (i, time) in times.iter().enumerate() {
@@ -971,6 +976,46 @@ mod tests {
         assert_eq!(column.first(2), None);
     }
 
+    #[test]
+    fn test_json_fastfield() {
+        let mut schema_builder = Schema::builder();
+        let json_field = schema_builder.add_json_field("json", FAST);
+        let schema = schema_builder.build();
+        let index = Index::create_in_ram(schema);
+        let mut index_writer = index.writer_for_tests().unwrap();
+        let json_doc = |json: serde_json::Value| {
+            let json_obj = json.as_object().unwrap().clone();
+            doc!(json_field=>json_obj)
+        };
+        index_writer
+            .add_document(json_doc(
+                serde_json::json!({"attr": {"size": 1, "color": "red"}}),
+            ))
+            .unwrap();
+        index_writer
+            .add_document(json_doc(serde_json::json!({"attr": {"size": [-2, 3.5]}})))
+            .unwrap();
+        index_writer
+            .add_document(json_doc(serde_json::json!({"attr.size": 4})))
+            .unwrap();
+        index_writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        let fast_fields = searcher.segment_reader(0u32).fast_fields();
+        // The mixed numbers of the path are coerced to f64.
+        let column: Column<f64> = fast_fields.column_opt("json.attr.size").unwrap().unwrap();
+        assert_eq!(column.values(0).collect::<Vec<_>>(), vec![1.0]);
+        assert_eq!(column.values(1).collect::<Vec<_>>(), vec![-2.0, 3.5]);
+        assert_eq!(column.first(2), None);
+        // Dots in keys have to be escaped.
+        let column: Column<i64> = fast_fields.column_opt("json.attr\\.size").unwrap().unwrap();
+        assert_eq!(column.first(2), Some(4));
+        let str_column = fast_fields.str("json.attr.color").unwrap().unwrap();
+        assert_eq!(str_column.term_ords(0).collect::<Vec<_>>(), vec![0]);
+        assert!(fast_fields.str("json.attr.size").unwrap().is_none());
+        assert!(fast_fields.u64_lenient("json.unknown").unwrap().is_none());
+        assert!(fast_fields.u64_lenient("unknown.attr").unwrap().is_none());
+    }
+
     #[test]
     fn test_mapping_bug_docids_for_value_range() {
         let mut schema_builder = Schema::builder();
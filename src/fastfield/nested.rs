@@ -0,0 +1,218 @@
+//! Columns of the nested json paths.
+//!
+//! For a nested path like `items`, every value below it, e.g. of `items.sku`, is recorded in its
+//! usual column and additionally its element ordinal is recorded in an ordinal column. The
+//! ordinal column has one value per value of the value column, in the same order, so that
+//! zipping their values of a document gives the element of every value. Since the values of a
+//! json path can end up in columns of different types, there is one ordinal column per type of
+//! value column.
+//!
+//! The nested path itself gets a column with the number of elements of each document.
+//!
+//! [`NestedDocuments`] turns these columns into fast fields, which have a row per element.
+
+use std::ops::Range;
+
+use columnar::{ColumnType, ColumnarReader, ColumnarWriter, DynamicColumn, NumericalValue};
+
+use super::FastFieldReaders;
+use crate::directory::FileSlice;
+use crate::{DocId, TantivyError};
+
+/// Separates the name of a value column from the suffix of its nested columns.
+const NESTED_COLUMN_SEP: char = '\u{2}';
+
+/// Returns the name of the column with the element ordinals of the values of a column.
+pub(crate) fn nested_ordinal_column_name(column_name: &str, column_type: ColumnType) -> String {
+    let mut ordinal_column_name = column_name.to_string();
+    push_nested_ordinal_suffix(&mut ordinal_column_name, column_type);
+    ordinal_column_name
+}
+
+/// Appends the suffix of the ordinal column to the name of a value column.
+pub(crate) fn push_nested_ordinal_suffix(column_name: &mut String, column_type: ColumnType) {
+    column_name.push(NESTED_COLUMN_SEP);
+    // Numerical values of different types are coerced into a single column.
+    column_name.push_str(match column_type {
+        ColumnType::I64 | ColumnType::U64 | ColumnType::F64 => "num",
+        ColumnType::Str => "str",
+        ColumnType::Bytes => "bytes",
+        ColumnType::Bool => "bool",
+        ColumnType::IpAddr => "ip",
+        ColumnType::DateTime => "date",
+    });
+}
+
+/// Returns the name of the column with the number of elements of a nested path.
+pub(crate) fn nested_count_column_name(nested_path_column_name: &str) -> String {
+    format!("{}{}count", nested_path_column_name, NESTED_COLUMN_SEP)
+}
+
+/// The elements of a nested json path in a segment, as documents with their own fast fields.
+///
+/// The nested documents of a document are numbered consecutively, in the order of the
+/// documents. Their fast fields have the same names as in the segment, e.g. `order.items.sku`,
+/// but only hold the values of the element.
+pub(crate) struct NestedDocuments {
+    /// The first nested document of every document, followed by the number of nested documents.
+    doc_starts: Vec<DocId>,
+    fast_fields: FastFieldReaders,
+}
+
+impl NestedDocuments {
+    /// Builds the nested documents of the `nested_path` with the fast fields `field_names`, which
+    /// have to be below the nested path.
+    ///
+    /// The fast fields are copied into memory, so that the cost grows with the number of values
+    /// of the fields.
+    pub(crate) fn build<'a>(
+        fast_fields: &FastFieldReaders,
+        max_doc: DocId,
+        nested_path: &str,
+        field_names: impl Iterator<Item = &'a str>,
+    ) -> crate::Result<NestedDocuments> {
+        let mut doc_starts = Vec::with_capacity(max_doc as usize + 1);
+        let mut num_nested_docs: DocId = 0;
+        let element_counts = fast_fields.nested_element_counts(nested_path)?;
+        for doc in 0..max_doc {
+            doc_starts.push(num_nested_docs);
+            if let Some(element_counts) = element_counts.as_ref() {
+                num_nested_docs += element_counts.first(doc).unwrap_or(0) as DocId;
+            }
+        }
+        doc_starts.push(num_nested_docs);
+
+        let field_prefix = format!("{}.", nested_path);
+        let mut columnar_writer = ColumnarWriter::default();
+        let mut str_buffer = String::new();
+        let mut bytes_buffer = Vec::new();
+        for field_name in field_names {
+            if !field_name.starts_with(&field_prefix) {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "Field {} is not below the nested path {}",
+                    field_name, nested_path
+                )));
+            }
+            let Some(column_name) = fast_fields.resolve_field(field_name) else {
+                continue;
+            };
+            for (column, ordinals) in fast_fields.nested_columns(field_name)? {
+                for doc in 0..max_doc {
+                    let nested_docs = doc_starts[doc as usize]..doc_starts[doc as usize + 1];
+                    // Ordinals outside of the elements, e.g. of a path inside another nested
+                    // path, are ignored.
+                    let rows = ordinals
+                        .values(doc)
+                        .map(|ordinal| nested_docs.start.saturating_add(ordinal as DocId))
+                        .map(|row| Some(row).filter(|row| nested_docs.contains(row)));
+                    record_nested_values(
+                        &column,
+                        doc,
+                        rows,
+                        &column_name,
+                        &mut columnar_writer,
+                        &mut str_buffer,
+                        &mut bytes_buffer,
+                    )?;
+                }
+            }
+        }
+        let mut columnar_bytes = Vec::new();
+        columnar_writer.serialize(num_nested_docs, None, &mut columnar_bytes)?;
+        let columnar = ColumnarReader::open(FileSlice::from(columnar_bytes))?;
+        Ok(NestedDocuments {
+            doc_starts,
+            fast_fields: FastFieldReaders::from_columnar(columnar, fast_fields.schema().clone()),
+        })
+    }
+
+    /// Returns the nested documents of a document.
+    pub(crate) fn nested_docs(&self, doc: DocId) -> Range<DocId> {
+        self.doc_starts[doc as usize]..self.doc_starts[doc as usize + 1]
+    }
+
+    /// Returns the number of nested documents.
+    pub(crate) fn num_nested_docs(&self) -> DocId {
+        self.doc_starts.last().copied().unwrap_or(0)
+    }
+
+    /// Returns the fast fields of the nested documents.
+    pub(crate) fn fast_fields(&self) -> &FastFieldReaders {
+        &self.fast_fields
+    }
+}
+
+/// Records the values of a document into the rows of their nested documents.
+fn record_nested_values(
+    column: &DynamicColumn,
+    doc: DocId,
+    rows: impl Iterator<Item = Option<DocId>>,
+    column_name: &str,
+    columnar_writer: &mut ColumnarWriter,
+    str_buffer: &mut String,
+    bytes_buffer: &mut Vec<u8>,
+) -> crate::Result<()> {
+    match column {
+        DynamicColumn::Bool(column) => {
+            for (row, val) in rows.zip(column.values(doc)) {
+                if let Some(row) = row {
+                    columnar_writer.record_bool(row, column_name, val);
+                }
+            }
+        }
+        DynamicColumn::I64(column) => {
+            for (row, val) in rows.zip(column.values(doc)) {
+                if let Some(row) = row {
+                    columnar_writer.record_numerical(row, column_name, NumericalValue::from(val));
+                }
+            }
+        }
+        DynamicColumn::U64(column) => {
+            for (row, val) in rows.zip(column.values(doc)) {
+                if let Some(row) = row {
+                    columnar_writer.record_numerical(row, column_name, NumericalValue::from(val));
+                }
+            }
+        }
+        DynamicColumn::F64(column) => {
+            for (row, val) in rows.zip(column.values(doc)) {
+                if let Some(row) = row {
+                    columnar_writer.record_numerical(row, column_name, NumericalValue::from(val));
+                }
+            }
+        }
+        DynamicColumn::IpAddr(column) => {
+            for (row, val) in rows.zip(column.values(doc)) {
+                if let Some(row) = row {
+                    columnar_writer.record_ip_addr(row, column_name, val);
+                }
+            }
+        }
+        DynamicColumn::DateTime(column) => {
+            for (row, val) in rows.zip(column.values(doc)) {
+                if let Some(row) = row {
+                    columnar_writer.record_datetime(row, column_name, val);
+                }
+            }
+        }
+        DynamicColumn::Bytes(column) => {
+            for (row, term_ord) in rows.zip(column.term_ords(doc)) {
+                if let Some(row) = row {
+                    bytes_buffer.clear();
+                    column.ord_to_bytes(term_ord, bytes_buffer)?;
+                    columnar_writer.record_bytes(row, column_name, bytes_buffer);
+                }
+            }
+        }
+        DynamicColumn::Str(column) => {
+            for (row, term_ord) in rows.zip(column.term_ords(doc)) {
+                if let Some(row) = row {
+                    str_buffer.clear();
+                    column.ord_to_str(term_ord, str_buffer)?;
+                    columnar_writer.record_str(row, column_name, str_buffer);
+                }
+            }
+        }
+    }
+    Ok(())
+}
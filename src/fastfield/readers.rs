@@ -7,8 +7,11 @@ use columnar::{
     DynamicColumnHandle, HasAssociatedColumnType, StrColumn,
 };
 
+use super::nested::{nested_count_column_name, nested_ordinal_column_name};
 use crate::directory::FileSlice;
-use crate::schema::Schema;
+use crate::indexer::split_json_path;
+use crate::schema::term::JSON_PATH_SEGMENT_SEP_STR;
+use crate::schema::{FieldType, Schema};
 use crate::space_usage::{FieldUsage, PerFieldSpaceUsage};
 
 /// Provides access to all of the BitpackedFastFieldReader.
@@ -18,12 +21,110 @@ use crate::space_usage::{FieldUsage, PerFieldSpaceUsage};
 #[derive(Clone)]
 pub struct FastFieldReaders {
     columnar: Arc<ColumnarReader>,
+    schema: Schema,
 }
 
 impl FastFieldReaders {
-    pub(crate) fn open(fast_field_file: FileSlice) -> io::Result<FastFieldReaders> {
+    pub(crate) fn open(fast_field_file: FileSlice, schema: Schema) -> io::Result<FastFieldReaders> {
         let columnar = Arc::new(ColumnarReader::open(fast_field_file)?);
-        Ok(FastFieldReaders { columnar })
+        Ok(FastFieldReaders { columnar, schema })
+    }
+
+    /// Returns the name of the columns associated to a field name.
+    ///
+    /// The field name is either the name of a field, or a path into a fast json field like
+    /// `attributes.size`, where dots that are part of a key of the json object are escaped.
+    /// Returns `None` if the field name doesn't match any field.
+    pub(crate) fn resolve_field(&self, field_name: &str) -> Option<String> {
+        let (field, json_path) = self.schema.find_field(field_name)?;
+        if json_path.is_empty() {
+            return Some(field_name.to_string());
+        }
+        let field_entry = self.schema.get_field_entry(field);
+        if !matches!(field_entry.field_type(), FieldType::JsonObject(_)) {
+            return None;
+        }
+        let mut column_name = field_entry.name().to_string();
+        for segment in split_json_path(json_path) {
+            column_name.push_str(JSON_PATH_SEGMENT_SEP_STR);
+            column_name.push_str(&segment);
+        }
+        Some(column_name)
+    }
+
+    fn read_columns(&self, field_name: &str) -> crate::Result<Vec<DynamicColumnHandle>> {
+        let Some(column_name) = self.resolve_field(field_name) else {
+            return Ok(Vec::new());
+        };
+        Ok(self.columnar.read_columns(&column_name)?)
+    }
+
+    /// Creates the fast field readers of a columnar, which isn't the fast field file of a segment,
+    /// e.g. the columnar of the nested documents of a segment.
+    pub(crate) fn from_columnar(columnar: ColumnarReader, schema: Schema) -> FastFieldReaders {
+        FastFieldReaders {
+            columnar: Arc::new(columnar),
+            schema,
+        }
+    }
+
+    /// Returns the column with the number of elements of a nested json path like `order.items`
+    /// for every document, see [`JsonObjectOptions::add_nested_path`].
+    ///
+    /// [`JsonObjectOptions::add_nested_path`]: crate::schema::JsonObjectOptions::add_nested_path
+    pub(crate) fn nested_element_counts(
+        &self,
+        nested_path: &str,
+    ) -> crate::Result<Option<Column<u64>>> {
+        let Some(column_name) = self.resolve_field(nested_path) else {
+            return Ok(None);
+        };
+        let count_column_name = nested_count_column_name(&column_name);
+        let Some(column_handle) = self
+            .columnar
+            .read_columns(&count_column_name)?
+            .into_iter()
+            .find(|column_handle| column_handle.column_type() == ColumnType::U64)
+        else {
+            return Ok(None);
+        };
+        Ok(column_handle.open()?.into())
+    }
+
+    /// Returns the columns of a json path below a nested path, e.g. `order.items.sku`, together
+    /// with the element ordinals of their values.
+    ///
+    /// Values recorded before the path was declared nested have no element ordinals and are
+    /// ignored.
+    pub(crate) fn nested_columns(
+        &self,
+        field_name: &str,
+    ) -> crate::Result<Vec<(DynamicColumn, Column<u64>)>> {
+        let Some(column_name) = self.resolve_field(field_name) else {
+            return Ok(Vec::new());
+        };
+        let mut nested_columns = Vec::new();
+        for column_handle in self.columnar.read_columns(&column_name)? {
+            let ordinal_column_name =
+                nested_ordinal_column_name(&column_name, column_handle.column_type());
+            let ordinal_column_handle = self
+                .columnar
+                .read_columns(&ordinal_column_name)?
+                .into_iter()
+                .find(|ordinal_handle| ordinal_handle.column_type() == ColumnType::U64);
+            let Some(ordinal_column_handle) = ordinal_column_handle else {
+                continue;
+            };
+            let ordinal_column: Option<Column<u64>> = ordinal_column_handle.open()?.into();
+            if let Some(ordinal_column) = ordinal_column {
+                nested_columns.push((column_handle.open()?, ordinal_column));
+            }
+        }
+        Ok(nested_columns)
+    }
+
+    pub(crate) fn schema(&self) -> &Schema {
+        &self.schema
     }
 
     pub(crate) fn columnar(&self) -> &ColumnarReader {
@@ -68,7 +169,6 @@ impl FastFieldReaders {
     /// Returns the number of `bytes` associated with a column.
     pub fn column_num_bytes(&self, field: &str) -> crate::Result<usize> {
         Ok(self
-            .columnar
             .read_columns(field)?
             .into_iter()
             .map(|column_handle| column_handle.num_bytes())
@@ -143,7 +243,6 @@ impl FastFieldReaders {
         column_type: ColumnType,
     ) -> crate::Result<Option<DynamicColumnHandle>> {
         let dynamic_column_handle_opt = self
-            .columnar
             .read_columns(field_name)?
             .into_iter()
             .filter(|column| column.column_type() == column_type)
@@ -154,9 +253,34 @@ impl FastFieldReaders {
     /// Returns the `u64` column used to represent any `u64`-mapped typed (i64, u64, f64, DateTime).
     #[doc(hidden)]
     pub fn u64_lenient(&self, field_name: &str) -> crate::Result<Option<Column<u64>>> {
-        for col in self.columnar.read_columns(field_name)? {
-            if let Some(col_u64) = col.open_u64_lenient()? {
-                return Ok(Some(col_u64));
+        Ok(self
+            .u64_lenient_for_type(None, field_name)?
+            .map(|(column, _column_type)| column))
+    }
+
+    /// Returns the `u64` column used to represent any `u64`-mapped typed (i64, u64, f64,
+    /// DateTime), together with the type of the column.
+    ///
+    /// A json path can have several columns of different types. If `type_white_list` is set,
+    /// only columns of these types are considered, in the order of the list.
+    #[doc(hidden)]
+    pub fn u64_lenient_for_type(
+        &self,
+        type_white_list: Option<&[ColumnType]>,
+        field_name: &str,
+    ) -> crate::Result<Option<(Column<u64>, ColumnType)>> {
+        let mut column_handles = self.read_columns(field_name)?;
+        if let Some(type_white_list) = type_white_list {
+            column_handles.retain(|column| type_white_list.contains(&column.column_type()));
+            column_handles.sort_by_key(|column| {
+                type_white_list
+                    .iter()
+                    .position(|column_type| *column_type == column.column_type())
+            });
+        }
+        for column_handle in column_handles {
+            if let Some(col_u64) = column_handle.open_u64_lenient()? {
+                return Ok(Some((col_u64, column_handle.column_type())));
             }
         }
         Ok(None)
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+
+use columnar::{ColumnIndex, ColumnValues};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::core::{Segment, SegmentComponent};
+use crate::fastfield::FastFieldReaders;
+use crate::schema::Type;
+
+/// Statistics of a numerical fast field in a segment, recorded in its
+/// [`SegmentMeta`](crate::SegmentMeta).
+///
+/// The values are expressed in the `u64` representation of the fast field, as returned by
+/// [`FastFieldReaders::u64_lenient`]. The min and max values are bounds, which do not take
+/// deleted documents into account: they may not be exact, but all values of the segment are
+/// guaranteed to lie in between.
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub struct FastFieldStats {
+    /// A lower bound of the values of the field.
+    pub min_value: u64,
+    /// An upper bound of the values of the field.
+    pub max_value: u64,
+    /// The number of documents without any value for the field.
+    pub num_nulls: u32,
+}
+
+fn has_stats(typ: Type) -> bool {
+    match typ {
+        Type::U64 | Type::I64 | Type::F64 | Type::Bool | Type::Date => true,
+        Type::IpAddr | Type::Str | Type::Facet | Type::Bytes | Type::Json => false,
+    }
+}
+
+/// Returns the number of rows without any value, read from the index of the column.
+fn num_nulls(column_index: &ColumnIndex) -> u32 {
+    match column_index {
+        ColumnIndex::Full => 0,
+        ColumnIndex::Optional(optional_index) => {
+            optional_index.num_rows() - optional_index.num_non_nulls()
+        }
+        ColumnIndex::Multivalued(multivalued_index) => {
+            // The values of a row range from its start offset to the one of the next row.
+            multivalued_index
+                .start_index_column
+                .iter()
+                .tuple_windows()
+                .filter(|(start, end)| start == end)
+                .count() as u32
+        }
+    }
+}
+
+/// Computes the statistics of the numerical fast fields of a segment, once its fast fields
+/// are written.
+///
+/// Fields without any value in the segment have no statistics.
+pub(crate) fn compute_fast_field_stats(
+    segment: &Segment,
+) -> crate::Result<BTreeMap<String, FastFieldStats>> {
+    let schema = segment.schema();
+    let fast_fields_data = segment.open_read(SegmentComponent::FastFields)?;
+    let fast_field_readers = FastFieldReaders::open(fast_fields_data, schema.clone())?;
+    let mut fast_field_stats = BTreeMap::new();
+    for (_field, field_entry) in schema.fields() {
+        if !field_entry.is_fast() || !has_stats(field_entry.field_type().value_type()) {
+            continue;
+        }
+        let Some(column) = fast_field_readers.u64_lenient(field_entry.name())? else {
+            continue;
+        };
+        if column.values.num_vals() == 0 {
+            continue;
+        }
+        let stats = FastFieldStats {
+            min_value: column.min_value(),
+            max_value: column.max_value(),
+            num_nulls: num_nulls(&column.idx),
+        };
+        fast_field_stats.insert(field_entry.name().to_string(), stats);
+    }
+    Ok(fast_field_stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use columnar::MonotonicallyMappableToU64;
+
+    use crate::indexer::NoMergePolicy;
+    use crate::schema::{Schema, FAST, INDEXED, STRING};
+    use crate::{DateTime, Index, SegmentId};
+
+    #[test]
+    fn test_fast_field_stats() -> crate::Result<()> {
+        let mut schema_builder = Schema::builder();
+        let id_field = schema_builder.add_text_field("id", STRING | FAST);
+        let score_field = schema_builder.add_i64_field("score", FAST);
+        let date_field = schema_builder.add_date_field("date", FAST);
+        let count_field = schema_builder.add_u64_field("count", INDEXED);
+        let tag_field = schema_builder.add_u64_field("tag", FAST);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.set_merge_policy(Box::new(NoMergePolicy));
+        index_writer.add_document(doc!(
+            id_field => "a",
+            score_field => -5i64,
+            date_field => DateTime::from_timestamp_secs(100),
+            count_field => 1u64,
+            tag_field => 7u64,
+            tag_field => 2u64,
+        ))?;
+        index_writer.add_document(doc!(id_field => "b", score_field => 3i64))?;
+        index_writer.commit()?;
+        index_writer.add_document(doc!(id_field => "c", score_field => 10i64))?;
+        index_writer.commit()?;
+
+        // The statistics are persisted in the meta.json file.
+        let segment_metas = index.load_metas()?.segments;
+        let first_segment_meta = segment_metas
+            .iter()
+            .find(|segment_meta| segment_meta.max_doc() == 2)
+            .unwrap();
+        let score_stats = first_segment_meta.fast_field_stats("score").unwrap();
+        assert_eq!(score_stats.min_value, (-5i64).to_u64());
+        assert_eq!(score_stats.max_value, 3i64.to_u64());
+        assert_eq!(score_stats.num_nulls, 0);
+        let date_stats = first_segment_meta.fast_field_stats("date").unwrap();
+        assert_eq!(
+            date_stats.max_value,
+            columnar::DateTime::from(DateTime::from_timestamp_secs(100)).to_u64()
+        );
+        assert_eq!(date_stats.num_nulls, 1);
+        let tag_stats = first_segment_meta.fast_field_stats("tag").unwrap();
+        assert_eq!((tag_stats.min_value, tag_stats.max_value), (2, 7));
+        assert_eq!(tag_stats.num_nulls, 1);
+        assert!(first_segment_meta.fast_field_stats("id").is_none());
+        assert!(first_segment_meta.fast_field_stats("count").is_none());
+
+        // The statistics are recomputed on merge.
+        let segment_ids: Vec<SegmentId> = index.searchable_segment_ids()?;
+        let merged_segment_meta = index_writer.merge(&segment_ids).wait()?.unwrap();
+        let score_stats = merged_segment_meta.fast_field_stats("score").unwrap();
+        assert_eq!(score_stats.min_value, (-5i64).to_u64());
+        assert_eq!(score_stats.max_value, 10i64.to_u64());
+        assert_eq!(
+            merged_segment_meta
+                .fast_field_stats("date")
+                .unwrap()
+                .num_nulls,
+            2
+        );
+        index_writer.commit()?;
+        let segment_metas = index.searchable_segment_metas()?;
+        assert_eq!(segment_metas.len(), 1);
+        assert_eq!(
+            segment_metas[0].fast_field_stats("score"),
+            merged_segment_meta.fast_field_stats("score")
+        );
+        Ok(())
+    }
+}
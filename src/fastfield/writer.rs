@@ -2,16 +2,25 @@ use std::io;
 
 use columnar::{ColumnType, ColumnarWriter, NumericalValue};
 
+use super::nested::{nested_count_column_name, push_nested_ordinal_suffix};
 use crate::indexer::doc_id_mapping::DocIdMapping;
+use crate::indexer::{infer_type_from_str, split_json_path, TextOrDateTime};
+use crate::schema::term::JSON_PATH_SEGMENT_SEP_STR;
 use crate::schema::{Document, FieldType, Schema, Type, Value};
-use crate::{DatePrecision, DocId};
+use crate::{DatePrecision, DateTime, DocId};
 
 /// The `FastFieldsWriter` groups all of the fast field writers.
 pub struct FastFieldsWriter {
     columnar_writer: ColumnarWriter,
     fast_field_names: Vec<Option<String>>, //< TODO see if we can cash the field name hash too.
     date_precisions: Vec<DatePrecision>,
+    expand_dots: Vec<bool>,
+    /// The column names of the nested paths of every json field.
+    nested_paths: Vec<Vec<String>>,
+    /// The number of elements of every nested path in the current document.
+    nested_element_counts: Vec<(String, u64)>,
     num_docs: DocId,
+    json_path_buffer: String,
 }
 
 impl FastFieldsWriter {
@@ -23,6 +32,8 @@ impl FastFieldsWriter {
             std::iter::repeat_with(DatePrecision::default)
                 .take(schema.num_fields())
                 .collect();
+        let mut expand_dots = vec![false; schema.num_fields()];
+        let mut nested_paths = vec![Vec::new(); schema.num_fields()];
         // TODO see other types
         for (field_id, field_entry) in schema.fields() {
             if !field_entry.field_type().is_fast() {
@@ -40,6 +51,23 @@ impl FastFieldsWriter {
                 Type::Facet => ColumnType::Str,
                 Type::Bytes => ColumnType::Bytes,
                 Type::Json => {
+                    // The columns of json fields are created on the fly, one per json path.
+                    if let FieldType::JsonObject(json_options) = field_entry.field_type() {
+                        expand_dots[field_id.field_id() as usize] =
+                            json_options.is_expand_dots_enabled();
+                        nested_paths[field_id.field_id() as usize] = json_options
+                            .nested_paths()
+                            .iter()
+                            .map(|nested_path| {
+                                let mut column_name = field_entry.name().to_string();
+                                for segment in split_json_path(nested_path) {
+                                    column_name.push_str(JSON_PATH_SEGMENT_SEP_STR);
+                                    column_name.push_str(&segment);
+                                }
+                                column_name
+                            })
+                            .collect();
+                    }
                     continue;
                 }
                 Type::IpAddr => ColumnType::IpAddr,
@@ -59,6 +87,10 @@ impl FastFieldsWriter {
             fast_field_names: fast_fields,
             num_docs: 0u32,
             date_precisions,
+            expand_dots,
+            nested_paths,
+            nested_element_counts: Vec::new(),
+            json_path_buffer: String::new(),
         }
     }
 
@@ -67,14 +99,16 @@ impl FastFieldsWriter {
         self.columnar_writer.mem_usage()
     }
 
-    pub(crate) fn sort_order(
+    /// Returns, for every document, a key of the first value of the fast field `field_name`.
+    ///
+    /// See [`ColumnarWriter::first_value_sort_keys`].
+    pub(crate) fn first_value_sort_keys(
         &self,
-        sort_field: &str,
+        field_name: &str,
         num_docs: DocId,
-        reversed: bool,
-    ) -> Vec<DocId> {
+    ) -> Vec<Option<u64>> {
         self.columnar_writer
-            .sort_order(sort_field, num_docs, reversed)
+            .first_value_sort_keys(field_name, num_docs)
     }
 
     /// Indexes all of the fastfields of a new document.
@@ -136,7 +170,24 @@ impl FastFieldsWriter {
                             facet.encoded_str(),
                         );
                     }
-                    Value::JsonObject(_) => todo!(),
+                    Value::JsonObject(json_obj) => {
+                        let field_id = field_value.field().field_id() as usize;
+                        self.json_path_buffer.clear();
+                        self.json_path_buffer.push_str(field_name);
+                        let mut nested_elements = NestedElements {
+                            nested_paths: &self.nested_paths[field_id],
+                            element_counts: &mut self.nested_element_counts,
+                        };
+                        record_json_obj_to_columnar_writer(
+                            doc_id,
+                            json_obj,
+                            self.expand_dots[field_id],
+                            &mut self.json_path_buffer,
+                            &mut self.columnar_writer,
+                            &mut nested_elements,
+                            None,
+                        );
+                    }
                     Value::IpAddr(ip_addr) => {
                         self.columnar_writer
                             .record_ip_addr(doc_id, field_name.as_str(), *ip_addr);
@@ -144,6 +195,16 @@ impl FastFieldsWriter {
                 }
             }
         }
+        for (nested_path, element_count) in self.nested_element_counts.drain(..) {
+            let count_column_name = nested_count_column_name(&nested_path);
+            self.columnar_writer
+                .record_column_type(&count_column_name, ColumnType::U64, false);
+            self.columnar_writer.record_numerical(
+                doc_id,
+                &count_column_name,
+                NumericalValue::from(element_count),
+            );
+        }
         self.num_docs += 1;
         Ok(())
     }
@@ -163,3 +224,173 @@ impl FastFieldsWriter {
         Ok(())
     }
 }
+
+/// The nested paths of a json field and the number of their elements in the current document.
+struct NestedElements<'a> {
+    nested_paths: &'a [String],
+    element_counts: &'a mut Vec<(String, u64)>,
+}
+
+impl<'a> NestedElements<'a> {
+    /// Returns the ordinal of a new element, if the path is nested.
+    fn next_ordinal(&mut self, column_name: &str) -> Option<u64> {
+        if !self.nested_paths.iter().any(|path| path == column_name) {
+            return None;
+        }
+        if let Some((_, element_count)) = self
+            .element_counts
+            .iter_mut()
+            .find(|(path, _)| path == column_name)
+        {
+            *element_count += 1;
+            return Some(*element_count - 1);
+        }
+        self.element_counts.push((column_name.to_string(), 1));
+        Some(0)
+    }
+}
+
+/// Records the element ordinal of the value, which was just recorded in the column
+/// `json_path_buffer`.
+fn record_nested_ordinal(
+    doc: DocId,
+    column_type: ColumnType,
+    nested_ordinal: Option<u64>,
+    json_path_buffer: &mut String,
+    columnar_writer: &mut ColumnarWriter,
+) {
+    if let Some(nested_ordinal) = nested_ordinal {
+        let len_path = json_path_buffer.len();
+        push_nested_ordinal_suffix(json_path_buffer, column_type);
+        // Small numbers would be coerced to i64 otherwise.
+        columnar_writer.record_column_type(json_path_buffer, ColumnType::U64, false);
+        columnar_writer.record_numerical(
+            doc,
+            json_path_buffer,
+            NumericalValue::from(nested_ordinal),
+        );
+        json_path_buffer.truncate(len_path);
+    }
+}
+
+fn record_json_obj_to_columnar_writer(
+    doc: DocId,
+    json_obj: &serde_json::Map<String, serde_json::Value>,
+    expand_dots: bool,
+    json_path_buffer: &mut String,
+    columnar_writer: &mut ColumnarWriter,
+    nested_elements: &mut NestedElements,
+    nested_ordinal: Option<u64>,
+) {
+    for (key, child) in json_obj {
+        let len_path = json_path_buffer.len();
+        json_path_buffer.push_str(JSON_PATH_SEGMENT_SEP_STR);
+        if expand_dots {
+            json_path_buffer.push_str(&key.replace('.', JSON_PATH_SEGMENT_SEP_STR));
+        } else {
+            json_path_buffer.push_str(key);
+        }
+        record_json_value_to_columnar_writer(
+            doc,
+            child,
+            expand_dots,
+            json_path_buffer,
+            columnar_writer,
+            nested_elements,
+            nested_ordinal,
+        );
+        json_path_buffer.truncate(len_path);
+    }
+}
+
+fn record_json_value_to_columnar_writer(
+    doc: DocId,
+    json_val: &serde_json::Value,
+    expand_dots: bool,
+    json_path_buffer: &mut String,
+    columnar_writer: &mut ColumnarWriter,
+    nested_elements: &mut NestedElements,
+    nested_ordinal: Option<u64>,
+) {
+    match json_val {
+        serde_json::Value::Null => {}
+        serde_json::Value::Bool(bool_val) => {
+            columnar_writer.record_bool(doc, json_path_buffer, *bool_val);
+            record_nested_ordinal(
+                doc,
+                ColumnType::Bool,
+                nested_ordinal,
+                json_path_buffer,
+                columnar_writer,
+            );
+        }
+        serde_json::Value::Number(number) => {
+            // Numbers of different types end up in the same column, which is coerced to a type
+            // that can hold all of them.
+            let numerical_value = if let Some(number_u64) = number.as_u64() {
+                NumericalValue::from(number_u64)
+            } else if let Some(number_i64) = number.as_i64() {
+                NumericalValue::from(number_i64)
+            } else if let Some(number_f64) = number.as_f64() {
+                NumericalValue::from(number_f64)
+            } else {
+                return;
+            };
+            columnar_writer.record_numerical(doc, json_path_buffer, numerical_value);
+            record_nested_ordinal(
+                doc,
+                ColumnType::U64,
+                nested_ordinal,
+                json_path_buffer,
+                columnar_writer,
+            );
+        }
+        serde_json::Value::String(text) => {
+            let column_type = match infer_type_from_str(text) {
+                TextOrDateTime::Text(text) => {
+                    columnar_writer.record_str(doc, json_path_buffer, text);
+                    ColumnType::Str
+                }
+                TextOrDateTime::DateTime(dt) => {
+                    let datetime = DateTime::from_utc(dt).truncate(DatePrecision::Seconds);
+                    columnar_writer.record_datetime(doc, json_path_buffer, datetime.into());
+                    ColumnType::DateTime
+                }
+            };
+            record_nested_ordinal(
+                doc,
+                column_type,
+                nested_ordinal,
+                json_path_buffer,
+                columnar_writer,
+            );
+        }
+        serde_json::Value::Array(arr) => {
+            for child in arr {
+                record_json_value_to_columnar_writer(
+                    doc,
+                    child,
+                    expand_dots,
+                    json_path_buffer,
+                    columnar_writer,
+                    nested_elements,
+                    nested_ordinal,
+                );
+            }
+        }
+        serde_json::Value::Object(json_obj) => {
+            // Every object at a nested path is an element, unless it is inside another element.
+            let nested_ordinal =
+                nested_ordinal.or_else(|| nested_elements.next_ordinal(json_path_buffer));
+            record_json_obj_to_columnar_writer(
+                doc,
+                json_obj,
+                expand_dots,
+                json_path_buffer,
+                columnar_writer,
+                nested_elements,
+                nested_ordinal,
+            );
+        }
+    }
+}
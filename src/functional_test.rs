@@ -78,10 +78,10 @@ fn test_functional_indexing_sorted() -> crate::Result<()> {
 
     let mut index_builder = Index::builder().schema(schema);
     index_builder = index_builder.settings(IndexSettings {
-        sort_by_field: Some(IndexSortByField {
+        sort_by_fields: vec![IndexSortByField {
             field: "id".to_string(),
             order: Order::Desc,
-        }),
+        }],
         ..Default::default()
     });
     let index = index_builder.create_from_tempdir().unwrap();
@@ -1,11 +1,14 @@
 //! This module is used when sorting the index by a property, e.g.
 //! to get mappings from old doc_id to new doc_id and vice versa, after sorting
 
+use std::cmp::Ordering;
+
+use columnar::{BytesColumn, Cardinality, Column, ColumnValues, TermMerger};
 use common::ReadOnlyBitSet;
 
 use super::SegmentWriter;
-use crate::schema::{Field, Schema};
-use crate::{DocAddress, DocId, IndexSortByField, TantivyError};
+use crate::schema::{Field, Schema, Type};
+use crate::{DocAddress, DocId, IndexSortByField, SegmentReader, TantivyError};
 
 #[derive(Copy, Clone, Eq, PartialEq)]
 pub enum MappingType {
@@ -121,6 +124,226 @@ impl DocIdMapping {
     }
 }
 
+/// Returns true if the documents of an index can be sorted by a fast field of type `typ`.
+pub(crate) fn is_type_valid_for_index_sort(typ: Type) -> bool {
+    match typ {
+        Type::U64 | Type::I64 | Type::F64 | Type::Bool | Type::Date | Type::Str | Type::Bytes => {
+            true
+        }
+        Type::Facet | Type::IpAddr | Type::Json => false,
+    }
+}
+
+/// The sort keys of the documents of a segment, for one of the fields the index is sorted by.
+///
+/// The keys are `u64`s ordered like the first value of the documents for the field. A document
+/// without any value has no key, which comes before any key.
+pub(crate) enum SortKeyColumn {
+    /// The keys of the documents of a segment being written.
+    Keys(Vec<Option<u64>>),
+    /// The keys read from the fast field of a segment.
+    ///
+    /// For text and bytes fields, the column holds term ordinals, which are mapped to keys
+    /// comparable across the segments being merged.
+    Column {
+        column: Column<u64>,
+        term_ord_to_key: Option<Vec<u64>>,
+    },
+    /// The segment has no value for the field.
+    Empty,
+}
+
+impl SortKeyColumn {
+    #[inline]
+    fn key(&self, doc: DocId) -> Option<u64> {
+        match self {
+            SortKeyColumn::Keys(keys) => keys[doc as usize],
+            SortKeyColumn::Column {
+                column,
+                term_ord_to_key,
+            } => {
+                let val = column.first(doc)?;
+                Some(match term_ord_to_key {
+                    Some(term_ord_to_key) => term_ord_to_key[val as usize],
+                    None => val,
+                })
+            }
+            SortKeyColumn::Empty => None,
+        }
+    }
+
+    /// Returns a lower and an upper bound of the keys of the segment.
+    fn key_bounds(&self) -> (Option<u64>, Option<u64>) {
+        match self {
+            SortKeyColumn::Keys(keys) => (
+                keys.iter().min().copied().flatten(),
+                keys.iter().max().copied().flatten(),
+            ),
+            SortKeyColumn::Column {
+                column,
+                term_ord_to_key,
+            } => {
+                if column.values.num_vals() == 0 {
+                    return (None, None);
+                }
+                let to_key = |val: u64| match term_ord_to_key {
+                    Some(term_ord_to_key) => {
+                        term_ord_to_key[(val as usize).min(term_ord_to_key.len() - 1)]
+                    }
+                    None => val,
+                };
+                let lower_bound = if column.get_cardinality() == Cardinality::Full {
+                    Some(to_key(column.min_value()))
+                } else {
+                    None
+                };
+                (lower_bound, Some(to_key(column.max_value())))
+            }
+            SortKeyColumn::Empty => (None, None),
+        }
+    }
+}
+
+/// The sort keys of the documents of a segment, for all of the fields the index is sorted by.
+pub(crate) struct SegmentSortKeys {
+    columns: Vec<SortKeyColumn>,
+}
+
+impl SegmentSortKeys {
+    /// Opens the sort keys of the segments to merge.
+    ///
+    /// The term ordinals of text and bytes fields are specific to each segment, so they are
+    /// mapped to the rank of their term among the terms of all of the segments.
+    pub(crate) fn open_for_merge(
+        readers: &[SegmentReader],
+        sort_by_fields: &[IndexSortByField],
+    ) -> crate::Result<Vec<SegmentSortKeys>> {
+        let mut segments_columns: Vec<Vec<SortKeyColumn>> =
+            readers.iter().map(|_| Vec::new()).collect();
+        for sort_by_field in sort_by_fields {
+            let columns = open_sort_key_columns(readers, sort_by_field)?;
+            for (segment_columns, column) in segments_columns.iter_mut().zip(columns) {
+                segment_columns.push(column);
+            }
+        }
+        Ok(segments_columns
+            .into_iter()
+            .map(|columns| SegmentSortKeys { columns })
+            .collect())
+    }
+
+    /// Compares the document `doc` of this segment with the document `other_doc` of the
+    /// segment `other`, in the order of the index.
+    pub(crate) fn compare(
+        &self,
+        doc: DocId,
+        other: &SegmentSortKeys,
+        other_doc: DocId,
+        sort_by_fields: &[IndexSortByField],
+    ) -> Ordering {
+        for (sort_by_field, (column, other_column)) in sort_by_fields
+            .iter()
+            .zip(self.columns.iter().zip(other.columns.iter()))
+        {
+            let ordering = column.key(doc).cmp(&other_column.key(other_doc));
+            let ordering = if sort_by_field.order.is_desc() {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+
+    /// Returns a lower and an upper bound of the keys of the primary sort field.
+    pub(crate) fn primary_key_bounds(&self) -> (Option<u64>, Option<u64>) {
+        self.columns
+            .first()
+            .map(SortKeyColumn::key_bounds)
+            .unwrap_or((None, None))
+    }
+}
+
+fn open_sort_key_columns(
+    readers: &[SegmentReader],
+    sort_by_field: &IndexSortByField,
+) -> crate::Result<Vec<SortKeyColumn>> {
+    let Some(reader) = readers.first() else {
+        return Ok(Vec::new());
+    };
+    let schema = reader.schema();
+    let field = expect_field_id_for_sort_field(schema, sort_by_field)?;
+    let field_name = sort_by_field.field.as_str();
+    let typ = schema.get_field_entry(field).field_type().value_type();
+    match typ {
+        Type::Str | Type::Bytes => {
+            let mut bytes_columns: Vec<Option<BytesColumn>> = Vec::with_capacity(readers.len());
+            for reader in readers {
+                let bytes_column_opt = if typ == Type::Str {
+                    reader.fast_fields().str(field_name)?.map(BytesColumn::from)
+                } else {
+                    reader.fast_fields().bytes(field_name)?
+                };
+                bytes_columns.push(bytes_column_opt);
+            }
+            let mut term_ord_to_keys: Vec<Vec<u64>> = bytes_columns
+                .iter()
+                .map(|bytes_column_opt| {
+                    let num_terms = bytes_column_opt
+                        .as_ref()
+                        .map(BytesColumn::num_terms)
+                        .unwrap_or(0);
+                    vec![0u64; num_terms]
+                })
+                .collect();
+            let mut streams = Vec::new();
+            let mut stream_segment_ords = Vec::new();
+            for (segment_ord, bytes_column) in bytes_columns.iter().enumerate() {
+                if let Some(bytes_column) = bytes_column {
+                    streams.push(bytes_column.dictionary().stream()?);
+                    stream_segment_ords.push(segment_ord);
+                }
+            }
+            let mut merged_terms = TermMerger::new(streams);
+            let mut key = 0u64;
+            while merged_terms.advance() {
+                for (stream_ord, term_ord) in merged_terms.matching_segments() {
+                    term_ord_to_keys[stream_segment_ords[stream_ord]][term_ord as usize] = key;
+                }
+                key += 1;
+            }
+            Ok(bytes_columns
+                .iter()
+                .zip(term_ord_to_keys)
+                .map(|(bytes_column_opt, term_ord_to_key)| {
+                    let Some(bytes_column) = bytes_column_opt else {
+                        return SortKeyColumn::Empty;
+                    };
+                    SortKeyColumn::Column {
+                        column: bytes_column.ords().clone(),
+                        term_ord_to_key: Some(term_ord_to_key),
+                    }
+                })
+                .collect())
+        }
+        _ => readers
+            .iter()
+            .map(|reader| {
+                Ok(match reader.fast_fields().u64_lenient(field_name)? {
+                    Some(column) => SortKeyColumn::Column {
+                        column,
+                        term_ord_to_key: None,
+                    },
+                    None => SortKeyColumn::Empty,
+                })
+            })
+            .collect(),
+    }
+}
+
 pub(crate) fn expect_field_id_for_sort_field(
     schema: &Schema,
     sort_by_field: &IndexSortByField,
@@ -135,17 +358,26 @@ pub(crate) fn expect_field_id_for_sort_field(
 
 // Generates a document mapping in the form of [index new doc_id] -> old doc_id
 // TODO detect if field is already sorted and discard mapping
-pub(crate) fn get_doc_id_mapping_from_field(
-    sort_by_field: IndexSortByField,
+pub(crate) fn get_doc_id_mapping_from_fields(
+    sort_by_fields: &[IndexSortByField],
     segment_writer: &SegmentWriter,
 ) -> crate::Result<DocIdMapping> {
     let schema = segment_writer.segment_serializer.segment().schema();
-    expect_field_id_for_sort_field(&schema, &sort_by_field)?; // for now expect
-    let new_doc_id_to_old = segment_writer.fast_field_writers.sort_order(
-        sort_by_field.field.as_str(),
-        segment_writer.max_doc(),
-        sort_by_field.order.is_desc(),
-    );
+    let max_doc = segment_writer.max_doc();
+    let mut columns = Vec::with_capacity(sort_by_fields.len());
+    for sort_by_field in sort_by_fields {
+        expect_field_id_for_sort_field(&schema, sort_by_field)?;
+        let keys = segment_writer
+            .fast_field_writers
+            .first_value_sort_keys(&sort_by_field.field, max_doc);
+        columns.push(SortKeyColumn::Keys(keys));
+    }
+    let sort_keys = SegmentSortKeys { columns };
+    // The sort is stable: documents with the same keys keep the order they were added in.
+    let mut new_doc_id_to_old: Vec<DocId> = (0..max_doc).collect();
+    new_doc_id_to_old.sort_by(|&left_doc, &right_doc| {
+        sort_keys.compare(left_doc, &sort_keys, right_doc, sort_by_fields)
+    });
     // create new doc_id to old doc_id index (used in fast_field_writers)
     Ok(DocIdMapping::from_new_id_to_old_id(new_doc_id_to_old))
 }
@@ -154,9 +386,13 @@ pub(crate) fn get_doc_id_mapping_from_field(
 mod tests_indexsorting {
     use crate::collector::TopDocs;
     use crate::indexer::doc_id_mapping::DocIdMapping;
+    use crate::indexer::NoMergePolicy;
     use crate::query::QueryParser;
     use crate::schema::{Schema, *};
-    use crate::{DocAddress, Index, IndexSettings, IndexSortByField, Order};
+    use crate::{
+        DocAddress, Index, IndexSettings, IndexSortByField, Order, SegmentId, SegmentReader,
+        TantivyError,
+    };
 
     fn create_test_index(
         index_settings: Option<IndexSettings>,
@@ -230,10 +466,10 @@ mod tests_indexsorting {
             // sort by field asc
             let index = create_test_index(
                 Some(IndexSettings {
-                    sort_by_field: Some(IndexSortByField {
+                    sort_by_fields: vec![IndexSortByField {
                         field: "my_number".to_string(),
                         order: Order::Asc,
-                    }),
+                    }],
                     ..Default::default()
                 }),
                 option.clone(),
@@ -262,10 +498,10 @@ mod tests_indexsorting {
             // sort by field desc
             let index = create_test_index(
                 Some(IndexSettings {
-                    sort_by_field: Some(IndexSortByField {
+                    sort_by_fields: vec![IndexSortByField {
                         field: "my_number".to_string(),
                         order: Order::Desc,
-                    }),
+                    }],
                     ..Default::default()
                 }),
                 option.clone(),
@@ -321,10 +557,10 @@ mod tests_indexsorting {
         // sort by field asc
         let index = create_test_index(
             Some(IndexSettings {
-                sort_by_field: Some(IndexSortByField {
+                sort_by_fields: vec![IndexSortByField {
                     field: "my_number".to_string(),
                     order: Order::Asc,
-                }),
+                }],
                 ..Default::default()
             }),
             get_text_options(),
@@ -346,10 +582,10 @@ mod tests_indexsorting {
         // sort by field desc
         let index = create_test_index(
             Some(IndexSettings {
-                sort_by_field: Some(IndexSortByField {
+                sort_by_fields: vec![IndexSortByField {
                     field: "my_number".to_string(),
                     order: Order::Desc,
-                }),
+                }],
                 ..Default::default()
             }),
             get_text_options(),
@@ -381,10 +617,10 @@ mod tests_indexsorting {
 
         let index = create_test_index(
             Some(IndexSettings {
-                sort_by_field: Some(IndexSortByField {
+                sort_by_fields: vec![IndexSortByField {
                     field: "my_number".to_string(),
                     order: Order::Asc,
-                }),
+                }],
                 ..Default::default()
             }),
             get_text_options(),
@@ -412,10 +648,10 @@ mod tests_indexsorting {
         // sort by field desc
         let index = create_test_index(
             Some(IndexSettings {
-                sort_by_field: Some(IndexSortByField {
+                sort_by_fields: vec![IndexSortByField {
                     field: "my_number".to_string(),
                     order: Order::Desc,
-                }),
+                }],
                 ..Default::default()
             }),
             get_text_options(),
@@ -448,16 +684,16 @@ mod tests_indexsorting {
     // fn test_sort_index_fast_field() -> crate::Result<()> {
     //     let index = create_test_index(
     //         Some(IndexSettings {
-    //             sort_by_field: Some(IndexSortByField {
+    //             sort_by_fields: vec![IndexSortByField {
     //                 field: "my_number".to_string(),
     //                 order: Order::Asc,
-    //             }),
+    //             }],
     //             ..Default::default()
     //         }),
     //         get_text_options(),
     //     )?;
     //     assert_eq!(
-    //         index.settings().sort_by_field.as_ref().unwrap().field,
+    //         index.settings().sort_by_fields[0].field,
     //         "my_number".to_string()
     //     );
 
@@ -487,6 +723,144 @@ mod tests_indexsorting {
     //     Ok(())
     // }
 
+    fn compound_sort_settings() -> IndexSettings {
+        IndexSettings {
+            sort_by_fields: vec![
+                IndexSortByField {
+                    field: "tenant".to_string(),
+                    order: Order::Asc,
+                },
+                IndexSortByField {
+                    field: "timestamp".to_string(),
+                    order: Order::Desc,
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn tenants_and_timestamps(
+        segment_reader: &SegmentReader,
+    ) -> crate::Result<Vec<(Option<String>, i64)>> {
+        let tenant_column = segment_reader.fast_fields().str("tenant")?.unwrap();
+        let timestamp_column = segment_reader.fast_fields().i64("timestamp")?;
+        let mut tenants_and_timestamps = Vec::new();
+        for doc in 0..segment_reader.max_doc() {
+            let tenant = tenant_column
+                .ords()
+                .first(doc)
+                .map(|term_ord| {
+                    let mut tenant = String::new();
+                    tenant_column.ord_to_str(term_ord, &mut tenant)?;
+                    Ok::<_, std::io::Error>(tenant)
+                })
+                .transpose()?;
+            tenants_and_timestamps.push((tenant, timestamp_column.get_val(doc)));
+        }
+        Ok(tenants_and_timestamps)
+    }
+
+    #[test]
+    fn test_sort_index_by_multiple_fields() -> crate::Result<()> {
+        let mut schema_builder = Schema::builder();
+        let tenant_field = schema_builder.add_text_field("tenant", STRING | FAST);
+        let timestamp_field = schema_builder.add_i64_field("timestamp", FAST);
+        let index = Index::builder()
+            .schema(schema_builder.build())
+            .settings(compound_sort_settings())
+            .create_in_ram()?;
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.set_merge_policy(Box::new(NoMergePolicy));
+        index_writer.add_document(doc!(tenant_field => "b", timestamp_field => 1i64))?;
+        index_writer.add_document(doc!(tenant_field => "a", timestamp_field => 2i64))?;
+        index_writer.add_document(doc!(tenant_field => "b", timestamp_field => 3i64))?;
+        index_writer.add_document(doc!(timestamp_field => 4i64))?;
+        index_writer.add_document(doc!(tenant_field => "a", timestamp_field => 1i64))?;
+        index_writer.commit()?;
+        index_writer.add_document(doc!(tenant_field => "c", timestamp_field => 5i64))?;
+        index_writer.add_document(doc!(tenant_field => "a", timestamp_field => 3i64))?;
+        index_writer.add_document(doc!(tenant_field => "b", timestamp_field => 2i64))?;
+        index_writer.commit()?;
+
+        let tenant = |tenant: &str| Some(tenant.to_string());
+        let searcher = index.reader()?.searcher();
+        let mut segments_docs: Vec<Vec<(Option<String>, i64)>> = searcher
+            .segment_readers()
+            .iter()
+            .map(tenants_and_timestamps)
+            .collect::<crate::Result<_>>()?;
+        segments_docs.sort_by_key(Vec::len);
+        assert_eq!(
+            segments_docs,
+            vec![
+                vec![(tenant("a"), 3), (tenant("b"), 2), (tenant("c"), 5)],
+                vec![
+                    (None, 4),
+                    (tenant("a"), 2),
+                    (tenant("a"), 1),
+                    (tenant("b"), 3),
+                    (tenant("b"), 1),
+                ],
+            ]
+        );
+
+        // The term ordinals of the segments differ, but the merged segment is sorted by term.
+        let segment_ids: Vec<SegmentId> = index.searchable_segment_ids()?;
+        index_writer.merge(&segment_ids).wait()?;
+        let searcher = index.reader()?.searcher();
+        assert_eq!(searcher.segment_readers().len(), 1);
+        assert_eq!(
+            tenants_and_timestamps(searcher.segment_reader(0))?,
+            vec![
+                (None, 4),
+                (tenant("a"), 3),
+                (tenant("a"), 2),
+                (tenant("a"), 1),
+                (tenant("b"), 3),
+                (tenant("b"), 2),
+                (tenant("b"), 1),
+                (tenant("c"), 5),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_index_invalid_settings() {
+        let mut schema_builder = Schema::builder();
+        schema_builder.add_text_field("tenant", STRING | FAST);
+        schema_builder.add_i64_field("timestamp", FAST);
+        schema_builder.add_ip_addr_field("ip", FAST);
+        schema_builder.add_u64_field("count", INDEXED);
+        let schema = schema_builder.build();
+        let sort_by_field = |field: &str| IndexSortByField {
+            field: field.to_string(),
+            order: Order::Asc,
+        };
+        let invalid_settings = [
+            IndexSettings {
+                sort_by_fields: vec![sort_by_field("tenant"), sort_by_field("ip")],
+                ..Default::default()
+            },
+            IndexSettings {
+                sort_by_fields: vec![sort_by_field("count")],
+                ..Default::default()
+            },
+            IndexSettings {
+                sort_by_fields: vec![sort_by_field("missing")],
+                ..Default::default()
+            },
+        ];
+        for settings in invalid_settings {
+            let err = Index::builder()
+                .schema(schema.clone())
+                .settings(settings)
+                .create_in_ram()
+                .unwrap_err();
+            assert!(matches!(err, TantivyError::InvalidArgument(_)));
+        }
+    }
+
     #[test]
     fn test_doc_mapping() {
         let doc_mapping = DocIdMapping::from_new_id_to_old_id(vec![3, 2, 5]);
@@ -1,14 +1,15 @@
 use std::ops::Range;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
 use std::thread;
 use std::thread::JoinHandle;
 
 use common::BitSet;
-use smallvec::smallvec;
 
 use super::operation::{AddOperation, UserOperation};
 use super::segment_updater::SegmentUpdater;
 use super::{AddBatch, AddBatchReceiver, AddBatchSender, PreparedCommit};
+use crate::collector::DocSetCollector;
 use crate::core::{Index, Segment, SegmentComponent, SegmentId, SegmentMeta, SegmentReader};
 use crate::directory::{DirectoryLock, GarbageCollectionResult, TerminatingWrite};
 use crate::error::TantivyError;
@@ -18,10 +19,11 @@ use crate::indexer::doc_opstamp_mapping::DocToOpstampMapping;
 use crate::indexer::index_writer_status::IndexWriterStatus;
 use crate::indexer::operation::DeleteOperation;
 use crate::indexer::stamper::Stamper;
+use crate::indexer::write_ahead_log::{LogRecord, WriteAheadLog};
 use crate::indexer::{MergePolicy, SegmentEntry, SegmentWriter};
 use crate::query::{EnableScoring, Query, TermQuery};
-use crate::schema::{Document, IndexRecordOption, Term};
-use crate::{FutureResult, Opstamp};
+use crate::schema::{Document, FieldType, IndexRecordOption, Schema, Term, Value};
+use crate::{DocAddress, FutureResult, IndexReader, Opstamp, ReloadPolicy, Searcher};
 
 // Size of the margin for the `memory_arena`. A segment is closed when the remaining memory
 // in the `memory_arena` goes below MARGIN_IN_BYTES.
@@ -75,6 +77,18 @@ pub struct IndexWriter {
 
     stamper: Stamper,
     committed_opstamp: Opstamp,
+    // Number of operation groups run so far. The operations are all committed when the last
+    // successful commit covers as many of them.
+    num_operations: AtomicU64,
+    // Held shared while a group of operations is stamped and enqueued, and exclusively by the
+    // groups reading the committed documents, so that no other operation can slip in between
+    // their check of the uncommitted operations and their enqueueing.
+    operations_lock: RwLock<()>,
+    // The reader of the last commit read by the update-by-query operations, along with the
+    // opstamp of the commit it was loaded at.
+    committed_reader: Mutex<Option<(Opstamp, IndexReader)>>,
+
+    write_ahead_log: Option<Arc<Mutex<WriteAheadLog>>>,
 }
 
 fn compute_deleted_bitset(
@@ -196,7 +210,10 @@ fn index_documents(
 
     let doc_opstamps: Vec<Opstamp> = segment_writer.finalize()?;
 
-    let segment_with_max_doc = segment.with_max_doc(max_doc);
+    let segment_with_max_doc = segment
+        .with_max_doc(max_doc)
+        .with_fast_field_stats()?
+        .with_num_bytes()?;
 
     let alive_bitset_opt = apply_deletes(&segment_with_max_doc, &mut delete_cursor, &doc_opstamps)?;
 
@@ -309,13 +326,49 @@ impl IndexWriter {
 
             committed_opstamp: current_opstamp,
             stamper,
+            num_operations: AtomicU64::new(0),
+            operations_lock: RwLock::new(()),
+            committed_reader: Mutex::new(None),
 
             worker_id: 0,
+
+            write_ahead_log: None,
         };
         index_writer.start_workers()?;
+        if let Some(durability) = index.settings().write_ahead_log {
+            let (write_ahead_log, records) =
+                WriteAheadLog::open(index.directory(), current_opstamp, durability)?;
+            index_writer.replay(records)?;
+            index_writer.write_ahead_log = Some(Arc::new(Mutex::new(write_ahead_log)));
+        }
         Ok(index_writer)
     }
 
+    /// Replays the operations of the write-ahead log, which were not committed.
+    fn replay(&self, records: Vec<LogRecord>) -> crate::Result<()> {
+        let mut next_opstamp = self.committed_opstamp;
+        for record in records {
+            match record {
+                LogRecord::Operations(operations) => {
+                    for (opstamp, _operation) in &operations {
+                        next_opstamp = next_opstamp.max(opstamp + 1);
+                    }
+                    self.run_operations(operations, false)?;
+                }
+                LogRecord::DeleteAll(opstamp) => {
+                    next_opstamp = next_opstamp.max(opstamp + 1);
+                    self.segment_updater.remove_all_segments();
+                }
+            }
+        }
+        info!(
+            "Replayed the write-ahead log up to opstamp {}",
+            next_opstamp
+        );
+        self.stamper.revert(next_opstamp);
+        Ok(())
+    }
+
     fn drop_sender(&mut self) {
         let (sender, _receiver) = crossbeam_channel::bounded(1);
         self.operation_sender = sender;
@@ -497,6 +550,20 @@ impl IndexWriter {
     /// }
     /// ```
     pub fn delete_all_documents(&self) -> crate::Result<Opstamp> {
+        let _operations_guard = self
+            .operations_lock
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.num_operations.fetch_add(1, Ordering::SeqCst);
+        if let Some(write_ahead_log) = &self.write_ahead_log {
+            // The stamper is not reverted, so that the operations of the log can be ordered
+            // with the commits.
+            let opstamp = self.stamper.stamp();
+            self.lock_write_ahead_log(write_ahead_log)?
+                .append_delete_all(opstamp)?;
+            self.segment_updater.remove_all_segments();
+            return Ok(self.committed_opstamp);
+        }
         // Delete segments
         self.segment_updater.remove_all_segments();
         // Return new stamp - reverted stamp
@@ -545,6 +612,11 @@ impl IndexWriter {
         self.segment_updater.kill();
         let document_receiver_res = self.operation_receiver();
 
+        // the new index_writer must not replay the operations which are rolled back.
+        if let Some(write_ahead_log) = &self.write_ahead_log {
+            self.lock_write_ahead_log(write_ahead_log)?.discard()?;
+        }
+
         // take the directory lock to create a new index_writer.
         let directory_lock = self
             ._directory_lock
@@ -625,6 +697,10 @@ impl IndexWriter {
         }
 
         let commit_opstamp = self.stamper.stamp();
+        if let Some(write_ahead_log) = &self.write_ahead_log {
+            self.lock_write_ahead_log(write_ahead_log)?
+                .rotate(commit_opstamp)?;
+        }
         let prepared_commit = PreparedCommit::new(self, commit_opstamp);
         info!("Prepared commit {}", commit_opstamp);
         Ok(prepared_commit)
@@ -651,6 +727,46 @@ impl IndexWriter {
         &self.segment_updater
     }
 
+    /// Returns the number of operation groups run so far.
+    pub(crate) fn num_operations(&self) -> u64 {
+        self.num_operations.load(Ordering::SeqCst)
+    }
+
+    /// Returns true if some operations were run since the last successful commit.
+    ///
+    /// The commits which are still in progress are waited for.
+    fn has_uncommitted_operations(&self) -> crate::Result<bool> {
+        let num_operations = self.num_operations();
+        if num_operations > self.segment_updater.num_committed_operations() {
+            self.segment_updater.wait_scheduled_tasks()?;
+        }
+        Ok(num_operations > self.segment_updater.num_committed_operations())
+    }
+
+    /// Returns the write-ahead log, if it is enabled.
+    pub(crate) fn write_ahead_log(&self) -> Option<Arc<Mutex<WriteAheadLog>>> {
+        self.write_ahead_log.clone()
+    }
+
+    /// Returns true if the write-ahead log failed to journal an operation. All of the
+    /// following operations and commits fail until the index writer is rolled back.
+    fn is_write_ahead_log_poisoned(&self) -> bool {
+        match &self.write_ahead_log {
+            Some(write_ahead_log) => self
+                .lock_write_ahead_log(write_ahead_log)
+                .map(|write_ahead_log| write_ahead_log.is_poisoned())
+                .unwrap_or(true),
+            None => false,
+        }
+    }
+
+    fn lock_write_ahead_log<'a>(
+        &self,
+        write_ahead_log: &'a Mutex<WriteAheadLog>,
+    ) -> crate::Result<std::sync::MutexGuard<'a, WriteAheadLog>> {
+        write_ahead_log.lock().map_err(|_| TantivyError::Poisoned)
+    }
+
     /// Delete all documents containing a given term.
     ///
     /// Delete operation only affects documents that
@@ -660,11 +776,22 @@ impl IndexWriter {
     /// Like adds, the deletion itself will be visible
     /// only after calling `commit()`.
     pub fn delete_term(&self, term: Term) -> Opstamp {
-        let query = TermQuery::new(term, IndexRecordOption::Basic);
         // For backward compatibility, if Term is invalid for the index, do nothing but return an
         // Opstamp
-        self.delete_query(Box::new(query))
-            .unwrap_or_else(|_| self.stamper.stamp())
+        let (opstamp, result) =
+            self.stamp_and_run_operations(vec![UserOperation::Delete(term)], false);
+        if let Err(err) = result {
+            if self.is_write_ahead_log_poisoned() {
+                error!(
+                    "Failed to journal the deletion of a term, the index writer needs to be \
+                     rolled back: {}",
+                    err
+                );
+            } else {
+                warn!("Failed to delete term: {}", err);
+            }
+        }
+        opstamp
     }
 
     /// Delete all documents matching a given query.
@@ -676,18 +803,81 @@ impl IndexWriter {
     ///
     /// Like adds, the deletion itself will be visible
     /// only after calling `commit()`.
+    ///
+    /// Queries cannot be journaled, so an `InvalidArgument` error is returned if the
+    /// write-ahead log is enabled.
     #[doc(hidden)]
     pub fn delete_query(&self, query: Box<dyn Query>) -> crate::Result<Opstamp> {
-        let weight = query.weight(EnableScoring::disabled_from_schema(&self.index.schema()))?;
-        let opstamp = self.stamper.stamp();
-        let delete_operation = DeleteOperation {
-            opstamp,
-            target: weight,
+        let (opstamp, result) =
+            self.stamp_and_run_operations(vec![UserOperation::DeleteQuery(query)], false);
+        result?;
+        Ok(opstamp)
+    }
+
+    /// Re-indexes the documents matching a given query after applying `transform` to them.
+    /// Returns an `Err` if the query can't be executed.
+    ///
+    /// The documents are read from the doc store of the last commit and are loaded into memory
+    /// at once. As they are re-indexed from their stored version, all of the fields of the
+    /// schema need to be stored.
+    ///
+    /// The update is only allowed when all of the changes since the last commit were
+    /// committed, as it would otherwise overwrite the documents added since then, and revive
+    /// the deleted ones. An `InvalidArgument` error is returned otherwise. A commit which is
+    /// still in progress is waited for, and the other operations wait for the update to be
+    /// enqueued.
+    ///
+    /// The delete and the adds share the same opstamp, so that the update is atomic: a commit
+    /// either contains all of them or none.
+    ///
+    /// Queries cannot be journaled, so an `InvalidArgument` error is returned if the
+    /// write-ahead log is enabled.
+    ///
+    /// If the indexing pipeline is full, this call may block.
+    pub fn update_query(
+        &self,
+        query: Box<dyn Query>,
+        transform: impl Fn(Document) -> Document + Send + Sync + 'static,
+    ) -> crate::Result<Opstamp> {
+        let operation = UserOperation::UpdateQuery {
+            query,
+            transform: Box::new(transform),
         };
-        self.delete_queue.push(delete_operation);
+        let (opstamp, result) = self.stamp_and_run_operations(vec![operation], false);
+        result?;
         Ok(opstamp)
     }
 
+    /// Returns a searcher on the last commit.
+    ///
+    /// The reader is created on the first call, and reloaded when a commit happened since.
+    fn committed_searcher(&self) -> crate::Result<Searcher> {
+        let commit_opstamp = self.segment_updater.load_meta().opstamp;
+        let mut committed_reader = self
+            .committed_reader
+            .lock()
+            .map_err(|_| TantivyError::Poisoned)?;
+        match committed_reader.as_mut() {
+            Some((reader_opstamp, reader)) => {
+                if *reader_opstamp != commit_opstamp {
+                    reader.reload()?;
+                    *reader_opstamp = commit_opstamp;
+                }
+                Ok(reader.searcher())
+            }
+            None => {
+                let reader: IndexReader = self
+                    .index
+                    .reader_builder()
+                    .reload_policy(ReloadPolicy::Manual)
+                    .try_into()?;
+                let searcher = reader.searcher();
+                *committed_reader = Some((commit_opstamp, reader));
+                Ok(searcher)
+            }
+        }
+    }
+
     /// Returns the opstamp of the last successful commit.
     ///
     /// This is, for instance, the opstamp the index will
@@ -707,11 +897,57 @@ impl IndexWriter {
     /// be used by the client to align commits with its own
     /// document queue.
     pub fn add_document(&self, document: Document) -> crate::Result<Opstamp> {
-        let opstamp = self.stamper.stamp();
-        self.send_add_documents_batch(smallvec![AddOperation { opstamp, document }])?;
+        let (opstamp, result) =
+            self.stamp_and_run_operations(vec![UserOperation::Add(document)], false);
+        result?;
+        Ok(opstamp)
+    }
+
+    /// Adds a document, replacing the documents with the same unique key.
+    ///
+    /// The schema needs a unique key field (see
+    /// [`SchemaBuilder::set_unique_key`](crate::schema::SchemaBuilder::set_unique_key)) and the
+    /// document needs exactly one value for it. Otherwise an `InvalidArgument` error is returned.
+    ///
+    /// The delete of the previous documents and the add share the same opstamp, so that the
+    /// replacement is atomic: a commit either contains both of them or neither.
+    ///
+    /// If the indexing pipeline is full, this call may block.
+    pub fn upsert_document(&self, document: Document) -> crate::Result<Opstamp> {
+        unique_key_term(&self.index.schema(), &document)?;
+        let (opstamp, result) =
+            self.stamp_and_run_operations(vec![UserOperation::Upsert(document)], false);
+        result?;
         Ok(opstamp)
     }
 
+    /// Returns the deletion of all documents containing `term`.
+    ///
+    /// The deletion only affects documents with an opstamp lower than `opstamp`.
+    fn delete_term_operation(
+        &self,
+        term: Term,
+        opstamp: Opstamp,
+    ) -> crate::Result<DeleteOperation> {
+        let query = TermQuery::new(term, IndexRecordOption::Basic);
+        self.delete_query_operation(&query, opstamp)
+    }
+
+    /// Returns the deletion of all documents matching `query`.
+    ///
+    /// The deletion only affects documents with an opstamp lower than `opstamp`.
+    fn delete_query_operation(
+        &self,
+        query: &dyn Query,
+        opstamp: Opstamp,
+    ) -> crate::Result<DeleteOperation> {
+        let weight = query.weight(EnableScoring::disabled_from_schema(&self.index.schema()))?;
+        Ok(DeleteOperation {
+            opstamp,
+            target: weight,
+        })
+    }
+
     /// Gets a range of stamps from the stamper and "pops" the last stamp
     /// from the range returning a tuple of the last optstamp and the popped
     /// range.
@@ -741,40 +977,147 @@ impl IndexWriter {
     /// Like adds and deletes (see `IndexWriter.add_document` and
     /// `IndexWriter.delete_term`), the changes made by calling `run` will be
     /// visible to readers only after calling `commit()`.
+    ///
+    /// An [`UserOperation::UpdateQuery`] loads all of its matching stored documents into memory,
+    /// and is rejected with an `InvalidArgument` error unless it is the first operation of the
+    /// group and all of the previous operations were committed. See
+    /// [`IndexWriter::update_query`].
     pub fn run<I>(&self, user_operations: I) -> crate::Result<Opstamp>
     where
         I: IntoIterator<Item = UserOperation>,
         I::IntoIter: ExactSizeIterator,
     {
-        let user_operations_it = user_operations.into_iter();
-        let count = user_operations_it.len() as u64;
-        if count == 0 {
+        let user_operations: Vec<UserOperation> = user_operations.into_iter().collect();
+        if user_operations.is_empty() {
             return Ok(self.stamper.stamp());
         }
-        let (batch_opstamp, stamps) = self.get_batch_opstamps(count);
+        let (batch_opstamp, result) = self.stamp_and_run_operations(user_operations, true);
+        result?;
+        Ok(batch_opstamp)
+    }
 
-        let mut adds = AddBatch::default();
+    /// Stamps a group of operations and runs it. Returns the opstamp of the group, which is
+    /// also returned when running it fails.
+    ///
+    /// `is_batch` tells whether the operations were sent with `run`, in which case the group
+    /// gets an opstamp of its own, following the ones of its operations.
+    fn stamp_and_run_operations(
+        &self,
+        user_operations: Vec<UserOperation>,
+        is_batch: bool,
+    ) -> (Opstamp, crate::Result<()>) {
+        let reads_committed_docs = user_operations
+            .iter()
+            .any(|operation| matches!(operation, UserOperation::UpdateQuery { .. }));
+        // The guards only protect the ordering of the operations, so a poisoned lock is fine.
+        let _shared_guard;
+        let _exclusive_guard;
+        if reads_committed_docs {
+            _exclusive_guard = self
+                .operations_lock
+                .write()
+                .unwrap_or_else(PoisonError::into_inner);
+        } else {
+            _shared_guard = self
+                .operations_lock
+                .read()
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        let (opstamp, stamps) = if is_batch {
+            self.get_batch_opstamps(user_operations.len() as u64)
+        } else {
+            let opstamp = self.stamper.stamp();
+            (opstamp, opstamp..opstamp + 1)
+        };
+        let result = self.run_operations(stamps.zip(user_operations).collect(), is_batch);
+        (opstamp, result)
+    }
 
-        for (user_op, opstamp) in user_operations_it.zip(stamps) {
-            match user_op {
+    /// Journals a group of stamped operations in the write-ahead log, and sends them to the
+    /// indexing workers.
+    ///
+    /// `is_batch` tells whether the operations were sent with `run`.
+    fn run_operations(
+        &self,
+        operations: Vec<(Opstamp, UserOperation)>,
+        is_batch: bool,
+    ) -> crate::Result<()> {
+        // The deletes of all the operations are prepared before any of them is journaled or
+        // applied, so that an invalid operation leaves the index untouched.
+        let mut deletes = Vec::new();
+        let mut updated_docs = Vec::new();
+        let mut committed_searcher = None;
+        let mut has_previous_operations = false;
+        for (opstamp, operation) in &operations {
+            let is_query_operation = matches!(
+                operation,
+                UserOperation::DeleteQuery(_) | UserOperation::UpdateQuery { .. }
+            );
+            if self.write_ahead_log.is_some() && is_query_operation {
+                return Err(TantivyError::InvalidArgument(
+                    "Operations based on a query cannot be run while the write-ahead log is \
+                     enabled, see `IndexSettings::write_ahead_log`"
+                        .to_string(),
+                ));
+            }
+            match operation {
                 UserOperation::Delete(term) => {
-                    let query = TermQuery::new(term, IndexRecordOption::Basic);
-                    let weight =
-                        query.weight(EnableScoring::disabled_from_schema(&self.index.schema()))?;
-                    let delete_operation = DeleteOperation {
-                        opstamp,
-                        target: weight,
-                    };
-                    self.delete_queue.push(delete_operation);
+                    deletes.push(self.delete_term_operation(term.clone(), *opstamp)?);
+                }
+                UserOperation::Add(_) => {}
+                UserOperation::Upsert(document) => {
+                    let key_term = unique_key_term(&self.index.schema(), document)?;
+                    deletes.push(self.delete_term_operation(key_term, *opstamp)?);
+                }
+                UserOperation::DeleteQuery(query) => {
+                    deletes.push(self.delete_query_operation(query.as_ref(), *opstamp)?);
                 }
-                UserOperation::Add(document) => {
-                    let add_operation = AddOperation { opstamp, document };
-                    adds.push(add_operation);
+                UserOperation::UpdateQuery { query, .. } => {
+                    if has_previous_operations || self.has_uncommitted_operations()? {
+                        return Err(TantivyError::InvalidArgument(
+                            "Update-by-query requires all of the previous operations to be \
+                             committed"
+                                .to_string(),
+                        ));
+                    }
+                    check_all_fields_stored(&self.index.schema())?;
+                    if committed_searcher.is_none() {
+                        committed_searcher = Some(self.committed_searcher()?);
+                    }
+                    let searcher = committed_searcher.as_ref().unwrap();
+                    updated_docs.push(committed_docs_matching(searcher, query.as_ref())?);
+                    deletes.push(self.delete_query_operation(query.as_ref(), *opstamp)?);
                 }
             }
+            has_previous_operations = true;
         }
-        self.send_add_documents_batch(adds)?;
-        Ok(batch_opstamp)
+        if let Some(write_ahead_log) = &self.write_ahead_log {
+            self.lock_write_ahead_log(write_ahead_log)?
+                .append_operations(&operations, is_batch)?;
+        }
+        self.num_operations.fetch_add(1, Ordering::SeqCst);
+        for delete_operation in deletes {
+            self.delete_queue.push(delete_operation);
+        }
+        let mut adds = AddBatch::default();
+        let mut updated_docs_it = updated_docs.into_iter();
+        for (opstamp, operation) in operations {
+            match operation {
+                UserOperation::Delete(_) | UserOperation::DeleteQuery(_) => {}
+                UserOperation::Add(document) | UserOperation::Upsert(document) => {
+                    adds.push(AddOperation { opstamp, document });
+                }
+                UserOperation::UpdateQuery { transform, .. } => {
+                    for document in updated_docs_it.next().unwrap_or_default() {
+                        adds.push(AddOperation {
+                            opstamp,
+                            document: transform(document),
+                        });
+                    }
+                }
+            }
+        }
+        self.send_add_documents_batch(adds)
     }
 
     fn send_add_documents_batch(&self, add_ops: AddBatch) -> crate::Result<()> {
@@ -786,6 +1129,67 @@ impl IndexWriter {
     }
 }
 
+/// Returns the stored documents of `searcher` matching `query`, in doc address order.
+fn committed_docs_matching(searcher: &Searcher, query: &dyn Query) -> crate::Result<Vec<Document>> {
+    let mut doc_addresses: Vec<DocAddress> = searcher
+        .search(query, &DocSetCollector)?
+        .into_iter()
+        .collect();
+    doc_addresses.sort();
+    doc_addresses
+        .into_iter()
+        .map(|doc_address| searcher.doc(doc_address))
+        .collect()
+}
+
+/// Returns an error if a field of `schema` is not stored, and would be lost when re-indexing
+/// the stored documents.
+fn check_all_fields_stored(schema: &Schema) -> crate::Result<()> {
+    if let Some((_, field_entry)) = schema
+        .fields()
+        .find(|(_, field_entry)| !field_entry.is_stored())
+    {
+        return Err(TantivyError::InvalidArgument(format!(
+            "Update-by-query requires all of the fields to be stored, but {} is not stored",
+            field_entry.name()
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the term of the unique key of `document`.
+fn unique_key_term(schema: &Schema, document: &Document) -> crate::Result<Term> {
+    let key_field = schema.unique_key_field().ok_or_else(|| {
+        TantivyError::InvalidArgument("Upserts require a schema with a unique key".to_string())
+    })?;
+    let field_entry = schema.get_field_entry(key_field);
+    let mut key_values = document.get_all(key_field);
+    let (Some(key_value), None) = (key_values.next(), key_values.next()) else {
+        return Err(TantivyError::InvalidArgument(format!(
+            "Upserted document must have exactly one value for the unique key {}",
+            field_entry.name()
+        )));
+    };
+    let key_term = match (field_entry.field_type(), key_value) {
+        (FieldType::Str(_), Value::Str(text)) => Term::from_field_text(key_field, text),
+        (FieldType::U64(_), Value::U64(val)) => Term::from_field_u64(key_field, *val),
+        (FieldType::I64(_), Value::I64(val)) => Term::from_field_i64(key_field, *val),
+        (FieldType::F64(_), Value::F64(val)) => Term::from_field_f64(key_field, *val),
+        (FieldType::Bool(_), Value::Bool(val)) => Term::from_field_bool(key_field, *val),
+        (FieldType::Date(_), Value::Date(val)) => Term::from_field_date(key_field, *val),
+        (FieldType::IpAddr(_), Value::IpAddr(val)) => Term::from_field_ip_addr(key_field, *val),
+        (FieldType::Bytes(_), Value::Bytes(bytes)) => Term::from_field_bytes(key_field, bytes),
+        _ => {
+            return Err(TantivyError::InvalidArgument(format!(
+                "Invalid value {:?} for the unique key {}",
+                key_value,
+                field_entry.name()
+            )));
+        }
+    };
+    Ok(key_term)
+}
+
 impl Drop for IndexWriter {
     fn drop(&mut self) {
         self.segment_updater.kill();
@@ -800,20 +1204,23 @@ impl Drop for IndexWriter {
 mod tests {
     use std::collections::{HashMap, HashSet};
     use std::net::Ipv6Addr;
+    use std::sync::Arc;
+    use std::thread;
 
     use columnar::{Cardinality, Column, MonotonicallyMappableToU128};
     use proptest::prop_oneof;
     use proptest::strategy::Strategy;
 
     use super::super::operation::UserOperation;
-    use crate::collector::TopDocs;
+    use crate::collector::{Count, TopDocs};
     use crate::directory::error::LockError;
     use crate::error::*;
     use crate::indexer::NoMergePolicy;
-    use crate::query::{BooleanQuery, Occur, Query, QueryParser, TermQuery};
+    use crate::query::{AllQuery, BooleanQuery, Occur, Query, QueryParser, RangeQuery, TermQuery};
     use crate::schema::{
-        self, Facet, FacetOptions, IndexRecordOption, IpAddrOptions, NumericOptions, Schema,
-        TextFieldIndexing, TextOptions, FAST, INDEXED, STORED, STRING, TEXT,
+        self, Document, Facet, FacetOptions, Field, IndexRecordOption, IpAddrOptions,
+        NumericOptions, Schema, TextFieldIndexing, TextOptions, FAST, INDEXED, STORED, STRING,
+        TEXT,
     };
     use crate::store::DOCSTORE_CACHE_CAPACITY;
     use crate::{
@@ -949,6 +1356,418 @@ mod tests {
         assert_eq!(batch_opstamp2, 1u64);
     }
 
+    fn docs_with_key(index: &Index, id_field: Field, id: &str) -> crate::Result<Vec<String>> {
+        let searcher = index.reader()?.searcher();
+        let title_field = index.schema().get_field("title")?;
+        let query = TermQuery::new(
+            Term::from_field_text(id_field, id),
+            IndexRecordOption::Basic,
+        );
+        let mut titles = Vec::new();
+        for (_score, doc_address) in searcher.search(&query, &TopDocs::with_limit(10))? {
+            let doc = searcher.doc(doc_address)?;
+            let title = doc.get_first(title_field).unwrap().as_text().unwrap();
+            titles.push(title.to_string());
+        }
+        titles.sort();
+        Ok(titles)
+    }
+
+    fn unique_key_index() -> (Index, Field, Field) {
+        let mut schema_builder = schema::Schema::builder();
+        let id_field = schema_builder.add_text_field("id", STRING);
+        let title_field = schema_builder.add_text_field("title", TEXT | STORED);
+        schema_builder.set_unique_key(id_field).unwrap();
+        let index = Index::create_in_ram(schema_builder.build());
+        (index, id_field, title_field)
+    }
+
+    #[test]
+    fn test_upsert_document() -> crate::Result<()> {
+        let (index, id_field, title_field) = unique_key_index();
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.add_document(doc!(id_field => "1", title_field => "first"))?;
+        index_writer.add_document(doc!(id_field => "2", title_field => "other"))?;
+        let opstamp =
+            index_writer.upsert_document(doc!(id_field => "1", title_field => "second"))?;
+        assert_eq!(opstamp, 2);
+        index_writer.commit()?;
+        assert_eq!(docs_with_key(&index, id_field, "1")?, vec!["second"]);
+        assert_eq!(docs_with_key(&index, id_field, "2")?, vec!["other"]);
+
+        // The previous version is also replaced if it was committed before.
+        index_writer.upsert_document(doc!(id_field => "1", title_field => "third"))?;
+        index_writer.upsert_document(doc!(id_field => "3", title_field => "new"))?;
+        index_writer.commit()?;
+        assert_eq!(docs_with_key(&index, id_field, "1")?, vec!["third"]);
+        assert_eq!(docs_with_key(&index, id_field, "2")?, vec!["other"]);
+        assert_eq!(docs_with_key(&index, id_field, "3")?, vec!["new"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_upsert_in_operations_group() -> crate::Result<()> {
+        let (index, id_field, title_field) = unique_key_index();
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.add_document(doc!(id_field => "1", title_field => "first"))?;
+        index_writer.commit()?;
+        let operations = vec![
+            UserOperation::Upsert(doc!(id_field => "1", title_field => "second")),
+            UserOperation::Upsert(doc!(id_field => "1", title_field => "third")),
+            UserOperation::Add(doc!(id_field => "2", title_field => "added")),
+            UserOperation::Upsert(doc!(id_field => "2", title_field => "upserted")),
+            UserOperation::Upsert(doc!(id_field => "3", title_field => "new")),
+            UserOperation::Delete(Term::from_field_text(id_field, "3")),
+        ];
+        index_writer.run(operations)?;
+        index_writer.commit()?;
+        assert_eq!(docs_with_key(&index, id_field, "1")?, vec!["third"]);
+        assert_eq!(docs_with_key(&index, id_field, "2")?, vec!["upserted"]);
+        assert!(docs_with_key(&index, id_field, "3")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_upsert_invalid_document() -> crate::Result<()> {
+        let (index, id_field, title_field) = unique_key_index();
+        let index_writer = index.writer_for_tests()?;
+        let err = index_writer
+            .upsert_document(doc!(title_field => "no key"))
+            .unwrap_err();
+        assert!(matches!(err, TantivyError::InvalidArgument(_)));
+        let err = index_writer
+            .upsert_document(doc!(id_field => "1", id_field => "2"))
+            .unwrap_err();
+        assert!(matches!(err, TantivyError::InvalidArgument(_)));
+        let err = index_writer
+            .run(vec![UserOperation::Upsert(doc!(title_field => "no key"))])
+            .unwrap_err();
+        assert!(matches!(err, TantivyError::InvalidArgument(_)));
+
+        let mut schema_builder = schema::Schema::builder();
+        let text_field = schema_builder.add_text_field("text", STRING);
+        let index = Index::create_in_ram(schema_builder.build());
+        let index_writer = index.writer_for_tests()?;
+        let err = index_writer
+            .upsert_document(doc!(text_field => "a"))
+            .unwrap_err();
+        assert!(matches!(err, TantivyError::InvalidArgument(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_upsert_leaves_operations_group_unapplied() -> crate::Result<()> {
+        let (index, id_field, title_field) = unique_key_index();
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.add_document(doc!(id_field => "1", title_field => "first"))?;
+        index_writer.commit()?;
+        let operations = vec![
+            UserOperation::Delete(Term::from_field_text(id_field, "1")),
+            UserOperation::Upsert(doc!(id_field => "1", title_field => "second")),
+            UserOperation::Upsert(doc!(title_field => "no key")),
+        ];
+        let err = index_writer.run(operations).unwrap_err();
+        assert!(matches!(err, TantivyError::InvalidArgument(_)));
+        index_writer.commit()?;
+        assert_eq!(docs_with_key(&index, id_field, "1")?, vec!["first"]);
+        Ok(())
+    }
+
+    fn tenant_index() -> crate::Result<(Index, Field, Field, Field)> {
+        let mut schema_builder = schema::Schema::builder();
+        let tenant_field = schema_builder.add_text_field("tenant", STRING | STORED);
+        let date_field = schema_builder.add_date_field("date", INDEXED | FAST | STORED);
+        let body_field = schema_builder.add_text_field("body", TEXT | STORED);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer = index.writer_for_tests()?;
+        for (tenant, day) in [("a", 1), ("a", 5), ("b", 1)] {
+            index_writer.add_document(doc!(
+                tenant_field => tenant,
+                date_field => DateTime::from_timestamp_secs(day * 86_400),
+                body_field => format!("{} secret {}", tenant, day),
+            ))?;
+        }
+        index_writer.commit()?;
+        Ok((index, tenant_field, date_field, body_field))
+    }
+
+    fn tenant_before_day_query(tenant_field: Field, tenant: &str, day: i64) -> Box<dyn Query> {
+        Box::new(BooleanQuery::intersection(vec![
+            Box::new(TermQuery::new(
+                Term::from_field_text(tenant_field, tenant),
+                IndexRecordOption::Basic,
+            )),
+            Box::new(RangeQuery::new_date(
+                "date".to_string(),
+                DateTime::from_timestamp_secs(0)..DateTime::from_timestamp_secs(day * 86_400),
+            )),
+        ]))
+    }
+
+    #[test]
+    fn test_delete_query_in_operations_group() -> crate::Result<()> {
+        let (index, tenant_field, date_field, body_field) = tenant_index()?;
+        let mut index_writer = index.writer_for_tests()?;
+        let operations = vec![
+            UserOperation::DeleteQuery(tenant_before_day_query(tenant_field, "a", 3)),
+            UserOperation::Add(doc!(
+                tenant_field => "a",
+                date_field => DateTime::from_timestamp_secs(2 * 86_400),
+                body_field => "a secret 2",
+            )),
+        ];
+        index_writer.run(operations)?;
+        index_writer.commit()?;
+        let searcher = index.reader()?.searcher();
+        let query = QueryParser::for_index(&index, vec![body_field]).parse_query("secret")?;
+        let mut bodies: Vec<String> = searcher
+            .search(&query, &TopDocs::with_limit(10))?
+            .into_iter()
+            .map(|(_score, doc_address)| {
+                let doc = searcher.doc(doc_address).unwrap();
+                let body = doc.get_first(body_field).unwrap().as_text().unwrap();
+                body.to_string()
+            })
+            .collect();
+        bodies.sort();
+        assert_eq!(bodies, vec!["a secret 2", "a secret 5", "b secret 1"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_query() -> crate::Result<()> {
+        let (index, tenant_field, date_field, body_field) = tenant_index()?;
+        let mut index_writer = index.writer_for_tests()?;
+        let redact = move |doc: Document| {
+            let mut redacted_doc = Document::default();
+            for field_value in doc.field_values() {
+                if field_value.field() != body_field {
+                    redacted_doc.add_field_value(field_value.field(), field_value.value().clone());
+                }
+            }
+            redacted_doc.add_text(body_field, "redacted");
+            redacted_doc
+        };
+        let opstamp =
+            index_writer.update_query(tenant_before_day_query(tenant_field, "a", 3), redact)?;
+        // The three added documents and the commit used the opstamps 0 to 3.
+        assert_eq!(opstamp, 4);
+        index_writer.commit()?;
+        index_writer.run(vec![UserOperation::UpdateQuery {
+            query: Box::new(TermQuery::new(
+                Term::from_field_text(tenant_field, "b"),
+                IndexRecordOption::Basic,
+            )),
+            transform: Box::new(redact),
+        }])?;
+        index_writer.commit()?;
+
+        let searcher = index.reader()?.searcher();
+        assert_eq!(searcher.num_docs(), 3);
+        let query_parser = QueryParser::for_index(&index, vec![body_field]);
+        let redacted_docs = searcher.search(
+            &query_parser.parse_query("redacted")?,
+            &TopDocs::with_limit(10),
+        )?;
+        assert_eq!(redacted_docs.len(), 2);
+        for (_score, doc_address) in redacted_docs {
+            let doc = searcher.doc(doc_address)?;
+            assert_eq!(
+                doc.get_first(date_field).unwrap().as_date(),
+                Some(DateTime::from_timestamp_secs(86_400))
+            );
+        }
+        let secret_docs = searcher.search(
+            &query_parser.parse_query("secret")?,
+            &TopDocs::with_limit(10),
+        )?;
+        assert_eq!(secret_docs.len(), 1);
+        let doc = searcher.doc(secret_docs[0].1)?;
+        let body = doc.get_first(body_field).unwrap().as_text();
+        assert_eq!(body, Some("a secret 5"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_query_rejects_uncommitted_operations() -> crate::Result<()> {
+        let (index, tenant_field, date_field, body_field) = tenant_index()?;
+        let mut index_writer = index.writer_for_tests()?;
+        let new_doc = || {
+            doc!(
+                tenant_field => "a",
+                date_field => DateTime::from_timestamp_secs(2 * 86_400),
+                body_field => "a secret 2",
+            )
+        };
+        let redact = move |mut doc: Document| {
+            doc.add_text(body_field, "redacted");
+            doc
+        };
+        index_writer.add_document(new_doc())?;
+        let err = index_writer
+            .update_query(tenant_before_day_query(tenant_field, "a", 3), redact)
+            .unwrap_err();
+        assert!(matches!(err, TantivyError::InvalidArgument(_)));
+        index_writer.commit()?;
+        let err = index_writer
+            .run(vec![
+                UserOperation::Delete(Term::from_field_text(tenant_field, "b")),
+                UserOperation::UpdateQuery {
+                    query: tenant_before_day_query(tenant_field, "a", 3),
+                    transform: Box::new(redact),
+                },
+            ])
+            .unwrap_err();
+        assert!(matches!(err, TantivyError::InvalidArgument(_)));
+        index_writer.commit()?;
+
+        let searcher = index.reader()?.searcher();
+        assert_eq!(searcher.num_docs(), 4);
+        let query_parser = QueryParser::for_index(&index, vec![body_field]);
+        let count = searcher.search(&query_parser.parse_query("redacted")?, &Count)?;
+        assert_eq!(count, 0);
+
+        index_writer.update_query(tenant_before_day_query(tenant_field, "a", 3), redact)?;
+        index_writer.commit()?;
+        let searcher = index.reader()?.searcher();
+        assert_eq!(searcher.num_docs(), 4);
+        let count = searcher.search(&query_parser.parse_query("redacted")?, &Count)?;
+        assert_eq!(count, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_query_waits_for_commit_in_progress() -> crate::Result<()> {
+        let (index, tenant_field, date_field, body_field) = tenant_index()?;
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.add_document(doc!(
+            tenant_field => "a",
+            date_field => DateTime::from_timestamp_secs(2 * 86_400),
+            body_field => "a secret 2",
+        ))?;
+        let commit_future = index_writer.prepare_commit()?.commit_future();
+        index_writer.update_query(
+            tenant_before_day_query(tenant_field, "a", 3),
+            move |mut doc: Document| {
+                doc.add_text(body_field, "redacted");
+                doc
+            },
+        )?;
+        commit_future.wait()?;
+        index_writer.commit()?;
+
+        let searcher = index.reader()?.searcher();
+        assert_eq!(searcher.num_docs(), 4);
+        let query_parser = QueryParser::for_index(&index, vec![body_field]);
+        let count = searcher.search(&query_parser.parse_query("redacted")?, &Count)?;
+        assert_eq!(count, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_query_concurrent_with_adds() -> crate::Result<()> {
+        let (index, tenant_field, date_field, body_field) = tenant_index()?;
+        let mut index_writer = index.writer_for_tests()?;
+        let redact = move |mut doc: Document| {
+            doc.add_text(body_field, "redacted");
+            doc
+        };
+        index_writer.update_query(tenant_before_day_query(tenant_field, "a", 3), redact)?;
+        index_writer.commit()?;
+
+        let index_writer = Arc::new(index_writer);
+        let adder = {
+            let index_writer = index_writer.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    index_writer
+                        .add_document(doc!(
+                            tenant_field => "a",
+                            date_field => DateTime::from_timestamp_secs(2 * 86_400),
+                            body_field => "a secret 2",
+                        ))
+                        .unwrap();
+                }
+            })
+        };
+        for _ in 0..100 {
+            // The update is rejected as soon as one of the adds is enqueued. The adds enqueued
+            // before it must not be deleted by it.
+            let _ =
+                index_writer.update_query(tenant_before_day_query(tenant_field, "a", 3), redact);
+        }
+        adder.join().unwrap();
+        let mut index_writer = Arc::try_unwrap(index_writer).ok().unwrap();
+        index_writer.commit()?;
+        let searcher = index.reader()?.searcher();
+        assert_eq!(searcher.num_docs(), 103);
+
+        // The reader of the committed documents is reloaded after the commit.
+        index_writer.update_query(tenant_before_day_query(tenant_field, "a", 3), redact)?;
+        index_writer.commit()?;
+        let searcher = index.reader()?.searcher();
+        assert_eq!(searcher.num_docs(), 103);
+        let query_parser = QueryParser::for_index(&index, vec![body_field]);
+        let count = searcher.search(&query_parser.parse_query("redacted")?, &Count)?;
+        assert_eq!(count, 101);
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_query_rejects_non_stored_fields() -> crate::Result<()> {
+        let mut schema_builder = schema::Schema::builder();
+        let tenant_field = schema_builder.add_text_field("tenant", STRING | STORED);
+        let body_field = schema_builder.add_text_field("body", TEXT);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.add_document(doc!(tenant_field => "a", body_field => "secret"))?;
+        index_writer.commit()?;
+        let query = TermQuery::new(
+            Term::from_field_text(tenant_field, "a"),
+            IndexRecordOption::Basic,
+        );
+        let err = index_writer
+            .update_query(Box::new(query), |doc| doc)
+            .unwrap_err();
+        assert!(matches!(err, TantivyError::InvalidArgument(_)));
+        index_writer.commit()?;
+        let searcher = index.reader()?.searcher();
+        let body_query = TermQuery::new(
+            Term::from_field_text(body_field, "secret"),
+            IndexRecordOption::Basic,
+        );
+        assert_eq!(searcher.search(&body_query, &Count)?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_operation_send_and_eq() {
+        fn assert_send<T: Send>(_: &T) {}
+        let mut schema_builder = schema::Schema::builder();
+        let text_field = schema_builder.add_text_field("text", STRING);
+        let update_operation = UserOperation::UpdateQuery {
+            query: Box::new(AllQuery),
+            transform: Box::new(|doc| doc),
+        };
+        assert_send(&update_operation);
+        assert_ne!(
+            update_operation,
+            UserOperation::DeleteQuery(Box::new(AllQuery))
+        );
+        assert_eq!(
+            UserOperation::Add(doc!(text_field => "a")),
+            UserOperation::Add(doc!(text_field => "a"))
+        );
+        assert_ne!(
+            UserOperation::Add(doc!(text_field => "a")),
+            UserOperation::Upsert(doc!(text_field => "a"))
+        );
+        assert_eq!(
+            UserOperation::Delete(Term::from_field_text(text_field, "a")),
+            UserOperation::Delete(Term::from_field_text(text_field, "a"))
+        );
+    }
+
     #[test]
     fn test_lockfile_stops_duplicates() {
         let schema_builder = schema::Schema::builder();
@@ -1452,10 +2271,10 @@ mod tests {
         let schema = schema_builder.build();
 
         let settings = IndexSettings {
-            sort_by_field: Some(IndexSortByField {
+            sort_by_fields: vec![IndexSortByField {
                 field: "id".to_string(),
                 order: Order::Desc,
-            }),
+            }],
             ..Default::default()
         };
 
@@ -1501,10 +2320,10 @@ mod tests {
         let schema = schema_builder.build();
 
         let settings = IndexSettings {
-            sort_by_field: Some(IndexSortByField {
+            sort_by_fields: vec![IndexSortByField {
                 field: "id".to_string(),
                 order: Order::Desc,
-            }),
+            }],
             ..Default::default()
         };
 
@@ -1665,10 +2484,10 @@ mod tests {
         let schema = schema_builder.build();
         let settings = if sort_index {
             IndexSettings {
-                sort_by_field: Some(IndexSortByField {
+                sort_by_fields: vec![IndexSortByField {
                     field: "id".to_string(),
                     order: Order::Asc,
-                }),
+                }],
                 ..Default::default()
             }
         } else {
@@ -2190,10 +3009,10 @@ mod tests {
         let id = schema_builder.add_u64_field("id", FAST);
         let schema = schema_builder.build();
         let settings = IndexSettings {
-            sort_by_field: Some(IndexSortByField {
+            sort_by_fields: vec![IndexSortByField {
                 field: "id".to_string(),
                 order: Order::Asc,
-            }),
+            }],
             ..Default::default()
         };
         let index = Index::builder()
@@ -2321,10 +3140,10 @@ mod tests {
         let schema = schema_builder.build();
 
         let settings = IndexSettings {
-            sort_by_field: Some(IndexSortByField {
+            sort_by_fields: vec![IndexSortByField {
                 field: "sort_by".to_string(),
                 order: Order::Asc,
-            }),
+            }],
             ..Default::default()
         };
 
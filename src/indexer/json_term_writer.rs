@@ -184,12 +184,12 @@ fn index_json_value(
     }
 }
 
-enum TextOrDateTime<'a> {
+pub(crate) enum TextOrDateTime<'a> {
     Text(&'a str),
     DateTime(OffsetDateTime),
 }
 
-fn infer_type_from_str(text: &str) -> TextOrDateTime {
+pub(crate) fn infer_type_from_str(text: &str) -> TextOrDateTime {
     match OffsetDateTime::parse(text, &Rfc3339) {
         Ok(dt) => {
             let dt_utc = dt.to_offset(UtcOffset::UTC);
@@ -268,7 +268,7 @@ pub struct JsonTermWriter<'a> {
 /// In other words,
 /// - `k8s.node` ends up as `["k8s", "node"]`.
 /// - `k8s\.node` ends up as `["k8s.node"]`.
-fn split_json_path(json_path: &str) -> Vec<String> {
+pub(crate) fn split_json_path(json_path: &str) -> Vec<String> {
     let mut escaped_state: bool = false;
     let mut json_path_segments = Vec::new();
     let mut buffer = String::new();
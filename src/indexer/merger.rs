@@ -1,27 +1,26 @@
+use std::cmp::Ordering;
 use std::sync::Arc;
 
-use columnar::{
-    ColumnValues, ColumnarReader, MergeRowOrder, RowAddr, ShuffleMergeOrder, StackMergeOrder,
-};
+use columnar::{ColumnarReader, MergeRowOrder, RowAddr, ShuffleMergeOrder, StackMergeOrder};
 use common::ReadOnlyBitSet;
 use itertools::Itertools;
 use measure_time::debug_time;
 
-use crate::core::{Segment, SegmentReader};
+use crate::core::{apply_retention, current_cutoff, Segment, SegmentReader};
 use crate::directory::WritePtr;
 use crate::docset::{DocSet, TERMINATED};
 use crate::error::DataCorruption;
-use crate::fastfield::{AliveBitSet, FastFieldNotAvailableError};
+use crate::fastfield::AliveBitSet;
 use crate::fieldnorm::{FieldNormReader, FieldNormReaders, FieldNormsSerializer, FieldNormsWriter};
-use crate::indexer::doc_id_mapping::{MappingType, SegmentDocIdMapping};
+use crate::indexer::doc_id_mapping::{MappingType, SegmentDocIdMapping, SegmentSortKeys};
 use crate::indexer::SegmentSerializer;
 use crate::postings::{InvertedIndexSerializer, Postings, SegmentPostings};
 use crate::schema::{Field, FieldType, Schema};
 use crate::store::StoreWriter;
 use crate::termdict::{TermMerger, TermOrdinal};
 use crate::{
-    DocAddress, DocId, IndexSettings, IndexSortByField, InvertedIndexReader, Order,
-    SegmentComponent, SegmentOrdinal,
+    DocAddress, DocId, IndexSettings, IndexSortByField, InvertedIndexReader, SegmentComponent,
+    SegmentOrdinal,
 };
 
 /// Segment's max doc must be `< MAX_DOC_LIMIT`.
@@ -83,6 +82,8 @@ pub struct IndexMerger {
     index_settings: IndexSettings,
     schema: Schema,
     pub(crate) readers: Vec<SegmentReader>,
+    // The sort keys of the readers, if the index is sorted.
+    sort_keys: Vec<SegmentSortKeys>,
     max_doc: u32,
 }
 
@@ -164,19 +165,32 @@ impl IndexMerger {
         alive_bitset_opt: Vec<Option<AliveBitSet>>,
     ) -> crate::Result<IndexMerger> {
         let mut readers = vec![];
+        // Expired documents are dropped from the merged segment.
+        let retention_cutoff_opt = index_settings
+            .retention
+            .as_ref()
+            .map(|retention| (retention, current_cutoff(retention)));
         for (segment, new_alive_bitset_opt) in segments.iter().zip(alive_bitset_opt.into_iter()) {
             if segment.meta().num_docs() > 0 {
                 let reader =
                     SegmentReader::open_with_custom_alive_set(segment, new_alive_bitset_opt)?;
-                readers.push(reader);
+                if let Some((retention, cutoff)) = retention_cutoff_opt {
+                    readers.extend(apply_retention(reader, retention, cutoff)?);
+                } else {
+                    readers.push(reader);
+                }
             }
         }
 
         let max_doc = readers.iter().map(|reader| reader.num_docs()).sum();
-        if let Some(sort_by_field) = index_settings.sort_by_field.as_ref() {
-            readers = Self::sort_readers_by_min_sort_field(readers, sort_by_field)?;
-        }
         // sort segments by their natural sort setting
+        let sort_by_fields = &index_settings.sort_by_fields;
+        let mut sort_keys = Vec::new();
+        if !sort_by_fields.is_empty() {
+            sort_keys = SegmentSortKeys::open_for_merge(&readers, sort_by_fields)?;
+            (readers, sort_keys) =
+                Self::sort_readers_by_min_sort_key(readers, sort_keys, &sort_by_fields[0]);
+        }
         if max_doc >= MAX_DOC_LIMIT {
             let err_msg = format!(
                 "The segment resulting from this merge would have {} docs,which exceeds the limit \
@@ -189,32 +203,32 @@ impl IndexMerger {
             index_settings,
             schema,
             readers,
+            sort_keys,
             max_doc,
         })
     }
 
-    fn sort_readers_by_min_sort_field(
+    /// Returns the number of documents of the merged segment.
+    pub(crate) fn num_docs(&self) -> u32 {
+        self.max_doc
+    }
+
+    fn sort_readers_by_min_sort_key(
         readers: Vec<SegmentReader>,
+        sort_keys: Vec<SegmentSortKeys>,
         sort_by_field: &IndexSortByField,
-    ) -> crate::Result<Vec<SegmentReader>> {
+    ) -> (Vec<SegmentReader>, Vec<SegmentSortKeys>) {
         // presort the readers by their min_values, so that when they are disjunct, we can use
         // the regular merge logic (implicitly sorted)
-        let mut readers_with_min_sort_values = readers
-            .into_iter()
-            .map(|reader| {
-                let accessor = Self::get_sort_field_accessor(&reader, sort_by_field)?;
-                Ok((reader, accessor.min_value()))
-            })
-            .collect::<crate::Result<Vec<_>>>()?;
+        let mut readers_with_sort_keys: Vec<(SegmentReader, SegmentSortKeys)> =
+            readers.into_iter().zip(sort_keys).collect();
         if sort_by_field.order.is_asc() {
-            readers_with_min_sort_values.sort_by_key(|(_, min_val)| *min_val);
+            readers_with_sort_keys.sort_by_key(|(_, sort_keys)| sort_keys.primary_key_bounds().0);
         } else {
-            readers_with_min_sort_values.sort_by_key(|(_, min_val)| std::cmp::Reverse(*min_val));
+            readers_with_sort_keys
+                .sort_by_key(|(_, sort_keys)| std::cmp::Reverse(sort_keys.primary_key_bounds().0));
         }
-        Ok(readers_with_min_sort_values
-            .into_iter()
-            .map(|(reader, _)| reader)
-            .collect())
+        readers_with_sort_keys.into_iter().unzip()
     }
 
     fn write_fieldnorms(
@@ -262,81 +276,45 @@ impl IndexMerger {
     /// able to just stack them.
     pub(crate) fn is_disjunct_and_sorted_on_sort_property(
         &self,
-        sort_by_field: &IndexSortByField,
-    ) -> crate::Result<bool> {
-        let reader_ordinal_and_field_accessors =
-            self.get_reader_with_sort_field_accessor(sort_by_field)?;
-
-        let everything_is_in_order = reader_ordinal_and_field_accessors
-            .into_iter()
-            .map(|(_, col)| Arc::new(col))
+        sort_by_fields: &[IndexSortByField],
+    ) -> bool {
+        let Some(sort_by_field) = sort_by_fields.first() else {
+            return true;
+        };
+        // With several sort fields, documents sharing their primary key across two segments
+        // need to be ordered by the other fields.
+        let allow_equal_keys = sort_by_fields.len() == 1;
+        self.sort_keys
+            .iter()
+            .map(SegmentSortKeys::primary_key_bounds)
             .tuple_windows()
-            .all(|(field_accessor1, field_accessor2)| {
-                if sort_by_field.order.is_asc() {
-                    field_accessor1.max_value() <= field_accessor2.min_value()
+            .all(|((min_key1, max_key1), (min_key2, max_key2))| {
+                let (last_key, next_key) = if sort_by_field.order.is_asc() {
+                    (max_key1, min_key2)
                 } else {
-                    field_accessor1.min_value() >= field_accessor2.max_value()
-                }
-            });
-        Ok(everything_is_in_order)
-    }
-
-    pub(crate) fn get_sort_field_accessor(
-        reader: &SegmentReader,
-        sort_by_field: &IndexSortByField,
-    ) -> crate::Result<Arc<dyn ColumnValues>> {
-        reader.schema().get_field(&sort_by_field.field)?;
-        let value_accessor = reader
-            .fast_fields()
-            .u64_lenient(&sort_by_field.field)?
-            .ok_or_else(|| FastFieldNotAvailableError {
-                field_name: sort_by_field.field.to_string(),
-            })?;
-        Ok(value_accessor.first_or_default_col(0u64))
-    }
-    /// Collecting value_accessors into a vec to bind the lifetime.
-    pub(crate) fn get_reader_with_sort_field_accessor(
-        &self,
-        sort_by_field: &IndexSortByField,
-    ) -> crate::Result<Vec<(SegmentOrdinal, Arc<dyn ColumnValues>)>> {
-        let reader_ordinal_and_field_accessors = self
-            .readers
-            .iter()
-            .enumerate()
-            .map(|(reader_ordinal, _)| reader_ordinal as SegmentOrdinal)
-            .map(|reader_ordinal: SegmentOrdinal| {
-                let value_accessor = Self::get_sort_field_accessor(
-                    &self.readers[reader_ordinal as usize],
-                    sort_by_field,
-                )?;
-                Ok((reader_ordinal, value_accessor))
+                    (max_key2, min_key1)
+                };
+                last_key < next_key || (allow_equal_keys && last_key == next_key)
             })
-            .collect::<crate::Result<Vec<_>>>()?;
-        Ok(reader_ordinal_and_field_accessors)
     }
 
     /// Generates the doc_id mapping where position in the vec=new
     /// doc_id.
-    /// ReaderWithOrdinal will include the ordinal position of the
-    /// reader in self.readers.
-    pub(crate) fn generate_doc_id_mapping_with_sort_by_field(
+    pub(crate) fn generate_doc_id_mapping_with_sort_by_fields(
         &self,
-        sort_by_field: &IndexSortByField,
+        sort_by_fields: &[IndexSortByField],
     ) -> crate::Result<SegmentDocIdMapping> {
-        let reader_ordinal_and_field_accessors =
-            self.get_reader_with_sort_field_accessor(sort_by_field)?;
-        // Loading the field accessor on demand causes a 15x regression
-
-        // create iterators over segment/sort_accessor/doc_id  tuple
-        let doc_id_reader_pair =
-            reader_ordinal_and_field_accessors
-                .iter()
-                .map(|(reader_ord, ff_reader)| {
-                    let reader = &self.readers[*reader_ord as usize];
-                    reader
-                        .doc_ids_alive()
-                        .map(move |doc_id| (doc_id, reader_ord, ff_reader))
-                });
+        // create iterators over segment/sort_keys/doc_id  tuple
+        let doc_id_reader_pair = self
+            .readers
+            .iter()
+            .zip(self.sort_keys.iter())
+            .enumerate()
+            .map(|(reader_ord, (reader, sort_keys))| {
+                reader
+                    .doc_ids_alive()
+                    .map(move |doc_id| (doc_id, reader_ord as SegmentOrdinal, sort_keys))
+            });
 
         let total_num_new_docs = self
             .readers
@@ -350,16 +328,8 @@ impl IndexMerger {
         sorted_doc_ids.extend(
             doc_id_reader_pair
                 .into_iter()
-                .kmerge_by(|a, b| {
-                    let val1 = a.2.get_val(a.0);
-                    let val2 = b.2.get_val(b.0);
-                    if sort_by_field.order == Order::Asc {
-                        val1 < val2
-                    } else {
-                        val1 > val2
-                    }
-                })
-                .map(|(doc_id, &segment_ord, _)| DocAddress {
+                .kmerge_by(|a, b| a.2.compare(a.0, b.2, b.0, sort_by_fields) == Ordering::Less)
+                .map(|(doc_id, segment_ord, _)| DocAddress {
                     doc_id,
                     segment_ord,
                 }),
@@ -690,17 +660,13 @@ impl IndexMerger {
     /// # Returns
     /// The number of documents in the resulting segment.
     pub fn write(&self, mut serializer: SegmentSerializer) -> crate::Result<u32> {
-        let doc_id_mapping = if let Some(sort_by_field) = self.index_settings.sort_by_field.as_ref()
-        {
-            // If the documents are already sorted and stackable, we ignore the mapping and execute
-            // it as if there was no sorting
-            if self.is_disjunct_and_sorted_on_sort_property(sort_by_field)? {
-                self.get_doc_id_from_concatenated_data()?
-            } else {
-                self.generate_doc_id_mapping_with_sort_by_field(sort_by_field)?
-            }
-        } else {
+        let sort_by_fields = &self.index_settings.sort_by_fields;
+        // If the documents are already sorted and stackable, we ignore the mapping and execute
+        // it as if there was no sorting
+        let doc_id_mapping = if self.is_disjunct_and_sorted_on_sort_property(sort_by_fields) {
             self.get_doc_id_from_concatenated_data()?
+        } else {
+            self.generate_doc_id_mapping_with_sort_by_fields(sort_by_fields)?
         };
         debug!("write-fieldnorms");
         if let Some(fieldnorms_serializer) = serializer.extract_fieldnorms_serializer() {
@@ -1222,10 +1188,10 @@ mod tests {
         // In the merge case this will go through the doc_id mapping code
         test_merge_facets(
             Some(IndexSettings {
-                sort_by_field: Some(IndexSortByField {
+                sort_by_fields: vec![IndexSortByField {
                     field: "intval".to_string(),
                     order: Order::Desc,
-                }),
+                }],
                 ..Default::default()
             }),
             true,
@@ -1234,10 +1200,10 @@ mod tests {
         // sorted and disjunct
         test_merge_facets(
             Some(IndexSettings {
-                sort_by_field: Some(IndexSortByField {
+                sort_by_fields: vec![IndexSortByField {
                     field: "intval".to_string(),
                     order: Order::Desc,
-                }),
+                }],
                 ..Default::default()
             }),
             false,
@@ -1249,10 +1215,10 @@ mod tests {
         // In the merge case this will go through the doc_id mapping code
         test_merge_facets(
             Some(IndexSettings {
-                sort_by_field: Some(IndexSortByField {
+                sort_by_fields: vec![IndexSortByField {
                     field: "intval".to_string(),
                     order: Order::Desc,
-                }),
+                }],
                 ..Default::default()
             }),
             true,
@@ -1261,10 +1227,10 @@ mod tests {
         // sorted and disjunct
         test_merge_facets(
             Some(IndexSettings {
-                sort_by_field: Some(IndexSortByField {
+                sort_by_fields: vec![IndexSortByField {
                     field: "intval".to_string(),
                     order: Order::Desc,
-                }),
+                }],
                 ..Default::default()
             }),
             false,
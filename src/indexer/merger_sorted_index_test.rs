@@ -143,10 +143,10 @@ mod tests {
     #[test]
     fn test_merge_sorted_postinglist_sort_issue() {
         create_test_index_posting_list_issue(Some(IndexSettings {
-            sort_by_field: Some(IndexSortByField {
+            sort_by_fields: vec![IndexSortByField {
                 field: "intval".to_string(),
                 order: Order::Desc,
-            }),
+            }],
             ..Default::default()
         }));
     }
@@ -164,10 +164,10 @@ mod tests {
     fn test_merge_sorted_index_desc_(force_disjunct_segment_sort_values: bool) {
         let index = create_test_index(
             Some(IndexSettings {
-                sort_by_field: Some(IndexSortByField {
+                sort_by_fields: vec![IndexSortByField {
                     field: "intval".to_string(),
                     order: Order::Desc,
-                }),
+                }],
                 ..Default::default()
             }),
             force_disjunct_segment_sort_values,
@@ -350,10 +350,10 @@ mod tests {
     // fn test_merge_sorted_index_asc() {
     //     let index = create_test_index(
     //         Some(IndexSettings {
-    //             sort_by_field: Some(IndexSortByField {
+    //             sort_by_fields: vec![IndexSortByField {
     //                 field: "intval".to_string(),
     //                 order: Order::Asc,
-    //             }),
+    //             }],
     //             ..Default::default()
     //         }),
     //         false,
@@ -490,7 +490,7 @@ mod bench_sorted_index_merge {
         let schema = schema_builder.build();
 
         let index_builder = Index::builder().schema(schema).settings(IndexSettings {
-            sort_by_field,
+            sort_by_fields: sort_by_field.into_iter().collect(),
             ..Default::default()
         });
         let index = index_builder.create_in_ram().unwrap();
@@ -561,7 +561,7 @@ mod bench_sorted_index_merge {
             IndexMerger::open(index.schema(), index.settings().clone(), &segments[..])?;
         b.iter(|| {
             merger
-                .generate_doc_id_mapping_with_sort_by_field(&sort_by_field)
+                .generate_doc_id_mapping_with_sort_by_fields(&[sort_by_field.clone()])
                 .unwrap();
         });
 
@@ -20,13 +20,17 @@ pub mod segment_serializer;
 pub mod segment_updater;
 mod segment_writer;
 mod stamper;
+mod tiered_merge_policy;
+mod time_partitioned_merge_policy;
+mod write_ahead_log;
 
 use crossbeam_channel as channel;
 use smallvec::SmallVec;
 
 pub use self::index_writer::IndexWriter;
 pub(crate) use self::json_term_writer::{
-    convert_to_fast_value_and_get_term, set_string_and_get_terms, JsonTermWriter,
+    convert_to_fast_value_and_get_term, infer_type_from_str, set_string_and_get_terms,
+    split_json_path, JsonTermWriter, TextOrDateTime,
 };
 pub use self::log_merge_policy::LogMergePolicy;
 pub use self::merge_operation::MergeOperation;
@@ -37,6 +41,8 @@ pub use self::segment_manager::SegmentManager;
 pub use self::segment_serializer::SegmentSerializer;
 pub use self::segment_updater::{merge_filtered_segments, merge_indices};
 pub use self::segment_writer::SegmentWriter;
+pub use self::tiered_merge_policy::TieredMergePolicy;
+pub use self::time_partitioned_merge_policy::TimePartitionedMergePolicy;
 use crate::indexer::operation::AddOperation;
 
 /// Alias for the default merge policy, which is the `LogMergePolicy`.
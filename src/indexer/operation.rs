@@ -1,4 +1,6 @@
-use crate::query::Weight;
+use std::fmt;
+
+use crate::query::{Query, Weight};
 use crate::schema::{Document, Term};
 use crate::Opstamp;
 
@@ -15,11 +17,60 @@ pub struct AddOperation {
     pub document: Document,
 }
 
+/// Function rewriting a stored document in an update-by-query operation.
+pub type DocumentTransform = Box<dyn Fn(Document) -> Document + Send + Sync>;
+
 /// UserOperation is an enum type that encapsulates other operation types.
-#[derive(Eq, PartialEq, Debug)]
 pub enum UserOperation {
     /// Add operation
     Add(Document),
     /// Delete operation
     Delete(Term),
+    /// Upsert operation, replacing the documents with the same unique key.
+    ///
+    /// See [`IndexWriter::upsert_document`](crate::IndexWriter::upsert_document).
+    Upsert(Document),
+    /// Delete-by-query operation
+    DeleteQuery(Box<dyn Query>),
+    /// Update-by-query operation, re-indexing the documents matching the query after applying
+    /// the transform to them.
+    ///
+    /// All of the matching documents of the last commit are loaded into memory at once. The
+    /// operation is rejected with an `InvalidArgument` error if some operations were not
+    /// committed yet, including the operations preceding it in the same group.
+    ///
+    /// See [`IndexWriter::update_query`](crate::IndexWriter::update_query).
+    UpdateQuery {
+        /// The query selecting the documents to update.
+        query: Box<dyn Query>,
+        /// The transform applied to the stored version of every matching document.
+        transform: DocumentTransform,
+    },
+}
+
+impl fmt::Debug for UserOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UserOperation::Add(document) => f.debug_tuple("Add").field(document).finish(),
+            UserOperation::Delete(term) => f.debug_tuple("Delete").field(term).finish(),
+            UserOperation::Upsert(document) => f.debug_tuple("Upsert").field(document).finish(),
+            UserOperation::DeleteQuery(query) => f.debug_tuple("DeleteQuery").field(query).finish(),
+            UserOperation::UpdateQuery { query, .. } => f
+                .debug_struct("UpdateQuery")
+                .field("query", query)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+/// Operations based on a query are never equal, as queries cannot be compared.
+impl PartialEq for UserOperation {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (UserOperation::Add(left), UserOperation::Add(right))
+            | (UserOperation::Upsert(left), UserOperation::Upsert(right)) => left == right,
+            (UserOperation::Delete(left), UserOperation::Delete(right)) => left == right,
+            _ => false,
+        }
+    }
 }
@@ -45,8 +45,11 @@ impl<'a> PreparedCommit<'a> {
     /// At this point deletes have not been flushed yet.
     pub fn commit_future(self) -> FutureResult<Opstamp> {
         info!("committing {}", self.opstamp);
-        self.index_writer
-            .segment_updater()
-            .schedule_commit(self.opstamp, self.payload)
+        self.index_writer.segment_updater().schedule_commit(
+            self.opstamp,
+            self.payload,
+            self.index_writer.num_operations(),
+            self.index_writer.write_ahead_log(),
+        )
     }
 }
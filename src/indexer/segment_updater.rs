@@ -3,29 +3,31 @@ use std::collections::HashSet;
 use std::io::Write;
 use std::ops::Deref;
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 use fail::fail_point;
 use rayon::{ThreadPool, ThreadPoolBuilder};
 
 use super::segment_manager::SegmentManager;
 use crate::core::{
-    Index, IndexMeta, IndexSettings, Segment, SegmentId, SegmentMeta, META_FILEPATH,
+    current_cutoff, is_segment_expired, is_segment_meta_expired, Index, IndexMeta, IndexRetention,
+    IndexSettings, Segment, SegmentComponent, SegmentId, SegmentMeta, META_FILEPATH,
 };
 use crate::directory::{Directory, DirectoryClone, GarbageCollectionResult};
-use crate::fastfield::AliveBitSet;
+use crate::fastfield::{AliveBitSet, FastFieldReaders};
 use crate::indexer::delete_queue::DeleteCursor;
 use crate::indexer::index_writer::advance_deletes;
 use crate::indexer::merge_operation::MergeOperationInventory;
 use crate::indexer::merger::IndexMerger;
 use crate::indexer::segment_manager::SegmentsStatus;
 use crate::indexer::stamper::Stamper;
+use crate::indexer::write_ahead_log::WriteAheadLog;
 use crate::indexer::{
     DefaultMergePolicy, MergeCandidate, MergeOperation, MergePolicy, SegmentEntry,
     SegmentSerializer,
 };
-use crate::{FutureResult, Opstamp};
+use crate::{DateTime, FutureResult, Opstamp};
 
 const NUM_MERGE_THREADS: usize = 4;
 
@@ -119,6 +121,10 @@ fn merge(
     // An IndexMerger is like a "view" of our merged segments.
     let merger: IndexMerger =
         IndexMerger::open(index.schema(), index.settings().clone(), &segments[..])?;
+    if merger.num_docs() == 0 {
+        // All of the documents are expired.
+        return Ok(None);
+    }
 
     // ... we just serialize this index merger in our new segment to merge the segments.
     let segment_serializer = SegmentSerializer::for_segment(merged_segment.clone(), true)?;
@@ -128,6 +134,12 @@ fn merge(
     let merged_segment_id = merged_segment.id();
 
     let segment_meta = index.new_segment_meta(merged_segment_id, num_docs);
+    let segment_meta = index
+        .segment(segment_meta)
+        .with_fast_field_stats()?
+        .with_num_bytes()?
+        .meta()
+        .clone();
     Ok(Some(SegmentEntry::new(segment_meta, delete_cursor, None)))
 }
 
@@ -232,6 +244,12 @@ pub fn merge_filtered_segments<T: Into<Box<dyn Directory>>>(
     let num_docs = merger.write(segment_serializer)?;
 
     let segment_meta = merged_index.new_segment_meta(merged_segment_id, num_docs);
+    let segment_meta = merged_index
+        .segment(segment_meta)
+        .with_fast_field_stats()?
+        .with_num_bytes()?
+        .meta()
+        .clone();
 
     let stats = format!(
         "Segments Merge: [{}]",
@@ -276,6 +294,8 @@ pub(crate) struct InnerSegmentUpdater {
     killed: AtomicBool,
     stamper: Stamper,
     merge_operations: MergeOperationInventory,
+    // Number of operation groups of the `IndexWriter` covered by the last successful commit.
+    num_committed_operations: AtomicU64,
 }
 
 impl SegmentUpdater {
@@ -315,6 +335,7 @@ impl SegmentUpdater {
             killed: AtomicBool::new(false),
             stamper,
             merge_operations: Default::default(),
+            num_committed_operations: AtomicU64::new(0),
         })))
     }
 
@@ -379,6 +400,48 @@ impl SegmentUpdater {
         Ok(segment_entries)
     }
 
+    /// Removes the segments whose documents are all expired, according to the retention
+    /// settings of the index.
+    ///
+    /// Segments which are being merged are kept, as the merge drops their expired documents.
+    fn is_segment_expired(
+        &self,
+        segment_meta: &SegmentMeta,
+        retention: &IndexRetention,
+        cutoff: DateTime,
+    ) -> crate::Result<bool> {
+        if let Some(is_expired) = is_segment_meta_expired(segment_meta, retention, cutoff) {
+            return Ok(is_expired);
+        }
+        // Segments written before the fast field statistics were recorded.
+        let segment = self.index.segment(segment_meta.clone());
+        let fast_fields_data = segment.open_read(SegmentComponent::FastFields)?;
+        let fast_field_readers = FastFieldReaders::open(fast_fields_data, self.index.schema())?;
+        is_segment_expired(&fast_field_readers, retention, cutoff)
+    }
+
+    fn remove_expired_segments(
+        &self,
+        segment_entries: Vec<SegmentEntry>,
+    ) -> crate::Result<Vec<SegmentEntry>> {
+        let Some(retention) = self.index.settings().retention.as_ref() else {
+            return Ok(segment_entries);
+        };
+        let cutoff = current_cutoff(retention);
+        let segments_in_merge = self.merge_operations.segment_in_merge();
+        let mut alive_segment_entries = Vec::with_capacity(segment_entries.len());
+        for segment_entry in segment_entries {
+            if !segments_in_merge.contains(&segment_entry.segment_id())
+                && self.is_segment_expired(segment_entry.meta(), retention, cutoff)?
+            {
+                info!("Removing expired segment {:?}", segment_entry.segment_id());
+                continue;
+            }
+            alive_segment_entries.push(segment_entry);
+        }
+        Ok(alive_segment_entries)
+    }
+
     pub fn save_metas(
         &self,
         opstamp: Opstamp,
@@ -437,27 +500,51 @@ impl SegmentUpdater {
         files
     }
 
+    /// Schedules a commit, which also deletes the files of `write_ahead_log` whose operations
+    /// are all committed once the commit is persisted.
     pub(crate) fn schedule_commit(
         &self,
         opstamp: Opstamp,
         payload: Option<String>,
+        num_operations: u64,
+        write_ahead_log: Option<Arc<Mutex<WriteAheadLog>>>,
     ) -> FutureResult<Opstamp> {
         let segment_updater: SegmentUpdater = self.clone();
         self.schedule_task(move || {
             let segment_entries = segment_updater.purge_deletes(opstamp)?;
+            let segment_entries = segment_updater.remove_expired_segments(segment_entries)?;
             segment_updater.segment_manager.commit(segment_entries);
             segment_updater.save_metas(opstamp, payload)?;
+            segment_updater
+                .num_committed_operations
+                .fetch_max(num_operations, Ordering::SeqCst);
+            if let Some(write_ahead_log) = write_ahead_log {
+                write_ahead_log
+                    .lock()
+                    .map_err(|_| crate::TantivyError::Poisoned)?
+                    .remove_committed_files(opstamp)?;
+            }
             let _ = garbage_collect_files(segment_updater.clone());
             segment_updater.consider_merge_options();
             Ok(opstamp)
         })
     }
 
+    /// Returns the number of operation groups covered by the last successful commit.
+    pub(crate) fn num_committed_operations(&self) -> u64 {
+        self.num_committed_operations.load(Ordering::SeqCst)
+    }
+
+    /// Waits for the tasks scheduled so far, like commits, to be done.
+    pub(crate) fn wait_scheduled_tasks(&self) -> crate::Result<()> {
+        self.schedule_task(|| Ok(())).wait()
+    }
+
     fn store_meta(&self, index_meta: &IndexMeta) {
         *self.active_index_meta.write().unwrap() = Arc::new(index_meta.clone());
     }
 
-    fn load_meta(&self) -> Arc<IndexMeta> {
+    pub(crate) fn load_meta(&self) -> Arc<IndexMeta> {
         self.active_index_meta.read().unwrap().clone()
     }
 
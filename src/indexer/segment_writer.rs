@@ -1,7 +1,7 @@
 use columnar::MonotonicallyMappableToU64;
 use itertools::Itertools;
 
-use super::doc_id_mapping::{get_doc_id_mapping_from_field, DocIdMapping};
+use super::doc_id_mapping::{get_doc_id_mapping_from_fields, DocIdMapping};
 use super::operation::AddOperation;
 use crate::core::Segment;
 use crate::fastfield::FastFieldsWriter;
@@ -125,15 +125,13 @@ impl SegmentWriter {
     /// be used afterwards.
     pub fn finalize(mut self) -> crate::Result<Vec<u64>> {
         self.fieldnorms_writer.fill_up_to_max_doc(self.max_doc);
-        let mapping: Option<DocIdMapping> = self
-            .segment_serializer
-            .segment()
-            .index()
-            .settings()
-            .sort_by_field
-            .clone()
-            .map(|sort_by_field| get_doc_id_mapping_from_field(sort_by_field, &self))
-            .transpose()?;
+        let index_settings = self.segment_serializer.segment().index().settings().clone();
+        let sort_by_fields = &index_settings.sort_by_fields;
+        let mapping: Option<DocIdMapping> = if sort_by_fields.is_empty() {
+            None
+        } else {
+            Some(get_doc_id_mapping_from_fields(sort_by_fields, &self)?)
+        };
         remap_and_write(
             &self.per_field_postings_writers,
             self.ctx,
@@ -0,0 +1,495 @@
+use itertools::Itertools;
+
+use super::merge_policy::{MergeCandidate, MergePolicy};
+use crate::core::SegmentMeta;
+
+const DEFAULT_MAX_MERGE_AT_ONCE: usize = 10;
+const DEFAULT_SEGMENTS_PER_TIER: f64 = 10.0;
+const DEFAULT_MAX_MERGED_SEGMENT_BYTES: u64 = 5 * 1024 * 1024 * 1024;
+const DEFAULT_FLOOR_SEGMENT_BYTES: u64 = 2 * 1024 * 1024;
+const DEFAULT_DELETES_PCT_ALLOWED: f64 = 33.0;
+
+/// `TieredMergePolicy` merges segments of approximately equal size, in bytes, subject to an
+/// allowed number of segments per tier.
+///
+/// It is modeled on Lucene's `TieredMergePolicy`:
+/// - the index is allowed to have up to `segments_per_tier` segments of each tier, tiers
+///   growing exponentially in size. As long as there are more segments than allowed, the merge
+///   with the best score is picked: merges of segments of similar sizes, of a small total size,
+///   and reclaiming more deletes score better.
+/// - the merged segment never exceeds `max_merged_segment_bytes`. Segments which are more than
+///   half this size are not merged with other segments.
+/// - segments with a ratio of deleted documents above `deletes_pct_allowed` are rewritten on
+///   their own to reclaim their deletes, whatever their size.
+///
+/// The size of a segment is the number of bytes of its files, prorated to its number of alive
+/// documents. Segments written before their size was recorded in their
+/// [`SegmentMeta`](crate::SegmentMeta) are considered as small as `floor_segment_bytes`.
+///
+/// The policy can also be switched to a force merge mode with
+/// [`TieredMergePolicy::set_force_merge_max_num_segments`].
+#[derive(Debug, Clone)]
+pub struct TieredMergePolicy {
+    max_merge_at_once: usize,
+    segments_per_tier: f64,
+    max_merged_segment_bytes: u64,
+    floor_segment_bytes: u64,
+    deletes_pct_allowed: f64,
+    force_merge_max_num_segments: Option<usize>,
+}
+
+impl TieredMergePolicy {
+    /// Set the maximum number of segments merged at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if max_merge_at_once is lower than 2.
+    pub fn set_max_merge_at_once(&mut self, max_merge_at_once: usize) {
+        assert!(max_merge_at_once >= 2);
+        self.max_merge_at_once = max_merge_at_once;
+    }
+
+    /// Set the number of segments allowed per tier.
+    ///
+    /// Lower values mean more merging, but fewer segments.
+    ///
+    /// # Panics
+    ///
+    /// Panics if segments_per_tier is lower than 2.
+    pub fn set_segments_per_tier(&mut self, segments_per_tier: f64) {
+        assert!(segments_per_tier >= 2.0);
+        self.segments_per_tier = segments_per_tier;
+    }
+
+    /// Set the maximum size in bytes of a segment resulting from a merge.
+    ///
+    /// The size of the merged segment is estimated as the sum of the sizes of the merged
+    /// segments, so that it can exceed this value if merging does not shrink the segments.
+    pub fn set_max_merged_segment_bytes(&mut self, max_merged_segment_bytes: u64) {
+        self.max_merged_segment_bytes = max_merged_segment_bytes;
+    }
+
+    /// Set the size in bytes under which segments are considered as all having the same
+    /// size.
+    ///
+    /// This prevents having a long tail of tiny segments.
+    pub fn set_floor_segment_bytes(&mut self, floor_segment_bytes: u64) {
+        self.floor_segment_bytes = floor_segment_bytes;
+    }
+
+    /// Set the percentage of deleted documents in a segment to tolerate.
+    ///
+    /// A segment above this percentage is merged on its own to reclaim its deletes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if deletes_pct_allowed is not within (0..100].
+    pub fn set_deletes_pct_allowed(&mut self, deletes_pct_allowed: f64) {
+        assert!(deletes_pct_allowed > 0.0);
+        assert!(deletes_pct_allowed <= 100.0);
+        self.deletes_pct_allowed = deletes_pct_allowed;
+    }
+
+    /// Switch the policy to a force merge mode, merging the segments down to at most
+    /// `max_num_segments` segments.
+    ///
+    /// In this mode, only the merges needed to reach `max_num_segments` are suggested, merging
+    /// the smallest segments together regardless of `max_merged_segment_bytes`. `None` brings
+    /// the policy back to regular merging.
+    ///
+    /// # Panics
+    ///
+    /// Panics if max_num_segments is 0.
+    pub fn set_force_merge_max_num_segments(&mut self, max_num_segments: Option<usize>) {
+        assert_ne!(max_num_segments, Some(0));
+        self.force_merge_max_num_segments = max_num_segments;
+    }
+
+    fn merge_factor(&self) -> usize {
+        self.max_merge_at_once.min(self.segments_per_tier as usize)
+    }
+
+    fn floor_size(&self, size: u64) -> u64 {
+        size.max(self.floor_segment_bytes)
+    }
+
+    fn has_too_many_deletes(&self, segment: &SegmentMeta) -> bool {
+        deletes_pct(segment) > self.deletes_pct_allowed
+    }
+
+    /// Returns the number of segments the index is allowed to have, given the sizes of its
+    /// segments.
+    fn allowed_num_segments(&self, sizes: &[u64]) -> usize {
+        let Some(&min_size) = sizes.iter().min() else {
+            return 0;
+        };
+        let mut tier_size = self
+            .floor_size(min_size)
+            .min(self.max_merged_segment_bytes)
+            .max(1) as f64;
+        let mut bytes_left = sizes.iter().sum::<u64>() as f64;
+        let mut allowed_num_segments = 0.0;
+        loop {
+            let num_segments_in_tier = bytes_left / tier_size;
+            if num_segments_in_tier < self.segments_per_tier
+                || tier_size >= self.max_merged_segment_bytes as f64
+            {
+                allowed_num_segments += num_segments_in_tier.ceil();
+                break;
+            }
+            allowed_num_segments += self.segments_per_tier;
+            bytes_left -= self.segments_per_tier * tier_size;
+            tier_size =
+                (tier_size * self.merge_factor() as f64).min(self.max_merged_segment_bytes as f64);
+        }
+        allowed_num_segments.max(self.segments_per_tier) as usize
+    }
+
+    /// Scores a merge. Lower scores are better.
+    fn score(&self, merge: &[&SizedSegment], hit_too_large: bool) -> f64 {
+        let merged_size: u64 = merge.iter().map(|segment| segment.size).sum();
+        let merged_size_with_deletes: u64 =
+            merge.iter().map(|segment| segment.size_with_deletes).sum();
+        // Merges of segments of similar sizes are favored.
+        let skew = if hit_too_large {
+            // The merge is as large as it can be: pretend it is perfectly balanced.
+            1.0 / self.merge_factor() as f64
+        } else {
+            let floored_sizes: u64 = merge
+                .iter()
+                .map(|segment| self.floor_size(segment.size))
+                .sum();
+            self.floor_size(merge[0].size) as f64 / floored_sizes as f64
+        };
+        // Small merges are favored, but only slightly.
+        let size_factor = (self.floor_size(merged_size) as f64).powf(0.05);
+        // Merges reclaiming deletes are favored.
+        let non_deleted_ratio = if merged_size_with_deletes == 0 {
+            1.0
+        } else {
+            merged_size as f64 / merged_size_with_deletes as f64
+        };
+        skew * size_factor * non_deleted_ratio.powi(2)
+    }
+
+    /// Finds the best merge among the segments, sorted by decreasing size.
+    ///
+    /// Returns the positions of the segments to merge.
+    fn find_best_merge(&self, segments: &[&SizedSegment]) -> Option<Vec<usize>> {
+        let merge_factor = self.merge_factor();
+        let mut best_merge: Option<(f64, Vec<usize>)> = None;
+        for start in 0..segments.len() {
+            let mut merge: Vec<usize> = Vec::new();
+            let mut merged_size = 0u64;
+            let mut hit_too_large = false;
+            for (pos, segment) in segments.iter().enumerate().skip(start) {
+                if merge.len() >= self.max_merge_at_once {
+                    break;
+                }
+                if merged_size + segment.size > self.max_merged_segment_bytes {
+                    // Smaller segments may still fit in the merge.
+                    hit_too_large = true;
+                    continue;
+                }
+                merge.push(pos);
+                merged_size += segment.size;
+            }
+            if merge.len() < 2 {
+                continue;
+            }
+            if best_merge.is_some() && !hit_too_large && merge.len() < merge_factor {
+                // The next merges are only going to be smaller.
+                break;
+            }
+            let merge_segments: Vec<&SizedSegment> =
+                merge.iter().map(|&pos| segments[pos]).collect();
+            let score = self.score(&merge_segments, hit_too_large);
+            if best_merge
+                .as_ref()
+                .map(|(best_score, _)| score < *best_score)
+                .unwrap_or(true)
+            {
+                best_merge = Some((score, merge));
+            }
+        }
+        best_merge.map(|(_, merge)| merge)
+    }
+
+    fn compute_force_merge_candidates(
+        &self,
+        segments: &[SegmentMeta],
+        max_num_segments: usize,
+    ) -> Vec<MergeCandidate> {
+        if segments.len() <= max_num_segments {
+            return Vec::new();
+        }
+        let num_segments_to_merge = segments.len() - max_num_segments + 1;
+        let smallest_segment_ids = segments
+            .iter()
+            .map(SizedSegment::new)
+            .sorted_by_key(|segment| segment.size)
+            .take(num_segments_to_merge)
+            .map(|segment| segment.segment.id())
+            .collect();
+        vec![MergeCandidate(smallest_segment_ids)]
+    }
+}
+
+fn deletes_pct(segment: &SegmentMeta) -> f64 {
+    if segment.max_doc() == 0 {
+        return 0.0;
+    }
+    100.0 * segment.num_deleted_docs() as f64 / segment.max_doc() as f64
+}
+
+struct SizedSegment<'a> {
+    segment: &'a SegmentMeta,
+    // The size of the segment, prorated to its number of alive documents.
+    size: u64,
+    size_with_deletes: u64,
+}
+
+impl<'a> SizedSegment<'a> {
+    fn new(segment: &'a SegmentMeta) -> SizedSegment<'a> {
+        let size_with_deletes = segment.num_bytes().unwrap_or(0);
+        let size = if segment.max_doc() == 0 {
+            0
+        } else {
+            (size_with_deletes as f64 * segment.num_docs() as f64 / segment.max_doc() as f64) as u64
+        };
+        SizedSegment {
+            segment,
+            size,
+            size_with_deletes,
+        }
+    }
+}
+
+impl MergePolicy for TieredMergePolicy {
+    fn compute_merge_candidates(&self, segments: &[SegmentMeta]) -> Vec<MergeCandidate> {
+        if let Some(max_num_segments) = self.force_merge_max_num_segments {
+            return self.compute_force_merge_candidates(segments, max_num_segments);
+        }
+        let mut merge_candidates = Vec::new();
+        let mut eligible_segments: Vec<SizedSegment> = Vec::new();
+        for segment in segments {
+            if self.has_too_many_deletes(segment) {
+                merge_candidates.push(MergeCandidate(vec![segment.id()]));
+                continue;
+            }
+            let sized_segment = SizedSegment::new(segment);
+            // Segments that are too large to be merged with others are left alone.
+            if sized_segment.size <= self.max_merged_segment_bytes / 2 {
+                eligible_segments.push(sized_segment);
+            }
+        }
+        eligible_segments.sort_by_key(|segment| std::cmp::Reverse(segment.size));
+
+        let sizes: Vec<u64> = eligible_segments
+            .iter()
+            .map(|segment| segment.size)
+            .collect();
+        let allowed_num_segments = self.allowed_num_segments(&sizes);
+        let mut remaining_segments: Vec<&SizedSegment> = eligible_segments.iter().collect();
+        let mut num_merged_segments = 0;
+        while remaining_segments.len() + num_merged_segments > allowed_num_segments {
+            let Some(merge) = self.find_best_merge(&remaining_segments) else {
+                break;
+            };
+            merge_candidates.push(MergeCandidate(
+                merge
+                    .iter()
+                    .map(|&pos| remaining_segments[pos].segment.id())
+                    .collect(),
+            ));
+            for &pos in merge.iter().rev() {
+                remaining_segments.remove(pos);
+            }
+            num_merged_segments += 1;
+        }
+        merge_candidates
+    }
+}
+
+impl Default for TieredMergePolicy {
+    fn default() -> TieredMergePolicy {
+        TieredMergePolicy {
+            max_merge_at_once: DEFAULT_MAX_MERGE_AT_ONCE,
+            segments_per_tier: DEFAULT_SEGMENTS_PER_TIER,
+            max_merged_segment_bytes: DEFAULT_MAX_MERGED_SEGMENT_BYTES,
+            floor_segment_bytes: DEFAULT_FLOOR_SEGMENT_BYTES,
+            deletes_pct_allowed: DEFAULT_DELETES_PCT_ALLOWED,
+            force_merge_max_num_segments: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use once_cell::sync::Lazy;
+
+    use super::*;
+    use crate::core::{SegmentId, SegmentMetaInventory};
+    use crate::indexer::NoMergePolicy;
+    use crate::schema::{Schema, INDEXED};
+    use crate::{Index, Term};
+
+    static INVENTORY: Lazy<SegmentMetaInventory> = Lazy::new(SegmentMetaInventory::default);
+
+    const MB: u64 = 1024 * 1024;
+
+    fn create_segment_meta(num_bytes: u64) -> SegmentMeta {
+        INVENTORY
+            .new_segment_meta(SegmentId::generate_random(), 1_000)
+            .with_num_bytes(num_bytes)
+    }
+
+    fn total_bytes(merge_candidate: &MergeCandidate, segments: &[SegmentMeta]) -> u64 {
+        segments
+            .iter()
+            .filter(|segment| merge_candidate.0.contains(&segment.id()))
+            .map(|segment| segment.num_bytes().unwrap())
+            .sum()
+    }
+
+    #[test]
+    fn test_tiered_merge_policy_empty() {
+        assert!(TieredMergePolicy::default()
+            .compute_merge_candidates(&[])
+            .is_empty());
+    }
+
+    #[test]
+    fn test_tiered_merge_policy_within_budget() {
+        let segments: Vec<SegmentMeta> = std::iter::repeat_with(|| create_segment_meta(MB))
+            .take(10)
+            .collect();
+        assert!(TieredMergePolicy::default()
+            .compute_merge_candidates(&segments)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_tiered_merge_policy_merges_small_segments() {
+        let segments: Vec<SegmentMeta> = std::iter::repeat_with(|| create_segment_meta(MB))
+            .take(25)
+            .collect();
+        let merge_candidates = TieredMergePolicy::default().compute_merge_candidates(&segments);
+        // 25 segments of the lowest tier are brought down to 7 segments.
+        assert_eq!(merge_candidates.len(), 2);
+        assert_eq!(merge_candidates[0].0.len(), 10);
+        assert_eq!(merge_candidates[1].0.len(), 10);
+        assert!(merge_candidates[0]
+            .0
+            .iter()
+            .all(|segment_id| !merge_candidates[1].0.contains(segment_id)));
+    }
+
+    #[test]
+    fn test_tiered_merge_policy_max_merged_segment_bytes() {
+        let mut tiered_merge_policy = TieredMergePolicy::default();
+        tiered_merge_policy.set_max_merged_segment_bytes(100 * MB);
+        let segments: Vec<SegmentMeta> = std::iter::repeat_with(|| create_segment_meta(30 * MB))
+            .take(30)
+            .collect();
+        let merge_candidates = tiered_merge_policy.compute_merge_candidates(&segments);
+        assert!(!merge_candidates.is_empty());
+        for merge_candidate in &merge_candidates {
+            assert_eq!(merge_candidate.0.len(), 3);
+            assert!(total_bytes(merge_candidate, &segments) <= 100 * MB);
+        }
+
+        // Segments larger than half of the max merged segment size are left alone.
+        let large_segments: Vec<SegmentMeta> =
+            std::iter::repeat_with(|| create_segment_meta(60 * MB))
+                .take(30)
+                .collect();
+        assert!(tiered_merge_policy
+            .compute_merge_candidates(&large_segments)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_tiered_merge_policy_reclaims_deletes_of_large_segments() {
+        let segments = vec![
+            create_segment_meta(4 * 1024 * MB).with_delete_meta(400, 1),
+            create_segment_meta(4 * 1024 * MB).with_delete_meta(100, 1),
+            create_segment_meta(4 * 1024 * MB),
+        ];
+        let merge_candidates = TieredMergePolicy::default().compute_merge_candidates(&segments);
+        assert_eq!(merge_candidates.len(), 1);
+        assert_eq!(merge_candidates[0].0, vec![segments[0].id()]);
+    }
+
+    #[test]
+    fn test_tiered_merge_policy_prefers_merges_reclaiming_deletes() {
+        let mut tiered_merge_policy = TieredMergePolicy::default();
+        tiered_merge_policy.set_segments_per_tier(2.0);
+        tiered_merge_policy.set_max_merge_at_once(2);
+        tiered_merge_policy.set_floor_segment_bytes(1);
+        let segments = vec![
+            create_segment_meta(100),
+            create_segment_meta(100),
+            create_segment_meta(100).with_delete_meta(200, 1),
+            create_segment_meta(100),
+            create_segment_meta(100),
+        ];
+        let merge_candidates = tiered_merge_policy.compute_merge_candidates(&segments);
+        assert_eq!(merge_candidates.len(), 1);
+        assert_eq!(merge_candidates[0].0.len(), 2);
+        assert!(merge_candidates[0].0.contains(&segments[2].id()));
+    }
+
+    #[test]
+    fn test_tiered_merge_policy_force_merge() {
+        let mut tiered_merge_policy = TieredMergePolicy::default();
+        tiered_merge_policy.set_force_merge_max_num_segments(Some(2));
+        let segments = vec![
+            create_segment_meta(3 * MB),
+            create_segment_meta(10 * 1024 * MB),
+            create_segment_meta(MB),
+            create_segment_meta(2 * MB),
+        ];
+        let merge_candidates = tiered_merge_policy.compute_merge_candidates(&segments);
+        assert_eq!(merge_candidates.len(), 1);
+        assert_eq!(
+            merge_candidates[0].0,
+            vec![segments[2].id(), segments[3].id(), segments[0].id()]
+        );
+        tiered_merge_policy.set_force_merge_max_num_segments(Some(4));
+        assert!(tiered_merge_policy
+            .compute_merge_candidates(&segments)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_tiered_merge_policy_force_merge_index() -> crate::Result<()> {
+        let mut schema_builder = Schema::builder();
+        let int_field = schema_builder.add_u64_field("intval", INDEXED);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.set_merge_policy(Box::new(NoMergePolicy));
+        for val in 0..4u64 {
+            index_writer.add_document(doc!(int_field => val))?;
+            index_writer.commit()?;
+        }
+        let segment_metas = index.searchable_segment_metas()?;
+        assert_eq!(segment_metas.len(), 4);
+        assert!(segment_metas
+            .iter()
+            .all(|segment_meta| segment_meta.num_bytes().unwrap() > 0));
+
+        let mut tiered_merge_policy = TieredMergePolicy::default();
+        tiered_merge_policy.set_force_merge_max_num_segments(Some(1));
+        index_writer.set_merge_policy(Box::new(tiered_merge_policy));
+        index_writer.delete_term(Term::from_field_u64(int_field, 0));
+        index_writer.commit()?;
+        index_writer.wait_merging_threads()?;
+
+        let segment_metas = index.searchable_segment_metas()?;
+        assert_eq!(segment_metas.len(), 1);
+        assert_eq!(segment_metas[0].num_docs(), 3);
+        assert!(segment_metas[0].num_bytes().unwrap() > 0);
+        Ok(())
+    }
+}
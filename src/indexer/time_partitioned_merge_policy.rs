@@ -0,0 +1,380 @@
+use columnar::MonotonicallyMappableToU64;
+use itertools::Itertools;
+use time::OffsetDateTime;
+
+use super::merge_policy::{MergeCandidate, MergePolicy};
+use crate::core::SegmentMeta;
+use crate::DateTime;
+
+const DEFAULT_MIN_NUM_SEGMENTS_IN_MERGE: usize = 8;
+const DEFAULT_MAX_DOCS_BEFORE_MERGE: usize = 10_000_000;
+const DEFAULT_MAX_MERGE_AT_ONCE: usize = 10;
+const DEFAULT_MAX_GAP_SECS: u64 = 3600;
+
+/// `TimePartitionedMergePolicy` merges segments which are adjacent in time, for append-mostly
+/// time series.
+///
+/// The time range of a segment is given by the statistics of a date fast field, recorded in its
+/// [`SegmentMeta`](crate::SegmentMeta::fast_field_stats). Segments are ordered by their time
+/// range, and only runs of consecutive segments in this order are merged together, so that
+/// merging never makes the time range of a segment overlap another one more than it already did.
+/// A run ends where the next segment starts more than `max_gap_secs` after the end of the run,
+/// and contains at most `max_merge_at_once` segments.
+///
+/// Segments whose documents are all older than `frozen_after_secs` are frozen: they are never
+/// merged again, deletes included, so that they stay immutable. Segments without any date, or
+/// written before statistics were recorded, are never merged either.
+#[derive(Debug, Clone)]
+pub struct TimePartitionedMergePolicy {
+    field: String,
+    min_num_segments: usize,
+    max_docs_before_merge: usize,
+    max_merge_at_once: usize,
+    max_gap_secs: u64,
+    frozen_after_secs: Option<u64>,
+    max_merged_time_range_secs: Option<u64>,
+}
+
+impl TimePartitionedMergePolicy {
+    /// Creates a policy partitioning the segments by the date fast field `field`.
+    pub fn new(field: impl Into<String>) -> TimePartitionedMergePolicy {
+        TimePartitionedMergePolicy {
+            field: field.into(),
+            min_num_segments: DEFAULT_MIN_NUM_SEGMENTS_IN_MERGE,
+            max_docs_before_merge: DEFAULT_MAX_DOCS_BEFORE_MERGE,
+            max_merge_at_once: DEFAULT_MAX_MERGE_AT_ONCE,
+            max_gap_secs: DEFAULT_MAX_GAP_SECS,
+            frozen_after_secs: None,
+            max_merged_time_range_secs: None,
+        }
+    }
+
+    /// Set the minimum number of adjacent segments that may be merged together.
+    pub fn set_min_num_segments(&mut self, min_num_segments: usize) {
+        self.min_num_segments = min_num_segments;
+    }
+
+    /// Set the maximum number docs in a segment for it to be considered for
+    /// merging. A segment can still reach more than max_docs, by merging many
+    /// smaller ones.
+    pub fn set_max_docs_before_merge(&mut self, max_docs_merge_size: usize) {
+        self.max_docs_before_merge = max_docs_merge_size;
+    }
+
+    /// Set the maximum number of segments merged at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if max_merge_at_once is lower than 2.
+    pub fn set_max_merge_at_once(&mut self, max_merge_at_once: usize) {
+        assert!(max_merge_at_once >= 2);
+        self.max_merge_at_once = max_merge_at_once;
+    }
+
+    /// Set the maximum gap in seconds between the time ranges of two segments for them to be
+    /// considered adjacent.
+    pub fn set_max_gap_secs(&mut self, max_gap_secs: u64) {
+        self.max_gap_secs = max_gap_secs;
+    }
+
+    /// Set the age in seconds after which segments are frozen.
+    ///
+    /// A segment is frozen once its most recent date is older than `frozen_after_secs`.
+    pub fn set_frozen_after_secs(&mut self, frozen_after_secs: u64) {
+        self.frozen_after_secs = Some(frozen_after_secs);
+    }
+
+    /// Set the maximum time range in seconds of a segment resulting from a merge.
+    ///
+    /// Segments are then grouped into time partitions of at most this duration.
+    pub fn set_max_merged_time_range_secs(&mut self, max_merged_time_range_secs: u64) {
+        self.max_merged_time_range_secs = Some(max_merged_time_range_secs);
+    }
+
+    /// Returns the date before which segments are frozen, in the `u64` representation of the
+    /// fast field, at the time `now`.
+    fn frozen_cutoff(&self, now: DateTime) -> Option<u64> {
+        let frozen_after_secs = self.frozen_after_secs?;
+        let frozen_after_micros = frozen_after_secs.saturating_mul(1_000_000);
+        let frozen_after_micros = i64::try_from(frozen_after_micros).unwrap_or(i64::MAX);
+        let cutoff = DateTime::from_timestamp_micros(
+            now.into_timestamp_micros()
+                .saturating_sub(frozen_after_micros),
+        );
+        Some(columnar::DateTime::from(cutoff).to_u64())
+    }
+
+    fn compute_merge_candidates_at(
+        &self,
+        segments: &[SegmentMeta],
+        now: DateTime,
+    ) -> Vec<MergeCandidate> {
+        let frozen_cutoff_opt = self.frozen_cutoff(now);
+        let max_time_range_opt = self
+            .max_merged_time_range_secs
+            .map(|secs| secs.saturating_mul(1_000_000));
+        let max_gap = self.max_gap_secs.saturating_mul(1_000_000);
+        let time_sorted_segments = segments
+            .iter()
+            .filter_map(|segment| {
+                let stats = segment.fast_field_stats(&self.field)?;
+                Some((stats.min_value, stats.max_value, segment))
+            })
+            .sorted_by_key(|(min_value, max_value, _)| (*min_value, *max_value));
+
+        let mut runs: Vec<Vec<&SegmentMeta>> = Vec::new();
+        let mut run: Vec<&SegmentMeta> = Vec::new();
+        let mut run_min_value = 0u64;
+        let mut run_max_value = 0u64;
+        for (min_value, max_value, segment) in time_sorted_segments {
+            let is_frozen = frozen_cutoff_opt
+                .map(|frozen_cutoff| max_value < frozen_cutoff)
+                .unwrap_or(false);
+            if is_frozen || segment.num_docs() > self.max_docs_before_merge as u32 {
+                // Segments around a segment which is not merged are not adjacent anymore.
+                runs.push(std::mem::take(&mut run));
+                continue;
+            }
+            let exceeds_time_range = max_time_range_opt
+                .map(|max_time_range| run_max_value.max(max_value) - run_min_value > max_time_range)
+                .unwrap_or(false);
+            if !run.is_empty()
+                && (exceeds_time_range
+                    || min_value > run_max_value.saturating_add(max_gap)
+                    || run.len() >= self.max_merge_at_once)
+            {
+                runs.push(std::mem::take(&mut run));
+            }
+            if run.is_empty() {
+                run_min_value = min_value;
+                run_max_value = max_value;
+            } else {
+                run_max_value = run_max_value.max(max_value);
+            }
+            run.push(segment);
+        }
+        runs.push(run);
+
+        runs.into_iter()
+            .filter(|run| run.len() >= self.min_num_segments.max(2))
+            .map(|run| MergeCandidate(run.iter().map(|segment| segment.id()).collect()))
+            .collect()
+    }
+}
+
+impl MergePolicy for TimePartitionedMergePolicy {
+    fn compute_merge_candidates(&self, segments: &[SegmentMeta]) -> Vec<MergeCandidate> {
+        self.compute_merge_candidates_at(segments, DateTime::from_utc(OffsetDateTime::now_utc()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use once_cell::sync::Lazy;
+    use time::Duration;
+
+    use super::*;
+    use crate::core::{SegmentId, SegmentMetaInventory};
+    use crate::fastfield::FastFieldStats;
+    use crate::indexer::NoMergePolicy;
+    use crate::schema::{Schema, FAST};
+    use crate::Index;
+
+    static INVENTORY: Lazy<SegmentMetaInventory> = Lazy::new(SegmentMetaInventory::default);
+
+    const HOUR_SECS: i64 = 3600;
+
+    fn date_to_u64(timestamp_secs: i64) -> u64 {
+        columnar::DateTime::from(DateTime::from_timestamp_secs(timestamp_secs)).to_u64()
+    }
+
+    fn create_segment_meta(num_docs: u32, min_secs: i64, max_secs: i64) -> SegmentMeta {
+        let stats = FastFieldStats {
+            min_value: date_to_u64(min_secs),
+            max_value: date_to_u64(max_secs),
+            num_nulls: 0,
+        };
+        INVENTORY
+            .new_segment_meta(SegmentId::generate_random(), num_docs)
+            .with_fast_field_stats(BTreeMap::from([("timestamp".to_string(), stats)]))
+    }
+
+    fn test_merge_policy() -> TimePartitionedMergePolicy {
+        let mut merge_policy = TimePartitionedMergePolicy::new("timestamp");
+        merge_policy.set_min_num_segments(2);
+        merge_policy.set_max_docs_before_merge(1_000);
+        merge_policy
+    }
+
+    fn now() -> DateTime {
+        DateTime::from_timestamp_secs(100 * HOUR_SECS)
+    }
+
+    #[test]
+    fn test_time_partitioned_merge_policy_merges_adjacent_segments() {
+        let test_input = vec![
+            create_segment_meta(10, 2 * HOUR_SECS, 3 * HOUR_SECS),
+            INVENTORY.new_segment_meta(SegmentId::generate_random(), 10),
+            create_segment_meta(10, 0, HOUR_SECS),
+            create_segment_meta(10, HOUR_SECS, 2 * HOUR_SECS),
+        ];
+        let merge_candidates = test_merge_policy().compute_merge_candidates_at(&test_input, now());
+        assert_eq!(merge_candidates.len(), 1);
+        // The segment without statistics is left alone.
+        assert_eq!(
+            merge_candidates[0].0,
+            vec![test_input[2].id(), test_input[3].id(), test_input[0].id()]
+        );
+    }
+
+    #[test]
+    fn test_time_partitioned_merge_policy_min_num_segments() {
+        let mut merge_policy = test_merge_policy();
+        merge_policy.set_min_num_segments(3);
+        let test_input = vec![
+            create_segment_meta(10, 0, HOUR_SECS),
+            create_segment_meta(10, HOUR_SECS, 2 * HOUR_SECS),
+        ];
+        assert!(merge_policy
+            .compute_merge_candidates_at(&test_input, now())
+            .is_empty());
+    }
+
+    #[test]
+    fn test_time_partitioned_merge_policy_large_segment_splits_runs() {
+        let test_input = vec![
+            create_segment_meta(10, 0, HOUR_SECS),
+            create_segment_meta(10, HOUR_SECS, 2 * HOUR_SECS),
+            create_segment_meta(10_000, 2 * HOUR_SECS, 3 * HOUR_SECS),
+            create_segment_meta(10, 3 * HOUR_SECS, 4 * HOUR_SECS),
+            create_segment_meta(10, 4 * HOUR_SECS, 5 * HOUR_SECS),
+        ];
+        let merge_candidates = test_merge_policy().compute_merge_candidates_at(&test_input, now());
+        assert_eq!(merge_candidates.len(), 2);
+        assert_eq!(
+            merge_candidates[0].0,
+            vec![test_input[0].id(), test_input[1].id()]
+        );
+        assert_eq!(
+            merge_candidates[1].0,
+            vec![test_input[3].id(), test_input[4].id()]
+        );
+    }
+
+    #[test]
+    fn test_time_partitioned_merge_policy_frozen_segments() {
+        let mut merge_policy = test_merge_policy();
+        merge_policy.set_frozen_after_secs(10 * HOUR_SECS as u64);
+        let test_input = vec![
+            create_segment_meta(10, 0, HOUR_SECS),
+            create_segment_meta(10, HOUR_SECS, 2 * HOUR_SECS),
+            create_segment_meta(10, 80 * HOUR_SECS, 95 * HOUR_SECS),
+            create_segment_meta(10, 95 * HOUR_SECS, 99 * HOUR_SECS),
+        ];
+        let merge_candidates = merge_policy.compute_merge_candidates_at(&test_input, now());
+        assert_eq!(merge_candidates.len(), 1);
+        assert_eq!(
+            merge_candidates[0].0,
+            vec![test_input[2].id(), test_input[3].id()]
+        );
+    }
+
+    #[test]
+    fn test_time_partitioned_merge_policy_max_merged_time_range() {
+        let mut merge_policy = test_merge_policy();
+        merge_policy.set_max_merged_time_range_secs(2 * HOUR_SECS as u64);
+        let test_input: Vec<SegmentMeta> = (0..5)
+            .map(|hour| create_segment_meta(10, hour * HOUR_SECS, (hour + 1) * HOUR_SECS))
+            .collect();
+        let merge_candidates = merge_policy.compute_merge_candidates_at(&test_input, now());
+        assert_eq!(merge_candidates.len(), 2);
+        assert_eq!(
+            merge_candidates[0].0,
+            vec![test_input[0].id(), test_input[1].id()]
+        );
+        assert_eq!(
+            merge_candidates[1].0,
+            vec![test_input[2].id(), test_input[3].id()]
+        );
+    }
+
+    #[test]
+    fn test_time_partitioned_merge_policy_distant_clusters() {
+        let test_input = vec![
+            create_segment_meta(10, 50 * HOUR_SECS, 51 * HOUR_SECS),
+            create_segment_meta(10, 0, HOUR_SECS),
+            create_segment_meta(10, 51 * HOUR_SECS + 60, 52 * HOUR_SECS),
+            create_segment_meta(10, HOUR_SECS + 60, 2 * HOUR_SECS),
+        ];
+        let merge_candidates = test_merge_policy().compute_merge_candidates_at(&test_input, now());
+        assert_eq!(merge_candidates.len(), 2);
+        assert_eq!(
+            merge_candidates[0].0,
+            vec![test_input[1].id(), test_input[3].id()]
+        );
+        assert_eq!(
+            merge_candidates[1].0,
+            vec![test_input[0].id(), test_input[2].id()]
+        );
+
+        let mut merge_policy = test_merge_policy();
+        merge_policy.set_max_gap_secs(50 * HOUR_SECS as u64);
+        let merge_candidates = merge_policy.compute_merge_candidates_at(&test_input, now());
+        assert_eq!(merge_candidates.len(), 1);
+        assert_eq!(merge_candidates[0].0.len(), 4);
+    }
+
+    #[test]
+    fn test_time_partitioned_merge_policy_max_merge_at_once() {
+        let mut merge_policy = test_merge_policy();
+        merge_policy.set_max_merge_at_once(2);
+        let test_input: Vec<SegmentMeta> = (0..5)
+            .map(|hour| create_segment_meta(10, hour * HOUR_SECS, (hour + 1) * HOUR_SECS))
+            .collect();
+        let merge_candidates = merge_policy.compute_merge_candidates_at(&test_input, now());
+        assert_eq!(merge_candidates.len(), 2);
+        assert_eq!(
+            merge_candidates[0].0,
+            vec![test_input[0].id(), test_input[1].id()]
+        );
+        assert_eq!(
+            merge_candidates[1].0,
+            vec![test_input[2].id(), test_input[3].id()]
+        );
+    }
+
+    #[test]
+    fn test_time_partitioned_merge_policy_index() -> crate::Result<()> {
+        let mut schema_builder = Schema::builder();
+        let date_field = schema_builder.add_date_field("timestamp", FAST);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.set_merge_policy(Box::new(NoMergePolicy));
+        let now = OffsetDateTime::now_utc();
+        for days_ago in [30, 20, 2, 1] {
+            let date = DateTime::from_utc(now - Duration::days(days_ago));
+            index_writer.add_document(doc!(date_field => date))?;
+            index_writer.commit()?;
+        }
+
+        let mut merge_policy = TimePartitionedMergePolicy::new("timestamp");
+        merge_policy.set_min_num_segments(2);
+        merge_policy.set_frozen_after_secs(10 * 24 * HOUR_SECS as u64);
+        merge_policy.set_max_gap_secs(2 * 24 * HOUR_SECS as u64);
+        index_writer.set_merge_policy(Box::new(merge_policy));
+        index_writer.commit()?;
+        index_writer.wait_merging_threads()?;
+
+        let mut segment_num_docs: Vec<u32> = index
+            .searchable_segment_metas()?
+            .iter()
+            .map(SegmentMeta::num_docs)
+            .collect();
+        segment_num_docs.sort();
+        // The two recent segments are merged, the frozen ones are left alone.
+        assert_eq!(segment_num_docs, vec![1, 1, 2]);
+        Ok(())
+    }
+}
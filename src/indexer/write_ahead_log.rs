@@ -0,0 +1,708 @@
+//! Write-ahead log of the operations of an `IndexWriter`.
+//!
+//! When [`IndexSettings::write_ahead_log`](crate::IndexSettings::write_ahead_log) is enabled,
+//! the index writer journals every operation with its opstamp before it is handed to the
+//! indexing workers. The operations which were not committed yet are replayed when a new index
+//! writer is opened, so that they survive a crash of the process.
+//!
+//! Operations based on a query cannot be serialized. As the replay would miss the documents
+//! they delete, they are rejected by the index writer while the log is enabled.
+//!
+//! The records are written to the operating system as soon as they are appended, and synced to
+//! the storage according to the [`WriteAheadLogDurability`]. If a record cannot be written, the
+//! log is poisoned: it could otherwise hold a torn record in the middle of the file, and hide
+//! the records following it on replay. All of the appends and commits fail until the index
+//! writer is rolled back.
+//!
+//! The log is made of a list of files `.wal.<id>`, listed in the `.wal.json` manifest. Their
+//! names start with a `.`, so they are not subject to garbage collection. The log is rotated
+//! when a commit is prepared, and the files holding the operations of a commit are deleted once
+//! the commit is persisted.
+//!
+//! Each file is a sequence of records, each of which is written at once:
+//! - the length of the payload as `u32`
+//! - the crc32 of the payload as `u32`
+//! - the payload, i.e. the record kind as `u8` followed by the operations of the record.
+//!
+//! A record that is truncated or fails the checksum is the result of a crash while writing it.
+//! It is ignored, as well as everything following it in the file.
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use common::{BinarySerializable, VInt};
+use crc32fast::Hasher;
+use fail::fail_point;
+
+use crate::directory::error::{DeleteError, OpenReadError};
+use crate::directory::{Directory, ManagedDirectory, TerminatingWrite, WritePtr};
+use crate::indexer::operation::UserOperation;
+use crate::schema::{Document, Term};
+use crate::{Opstamp, TantivyError, WriteAheadLogDurability};
+
+const MANIFEST_FILEPATH: &str = ".wal.json";
+
+const OPERATIONS_RECORD: u8 = 0;
+const DELETE_ALL_RECORD: u8 = 1;
+
+const ADD_CODE: u8 = 0;
+const DELETE_CODE: u8 = 1;
+const UPSERT_CODE: u8 = 2;
+
+/// A record of the write-ahead log.
+#[derive(Debug)]
+pub(crate) enum LogRecord {
+    /// A group of operations, which were sent together to the indexing workers.
+    Operations(Vec<(Opstamp, UserOperation)>),
+    /// All of the documents were deleted.
+    DeleteAll(Opstamp),
+}
+
+impl LogRecord {
+    fn first_opstamp(&self) -> Option<Opstamp> {
+        match self {
+            LogRecord::Operations(operations) => operations.first().map(|(opstamp, _)| *opstamp),
+            LogRecord::DeleteAll(opstamp) => Some(*opstamp),
+        }
+    }
+}
+
+/// The list of the files of the log, persisted in `.wal.json`.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct Manifest {
+    files: Vec<PathBuf>,
+    next_file_id: u64,
+}
+
+#[derive(Debug)]
+struct LogFile {
+    path: PathBuf,
+    /// The opstamp of the commit containing all of the operations of the file, once the file is
+    /// not written anymore.
+    commit_opstamp: Option<Opstamp>,
+}
+
+/// The write-ahead log of an `IndexWriter`.
+pub(crate) struct WriteAheadLog {
+    directory: ManagedDirectory,
+    files: Vec<LogFile>,
+    next_file_id: u64,
+    writer: Option<WritePtr>,
+    durability: WriteAheadLogDurability,
+    /// The error which poisoned the log, if an append failed.
+    failure: Option<TantivyError>,
+}
+
+impl WriteAheadLog {
+    /// Opens the write-ahead log of the directory, and returns the records of the operations,
+    /// which are more recent than the last commit `committed_opstamp`, in opstamp order.
+    pub(crate) fn open(
+        directory: &ManagedDirectory,
+        committed_opstamp: Opstamp,
+        durability: WriteAheadLogDurability,
+    ) -> crate::Result<(WriteAheadLog, Vec<LogRecord>)> {
+        let manifest: Manifest = match directory.atomic_read(Path::new(MANIFEST_FILEPATH)) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(OpenReadError::FileDoesNotExist(_)) => Manifest::default(),
+            Err(err) => return Err(err.into()),
+        };
+        let mut write_ahead_log = WriteAheadLog {
+            directory: directory.clone(),
+            files: Vec::new(),
+            next_file_id: manifest.next_file_id,
+            writer: None,
+            durability,
+            failure: None,
+        };
+        let mut records = Vec::new();
+        let mut committed_files = Vec::new();
+        for path in manifest.files {
+            let data = match directory.atomic_read(&path) {
+                Ok(data) => data,
+                Err(OpenReadError::FileDoesNotExist(_)) => Vec::new(),
+                Err(err) => return Err(err.into()),
+            };
+            let num_records = records.len();
+            records.extend(
+                parse_records(&data)?
+                    .into_iter()
+                    .filter_map(|record| remove_committed_operations(record, committed_opstamp)),
+            );
+            if records.len() > num_records {
+                write_ahead_log.files.push(LogFile {
+                    path,
+                    commit_opstamp: None,
+                });
+            } else {
+                committed_files.push(path);
+            }
+        }
+        records.sort_by_key(|record| record.first_opstamp());
+        write_ahead_log.start_new_file()?;
+        write_ahead_log.delete_files(&committed_files)?;
+        Ok((write_ahead_log, records))
+    }
+
+    /// Appends a group of operations to the log.
+    ///
+    /// `is_batch` tells whether the operations were sent with `IndexWriter::run`, in which case
+    /// they are synced with the `PerBatch` durability.
+    ///
+    /// An `InvalidArgument` error is returned if one of the operations is based on a query.
+    pub(crate) fn append_operations(
+        &mut self,
+        operations: &[(Opstamp, UserOperation)],
+        is_batch: bool,
+    ) -> crate::Result<()> {
+        self.check_failure()?;
+        let mut payload = vec![OPERATIONS_RECORD];
+        VInt(operations.len() as u64).serialize(&mut payload)?;
+        for (opstamp, operation) in operations {
+            opstamp.serialize(&mut payload)?;
+            serialize_operation(operation, &mut payload)?;
+        }
+        let sync = match self.durability {
+            WriteAheadLogDurability::PerOperation => true,
+            WriteAheadLogDurability::PerBatch => is_batch,
+            WriteAheadLogDurability::OnCommit => false,
+        };
+        let result = self.append_record(&payload, sync);
+        self.poison_on_error(result)
+    }
+
+    /// Discards all of the operations of the log, and journals that all of the documents were
+    /// deleted.
+    ///
+    /// `opstamp` needs to be greater or equal to the opstamp of the last commit, and lower than
+    /// the opstamp of the next one.
+    pub(crate) fn append_delete_all(&mut self, opstamp: Opstamp) -> crate::Result<()> {
+        self.check_failure()?;
+        let result = self.discard().and_then(|()| {
+            self.start_new_file()?;
+            let mut payload = vec![DELETE_ALL_RECORD];
+            opstamp.serialize(&mut payload)?;
+            // The previous operations are gone, so the record is always synced.
+            self.append_record(&payload, true)
+        });
+        self.poison_on_error(result)
+    }
+
+    /// Syncs the current file, and starts a new one for the operations following the commit
+    /// `commit_opstamp`.
+    pub(crate) fn rotate(&mut self, commit_opstamp: Opstamp) -> crate::Result<()> {
+        self.check_failure()?;
+        let result = self.sync().and_then(|()| {
+            for file in &mut self.files {
+                file.commit_opstamp.get_or_insert(commit_opstamp);
+            }
+            self.start_new_file()
+        });
+        self.poison_on_error(result)
+    }
+
+    /// Deletes the files whose operations are all part of the commit `committed_opstamp`
+    /// or of an earlier commit.
+    pub(crate) fn remove_committed_files(
+        &mut self,
+        committed_opstamp: Opstamp,
+    ) -> crate::Result<()> {
+        let (committed_files, files) =
+            std::mem::take(&mut self.files)
+                .into_iter()
+                .partition(|file| {
+                    file.commit_opstamp
+                        .map(|commit_opstamp| commit_opstamp <= committed_opstamp)
+                        .unwrap_or(false)
+                });
+        self.files = files;
+        let committed_paths: Vec<PathBuf> = committed_files
+            .into_iter()
+            .map(|file: LogFile| file.path)
+            .collect();
+        self.delete_files(&committed_paths)
+    }
+
+    /// Deletes all of the files of the log.
+    ///
+    /// Nothing can be appended to the log afterwards.
+    pub(crate) fn discard(&mut self) -> crate::Result<()> {
+        // The writer has to be closed first, as flushing it could recreate its file.
+        self.writer = None;
+        let paths: Vec<PathBuf> = std::mem::take(&mut self.files)
+            .into_iter()
+            .map(|file| file.path)
+            .collect();
+        self.delete_files(&paths)
+    }
+
+    /// Returns true if an append failed, in which case the index writer needs to be rolled
+    /// back.
+    pub(crate) fn is_poisoned(&self) -> bool {
+        self.failure.is_some()
+    }
+
+    fn check_failure(&self) -> crate::Result<()> {
+        match &self.failure {
+            Some(err) => Err(TantivyError::InternalError(format!(
+                "The write-ahead log is poisoned by a previous failure ({}), the index writer \
+                 needs to be rolled back",
+                err
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn poison_on_error(&mut self, result: crate::Result<()>) -> crate::Result<()> {
+        if let Err(err) = &result {
+            self.failure = Some(err.clone());
+        }
+        result
+    }
+
+    fn append_record(&mut self, payload: &[u8], sync: bool) -> crate::Result<()> {
+        let writer = self.writer.as_mut().ok_or_else(|| {
+            TantivyError::InternalError("The write-ahead log was discarded".to_string())
+        })?;
+        let mut hasher = Hasher::new();
+        hasher.update(payload);
+        let mut record = Vec::with_capacity(payload.len() + 8);
+        (payload.len() as u32).serialize(&mut record)?;
+        hasher.finalize().serialize(&mut record)?;
+        record.extend_from_slice(payload);
+        fail_point!("WriteAheadLog::append_record", |msg| Err(TantivyError::from(
+            io::Error::new(
+                io::ErrorKind::Other,
+                msg.unwrap_or_else(|| "Undefined".to_string())
+            )
+        )));
+        writer.write_all(&record)?;
+        if sync {
+            writer.sync()?;
+        } else {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> crate::Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.sync()?;
+        }
+        Ok(())
+    }
+
+    fn start_new_file(&mut self) -> crate::Result<()> {
+        let path = PathBuf::from(format!(".wal.{}", self.next_file_id));
+        self.next_file_id += 1;
+        self.files.push(LogFile {
+            path: path.clone(),
+            commit_opstamp: None,
+        });
+        // The file is listed before it is created, so that it cannot be forgotten.
+        self.save_manifest()?;
+        self.writer = Some(self.directory.open_write(&path)?);
+        Ok(())
+    }
+
+    /// Removes `paths` from the manifest, then deletes them.
+    fn delete_files(&self, paths: &[PathBuf]) -> crate::Result<()> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+        self.save_manifest()?;
+        for path in paths {
+            match self.directory.delete(path) {
+                Ok(()) | Err(DeleteError::FileDoesNotExist(_)) => {}
+                Err(DeleteError::IoError { io_error, .. }) => {
+                    return Err(TantivyError::IoError(io_error));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn save_manifest(&self) -> crate::Result<()> {
+        let manifest = Manifest {
+            files: self.files.iter().map(|file| file.path.clone()).collect(),
+            next_file_id: self.next_file_id,
+        };
+        let data = serde_json::to_vec(&manifest)?;
+        self.directory
+            .atomic_write(Path::new(MANIFEST_FILEPATH), &data)?;
+        Ok(())
+    }
+}
+
+fn serialize_operation(operation: &UserOperation, payload: &mut Vec<u8>) -> crate::Result<()> {
+    match operation {
+        UserOperation::Add(document) => {
+            ADD_CODE.serialize(payload)?;
+            document.serialize(payload)?;
+        }
+        UserOperation::Delete(term) => {
+            DELETE_CODE.serialize(payload)?;
+            term.as_slice().to_vec().serialize(payload)?;
+        }
+        UserOperation::Upsert(document) => {
+            UPSERT_CODE.serialize(payload)?;
+            document.serialize(payload)?;
+        }
+        UserOperation::DeleteQuery(_) | UserOperation::UpdateQuery { .. } => {
+            return Err(TantivyError::InvalidArgument(
+                "Operations based on a query cannot be journaled in the write-ahead log"
+                    .to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn deserialize_operation<R: Read>(reader: &mut R) -> io::Result<UserOperation> {
+    let operation = match u8::deserialize(reader)? {
+        ADD_CODE => UserOperation::Add(Document::deserialize(reader)?),
+        DELETE_CODE => UserOperation::Delete(Term::wrap(Vec::<u8>::deserialize(reader)?)),
+        UPSERT_CODE => UserOperation::Upsert(Document::deserialize(reader)?),
+        code => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown operation code {} in the write-ahead log", code),
+            ));
+        }
+    };
+    Ok(operation)
+}
+
+fn deserialize_record(mut payload: &[u8]) -> io::Result<LogRecord> {
+    match u8::deserialize(&mut payload)? {
+        OPERATIONS_RECORD => {
+            let num_operations = VInt::deserialize(&mut payload)?.val() as usize;
+            let operations = (0..num_operations)
+                .map(|_| {
+                    let opstamp = Opstamp::deserialize(&mut payload)?;
+                    Ok((opstamp, deserialize_operation(&mut payload)?))
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+            Ok(LogRecord::Operations(operations))
+        }
+        DELETE_ALL_RECORD => Ok(LogRecord::DeleteAll(Opstamp::deserialize(&mut payload)?)),
+        kind => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unknown record kind {} in the write-ahead log", kind),
+        )),
+    }
+}
+
+/// Parses the records of a file of the log, stopping at the first incomplete record.
+fn parse_records(mut data: &[u8]) -> crate::Result<Vec<LogRecord>> {
+    let mut records = Vec::new();
+    while data.len() >= 8 {
+        let payload_len = u32::deserialize(&mut &data[0..4])? as usize;
+        let checksum = u32::deserialize(&mut &data[4..8])?;
+        let Some(payload) = data.get(8..8 + payload_len) else {
+            break;
+        };
+        let mut hasher = Hasher::new();
+        hasher.update(payload);
+        if hasher.finalize() != checksum {
+            break;
+        }
+        let record = deserialize_record(payload).map_err(|io_error| {
+            TantivyError::IoError(Arc::new(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid record in the write-ahead log: {}", io_error),
+            )))
+        })?;
+        records.push(record);
+        data = &data[8 + payload_len..];
+    }
+    Ok(records)
+}
+
+/// Removes the operations of `record` which are part of the last commit.
+fn remove_committed_operations(record: LogRecord, committed_opstamp: Opstamp) -> Option<LogRecord> {
+    match record {
+        LogRecord::Operations(mut operations) => {
+            operations.retain(|(opstamp, _)| *opstamp >= committed_opstamp);
+            if operations.is_empty() {
+                None
+            } else {
+                Some(LogRecord::Operations(operations))
+            }
+        }
+        LogRecord::DeleteAll(opstamp) => {
+            (opstamp >= committed_opstamp).then_some(LogRecord::DeleteAll(opstamp))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{parse_records, LogRecord, WriteAheadLog, MANIFEST_FILEPATH};
+    use crate::collector::Count;
+    use crate::directory::{Directory, RamDirectory};
+    use crate::indexer::operation::UserOperation;
+    use crate::query::{AllQuery, TermQuery};
+    use crate::schema::{Field, IndexRecordOption, Schema, STRING};
+    use crate::{Index, IndexSettings, TantivyError, Term, WriteAheadLogDurability};
+
+    fn wal_index() -> (Index, Field) {
+        let mut schema_builder = Schema::builder();
+        let text_field = schema_builder.add_text_field("text", STRING);
+        let index = Index::builder()
+            .schema(schema_builder.build())
+            .settings(IndexSettings {
+                write_ahead_log: Some(WriteAheadLogDurability::PerOperation),
+                ..Default::default()
+            })
+            .create_in_ram()
+            .unwrap();
+        (index, text_field)
+    }
+
+    fn num_docs(index: &Index, text_field: Field, text: &str) -> crate::Result<usize> {
+        let searcher = index.reader()?.searcher();
+        let query = TermQuery::new(
+            Term::from_field_text(text_field, text),
+            IndexRecordOption::Basic,
+        );
+        searcher.search(&query, &Count)
+    }
+
+    fn log_files(index: &Index) -> Vec<String> {
+        let manifest = index
+            .directory()
+            .atomic_read(Path::new(MANIFEST_FILEPATH))
+            .unwrap();
+        let manifest: serde_json::Value = serde_json::from_slice(&manifest).unwrap();
+        manifest["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|path| path.as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_records_ignores_torn_record() -> crate::Result<()> {
+        let (index, text_field) = wal_index();
+        let (mut write_ahead_log, records) = WriteAheadLog::open(index.directory(), 0, WriteAheadLogDurability::PerOperation)?;
+        assert!(records.is_empty());
+        write_ahead_log.append_operations(
+            &[
+                (0, UserOperation::Add(doc!(text_field => "a"))),
+                (
+                    1,
+                    UserOperation::Delete(Term::from_field_text(text_field, "b")),
+                ),
+            ],
+            true,
+        )?;
+        write_ahead_log.append_operations(
+            &[(2, UserOperation::Add(doc!(text_field => "c")))],
+            false,
+        )?;
+        let data = index.directory().atomic_read(Path::new(".wal.0"))?;
+
+        let records = parse_records(&data)?;
+        assert_eq!(records.len(), 2);
+        let LogRecord::Operations(operations) = &records[0] else {
+            panic!("expected operations");
+        };
+        assert_eq!(operations.len(), 2);
+        assert_eq!(operations[1].0, 1);
+        assert!(matches!(
+            &operations[1].1,
+            UserOperation::Delete(term) if term == &Term::from_field_text(text_field, "b")
+        ));
+
+        // The last record was not entirely written.
+        assert_eq!(parse_records(&data[..data.len() - 1])?.len(), 1);
+        // The last record is corrupted.
+        let mut corrupted_data = data.clone();
+        *corrupted_data.last_mut().unwrap() ^= 1;
+        assert_eq!(parse_records(&corrupted_data)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_ahead_log_replay() -> crate::Result<()> {
+        let (index, text_field) = wal_index();
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.add_document(doc!(text_field => "a"))?;
+        index_writer.add_document(doc!(text_field => "b"))?;
+        index_writer.commit()?;
+        index_writer.add_document(doc!(text_field => "c"))?;
+        index_writer.delete_term(Term::from_field_text(text_field, "a"));
+        index_writer.run(vec![
+            UserOperation::Add(doc!(text_field => "d")),
+            UserOperation::Delete(Term::from_field_text(text_field, "c")),
+            UserOperation::Add(doc!(text_field => "c")),
+        ])?;
+        let last_opstamp = index_writer.add_document(doc!(text_field => "e"))?;
+        // The uncommitted operations are lost without the write-ahead log.
+        drop(index_writer);
+        assert_eq!(num_docs(&index, text_field, "a")?, 1);
+        assert_eq!(num_docs(&index, text_field, "c")?, 0);
+
+        let mut index_writer = index.writer_for_tests()?;
+        assert!(index_writer.commit()? > last_opstamp);
+        assert_eq!(num_docs(&index, text_field, "a")?, 0);
+        assert_eq!(num_docs(&index, text_field, "b")?, 1);
+        assert_eq!(num_docs(&index, text_field, "c")?, 1);
+        assert_eq!(num_docs(&index, text_field, "d")?, 1);
+        assert_eq!(num_docs(&index, text_field, "e")?, 1);
+
+        // The committed operations are not replayed again.
+        drop(index_writer);
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.commit()?;
+        assert_eq!(num_docs(&index, text_field, "c")?, 1);
+        assert_eq!(index.reader()?.searcher().num_docs(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_ahead_log_truncated_on_commit() -> crate::Result<()> {
+        let (index, text_field) = wal_index();
+        let mut index_writer = index.writer_for_tests()?;
+        assert_eq!(log_files(&index), vec![".wal.0"]);
+        index_writer.add_document(doc!(text_field => "a"))?;
+        index_writer.commit()?;
+        assert_eq!(log_files(&index), vec![".wal.1"]);
+        assert!(!index.directory().exists(Path::new(".wal.0"))?);
+
+        index_writer.add_document(doc!(text_field => "b"))?;
+        index_writer.prepare_commit()?.commit_future().wait()?;
+        assert_eq!(log_files(&index), vec![".wal.2"]);
+        assert!(!index.directory().exists(Path::new(".wal.1"))?);
+
+        // A stale log is not replayed by the next index writer.
+        drop(index_writer);
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.commit()?;
+        assert_eq!(num_docs(&index, text_field, "b")?, 1);
+        assert_eq!(index.reader()?.searcher().num_docs(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_ahead_log_rollback() -> crate::Result<()> {
+        let (index, text_field) = wal_index();
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.add_document(doc!(text_field => "a"))?;
+        index_writer.commit()?;
+        index_writer.add_document(doc!(text_field => "b"))?;
+        index_writer.rollback()?;
+        index_writer.add_document(doc!(text_field => "c"))?;
+        drop(index_writer);
+
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.commit()?;
+        assert_eq!(num_docs(&index, text_field, "a")?, 1);
+        assert_eq!(num_docs(&index, text_field, "b")?, 0);
+        assert_eq!(num_docs(&index, text_field, "c")?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_ahead_log_delete_all_documents() -> crate::Result<()> {
+        let (index, text_field) = wal_index();
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.add_document(doc!(text_field => "a"))?;
+        index_writer.commit()?;
+        index_writer.add_document(doc!(text_field => "b"))?;
+        index_writer.delete_all_documents()?;
+        index_writer.add_document(doc!(text_field => "c"))?;
+        drop(index_writer);
+
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.commit()?;
+        let searcher = index.reader()?.searcher();
+        assert_eq!(searcher.num_docs(), 1);
+        assert_eq!(num_docs(&index, text_field, "c")?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_ahead_log_rejects_query_operations() -> crate::Result<()> {
+        let (index, text_field) = wal_index();
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.add_document(doc!(text_field => "a"))?;
+        index_writer.add_document(doc!(text_field => "b"))?;
+        index_writer.commit()?;
+
+        let err = index_writer
+            .delete_query(Box::new(AllQuery))
+            .unwrap_err();
+        assert!(matches!(err, TantivyError::InvalidArgument(_)));
+        let err = index_writer
+            .run(vec![
+                UserOperation::Delete(Term::from_field_text(text_field, "a")),
+                UserOperation::DeleteQuery(Box::new(AllQuery)),
+            ])
+            .unwrap_err();
+        assert!(matches!(err, TantivyError::InvalidArgument(_)));
+        index_writer.delete_term(Term::from_field_text(text_field, "b"));
+        index_writer.add_document(doc!(text_field => "c"))?;
+        drop(index_writer);
+
+        // The deleted document stays deleted after the replay, and the rejected batch was
+        // not applied.
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.commit()?;
+        assert_eq!(num_docs(&index, text_field, "a")?, 1);
+        assert_eq!(num_docs(&index, text_field, "b")?, 0);
+        assert_eq!(num_docs(&index, text_field, "c")?, 1);
+        assert_eq!(index.reader()?.searcher().num_docs(), 2);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn test_write_ahead_log_is_readable_before_terminate() -> crate::Result<()> {
+        let tempdir = tempfile::TempDir::new()?;
+        let mut schema_builder = Schema::builder();
+        let text_field = schema_builder.add_text_field("text", STRING);
+        let index = Index::builder()
+            .schema(schema_builder.build())
+            .settings(IndexSettings {
+                write_ahead_log: Some(WriteAheadLogDurability::PerOperation),
+                ..Default::default()
+            })
+            .create_in_dir(tempdir.path())?;
+        let index_writer = index.writer_for_tests()?;
+        index_writer.add_document(doc!(text_field => "a"))?;
+        index_writer.delete_term(Term::from_field_text(text_field, "b"));
+
+        // The index writer is still alive, so the log was not terminated.
+        let data = std::fs::read(tempdir.path().join(".wal.0"))?;
+        let records = parse_records(&data)?;
+        assert_eq!(records.len(), 2);
+        assert!(matches!(
+            &records[1],
+            LogRecord::Operations(operations) if operations[0].0 == 1
+        ));
+        drop(index_writer);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_ahead_log_disabled() -> crate::Result<()> {
+        let directory = RamDirectory::create();
+        let mut schema_builder = Schema::builder();
+        let text_field = schema_builder.add_text_field("text", STRING);
+        let index = Index::create(
+            directory.clone(),
+            schema_builder.build(),
+            Default::default(),
+        )?;
+        let mut index_writer = index.writer_for_tests()?;
+        index_writer.add_document(doc!(text_field => "a"))?;
+        index_writer.commit()?;
+        assert!(!directory.exists(Path::new(MANIFEST_FILEPATH))?);
+        Ok(())
+    }
+}
@@ -310,12 +310,12 @@ use serde::{Deserialize, Serialize};
 
 pub use self::docset::{DocSet, TERMINATED};
 pub use crate::core::{
-    Executor, Index, IndexBuilder, IndexMeta, IndexSettings, IndexSortByField, InvertedIndexReader,
-    Order, Searcher, SearcherGeneration, Segment, SegmentComponent, SegmentId, SegmentMeta,
-    SegmentReader, SingleSegmentIndexWriter,
+    Executor, Index, IndexBuilder, IndexMeta, IndexRetention, IndexSettings, IndexSortByField,
+    InvertedIndexReader, Order, Searcher, SearcherGeneration, Segment, SegmentComponent, SegmentId,
+    SegmentMeta, SegmentReader, SingleSegmentIndexWriter, WriteAheadLogDurability,
 };
 pub use crate::directory::Directory;
-pub use crate::indexer::operation::UserOperation;
+pub use crate::indexer::operation::{DocumentTransform, UserOperation};
 pub use crate::indexer::{merge_filtered_segments, merge_indices, IndexWriter, PreparedCommit};
 pub use crate::postings::Postings;
 pub use crate::schema::{DateOptions, DatePrecision, Document, Term};
@@ -372,6 +372,7 @@ pub fn version_string() -> &'static str {
 pub mod merge_policy {
     pub use crate::indexer::{
         DefaultMergePolicy, LogMergePolicy, MergeCandidate, MergePolicy, NoMergePolicy,
+        TieredMergePolicy, TimePartitionedMergePolicy,
     };
 }
 
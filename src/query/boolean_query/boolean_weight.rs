@@ -181,6 +181,29 @@ impl<TScoreCombiner: ScoreCombiner + Sync> Weight for BooleanWeight<TScoreCombin
         }
     }
 
+    fn may_match(&self, reader: &SegmentReader) -> bool {
+        let mut has_must = false;
+        let mut has_should = false;
+        let mut should_may_match = false;
+        for (occur, weight) in &self.weights {
+            match occur {
+                Occur::Must => {
+                    if !weight.may_match(reader) {
+                        return false;
+                    }
+                    has_must = true;
+                }
+                Occur::Should => {
+                    has_should = true;
+                    should_may_match = should_may_match || weight.may_match(reader);
+                }
+                Occur::MustNot => {}
+            }
+        }
+        // Without any `Must` clause, one of the `Should` clauses has to match.
+        has_must || !has_should || should_may_match
+    }
+
     fn explain(&self, reader: &SegmentReader, doc: DocId) -> crate::Result<Explanation> {
         let mut scorer = self.scorer(reader, 1.0)?;
         if scorer.seek(doc) != doc {
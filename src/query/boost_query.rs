@@ -69,6 +69,10 @@ impl Weight for BoostWeight {
         self.weight.scorer(reader, boost * self.boost)
     }
 
+    fn may_match(&self, reader: &SegmentReader) -> bool {
+        self.weight.may_match(reader)
+    }
+
     fn explain(&self, reader: &SegmentReader, doc: u32) -> crate::Result<Explanation> {
         let mut scorer = self.scorer(reader, 1.0)?;
         if scorer.seek(doc) != doc {
@@ -67,6 +67,10 @@ impl Weight for ConstWeight {
         Ok(Box::new(ConstScorer::new(inner_scorer, boost * self.score)))
     }
 
+    fn may_match(&self, reader: &SegmentReader) -> bool {
+        self.weight.may_match(reader)
+    }
+
     fn explain(&self, reader: &SegmentReader, doc: u32) -> crate::Result<Explanation> {
         let mut scorer = self.scorer(reader, 1.0)?;
         if scorer.seek(doc) != doc {
@@ -0,0 +1,348 @@
+use std::ops::{Bound, RangeBounds};
+
+use columnar::{Column, ColumnType, MonotonicallyMappableToU64, StrColumn};
+use common::BitSet;
+
+use crate::fastfield::NestedDocuments;
+use crate::query::{BitSetDocSet, ConstScorer, EnableScoring, Explanation, Query, Scorer, Weight};
+use crate::{DocId, DocSet, Score, SegmentReader, TantivyError};
+
+/// A condition on a fast field of the elements of a [`NestedQuery`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum NestedCondition {
+    /// Matches elements with the text value.
+    Text(String),
+    /// Matches elements with the boolean value.
+    Bool(bool),
+    /// Matches elements with a numeric value in the range.
+    Range(Bound<f64>, Bound<f64>),
+}
+
+/// The `NestedQuery` matches the documents with an element of a nested json path, which
+/// satisfies all of the conditions.
+///
+/// A condition on the flattened values of an array of objects, e.g. `items.sku:a AND
+/// items.price:>4` also matches an order, which contains a sku `a` with a price of `3` and
+/// another item with a price of `5`. If `items` is declared as nested path with
+/// [`JsonObjectOptions::add_nested_path`](crate::schema::JsonObjectOptions::add_nested_path), a
+/// nested query checks the conditions on one item at a time.
+///
+/// The conditions are evaluated on the fast fields of the elements, which have to be below the
+/// nested path. They are copied into memory once per segment, see also the
+/// [`nested` aggregation](crate::aggregation::bucket::NestedAggregation).
+///
+/// ```rust
+/// use std::ops::Bound;
+///
+/// use tantivy::collector::Count;
+/// use tantivy::query::{NestedCondition, NestedQuery};
+/// use tantivy::schema::{JsonObjectOptions, Schema, FAST};
+/// use tantivy::{doc, Index};
+///
+/// # fn test() -> tantivy::Result<()> {
+/// let mut schema_builder = Schema::builder();
+/// let order = schema_builder.add_json_field(
+///     "order",
+///     JsonObjectOptions::from(FAST).add_nested_path("items"),
+/// );
+/// let index = Index::create_in_ram(schema_builder.build());
+/// let mut index_writer = index.writer_with_num_threads(1, 15_000_000)?;
+/// let order_json = serde_json::json!({
+///     "items": [{ "sku": "a", "price": 3 }, { "sku": "b", "price": 5 }]
+/// });
+/// index_writer.add_document(doc!(order => order_json.as_object().unwrap().clone()))?;
+/// index_writer.commit()?;
+///
+/// let searcher = index.reader()?.searcher();
+/// let expensive_a = NestedQuery::new(
+///     "order.items",
+///     vec![
+///         ("order.items.sku".to_string(), NestedCondition::Text("a".to_string())),
+///         (
+///             "order.items.price".to_string(),
+///             NestedCondition::Range(Bound::Excluded(4.0), Bound::Unbounded),
+///         ),
+///     ],
+/// );
+/// assert_eq!(searcher.search(&expensive_a, &Count)?, 0);
+/// # Ok(())
+/// # }
+/// # assert!(test().is_ok());
+/// ```
+#[derive(Clone, Debug)]
+pub struct NestedQuery {
+    path: String,
+    conditions: Vec<(String, NestedCondition)>,
+}
+
+impl NestedQuery {
+    /// Creates a new `NestedQuery` on the nested path, including the name of the json field, with
+    /// the conditions on the fields of the elements.
+    pub fn new(path: impl Into<String>, conditions: Vec<(String, NestedCondition)>) -> NestedQuery {
+        NestedQuery {
+            path: path.into(),
+            conditions,
+        }
+    }
+
+    /// The nested path of the query.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The conditions on the fields of the elements.
+    pub fn conditions(&self) -> &[(String, NestedCondition)] {
+        &self.conditions
+    }
+}
+
+impl Query for NestedQuery {
+    fn weight(&self, _enable_scoring: EnableScoring<'_>) -> crate::Result<Box<dyn Weight>> {
+        if self.path.is_empty() {
+            return Err(TantivyError::InvalidArgument(
+                "nested query requires a path".to_string(),
+            ));
+        }
+        Ok(Box::new(NestedWeight {
+            query: self.clone(),
+        }))
+    }
+}
+
+struct NestedWeight {
+    query: NestedQuery,
+}
+
+/// A condition with the fast field of the elements of a segment. A missing fast field doesn't
+/// match.
+enum NestedConditionReader {
+    Text(Option<(StrColumn, u64)>),
+    Bool(Option<Column<bool>>, bool),
+    Range(Option<(Column<u64>, ColumnType)>, Bound<f64>, Bound<f64>),
+}
+
+impl NestedConditionReader {
+    fn open(
+        nested_documents: &NestedDocuments,
+        field_name: &str,
+        condition: &NestedCondition,
+    ) -> crate::Result<NestedConditionReader> {
+        let fast_fields = nested_documents.fast_fields();
+        Ok(match condition {
+            NestedCondition::Text(text) => {
+                let mut column_and_term_ord = None;
+                if let Some(str_column) = fast_fields.str(field_name)? {
+                    if let Some(term_ord) = str_column.dictionary().term_ord(text)? {
+                        column_and_term_ord = Some((str_column, term_ord));
+                    }
+                }
+                NestedConditionReader::Text(column_and_term_ord)
+            }
+            NestedCondition::Bool(val) => {
+                NestedConditionReader::Bool(fast_fields.column_opt(field_name)?, *val)
+            }
+            NestedCondition::Range(lower, upper) => NestedConditionReader::Range(
+                fast_fields.u64_lenient_for_type(
+                    Some(&[ColumnType::F64, ColumnType::I64, ColumnType::U64]),
+                    field_name,
+                )?,
+                *lower,
+                *upper,
+            ),
+        })
+    }
+
+    fn matches(&self, nested_doc: DocId) -> bool {
+        match self {
+            NestedConditionReader::Text(column_and_term_ord) => column_and_term_ord
+                .as_ref()
+                .map(|(str_column, term_ord)| {
+                    str_column.term_ords(nested_doc).any(|ord| ord == *term_ord)
+                })
+                .unwrap_or(false),
+            NestedConditionReader::Bool(column, expected) => column
+                .as_ref()
+                .map(|column| column.values(nested_doc).any(|val| val == *expected))
+                .unwrap_or(false),
+            NestedConditionReader::Range(column, lower, upper) => column
+                .as_ref()
+                .map(|(column, column_type)| {
+                    column
+                        .values(nested_doc)
+                        .map(|val| f64_from_u64(val, *column_type))
+                        .any(|val| (*lower, *upper).contains(&val))
+                })
+                .unwrap_or(false),
+        }
+    }
+}
+
+fn f64_from_u64(val: u64, column_type: ColumnType) -> f64 {
+    match column_type {
+        ColumnType::I64 => i64::from_u64(val) as f64,
+        ColumnType::F64 => f64::from_u64(val),
+        _ => val as f64,
+    }
+}
+
+impl Weight for NestedWeight {
+    fn scorer(&self, reader: &SegmentReader, boost: Score) -> crate::Result<Box<dyn Scorer>> {
+        let nested_documents = NestedDocuments::build(
+            reader.fast_fields(),
+            reader.max_doc(),
+            &self.query.path,
+            self.query
+                .conditions
+                .iter()
+                .map(|(field_name, _)| field_name.as_str()),
+        )?;
+        let conditions = self
+            .query
+            .conditions
+            .iter()
+            .map(|(field_name, condition)| {
+                NestedConditionReader::open(&nested_documents, field_name, condition)
+            })
+            .collect::<crate::Result<Vec<_>>>()?;
+
+        let mut docs = BitSet::with_max_value(reader.max_doc());
+        for doc in 0..reader.max_doc() {
+            let matches = nested_documents
+                .nested_docs(doc)
+                .any(|nested_doc| conditions.iter().all(|cond| cond.matches(nested_doc)));
+            if matches {
+                docs.insert(doc);
+            }
+        }
+        Ok(Box::new(ConstScorer::new(BitSetDocSet::from(docs), boost)))
+    }
+
+    fn explain(&self, reader: &SegmentReader, doc: DocId) -> crate::Result<Explanation> {
+        let mut scorer = self.scorer(reader, 1.0)?;
+        if scorer.seek(doc) != doc {
+            return Err(TantivyError::InvalidArgument(format!(
+                "Document #({}) does not match",
+                doc
+            )));
+        }
+        Ok(Explanation::new("NestedQuery", scorer.score()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use serde_json::json;
+
+    use super::{NestedCondition, NestedQuery};
+    use crate::collector::Count;
+    use crate::query::QueryParser;
+    use crate::schema::{JsonObjectOptions, Schema, FAST, STORED, TEXT};
+    use crate::{Index, IndexWriter};
+
+    fn nested_query(sku: &str, min_price: f64) -> NestedQuery {
+        NestedQuery::new(
+            "order.items",
+            vec![
+                (
+                    "order.items.sku".to_string(),
+                    NestedCondition::Text(sku.to_string()),
+                ),
+                (
+                    "order.items.price".to_string(),
+                    NestedCondition::Range(Bound::Included(min_price), Bound::Unbounded),
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_nested_query() -> crate::Result<()> {
+        let mut schema_builder = Schema::builder();
+        let json_options = JsonObjectOptions::from(STORED | TEXT)
+            .set_fast()
+            .add_nested_path("items");
+        let order = schema_builder.add_json_field("order", json_options);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer: IndexWriter = index.writer_for_tests()?;
+        let orders = [
+            json!({ "items": [{ "sku": "a", "price": 3 }, { "sku": "b", "price": 5 }] }),
+            json!({ "items": [{ "sku": "b", "price": -1.5 }, { "sku": "a", "price": 7 }] }),
+            json!({ "items": { "sku": "a", "price": 4, "gift": true } }),
+            json!({ "items": [] }),
+        ];
+        for (i, order_json) in orders.into_iter().enumerate() {
+            index_writer.add_document(doc!(order => order_json.as_object().unwrap().clone()))?;
+            if i == 1 {
+                index_writer.commit()?;
+            }
+        }
+        index_writer.commit()?;
+
+        let searcher = index.reader()?.searcher();
+        assert_eq!(searcher.segment_readers().len(), 2);
+        // The flattened values of the first order match, although the item `a` costs 3.
+        let query_parser = QueryParser::for_index(&index, vec![order]);
+        let flat_query = query_parser.parse_query("order.items.sku:a AND order.items.price:5")?;
+        assert_eq!(searcher.search(&flat_query, &Count)?, 1);
+        let nested_price_query = NestedQuery::new(
+            "order.items",
+            vec![
+                (
+                    "order.items.sku".to_string(),
+                    NestedCondition::Text("a".to_string()),
+                ),
+                (
+                    "order.items.price".to_string(),
+                    NestedCondition::Range(Bound::Included(5.0), Bound::Included(5.0)),
+                ),
+            ],
+        );
+        assert_eq!(searcher.search(&nested_price_query, &Count)?, 0);
+        assert_eq!(searcher.search(&nested_query("a", 5.0), &Count)?, 1);
+        assert_eq!(searcher.search(&nested_query("a", 4.0), &Count)?, 2);
+        assert_eq!(searcher.search(&nested_query("b", -2.0), &Count)?, 2);
+        assert_eq!(searcher.search(&nested_query("c", 0.0), &Count)?, 0);
+        let gift_query = NestedQuery::new(
+            "order.items",
+            vec![("order.items.gift".to_string(), NestedCondition::Bool(true))],
+        );
+        assert_eq!(searcher.search(&gift_query, &Count)?, 1);
+
+        // The elements are kept apart after merging the segments.
+        let segment_ids = index.searchable_segment_ids()?;
+        index_writer.merge(&segment_ids).wait()?;
+        index_writer.wait_merging_threads()?;
+        let searcher = index.reader()?.searcher();
+        assert_eq!(searcher.segment_readers().len(), 1);
+        assert_eq!(searcher.search(&nested_query("a", 5.0), &Count)?, 1);
+        assert_eq!(searcher.search(&nested_query("a", 4.0), &Count)?, 2);
+        assert_eq!(searcher.search(&nested_query("b", 0.0), &Count)?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_query_field_outside_of_path() -> crate::Result<()> {
+        let mut schema_builder = Schema::builder();
+        let json_options = JsonObjectOptions::from(FAST).add_nested_path("items");
+        let order = schema_builder.add_json_field("order", json_options);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut index_writer: IndexWriter = index.writer_for_tests()?;
+        index_writer.add_document(doc!(order => json!({ "customer": "alice" })
+            .as_object()
+            .unwrap()
+            .clone()))?;
+        index_writer.commit()?;
+        let searcher = index.reader()?.searcher();
+        let query = NestedQuery::new(
+            "order.items",
+            vec![(
+                "order.customer".to_string(),
+                NestedCondition::Text("alice".to_string()),
+            )],
+        );
+        assert!(searcher.search(&query, &Count).is_err());
+        Ok(())
+    }
+}
@@ -32,6 +32,9 @@ impl FastFieldRangeWeight {
 
 impl Weight for FastFieldRangeWeight {
     fn scorer(&self, reader: &SegmentReader, boost: Score) -> crate::Result<Box<dyn Scorer>> {
+        if !self.may_match(reader) {
+            return Ok(Box::new(EmptyScorer));
+        }
         let fast_field_reader = reader.fast_fields();
         let Some(column) = fast_field_reader.u64_lenient(&self.field)? else {
             return Ok(Box::new(EmptyScorer));
@@ -49,6 +52,18 @@ impl Weight for FastFieldRangeWeight {
         Ok(Box::new(ConstScorer::new(docset, boost)))
     }
 
+    fn may_match(&self, reader: &SegmentReader) -> bool {
+        let Some(stats) = reader.fast_field_stats(&self.field) else {
+            return true;
+        };
+        !is_disjoint(
+            &self.left_bound,
+            &self.right_bound,
+            stats.min_value,
+            stats.max_value,
+        )
+    }
+
     fn explain(&self, reader: &SegmentReader, doc: DocId) -> crate::Result<Explanation> {
         let mut scorer = self.scorer(reader, 1.0)?;
         if scorer.seek(doc) != doc {
@@ -63,6 +78,26 @@ impl Weight for FastFieldRangeWeight {
     }
 }
 
+/// Returns true if no value of `[min_value, max_value]` is within the bounds.
+fn is_disjoint(
+    left_bound: &Bound<u64>,
+    right_bound: &Bound<u64>,
+    min_value: u64,
+    max_value: u64,
+) -> bool {
+    let above_max = match left_bound {
+        Bound::Included(val) => *val > max_value,
+        Bound::Excluded(val) => *val >= max_value,
+        Bound::Unbounded => false,
+    };
+    let below_min = match right_bound {
+        Bound::Included(val) => *val < min_value,
+        Bound::Excluded(val) => *val <= min_value,
+        Bound::Unbounded => false,
+    };
+    above_max || below_min
+}
+
 fn bound_to_value_range<T: MonotonicallyMappableToU64>(
     left_bound: &Bound<T>,
     right_bound: &Bound<T>,
@@ -89,14 +124,16 @@ fn bound_to_value_range<T: MonotonicallyMappableToU64>(
 pub mod tests {
     use std::ops::{Bound, RangeInclusive};
 
+    use columnar::MonotonicallyMappableToU64;
     use proptest::prelude::*;
     use rand::rngs::StdRng;
     use rand::seq::SliceRandom;
     use rand::SeedableRng;
 
     use crate::collector::Count;
+    use crate::indexer::NoMergePolicy;
     use crate::query::range_query::range_query_u64_fastfield::FastFieldRangeWeight;
-    use crate::query::{QueryParser, Weight};
+    use crate::query::{EnableScoring, QueryParser, Weight};
     use crate::schema::{NumericOptions, Schema, SchemaBuilder, FAST, INDEXED, STORED, STRING};
     use crate::{Index, TERMINATED};
 
@@ -175,6 +212,66 @@ pub mod tests {
         assert_eq!(scorer.doc(), TERMINATED);
     }
 
+    #[test]
+    fn test_range_prunes_segments_by_fast_field_stats() -> crate::Result<()> {
+        let mut schema_builder = SchemaBuilder::new();
+        let field = schema_builder.add_i64_field("value", INDEXED | FAST);
+        let index = Index::create_in_ram(schema_builder.build());
+        let mut writer = index.writer_for_tests()?;
+        writer.set_merge_policy(Box::new(NoMergePolicy));
+        for value in -10i64..10 {
+            writer.add_document(doc!(field => value))?;
+        }
+        writer.commit()?;
+        for value in 100i64..105 {
+            writer.add_document(doc!(field => value))?;
+        }
+        writer.commit()?;
+        let searcher = index.reader()?.searcher();
+        let segment_with_num_docs = |num_docs: u32| {
+            searcher
+                .segment_readers()
+                .iter()
+                .find(|segment_reader| segment_reader.num_docs() == num_docs)
+                .unwrap()
+        };
+        let low_segment = segment_with_num_docs(20);
+        assert_eq!(
+            low_segment.fast_field_stats("value").unwrap().max_value,
+            9i64.to_u64()
+        );
+        let high_segment = segment_with_num_docs(5);
+        let query_parser = QueryParser::for_index(&index, vec![]);
+        let may_match = |query: &str| -> crate::Result<(bool, bool, usize)> {
+            let query = query_parser.parse_query(query)?;
+            let weight = query.weight(EnableScoring::disabled_from_searcher(&searcher))?;
+            let count = searcher.search(&query, &Count)?;
+            Ok((
+                weight.may_match(low_segment),
+                weight.may_match(high_segment),
+                count,
+            ))
+        };
+        assert_eq!(may_match("value:[9 TO 100]")?, (true, true, 2));
+        assert_eq!(may_match("value:{9 TO 100]")?, (false, true, 1));
+        assert_eq!(may_match("value:[-20 TO -10}")?, (false, false, 0));
+        assert_eq!(may_match("value:[50 TO *]")?, (false, true, 5));
+        assert_eq!(may_match("value:[* TO -5]")?, (true, false, 6));
+        assert_eq!(
+            may_match("+value:[* TO -5] +value:[50 TO *]")?,
+            (false, false, 0)
+        );
+        assert_eq!(
+            may_match("value:[* TO -5] value:[50 TO *]")?,
+            (true, true, 11)
+        );
+        assert_eq!(
+            may_match("value:[50 TO *] -value:[103 TO *]")?,
+            (false, true, 3)
+        );
+        Ok(())
+    }
+
     #[test]
     fn range_regression3_test() {
         let ops = vec![doc_from_id_1(1), doc_from_id_1(2), doc_from_id_1(3)];
@@ -66,6 +66,16 @@ pub trait Weight: Send + Sync + 'static {
     /// Returns an [`Explanation`] for the given document.
     fn explain(&self, reader: &SegmentReader, doc: DocId) -> crate::Result<Explanation>;
 
+    /// Returns `false` if none of the documents of the segment can match, judging only from
+    /// the segment metadata, e.g. the [fast field statistics](SegmentReader::fast_field_stats).
+    ///
+    /// Collectors may skip the segments which cannot match, see
+    /// [`Collector::collect_segments`](crate::collector::Collector::collect_segments).
+    /// Returning `true` is always correct.
+    fn may_match(&self, _reader: &SegmentReader) -> bool {
+        true
+    }
+
     /// Returns the number documents within the given [`SegmentReader`].
     fn count(&self, reader: &SegmentReader) -> crate::Result<u32> {
         let mut scorer = self.scorer(reader, 1.0)?;
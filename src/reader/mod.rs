@@ -9,6 +9,7 @@ pub use warming::Warmer;
 
 use self::warming::WarmingState;
 use crate::core::searcher::{SearcherGeneration, SearcherInner};
+use crate::core::{reader_cutoff_micros, RetentionFilterCache};
 use crate::directory::{Directory, WatchCallback, WatchHandle, META_LOCK};
 use crate::store::DOCSTORE_CACHE_CAPACITY;
 use crate::{Index, Inventory, Searcher, SegmentReader, TrackedObject};
@@ -153,13 +154,24 @@ impl TryInto<IndexReader> for IndexReaderBuilder {
     }
 }
 
+/// The searcher of an index reader, along with what is needed to exclude the documents which
+/// expire after it was loaded.
+struct LoadedSearcher {
+    searcher: Arc<SearcherInner>,
+    // The segment readers of the loaded commit, before the expired documents are excluded.
+    segment_readers: Vec<SegmentReader>,
+    // The cutoff of the retention filter applied to the searcher, if the index has a retention.
+    cutoff_micros: Option<i64>,
+}
+
 struct InnerIndexReader {
     doc_store_cache_num_blocks: usize,
     index: Index,
     warming_state: WarmingState,
-    searcher: arc_swap::ArcSwap<SearcherInner>,
+    searcher: arc_swap::ArcSwap<LoadedSearcher>,
     searcher_generation_counter: Arc<AtomicU64>,
     searcher_generation_inventory: Inventory<SearcherGeneration>,
+    retention_filter_cache: RetentionFilterCache,
 }
 
 impl InnerIndexReader {
@@ -172,6 +184,7 @@ impl InnerIndexReader {
         searcher_generation_inventory: Inventory<SearcherGeneration>,
     ) -> crate::Result<Self> {
         let searcher_generation_counter: Arc<AtomicU64> = Default::default();
+        let retention_filter_cache = RetentionFilterCache::default();
 
         let searcher = Self::create_searcher(
             &index,
@@ -179,6 +192,8 @@ impl InnerIndexReader {
             &warming_state,
             &searcher_generation_counter,
             &searcher_generation_inventory,
+            &retention_filter_cache,
+            Self::open_segment_readers(&index)?,
         )?;
         Ok(InnerIndexReader {
             doc_store_cache_num_blocks,
@@ -187,6 +202,7 @@ impl InnerIndexReader {
             searcher: ArcSwap::from(searcher),
             searcher_generation_counter,
             searcher_generation_inventory,
+            retention_filter_cache,
         })
     }
     /// Opens the freshest segments [`SegmentReader`].
@@ -197,7 +213,7 @@ impl InnerIndexReader {
         // Prevents segment files from getting deleted while we are in the process of opening them
         let _meta_lock = index.directory().acquire_lock(&META_LOCK)?;
         let searchable_segments = index.searchable_segments()?;
-        let segment_readers = searchable_segments
+        let segment_readers: Vec<SegmentReader> = searchable_segments
             .iter()
             .map(SegmentReader::open)
             .collect::<crate::Result<_>>()?;
@@ -221,10 +237,24 @@ impl InnerIndexReader {
         warming_state: &WarmingState,
         searcher_generation_counter: &Arc<AtomicU64>,
         searcher_generation_inventory: &Inventory<SearcherGeneration>,
-    ) -> crate::Result<Arc<SearcherInner>> {
-        let segment_readers = Self::open_segment_readers(index)?;
+        retention_filter_cache: &RetentionFilterCache,
+        segment_readers: Vec<SegmentReader>,
+    ) -> crate::Result<Arc<LoadedSearcher>> {
+        // Expired documents are excluded from the searcher, as of the time it is created.
+        let (alive_segment_readers, cutoff_micros) = match index.settings().retention.as_ref() {
+            Some(retention) => {
+                let cutoff_micros = reader_cutoff_micros(retention);
+                let alive_segment_readers = retention_filter_cache.apply_retention(
+                    segment_readers.clone(),
+                    retention,
+                    cutoff_micros,
+                )?;
+                (alive_segment_readers, Some(cutoff_micros))
+            }
+            None => (segment_readers.clone(), None),
+        };
         let searcher_generation = Self::track_segment_readers_in_inventory(
-            &segment_readers,
+            &alive_segment_readers,
             searcher_generation_counter,
             searcher_generation_inventory,
         );
@@ -233,13 +263,17 @@ impl InnerIndexReader {
         let searcher = Arc::new(SearcherInner::new(
             schema,
             index.clone(),
-            segment_readers,
+            alive_segment_readers,
             searcher_generation,
             doc_store_cache_num_blocks,
         )?);
 
         warming_state.warm_new_searcher_generation(&searcher.clone().into())?;
-        Ok(searcher)
+        Ok(Arc::new(LoadedSearcher {
+            searcher,
+            segment_readers,
+            cutoff_micros,
+        }))
     }
 
     fn reload(&self) -> crate::Result<()> {
@@ -249,6 +283,8 @@ impl InnerIndexReader {
             &self.warming_state,
             &self.searcher_generation_counter,
             &self.searcher_generation_inventory,
+            &self.retention_filter_cache,
+            Self::open_segment_readers(&self.index)?,
         )?;
 
         self.searcher.store(searcher);
@@ -257,7 +293,36 @@ impl InnerIndexReader {
     }
 
     fn searcher(&self) -> Searcher {
-        self.searcher.load().clone().into()
+        let loaded_searcher = self.searcher.load_full();
+        let Some(retention) = self.index.settings().retention.as_ref() else {
+            return loaded_searcher.searcher.clone().into();
+        };
+        if loaded_searcher.cutoff_micros == Some(reader_cutoff_micros(retention)) {
+            return loaded_searcher.searcher.clone().into();
+        }
+        // The cutoff moved since the searcher was created: the documents which expired since
+        // then are excluded from a new searcher on the same commit, so that the readers which
+        // are not reloaded, e.g. with `ReloadPolicy::Manual`, do not keep serving them.
+        match Self::create_searcher(
+            &self.index,
+            self.doc_store_cache_num_blocks,
+            &self.warming_state,
+            &self.searcher_generation_counter,
+            &self.searcher_generation_inventory,
+            &self.retention_filter_cache,
+            loaded_searcher.segment_readers.clone(),
+        ) {
+            Ok(searcher) => {
+                // A concurrent reload wins over the searcher of the previous commit.
+                self.searcher
+                    .compare_and_swap(&loaded_searcher, searcher.clone());
+                searcher.searcher.clone().into()
+            }
+            Err(err) => {
+                warn!("Failed to exclude the expired documents from the searcher: {}", err);
+                loaded_searcher.searcher.clone().into()
+            }
+        }
     }
 }
 
@@ -288,6 +353,11 @@ impl IndexReader {
     ///
     /// This automatic reload can take 10s of milliseconds to kick in however, and in unit tests
     /// it can be nice to deterministically force the reload of searchers.
+    ///
+    /// If the index has a [retention](crate::IndexSettings::retention), the segments whose
+    /// documents are all expired are left out of the new searcher. This shifts the ordinals of
+    /// the following segments, so the [`DocAddress`](crate::DocAddress) values obtained from a
+    /// previous searcher are not valid for the new one.
     pub fn reload(&self) -> crate::Result<()> {
         self.inner.reload()
     }
@@ -299,7 +369,82 @@ impl IndexReader {
     ///
     /// The same searcher must be used for a given query, as it ensures
     /// the use of a consistent segment set.
+    ///
+    /// If the index has a [retention](crate::IndexSettings::retention), the documents which
+    /// expired since the last searcher was created are excluded from the returned searcher,
+    /// even if the reader was not reloaded. As with [`IndexReader::reload`], the segment
+    /// ordinals may then shift.
     pub fn searcher(&self) -> Searcher {
         self.inner.searcher()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use time::{Duration, OffsetDateTime};
+
+    use super::LoadedSearcher;
+    use crate::core::searcher::{SearcherGeneration, SearcherInner};
+    use crate::schema::{Schema, FAST};
+    use crate::{DateTime, Index, IndexRetention, IndexSettings, ReloadPolicy};
+
+    #[test]
+    fn test_manual_reader_excludes_docs_expired_since_load() -> crate::Result<()> {
+        let mut schema_builder = Schema::builder();
+        let date_field = schema_builder.add_date_field("timestamp", FAST);
+        let index = Index::builder()
+            .schema(schema_builder.build())
+            .settings(IndexSettings {
+                retention: Some(IndexRetention {
+                    field: "timestamp".to_string(),
+                    retention_secs: 5 * 24 * 3600,
+                }),
+                ..Default::default()
+            })
+            .create_in_ram()?;
+        let mut index_writer = index.writer_for_tests()?;
+        for days_ago in [10, 1] {
+            let date = OffsetDateTime::now_utc() - Duration::days(days_ago);
+            index_writer.add_document(doc!(date_field => DateTime::from_utc(date)))?;
+        }
+        index_writer.commit()?;
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+        let searcher = reader.searcher();
+        assert_eq!(searcher.num_docs(), 1);
+        assert_eq!(
+            reader.searcher().generation().generation_id(),
+            searcher.generation().generation_id()
+        );
+
+        // Pretends that the searcher was loaded before the first document expired.
+        let inner = &reader.inner;
+        let segment_readers = inner.searcher.load().segment_readers.clone();
+        let searcher_generation = inner
+            .searcher_generation_inventory
+            .track(SearcherGeneration::from_segment_readers(&segment_readers, 1_000));
+        let unfiltered_searcher = SearcherInner::new(
+            index.schema(),
+            index.clone(),
+            segment_readers.clone(),
+            searcher_generation,
+            inner.doc_store_cache_num_blocks,
+        )?;
+        inner.searcher.store(Arc::new(LoadedSearcher {
+            searcher: Arc::new(unfiltered_searcher),
+            segment_readers,
+            cutoff_micros: Some(i64::MIN),
+        }));
+        let new_searcher = reader.searcher();
+        assert_eq!(new_searcher.num_docs(), 1);
+        assert_eq!(
+            reader.searcher().generation().generation_id(),
+            new_searcher.generation().generation_id()
+        );
+        Ok(())
+    }
+}
@@ -19,6 +19,12 @@ pub struct FieldEntry {
     name: String,
     #[serde(flatten)]
     field_type: FieldType,
+    #[serde(default, skip_serializing_if = "is_false")]
+    unique_key: bool,
+}
+
+fn is_false(val: &bool) -> bool {
+    !val
 }
 
 impl FieldEntry {
@@ -28,6 +34,7 @@ impl FieldEntry {
         FieldEntry {
             name: field_name,
             field_type,
+            unique_key: false,
         }
     }
 
@@ -108,6 +115,17 @@ impl FieldEntry {
         self.field_type.is_fast()
     }
 
+    /// Returns true if the field is the unique key of the schema.
+    ///
+    /// See [`SchemaBuilder::set_unique_key`](crate::schema::SchemaBuilder::set_unique_key).
+    pub fn is_unique_key(&self) -> bool {
+        self.unique_key
+    }
+
+    pub(crate) fn set_unique_key(&mut self) {
+        self.unique_key = true;
+    }
+
     /// Returns true if the field is stored
     pub fn is_stored(&self) -> bool {
         match self.field_type {
@@ -2,7 +2,7 @@ use std::ops::BitOr;
 
 use serde::{Deserialize, Serialize};
 
-use crate::schema::flags::{SchemaFlagList, StoredFlag};
+use crate::schema::flags::{FastFlag, SchemaFlagList, StoredFlag};
 use crate::schema::{TextFieldIndexing, TextOptions};
 
 /// The `JsonObjectOptions` make it possible to
@@ -13,10 +13,20 @@ pub struct JsonObjectOptions {
     // If set to some, int, date, f64 and text will be indexed.
     // Text will use the TextFieldIndexing setting for indexing.
     indexing: Option<TextFieldIndexing>,
+    // If set, the values of the json object are stored in fast fields, one column per json path.
+    #[serde(default, skip_serializing_if = "is_false")]
+    fast: bool,
+    // The json paths of arrays of objects, whose elements keep their identity in fast fields.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    nested_paths: Vec<String>,
 
     expand_dots_enabled: bool,
 }
 
+fn is_false(val: &bool) -> bool {
+    !*val
+}
+
 impl JsonObjectOptions {
     /// Returns `true` if the json object should be stored.
     pub fn is_stored(&self) -> bool {
@@ -28,6 +38,19 @@ impl JsonObjectOptions {
         self.indexing.is_some()
     }
 
+    /// Returns `true` iff the values of the json object are stored in fast fields.
+    ///
+    /// Each json path gets its own columns, which can be accessed with the path joined with `.`
+    /// to the field name, e.g. `attributes.size`.
+    pub fn is_fast(&self) -> bool {
+        self.fast
+    }
+
+    /// Returns the nested json paths, see [`JsonObjectOptions::add_nested_path`].
+    pub fn nested_paths(&self) -> &[String] {
+        &self.nested_paths
+    }
+
     /// Returns `true` iff dots in json keys should be expanded.
     ///
     /// When expand_dots is enabled, json object like
@@ -67,6 +90,33 @@ impl JsonObjectOptions {
         self
     }
 
+    /// Sets the field as a fast field.
+    #[must_use]
+    pub fn set_fast(mut self) -> Self {
+        self.fast = true;
+        self
+    }
+
+    /// Declares the json path of an array of objects, like `items` in
+    /// `{"items": [{"sku": "a", "price": 3}, {"sku": "b", "price": 5}]}`, as nested.
+    ///
+    /// By default the values of all elements are flattened into one column per path, so that the
+    /// `sku` of the first element can't be told apart from the `sku` of the second element. For
+    /// a nested path, the fast fields additionally record the element of every value, which is
+    /// used by the [`nested` aggregation](crate::aggregation::bucket::NestedAggregation) and the
+    /// [`NestedQuery`](crate::query::NestedQuery).
+    ///
+    /// The path is relative to the field. A single object at the path is a nested element too.
+    /// Nested paths only apply to fast fields, and paths inside a nested path are not nested
+    /// again.
+    #[must_use]
+    pub fn add_nested_path(mut self, nested_path: &str) -> Self {
+        if !self.nested_paths.iter().any(|path| path == nested_path) {
+            self.nested_paths.push(nested_path.to_string());
+        }
+        self
+    }
+
     /// Sets the field as indexed, with the specific indexing options.
     #[must_use]
     pub fn set_indexing_options(mut self, indexing: TextFieldIndexing) -> Self {
@@ -80,6 +130,20 @@ impl From<StoredFlag> for JsonObjectOptions {
         JsonObjectOptions {
             stored: true,
             indexing: None,
+            fast: false,
+            nested_paths: Vec::new(),
+            expand_dots_enabled: false,
+        }
+    }
+}
+
+impl From<FastFlag> for JsonObjectOptions {
+    fn from(_fast_flag: FastFlag) -> Self {
+        JsonObjectOptions {
+            stored: false,
+            indexing: None,
+            fast: true,
+            nested_paths: Vec::new(),
             expand_dots_enabled: false,
         }
     }
@@ -96,9 +160,17 @@ impl<T: Into<JsonObjectOptions>> BitOr<T> for JsonObjectOptions {
 
     fn bitor(self, other: T) -> Self {
         let other: JsonObjectOptions = other.into();
+        let mut nested_paths = self.nested_paths;
+        for nested_path in other.nested_paths {
+            if !nested_paths.contains(&nested_path) {
+                nested_paths.push(nested_path);
+            }
+        }
         JsonObjectOptions {
             indexing: self.indexing.or(other.indexing),
             stored: self.stored | other.stored,
+            fast: self.fast | other.fast,
+            nested_paths,
             expand_dots_enabled: self.expand_dots_enabled | other.expand_dots_enabled,
         }
     }
@@ -120,6 +192,8 @@ impl From<TextOptions> for JsonObjectOptions {
         JsonObjectOptions {
             stored: text_options.is_stored(),
             indexing: text_options.get_indexing_options().cloned(),
+            fast: text_options.is_fast(),
+            nested_paths: Vec::new(),
             expand_dots_enabled: false,
         }
     }
@@ -128,7 +202,7 @@ impl From<TextOptions> for JsonObjectOptions {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::schema::{STORED, TEXT};
+    use crate::schema::{FAST, STORED, TEXT};
 
     #[test]
     fn test_json_options() {
@@ -147,5 +221,36 @@ mod tests {
             assert!(json_options.is_stored());
             assert!(!json_options.is_indexed());
         }
+        {
+            let json_options: JsonObjectOptions = (STORED | FAST).into();
+            assert!(json_options.is_stored());
+            assert!(json_options.is_fast());
+            assert!(!json_options.is_indexed());
+        }
+        {
+            let json_options = JsonObjectOptions::from(FAST)
+                .add_nested_path("items")
+                .add_nested_path("items");
+            let json_options = json_options | JsonObjectOptions::from(STORED).add_nested_path("a");
+            assert_eq!(json_options.nested_paths(), &["items", "a"]);
+            assert!(json_options.is_stored());
+        }
+    }
+
+    #[test]
+    fn test_json_options_serialization() {
+        let json_options: JsonObjectOptions = STORED.into();
+        assert_eq!(
+            serde_json::to_string(&json_options).unwrap(),
+            r#"{"stored":true,"indexing":null,"expand_dots_enabled":false}"#
+        );
+        let json_options: JsonObjectOptions = (STORED | FAST).into();
+        let json = serde_json::to_string(&json_options).unwrap();
+        assert_eq!(
+            json,
+            r#"{"stored":true,"indexing":null,"fast":true,"expand_dots_enabled":false}"#
+        );
+        let deserialized: JsonObjectOptions = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, json_options);
     }
 }
@@ -208,6 +208,54 @@ impl SchemaBuilder {
         field
     }
 
+    /// Declares `field` as the unique key of the schema.
+    ///
+    /// Documents are identified by the value of their unique key field, which allows to replace
+    /// a previous version of a document with
+    /// [`IndexWriter::upsert_document`](crate::IndexWriter::upsert_document).
+    ///
+    /// A `SchemaError` is returned if the schema already has a unique key, if the field is not
+    /// indexed, if the field is a json or facet field, or if it is a text field which is not
+    /// indexed with the `raw` tokenizer (e.g. [`STRING`](crate::schema::STRING)).
+    pub fn set_unique_key(&mut self, field: Field) -> crate::Result<()> {
+        if let Some(previous_key) = self.fields.iter().find(|entry| entry.is_unique_key()) {
+            return Err(TantivyError::SchemaError(format!(
+                "Schema already has the unique key {}",
+                previous_key.name()
+            )));
+        }
+        let field_entry = &mut self.fields[field.field_id() as usize];
+        if !field_entry.is_indexed() {
+            return Err(TantivyError::SchemaError(format!(
+                "Unique key {} must be indexed",
+                field_entry.name()
+            )));
+        }
+        match field_entry.field_type() {
+            FieldType::JsonObject(_) | FieldType::Facet(_) => {
+                return Err(TantivyError::SchemaError(format!(
+                    "Field {} of type {:?} cannot be a unique key",
+                    field_entry.name(),
+                    field_entry.field_type().value_type()
+                )));
+            }
+            FieldType::Str(text_options) => {
+                let tokenizer = text_options
+                    .get_indexing_options()
+                    .map(|indexing_options| indexing_options.tokenizer());
+                if tokenizer != Some("raw") {
+                    return Err(TantivyError::SchemaError(format!(
+                        "Unique key {} must be indexed with the raw tokenizer",
+                        field_entry.name()
+                    )));
+                }
+            }
+            _ => {}
+        }
+        field_entry.set_unique_key();
+        Ok(())
+    }
+
     /// Finalize the creation of a `Schema`
     /// This will consume your `SchemaBuilder`
     pub fn build(self) -> Schema {
@@ -303,6 +351,15 @@ impl Schema {
             .map(|(field_id, field_entry)| (Field::from_field_id(field_id as u32), field_entry))
     }
 
+    /// Returns the unique key field of the schema, if any.
+    ///
+    /// See [`SchemaBuilder::set_unique_key`].
+    pub fn unique_key_field(&self) -> Option<Field> {
+        self.fields()
+            .find(|(_, field_entry)| field_entry.is_unique_key())
+            .map(|(field, _)| field)
+    }
+
     /// Creates a new builder.
     pub fn builder() -> SchemaBuilder {
         SchemaBuilder::default()
@@ -486,6 +543,7 @@ mod tests {
     use crate::schema::field_type::ValueParsingError;
     use crate::schema::schema::DocParsingError::InvalidJson;
     use crate::schema::*;
+    use crate::TantivyError;
 
     #[test]
     fn test_locate_splitting_dots() {
@@ -979,6 +1037,63 @@ mod tests {
         assert_eq!(schema_json, expected);
     }
 
+    #[test]
+    fn test_schema_unique_key() -> crate::Result<()> {
+        let mut schema_builder = Schema::builder();
+        let title = schema_builder.add_text_field("title", TEXT);
+        let id = schema_builder.add_u64_field("id", INDEXED);
+        schema_builder.set_unique_key(id)?;
+        let schema = schema_builder.build();
+        assert_eq!(schema.unique_key_field(), Some(id));
+        assert!(!schema.get_field_entry(title).is_unique_key());
+
+        let schema_json = serde_json::to_string(&schema).unwrap();
+        assert!(schema_json.contains(r#""unique_key":true"#));
+        let deserialized_schema: Schema = serde_json::from_str(&schema_json).unwrap();
+        assert_eq!(deserialized_schema, schema);
+        assert_eq!(deserialized_schema.unique_key_field(), Some(id));
+
+        let schema = Schema::builder().build();
+        assert_eq!(schema.unique_key_field(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_invalid_unique_key() {
+        let mut schema_builder = Schema::builder();
+        let title = schema_builder.add_text_field("title", TEXT);
+        let stored_id = schema_builder.add_u64_field("stored_id", STORED);
+        let facet = schema_builder.add_facet_field("facet", FacetOptions::default());
+        let json = schema_builder.add_json_field("json", STRING);
+        for (field, expected_err) in [
+            (title, "must be indexed with the raw tokenizer"),
+            (stored_id, "must be indexed"),
+            (facet, "cannot be a unique key"),
+            (json, "cannot be a unique key"),
+        ] {
+            let err = schema_builder.set_unique_key(field).unwrap_err();
+            assert!(
+                matches!(&err, TantivyError::SchemaError(msg) if msg.contains(expected_err)),
+                "{:?}",
+                err
+            );
+        }
+        let schema = schema_builder.build();
+        assert_eq!(schema.unique_key_field(), None);
+    }
+
+    #[test]
+    fn test_schema_two_unique_keys() -> crate::Result<()> {
+        let mut schema_builder = Schema::builder();
+        let id = schema_builder.add_text_field("id", STRING);
+        let other_id = schema_builder.add_text_field("other_id", STRING);
+        schema_builder.set_unique_key(id)?;
+        let err = schema_builder.set_unique_key(other_id).unwrap_err();
+        assert!(matches!(err, TantivyError::SchemaError(msg) if msg.contains("already has")));
+        assert_eq!(schema_builder.build().unique_key_field(), Some(id));
+        Ok(())
+    }
+
     #[test]
     fn test_find_field() {
         let mut schema_builder = Schema::builder();
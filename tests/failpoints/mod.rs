@@ -1,8 +1,9 @@
 use std::path::Path;
 
 use tantivy::directory::{Directory, ManagedDirectory, RamDirectory, TerminatingWrite};
-use tantivy::schema::{Schema, TEXT};
-use tantivy::{doc, Index, Term};
+use tantivy::query::TermQuery;
+use tantivy::schema::{Document, IndexRecordOption, Schema, STORED, TEXT};
+use tantivy::{doc, Index, IndexSettings, TantivyError, Term, WriteAheadLogDurability};
 
 #[test]
 fn test_failpoints_managed_directory_gc_if_delete_fails() {
@@ -65,6 +66,67 @@ fn test_write_commit_fails() -> tantivy::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_update_query_after_failed_commit() -> tantivy::Result<()> {
+    let _fail_scenario_guard = fail::FailScenario::setup();
+    let mut schema_builder = Schema::builder();
+    let text_field = schema_builder.add_text_field("text", TEXT | STORED);
+    let index = Index::create_in_ram(schema_builder.build());
+
+    let mut index_writer = index.writer_with_num_threads(1, 3_000_000)?;
+    index_writer.add_document(doc!(text_field => "a"))?;
+    index_writer.commit()?;
+    fail::cfg("save_metas", "return(error_write_failed)").unwrap();
+    index_writer.add_document(doc!(text_field => "b"))?;
+    assert!(index_writer.commit().is_err());
+    fail::remove("save_metas");
+
+    // The add of "b" is still not committed, so it would be overwritten by the update.
+    let query = TermQuery::new(
+        Term::from_field_text(text_field, "a"),
+        IndexRecordOption::Basic,
+    );
+    let err = index_writer
+        .update_query(Box::new(query), |doc: Document| doc)
+        .unwrap_err();
+    assert!(matches!(err, TantivyError::InvalidArgument(_)));
+    Ok(())
+}
+
+#[test]
+fn test_write_ahead_log_poisoned_by_failed_append() -> tantivy::Result<()> {
+    let _fail_scenario_guard = fail::FailScenario::setup();
+    let mut schema_builder = Schema::builder();
+    let text_field = schema_builder.add_text_field("text", TEXT);
+    let index = Index::builder()
+        .schema(schema_builder.build())
+        .settings(IndexSettings {
+            write_ahead_log: Some(WriteAheadLogDurability::PerBatch),
+            ..Default::default()
+        })
+        .create_in_ram()?;
+
+    let mut index_writer = index.writer_with_num_threads(1, 3_000_000)?;
+    index_writer.add_document(doc!(text_field => "a"))?;
+    fail::cfg("WriteAheadLog::append_record", "1*return(error_write_failed)").unwrap();
+    assert!(index_writer.add_document(doc!(text_field => "b")).is_err());
+    // The log stays poisoned, even though the failpoint is exhausted.
+    index_writer.delete_term(Term::from_field_text(text_field, "a"));
+    assert!(index_writer.add_document(doc!(text_field => "c")).is_err());
+    assert!(index_writer.commit().is_err());
+
+    index_writer.rollback()?;
+    index_writer.add_document(doc!(text_field => "d"))?;
+    index_writer.commit()?;
+    let searcher = index.reader()?.searcher();
+    assert_eq!(searcher.num_docs(), 1);
+    assert_eq!(
+        searcher.doc_freq(&Term::from_field_text(text_field, "d"))?,
+        1
+    );
+    Ok(())
+}
+
 // Motivated by
 // - https://github.com/quickwit-oss/quickwit/issues/730
 // Details at